@@ -210,8 +210,9 @@ class AsciinemaPlayer(
                 }
             }
 
-            // Update frame
-            _frame.value = virtualTerminal.snapshot()
+            // Update frame, from the diff alone when it carries the content
+            _frame.value = virtualTerminal.pollDiff()?.let { _frame.value.apply(it) }
+                ?: virtualTerminal.snapshot()
             println("[AsciinemaPlayer] playEvents: Frame updated, lines with content: ${_frame.value.lines.count { it.runs.isNotEmpty() }}")
         }
         println("[AsciinemaPlayer] playEvents: Playback loop ended")
@@ -205,7 +205,7 @@ private fun DrawScope.drawTerminal(
                     drawRect(
                         color = bgColor,
                         topLeft = Offset(x, y),
-                        size = Size(run.length * cellWidth, cellHeight)
+                        size = Size(run.cells * cellWidth, cellHeight)
                     )
                 }
             }
@@ -224,26 +224,47 @@ private fun DrawScope.drawTerminal(
                 android.util.Log.d("TerminalCanvas", "Drawing run: text='${run.text}', colStart=${run.colStart}, x=$x, y=$y, color=$fgColor, fontSize=${fontSize * scale}")
             }
 
-            drawText(
-                textMeasurer = textMeasurer,
-                text = run.text,
-                topLeft = Offset(x, y),
-                style = TextStyle(
-                    fontSize = fontSize.sp,
-                    fontFamily = fontFamily,
-                    color = fgColor.toComposeColor(),
-                    fontWeight = if (run.style.bold) androidx.compose.ui.text.font.FontWeight.Bold else androidx.compose.ui.text.font.FontWeight.Normal,
-                    fontStyle = if (run.style.italic) androidx.compose.ui.text.font.FontStyle.Italic else androidx.compose.ui.text.font.FontStyle.Normal,
-                    textDecoration = when {
-                        run.style.underline && run.style.strikethrough -> TextDecoration.combine(
-                            listOf(TextDecoration.Underline, TextDecoration.LineThrough)
-                        )
-                        run.style.underline -> TextDecoration.Underline
-                        run.style.strikethrough -> TextDecoration.LineThrough
-                        else -> null
-                    }
-                )
+            val textStyle = TextStyle(
+                fontSize = fontSize.sp,
+                fontFamily = fontFamily,
+                color = fgColor.toComposeColor(),
+                fontWeight = if (run.style.bold) androidx.compose.ui.text.font.FontWeight.Bold else androidx.compose.ui.text.font.FontWeight.Normal,
+                fontStyle = if (run.style.italic) androidx.compose.ui.text.font.FontStyle.Italic else androidx.compose.ui.text.font.FontStyle.Normal,
+                textDecoration = when {
+                    run.style.underline && run.style.strikethrough -> TextDecoration.combine(
+                        listOf(TextDecoration.Underline, TextDecoration.LineThrough)
+                    )
+                    run.style.underline -> TextDecoration.Underline
+                    run.style.strikethrough -> TextDecoration.LineThrough
+                    else -> null
+                }
             )
+
+            // A wide run's code points each get their own pair of columns,
+            // whatever the font's advance for them
+            val width = run.cellsPerCodePoint
+            if (width == 1) {
+                drawText(
+                    textMeasurer = textMeasurer,
+                    text = run.text,
+                    topLeft = Offset(x, y),
+                    style = textStyle
+                )
+            } else {
+                var offset = 0
+                var column = 0
+                while (offset < run.text.length) {
+                    val next = run.text.offsetByCodePoints(offset, 1)
+                    drawText(
+                        textMeasurer = textMeasurer,
+                        text = run.text.substring(offset, next),
+                        topLeft = Offset(x + column * cellWidth, y),
+                        style = textStyle
+                    )
+                    offset = next
+                    column += width
+                }
+            }
         }
     }
 
@@ -252,11 +273,26 @@ private fun DrawScope.drawTerminal(
         val cursorX = frame.cursor.col * cellWidth
         val cursorY = frame.cursor.row * cellHeight
 
-        drawRect(
-            color = theme.foreground.toComposeColor().copy(alpha = 0.7f),
-            topLeft = Offset(cursorX, cursorY),
-            size = Size(cellWidth, cellHeight * 0.2f) // Underline cursor
-        )
+        val color = theme.foreground.toComposeColor()
+
+        // Blinking isn't animated; blinking and steady cursors draw alike
+        when (frame.cursor.shape) {
+            CursorShape.BLOCK -> drawRect(
+                color = color.copy(alpha = 0.5f),
+                topLeft = Offset(cursorX, cursorY),
+                size = Size(cellWidth, cellHeight)
+            )
+            CursorShape.UNDERLINE -> drawRect(
+                color = color.copy(alpha = 0.7f),
+                topLeft = Offset(cursorX, cursorY + cellHeight * 0.85f),
+                size = Size(cellWidth, cellHeight * 0.15f)
+            )
+            CursorShape.BAR -> drawRect(
+                color = color.copy(alpha = 0.7f),
+                topLeft = Offset(cursorX, cursorY),
+                size = Size(cellWidth * 0.15f, cellHeight)
+            )
+        }
     }
 }
 
@@ -22,20 +22,47 @@ data class CellStyle(
 /**
  * A run of text with the same style.
  * Used to efficiently represent terminal lines.
+ *
+ * [cells] is the columns the run covers. Every code point of a run is the
+ * same width, so a run of wide characters (CJK, emoji) covers two columns
+ * per code point; see [cellsPerCodePoint].
+ *
+ * [linkId] is nonzero for text inside an OSC 8 hyperlink; see
+ * [TerminalFrame.links].
  */
 data class TextRun(
     val colStart: Int,
     val text: String,
-    val style: CellStyle = CellStyle.DEFAULT
+    val style: CellStyle = CellStyle.DEFAULT,
+    val cells: Int = text.codePointCount(0, text.length),
+    val linkId: Int = 0
 ) {
     val length: Int get() = text.length
+
+    /** Columns each code point takes: 2 for wide text, otherwise 1. */
+    val cellsPerCodePoint: Int
+        get() {
+            val count = text.codePointCount(0, text.length)
+            return if (count == 0) 1 else maxOf(1, cells / count)
+        }
+}
+
+/**
+ * Per-row size attribute set by DECDWL/DECDHL.
+ */
+enum class LineAttribute {
+    SINGLE,
+    DOUBLE_WIDTH,
+    DOUBLE_HEIGHT_TOP,
+    DOUBLE_HEIGHT_BOTTOM
 }
 
 /**
  * A single line in the terminal buffer.
  */
 data class TerminalLine(
-    val runs: List<TextRun> = emptyList()
+    val runs: List<TextRun> = emptyList(),
+    val attribute: LineAttribute = LineAttribute.SINGLE
 ) {
     companion object {
         val EMPTY = TerminalLine(emptyList())
@@ -1,14 +1,22 @@
 package uk.adedamola.asciicast.vt
 
 /**
- * Cursor position and visibility.
+ * Cursor position, visibility and style (DECSCUSR).
  */
 data class Cursor(
     val row: Int,
     val col: Int,
-    val visible: Boolean = true
+    val visible: Boolean = true,
+    val shape: CursorShape = CursorShape.BLOCK,
+    val blink: Boolean = true
 )
 
+enum class CursorShape {
+    BLOCK,
+    UNDERLINE,
+    BAR
+}
+
 /**
  * A complete snapshot of the terminal state.
  * This is an immutable representation suitable for rendering.
@@ -19,7 +27,26 @@ data class TerminalFrame(
     val lines: List<TerminalLine>,
     val cursor: Cursor,
     val theme: Theme = Theme.DEFAULT,
-    val title: String? = null
+    val title: String? = null,
+    /**
+     * URIs by [TextRun.linkId], as of the last snapshot. Lines applied from
+     * diffs can use ids missing here; the backend looks those up.
+     */
+    val links: Map<Int, String> = emptyMap(),
+    /**
+     * Inline images shown, in the order of their top rows. A diff whose
+     * placements changed carries them as [TerminalDiff.imageChanges]
+     * (added, moved as lines scroll, or removed), which [apply] applies,
+     * so no snapshot is needed. Backends that hold pixels also say when an
+     * image is first given cells and when no row shows it any more, as
+     * avt's `ImageAdded` and `ImageRemoved` events, to fetch and drop them.
+     */
+    val images: List<ImagePlacement> = emptyList(),
+    /**
+     * A full-screen program has switched to the alternate screen, which
+     * has no scrollback; a renderer that shows scrollback hides it then.
+     */
+    val altScreenActive: Boolean = false
 ) {
     init {
         require(lines.size == rows) {
@@ -41,8 +68,126 @@ data class TerminalFrame(
             )
         }
     }
+
+    /**
+     * This frame with [diff] applied, or null if the diff doesn't carry
+     * its content (take a new snapshot instead).
+     */
+    fun apply(diff: TerminalDiff): TerminalFrame? {
+        if (diff.fullRedraw) return null
+        val content = diff.content ?: return null
+        return copy(
+            cols = content.cols,
+            rows = content.rows,
+            lines = List(content.rows) { row ->
+                val old = lines.getOrNull(row) ?: TerminalLine.EMPTY
+                val new = content.lines[row]
+                val span = content.spans[row]
+                when {
+                    new == null -> old
+                    span == null -> new
+                    else -> old.splice(new, span)
+                }
+            },
+            cursor = content.cursor,
+            altScreenActive = content.altScreenActive,
+            images = diff.imageChanges?.let { images.apply(it) } ?: images
+        )
+    }
+}
+
+/**
+ * [this] image table with [changes] applied, in the order of the
+ * placements' top rows. A change replaces the placement showing the same
+ * row of the same image at its top.
+ */
+private fun List<ImagePlacement>.apply(changes: List<ImageChange>): List<ImagePlacement> {
+    val table = toMutableList()
+    for (change in changes) {
+        val placement = change.placement
+        table.removeAll { it.imageId == placement.imageId && it.imageRow == placement.imageRow }
+        if (change.kind != ImageChange.Kind.REMOVED) {
+            table.add(placement)
+        }
+    }
+    table.sortBy { it.row }
+    return table
 }
 
+/**
+ * [this] with the columns in [span] taken from [line], which only has runs
+ * inside it. A code point takes [TextRun.cellsPerCodePoint] columns, as in
+ * the backend.
+ */
+private fun TerminalLine.splice(line: TerminalLine, span: IntRange): TerminalLine {
+    val spliced = ArrayList<TextRun>(runs.size + line.runs.size)
+    runs.mapNotNullTo(spliced) { it.crop(0, span.first) }
+    spliced.addAll(line.runs)
+    runs.mapNotNullTo(spliced) { it.crop(span.last + 1, Int.MAX_VALUE) }
+    return TerminalLine(runs = spliced, attribute = line.attribute)
+}
+
+/**
+ * The part of this run in columns `from until to`, or null if none. A wide
+ * character belongs to the range its first column is in.
+ */
+private fun TextRun.crop(from: Int, to: Int): TextRun? {
+    val width = cellsPerCodePoint
+    val count = text.codePointCount(0, text.length)
+    // First and last code point starting in range
+    val startIndex = maxOf(0, (from - colStart + width - 1) / width)
+    val endIndex = ((to.toLong() - colStart + width - 1) / width).coerceIn(0L, count.toLong()).toInt()
+    if (startIndex >= endIndex) return null
+    if (startIndex == 0 && endIndex == count) return this
+    val first = text.offsetByCodePoints(0, startIndex)
+    val last = text.offsetByCodePoints(first, endIndex - startIndex)
+    return copy(
+        colStart = colStart + startIndex * width,
+        text = text.substring(first, last),
+        cells = (endIndex - startIndex) * width
+    )
+}
+
+/**
+ * Rows [row] to [row] + [rows] - 1, columns [col] to [col] + [cols] - 1 of
+ * the screen showing image [imageId], which is [imageRows] rows tall;
+ * [imageRow] is its row at the top. Ask the backend for the pixels by id.
+ */
+data class ImagePlacement(
+    val imageId: Int,
+    val col: Int,
+    val row: Int,
+    val rows: Int,
+    val cols: Int,
+    val imageRow: Int,
+    val imageRows: Int
+)
+
+/**
+ * A placement of [TerminalFrame.images] a diff added, moved (scrolled or
+ * cut short; it replaces the one with the same [ImagePlacement.imageId]
+ * and [ImagePlacement.imageRow]) or removed.
+ */
+data class ImageChange(val kind: Kind, val placement: ImagePlacement) {
+    enum class Kind { ADDED, MOVED, REMOVED }
+}
+
+/**
+ * New content of a diff's dirty lines, for backends that send it along.
+ */
+data class DiffContent(
+    val cols: Int,
+    val rows: Int,
+    val cursor: Cursor,
+    val lines: Map<Int, TerminalLine>,
+    /**
+     * Rows whose line in [lines] covers only these columns; the rest of the
+     * row is unchanged. Rows not here are sent whole.
+     */
+    val spans: Map<Int, IntRange> = emptyMap(),
+    val altScreenActive: Boolean = false
+)
+
 /**
  * A diff representing changes to a terminal frame.
  * Used for efficient updates when only parts of the terminal changed.
@@ -50,9 +195,30 @@ data class TerminalFrame(
 data class TerminalDiff(
     val dirtyLines: Set<Int> = emptySet(),
     val cursorChanged: Boolean = false,
+    /** The cursor's shape, blink or visibility changed */
+    val cursorStyleChanged: Boolean = false,
     val titleChanged: Boolean = false,
     val resized: Boolean = false,
-    val fullRedraw: Boolean = false
+    /** Switched to or from the alternate screen; every row is dirty */
+    val altScreenChanged: Boolean = false,
+    /**
+     * The program changed its input modes (bracketed paste, mouse
+     * reporting, application keys); the backend has the new ones
+     */
+    val modesChanged: Boolean = false,
+    val fullRedraw: Boolean = false,
+    /** Set when the backend sends the changed lines with the diff */
+    val content: DiffContent? = null,
+    /**
+     * For backends that track damage, the changed columns of each row in
+     * [dirtyLines], left to right; only those cells need redrawing
+     */
+    val damage: Map<Int, List<IntRange>> = emptyMap(),
+    /**
+     * How [TerminalFrame.images] changed, removals first, or null if it
+     * didn't
+     */
+    val imageChanges: List<ImageChange>? = null
 ) {
     companion object {
         val NONE = TerminalDiff()
@@ -0,0 +1,40 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Native allocations by subsystem, from a debug build of the library with
+ * the `alloc-stats` feature. Frees aren't attributed, so [liveBytes] and
+ * [peakBytes] are for the whole library.
+ */
+data class AvtAllocStats(
+    val grid: Counts,
+    val parser: Counts,
+    val encoder: Counts,
+    val other: Counts,
+    val liveBytes: Long,
+    val peakBytes: Long
+) {
+    /** Allocations (reallocations included) and the bytes they asked for. */
+    data class Counts(val allocations: Long, val bytes: Long)
+
+    /** One line per subsystem, for logs and bug reports. */
+    fun dump(): String = buildString {
+        val subsystems = listOf("grid" to grid, "parser" to parser, "encoder" to encoder, "other" to other)
+        for ((name, counts) in subsystems) {
+            append("$name: ${counts.allocations} allocations, ${counts.bytes} bytes\n")
+        }
+        append("live: $liveBytes bytes, peak: $peakBytes bytes")
+    }
+
+    companion object {
+        /**
+         * The counters now.
+         * @throws UnsatisfiedLinkError if the library wasn't built with
+         *   `alloc-stats`
+         */
+        fun read(): AvtAllocStats {
+            val values = AvtNative.vtAllocStats()
+            val counts = { i: Int -> Counts(values[2 * i], values[2 * i + 1]) }
+            return AvtAllocStats(counts(0), counts(1), counts(2), counts(3), values[8], values[9])
+        }
+    }
+}
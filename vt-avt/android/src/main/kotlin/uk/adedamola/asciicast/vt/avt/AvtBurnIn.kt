@@ -0,0 +1,45 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Screen-burn protection for an always-on display (see
+ * `rust/src/burnin.rs`): the frame walks up to [shiftPx] pixels around its
+ * resting place, a pixel every [shiftPeriodMillis], and rows unchanged for
+ * [dimAfterMillis] fade [dimLevel] (of 255) of the way to their background.
+ * The default does nothing.
+ */
+data class AvtBurnInProtection(
+    val shiftPx: Int = 0,
+    val shiftPeriodMillis: Int = 0,
+    val dimAfterMillis: Int = 0,
+    val dimLevel: Int = 0
+) {
+    /** The layout [AvtNative.vtBurnIn] takes. */
+    fun toInts(): IntArray = intArrayOf(shiftPx, shiftPeriodMillis, dimAfterMillis, dimLevel)
+
+    companion object {
+        /** A pixel a minute within 4, and static rows halved after 5 minutes. */
+        val AMBIENT = AvtBurnInProtection(
+            shiftPx = 4,
+            shiftPeriodMillis = 60_000,
+            dimAfterMillis = 300_000,
+            dimLevel = 128
+        )
+    }
+}
+
+/**
+ * What to apply to one ambient frame: draw everything [dx], [dy] pixels
+ * off (inside a [AvtBurnInProtection.shiftPx] margin), and fade each row's
+ * resolved foreground [rowDims] (of 255) toward its background.
+ */
+data class AvtBurnInFrame(val dx: Int, val dy: Int, val rowDims: IntArray) {
+    companion object {
+        internal fun of(values: IntArray): AvtBurnInFrame? =
+            if (values.size < 2) null else AvtBurnInFrame(values[0], values[1], values.copyOfRange(2, values.size))
+    }
+
+    override fun equals(other: Any?): Boolean =
+        other is AvtBurnInFrame && dx == other.dx && dy == other.dy && rowDims.contentEquals(other.rowDims)
+
+    override fun hashCode(): Int = (dx * 31 + dy) * 31 + rowDims.contentHashCode()
+}
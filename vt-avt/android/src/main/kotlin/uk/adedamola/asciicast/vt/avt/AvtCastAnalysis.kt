@@ -0,0 +1,80 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.nio.ByteBuffer
+
+/**
+ * What's in a recording and when it's busy, from [of]. Times are playback
+ * times (idle time limit applied, as for [AvtVirtualTerminal.seek]), but
+ * the idle gaps are the pauses of a second or more as recorded.
+ */
+data class AvtCastAnalysis(
+    val durationMicros: Long,
+    /** Duration as recorded, without the idle time limit */
+    val recordedMicros: Long,
+    val outputEvents: Long,
+    val inputEvents: Long,
+    val markers: Long,
+    val resizes: Long,
+    val otherEvents: Long,
+    val outputBytes: Long,
+    val gapCount: Long,
+    val gapTotalMicros: Long,
+    val longestGapMicros: Long,
+    /** Playback time where the longest gap starts */
+    val longestGapAtMicros: Long,
+    /** Length of each [histogram] bucket */
+    val bucketMicros: Long,
+    /** Output bytes in each bucket of playback time, from 0 */
+    val histogram: List<Long>
+) {
+    val events: Long
+        get() = outputEvents + inputEvents + markers + resizes + otherEvents
+
+    /**
+     * Start times of the [count] buckets with the most output, busiest
+     * first; buckets without any are left out.
+     */
+    fun busiest(count: Int): List<Long> =
+        histogram.indices
+            .filter { histogram[it] > 0 }
+            .sortedByDescending { histogram[it] }
+            .take(count)
+            .map { it * bucketMicros }
+
+    companion object {
+        /**
+         * Analyze the cast [castHandle] (from [AvtNative.castOpen]), its
+         * output split into [buckets] stretches, e.g. one per pixel of the
+         * activity strip.
+         */
+        fun of(castHandle: Long, buckets: Int): AvtCastAnalysis {
+            val buffer = ByteBuffer.wrap(AvtNative.castAnalyze(castHandle, buckets))
+            require(buffer.hasRemaining()) { "invalid cast handle" }
+
+            val fields = LongArray(13) { buffer.readVarint() }
+            val histogram = List(buffer.readVarint().toInt()) { buffer.readVarint() }
+            return AvtCastAnalysis(
+                fields[0], fields[1], fields[2], fields[3], fields[4], fields[5], fields[6],
+                fields[7], fields[8], fields[9], fields[10], fields[11], fields[12], histogram
+            )
+        }
+
+        private fun ByteBuffer.readVarint(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
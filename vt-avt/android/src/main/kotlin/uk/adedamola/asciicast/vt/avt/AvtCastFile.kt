@@ -0,0 +1,192 @@
+package uk.adedamola.asciicast.vt.avt
+
+import android.content.res.AssetFileDescriptor
+import android.os.ParcelFileDescriptor
+import uk.adedamola.asciicast.vt.TermEvent
+import uk.adedamola.asciicast.vt.TimedTermEvent
+import java.io.IOException
+import java.nio.ByteBuffer
+
+/**
+ * asciicast v2 recording parsed natively, a batch of events per JNI call.
+ *
+ * A faster alternative to the Kotlin `AsciicastParser` for long
+ * recordings. Only output, input, marker and resize events are delivered.
+ *
+ * Thread safety: Not thread-safe.
+ */
+class AvtCastFile private constructor(private var handle: Long) : AutoCloseable {
+
+    companion object {
+        /** Open the recording at [path]. */
+        fun open(path: String): AvtCastFile =
+            AvtCastFile(AvtNative.castFileOpen(path).also {
+                if (it == 0L) throw IOException("can't open asciicast v2 file $path")
+            })
+
+        /**
+         * Open a recording by reading only its header, for a fast first
+         * frame; [finish] parses the rest. Takes over [file], which is
+         * closed when this is.
+         */
+        fun openHeader(file: ParcelFileDescriptor): AvtCastFile =
+            AvtCastFile(AvtNative.castOpenHeader(file.detachFd()).also {
+                if (it == 0L) throw IOException("not an asciicast v2 recording")
+            })
+
+        /**
+         * Stream a recording from [source], e.g. encrypted storage or an
+         * [AvtHttpCastSource]. Reads the header now, so call it where
+         * [source] may block.
+         */
+        fun open(source: AvtCastSource): AvtCastFile =
+            AvtCastFile(AvtNative.castOpenSource(source).also {
+                if (it == 0L) throw IOException("can't read an asciicast v2 recording from the source")
+            })
+
+        /**
+         * Open an uncompressed asset (from `AssetManager.openFd`) without
+         * copying it. Takes over [asset], which is closed when this is.
+         */
+        fun open(asset: AssetFileDescriptor): AvtCastFile {
+            val fd = asset.parcelFileDescriptor.detachFd()
+            return AvtCastFile(AvtNative.castOpenRange(fd, asset.startOffset, asset.declaredLength).also {
+                if (it == 0L) throw IOException("not an asciicast v2 recording")
+            })
+        }
+
+        /**
+         * Follow a recording still being written to [file]; read it with
+         * [pollNewEvents]. Events show up as the writer flushes them. Takes
+         * over [file], which is closed when this is.
+         */
+        fun follow(file: ParcelFileDescriptor): AvtCastFile =
+            AvtCastFile(AvtNative.castOpenFollow(file.detachFd()).also {
+                if (it == 0L) throw IOException("not an asciicast recording, or its header isn't written yet")
+            })
+
+        /**
+         * Open [file] mapped into memory, as it is now. Takes over [file],
+         * which is closed once mapped.
+         */
+        fun openMapped(file: ParcelFileDescriptor): AvtCastFile =
+            AvtCastFile(AvtNative.castOpenMapped(file.detachFd()).also {
+                if (it == 0L) throw IOException("can't map an asciicast v2 recording")
+            })
+
+        /**
+         * Open a recording encrypted with [key] (see
+         * [AvtNative.castWriteEncrypted]), decrypted as it's read. Takes
+         * over [file], which is closed when this is. Needs the native
+         * `encryption` feature.
+         */
+        fun openEncrypted(file: ParcelFileDescriptor, key: ByteArray): AvtCastFile =
+            AvtCastFile(AvtNative.castOpenEncrypted(file.detachFd(), key).also {
+                if (it == 0L) throw IOException("not an encrypted recording, or the wrong key")
+            })
+
+        /** Open a recording already in memory (an asset, a download). */
+        fun fromBytes(bytes: ByteArray): AvtCastFile =
+            AvtCastFile(AvtNative.castFileOpenBytes(bytes).also {
+                require(it != 0L) { "not an asciicast v2 recording" }
+            })
+    }
+
+    /** The header line as JSON, keys the native side ignores included. */
+    val headerJson: String = AvtNative.castFileHeader(handle) ?: "{}"
+
+    private var previousMicros = 0L
+
+    /**
+     * The remaining events, read [batchSize] at a time. `deltaMicros` is
+     * measured from the previous event delivered. Throws [IOException] at
+     * the end if a malformed line cut the recording short.
+     */
+    fun events(batchSize: Int = 256): Sequence<TimedTermEvent> = sequence {
+        require(batchSize > 0) { "batchSize must be positive" }
+        while (true) {
+            val batch = nextBatch(batchSize)
+            if (batch.isEmpty()) break
+            yieldAll(batch)
+        }
+        AvtNative.castFileError(handle)?.let { throw IOException(it) }
+    }
+
+    /**
+     * The events not yet delivered, parsed into a cast handle (free with
+     * [AvtNative.castFree]). Reads to the end of the file, so call it from
+     * a background job, not the main thread.
+     * @throws IOException if a malformed line cuts the recording short
+     */
+    fun finish(): Long {
+        val cast = AvtNative.castFileFinish(handle)
+        if (cast == 0L) throw IOException(AvtNative.castFileError(handle) ?: "invalid handle")
+        return cast
+    }
+
+    /**
+     * The events appended since the last poll of a [follow]ed recording,
+     * empty if nothing complete has been written since. Call it when the
+     * file changes (a `FileObserver`) or on a timer.
+     * @throws IOException once a malformed line has ended the recording
+     */
+    fun pollNewEvents(): List<TimedTermEvent> {
+        val events = decodeBatch(AvtNative.castPollNewEvents(handle))
+        if (events.isEmpty()) {
+            AvtNative.castFileError(handle)?.let { throw IOException(it) }
+        }
+        return events
+    }
+
+    private fun nextBatch(maxCount: Int): List<TimedTermEvent> =
+        decodeBatch(AvtNative.castFileNextEvents(handle, maxCount))
+
+    private fun decodeBatch(bytes: ByteArray): List<TimedTermEvent> {
+        val buffer = ByteBuffer.wrap(bytes)
+        if (!buffer.hasRemaining()) {
+            return emptyList()
+        }
+
+        return List(buffer.readVarint().toInt()) {
+            val micros = buffer.readVarint()
+            val code = buffer.get().toInt().toChar()
+            val payload = ByteArray(buffer.readVarint().toInt())
+            buffer.get(payload)
+
+            val event = when (code) {
+                'o' -> TermEvent.Output(String(payload, Charsets.UTF_8))
+                'i' -> TermEvent.Input(String(payload, Charsets.UTF_8))
+                'm' -> TermEvent.Marker(String(payload, Charsets.UTF_8))
+                else -> ByteBuffer.wrap(payload).let {
+                    TermEvent.Resize(cols = it.readVarint().toInt(), rows = it.readVarint().toInt())
+                }
+            }
+            TimedTermEvent(event, micros - previousMicros).also { previousMicros = micros }
+        }
+    }
+
+    override fun close() {
+        if (handle != 0L) {
+            AvtNative.castFileFree(handle)
+            handle = 0
+        }
+    }
+
+    private fun ByteBuffer.readVarint(): Long {
+        var result = 0L
+        var shift = 0
+
+        while (true) {
+            val byte = get().toInt() and 0xFF
+            result = result or ((byte and 0x7F).toLong() shl shift)
+
+            if ((byte and 0x80) == 0) {
+                break
+            }
+
+            shift += 7
+        }
+
+        return result
+    }
+}
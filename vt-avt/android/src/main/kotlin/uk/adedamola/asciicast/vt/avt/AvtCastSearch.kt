@@ -0,0 +1,50 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.nio.ByteBuffer
+
+/**
+ * Where and when a query appeared in a recording, from [search]: at
+ * [timeMicros] of playback time (for [AvtVirtualTerminal.seek]) on screen
+ * row [row], char [col] of the row's text [line].
+ */
+data class CastSearchHit(val timeMicros: Long, val row: Int, val col: Int, val line: String) {
+
+    companion object {
+        /**
+         * Every time [query] appeared while playing the cast [castHandle]
+         * (from [AvtNative.castOpen]), in order. Replays the whole
+         * recording: call it off the main thread.
+         */
+        fun search(castHandle: Long, query: String): List<CastSearchHit> {
+            val buffer = ByteBuffer.wrap(AvtNative.castSearch(castHandle, query))
+            require(buffer.hasRemaining()) { "invalid cast handle" }
+
+            var timeMicros = 0L
+            return List(buffer.readVarint().toInt()) {
+                timeMicros += buffer.readVarint()
+                val row = buffer.readVarint().toInt()
+                val col = buffer.readVarint().toInt()
+                val line = ByteArray(buffer.readVarint().toInt()).also { buffer.get(it) }
+                CastSearchHit(timeMicros, row, col, String(line, Charsets.UTF_8))
+            }
+        }
+
+        private fun ByteBuffer.readVarint(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
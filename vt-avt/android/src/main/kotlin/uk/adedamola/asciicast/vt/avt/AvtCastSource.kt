@@ -0,0 +1,66 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.io.IOException
+import java.net.HttpURLConnection
+import java.net.URL
+
+/**
+ * Random-access storage a recording is streamed from natively, for
+ * [AvtCastFile.open], e.g. decrypting app storage a block at a time so no
+ * plaintext copy is written. Called on the thread reading the cast file;
+ * an exception fails the read.
+ */
+interface AvtCastSource {
+    /** Up to [maxBytes] bytes at [offset]; empty at the end. */
+    fun readAt(offset: Long, maxBytes: Int): ByteArray
+
+    /** Bytes in the source as of now. */
+    fun length(): Long
+
+    /**
+     * The new [length] if the source grew since it was last asked, for a
+     * recording still being written or downloaded, otherwise -1.
+     */
+    fun pollAppend(): Long = -1
+}
+
+/**
+ * A recording served over HTTP, read with range requests. The server must
+ * send `Content-Length` and honor `Range`. Blocks on the network: read it
+ * off the main thread.
+ */
+class AvtHttpCastSource(
+    private val url: URL,
+    private val connect: (URL) -> HttpURLConnection = { it.openConnection() as HttpURLConnection }
+) : AvtCastSource {
+
+    private val length: Long by lazy {
+        val connection = connect(url).apply { requestMethod = "HEAD" }
+        try {
+            if (connection.responseCode !in 200..299) {
+                throw IOException("HTTP ${connection.responseCode} for $url")
+            }
+            connection.contentLengthLong.also {
+                if (it < 0) throw IOException("no Content-Length for $url")
+            }
+        } finally {
+            connection.disconnect()
+        }
+    }
+
+    override fun length(): Long = length
+
+    override fun readAt(offset: Long, maxBytes: Int): ByteArray {
+        if (offset >= length || maxBytes <= 0) return ByteArray(0)
+        val last = minOf(length, offset + maxBytes) - 1
+        val connection = connect(url).apply { setRequestProperty("Range", "bytes=$offset-$last") }
+        try {
+            if (connection.responseCode != HttpURLConnection.HTTP_PARTIAL) {
+                throw IOException("HTTP ${connection.responseCode} for a range of $url")
+            }
+            return connection.inputStream.use { it.readBytes() }
+        } finally {
+            connection.disconnect()
+        }
+    }
+}
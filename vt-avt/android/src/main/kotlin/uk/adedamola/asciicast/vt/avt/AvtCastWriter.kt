@@ -0,0 +1,57 @@
+package uk.adedamola.asciicast.vt.avt
+
+import android.os.ParcelFileDescriptor
+import java.io.Closeable
+import java.io.IOException
+
+/**
+ * Writes an asciicast v2 file from a stream the app feeds itself, such
+ * as a local shell or an SSH channel, with the stream's own times. Not
+ * thread-safe: feed it from the thread reading the stream.
+ */
+class AvtCastWriter private constructor(private var handle: Long) : Closeable {
+    companion object {
+        /**
+         * Record a [cols] x [rows] session to a new file at [path], as
+         * asciicast [version] 2 or 3.
+         */
+        fun create(cols: Int, rows: Int, path: String, version: Int = 2): AvtCastWriter =
+            AvtCastWriter(AvtNative.recNew(cols, rows, version, path).also {
+                if (it == 0L) throw IOException("can't create $path")
+            })
+
+        /** Record to [file], which is taken over and closed with this. */
+        fun create(
+            cols: Int,
+            rows: Int,
+            file: ParcelFileDescriptor,
+            version: Int = 2
+        ): AvtCastWriter =
+            AvtCastWriter(AvtNative.recNewFd(cols, rows, version, file.detachFd()).also {
+                if (it == 0L) throw IOException("can't start a recording")
+            })
+    }
+
+    fun output(timeMicros: Long, bytes: ByteArray) =
+        written(AvtNative.recWriteOutput(live(), timeMicros, bytes))
+
+    fun marker(timeMicros: Long, label: String) =
+        written(AvtNative.recMarker(live(), timeMicros, label))
+
+    fun resize(timeMicros: Long, cols: Int, rows: Int) =
+        written(AvtNative.recResize(live(), timeMicros, cols, rows))
+
+    /** Flush and close the file; throws if the final write failed. */
+    override fun close() {
+        if (handle == 0L) return
+        val finished = AvtNative.recFinish(handle)
+        handle = 0L
+        if (!finished) throw IOException("recording not fully written")
+    }
+
+    private fun live(): Long = handle.also { check(it != 0L) { "writer closed" } }
+
+    private fun written(ok: Boolean) {
+        if (!ok) throw IOException("recording write failed")
+    }
+}
@@ -0,0 +1,44 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.nio.ByteBuffer
+
+/**
+ * A marker in a recording, one chapter of it: [label] (maybe empty) at
+ * [timeMicros] of playback time, for [AvtVirtualTerminal.seek] or by its
+ * index for [AvtVirtualTerminal.seekToMarker].
+ */
+data class AvtChapter(val timeMicros: Long, val label: String) {
+
+    companion object {
+        /** The markers of the cast [castHandle] (from [AvtNative.castOpen]), in order. */
+        fun list(castHandle: Long): List<AvtChapter> {
+            val buffer = ByteBuffer.wrap(AvtNative.castMarkers(castHandle))
+            require(buffer.hasRemaining()) { "invalid cast handle" }
+
+            var timeMicros = 0L
+            return List(buffer.readVarint().toInt()) {
+                timeMicros += buffer.readVarint()
+                val label = ByteArray(buffer.readVarint().toInt()).also { buffer.get(it) }
+                AvtChapter(timeMicros, String(label, Charsets.UTF_8))
+            }
+        }
+
+        private fun ByteBuffer.readVarint(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
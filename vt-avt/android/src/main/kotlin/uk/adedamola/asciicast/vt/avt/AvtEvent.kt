@@ -0,0 +1,207 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.nio.ByteBuffer
+import java.nio.ByteOrder
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Events
+
+/**
+ * Something the terminal did that the app acts on rather than draws, from
+ * [AvtVirtualTerminal.takeEvents]. Mirrors `rust/src/events.rs`, which
+ * documents the wire format.
+ */
+sealed interface AvtEvent {
+    data object Bell : AvtEvent
+
+    /** Window title (OSC 0 or 2) */
+    data class Title(val title: String) : AvtEvent
+
+    /** Marker event in the recording */
+    data class Marker(val label: String) : AvtEvent
+
+    /** Resize event in the recording */
+    data class Resize(val cols: Int, val rows: Int) : AvtEvent
+
+    /**
+     * An image sequence to render: the DCS payload for sixel, the OSC
+     * payload from `File=` for iTerm2.
+     * @property protocol One of [IMAGE_SIXEL], [IMAGE_ITERM]
+     */
+    class Image(val protocol: Int, val data: ByteArray) : AvtEvent
+
+    /** Clipboard write (OSC 52), [base64Data] as sent. Reads never appear. */
+    data class Clipboard(val selection: String, val base64Data: String) : AvtEvent
+
+    /** Desktop notification (OSC 9, or OSC 777 notify) */
+    data class Notification(val title: String, val body: String) : AvtEvent
+
+    /** [text] matching the pattern watched as [tag] appeared at [row], [col] */
+    data class Watch(val tag: Int, val row: Int, val col: Int, val text: String) : AvtEvent
+
+    /**
+     * An interactive session changed state, [quietMillis] after its last
+     * output or input (see [AvtNative.vtSetActivityTimeout]).
+     * @property state One of [ACTIVITY_ACTIVE], [ACTIVITY_IDLE], [ACTIVITY_DISCONNECTED]
+     */
+    data class Activity(val state: Int, val quietMillis: Long) : AvtEvent
+
+    /**
+     * Input event in the recording, e.g. keys typed; only queued with
+     * [AvtNative.vtSetInputEvents] on.
+     */
+    data class Input(val text: String) : AvtEvent
+
+    /**
+     * The app set an option of the VT to a new value; the next diff shows
+     * it applied, so a player can note a theme switch without a snapshot.
+     * @property option One of [OPTION_THEME], [OPTION_BOLD_AS_BRIGHT], [OPTION_CURSOR_POLICY]
+     */
+    data class OptionChanged(val option: Int) : AvtEvent
+
+    /**
+     * Image [id] was decoded and given [cols] × [rows] cells;
+     * [AvtVirtualTerminal.image] has its pixels and the next diff its
+     * placement.
+     */
+    data class ImageAdded(val id: Int, val cols: Int, val rows: Int) : AvtEvent
+
+    /** No cell shows image [id] any more, so its pixels can be dropped. */
+    data class ImageRemoved(val id: Int) : AvtEvent
+
+    companion object {
+        /** Wire format version this decoder reads */
+        const val VERSION = Events.VERSION
+
+        /** Version of the timed batches from `vtPollEvents` */
+        const val VERSION_TIMED = Events.VERSION_TIMED
+
+        const val IMAGE_SIXEL = Events.IMAGE_SIXEL
+        const val IMAGE_ITERM = Events.IMAGE_ITERM
+
+        const val ACTIVITY_ACTIVE = Events.ACTIVITY_ACTIVE
+        const val ACTIVITY_IDLE = Events.ACTIVITY_IDLE
+        const val ACTIVITY_DISCONNECTED = Events.ACTIVITY_DISCONNECTED
+
+        const val OPTION_THEME = Events.OPTION_THEME
+        const val OPTION_BOLD_AS_BRIGHT = Events.OPTION_BOLD_AS_BRIGHT
+        const val OPTION_CURSOR_POLICY = Events.OPTION_CURSOR_POLICY
+
+        /**
+         * Decode a `vtTakeEvents` batch. Tags this decoder doesn't know are
+         * skipped, as are payload bytes past the fields it reads.
+         * @throws IllegalArgumentException for another version
+         */
+        fun decode(bytes: ByteArray): List<AvtEvent> = decode(bytes, VERSION).map { it.event }
+
+        /**
+         * Decode a `vtPollEvents` batch, as [decode] does.
+         * @throws IllegalArgumentException for another version
+         */
+        fun decodeTimed(bytes: ByteArray): List<TimedAvtEvent> = decode(bytes, VERSION_TIMED)
+
+        private fun decode(bytes: ByteArray, expected: Int): List<TimedAvtEvent> {
+            if (bytes.isEmpty()) {
+                return emptyList()
+            }
+            val buffer = ByteBuffer.wrap(bytes).order(ByteOrder.LITTLE_ENDIAN)
+            val version = buffer.get().toInt() and 0xFF
+            require(version == expected) { "unsupported event format version $version" }
+
+            val count = buffer.readVarint()
+            val events = ArrayList<TimedAvtEvent>(minOf(count, bytes.size / 2))
+            repeat(count) {
+                val tag = buffer.get().toInt() and 0xFF
+                val timeMicros = if (version == VERSION_TIMED) buffer.getLong() else 0L
+                val payload = ByteArray(buffer.readVarint())
+                buffer.get(payload)
+                decodePayload(tag, ByteBuffer.wrap(payload))?.let {
+                    events.add(TimedAvtEvent(timeMicros, it))
+                }
+            }
+            return events
+        }
+
+        private fun decodePayload(tag: Int, payload: ByteBuffer): AvtEvent? = when (tag) {
+            Events.TAG_BELL -> Bell
+            Events.TAG_TITLE -> Title(payload.restText())
+            Events.TAG_MARKER -> Marker(payload.restText())
+            Events.TAG_RESIZE -> Resize(cols = payload.readVarint(), rows = payload.readVarint())
+            Events.TAG_IMAGE -> {
+                val protocol = payload.get().toInt() and 0xFF
+                Image(protocol, ByteArray(payload.remaining()).also { payload.get(it) })
+            }
+            Events.TAG_CLIPBOARD, Events.TAG_NOTIFICATION -> {
+                val first = ByteArray(payload.readVarint()).also { payload.get(it) }
+                val firstText = String(first, Charsets.UTF_8)
+                if (tag == Events.TAG_CLIPBOARD) {
+                    Clipboard(selection = firstText, base64Data = payload.restText())
+                } else {
+                    Notification(title = firstText, body = payload.restText())
+                }
+            }
+            Events.TAG_WATCH -> Watch(
+                tag = payload.readVarint(),
+                row = payload.readVarint(),
+                col = payload.readVarint(),
+                text = payload.restText()
+            )
+            Events.TAG_ACTIVITY -> (payload.get().toInt() and 0xFF).takeIf { it <= ACTIVITY_DISCONNECTED }?.let {
+                Activity(state = it, quietMillis = payload.readVarintLong())
+            }
+            Events.TAG_INPUT -> Input(payload.restText())
+            Events.TAG_OPTION -> OptionChanged(payload.get().toInt() and 0xFF)
+            Events.TAG_IMAGE_ADDED -> ImageAdded(
+                id = payload.readVarint(),
+                cols = payload.readVarint(),
+                rows = payload.readVarint()
+            )
+            Events.TAG_IMAGE_REMOVED -> ImageRemoved(payload.readVarint())
+            else -> null
+        }
+
+        private fun ByteBuffer.restText(): String =
+            String(ByteArray(remaining()).also { get(it) }, Charsets.UTF_8)
+
+        private fun ByteBuffer.readVarintLong(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+
+        private fun ByteBuffer.readVarint(): Int {
+            var result = 0
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F) shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
+
+/**
+ * An [AvtEvent] and when it was queued, from
+ * [AvtVirtualTerminal.pollEvents]: the playback time during playback,
+ * otherwise microseconds since the VT was created.
+ */
+data class TimedAvtEvent(val timeMicros: Long, val event: AvtEvent)
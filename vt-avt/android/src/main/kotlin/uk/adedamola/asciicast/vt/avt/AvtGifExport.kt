@@ -0,0 +1,45 @@
+package uk.adedamola.asciicast.vt.avt
+
+import android.os.ParcelFileDescriptor
+import uk.adedamola.asciicast.vt.Theme
+import java.io.IOException
+
+/** Told of every frame [AvtGifExport.export] draws; return false to stop. */
+fun interface AvtProgress {
+    fun onProgress(framesDone: Int, frames: Int): Boolean
+}
+
+/**
+ * Animated GIF clips of a recording, drawn natively with the built-in
+ * bitmap font. Needs the native `renderer` feature.
+ */
+object AvtGifExport {
+    /**
+     * Write [startMicros] until [endMicros] of the cast [castHandle] (from
+     * [AvtNative.castOpen]) to [file] as a looping GIF at [fps] frames a
+     * second, at most 50, no wider than [maxWidthPx] (0 for no limit).
+     * Replays and draws every frame: call it off the main thread. [file]
+     * stays open. Returns the bytes written; throws [IOException] if the
+     * write failed or [progress] stopped the export, leaving part of a GIF.
+     */
+    fun export(
+        castHandle: Long,
+        file: ParcelFileDescriptor,
+        startMicros: Long,
+        endMicros: Long,
+        fps: Int = 10,
+        theme: Theme = Theme.DEFAULT,
+        maxWidthPx: Int = 0,
+        progress: AvtProgress? = null
+    ): Long {
+        require(castHandle != 0L) { "invalid cast handle" }
+        require(endMicros > startMicros) { "empty clip" }
+        require(fps in 1..50) { "fps must be 1 to 50" }
+        val written = AvtNative.castExportGif(
+            castHandle, startMicros, endMicros, fps, AvtStyles.paletteOf(theme), maxWidthPx,
+            file.fd, progress
+        )
+        if (written < 0) throw IOException("GIF export failed or was stopped")
+        return written
+    }
+}
@@ -0,0 +1,492 @@
+// Generated from rust/src/binding_tests.rs; don't edit. Regenerate with
+// `BLESS=1 cargo test binding_tests` or `./gradlew generateAvtHandles`.
+
+package uk.adedamola.asciicast.vt.avt
+
+import androidx.annotation.AnyThread
+import androidx.annotation.WorkerThread
+import java.io.Closeable
+
+/**
+ * A recording parsed into memory, to analyze, cut and export.
+ *
+ * Thread safety: one thread at a time; most calls walk the whole
+ * recording.
+ */
+@WorkerThread
+class AvtCast internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.castOpen], null if that fails. */
+        fun open(castBytes: ByteArray): AvtCast? =
+            AvtNative.castOpen(castBytes)
+                .takeIf { it != 0L }
+                ?.let(::AvtCast)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtCast is closed" }
+            return raw
+        }
+
+    /** [AvtNative.castNormalizeTiming] */
+    fun normalizeTiming(idleLimitMicros: Long, speed: Double): Long =
+        AvtNative.castNormalizeTiming(handle, idleLimitMicros, speed)
+
+    /** [AvtNative.castScanDimensions] */
+    fun scanDimensions(): IntArray =
+        AvtNative.castScanDimensions(handle)
+
+    /** [AvtNative.castSplitByMarkers] */
+    fun splitByMarkers(): List<AvtCast> =
+        AvtNative.castSplitByMarkers(handle).map(::AvtCast)
+
+    /** [AvtNative.castSplitByCommands] */
+    fun splitByCommands(): List<AvtCast> =
+        AvtNative.castSplitByCommands(handle).map(::AvtCast)
+
+    /** [AvtNative.castAppendWithGap] */
+    fun appendWithGap(b: AvtCast, gapMicros: Long): AvtCast? =
+        AvtNative.castAppendWithGap(handle, b.handle, gapMicros)
+            .takeIf { it != 0L }
+            ?.let(::AvtCast)
+
+    /** [AvtNative.castRebase] */
+    fun rebase(firstEventMicros: Long) {
+        AvtNative.castRebase(handle, firstEventMicros)
+    }
+
+    /** [AvtNative.castCheckMonotonic] */
+    fun checkMonotonic(): Int =
+        AvtNative.castCheckMonotonic(handle)
+
+    /** [AvtNative.castFindStalls] */
+    fun findStalls(minMicros: Long): LongArray =
+        AvtNative.castFindStalls(handle, minMicros)
+
+    /** [AvtNative.castSequenceStats] */
+    fun sequenceStats(): LongArray =
+        AvtNative.castSequenceStats(handle)
+
+    /** [AvtNative.castHighlights] */
+    fun highlights(targetMicros: Long): LongArray =
+        AvtNative.castHighlights(handle, targetMicros)
+
+    /** [AvtNative.castAnalyze] */
+    fun analyze(buckets: Int): ByteArray =
+        AvtNative.castAnalyze(handle, buckets)
+
+    /** [AvtNative.castSampleFrames] */
+    fun sampleFrames(maxFps: Double, redrawFps: Double): LongArray =
+        AvtNative.castSampleFrames(handle, maxFps, redrawFps)
+
+    /** [AvtNative.castPosterTime] */
+    fun posterTime(): Long =
+        AvtNative.castPosterTime(handle)
+
+    /** [AvtNative.castSearch] */
+    fun search(query: String): ByteArray =
+        AvtNative.castSearch(handle, query)
+
+    /** [AvtNative.castSimilarity] */
+    fun similarity(b: AvtCast): Double =
+        AvtNative.castSimilarity(handle, b.handle)
+
+    /** [AvtNative.castMarkers] */
+    fun markers(): ByteArray =
+        AvtNative.castMarkers(handle)
+
+    /** [AvtNative.castWriteEncrypted] */
+    fun writeEncrypted(fd: Int, key: ByteArray): Boolean =
+        AvtNative.castWriteEncrypted(handle, fd, key)
+
+    /** [AvtNative.castExportSigned] */
+    fun exportSigned(fd: Int, seed: ByteArray): Boolean =
+        AvtNative.castExportSigned(handle, fd, seed)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.castFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * Edits of a copy of an [AvtCast], with undo and redo.
+ *
+ * Thread safety: one thread at a time; an undo replays the edits
+ * left.
+ */
+@WorkerThread
+class AvtEditSession internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.editSessionNew], null if that fails. */
+        fun open(cast: AvtCast): AvtEditSession? =
+            AvtNative.editSessionNew(cast.handle)
+                .takeIf { it != 0L }
+                ?.let(::AvtEditSession)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtEditSession is closed" }
+            return raw
+        }
+
+    /** [AvtNative.editTrim] */
+    fun trim(startMicros: Long, endMicros: Long) {
+        AvtNative.editTrim(handle, startMicros, endMicros)
+    }
+
+    /** [AvtNative.editSplice] */
+    fun splice(atMicros: Long, cast: AvtCast) {
+        AvtNative.editSplice(handle, atMicros, cast.handle)
+    }
+
+    /** [AvtNative.editRedact] */
+    fun redact(text: String) {
+        AvtNative.editRedact(handle, text)
+    }
+
+    /** [AvtNative.editIdleCap] */
+    fun idleCap(maxMicros: Long) {
+        AvtNative.editIdleCap(handle, maxMicros)
+    }
+
+    /** [AvtNative.editUndo] */
+    fun undo(): Boolean =
+        AvtNative.editUndo(handle)
+
+    /** [AvtNative.editRedo] */
+    fun redo(): Boolean =
+        AvtNative.editRedo(handle)
+
+    /** [AvtNative.editCommit] */
+    fun commit(): AvtCast? =
+        AvtNative.editCommit(handle)
+            .takeIf { it != 0L }
+            ?.let(::AvtCast)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.editSessionFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * An edit decision list: segments of source casts, redactions and
+ * an idle cap, rendered on demand.
+ *
+ * Thread safety: one thread at a time.
+ */
+@WorkerThread
+class AvtEdl internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.edlNew], null if that fails. */
+        fun create(): AvtEdl? =
+            AvtNative.edlNew()
+                .takeIf { it != 0L }
+                ?.let(::AvtEdl)
+
+        /** [AvtNative.edlLoad], null if that fails. */
+        fun load(edlBytes: ByteArray): AvtEdl? =
+            AvtNative.edlLoad(edlBytes)
+                .takeIf { it != 0L }
+                ?.let(::AvtEdl)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtEdl is closed" }
+            return raw
+        }
+
+    /** [AvtNative.edlSave] */
+    fun save(): ByteArray =
+        AvtNative.edlSave(handle)
+
+    /** [AvtNative.edlAddSegment] */
+    fun addSegment(source: Int, startMicros: Long, endMicros: Long) {
+        AvtNative.edlAddSegment(handle, source, startMicros, endMicros)
+    }
+
+    /** [AvtNative.edlAddRedaction] */
+    fun addRedaction(text: String) {
+        AvtNative.edlAddRedaction(handle, text)
+    }
+
+    /** [AvtNative.edlSetIdleCap] */
+    fun setIdleCap(maxMicros: Long) {
+        AvtNative.edlSetIdleCap(handle, maxMicros)
+    }
+
+    /** [AvtNative.edlExport] */
+    fun export(casts: List<AvtCast>): ByteArray =
+        AvtNative.edlExport(handle, LongArray(casts.size) { casts[it].handle })
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.edlFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * The recording library's index, kept in a file. Needs the native
+ * `library` feature.
+ *
+ * Thread safety: one thread at a time, off the main thread.
+ */
+@WorkerThread
+class AvtLibrary internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.libraryOpen], null if that fails. */
+        fun open(path: String): AvtLibrary? =
+            AvtNative.libraryOpen(path)
+                .takeIf { it != 0L }
+                ?.let(::AvtLibrary)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtLibrary is closed" }
+            return raw
+        }
+
+    /** [AvtNative.librarySave] */
+    fun save(): Boolean =
+        AvtNative.librarySave(handle)
+
+    /** [AvtNative.libraryAdd] */
+    fun add(castBytes: ByteArray): String? =
+        AvtNative.libraryAdd(handle, castBytes)
+
+    /** [AvtNative.libraryRemove] */
+    fun remove(hash: String): Boolean =
+        AvtNative.libraryRemove(handle, hash)
+
+    /** [AvtNative.libraryAddBookmark] */
+    fun addBookmark(hash: String, timeMicros: Long, label: String): Boolean =
+        AvtNative.libraryAddBookmark(handle, hash, timeMicros, label)
+
+    /** [AvtNative.libraryQuery] */
+    fun query(title: String?, minMicros: Long, maxMicros: Long, bookmarkedOnly: Boolean): String? =
+        AvtNative.libraryQuery(handle, title, minMicros, maxMicros, bookmarkedOnly)
+
+    /** [AvtNative.librarySearch] */
+    fun search(query: String, limit: Int): String? =
+        AvtNative.librarySearch(handle, query, limit)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.libraryFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * A screen mirrored from another device, one frame at a time.
+ *
+ * Thread safety: one thread at a time.
+ */
+@AnyThread
+class AvtSyncReceiver internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.syncReceiverNew], null if that fails. */
+        fun create(): AvtSyncReceiver? =
+            AvtNative.syncReceiverNew()
+                .takeIf { it != 0L }
+                ?.let(::AvtSyncReceiver)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtSyncReceiver is closed" }
+            return raw
+        }
+
+    /** [AvtNative.syncReceiverApply] */
+    fun apply(frame: ByteArray): Long =
+        AvtNative.syncReceiverApply(handle, frame)
+
+    /** [AvtNative.syncReceiverSnapshot] */
+    fun snapshot(): ByteArray =
+        AvtNative.syncReceiverSnapshot(handle)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.syncReceiverFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * A raw TCP or telnet console, pumped into a terminal. Needs the
+ * native `net` feature.
+ *
+ * Thread safety: one thread at a time; pumping blocks.
+ */
+@WorkerThread
+class AvtNetConnection internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.netConnect], null if that fails. */
+        fun connect(host: String, port: Int, telnet: Boolean, timeoutMs: Int): AvtNetConnection? =
+            AvtNative.netConnect(host, port, telnet, timeoutMs)
+                .takeIf { it != 0L }
+                ?.let(::AvtNetConnection)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtNetConnection is closed" }
+            return raw
+        }
+
+    /** [AvtNative.netPump] */
+    fun pump(vtHandle: Long, timeoutMs: Int): Int =
+        AvtNative.netPump(handle, vtHandle, timeoutMs)
+
+    /** [AvtNative.netSend] */
+    fun send(bytes: ByteArray): Boolean =
+        AvtNative.netSend(handle, bytes)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.netFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * A shell on a remote PTY over SSH, pumped into a terminal. Needs
+ * the native `ssh` feature.
+ *
+ * Thread safety: one thread at a time; connecting and pumping
+ * block.
+ */
+@WorkerThread
+class AvtSshSession internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.sshConnect], null if that fails. */
+        fun connect(
+            host: String,
+            port: Int,
+            user: String,
+            password: String?,
+            privateKey: String?,
+            passphrase: String?,
+            hostKey: String?,
+            vtHandle: Long,
+            timeoutMs: Int,
+        ): AvtSshSession? =
+            AvtNative.sshConnect(
+                host,
+                port,
+                user,
+                password,
+                privateKey,
+                passphrase,
+                hostKey,
+                vtHandle,
+                timeoutMs,
+            )
+                .takeIf { it != 0L }
+                ?.let(::AvtSshSession)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtSshSession is closed" }
+            return raw
+        }
+
+    /** [AvtNative.sshPump] */
+    fun pump(vtHandle: Long, timeoutMs: Int): Int =
+        AvtNative.sshPump(handle, vtHandle, timeoutMs)
+
+    /** [AvtNative.sshSend] */
+    fun send(bytes: ByteArray): Boolean =
+        AvtNative.sshSend(handle, bytes)
+
+    /** [AvtNative.sshResize] */
+    fun resize(cols: Int, rows: Int): Boolean =
+        AvtNative.sshResize(handle, cols, rows)
+
+    /** [AvtNative.sshHostKey] */
+    fun hostKey(): String =
+        AvtNative.sshHostKey(handle)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.sshFree(raw)
+            raw = 0L
+        }
+    }
+}
+
+/**
+ * A recording being written from a terminal's session; [close]
+ * stops it.
+ *
+ * Thread safety: the terminal's thread only.
+ */
+@AnyThread
+class AvtRecorder internal constructor(private var raw: Long) : Closeable {
+    companion object {
+        /** [AvtNative.recorderStart], null if that fails. */
+        fun start(handle: Long, path: String): AvtRecorder? =
+            AvtNative.recorderStart(handle, path)
+                .takeIf { it != 0L }
+                ?.let(::AvtRecorder)
+
+        /** [AvtNative.recorderStartEncrypted], null if that fails. */
+        fun startEncrypted(handle: Long, fd: Int, key: ByteArray): AvtRecorder? =
+            AvtNative.recorderStartEncrypted(handle, fd, key)
+                .takeIf { it != 0L }
+                ?.let(::AvtRecorder)
+    }
+
+    /** For [AvtNative] functions taking the handle; throws once closed. */
+    internal val handle: Long
+        get() {
+            check(raw != 0L) { "AvtRecorder is closed" }
+            return raw
+        }
+
+    /** [AvtNative.recorderOutput] */
+    fun output(bytes: ByteArray): Boolean =
+        AvtNative.recorderOutput(handle, bytes)
+
+    /** [AvtNative.recorderInput] */
+    fun input(text: String): Boolean =
+        AvtNative.recorderInput(handle, text)
+
+    /** [AvtNative.recorderResize] */
+    fun resize(cols: Int, rows: Int): Boolean =
+        AvtNative.recorderResize(handle, cols, rows)
+
+    /** [AvtNative.recorderMarker] */
+    fun marker(label: String): Boolean =
+        AvtNative.recorderMarker(handle, label)
+
+    override fun close() {
+        if (raw != 0L) {
+            AvtNative.recorderStop(raw)
+            raw = 0L
+        }
+    }
+}
@@ -0,0 +1,13 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * An inline image's pixels, from [AvtVirtualTerminal.image]: [width] by
+ * [height], four bytes of RGBA each, rows top to bottom. Unpremultiplied
+ * unless [premultiplied], as [AvtVirtualTerminal.scaledImage] gives them.
+ */
+class AvtImage(
+    val width: Int,
+    val height: Int,
+    val rgba: ByteArray,
+    val premultiplied: Boolean = false
+)
@@ -0,0 +1,20 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * A live native object, as listed by [AvtNative.vtListHandles], so a debug
+ * screen or session switcher can enumerate what the library holds instead
+ * of mirroring it in Kotlin.
+ *
+ * @property kind One of AvtNative's HANDLE_ constants
+ */
+data class AvtLiveHandle(val kind: Int, val handle: Long, val label: String) {
+
+    companion object {
+        /** The live objects of [kind] and their labels. */
+        fun list(kind: Int): List<AvtLiveHandle> =
+            AvtNative.vtListHandles(kind).mapNotNull { handle ->
+                // Freed by another thread since the listing
+                AvtNative.vtHandleLabel(kind, handle)?.let { AvtLiveHandle(kind, handle, it) }
+            }
+    }
+}
@@ -0,0 +1,43 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Live broadcast from an asciinema server, decoded from its ALiS
+ * WebSocket messages into a terminal of its own.
+ *
+ * Pass every binary message the socket receives to [feed], then poll
+ * [vtHandle] for snapshots and diffs as for a player's terminal. The
+ * terminal is reset in place by each init, so one stream can be kept
+ * across reconnects.
+ *
+ * Thread safety: one thread at a time, the one polling [vtHandle].
+ */
+class AvtLiveStream : AutoCloseable {
+
+    private var handle: Long = AvtNative.streamNew().also {
+        check(it != 0L) { "native stream allocation failed" }
+    }
+
+    /** The stream's terminal; owned by the stream, valid until [close]. */
+    val vtHandle: Long = AvtNative.streamVt(handle)
+
+    /**
+     * Apply one binary message.
+     * @return One of AvtNative's PACKET_ constants
+     */
+    fun feed(message: ByteArray): Int = AvtNative.streamFeedPacket(handle, message)
+
+    /** Stream time of the last message in microseconds, null before the first. */
+    val timeMicros: Long?
+        get() = AvtNative.streamTime(handle).takeIf { it >= 0 }
+
+    /** The broadcaster's palette, as for [AvtNative.vtResolveStyles]; null if it sent none. */
+    val theme: IntArray?
+        get() = AvtNative.streamTheme(handle).takeIf { it.isNotEmpty() }
+
+    override fun close() {
+        if (handle != 0L) {
+            AvtNative.streamFree(handle)
+            handle = 0
+        }
+    }
+}
@@ -0,0 +1,50 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Approximate bytes one VT holds, by part; see
+ * [AvtVirtualTerminal.setLimits] for bounding them. Estimated from what's
+ * held, unlike [AvtAllocStats].
+ */
+data class AvtMemoryUsage(
+    val screen: Long,
+    val scrollback: Long,
+    /** Inline images' RGBA */
+    val images: Long,
+    /** Hyperlink URIs */
+    val links: Long,
+    /** Delta baselines */
+    val history: Long,
+    /** Encoding buffers, queued events and responses */
+    val buffers: Long,
+    /** Images holding pixels; a count, not part of [total] */
+    val imagesHeld: Long = 0,
+    /** Images evicted to placeholders so far; a count, not part of [total] */
+    val imagesEvicted: Long = 0
+) {
+    val total: Long get() = screen + scrollback + images + links + history + buffers
+
+    /** One line per part, for logs and bug reports. */
+    fun dump(): String = buildString {
+        val parts = listOf(
+            "screen" to screen,
+            "scrollback" to scrollback,
+            "images" to images,
+            "links" to links,
+            "history" to history,
+            "buffers" to buffers
+        )
+        for ((name, bytes) in parts) {
+            append("$name: $bytes bytes\n")
+        }
+        append("total: $total bytes\n")
+        append("images held: $imagesHeld, evicted: $imagesEvicted")
+    }
+
+    companion object {
+        /** From [AvtNative.vtMemoryUsage]; all zero if [values] is empty. */
+        fun of(values: LongArray): AvtMemoryUsage {
+            val at = { i: Int -> values.getOrElse(i) { 0L } }
+            return AvtMemoryUsage(at(0), at(1), at(2), at(3), at(4), at(5), at(6), at(7))
+        }
+    }
+}
@@ -0,0 +1,36 @@
+package uk.adedamola.asciicast.vt.avt
+
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Modes
+
+/**
+ * The input modes the program in an [AvtVirtualTerminal] has set, for
+ * deciding how to send it pastes, mouse events and keys; see modes.rs.
+ * A diff with [uk.adedamola.asciicast.vt.TerminalDiff.modesChanged] set
+ * means they changed.
+ */
+@JvmInline
+value class AvtModes(val bits: Int) {
+    /** Cursor keys send `ESC O` sequences (DECCKM) */
+    val appCursorKeys: Boolean get() = bits and Modes.APP_CURSOR_KEYS != 0
+
+    /** The keypad sends application sequences (DECKPAM) */
+    val appKeypad: Boolean get() = bits and Modes.APP_KEYPAD != 0
+
+    /** Wrap pastes in `ESC [200~` and `ESC [201~` (mode 2004) */
+    val bracketedPaste: Boolean get() = bits and Modes.BRACKETED_PASTE != 0
+
+    /** Report button presses and releases (mode 1000) */
+    val mouseClicks: Boolean get() = bits and Modes.MOUSE_CLICKS != 0
+
+    /** Also report motion with a button held (mode 1002) */
+    val mouseDrag: Boolean get() = bits and Modes.MOUSE_DRAG != 0
+
+    /** Also report motion without a button (mode 1003) */
+    val mouseMotion: Boolean get() = bits and Modes.MOUSE_MOTION != 0
+
+    /** Mouse reports in the SGR encoding (mode 1006) */
+    val mouseSgr: Boolean get() = bits and Modes.MOUSE_SGR != 0
+
+    /** Whether the program wants any mouse events */
+    val mouseReporting: Boolean get() = mouseClicks || mouseDrag || mouseMotion
+}
@@ -1,26 +1,344 @@
 package uk.adedamola.asciicast.vt.avt
 
+import java.nio.ByteBuffer
+
 /**
  * JNI bridge to Rust avt implementation.
  *
  * All methods are native and map directly to Rust functions in lib.rs.
+ * A panic in native code is thrown as [AvtNativeException] (the return
+ * value is then 0, false or null) instead of aborting the process.
  */
 internal object AvtNative {
     init {
         System.loadLibrary("asciicast_vt_avt")
+        // Only a library of a newer protocol negotiates snapshots and
+        // diffs down to ours; at the same version the formats must match
+        check(
+            vtSchemaHash() == AvtSchema.HASH ||
+                vtProtocolVersion() > AvtSchema.Protocol.VERSION
+        ) {
+            "libasciicast_vt_avt was built from other wire formats than AvtSchema.kt"
+        }
     }
 
+    /**
+     * Hash of the wire format constants the library was built with, as
+     * [AvtSchema.HASH] is of those the Kotlin side decodes with.
+     */
+    external fun vtSchemaHash(): Long
+
     /**
      * Create a new VT instance.
      * @return Opaque handle to VT instance
      */
     external fun vtNew(cols: Int, rows: Int): Long
 
+    /** Full terminal: scrollback, cursor-only diffs. Same as [vtNew]. */
+    const val MODE_FULL = 0
+
+    /** Widget ticker: no scrollback, diffs only when content changes. */
+    const val MODE_TICKER = 1
+
+    /**
+     * Create a new VT instance with the given mode.
+     * @param mode One of MODE_FULL, MODE_TICKER
+     * @return Opaque handle to VT instance
+     */
+    external fun vtNewWithMode(cols: Int, rows: Int, mode: Int): Long
+
+    /** avt's own behavior. */
+    const val QUIRKS_XTERM = 0
+
+    /** Linux virtual console: no alternate screen, DECDHL/DECDWL or REP. */
+    const val QUIRKS_LINUX = 1
+
+    /** tmux: no DECDHL/DECDWL. */
+    const val QUIRKS_TMUX = 2
+
+    /**
+     * Ignore the sequences the terminal a cast was recorded in ignored, so
+     * it renders as the author saw it. Kept across [vtReset].
+     * @param profile One of QUIRKS_XTERM, QUIRKS_LINUX, QUIRKS_TMUX
+     * @return false if handle or profile invalid
+     */
+    external fun vtSetQuirkProfile(handle: Long, profile: Int): Boolean
+
+    /** Show the cursor as the recording does. */
+    const val CURSOR_REAL = 0
+
+    /** Show a cursor until the recording shows one itself. */
+    const val CURSOR_AUTO = 1
+
+    /** Always show the cursor. */
+    const val CURSOR_ALWAYS = 2
+
+    /**
+     * Override cursor visibility in snapshots and diffs during playback,
+     * for recordings that hide the cursor and never show it again.
+     * Interactive sessions (with [vtNewWithConfig]) always show the real
+     * state. Queues [AvtEvent.OptionChanged] when changed. Kept across
+     * [vtReset].
+     * @param policy One of CURSOR_REAL, CURSOR_AUTO, CURSOR_ALWAYS
+     * @return false if handle or policy invalid
+     */
+    external fun vtSetCursorPolicy(handle: Long, policy: Int): Boolean
+
+    /**
+     * Queue the recording's input ("i") events as [AvtEvent.Input] during
+     * playback, in order with the other events, for showing keystrokes;
+     * off by default, when they're skipped. [vtSeek] drops them with the
+     * other events it replays. Kept across resets.
+     */
+    external fun vtSetInputEvents(handle: Long, on: Boolean)
+
+    /**
+     * Create a VT for an interactive session. Device attribute (DA1/DA2)
+     * and XTGETTCAP queries in fed output are answered from these
+     * capabilities, and status (DSR) and cursor position (CPR, DECXCPR)
+     * queries from the screen; collect the replies with [vtTakeResponses]
+     * and write them to the pty. Start the shell with the same TERM,
+     * COLORTERM and LANG.
+     * @param term TERM, e.g. "xterm-256color"; also the XTGETTCAP name
+     * @param colorTerm COLORTERM, or null for none; "truecolor" advertises
+     *   direct color (RGB/Tc)
+     * @param locale LANG, e.g. "en_US.UTF-8"
+     * @return Opaque handle to VT instance, or 0 on invalid strings
+     */
+    external fun vtNewWithConfig(cols: Int, rows: Int, mode: Int, term: String, colorTerm: String?, locale: String): Long
+
+    /**
+     * Replace the DA1 (`CSI c`) and DA2 (`CSI > c`) reply parameters.
+     * Defaults are `[62, 22]` (VT220, ANSI color, no sixel: the player
+     * can't show images) and `[1, 1, 0]`.
+     * @return false if the handle was not created with [vtNewWithConfig]
+     */
+    external fun vtSetDeviceAttributes(handle: Long, da1: IntArray, da2: IntArray): Boolean
+
+    /**
+     * Override the XTGETTCAP answer for a terminfo capability, e.g. hide
+     * `RGB` or add `Ms`.
+     * @param value String value, or null for a set boolean capability
+     * @param present false to answer "not found" regardless of [value]
+     * @return false if the handle was not created with [vtNewWithConfig]
+     */
+    external fun vtSetCapability(handle: Long, name: String, value: String?, present: Boolean): Boolean
+
+    /**
+     * Reply to ENQ (`0x05`) with [answerback] through [vtTakeResponses];
+     * empty, the default, sends nothing.
+     * @return false if the handle was not created with [vtNewWithConfig]
+     */
+    external fun vtSetAnswerback(handle: Long, answerback: String): Boolean
+
+    /**
+     * Take replies to queries fed since the last call.
+     * @return Bytes to write back to the program; empty for handles created
+     *   without a config
+     */
+    external fun vtTakeResponses(handle: Long): ByteArray
+
+    /**
+     * Take the events (bells, titles, clipboard writes, notifications,
+     * images, [vtWatch] hits, activity changes, and markers, resizes and
+     * input during playback) queued since the last call; decode with
+     * [AvtEvent.decode]. Only the newest 256 are kept between calls. Seeks drop the events their replay produces.
+     * @return Encoded batch, or empty array if handle invalid
+     */
+    external fun vtTakeEvents(handle: Long): ByteArray
+
+    /**
+     * [vtTakeEvents] with the time each event was queued: its playback time
+     * during playback, otherwise microseconds since the VT was created.
+     * Decode with [AvtEvent.decodeTimed].
+     * @return Encoded timed batch, or empty array if handle invalid
+     */
+    external fun vtPollEvents(handle: Long): ByteArray
+
+    /**
+     * Queue an [AvtEvent.Watch] tagged [tag] whenever text matching
+     * [pattern] appears on screen, replacing any watch with that tag. The
+     * pattern is a regex subset (literals, `.`, classes, `\d \w \s`,
+     * `* + ?`, `^ $` and top-level `|`; see `rust/src/watch.rs`), matched
+     * within one row. Text already on screen doesn't fire, and each hit
+     * fires once. Watches are kept across resets; at most 64 per VT.
+     * @return False for an invalid handle, a negative tag, an unsupported
+     *   pattern, or too many watches
+     */
+    external fun vtWatch(handle: Long, pattern: String, tag: Int): Boolean
+
+    /**
+     * Remove the watch with [tag].
+     * @return False for an invalid handle or when there was none
+     */
+    external fun vtUnwatch(handle: Long, tag: Int): Boolean
+
+    /**
+     * Queue an [AvtEvent.Activity] when an interactive session goes idle,
+     * after [timeoutMillis] with neither output nor [vtNoteInput], and
+     * again when it's active. Unlike [vtPollIdle], input counts and
+     * nothing needs polling beyond taking events, see [vtActivityDeadline].
+     * Kept across resets.
+     * @param timeoutMillis 0 to never go idle (default)
+     */
+    external fun vtSetActivityTimeout(handle: Long, timeoutMillis: Long)
+
+    /** Input was sent to the session (typed keys, pastes, keep-alives). */
+    external fun vtNoteInput(handle: Long)
+
+    /**
+     * The session ended ([disconnected]), queueing an [AvtEvent.Activity]
+     * `ACTIVITY_DISCONNECTED`, or was reconnected, queueing `ACTIVITY_ACTIVE`.
+     */
+    external fun vtSetDisconnected(handle: Long, disconnected: Boolean)
+
+    /**
+     * When to take events next for the idle change, while nothing else
+     * has the app taking them (a preview that isn't redrawing).
+     * @return Milliseconds until the session goes idle, or -1 if it can't
+     *   (no timeout, already idle, disconnected, invalid handle)
+     */
+    external fun vtActivityDeadline(handle: Long): Long
+
+    /**
+     * URI of the OSC 8 hyperlink printed at [row], [col] of the screen, for
+     * a tap on a [TextRun.linkId] run the frame doesn't list (lines from
+     * diffs carry ids without URIs).
+     * @return URI, or null if the cell has no link or the handle is invalid
+     */
+    external fun vtLinkAt(handle: Long, row: Int, col: Int): String?
+
+    /**
+     * Pixels of the Sixel or iTerm2 image [id] of a snapshot's image table,
+     * as varint width and height, then RGBA rows top to bottom. Width and
+     * height are 0 for an iTerm2 file that isn't a PNG, which is placed but
+     * not decoded. Needs the native `images` feature, without which no
+     * images are placed.
+     * @return Image, or empty array if it's no longer shown or the handle
+     *   is invalid
+     */
+    external fun vtImage(handle: Long, id: Int): ByteArray
+
+    /**
+     * [vtImage] scaled for cells of [cellWidth] × [cellHeight] pixels: box
+     * filtered down to the image's cells at that size, never up, and
+     * premultiplied, ready for `Bitmap.copyPixelsFromBuffer` into an
+     * `ARGB_8888` bitmap.
+     * @return Image, or empty array as for [vtImage]
+     */
+    external fun vtImageScaled(handle: Long, id: Int, cellWidth: Int, cellHeight: Int): ByteArray
+
+    /**
+     * Every distinct style on the visible screen, in order of first
+     * appearance, with default, 16-color, 256-color and RGB colors told
+     * apart (format in `rust/src/styles.rs`).
+     * @return Encoded table, or empty array if handle invalid
+     */
+    external fun vtStyleTable(handle: Long): ByteArray
+
     /**
      * Free a VT instance.
      */
     external fun vtFree(handle: Long)
 
+    /**
+     * Make calls with a freed or made-up handle, or one of another kind
+     * (a player's where a stream's belongs), throw IllegalStateException
+     * instead of quietly doing nothing, to catch use-after-free in debug
+     * builds. Covers every kind of native handle. Off by default.
+     */
+    external fun vtSetStrictHandles(enabled: Boolean)
+
+    /** [vtListHandles] kind: VTs, players' included. */
+    const val HANDLE_VT = 0
+
+    /** [vtListHandles] kind: players ([playerNew], [edlPlayer]). */
+    const val HANDLE_PLAYER = 1
+
+    /** [vtListHandles] kind: hardware console streams ([streamAttach]). */
+    const val HANDLE_STREAM = 2
+
+    /** [vtListHandles] kind: network consoles ([netConnect]). */
+    const val HANDLE_CONNECTION = 3
+
+    /** [vtListHandles] kind: recorders ([recorderStart]). */
+    const val HANDLE_RECORDER = 4
+
+    /** [vtListHandles] kind: session journals ([journalOpen]). */
+    const val HANDLE_JOURNAL = 5
+
+    /** [vtListHandles] kind: live broadcasts ([streamNew]). */
+    const val HANDLE_LIVE = 6
+
+    /** [vtListHandles] kind: snapshot readers ([vtOpenReader]). */
+    const val HANDLE_READER = 7
+
+    /** [vtListHandles] kind: SSH sessions ([sshConnect]). */
+    const val HANDLE_SESSION = 8
+
+    /** [vtListHandles] kind: edit decision lists ([edlNew], [edlLoad]). */
+    const val HANDLE_EDL = 9
+
+    /** [vtListHandles] kind: seek checkpoint indexes ([checkpointIndexNew]). */
+    const val HANDLE_CHECKPOINTS = 10
+
+    /** [vtListHandles] kind: recording libraries ([libraryOpen]). */
+    const val HANDLE_LIBRARY = 11
+
+    /** [vtListHandles] kind: sync receivers ([syncReceiverNew]). */
+    const val HANDLE_SYNC_RECEIVER = 12
+
+    /** [vtListHandles] kind: parsed casts ([castOpen], [castFileFinish], clips and edit results). */
+    const val HANDLE_CAST = 13
+
+    /** [vtListHandles] kind: streamed casts ([castFileOpen], [castOpenFollow]). */
+    const val HANDLE_CAST_FILE = 14
+
+    /** [vtListHandles] kind: edit sessions ([editSessionNew]). */
+    const val HANDLE_EDIT_SESSION = 15
+
+    /**
+     * Live native objects of [kind], for debug screens and session
+     * switchers (see [AvtLiveHandle]), in slot order.
+     * @param kind One of the HANDLE_ constants
+     * @return Their handles; empty for an unknown kind
+     */
+    external fun vtListHandles(kind: Int): LongArray
+
+    /**
+     * Label a live object for [vtListHandles] listings, e.g. with the host
+     * of a session.
+     * @return false for an unknown kind or a handle that isn't live
+     */
+    external fun vtSetHandleLabel(kind: Int, handle: Long, label: String): Boolean
+
+    /**
+     * @return The label set with [vtSetHandleLabel] (empty if none), or null
+     *   for an unknown kind or a handle that isn't live
+     */
+    external fun vtHandleLabel(kind: Int, handle: Long): String?
+
+    /**
+     * Pre-build VT instances until the warm pool holds [count] (it keeps
+     * at most 4), so [vtNewPooled] doesn't pay for allocation when a player
+     * opens. Slow; call off the main thread, e.g. at app start.
+     */
+    external fun vtPoolWarm(count: Int, cols: Int, rows: Int)
+
+    /**
+     * Like [vtNew], but reuse a pooled instance when there is one. Release
+     * it with [vtRecycle] to return it to the pool, or [vtFree].
+     * @return VT handle
+     */
+    external fun vtNewPooled(cols: Int, rows: Int): Long
+
+    /**
+     * Reset a VT instance and return it to the warm pool, or free it if the
+     * pool is full or it was created in ticker mode. The handle is invalid
+     * afterwards.
+     */
+    external fun vtRecycle(handle: Long)
+
     /**
      * Reset VT to new dimensions.
      */
@@ -28,23 +346,1780 @@ internal object AvtNative {
 
     /**
      * Resize VT.
+     * @param reflow Rewrap soft-wrapped lines, scrollback included, to the
+     *   new width, the cursor moving with its text; false cuts or pads
+     *   each row instead (the pen and most modes then start over)
      */
-    external fun vtResize(handle: Long, cols: Int, rows: Int)
+    external fun vtResize(handle: Long, cols: Int, rows: Int, reflow: Boolean)
 
     /**
      * Feed bytes to VT.
      */
     external fun vtFeed(handle: Long, bytes: ByteArray)
 
+    /**
+     * [vtFeed] of [length] bytes at [offset] in the direct [buffer]
+     * (`ByteBuffer.allocateDirect`), read in place without a copy. The
+     * buffer's position and limit are ignored and left alone.
+     * @return false, feeding nothing, if handle invalid, [buffer] not
+     *   direct, or the range outside its capacity
+     */
+    external fun vtFeedDirect(handle: Long, buffer: ByteBuffer, offset: Int, length: Int): Boolean
+
+    /**
+     * Feed many chunks in one call: [batch] is a flags byte, then per chunk
+     * a varint length and the bytes. With flag [BATCH_TIMED] each chunk
+     * starts with a varint of the microseconds since the previous one, so
+     * throughput stats spread the chunks over that span. A malformed batch
+     * feeds nothing.
+     * @return The diff the batch produced, as [vtPollDiffContent]; empty if
+     *   handle or batch invalid, or there is none to report yet
+     */
+    external fun vtFeedBatch(handle: Long, batch: ByteArray): ByteArray
+
+    /**
+     * Show typed input straight away, before the program echoes it, for
+     * high-latency sessions. Predicted cells carry style attr bit 0x40 in
+     * snapshots; draw them marked (e.g. underlined). They are confirmed or
+     * dropped against the real output on each [vtFeed]. Only printable
+     * ASCII on the cursor's row is predicted; other keys pause prediction
+     * until the echo catches up. Call with the same bytes sent to the pty.
+     */
+    external fun vtPredictInput(handle: Long, input: ByteArray)
+
+    /**
+     * Drop pending predictions, e.g. when the user turns prediction off.
+     */
+    external fun vtClearPredictions(handle: Long)
+
+    /**
+     * Send [rowCount] rows from [firstRow] again, whole, with the next
+     * diff, e.g. after a dialog covered them; a negative [rowCount] means
+     * every row from [firstRow] down, so `(0, -1)` is the whole screen.
+     */
+    external fun vtInvalidate(handle: Long, firstRow: Int, rowCount: Int)
+
+    /**
+     * Feed bytes to VT, tagged for latency tracing. The first [vtPollDiff]
+     * diff that reports the batch carries the ID: tag 3, then a varint
+     * count and 8-byte little-endian IDs, then the tag-1 body (see
+     * `rust/src/diff.rs`). Stamp the ID when input is sent and again when
+     * the diff is drawn for input-to-pixel latency.
+     */
+    external fun vtFeedTraced(handle: Long, bytes: ByteArray, traceId: Long)
+
     /**
      * Capture snapshot as encoded bytes.
      * @return Encoded snapshot, or empty array if handle invalid
      */
     external fun vtSnapshot(handle: Long): ByteArray
 
+    /**
+     * Like [vtSnapshot], but runs refer to styles by id, preceded by a
+     * style table update (format in `rust/src/styles.rs`). Starts a new
+     * style epoch: the update carries every style the snapshot uses.
+     * @return Encoded snapshot, or empty array if handle invalid
+     */
+    external fun vtSnapshotInterned(handle: Long): ByteArray
+
+    /**
+     * Like [vtSnapshotInterned], with only the [rowCount] rows from
+     * [firstRow] (cut short at the bottom), for a renderer that draws just
+     * what is visible. The header keeps the whole screen's size and cursor;
+     * the style update and link table cover those rows alone (format in
+     * `rust/src/styles.rs`). Starts a new style epoch.
+     * @return Encoded region, or empty array if handle invalid
+     */
+    external fun vtSnapshotRegion(handle: Long, firstRow: Int, rowCount: Int): ByteArray
+
+    /**
+     * [vtSnapshot] encoded into the start of the direct [buffer], reusing a
+     * native buffer and allocating no Java array. The buffer's position
+     * and limit are left alone.
+     * @return Bytes written; if the snapshot doesn't fit, nothing is
+     *   written and the size needed is returned negated (always below -1);
+     *   -1 if handle invalid or [buffer] not direct
+     */
+    external fun vtSnapshotInto(handle: Long, buffer: ByteBuffer): Int
+
+    /**
+     * Capture the screen as a delta against an earlier delta's screen, for
+     * resyncing without a full snapshot (e.g. after restoring UI state).
+     * Layout (see `rust/src/delta.rs`): varint seq, varint baseline, then
+     * as a snapshot header, then a varint row count and `row line` pairs
+     * for the rows that changed. Keep `seq` as the next baseline. The last
+     * 8 deltas are remembered; an unknown baseline yields baseline 0 and
+     * every row.
+     * @param baselineSeq `seq` of a delta received earlier, or 0 for none
+     * @return Encoded delta, or empty array if handle invalid
+     */
+    external fun vtSnapshotDelta(handle: Long, baselineSeq: Long): ByteArray
+
+    /**
+     * Register a consumer of the VT's screen (a local renderer, a remote
+     * viewer, a recorder), for sharing a session without feeding several
+     * VTs. Each polls [vtPollConsumer] and acks with [vtAckConsumer] at its
+     * own pace, with seq numbers of its own; [vtPollDiff] is unaffected.
+     * Kept across [vtReset]. At most 16 per VT.
+     * @return Consumer id, or 0 if handle invalid or there are too many
+     */
+    external fun vtAddConsumer(handle: Long): Int
+
+    /** @return false if handle or [id] invalid */
+    external fun vtRemoveConsumer(handle: Long, id: Int): Boolean
+
+    /**
+     * Consumer [id]'s next frame: a delta as from [vtSnapshotDelta] against
+     * the newest screen it acked, every row before the first ack. A lost
+     * frame needs no resend, the next carries its changes too.
+     * @return Encoded delta, or empty array if the screen is unchanged
+     *   since the consumer's last frame, during a synchronized update, or
+     *   if handle or [id] invalid
+     */
+    external fun vtPollConsumer(handle: Long, id: Int): ByteArray
+
+    /**
+     * Consumer [id] applied its frame [seq]; later frames are against it.
+     * @return false if handle or [id] invalid, or [seq] unknown or older
+     *   than the last ack (harmless)
+     */
+    external fun vtAckConsumer(handle: Long, id: Int, seq: Long): Boolean
+
+    /**
+     * Open a reader of the VT's screen for a render thread: from now on
+     * each feed, resize and reset publishes the screen, and
+     * [readerSnapshot] encodes the latest one without waiting for the
+     * feeder. Publishing stops when the last reader is freed.
+     * @return Reader handle, valid until [readerFree] even after [vtFree];
+     *   0 if handle invalid or 8 readers are open
+     */
+    external fun vtOpenReader(handle: Long): Long
+
+    /**
+     * The screen [reader] last saw published, in the snapshot format;
+     * kept while a synchronized update is in progress. Any thread, but one
+     * call at a time per reader.
+     * @return Snapshot bytes, empty for reader 0
+     */
+    external fun readerSnapshot(reader: Long): ByteArray
+
+    /** Free a reader from [vtOpenReader]. */
+    external fun readerFree(reader: Long)
+
+    /**
+     * 64-bit hash of the visible frame: rows, size, cursor and alternate
+     * screen (see `rust/src/framehash.rs`). Equal hashes mean the same
+     * frame, so recomposition or an exported frame can be skipped. Rows
+     * are rehashed only once they change.
+     * @return The hash, or 0 if handle invalid
+     */
+    external fun vtFrameHash(handle: Long): Long
+
+    /**
+     * Screen-burn protection for an always-on display at [nowMillis] (see
+     * `rust/src/burnin.rs`): how far to shift the frame and how far to fade
+     * each row's text toward its background, rows being compared by hash
+     * with the previous call. Call once per ambient frame, with a time
+     * that only moves forward.
+     * @param protection `[shiftPx, shiftPeriodMs, dimAfterMs, dimLevel]`,
+     *   see [AvtBurnInProtection]
+     * @return `[dx, dy, dim0, dim1, ...]`, dims being 0-255 per row, or
+     *   empty array if handle invalid or [nowMillis] negative
+     */
+    external fun vtBurnIn(handle: Long, nowMillis: Long, protection: IntArray): IntArray
+
     /**
      * Poll for differential update.
      * @return Encoded diff, or empty array if no diff
      */
     external fun vtPollDiff(handle: Long): ByteArray
+
+    /**
+     * Like [vtPollDiff], but every diff is tag 4 and carries the changed
+     * lines (snapshot line encoding) and the cursor, so one call per frame
+     * is enough to update a rendered frame.
+     * @return Encoded diff, or empty array if no diff
+     */
+    external fun vtPollDiffContent(handle: Long): ByteArray
+
+    /**
+     * Like [vtPollDiffContent], but as tag 5: only rows that changed since
+     * the last call, each cropped to the columns that changed. Calling
+     * another poll in between makes the next one send whole rows again.
+     * @return Encoded diff, or empty array if no diff
+     */
+    external fun vtPollDiffSpans(handle: Long): ByteArray
+
+    /**
+     * Like [vtPollDiffSpans], but as tag 6: styles by id, with the entries
+     * created since the last update. Ids stay stable within an epoch, so
+     * resolved styles can be cached by id.
+     * @return Encoded diff, or empty array if no diff
+     */
+    external fun vtPollDiffInterned(handle: Long): ByteArray
+
+    /**
+     * Like [vtPollDiff], but as tag 7: instead of rows, the cells that
+     * changed since the last call (or [vtPollDiffSpans] call) as
+     * `(row, colStart, colEnd)` rectangles, for renderers that patch their
+     * own cells from a snapshot. Rows redrawn the same are left out.
+     * @return Encoded diff, or empty array if no diff
+     */
+    external fun vtPollDiffDamage(handle: Long): ByteArray
+
+    /**
+     * Dump the current screen as an ANSI sequence that recreates it when fed
+     * to a fresh VT of the same size.
+     * @return ANSI dump, or null if handle invalid
+     */
+    external fun vtDumpAnsi(handle: Long): String?
+
+    /**
+     * Replace the VT's state with a dump replayed into a fresh terminal of
+     * the given size: the inverse of [vtDumpAnsi].
+     */
+    external fun vtRestore(handle: Long, cols: Int, rows: Int, dump: String)
+
+    /**
+     * Save the VT's state for a later [vtRestoreState]: a [vtDumpAnsi]
+     * dump tagged with the avt build and dump format that produced it, so a
+     * state cached before an app update isn't silently misrendered after.
+     * @return Saved state, or empty array if handle invalid
+     */
+    external fun vtSaveState(handle: Long): ByteArray
+
+    /**
+     * Restore a state from [vtSaveState].
+     * @return 0 restored exactly; 1 restored from another avt build, which
+     *   may render differently (rebuild anything cached from it); -1 refused,
+     *   incompatible dump format (VT unchanged); -2 not a valid state
+     */
+    external fun vtRestoreState(handle: Long, state: ByteArray): Int
+
+    /**
+     * Describe a saved state without restoring it.
+     * @return JSON `{"format", "avt", "current_format", "current_avt",
+     *   "compat"}` with compat one of "exact", "migrate", "incompatible";
+     *   empty string if not a saved state
+     */
+    external fun stateVersionInfo(state: ByteArray): String
+
+    /**
+     * Save the whole VT, for [vtDeserialize] after the process is killed:
+     * a [vtSaveState] state plus the scrollback, the options set (theme,
+     * bold-as-bright, cursor policy, input events), a character split
+     * across feeds and the rows the next diff would send. Links, images,
+     * queued events, readers and the interactive config aren't kept.
+     * @return Serialized VT; empty array if handle invalid or half an
+     *   escape sequence was fed (feed more and try again)
+     */
+    external fun vtSerialize(handle: Long): ByteArray
+
+    /**
+     * Create a VT from [vtSerialize] output, of its size and mode. Free it
+     * with [vtFree].
+     * @return Handle, or 0 if not a serialized VT or its dump format is
+     *   incompatible with this build
+     */
+    external fun vtDeserialize(bytes: ByteArray): Long
+
+    /**
+     * Replace the VT's state with xterm.js `SerializeAddon` output. The
+     * format doesn't record the terminal size, so pass the size the
+     * xterm.js terminal had.
+     */
+    external fun vtImportXterm(handle: Long, cols: Int, rows: Int, serialized: String)
+
+    /**
+     * Serialize the screen for a fresh xterm.js terminal of the same size,
+     * in the layout [vtImportXterm] reads. Double-width and double-height
+     * lines come out at normal size, as xterm.js has no line attributes.
+     * @return ANSI text, or null if handle invalid
+     */
+    external fun vtSerializeXterm(handle: Long): String?
+
+    /**
+     * Dump the current screen as JSON: size, cursor, modes, and each line's
+     * attribute and styled runs. Colors are null (default), a palette index,
+     * or "#rrggbb". For debugging and external tooling.
+     * @return JSON text, or null if handle invalid
+     */
+    external fun vtDumpJson(handle: Long): String?
+
+    /**
+     * Limit how often [vtPollDiff] returns a diff. Changes made between
+     * allowed polls are merged into the next diff.
+     * @param maxDiffsPerSecond Maximum rate, or 0 for no limit (default)
+     */
+    external fun vtSetUpdateBudget(handle: Long, maxDiffsPerSecond: Int)
+
+    /**
+     * Set how long the VT must go without feeds, resizes or resets before
+     * [vtPollIdle] reports idle.
+     * @param idleMillis Timeout in milliseconds, or 0 to disable (default)
+     */
+    external fun vtSetIdleTimeout(handle: Long, idleMillis: Int)
+
+    /**
+     * @return true once per quiet period, when the idle timeout has elapsed
+     *   since the last change; false otherwise
+     */
+    external fun vtPollIdle(handle: Long): Boolean
+
+    /**
+     * Shrink buffers left over-allocated by a burst of output. Also happens
+     * on its own at the first [vtPollDiff] after 30 seconds without changes.
+     * @return Bytes freed
+     */
+    external fun vtCompact(handle: Long): Long
+
+    /**
+     * Throughput for each of the last 60 seconds, oldest first, the
+     * current second last: `[bytes, feeds, bytes, feeds, ...]`. Counts
+     * start over at [vtReset].
+     * @return 120 values, or empty array if handle invalid
+     */
+    external fun vtTrafficHistory(handle: Long): LongArray
+
+    /**
+     * Current throughput, averaged over the last few whole seconds.
+     * @return `[bytesPerSecond, feedsPerSecond, millisSinceLastFeed]`, the
+     *   last -1 before any feed; empty array if handle invalid
+     */
+    external fun vtTrafficStats(handle: Long): DoubleArray
+
+    /**
+     * Bytes dropped without effect, such as NUL padding, since the VT was
+     * created or last reset. They never mark lines dirty.
+     * @return Byte count, or 0 if handle invalid
+     */
+    external fun vtIgnoredBytes(handle: Long): Long
+
+    /**
+     * Sequences fed since the VT was created that neither avt nor the
+     * wrapper acts on, by kind, with a sample of each (see
+     * [AvtUnknownSequences]). Kept across resets and seeks.
+     *
+     * Result layout: varint count of sequences of kinds not logged (past
+     * the first 32), varint kind count, then per kind a varint count, a
+     * varint event time in microseconds of the first, a varint sample
+     * length and the sample bytes.
+     * @return Encoded log, or empty array if handle invalid
+     */
+    external fun vtUnknownSequences(handle: Long): ByteArray
+
+    /**
+     * Feed [bytes] of seeded escape-sequence noise: mostly text and valid
+     * sequences, with stray controls, unterminated strings and invalid
+     * UTF-8 mixed in. Fed in uneven chunks with a diff polled after each
+     * and occasional resizes; the size is restored at the end. The same
+     * [seed] always produces the same bytes.
+     * @return Nanoseconds taken, or -1 if handle invalid
+     */
+    external fun vtStress(handle: Long, seed: Long, bytes: Long): Long
+
+    /**
+     * Detect tmux/screen panes on the visible screen (heuristic).
+     * @return Status bar row or -1, then `col, row, cols, rows` per pane;
+     *   empty array if handle invalid
+     */
+    external fun vtDetectPanes(handle: Long): IntArray
+
+    /**
+     * Snapshot of a region (usually a pane from [vtDetectPanes]), in the
+     * [vtSnapshot] format with coordinates relative to the region. The
+     * region is clipped to the screen.
+     * @return Encoded snapshot, or empty array if handle invalid
+     */
+    external fun vtRegionSnapshot(handle: Long, col: Int, row: Int, cols: Int, rows: Int): ByteArray
+
+    /**
+     * Text of a region, one line per row with trailing blanks trimmed.
+     * @return Region text, or null if handle invalid
+     */
+    external fun vtRegionText(handle: Long, col: Int, row: Int, cols: Int, rows: Int): String?
+
+    /**
+     * Find [query] inside a region. Matches don't span lines.
+     * @return `row, col` pairs relative to the region, or empty array
+     */
+    external fun vtRegionSearch(
+        handle: Long,
+        col: Int,
+        row: Int,
+        cols: Int,
+        rows: Int,
+        query: String
+    ): IntArray
+
+    /** Bold text in colors 0-7 uses the bright variant (8-15). */
+    const val RESOLVE_BOLD_AS_BRIGHT = 0x01
+
+    /**
+     * Resolve style ids (see [AvtStyles.styleId]) to final colors.
+     * @param palette 0xRRGGBB colors: foreground, background, then palette
+     *   entries from index 0; missing entries use the xterm defaults
+     * @param options Bitwise OR of RESOLVE_* flags
+     * @param minContrast Minimum WCAG contrast ratio of foreground against
+     *   background, or 1 to disable
+     * @return Three ints per id: foreground ARGB, background ARGB, attribute
+     *   bits (inverse already applied), or empty array on failure
+     */
+    external fun vtResolveStyles(
+        styleIds: LongArray,
+        palette: IntArray,
+        options: Int,
+        minContrast: Float
+    ): IntArray
+
+    /**
+     * Register the color theme [name], replacing any of that name, the
+     * built-ins included ("asciinema", "dracula", "monokai",
+     * "solarized-dark", "solarized-light"). Colors 16-255 are always
+     * xterm's.
+     * @param palette16 0xRRGGBB colors 0-15; missing ones keep xterm's
+     * @return false if [name] is empty or [palette16] can't be read
+     */
+    external fun themeRegister(name: String, palette16: IntArray, fg: Int, bg: Int): Boolean
+
+    /**
+     * Theme [name], registered or built in, as a [vtResolveStyles] palette
+     * with all 256 entries, so exports use the same colors as the player.
+     * @return Foreground, background, colors 0-255; empty array if there's
+     *   no such theme
+     */
+    external fun themePalette(name: String): IntArray
+
+    /**
+     * Resolve indexed colors in snapshots and diffs to theme [name]'s RGB
+     * (a copy taken now), or leave them indexed for null, the default.
+     * Default colors stay default. Redraws every row and queues
+     * [AvtEvent.OptionChanged] if the theme is new. Kept across [vtReset].
+     * @return false if handle invalid or there's no such theme
+     */
+    external fun vtSetTheme(handle: Long, name: String?): Boolean
+
+    /**
+     * Show bold text in colors 0-7 as 8-15 in snapshots and diffs, indexed
+     * or resolved by [vtSetTheme]; leave the renderer's own bold-as-bright
+     * off then. Redraws every row and queues [AvtEvent.OptionChanged] when
+     * changed. Off by default, kept across [vtReset].
+     * @return false if handle invalid
+     */
+    external fun vtSetBoldAsBright(handle: Long, on: Boolean): Boolean
+
+    /**
+     * Latest time, in microseconds, the cast and player functions take. Every
+     * time they take or return is in microseconds; a negative time or one
+     * past this is rejected as invalid.
+     */
+    const val MAX_TIME_MICROS = 1L shl 53
+
+    /**
+     * Parse a cast for inspection. Gzip and zstd compressed casts are
+     * recognized by their first bytes and parsed as they're decompressed,
+     * with the native `compression` feature; without it they fail.
+     * @return Opaque cast handle, or 0 if the cast could not be parsed
+     */
+    external fun castOpen(castBytes: ByteArray): Long
+
+    /**
+     * Free a cast handle.
+     */
+    external fun castFree(handle: Long)
+
+    /**
+     * Rewrite the cast [handle]'s event times to playback times, as
+     * asciinema plays it: each gap capped at [idleLimitMicros] (the
+     * header's `idle_time_limit` if not positive), then every time divided
+     * by [speed]. The header's limit is dropped, so event times can be
+     * scheduled as they are.
+     * @return The new duration in microseconds, or -1 if handle invalid or
+     *   [speed] isn't positive
+     */
+    external fun castNormalizeTiming(handle: Long, idleLimitMicros: Long, speed: Double): Long
+
+    /**
+     * Largest grid the recording needs: `[maxCols, maxRows]` over the
+     * header size and every resize event, each maximized independently.
+     * @return Two ints, or empty array if handle invalid
+     */
+    external fun castScanDimensions(handle: Long): IntArray
+
+    // Streaming cast reader (see `rust/src/castfile.rs`)
+
+    /**
+     * Open the asciicast v2 or v3 file at [path] for reading one batch of
+     * events at a time, without loading the whole recording. Gzip and zstd
+     * compressed files (`.cast.gz`, `.cast.zst`) are decompressed as
+     * they're read, here and by every other way of opening a cast file,
+     * with the native `compression` feature; without it they fail.
+     * @return Cast file handle (free with [castFileFree]), or 0 if the file
+     *   can't be opened or its header is invalid
+     */
+    external fun castFileOpen(path: String): Long
+
+    /** [castFileOpen] over a copy of [castBytes]. */
+    external fun castFileOpenBytes(castBytes: ByteArray): Long
+
+    /**
+     * [castFileOpen] over [fd], reading only the header line, so a player
+     * screen can show its chrome and first frame right away. The handle
+     * owns [fd] (pass `ParcelFileDescriptor.detachFd()`); it is closed on
+     * failure too. Parse the rest with [castFileFinish].
+     * @return Cast file handle, or 0 for an invalid descriptor or header
+     */
+    external fun castOpenHeader(fd: Int): Long
+
+    /**
+     * [castFileOpen] over the app's [source] (see `rust/src/source.rs`),
+     * called back on the thread that reads the handle, for recordings
+     * that aren't plain files: encrypted storage, HTTP.
+     * @return Cast file handle, or 0 if [source] failed or the header is
+     *   invalid
+     */
+    external fun castOpenSource(source: AvtCastSource): Long
+
+    /**
+     * [castFileOpen] over [length] bytes of [fd] from [offset], as an
+     * `AssetFileDescriptor` of an uncompressed asset gives. A negative
+     * [length] reads to the end, and on into lines appended while the
+     * handle is open. The handle owns [fd]; it is closed on failure too.
+     * @return Cast file handle, or 0 for an invalid argument or header
+     */
+    external fun castOpenRange(fd: Int, offset: Long, length: Long): Long
+
+    /**
+     * [castFileOpen] over [fd] mapped into memory, its size as when
+     * opened. [fd] is closed once mapped, or on failure.
+     * @return Cast file handle, or 0 if [fd] can't be mapped or the header
+     *   is invalid
+     */
+    external fun castOpenMapped(fd: Int): Long
+
+    /**
+     * Follow a recording still being written to [fd], like `tail -f`:
+     * read what's been written so far, then the events appended since,
+     * with [castPollNewEvents]. A line only partly written waits until
+     * it's complete. Not for compressed files. The handle owns [fd]; it is
+     * closed on failure too.
+     * @return Cast file handle, or 0 for an invalid descriptor or a header
+     *   line that's invalid or not all written yet
+     */
+    external fun castOpenFollow(fd: Int): Long
+
+    /**
+     * The events appended since the last poll of a [castOpenFollow]
+     * handle, in the [castFileNextEvents] layout. A batch with no events
+     * means nothing new yet, or if [castFileError] says so, that a
+     * malformed line ended the recording.
+     */
+    external fun castPollNewEvents(handle: Long): ByteArray
+
+    // Encrypted casts (native `encryption` feature, see `rust/src/crypt.rs`)
+
+    /**
+     * [castFileOpen] over the encrypted cast in [fd], decrypted a chunk at
+     * a time as it's read, so no plaintext reaches disk. The handle owns
+     * [fd]; it is closed on failure too. A chunk that fails to decrypt
+     * later ends the events as a malformed line would (see
+     * [castFileError]).
+     * @param key 32 bytes, e.g. unwrapped with an Android Keystore key
+     * @return Cast file handle, or 0 for an invalid key or file, or the
+     *   wrong key
+     */
+    external fun castOpenEncrypted(fd: Int, key: ByteArray): Long
+
+    /**
+     * Write the cast [castHandle] to [fd] encrypted with [key], for
+     * [castOpenEncrypted]. [fd] stays open.
+     * @return false for an invalid handle, fd or key, or a failed write
+     */
+    external fun castWriteEncrypted(castHandle: Long, fd: Int, key: ByteArray): Boolean
+
+    // Signed exports (native `signing` feature, see `rust/src/signed.rs`)
+
+    /**
+     * Write the cast [castHandle] to [fd] with an Ed25519 signature over a
+     * hash tree of its lines appended as a final `x-signature` event, for
+     * [castVerifySignature]. [fd] stays open.
+     * @param seed 32-byte Ed25519 private key
+     * @return false for an invalid handle, fd or seed, or a failed write
+     */
+    external fun castExportSigned(castHandle: Long, fd: Int, seed: ByteArray): Boolean
+
+    /**
+     * The 32-byte public key for [seed], to share with whoever verifies.
+     * @return null if [seed] isn't 32 bytes
+     */
+    external fun signPublicKey(seed: ByteArray): ByteArray?
+
+    /**
+     * Check the signed cast in [fd] against [publicKey]. [fd] stays open.
+     * @return 0 if intact, -1 if unsigned, -2 if not signed by the key, -3
+     *   if the signature can't be read, or the 1-based line where the
+     *   first changed chunk of lines starts
+     */
+    external fun castVerifySignature(fd: Int, publicKey: ByteArray): Long
+
+    /**
+     * Parse the events [handle] hasn't delivered into a cast, as from
+     * [castOpen], for [vtSeek], [vtSeekIndexed] and editing. Blocks until
+     * the file is read: run it as a background job.
+     * @return Cast handle (free with [castFree]), or 0 if handle invalid or
+     *   a line is malformed (see [castFileError])
+     */
+    external fun castFileFinish(handle: Long): Long
+
+    /** Free a cast file handle. */
+    external fun castFileFree(handle: Long)
+
+    /**
+     * The header line as JSON, including keys the native side doesn't
+     * interpret (title, env, theme).
+     * @return JSON object, or null if handle invalid
+     */
+    external fun castFileHeader(handle: Long): String?
+
+    /**
+     * Parse up to [maxCount] more output, input, marker and resize events;
+     * other codes are skipped.
+     * @return `event_count (time_us code:u8 payload_len payload)*`, where
+     *   `time_us` is microseconds from the start, `code` the event code's
+     *   ASCII byte and `payload` UTF-8 text, or `cols rows` varints for
+     *   resizes. No events once the file is exhausted (see
+     *   [castFileError]); empty array if handle invalid or [maxCount] < 1
+     */
+    external fun castFileNextEvents(handle: Long, maxCount: Int): ByteArray
+
+    /** @return Why reading stopped early, or null if it hasn't */
+    external fun castFileError(handle: Long): String?
+
+    /**
+     * Split a cast into one clip per chapter, cutting at each marker event.
+     * Every clip is a valid recording that starts on the screen as it was.
+     * @return Cast handles in order (free each with [castFree]), or empty
+     *   array if handle invalid
+     */
+    external fun castSplitByMarkers(handle: Long): LongArray
+
+    /**
+     * Split a cast into one clip per shell command (OSC 133 shell
+     * integration), each running from its prompt to the end of its output.
+     * @return Cast handles in order (free each with [castFree]), or empty
+     *   array if handle invalid
+     */
+    external fun castSplitByCommands(handle: Long): LongArray
+
+    /**
+     * Stitch two takes: [bHandle] starts [gapMicros] after the last event of
+     * [aHandle], on a reset terminal. Both casts must have monotonic
+     * timestamps (see [castCheckMonotonic]).
+     * @return New cast handle (free with [castFree]), or 0 if a handle is
+     *   invalid, a cast is out of order, or the gap is invalid
+     */
+    external fun castAppendWithGap(aHandle: Long, bHandle: Long, gapMicros: Long): Long
+
+    /** Shift every event of the cast so the first one is at [firstEventMicros]. */
+    external fun castRebase(handle: Long, firstEventMicros: Long)
+
+    /** @return Index of the first event earlier than the one before it, or -1 */
+    external fun castCheckMonotonic(handle: Long): Int
+
+    /**
+     * Find stretches longer than [minMicros] where the recording only
+     * redraws a spinner or progress bar in place (no new lines, no input,
+     * not in a full-screen app), to offer as speed-up regions.
+     * @return Flat `[startMicros, endMicros, playMicros, ...]` triples that
+     *   play each stretch in [minMicros]; can be passed to [castExport] as
+     *   is. Empty array if handle or time invalid.
+     */
+    external fun castFindStalls(handle: Long, minMicros: Long): LongArray
+
+    /**
+     * Count the escape sequences in the cast's output, for diagnostics
+     * (see [AvtSequenceStats]).
+     * @return `[unknown, sixelBytes, kittyBytes, itermBytes]`, then
+     *   `[key, count]` pairs where key is `kind shl 40 or prefix shl 32 or
+     *   id` (see `rust/src/sequences.rs`). Empty array if handle invalid.
+     */
+    external fun castSequenceStats(handle: Long): LongArray
+
+    /**
+     * Pick the most visually active stretches of the cast (by cells changed
+     * per second), in clips of a few seconds adding up to at most
+     * [targetMicros], for an automatic highlight reel. Replays the whole
+     * cast: call it off the main thread. Quiet stretches are never picked,
+     * so a mostly idle cast comes back shorter; one no longer than
+     * [targetMicros] comes back whole.
+     * @return Flat `[startMicros, endMicros, ...]` pairs in order. Empty
+     *   array if handle or time invalid.
+     */
+    external fun castHighlights(handle: Long, targetMicros: Long): LongArray
+
+    /**
+     * Duration, event counts, output per stretch of playback time and idle
+     * gaps of the cast, for a detail screen and the seek bar's activity
+     * strip (see [AvtCastAnalysis.of]). One pass over the events, without
+     * replaying them.
+     * @param buckets Stretches of playback time to split output into, 1 to
+     *   4096
+     * @return Varint fields in the order of `rust/src/analytics.rs`'s
+     *   `Analysis`, empty array if handle invalid
+     */
+    external fun castAnalyze(handle: Long, buckets: Int): ByteArray
+
+    /**
+     * Frame capture times for animation export: one frame after each change,
+     * at most [maxFps]. Lines redrawn in place (spinners, progress bars) are
+     * sampled at [redrawFps] instead, which keeps build logs small.
+     * @return Capture times in microseconds of cast time; each frame shows
+     *   every event at or before it. Empty array if handle invalid.
+     */
+    external fun castSampleFrames(handle: Long, maxFps: Double, redrawFps: Double): LongArray
+
+    /**
+     * When to take a gallery poster rather than the empty prompt at 0: the
+     * first time a fifth of the screen is in use, or the end of the first
+     * command (OSC 133) if that comes first (see `rust/src/poster.rs`).
+     * @return Playback time in microseconds, for [vtSeek]; -1 if handle
+     *   invalid
+     */
+    external fun castPosterTime(handle: Long): Long
+
+    /**
+     * The cast [castBytes] as shown [timeMicros] into playback, parsed and
+     * replayed on a temporary VT within the call: thumbnails with no
+     * handles to free, safe to make from a background worker.
+     * @return Encoded snapshot as from [vtSnapshot], or empty array if the
+     *   cast doesn't parse or the time is invalid
+     */
+    external fun castPosterAt(castBytes: ByteArray, timeMicros: Long): ByteArray
+
+    /**
+     * Find every time [query] (literal text) appears on screen during
+     * playback of the cast, for a "jump to" list: replays it on a headless
+     * terminal, so run it off the main thread. Text already on screen, say
+     * scrolled up, isn't a new hit; at most 1000 are returned.
+     * @return `hit_count (time_delta_us row col line_len line)*`, varints,
+     *   times in playback microseconds since the previous hit, `col` and
+     *   the trimmed UTF-8 row text as in [vtWatch] events; empty array if
+     *   handle invalid
+     */
+    external fun castSearch(handle: Long, query: String): ByteArray
+
+    /**
+     * Estimate how alike two recordings' output text is, ignoring styling
+     * and numbers (timings, versions), for grouping near-duplicate takes.
+     * @return 0.0 (nothing shared) to 1.0 (same text); 0.0 if either has
+     *   no text or a handle is invalid
+     */
+    external fun castSimilarity(aHandle: Long, bHandle: Long): Double
+
+    /**
+     * Open an edit session on a copy of a cast. Edits apply immediately and
+     * can be undone and redone; the cast handle stays untouched.
+     * @return Session handle, or 0 if [castHandle] is invalid
+     */
+    external fun editSessionNew(castHandle: Long): Long
+
+    external fun editSessionFree(handle: Long)
+
+    /**
+     * Keep only `[startMicros, endMicros]`, starting at 0. Output before the
+     * start is folded into the first frame.
+     */
+    external fun editTrim(handle: Long, startMicros: Long, endMicros: Long)
+
+    /** Insert a copy of the cast behind [castHandle] at [atMicros]. */
+    external fun editSplice(handle: Long, atMicros: Long, castHandle: Long)
+
+    /** Mask every occurrence of [text] in output, input and markers. */
+    external fun editRedact(handle: Long, text: String)
+
+    /** Cap pauses between events at [maxMicros]. */
+    external fun editIdleCap(handle: Long, maxMicros: Long)
+
+    /** @return false if there was nothing to undo */
+    external fun editUndo(handle: Long): Boolean
+
+    /** @return false if there was nothing to redo */
+    external fun editRedo(handle: Long): Boolean
+
+    /**
+     * Current result of the session as a new cast handle (free with
+     * [castFree]). The session stays open.
+     * @return Cast handle, or 0 if handle invalid
+     */
+    external fun editCommit(handle: Long): Long
+
+    /**
+     * Create an empty edit decision list: segments of source casts plus
+     * redaction and idle cap, rendered on demand without touching the
+     * sources. Sources are referred to by index into the cast handle array
+     * passed to [edlExport] and [edlPlayer].
+     * @return EDL handle
+     */
+    external fun edlNew(): Long
+
+    /**
+     * Load a list saved with [edlSave].
+     * @return EDL handle, or 0 if the bytes are not a valid list
+     */
+    external fun edlLoad(edlBytes: ByteArray): Long
+
+    external fun edlFree(handle: Long)
+
+    /** @return Saved form of the list (small JSON), or empty array if handle invalid */
+    external fun edlSave(handle: Long): ByteArray
+
+    /** Append `[startMicros, endMicros)` of source [source]. */
+    external fun edlAddSegment(handle: Long, source: Int, startMicros: Long, endMicros: Long)
+
+    /** Mask every occurrence of [text] in the result. */
+    external fun edlAddRedaction(handle: Long, text: String)
+
+    /** Cap pauses in the result at [maxMicros]; 0 removes the cap. */
+    external fun edlSetIdleCap(handle: Long, maxMicros: Long)
+
+    /**
+     * Render the list over [castHandles] (from [castOpen]) as cast bytes.
+     * @return Cast file bytes, or empty array if no segment has a source
+     */
+    external fun edlExport(handle: Long, castHandles: LongArray): ByteArray
+
+    /**
+     * Player for the rendered list, free with [playerFree].
+     * @return Player handle, or 0 if no segment has a source
+     */
+    external fun edlPlayer(handle: Long, castHandles: LongArray): Long
+
+    /** [playerSetPauseOn]: pause at marker events. */
+    const val PAUSE_MARKER = 0x01
+
+    /** [playerSetPauseOn]: pause at output that hit a [vtWatch] pattern. */
+    const val PAUSE_WATCH = 0x02
+
+    /** [playerSetPauseOn]: pause where a command finishes (OSC 133). */
+    const val PAUSE_COMMAND = 0x04
+
+    /** [playerSetPauseOn]: pause where a command finishes with a nonzero status. */
+    const val PAUSE_FAILED_COMMAND = 0x08
+
+    /**
+     * Load a cast for native playback.
+     * @return Opaque player handle, or 0 if the cast could not be parsed
+     */
+    external fun playerNew(castBytes: ByteArray): Long
+
+    /**
+     * Free a player and its VT.
+     */
+    external fun playerFree(handle: Long)
+
+    /**
+     * VT handle of the player's terminal, for [vtSnapshot] and [vtPollDiff].
+     * Owned by the player: valid until [playerFree], never pass it to [vtFree].
+     */
+    external fun playerVt(handle: Long): Long
+
+    /**
+     * Pin the player's terminal to a size (e.g. after rotation) without
+     * touching the event schedule; recorded resizes are remembered and
+     * applied again once the override is cleared.
+     * @param cols Columns, or 0 to follow the recording again
+     * @param rows Rows, or 0 to follow the recording again
+     */
+    external fun playerSetViewSize(handle: Long, cols: Int, rows: Int)
+
+    /**
+     * Apply all events scheduled up to [elapsedMicros] of playback time
+     * (idle time limit applied, speed is up to the caller).
+     * @return Microseconds until the next event, or -1 when playback is done.
+     *   The render loop can sleep that long instead of ticking every frame.
+     */
+    external fun playerTick(handle: Long, elapsedMicros: Long): Long
+
+    /**
+     * Make playback pause itself: the tick that applies a triggering event
+     * applies nothing after it, so playback stops exactly there.
+     * @param flags [PAUSE_MARKER], [PAUSE_WATCH] (a [vtWatch] hit),
+     *   [PAUSE_COMMAND] and [PAUSE_FAILED_COMMAND] (shell integration),
+     *   or 0 (the default) to never pause
+     */
+    external fun playerSetPauseOn(handle: Long, flags: Int)
+
+    /**
+     * [playerTick] that reports pauses from [playerSetPauseOn].
+     * @return `[nextEventInMicros, pauseReason, pausedAtMicros]`: as
+     *   [playerTick] (from the pause time when paused), the `PAUSE_*` flag
+     *   that paused playback or 0, and the playback time to stop the clock
+     *   at, or -1; empty if the handle is invalid
+     */
+    external fun playerTickPausable(handle: Long, elapsedMicros: Long): LongArray
+
+    /**
+     * [playerTick] that stops once [maxMicros] of wall-clock time is spent,
+     * so a huge seek can't block past a frame deadline. At least one due
+     * event is applied per call; the next call continues where this one
+     * stopped.
+     * @return `[reachedMicros, nextEventInMicros, pauseReason,
+     *   pausedAtMicros]`: the playback time caught up to (less than
+     *   [elapsedMicros] if the budget ran out, with the next event in 0, or
+     *   playback paused), then as [playerTick] and [playerTickPausable]
+     */
+    external fun playerTickBudgeted(handle: Long, elapsedMicros: Long, maxMicros: Long): LongArray
+
+    /**
+     * Reset the VT and replay the cast [castHandle] (from [castOpen]) up to
+     * [timeMicros] of playback time, idle time limit applied as by
+     * [playerTick], all in native code: one call per scrub step.
+     * @return Snapshot after the seek, or empty array if a handle or the
+     *   time is invalid
+     */
+    external fun vtSeek(handle: Long, castHandle: Long, timeMicros: Long): ByteArray
+
+    /**
+     * The cast [castHandle]'s markers ("m" events), its chapters, in order
+     * at their playback times (see [AvtChapter.list]).
+     * @return Varint count, then per marker a varint time delta in
+     *   microseconds, varint label length and UTF-8 label; empty array if
+     *   handle invalid
+     */
+    external fun castMarkers(castHandle: Long): ByteArray
+
+    /**
+     * [vtSeek] to marker [markerIndex] of [castMarkers], through the
+     * checkpoint index [checkpointIndex] as [vtSeekIndexed] unless it's 0.
+     * @return Snapshot after the seek, or empty array if a handle or the
+     *   index is invalid
+     */
+    external fun vtSeekToMarker(
+        handle: Long,
+        castHandle: Long,
+        markerIndex: Int,
+        checkpointIndex: Long
+    ): ByteArray
+
+    /**
+     * [vtSeek] straight from the cast file [fd] (left open, read from its
+     * offset), without parsing or indexing the whole recording: replays up
+     * to [timeMicros] of playback time but at most [maxBytes] of output,
+     * for a best-effort preview of a long recording.
+     * @return Snapshot, or empty array for an invalid handle, time,
+     *   descriptor or header
+     */
+    external fun castQuickFrame(handle: Long, fd: Int, timeMicros: Long, maxBytes: Int): ByteArray
+
+    /**
+     * Create a checkpoint index for [vtSeekIndexed], saving the VT state
+     * every [intervalMicros] of playback time or [intervalBytes] of output,
+     * whichever comes first. Non-positive values mean no such interval.
+     * One index serves one cast.
+     * @return Index handle (free with [checkpointIndexFree])
+     */
+    external fun checkpointIndexNew(intervalMicros: Long, intervalBytes: Long): Long
+
+    /** Free a checkpoint index. Safe to call with 0. */
+    external fun checkpointIndexFree(index: Long)
+
+    /** Number of checkpoints taken so far, 0 for an invalid index. */
+    external fun checkpointIndexLen(index: Long): Int
+
+    /**
+     * [vtSeek] from the nearest checkpoint in [index] at or before
+     * [timeMicros], taking new ones along the way, so that scrubbing back
+     * replays only a stretch of the cast. Scrollback from before the
+     * checkpoint isn't restored.
+     * @return Snapshot after the seek, or empty array if a handle or the
+     *   time is invalid
+     */
+    external fun vtSeekIndexed(
+        handle: Long,
+        castHandle: Long,
+        index: Long,
+        timeMicros: Long
+    ): ByteArray
+
+    /**
+     * Working directory changes reported by the shell (OSC 7, or OSC 1337
+     * CurrentDir), scanned once when the player is created.
+     *
+     * Result layout: varint change count, then per change a varint time delta
+     * in microseconds of playback time (relative to the previous change), a
+     * varint path length and the UTF-8 path.
+     * @return Encoded timeline, or empty array for an invalid handle
+     */
+    external fun playerCwdTimeline(handle: Long): ByteArray
+
+    /**
+     * Commands marked by shell integration (OSC 133), with the exit status
+     * the shell reported, so the seek bar can mark failed commands.
+     *
+     * Result layout: varint command count, then per command a varint start
+     * delta in microseconds of playback time (relative to the previous
+     * command's start), a varint duration, and a varint exit status plus one
+     * (0 when the shell didn't report it).
+     * @return Encoded timeline, or empty array for an invalid handle
+     */
+    external fun playerExitStatusTimeline(handle: Long): ByteArray
+
+    /** [vtFeedBatch] flag: chunks carry time deltas. */
+    const val BATCH_TIMED = 1
+
+    /** Keep input ("i") events as recorded. */
+    const val INPUT_KEEP = 0
+
+    /** Drop input events. Default for share exports. */
+    const val INPUT_STRIP = 1
+
+    /**
+     * Replace input data with a token unique to each event, an HMAC keyed
+     * with the salt and the event's index. Input timing still shows.
+     */
+    const val INPUT_HASH = 2
+
+    /**
+     * Rewrite an asciicast v2 file for export/sharing.
+     * @param inputPrivacy One of INPUT_KEEP, INPUT_STRIP, INPUT_HASH
+     * @param salt Key for INPUT_HASH tokens
+     * @return Rewritten cast
+     * @throws IllegalArgumentException if the cast could not be parsed, with
+     *   the reason
+     */
+    external fun castRewrite(castBytes: ByteArray, inputPrivacy: Int, salt: Long): ByteArray
+
+    /**
+     * Rewrite a cast like [castRewrite] and collect export metadata.
+     *
+     * Result layout: varint bell count, varint bell time deltas in
+     * microseconds (each relative to the previous bell), varint title length
+     * and UTF-8 title, varint command length and UTF-8 command, then the cast
+     * bytes. Bell times are in the exported (sped-up) timeline.
+     *
+     * The title is the window title (OSC 0/2) shown when the export starts,
+     * else the first one set, else the header title; the command is the
+     * first command line run (OSC 133 shell integration), else the header
+     * command. Either is empty when unknown. Meant for proposing filenames.
+     * @param speedRegions Flat `[startMicros, endMicros, playMicros, ...]`
+     *   triples in recording time, each region played in `playMicros`, e.g.
+     *   `[130_000_000, 270_000_000, 35_000_000]` plays 02:10-04:30 at 4x.
+     *   Overlapping regions keep the earlier one; empty for none.
+     * @return Encoded export result
+     * @throws IllegalArgumentException if the cast could not be parsed or a
+     *   region time is out of range, with the reason
+     */
+    external fun castExport(
+        castBytes: ByteArray,
+        inputPrivacy: Int,
+        salt: Long,
+        speedRegions: LongArray,
+    ): ByteArray
+
+    // Library index (native `library` feature)
+
+    /**
+     * Open the recording library index stored at [path], creating an empty
+     * one if the file doesn't exist yet. Entries are keyed by the SHA-256 of
+     * the cast file and hold its metadata, output text and bookmarks.
+     * @return Library handle, or 0 if the file exists but can't be read
+     */
+    external fun libraryOpen(path: String): Long
+
+    /**
+     * Free a library handle without saving.
+     */
+    external fun libraryFree(handle: Long)
+
+    /**
+     * Write the index back to its file.
+     * @return true on success
+     */
+    external fun librarySave(handle: Long): Boolean
+
+    /**
+     * Index a cast file. Adding one already indexed keeps its bookmarks.
+     * @return Hex content hash, or null if the cast could not be parsed
+     */
+    external fun libraryAdd(handle: Long, castBytes: ByteArray): String?
+
+    /**
+     * @return true if [hash] was indexed
+     */
+    external fun libraryRemove(handle: Long, hash: String): Boolean
+
+    /**
+     * Bookmark a point of an indexed recording.
+     * @return false if [hash] isn't indexed
+     */
+    external fun libraryAddBookmark(handle: Long, hash: String, timeMicros: Long, label: String): Boolean
+
+    /**
+     * List indexed recordings matching every filter, as a JSON array of
+     * `{"hash", "title", "timestamp", "cols", "rows", "duration_us",
+     * "bookmarks": [[timeMicros, label], ...]}` objects ("title" and
+     * "timestamp", Unix seconds, absent when unknown).
+     * @param title Case-insensitive title substring; null or empty for any
+     * @param minMicros Shortest duration, or -1 for any
+     * @param maxMicros Longest duration, or -1 for any
+     * @param bookmarkedOnly Only recordings with at least one bookmark
+     * @return JSON text, or null if handle invalid
+     */
+    external fun libraryQuery(
+        handle: Long,
+        title: String?,
+        minMicros: Long,
+        maxMicros: Long,
+        bookmarkedOnly: Boolean,
+    ): String?
+
+    /**
+     * Search the output text of every indexed recording, case-insensitive.
+     * Newest recordings (by header timestamp) come first, in time order
+     * within one.
+     * @param limit Most hits to return; 0 for all
+     * @return JSON array of `[hash, timeMicros, lineText]` hits, or null if
+     *   handle invalid
+     */
+    external fun librarySearch(handle: Long, query: String, limit: Int): String?
+
+    // State sync receiver (see `rust/src/sync.rs`)
+
+    /**
+     * Create a receiver for terminal state pushed by a sync sender, e.g. a
+     * companion server. Frames are deltas against the last screen the
+     * receiver acked, so lost or reordered frames need no resend.
+     * @return Receiver handle
+     */
+    external fun syncReceiverNew(): Long
+
+    /**
+     * Free a receiver.
+     */
+    external fun syncReceiverFree(handle: Long)
+
+    /**
+     * Apply a frame as received from the transport.
+     * @return seq to send back as the ack; 0 for a stale frame (nothing to
+     *   ack); -1 for a corrupt frame or one against a screen this receiver
+     *   doesn't have (wait for the next)
+     */
+    external fun syncReceiverApply(handle: Long, frame: ByteArray): Long
+
+    /**
+     * The newest received screen, in the [vtSnapshot] format.
+     * @return Encoded snapshot, or empty array before the first frame
+     */
+    external fun syncReceiverSnapshot(handle: Long): ByteArray
+
+    // Network consoles (native `net` feature, see `rust/src/net.rs`)
+
+    /**
+     * Connect to a console exposed over TCP, e.g. a serial port behind
+     * ser2net. With [telnet] the stream is decoded as telnet: commands are
+     * stripped and option negotiation answered.
+     * @return Connection handle, or 0 if the host can't be reached within
+     *   [timeoutMs]
+     */
+    external fun netConnect(host: String, port: Int, telnet: Boolean, timeoutMs: Int): Long
+
+    /**
+     * Close a connection and free its handle.
+     */
+    external fun netFree(handle: Long)
+
+    /**
+     * Wait up to [timeoutMs] for output and feed it to the VT [vtHandle],
+     * on the calling thread; poll the VT's diffs as usual afterwards.
+     * @return Bytes read, 0 if nothing arrived in time, -1 once the
+     *   connection is closed or failed
+     */
+    external fun netPump(handle: Long, vtHandle: Long, timeoutMs: Int): Int
+
+    /**
+     * Send input, or the VT's [vtTakeResponses], to the device.
+     * @return false if the connection failed
+     */
+    external fun netSend(handle: Long, bytes: ByteArray): Boolean
+
+    // SSH sessions (native `ssh` feature, see `rust/src/ssh.rs`)
+
+    /**
+     * Connect over SSH and start the login shell on a PTY the size of the
+     * VT [vtHandle]. Authenticates with [privateKey] (OpenSSH or PEM text,
+     * unlocked with [passphrase]) if given, else [password]. A server whose
+     * host key fingerprint isn't [hostKey] is refused; with null any key is
+     * accepted, see [sshHostKey].
+     * @return Session handle, or 0 if connecting, authenticating or
+     *   starting the shell failed within [timeoutMs]
+     */
+    external fun sshConnect(
+        host: String,
+        port: Int,
+        user: String,
+        password: String?,
+        privateKey: String?,
+        passphrase: String?,
+        hostKey: String?,
+        vtHandle: Long,
+        timeoutMs: Int,
+    ): Long
+
+    /**
+     * Disconnect a session and free its handle.
+     */
+    external fun sshFree(handle: Long)
+
+    /**
+     * Wait up to [timeoutMs] for output and feed it to the VT [vtHandle],
+     * on the calling thread, as [netPump]. Keep-alives are only answered
+     * while pumping.
+     * @return Bytes read, 0 if nothing arrived in time, -1 once the
+     *   session is closed or failed
+     */
+    external fun sshPump(handle: Long, vtHandle: Long, timeoutMs: Int): Int
+
+    /**
+     * Send input, or the VT's [vtTakeResponses], to the shell.
+     * @return false if the session failed
+     */
+    external fun sshSend(handle: Long, bytes: ByteArray): Boolean
+
+    /**
+     * Resize the remote PTY, after [vtResize] on its VT.
+     * @return false for a size under 1×1 or a failed session
+     */
+    external fun sshResize(handle: Long, cols: Int, rows: Int): Boolean
+
+    /**
+     * SHA-256 fingerprint of the server's host key (`SHA256:…`), to pin
+     * as [sshConnect]'s hostKey after the first connection.
+     */
+    external fun sshHostKey(handle: Long): String
+
+    // Hardware console streams (see `rust/src/stream.rs`)
+
+    /** Keep line endings as received. */
+    const val NEWLINE_AS_IS = 0
+
+    /** LF not preceded by CR becomes CR LF. */
+    const val NEWLINE_LF_TO_CRLF = 1
+
+    /** CR not followed by LF becomes CR LF. */
+    const val NEWLINE_CR_TO_CRLF = 2
+
+    /** Both of the above. */
+    const val NEWLINE_ANY_TO_CRLF = 3
+
+    /** Returned by [streamPush]: stop reading until [FLOW_RESUME]. */
+    const val FLOW_PAUSE = 1
+
+    /** Returned by [streamDrain]: the queue has room again. */
+    const val FLOW_RESUME = 2
+
+    /**
+     * Attach a push stream to a VT: bytes pushed from a reader thread are
+     * queued and fed to the VT by [streamDrain] on the VT's thread.
+     * @param newline One of the NEWLINE_ constants
+     * @return Stream handle, or 0 if the VT handle or newline is invalid
+     */
+    external fun streamAttach(handle: Long, newline: Int): Long
+
+    /**
+     * Free a stream. Bytes still queued are dropped.
+     */
+    external fun streamDetach(stream: Long)
+
+    /**
+     * Pause the reader above [high] queued bytes and resume it at or below
+     * [low]. Defaults are 64 KiB and 16 KiB.
+     */
+    external fun streamSetWatermarks(stream: Long, high: Int, low: Int)
+
+    /**
+     * Queue bytes from the reader; safe from any thread.
+     * @return FLOW_PAUSE when the queue went over the high watermark, 0
+     *   otherwise, -1 if the stream is invalid
+     */
+    external fun streamPush(stream: Long, bytes: ByteArray): Int
+
+    /**
+     * Feed up to [maxBytes] queued bytes (all for 0) to the VT.
+     * @return FLOW_RESUME when the queue went back to the low watermark
+     *   after a pause, 0 otherwise, -1 if the stream or its VT is invalid
+     */
+    external fun streamDrain(stream: Long, maxBytes: Int): Int
+
+    // Live broadcasts (see `rust/src/alis.rs`)
+
+    /** Returned by [streamFeedPacket]: the message was applied. */
+    const val PACKET_APPLIED = 0
+
+    /** Returned by [streamFeedPacket]: the session ended, or paused until the next init. */
+    const val PACKET_ENDED = 1
+
+    /** Returned by [streamFeedPacket]: an unknown message type, or output before the first init. */
+    const val PACKET_SKIPPED = 2
+
+    /** Returned by [streamFeedPacket]: the message was cut short or invalid. */
+    const val PACKET_MALFORMED = -2
+
+    /**
+     * Start receiving an asciinema live stream (ALiS), e.g. from a server's
+     * WebSocket relay: feed every binary message to [streamFeedPacket].
+     * @return Stream handle
+     */
+    external fun streamNew(): Long
+
+    /**
+     * Free a live stream and its VT.
+     */
+    external fun streamFree(handle: Long)
+
+    /**
+     * VT handle of the stream's terminal, for [vtSnapshot] and [vtPollDiff].
+     * Owned by the stream: valid until [streamFree], never pass it to [vtFree].
+     * An init message resets it in place, so the handle outlives reconnects.
+     */
+    external fun streamVt(handle: Long): Long
+
+    /**
+     * Apply one binary message from the relay (the "ALiS" magic, init,
+     * output, resize or eot) to the stream's VT.
+     * @return One of the PACKET_ constants, -1 if the handle is invalid
+     */
+    external fun streamFeedPacket(handle: Long, bytes: ByteArray): Int
+
+    /**
+     * @return Stream time in microseconds of the last message, -1 before
+     *   the first
+     */
+    external fun streamTime(handle: Long): Long
+
+    /**
+     * Theme the broadcaster sent with the last init, as the `palette` of
+     * [vtResolveStyles] and the renderers.
+     * @return Empty if it sent none
+     */
+    external fun streamTheme(handle: Long): IntArray
+
+    // Recording live streams (see `rust/src/record.rs`)
+
+    /**
+     * Start recording a stream to a new asciicast v2 file at [path]. The
+     * first event reconstructs the VT's current screen (see [vtDumpAnsi]),
+     * so a recording started mid-stream replays from its first frame.
+     * Event times are measured from this call.
+     * @return Recorder handle, or 0 if the VT handle is invalid or the file
+     *   can't be created
+     */
+    external fun recorderStart(handle: Long, path: String): Long
+
+    /**
+     * [recorderStart] to [fd], encrypted with [key] as it's written, for
+     * [castOpenEncrypted]. The recorder owns [fd]; it is closed on failure
+     * too. The file only opens once [recorderStop] has sealed its end.
+     * Needs the native `encryption` feature.
+     * @param key 32 bytes, e.g. unwrapped with an Android Keystore key
+     * @return Recorder handle, or 0 if the VT handle, fd or key is invalid
+     */
+    external fun recorderStartEncrypted(handle: Long, fd: Int, key: ByteArray): Long
+
+    /**
+     * Flush and close the file, freeing the recorder.
+     * @return false if the final write failed
+     */
+    external fun recorderStop(recorder: Long): Boolean
+
+    /**
+     * Record output, as fed to the VT.
+     * @return false if the write failed
+     */
+    external fun recorderOutput(recorder: Long, bytes: ByteArray): Boolean
+
+    /**
+     * Record user input.
+     * @return false if the write failed
+     */
+    external fun recorderInput(recorder: Long, text: String): Boolean
+
+    /**
+     * Record a terminal resize.
+     * @return false if the size is invalid or the write failed
+     */
+    external fun recorderResize(recorder: Long, cols: Int, rows: Int): Boolean
+
+    /**
+     * Record a marker.
+     * @return false if the write failed
+     */
+    external fun recorderMarker(recorder: Long, label: String): Boolean
+
+    /**
+     * Start recording a [cols] x [rows] stream with no VT behind it, such
+     * as a local shell or SSH channel, to a new file at [path]. Unlike
+     * [recorderStart], event times come from the caller; one earlier than
+     * the last is written as the last.
+     * @param version asciicast version to write: 2, or 3 for asciinema 3.x
+     * @return Recorder handle (a [HANDLE_RECORDER]), or 0 for an invalid
+     *   size or version or if the file can't be created
+     */
+    external fun recNew(cols: Int, rows: Int, version: Int, path: String): Long
+
+    /** [recNew] to [fd], which the recorder owns; it is closed on failure too. */
+    external fun recNewFd(cols: Int, rows: Int, version: Int, fd: Int): Long
+
+    /**
+     * Record output at [timeMicros] since the start of the recording.
+     * @return false for an invalid handle or time, or a failed write
+     */
+    external fun recWriteOutput(recorder: Long, timeMicros: Long, bytes: ByteArray): Boolean
+
+    /** Record a marker at [timeMicros]. */
+    external fun recMarker(recorder: Long, timeMicros: Long, label: String): Boolean
+
+    /** Record a resize at [timeMicros]. */
+    external fun recResize(recorder: Long, timeMicros: Long, cols: Int, rows: Int): Boolean
+
+    /**
+     * Flush and close the file, freeing the recorder; as [recorderStop].
+     * @return false if the final write failed
+     */
+    external fun recFinish(recorder: Long): Boolean
+
+    // Session journal (see `rust/src/journal.rs`)
+
+    /** Smallest [journalOpen] capacity. */
+    const val JOURNAL_MIN_CAPACITY = 4096L
+
+    /**
+     * Start journaling the VT [vtHandle]'s output to [fd], a ring file of
+     * [capacity] bytes, so [sessionRecover] can rebuild it after a crash.
+     * The journal owns [fd] (pass `ParcelFileDescriptor.detachFd()`); it
+     * is closed on failure too. Feed the VT through [journalFeed] from then on.
+     * @return Journal handle, or 0 for an invalid VT, a capacity below
+     *   JOURNAL_MIN_CAPACITY or a failed write
+     */
+    external fun journalOpen(fd: Int, capacity: Long, vtHandle: Long): Long
+
+    /**
+     * Feed [bytes] to the VT [vtHandle] and journal them.
+     * @return false if the write failed; the VT is fed regardless
+     */
+    external fun journalFeed(handle: Long, vtHandle: Long, bytes: ByteArray): Boolean
+
+    /** Close the journal's file, leaving it for [sessionRecover], and free the handle. */
+    external fun journalClose(handle: Long)
+
+    /**
+     * Rebuild the VT [vtHandle] from the journal in [fd]: its newest
+     * checkpoint, then the output since. [fd] stays open.
+     * @return false for an invalid handle or fd, or a file that isn't a
+     *   readable journal
+     */
+    external fun sessionRecover(fd: Int, vtHandle: Long): Boolean
+
+    // Scrollback (see `rust/src/scrollback.rs`)
+
+    /**
+     * Limit the scrollback a VT keeps to [maxLines] lines and about
+     * [maxBytes] bytes, whichever is tighter; negative means no limit.
+     * A new limit drops the scrollback kept so far, so set it before
+     * feeding. Kept across [vtReset].
+     */
+    external fun vtSetScrollbackRetention(handle: Long, maxLines: Int, maxBytes: Long)
+
+    // Memory limits (see `rust/src/limits.rs`)
+
+    /**
+     * Limit what a VT holds: [maxScrollbackLines] scrollback lines,
+     * [maxImageBytes] bytes of image RGBA and about [maxTotalBytes] bytes in
+     * all; negative means no limit. The oldest scrollback lines and the
+     * least recently used images are evicted first, images before
+     * scrollback when over the total. An evicted image keeps its place as
+     * a placeholder with no pixels.
+     * Replaces [vtSetScrollbackRetention]'s limits and drops the scrollback
+     * kept so far. Kept across [vtReset].
+     */
+    external fun vtSetLimits(handle: Long, maxScrollbackLines: Int, maxImageBytes: Long, maxTotalBytes: Long)
+
+    /**
+     * Approximate bytes held by the VT's parts.
+     * @return `screen scrollback images links history buffers`, then the
+     *   counts of images holding pixels and of evictions; empty for an
+     *   invalid handle
+     */
+    external fun vtMemoryUsage(handle: Long): LongArray
+
+    /**
+     * Number of lines scrolled off the top of the screen and still kept.
+     * @return 0 for an invalid handle
+     */
+    external fun vtScrollbackLen(handle: Long): Int
+
+    /**
+     * Up to [count] scrollback lines from [start], 0 being the oldest.
+     * @return `total start line_count line*`, lines as in [vtSnapshot];
+     *   empty for an invalid handle or a negative argument
+     */
+    external fun vtScrollbackLines(handle: Long, start: Int, count: Int): ByteArray
+
+    /** [vtExtractText] mode: reading order, from one cell to the other. */
+    const val SELECT_LINEAR = 0
+
+    /** [vtExtractText] mode: the rectangle with the cells at its corners. */
+    const val SELECT_BLOCK = 1
+
+    /**
+     * Plain text between two cells, both included, for copy to clipboard.
+     * Rows count through the history: scrollback from the oldest line kept
+     * ([vtScrollbackLen] of them), then the visible rows. A wide character
+     * is copied whole if either of its columns is selected, and trailing
+     * whitespace is trimmed from each row.
+     * @param mode SELECT_LINEAR or SELECT_BLOCK
+     * @return UTF-8 text, rows joined with '\n'; empty array for an invalid
+     *   handle, mode or negative coordinate
+     */
+    external fun vtExtractText(
+        handle: Long,
+        startRow: Int,
+        startCol: Int,
+        endRow: Int,
+        endCol: Int,
+        mode: Int
+    ): ByteArray
+
+    /** [vtSearch] flag: match letters in either case. */
+    const val SEARCH_IGNORE_CASE = 0x01
+
+    /** [vtSearch] flag: only matches not inside a longer word. */
+    const val SEARCH_WHOLE_WORD = 0x02
+
+    /** [vtSearch] flag: the query is a pattern, in the [vtWatch] subset. */
+    const val SEARCH_REGEX = 0x04
+
+    /**
+     * Find [query] in the scrollback and the screen, rows counted as for
+     * [vtExtractText]. Matches stay within a row, trailing blanks trimmed,
+     * and don't overlap; at most 10000 are returned.
+     * @param flags Bitwise OR of SEARCH_* flags
+     * @return `match_count (row col_start col_end)*`, varints, columns
+     *   `col_start until col_end`; empty array for an invalid handle or
+     *   flags, or an unsupported pattern
+     */
+    external fun vtSearch(handle: Long, query: String, flags: Int): ByteArray
+
+    // Transcript export (native `exporters` feature, see `rust/src/transcript.rs`)
+
+    /** [vtExportScrollback] format: plain text. */
+    const val EXPORT_TEXT = 0
+
+    /** [vtExportScrollback] format: text with SGR color and style sequences. */
+    const val EXPORT_ANSI = 1
+
+    /** [vtExportScrollback] format: standalone HTML page. */
+    const val EXPORT_HTML = 2
+
+    /**
+     * Write the scrollback, oldest first, then the visible rows to [fd] in
+     * bounded chunks, without copying the history into the Java heap.
+     * Trailing unstyled blanks are trimmed. [fd] stays open.
+     * @param format One of EXPORT_TEXT, EXPORT_ANSI, EXPORT_HTML
+     * @return Bytes written, or -1 for an invalid handle, fd or format, or a
+     *   failed write
+     */
+    external fun vtExportScrollback(handle: Long, fd: Int, format: Int): Long
+
+    /**
+     * The screen as a standalone SVG document in the style of svg-term:
+     * text runs, background colors and the cursor, set in the viewer's
+     * monospace font.
+     * @param palette As for [vtResolveStyles]
+     * @return The document, or empty string if handle invalid
+     */
+    external fun vtExportSvg(handle: Long, palette: IntArray): String
+
+    /**
+     * The visible rows, after the scrollback if [withScrollback], as a
+     * standalone HTML page, as [vtExportScrollback] writes with
+     * EXPORT_HTML, for "copy as rich text".
+     * @param palette As for [vtResolveStyles]
+     * @return The page, or empty string if handle or palette invalid
+     */
+    external fun vtExportHtml(handle: Long, palette: IntArray, withScrollback: Boolean): String
+
+    /**
+     * [vtExportHtml] as text with SGR color and style sequences.
+     * @return The text, or empty string if handle invalid
+     */
+    external fun vtExportAnsi(handle: Long, withScrollback: Boolean): String
+
+    /**
+     * [vtExportSvg] of the cast [castHandle] as shown [timeMicros] into
+     * playback, replayed on a temporary VT within the call.
+     * @return The document, or empty string if handle or time invalid
+     */
+    external fun castExportSvg(castHandle: Long, timeMicros: Long, palette: IntArray): String
+
+    // Software rendering (native `renderer` feature, see `rust/src/render.rs`
+    // and `rust/src/gif.rs`)
+
+    /**
+     * Draw the screen as [width] x [height] pixels into the direct
+     * [buffer], in the layout `Bitmap.copyPixelsFromBuffer` reads for
+     * `ARGB_8888`. Text uses the bundled DejaVu Sans Mono sized to the cell
+     * (the size divided by the terminal's); leftover pixels are background.
+     * Bold is bright as [vtSetBoldAsBright] set.
+     * @param themeId A theme of [themeRegister], or a built-in one
+     * @return false, drawing nothing, if handle invalid, no such theme,
+     *   [buffer] not direct, or smaller than `4 * width * height` bytes
+     */
+    external fun vtRenderBitmap(
+        handle: Long,
+        buffer: ByteBuffer,
+        width: Int,
+        height: Int,
+        themeId: String
+    ): Boolean
+
+    /**
+     * [vtRenderBitmap] for an always-on display at [nowMillis], shifted and
+     * with static rows dimmed as [vtBurnIn] says. The terminal is drawn
+     * inside a margin of the largest shift, so it's never cut off.
+     * @param protection As for [vtBurnIn]
+     * @return false, drawing nothing, as for [vtRenderBitmap] or if
+     *   [nowMillis] negative
+     */
+    external fun vtRenderBitmapProtected(
+        handle: Long,
+        buffer: ByteBuffer,
+        width: Int,
+        height: Int,
+        themeId: String,
+        nowMillis: Long,
+        protection: IntArray
+    ): Boolean
+
+    /**
+     * Write [startMicros] until [endMicros] of playback of the cast
+     * [castHandle] to [fd] as a looping animated GIF, replayed on a
+     * temporary VT and drawn as by [vtRenderBitmap] at [fps] frames a
+     * second (1 to 50). Unchanged frames are merged into one. Cells are at
+     * most 12 pixels wide, fewer to keep the image within [maxWidthPx]
+     * (0 for no limit). [progress], if not null, is called after every
+     * frame, on the calling thread; returning false (or throwing) stops
+     * the export. [fd] stays open.
+     * @param palette As for [vtResolveStyles]
+     * @return Bytes written, or -1 for an invalid argument, a failed write
+     *   or a stopped export, after which [fd] holds part of a GIF
+     */
+    external fun castExportGif(
+        castHandle: Long,
+        startMicros: Long,
+        endMicros: Long,
+        fps: Int,
+        palette: IntArray,
+        maxWidthPx: Int,
+        fd: Int,
+        progress: AvtProgress?
+    ): Long
+
+    // Background parsing (see `rust/src/worker.rs`)
+
+    /**
+     * Parse [vtFeedAsync]'s bytes on a native thread of the VT's own, from a
+     * queue of [queueBytes] (0 or less for 1 MiB). Other calls on the
+     * handle wait for at most one slice of parsing.
+     * @return false for an invalid handle, a player's VT or one that already
+     *   has a worker
+     */
+    external fun vtStartWorker(handle: Long, queueBytes: Int): Boolean
+
+    /**
+     * Queue bytes for the worker to parse and return at once; one thread at
+     * a time. [vtPollDiff] adds up everything parsed since the last poll.
+     * @return how many bytes fit, the rest to be fed again later; 0 for an
+     *   invalid handle or without a worker
+     */
+    external fun vtFeedAsync(handle: Long, bytes: ByteArray): Int
+
+    /** Bytes queued for the worker and not yet parsed; 0 without one. */
+    external fun vtQueuedBytes(handle: Long): Int
+
+    /**
+     * Parse what's queued and end the worker, so feeds are on the caller's
+     * thread again. [vtFree] ends it without parsing the rest.
+     * @return false if the VT had no worker
+     */
+    external fun vtStopWorker(handle: Long): Boolean
+
+    // Performance counters (see `rust/src/perf.rs`)
+
+    /**
+     * Work done and time spent by the VT since it was created, resets
+     * included (see [AvtVtStats]).
+     * @return `bytesFed feeds parseNanos snapshots snapshotNanos diffs
+     *   diffNanos maxDirtyLines`; empty for an invalid handle
+     */
+    external fun vtStats(handle: Long): LongArray
+
+    // Allocation stats (native `alloc-stats` feature, see `rust/src/alloc_stats.rs`)
+
+    /**
+     * Allocations counted since the library loaded, for the whole process
+     * (see [AvtAllocStats]).
+     * @return `[allocations, bytes]` for each of grid, parser, encoder and
+     *   other, then `[liveBytes, peakBytes]`
+     */
+    external fun vtAllocStats(): LongArray
+
+    // Scratch files (see `rust/src/scratch.rs`)
+
+    /**
+     * Where exports that spool to disk keep their scratch files, e.g.
+     * `File(context.cacheDir, "avt")`; created if missing. Until this is
+     * called, such exports fail rather than write to the working directory.
+     * @return false if the directory can't be created
+     */
+    external fun vtSetScratchDir(path: String): Boolean
+
+    /**
+     * Remove scratch files left by processes that died mid-export; call
+     * once at startup, after [vtSetScratchDir].
+     * @return Files removed, or -1 without a scratch directory
+     */
+    external fun cleanupOrphanedTemp(): Int
+
+    // Viewing metadata sync (see `rust/src/viewsync.rs`)
+
+    /**
+     * Merge two sync payloads (see [AvtSyncItem]): every item of either,
+     * the last modified where both have one, deletions included.
+     * @return The merged payload, or empty array if either doesn't decode
+     */
+    external fun syncMerge(local: ByteArray, remote: ByteArray): ByteArray
+
+    // Wire protocol (see `rust/src/protocol.rs`)
+
+    /** The newest snapshot and diff protocol version the library writes. */
+    external fun vtProtocolVersion(): Int
+
+    /**
+     * Send [handle]'s snapshots and diffs behind a header with the
+     * protocol version (the lower of [version] and the library's) and
+     * features, leaving out any feature not in [features]; version 0
+     * sends no header. Kept across resets.
+     * @param features `AvtSchema.Protocol.FEATURE_*` bits
+     * @return The features it will send, or -1 for an invalid handle
+     */
+    external fun vtSetWireFormat(handle: Long, version: Int, features: Int): Int
+
+    // Input modes (see `rust/src/modes.rs`)
+
+    /**
+     * Input modes the program has set: bracketed paste, mouse reporting,
+     * application cursor keys and keypad.
+     * @return `AvtSchema.Modes` bits, or 0 for an invalid handle
+     */
+    external fun vtModes(handle: Long): Int
 }
@@ -0,0 +1,8 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Thrown by [AvtNative] calls when native code panics. The call returned
+ * nothing useful, and the handle it was given may be left inconsistent:
+ * free it and start over rather than keep using it.
+ */
+class AvtNativeException(message: String) : RuntimeException(message)
@@ -0,0 +1,162 @@
+// Generated from rust/src/schema.rs; don't edit. Regenerate with
+// `BLESS=1 cargo test schema_tests` or `./gradlew generateAvtSchema`.
+
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Tags, flag bits and versions of the native wire formats, each
+ * documented in the Rust module named on its object, and decoders
+ * of their flags.
+ */
+object AvtSchema {
+    /** Of every constant below; the library's is `AvtNative.vtSchemaHash` */
+    const val HASH = -6306270529404125455L
+
+    /** rust/src/snapshot.rs */
+    object Snapshot {
+        const val ATTR_BOLD = 1
+        const val ATTR_ITALIC = 2
+        const val ATTR_UNDERLINE = 4
+        const val ATTR_STRIKETHROUGH = 8
+        const val ATTR_BLINK = 16
+        const val ATTR_INVERSE = 32
+        const val ATTR_PREDICTED = 64
+        const val ATTR_FAINT = 128
+        const val CURSOR_VISIBLE = 1
+        const val CURSOR_SHAPE_SHIFT = 1
+        const val CURSOR_SHAPE_MASK = 3
+        const val CURSOR_STEADY = 8
+        const val ALT_SCREEN = 16
+        const val COLOR_INDEXED = 0
+        const val COLOR_RGB = 1
+        const val COLOR_DEFAULT = 2
+        const val EXTENT_LINK = 1
+
+        /** A style's `ATTR_*` bits */
+        @JvmInline
+        value class Attrs(val bits: Int) {
+            val bold: Boolean get() = bits and ATTR_BOLD != 0
+            val italic: Boolean get() = bits and ATTR_ITALIC != 0
+            val underline: Boolean get() = bits and ATTR_UNDERLINE != 0
+            val strikethrough: Boolean get() = bits and ATTR_STRIKETHROUGH != 0
+            val blink: Boolean get() = bits and ATTR_BLINK != 0
+            val inverse: Boolean get() = bits and ATTR_INVERSE != 0
+            val predicted: Boolean get() = bits and ATTR_PREDICTED != 0
+            val faint: Boolean get() = bits and ATTR_FAINT != 0
+        }
+
+        /** The cursor's flags byte */
+        @JvmInline
+        value class CursorFlags(val bits: Int) {
+            val visible: Boolean get() = bits and CURSOR_VISIBLE != 0
+            val steady: Boolean get() = bits and CURSOR_STEADY != 0
+            val altScreen: Boolean get() = bits and ALT_SCREEN != 0
+            val shape: Int get() = (bits shr CURSOR_SHAPE_SHIFT) and CURSOR_SHAPE_MASK
+        }
+    }
+
+    /** rust/src/diff.rs */
+    object Diff {
+        const val KIND_NONE = 0
+        const val KIND_LINES = 1
+        const val KIND_CURSOR = 2
+        const val KIND_TRACED = 3
+        const val KIND_CONTENT = 4
+        const val KIND_SPANS = 5
+        const val KIND_INTERNED = 6
+        const val KIND_DAMAGE = 7
+        const val KIND_VERSIONED = 8
+        const val CURSOR_MOVED = 1
+        const val CURSOR_RESTYLED = 2
+        const val RESIZED = 1
+        const val SCREEN_SWITCHED = 2
+        const val MODES_CHANGED = 4
+        const val IMAGES_CHANGED = 8
+        const val IMAGE_ADDED = 1
+        const val IMAGE_MOVED = 2
+        const val IMAGE_REMOVED = 3
+
+        /** A diff's cursor byte */
+        @JvmInline
+        value class CursorChange(val bits: Int) {
+            val moved: Boolean get() = bits and CURSOR_MOVED != 0
+            val restyled: Boolean get() = bits and CURSOR_RESTYLED != 0
+        }
+
+        /** A diff's `resized` byte */
+        @JvmInline
+        value class ScreenChange(val bits: Int) {
+            val resized: Boolean get() = bits and RESIZED != 0
+            val screenSwitched: Boolean get() = bits and SCREEN_SWITCHED != 0
+            val modesChanged: Boolean get() = bits and MODES_CHANGED != 0
+            val imagesChanged: Boolean get() = bits and IMAGES_CHANGED != 0
+        }
+    }
+
+    /** rust/src/styles.rs */
+    object Styles {
+        const val COLOR_DEFAULT = 0
+        const val COLOR_ANSI = 1
+        const val COLOR_INDEXED = 2
+        const val COLOR_RGB = 3
+    }
+
+    /** rust/src/events.rs */
+    object Events {
+        const val VERSION = 1
+        const val VERSION_TIMED = 2
+        const val TAG_BELL = 1
+        const val TAG_TITLE = 2
+        const val TAG_MARKER = 3
+        const val TAG_RESIZE = 4
+        const val TAG_IMAGE = 5
+        const val TAG_CLIPBOARD = 6
+        const val TAG_NOTIFICATION = 7
+        const val TAG_WATCH = 8
+        const val TAG_ACTIVITY = 9
+        const val TAG_INPUT = 10
+        const val TAG_OPTION = 11
+        const val TAG_IMAGE_ADDED = 12
+        const val TAG_IMAGE_REMOVED = 13
+        const val IMAGE_SIXEL = 1
+        const val IMAGE_ITERM = 2
+        const val ACTIVITY_ACTIVE = 0
+        const val ACTIVITY_IDLE = 1
+        const val ACTIVITY_DISCONNECTED = 2
+        const val OPTION_THEME = 1
+        const val OPTION_BOLD_AS_BRIGHT = 2
+        const val OPTION_CURSOR_POLICY = 3
+    }
+
+    /** rust/src/state.rs */
+    object State {
+        const val DUMP_FORMAT = 1
+        const val AVT_VERSION = "0.17.0+86302bcf"
+    }
+
+    /** rust/src/persist.rs */
+    object Persist {
+        const val VERSION = 1
+    }
+
+    /** rust/src/modes.rs */
+    object Modes {
+        const val APP_CURSOR_KEYS = 1
+        const val APP_KEYPAD = 2
+        const val BRACKETED_PASTE = 4
+        const val MOUSE_CLICKS = 8
+        const val MOUSE_DRAG = 16
+        const val MOUSE_MOTION = 32
+        const val MOUSE_SGR = 64
+    }
+
+    /** rust/src/protocol.rs */
+    object Protocol {
+        const val VERSION = 1
+        const val SNAPSHOT_HEADER = 0
+        const val FEATURE_LINKS = 1
+        const val FEATURE_IMAGES = 2
+        const val FEATURE_CURSOR_SHAPE = 4
+        const val FEATURES = 7
+    }
+}
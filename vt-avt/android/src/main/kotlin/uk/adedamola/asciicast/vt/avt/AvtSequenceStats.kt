@@ -0,0 +1,52 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Which escape sequences a cast's output uses and how much of it is
+ * inline images, to explain a recording that plays back wrong ("uses
+ * kitty graphics, which the player doesn't show").
+ */
+data class AvtSequenceStats(
+    /** Occurrences of each sequence, by name, e.g. `CSI ?h` or `OSC 8` */
+    val counts: Map<String, Long>,
+    /** Sequences neither the emulator nor the player acts on */
+    val unknown: Long,
+    val sixelBytes: Long,
+    val kittyBytes: Long,
+    val itermBytes: Long
+) {
+    val hasImages: Boolean
+        get() = sixelBytes > 0 || kittyBytes > 0 || itermBytes > 0
+
+    /** One line per sequence, most used first, for logs and bug reports. */
+    fun dump(): String = buildString {
+        for ((name, count) in counts.entries.sortedByDescending { it.value }) {
+            append("$name: $count\n")
+        }
+        append("unknown: $unknown, sixel: $sixelBytes bytes, kitty: $kittyBytes bytes, ")
+        append("iTerm2: $itermBytes bytes")
+    }
+
+    companion object {
+        /** Count the sequences of a cast (see [AvtNative.castOpen]); null if handle invalid. */
+        fun of(castHandle: Long): AvtSequenceStats? {
+            val values = AvtNative.castSequenceStats(castHandle)
+            if (values.isEmpty()) return null
+            val counts = LinkedHashMap<String, Long>()
+            for (i in 4 until values.size step 2) {
+                counts[name(values[i])] = values[i + 1]
+            }
+            return AvtSequenceStats(counts, values[0], values[1], values[2], values[3])
+        }
+
+        private fun name(key: Long): String {
+            val prefix = ((key shr 32) and 0xff).toInt()
+            val id = key.toInt()
+            val prefixText = if (prefix == 0) "" else prefix.toChar().toString()
+            return when ((key shr 40).toInt()) {
+                0 -> "CSI $prefixText${id.toChar()}"
+                1 -> if (id < 0) "OSC" else "OSC $id"
+                else -> "DCS $prefixText${if (id < 0) "" else id.toChar()}".trimEnd()
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Byte stream from a hardware console, fed to an [AvtVirtualTerminal].
+ *
+ * The reader thread (e.g. usb-serial-for-android's
+ * `SerialInputOutputManager.Listener.onNewData`) calls [push]; the
+ * terminal's thread calls [drain] before polling diffs. Bytes are queued
+ * in native code in between, with line endings normalized as configured.
+ *
+ * Thread safety: [push] from any thread, everything else from the
+ * terminal's thread.
+ */
+class AvtSerialStream internal constructor(
+    vtHandle: Long,
+    newline: Int,
+    private val flowControl: FlowControl?
+) : AutoCloseable {
+
+    /**
+     * Flow control for readers that can hold data back (stop reading, or
+     * drop RTS). Data pushed while paused is still queued.
+     */
+    interface FlowControl {
+        /** The queue is over its high watermark; called on the reader thread. */
+        fun pause()
+
+        /** The queue has drained; called on the terminal's thread. */
+        fun resume()
+    }
+
+    private var handle: Long = AvtNative.streamAttach(vtHandle, newline).also {
+        require(it != 0L) { "invalid terminal or newline mode" }
+    }
+
+    /** Pause above [high] queued bytes, resume at or below [low]. */
+    fun setWatermarks(high: Int, low: Int) {
+        require(low in 0 until high) { "need 0 <= low < high" }
+        AvtNative.streamSetWatermarks(handle, high, low)
+    }
+
+    fun push(bytes: ByteArray) {
+        if (AvtNative.streamPush(handle, bytes) == AvtNative.FLOW_PAUSE) {
+            flowControl?.pause()
+        }
+    }
+
+    /**
+     * Feed queued bytes to the terminal.
+     * @param maxBytes At most this many, to bound the work per frame; 0 for all
+     */
+    fun drain(maxBytes: Int = 0) {
+        require(maxBytes >= 0) { "maxBytes must not be negative" }
+        if (AvtNative.streamDrain(handle, maxBytes) == AvtNative.FLOW_RESUME) {
+            flowControl?.resume()
+        }
+    }
+
+    override fun close() {
+        if (handle != 0L) {
+            AvtNative.streamDetach(handle)
+            handle = 0
+        }
+    }
+}
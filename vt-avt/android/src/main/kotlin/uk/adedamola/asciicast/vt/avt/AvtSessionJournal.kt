@@ -0,0 +1,35 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * Journal of an interactive session's output, kept in a ring file so the
+ * terminal can be rebuilt with [AvtVirtualTerminal.recoverSession] if the
+ * process dies. Feed the session's output through [feed] instead of the
+ * terminal; [close] leaves the file in place, so delete it after a clean
+ * exit.
+ *
+ * Thread safety: the terminal's thread only.
+ */
+class AvtSessionJournal internal constructor(
+    private val vtHandle: Long,
+    fd: Int,
+    capacity: Long
+) : AutoCloseable {
+
+    // Native code closes fd on failure, so check nothing before handing it over
+    private var handle: Long = AvtNative.journalOpen(fd, capacity, vtHandle).also {
+        require(it != 0L) { "invalid terminal, capacity too small or journal unwritable" }
+    }
+
+    /**
+     * Feed [bytes] to the terminal and journal them.
+     * @return false if the journal write failed; the terminal is fed regardless
+     */
+    fun feed(bytes: ByteArray): Boolean = AvtNative.journalFeed(handle, vtHandle, bytes)
+
+    override fun close() {
+        if (handle != 0L) {
+            AvtNative.journalClose(handle)
+            handle = 0
+        }
+    }
+}
@@ -0,0 +1,57 @@
+package uk.adedamola.asciicast.vt.avt
+
+import android.os.ParcelFileDescriptor
+import java.io.IOException
+
+/** What [AvtSignedExport.verify] found. */
+sealed interface AvtSignatureCheck {
+    /** Signed by the key and unchanged. */
+    data object Valid : AvtSignatureCheck
+
+    /** No signature at the end of the file. */
+    data object Unsigned : AvtSignatureCheck
+
+    /** Signed, but not by the key. */
+    data object WrongKey : AvtSignatureCheck
+
+    /** The signature can't be read. */
+    data object Malformed : AvtSignatureCheck
+
+    /** Signed by the key, but changed from 1-based [line] on. */
+    data class Altered(val line: Long) : AvtSignatureCheck
+}
+
+/**
+ * Tamper-evident sharing: casts signed with an Ed25519 key the app keeps,
+ * checked by whoever has the public key. Needs the native `signing`
+ * feature.
+ */
+object AvtSignedExport {
+    /** The public key to share for [seed], a 32-byte Ed25519 private key. */
+    fun publicKey(seed: ByteArray): ByteArray {
+        require(seed.size == 32) { "seed must be 32 bytes" }
+        return AvtNative.signPublicKey(seed) ?: throw IllegalStateException("signing failed")
+    }
+
+    /**
+     * Write the cast [castHandle] (from [AvtNative.castOpen]) to [file]
+     * signed with [seed]. [file] stays open.
+     */
+    fun export(castHandle: Long, file: ParcelFileDescriptor, seed: ByteArray) {
+        require(castHandle != 0L) { "invalid cast handle" }
+        require(seed.size == 32) { "seed must be 32 bytes" }
+        if (!AvtNative.castExportSigned(castHandle, file.fd, seed)) {
+            throw IOException("signed export failed")
+        }
+    }
+
+    /** Check the signed cast in [file] against [publicKey]. [file] stays open. */
+    fun verify(file: ParcelFileDescriptor, publicKey: ByteArray): AvtSignatureCheck =
+        when (val result = AvtNative.castVerifySignature(file.fd, publicKey)) {
+            0L -> AvtSignatureCheck.Valid
+            -1L -> AvtSignatureCheck.Unsigned
+            -2L -> AvtSignatureCheck.WrongKey
+            -3L -> AvtSignatureCheck.Malformed
+            else -> AvtSignatureCheck.Altered(result)
+        }
+}
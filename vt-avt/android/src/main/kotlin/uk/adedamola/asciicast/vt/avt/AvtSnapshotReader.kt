@@ -0,0 +1,29 @@
+package uk.adedamola.asciicast.vt.avt
+
+import uk.adedamola.asciicast.vt.TerminalFrame
+
+/**
+ * The screen of an [AvtVirtualTerminal], as last published by its feeds,
+ * from [AvtVirtualTerminal.openSnapshotReader].
+ *
+ * Thread safety: one thread at a time, which needn't be the terminal's;
+ * reading never waits for a feed.
+ */
+class AvtSnapshotReader internal constructor(
+    private var handle: Long,
+    private val decode: (ByteArray) -> TerminalFrame
+) : AutoCloseable {
+
+    /** The latest published screen. */
+    fun snapshot(): TerminalFrame {
+        check(handle != 0L) { "reader closed" }
+        return decode(AvtNative.readerSnapshot(handle))
+    }
+
+    override fun close() {
+        if (handle != 0L) {
+            AvtNative.readerFree(handle)
+            handle = 0
+        }
+    }
+}
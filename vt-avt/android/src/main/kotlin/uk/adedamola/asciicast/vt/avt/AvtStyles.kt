@@ -0,0 +1,81 @@
+package uk.adedamola.asciicast.vt.avt
+
+import uk.adedamola.asciicast.vt.CellStyle
+import uk.adedamola.asciicast.vt.Color
+import uk.adedamola.asciicast.vt.Theme
+
+/**
+ * Final colors for a [CellStyle], ready for a SpanStyle.
+ *
+ * @property foreground ARGB color int
+ * @property background ARGB color int
+ * @property attrs Attribute bits from the snapshot format, with inverse
+ *   already applied to the colors
+ */
+data class ResolvedStyle(
+    val foreground: Int,
+    val background: Int,
+    val attrs: Int
+)
+
+/**
+ * Batch color resolution through the native resolver, so palette,
+ * bold-as-bright and contrast rules match the Rust side exactly.
+ */
+object AvtStyles {
+    /**
+     * Pack a style into the id used by snapshot.rs Style::id().
+     */
+    fun styleId(style: CellStyle): Long {
+        var attrs = 0
+        if (style.bold) attrs = attrs or AvtSchema.Snapshot.ATTR_BOLD
+        if (style.italic) attrs = attrs or AvtSchema.Snapshot.ATTR_ITALIC
+        if (style.underline) attrs = attrs or AvtSchema.Snapshot.ATTR_UNDERLINE
+        if (style.strikethrough) attrs = attrs or AvtSchema.Snapshot.ATTR_STRIKETHROUGH
+        if (style.blink) attrs = attrs or AvtSchema.Snapshot.ATTR_BLINK
+        if (style.reverse) attrs = attrs or AvtSchema.Snapshot.ATTR_INVERSE
+
+        return attrs.toLong() or
+            (colorBits(style.foreground) shl 8) or
+            (colorBits(style.background) shl 34)
+    }
+
+    /**
+     * Resolve [styles] in a single native call.
+     */
+    fun resolve(
+        styles: List<CellStyle>,
+        theme: Theme,
+        boldAsBright: Boolean = false,
+        minContrast: Float = 1f
+    ): List<ResolvedStyle> {
+        val ids = LongArray(styles.size) { styleId(styles[it]) }
+        val options = if (boldAsBright) AvtNative.RESOLVE_BOLD_AS_BRIGHT else 0
+        val out = AvtNative.vtResolveStyles(ids, paletteOf(theme), options, minContrast)
+        if (out.size != styles.size * 3) return emptyList()
+
+        return List(styles.size) { i ->
+            ResolvedStyle(
+                foreground = out[i * 3],
+                background = out[i * 3 + 1],
+                attrs = out[i * 3 + 2]
+            )
+        }
+    }
+
+    private fun colorBits(color: Color): Long = when (color) {
+        is Color.Indexed -> (color.index and 0xFF).toLong()
+        is Color.Rgb -> (1L shl 24) or (color.r.toLong() shl 16) or
+            (color.g.toLong() shl 8) or color.b.toLong()
+        is Color.Default -> 2L shl 24
+    }
+
+    internal fun paletteOf(theme: Theme): IntArray {
+        val entries = theme.palette16 ?: theme.palette8 ?: emptyList()
+        val colors = listOf(theme.foreground, theme.background) + entries
+        return IntArray(colors.size) { i ->
+            val c = colors[i]
+            (c.r shl 16) or (c.g shl 8) or c.b
+        }
+    }
+}
@@ -0,0 +1,131 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.io.ByteArrayOutputStream
+import java.nio.ByteBuffer
+
+/**
+ * A bookmark, annotation or marker the user set on a recording, as synced
+ * between devices through the app's backend. Mirrors `rust/src/viewsync.rs`,
+ * which documents the payload format and how [merge] settles conflicts.
+ *
+ * @property kind One of [KIND_BOOKMARK], [KIND_ANNOTATION], [KIND_MARKER]
+ * @property cast Hex SHA-256 of the recording
+ * @property id Unique within [cast] and [kind], picked by the device that
+ *   created the item (a UUID)
+ * @property modifiedMillis When the item last changed, Unix milliseconds
+ * @property deleted A deletion, kept so it reaches every device
+ */
+data class AvtSyncItem(
+    val kind: Int,
+    val cast: String,
+    val id: String,
+    val modifiedMillis: Long,
+    val deleted: Boolean = false,
+    val timeMicros: Long,
+    val durationMicros: Long = 0,
+    val text: String = ""
+) {
+    companion object {
+        /** Payload format version this codec reads and writes */
+        const val VERSION = 1
+
+        const val KIND_BOOKMARK = 1
+        const val KIND_ANNOTATION = 2
+        const val KIND_MARKER = 3
+
+        private const val DELETED = 0x01
+
+        fun encode(items: List<AvtSyncItem>): ByteArray {
+            val out = ByteArrayOutputStream()
+            out.write(VERSION)
+            out.writeVarint(items.size.toLong())
+            for (item in items) {
+                val body = ByteArrayOutputStream()
+                body.writeText(item.cast)
+                body.writeText(item.id)
+                body.writeVarint(item.modifiedMillis)
+                body.write(if (item.deleted) DELETED else 0)
+                body.writeVarint(item.timeMicros)
+                body.writeVarint(item.durationMicros)
+                body.writeText(item.text)
+                out.write(item.kind)
+                out.writeVarint(body.size().toLong())
+                body.writeTo(out)
+            }
+            return out.toByteArray()
+        }
+
+        /**
+         * Decode a payload, ignoring body bytes past the fields known here.
+         * @throws IllegalArgumentException for another version
+         */
+        fun decode(bytes: ByteArray): List<AvtSyncItem> {
+            val buffer = ByteBuffer.wrap(bytes)
+            val version = buffer.get().toInt() and 0xFF
+            require(version == VERSION) { "unsupported sync payload version $version" }
+
+            return List(buffer.readVarint().toInt()) {
+                val kind = buffer.get().toInt() and 0xFF
+                val bodyBytes = ByteArray(buffer.readVarint().toInt()).also { buffer.get(it) }
+                val body = ByteBuffer.wrap(bodyBytes)
+                AvtSyncItem(
+                    kind = kind,
+                    cast = body.readText(),
+                    id = body.readText(),
+                    modifiedMillis = body.readVarint(),
+                    deleted = (body.get().toInt() and DELETED) != 0,
+                    timeMicros = body.readVarint(),
+                    durationMicros = body.readVarint(),
+                    text = body.readText()
+                )
+            }
+        }
+
+        /**
+         * Merge the [local] and [remote] payloads natively, keeping fields
+         * and kinds from newer app versions this codec would drop.
+         * @throws IllegalArgumentException if either doesn't decode
+         */
+        fun merge(local: ByteArray, remote: ByteArray): ByteArray {
+            val merged = AvtNative.syncMerge(local, remote)
+            require(merged.isNotEmpty()) { "invalid sync payload" }
+            return merged
+        }
+
+        private fun ByteArrayOutputStream.writeText(text: String) {
+            val bytes = text.toByteArray(Charsets.UTF_8)
+            writeVarint(bytes.size.toLong())
+            write(bytes)
+        }
+
+        private fun ByteArrayOutputStream.writeVarint(value: Long) {
+            var rest = value
+            while (rest !in 0..0x7F) {
+                write(((rest and 0x7F) or 0x80).toInt())
+                rest = rest ushr 7
+            }
+            write(rest.toInt())
+        }
+
+        private fun ByteBuffer.readText(): String =
+            String(ByteArray(readVarint().toInt()).also { get(it) }, Charsets.UTF_8)
+
+        private fun ByteBuffer.readVarint(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
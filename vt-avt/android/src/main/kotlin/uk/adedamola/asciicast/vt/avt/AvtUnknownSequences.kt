@@ -0,0 +1,75 @@
+package uk.adedamola.asciicast.vt.avt
+
+import java.nio.ByteBuffer
+
+/**
+ * Escape sequences a terminal was fed that neither the emulator nor the
+ * player acts on, one [Entry] per kind in the order they first came, from
+ * [read]. Meant for bug reports, to tell which missing features users'
+ * recordings actually need.
+ *
+ * @property unlogged Unknown sequences of kinds past the first 32, only counted
+ */
+data class AvtUnknownSequences(val entries: List<Entry>, val unlogged: Long) {
+
+    /**
+     * A kind of sequence (same final byte and intermediates, or OSC
+     * number): the first one as [sample], how often one came, and the
+     * event time in microseconds of the first.
+     * @property sample Controls spelled out, e.g. `ESC[12;3y`; cut at 48 bytes
+     */
+    data class Entry(val sample: String, val count: Long, val firstMicros: Long)
+
+    /** One line per kind, for logs and bug reports. */
+    fun dump(): String = buildString {
+        for (entry in entries) {
+            append("${entry.sample}: ${entry.count}, first at ${entry.firstMicros}us\n")
+        }
+        append("unlogged: $unlogged")
+    }
+
+    companion object {
+        /** The log of the VT [vtHandle]; null if the handle is invalid. */
+        fun read(vtHandle: Long): AvtUnknownSequences? {
+            val buffer = ByteBuffer.wrap(AvtNative.vtUnknownSequences(vtHandle))
+            if (!buffer.hasRemaining()) return null
+
+            val unlogged = buffer.readVarint()
+            val entries = List(buffer.readVarint().toInt()) {
+                val count = buffer.readVarint()
+                val firstMicros = buffer.readVarint()
+                val sample = ByteArray(buffer.readVarint().toInt()).also { buffer.get(it) }
+                Entry(printable(sample), count, firstMicros)
+            }
+            return AvtUnknownSequences(entries, unlogged)
+        }
+
+        private fun printable(sample: ByteArray): String = buildString {
+            for (ch in String(sample, Charsets.UTF_8)) {
+                when {
+                    ch == '\u001b' -> append("ESC")
+                    ch < ' ' -> append('^').append(ch + 0x40)
+                    else -> append(ch)
+                }
+            }
+        }
+
+        private fun ByteBuffer.readVarint(): Long {
+            var result = 0L
+            var shift = 0
+
+            while (true) {
+                val byte = get().toInt() and 0xFF
+                result = result or ((byte and 0x7F).toLong() shl shift)
+
+                if ((byte and 0x80) == 0) {
+                    break
+                }
+
+                shift += 7
+            }
+
+            return result
+        }
+    }
+}
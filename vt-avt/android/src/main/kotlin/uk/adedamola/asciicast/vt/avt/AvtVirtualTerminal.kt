@@ -1,7 +1,13 @@
 package uk.adedamola.asciicast.vt.avt
 
+import android.os.ParcelFileDescriptor
 import uk.adedamola.asciicast.vt.*
+import java.io.IOException
 import java.nio.ByteBuffer
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Diff
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Protocol
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Snapshot
+import uk.adedamola.asciicast.vt.avt.AvtSchema.Styles
 
 /**
  * VirtualTerminal implementation using Rust avt backend via JNI.
@@ -10,13 +16,26 @@ import java.nio.ByteBuffer
  * compatibility through the avt library.
  *
  * Thread safety: Not thread-safe. Call from a single thread or externally synchronize.
+ *
+ * @param ticker Lightweight mode for widgets that only show the last few
+ *   lines: no scrollback, and cursor movement alone produces no diff
  */
 class AvtVirtualTerminal(
     initialCols: Int = 80,
-    initialRows: Int = 24
+    initialRows: Int = 24,
+    ticker: Boolean = false
 ) : VirtualTerminal {
 
-    private var handle: Long = AvtNative.vtNew(initialCols, initialRows)
+    private var handle: Long = AvtNative.vtNewWithMode(
+        initialCols,
+        initialRows,
+        if (ticker) AvtNative.MODE_TICKER else AvtNative.MODE_FULL
+    )
+
+    init {
+        // Versioned headers, so a newer library keeps to what we decode
+        AvtNative.vtSetWireFormat(handle, Protocol.VERSION, Protocol.FEATURES)
+    }
 
     override var cols: Int = initialCols
         private set
@@ -37,6 +56,10 @@ class AvtVirtualTerminal(
 
     private var currentTheme: Theme = Theme.DEFAULT
 
+    /** Styles of the interned formats by id, for [styleEpoch] */
+    private val styleCache = ArrayList<CellStyle>()
+    private var styleEpoch: Int? = null
+
     override fun reset(cols: Int, rows: Int, theme: Theme?, initData: String?) {
         require(cols > 0 && rows > 0) { "cols and rows must be positive" }
 
@@ -51,13 +74,19 @@ class AvtVirtualTerminal(
         }
     }
 
-    override fun resize(cols: Int, rows: Int) {
+    override fun resize(cols: Int, rows: Int) = resize(cols, rows, reflow = true)
+
+    /**
+     * Resize, rewrapping soft-wrapped lines to the new width if [reflow]
+     * (e.g. when the device rotates), or else cutting or padding each row.
+     */
+    fun resize(cols: Int, rows: Int, reflow: Boolean) {
         require(cols > 0 && rows > 0) { "cols and rows must be positive" }
 
         this.cols = cols
         this.rows = rows
 
-        AvtNative.vtResize(handle, cols, rows)
+        AvtNative.vtResize(handle, cols, rows, reflow)
     }
 
     override fun feedUtf8(text: String) {
@@ -68,17 +97,68 @@ class AvtVirtualTerminal(
         AvtNative.vtFeed(handle, bytes)
     }
 
+    /**
+     * Parse [feedAsync]'s bytes on a native worker thread from now on, so a
+     * burst doesn't block the feeding thread; see [AvtNative.vtStartWorker].
+     * @return false if there's a worker already
+     */
+    fun startWorker(queueBytes: Int = 0): Boolean = AvtNative.vtStartWorker(handle, queueBytes)
+
+    /**
+     * Queue [bytes] for the worker and return without parsing them. A
+     * queue too full for all of them takes the start.
+     * @return how many were queued; 0 without a worker
+     */
+    fun feedAsync(bytes: ByteArray): Int = AvtNative.vtFeedAsync(handle, bytes)
+
+    /** Parse what's queued and stop the worker; false if there was none. */
+    fun stopWorker(): Boolean = AvtNative.vtStopWorker(handle)
+
+    /**
+     * Feed the bytes between [buffer]'s position and limit without copying
+     * them, and move the position to the limit. [buffer] must be direct.
+     */
+    fun feed(buffer: ByteBuffer) {
+        val fed = AvtNative.vtFeedDirect(handle, buffer, buffer.position(), buffer.remaining())
+        require(fed) { "invalid handle or buffer not direct" }
+        buffer.position(buffer.limit())
+    }
+
+    /**
+     * Feed [chunks] in one native call, e.g. every output event due in a
+     * frame at high playback speed, and return the diff they add up to
+     * (null if there is none to report yet; [pollDiff] later as usual).
+     *
+     * @param deltaMicros Time of each chunk since the previous one, for
+     *   throughput stats; null counts them all as arriving now
+     */
+    fun feedBatch(chunks: List<ByteArray>, deltaMicros: LongArray? = null): TerminalDiff? {
+        require(deltaMicros == null || deltaMicros.size == chunks.size) {
+            "one delta per chunk"
+        }
+        val batch = java.io.ByteArrayOutputStream(1 + chunks.sumOf { it.size + 4 })
+        batch.write(if (deltaMicros != null) AvtNative.BATCH_TIMED else 0)
+        chunks.forEachIndexed { i, chunk ->
+            deltaMicros?.let { batch.writeVarint(it[i].coerceIn(0, 0xFFFF_FFFFL)) }
+            batch.writeVarint(chunk.size.toLong())
+            batch.write(chunk)
+        }
+
+        val diffBytes = AvtNative.vtFeedBatch(handle, batch.toByteArray())
+        return if (diffBytes.isEmpty()) null else decodeDiff(diffBytes)
+    }
+
     override fun snapshot(): TerminalFrame {
-        val snapshotBytes = AvtNative.vtSnapshot(handle)
+        val snapshotBytes = AvtNative.vtSnapshotInterned(handle)
 
         return if (snapshotBytes.isEmpty()) {
-            // Fallback for TODO implementation
+            // Only for a freed handle
             android.util.Log.w("AvtVT", "Snapshot bytes are empty")
             TerminalFrame.empty(cols, rows, currentTheme)
         } else {
             try {
                 android.util.Log.d("AvtVT", "Decoding snapshot, bytes: ${snapshotBytes.size}")
-                decodeSnapshot(snapshotBytes)
+                decodeSnapshot(snapshotBytes, interned = true)
             } catch (e: Exception) {
                 android.util.Log.e("AvtVT", "Error decoding snapshot", e)
                 TerminalFrame.empty(cols, rows, currentTheme)
@@ -86,8 +166,74 @@ class AvtVirtualTerminal(
         }
     }
 
+    /**
+     * Like [snapshot], with only the [rowCount] rows from [firstRow] filled
+     * in; other rows are [TerminalLine.EMPTY]. Lets a lazy list fetch only
+     * the rows it shows.
+     */
+    fun snapshotRegion(firstRow: Int, rowCount: Int): TerminalFrame {
+        require(firstRow >= 0 && rowCount >= 0) { "bad region: $firstRow, $rowCount" }
+        val regionBytes = AvtNative.vtSnapshotRegion(handle, firstRow, rowCount)
+        // Only for a freed handle
+        if (regionBytes.isEmpty()) return TerminalFrame.empty(cols, rows, currentTheme)
+        return decodeRegion(regionBytes)
+    }
+
+    /**
+     * Show the cast [castHandle] (from [AvtNative.castOpen]) as it was
+     * [timeMicros] into playback, replaying it natively from the start,
+     * or from the nearest checkpoint in [checkpointIndex] (from
+     * [AvtNative.checkpointIndexNew]) if one is given.
+     */
+    fun seek(castHandle: Long, timeMicros: Long, checkpointIndex: Long = 0): TerminalFrame {
+        require(timeMicros in 0..AvtNative.MAX_TIME_MICROS) { "time out of range: $timeMicros" }
+        val snapshotBytes = if (checkpointIndex != 0L) {
+            AvtNative.vtSeekIndexed(handle, castHandle, checkpointIndex, timeMicros)
+        } else {
+            AvtNative.vtSeek(handle, castHandle, timeMicros)
+        }
+        require(snapshotBytes.isNotEmpty()) { "invalid cast handle" }
+
+        val frame = decodeSnapshot(snapshotBytes)
+        cols = frame.cols
+        rows = frame.rows
+        return frame
+    }
+
+    /**
+     * [seek] to chapter [index] of [AvtChapter.list] for [castHandle],
+     * through [checkpointIndex] if one is given.
+     */
+    fun seekToMarker(castHandle: Long, index: Int, checkpointIndex: Long = 0): TerminalFrame {
+        val snapshotBytes = AvtNative.vtSeekToMarker(handle, castHandle, index, checkpointIndex)
+        require(snapshotBytes.isNotEmpty()) { "invalid cast handle or marker index: $index" }
+
+        val frame = decodeSnapshot(snapshotBytes)
+        cols = frame.cols
+        rows = frame.rows
+        return frame
+    }
+
+    /**
+     * A preview of the recording in [file] at [timeMicros], replaying no
+     * more than [maxBytes] of its output (see [AvtNative.castQuickFrame]).
+     * The caller closes [file].
+     */
+    fun quickFrame(file: ParcelFileDescriptor, timeMicros: Long, maxBytes: Int = 256 * 1024): TerminalFrame {
+        require(timeMicros in 0..AvtNative.MAX_TIME_MICROS) { "time out of range: $timeMicros" }
+        val snapshotBytes = AvtNative.castQuickFrame(handle, file.fd, timeMicros, maxBytes)
+        if (snapshotBytes.isEmpty()) {
+            throw IOException("not an asciicast v2 recording")
+        }
+
+        val frame = decodeSnapshot(snapshotBytes)
+        cols = frame.cols
+        rows = frame.rows
+        return frame
+    }
+
     override fun pollDiff(): TerminalDiff? {
-        val diffBytes = AvtNative.vtPollDiff(handle)
+        val diffBytes = AvtNative.vtPollDiffInterned(handle)
 
         return if (diffBytes.isEmpty()) {
             null
@@ -96,6 +242,461 @@ class AvtVirtualTerminal(
         }
     }
 
+    /**
+     * Like [pollDiff], without the content: [TerminalDiff.damage] has the
+     * columns that changed in each dirty row, to be read from [snapshot].
+     * Don't mix with [pollDiff], which would then resend whole rows.
+     */
+    fun pollDamage(): TerminalDiff? {
+        val diffBytes = AvtNative.vtPollDiffDamage(handle)
+        return if (diffBytes.isEmpty()) null else decodeDiff(diffBytes)
+    }
+
+    /**
+     * Force a cursor to show during playback, for recordings that hide it
+     * permanently.
+     *
+     * @param policy One of AvtNative's CURSOR_ constants
+     */
+    fun setCursorPolicy(policy: Int) {
+        require(AvtNative.vtSetCursorPolicy(handle, policy)) { "unknown cursor policy $policy" }
+    }
+
+    /** Queue the recording's input as [AvtEvent.Input] during playback, see [AvtNative.vtSetInputEvents]. */
+    fun setInputEvents(on: Boolean) {
+        AvtNative.vtSetInputEvents(handle, on)
+    }
+
+    /** Resolve indexed colors with theme [name], or stop for null, see [AvtNative.vtSetTheme]. */
+    fun setTheme(name: String?) {
+        require(AvtNative.vtSetTheme(handle, name)) { "unknown theme $name" }
+    }
+
+    /** Show bold text in colors 0-7 as 8-15, see [AvtNative.vtSetBoldAsBright]. */
+    fun setBoldAsBright(on: Boolean) {
+        AvtNative.vtSetBoldAsBright(handle, on)
+    }
+
+    /**
+     * Cap [pollDiff] at [maxDiffsPerSecond] diffs per second (0 = no cap).
+     * Polls inside the interval return null and changes carry over.
+     */
+    fun setUpdateBudget(maxDiffsPerSecond: Int) {
+        require(maxDiffsPerSecond >= 0) { "maxDiffsPerSecond must not be negative" }
+        AvtNative.vtSetUpdateBudget(handle, maxDiffsPerSecond)
+    }
+
+    /**
+     * Arm idle detection: [pollIdle] reports idle once output has been static
+     * for [idleMillis] (0 = disabled).
+     */
+    fun setIdleTimeout(idleMillis: Int) {
+        require(idleMillis >= 0) { "idleMillis must not be negative" }
+        AvtNative.vtSetIdleTimeout(handle, idleMillis)
+    }
+
+    /**
+     * True once each time the terminal goes idle, so the caller can drop to
+     * a low-power polling rate until the next feed.
+     */
+    fun pollIdle(): Boolean = AvtNative.vtPollIdle(handle)
+
+    /**
+     * Give back memory a burst of output (a `cat` of a large file) left
+     * allocated, returning the bytes freed. Worth calling when a long-lived
+     * player goes to the background; quiet terminals also compact on their own.
+     */
+    fun compact(): Long = AvtNative.vtCompact(handle)
+
+    /**
+     * Bytes fed in each of the last 60 seconds, oldest first, for a
+     * throughput sparkline.
+     */
+    fun trafficHistory(): LongArray {
+        val values = AvtNative.vtTrafficHistory(handle)
+        return LongArray(values.size / 2) { values[it * 2] }
+    }
+
+    /** Bytes per second fed recently. */
+    fun byteRate(): Double = AvtNative.vtTrafficStats(handle).getOrElse(0) { 0.0 }
+
+    /** Feeds per second recently, roughly the stream's event rate. */
+    fun eventRate(): Double = AvtNative.vtTrafficStats(handle).getOrElse(1) { 0.0 }
+
+    /**
+     * Milliseconds since the last feed, or null before the first. A live
+     * stream quiet for much longer than usual has likely stalled.
+     */
+    fun millisSinceOutput(): Long? =
+        AvtNative.vtTrafficStats(handle).getOrNull(2)?.takeIf { it >= 0 }?.toLong()
+
+    /**
+     * Bytes fed that had no effect (NUL padding, XON/XOFF and the like).
+     * A large share points at a noisy source rather than a rendering bug.
+     */
+    fun ignoredBytes(): Long = AvtNative.vtIgnoredBytes(handle)
+
+    /**
+     * Soak this terminal with [bytes] of seeded noise (see
+     * [AvtNative.vtStress]), for stability checks and debug screens.
+     * @return Sustained throughput in bytes per second
+     */
+    fun stress(seed: Long, bytes: Long): Double {
+        val nanos = AvtNative.vtStress(handle, seed, bytes)
+        require(nanos >= 0) { "invalid handle" }
+        return if (nanos == 0L) 0.0 else bytes * 1e9 / nanos
+    }
+
+    /** Events (bells, title changes and the like) since the last call. */
+    fun takeEvents(): List<AvtEvent> = AvtEvent.decode(AvtNative.vtTakeEvents(handle))
+
+    /** [takeEvents] with the time each event was queued. */
+    fun pollEvents(): List<TimedAvtEvent> = AvtEvent.decodeTimed(AvtNative.vtPollEvents(handle))
+
+    /**
+     * Report text matching [pattern] as an [AvtEvent.Watch] tagged [tag]
+     * (see [AvtNative.vtWatch]).
+     * @throws IllegalArgumentException for a negative tag, an unsupported
+     *   pattern or too many watches
+     */
+    fun watch(pattern: String, tag: Int) {
+        require(AvtNative.vtWatch(handle, pattern, tag)) { "cannot watch \"$pattern\" as $tag" }
+    }
+
+    /** Stop the watch tagged [tag]; returns whether there was one. */
+    fun unwatch(tag: Int): Boolean = AvtNative.vtUnwatch(handle, tag)
+
+    /**
+     * Report the session going idle after [timeoutMillis] without output
+     * or [noteInput], and waking, as [AvtEvent.Activity]; 0 turns it off.
+     */
+    fun setActivityTimeout(timeoutMillis: Long) {
+        require(timeoutMillis >= 0) { "timeoutMillis must not be negative" }
+        AvtNative.vtSetActivityTimeout(handle, timeoutMillis)
+    }
+
+    /** Count input sent to the session as activity. */
+    fun noteInput() = AvtNative.vtNoteInput(handle)
+
+    /** Report the session ended, or reconnected, as an [AvtEvent.Activity]. */
+    fun setDisconnected(disconnected: Boolean) = AvtNative.vtSetDisconnected(handle, disconnected)
+
+    /** Milliseconds until the session would go idle, null if it can't now. */
+    fun activityDeadlineMillis(): Long? = AvtNative.vtActivityDeadline(handle).takeIf { it >= 0 }
+
+    /**
+     * A reader of this terminal's screen for a render thread, see
+     * [AvtNative.vtOpenReader]; close it when done.
+     */
+    fun openSnapshotReader(): AvtSnapshotReader {
+        val reader = AvtNative.vtOpenReader(handle)
+        check(reader != 0L) { "too many snapshot readers" }
+        return AvtSnapshotReader(reader) { decodeSnapshot(it) }
+    }
+
+    /** URI of the hyperlink at [row], [col] of the screen, if any. */
+    fun linkAt(row: Int, col: Int): String? = AvtNative.vtLinkAt(handle, row, col)
+
+    /**
+     * Pixels of image [id] from [TerminalFrame.images], or null if it's no
+     * longer shown, its format isn't decoded or it was evicted (draw a
+     * placeholder in its cells then). Needs the native `images` feature.
+     */
+    fun image(id: Int): AvtImage? = readImage(AvtNative.vtImage(handle, id), premultiplied = false)
+
+    /**
+     * [image] downscaled in native code to its cells at [cellWidth] ×
+     * [cellHeight] pixels and premultiplied, so it copies straight into an
+     * `ARGB_8888` [android.graphics.Bitmap] with `copyPixelsFromBuffer`.
+     */
+    fun scaledImage(id: Int, cellWidth: Int, cellHeight: Int): AvtImage? =
+        readImage(AvtNative.vtImageScaled(handle, id, cellWidth, cellHeight), premultiplied = true)
+
+    private fun readImage(bytes: ByteArray, premultiplied: Boolean): AvtImage? {
+        val buffer = ByteBuffer.wrap(bytes)
+        if (!buffer.hasRemaining()) return null
+        val width = buffer.readVarint()
+        val height = buffer.readVarint()
+        if (width == 0) return null
+        val rgba = ByteArray(width * height * 4).also { buffer.get(it) }
+        return AvtImage(width, height, rgba, premultiplied)
+    }
+
+    /** Distinct styles on the visible screen, in order of first appearance. */
+    fun styleTable(): List<CellStyle> {
+        val buffer = ByteBuffer.wrap(AvtNative.vtStyleTable(handle))
+        require(buffer.hasRemaining()) { "invalid handle" }
+        return List(buffer.readVarint()) { buffer.readTableStyle() }
+    }
+
+    /**
+     * Attach a push stream for a hardware console, e.g. a USB-serial
+     * reader. Close the stream before this terminal.
+     *
+     * @param newline One of AvtNative's NEWLINE_ constants
+     */
+    fun attachStream(
+        newline: Int = AvtNative.NEWLINE_AS_IS,
+        flowControl: AvtSerialStream.FlowControl? = null
+    ): AvtSerialStream = AvtSerialStream(handle, newline, flowControl)
+
+    /**
+     * Have the next [pollDiff] resend [rows], or every row, instead of
+     * taking a [snapshot] to redraw what was covered.
+     */
+    fun invalidate(rows: IntRange? = null) {
+        if (rows == null) {
+            AvtNative.vtInvalidate(handle, 0, -1)
+        } else if (!rows.isEmpty()) {
+            AvtNative.vtInvalidate(handle, rows.first, rows.last - rows.first + 1)
+        }
+    }
+
+    /** Hash of the frame [snapshot] would return, see [AvtNative.vtFrameHash]. */
+    fun frameHash(): Long = AvtNative.vtFrameHash(handle)
+
+    /**
+     * Shift and row fades for an ambient frame at [nowMillis], see
+     * [AvtNative.vtBurnIn]. Call once per frame, with a time that only
+     * moves forward.
+     */
+    fun burnIn(protection: AvtBurnInProtection, nowMillis: Long): AvtBurnInFrame =
+        requireNotNull(AvtBurnInFrame.of(AvtNative.vtBurnIn(handle, nowMillis, protection.toInts()))) {
+            "invalid handle or time"
+        }
+
+    /** Name this terminal in [AvtLiveHandle] listings, e.g. after its session. */
+    fun setLabel(label: String) {
+        AvtNative.vtSetHandleLabel(AvtNative.HANDLE_VT, handle, label)
+    }
+
+    /**
+     * Journal this terminal's output to [file], a ring of [capacity] bytes,
+     * for [recoverSession] after a crash. Feed through the journal from
+     * then on, and close it before this terminal. Takes over [file]'s
+     * descriptor.
+     */
+    fun startJournal(file: ParcelFileDescriptor, capacity: Long = 1L shl 20): AvtSessionJournal =
+        AvtSessionJournal(handle, file.detachFd(), capacity)
+
+    /**
+     * Rebuild this terminal from the journal in [file], written by
+     * [startJournal] in a process that didn't exit cleanly. The caller
+     * closes [file].
+     * @return The recovered frame, or null if [file] isn't a journal
+     */
+    fun recoverSession(file: ParcelFileDescriptor): TerminalFrame? {
+        if (!AvtNative.sessionRecover(file.fd, handle)) {
+            return null
+        }
+
+        val frame = snapshot()
+        cols = frame.cols
+        rows = frame.rows
+        return frame
+    }
+
+    /**
+     * This terminal whole, scrollback and options included, for
+     * [restoreSerialized] in a later process.
+     * @return Serialized terminal, or null mid escape sequence
+     */
+    fun serialize(): ByteArray? = AvtNative.vtSerialize(handle).takeIf { it.isNotEmpty() }
+
+    /**
+     * Replace this terminal with one [serialize] saved, size and mode
+     * included. Readers, journals and streams of the old one stop
+     * following it; open them again.
+     * @return The restored frame, or null (terminal unchanged) if [bytes]
+     *   aren't a serialized terminal this build can restore
+     */
+    fun restoreSerialized(bytes: ByteArray): TerminalFrame? {
+        val restored = AvtNative.vtDeserialize(bytes)
+        if (restored == 0L) {
+            return null
+        }
+
+        AvtNative.vtFree(handle)
+        handle = restored
+        styleEpoch = null
+        val frame = snapshot()
+        cols = frame.cols
+        rows = frame.rows
+        return frame
+    }
+
+    /**
+     * Keep at most [maxLines] lines and about [maxBytes] bytes of scrollback
+     * (null = no limit). Drops the scrollback kept so far, so call it before
+     * feeding.
+     */
+    fun setScrollbackRetention(maxLines: Int? = null, maxBytes: Long? = null) {
+        require((maxLines ?: 0) >= 0 && (maxBytes ?: 0) >= 0) { "limits must not be negative" }
+        AvtNative.vtSetScrollbackRetention(handle, maxLines ?: -1, maxBytes ?: -1)
+    }
+
+    /**
+     * Keep at most [maxScrollbackLines] lines of scrollback, [maxImageBytes]
+     * bytes of images and about [maxTotalBytes] bytes in all (null = no
+     * limit), evicting the oldest first. Replaces [setScrollbackRetention]'s
+     * limits and drops the scrollback kept so far.
+     */
+    fun setLimits(maxScrollbackLines: Int? = null, maxImageBytes: Long? = null, maxTotalBytes: Long? = null) {
+        require((maxScrollbackLines ?: 0) >= 0 && (maxImageBytes ?: 0) >= 0 && (maxTotalBytes ?: 0) >= 0) {
+            "limits must not be negative"
+        }
+        AvtNative.vtSetLimits(handle, maxScrollbackLines ?: -1, maxImageBytes ?: -1, maxTotalBytes ?: -1)
+    }
+
+    /** What this VT holds now, for diagnostics. */
+    fun memoryUsage(): AvtMemoryUsage = AvtMemoryUsage.of(AvtNative.vtMemoryUsage(handle))
+
+    /**
+     * How the program wants its input; read again after a diff with
+     * [TerminalDiff.modesChanged].
+     */
+    fun modes(): AvtModes = AvtModes(AvtNative.vtModes(handle))
+
+    /** Native parse and encode counters so far; subtract two for a window. */
+    fun stats(): AvtVtStats = AvtVtStats.of(AvtNative.vtStats(handle))
+
+    /** Lines scrolled off the top and still kept, for sizing a scroll bar. */
+    fun scrollbackSize(): Int = AvtNative.vtScrollbackLen(handle)
+
+    /**
+     * Up to [count] scrollback lines from [start], 0 being the oldest.
+     * Indices shift as output scrolls more lines off and the oldest are
+     * dropped, so re-anchor on [ScrollbackPage.total].
+     */
+    fun scrollbackLines(start: Int, count: Int): ScrollbackPage {
+        require(start >= 0 && count >= 0) { "start and count must not be negative" }
+        val buffer = ByteBuffer.wrap(AvtNative.vtScrollbackLines(handle, start, count))
+        if (!buffer.hasRemaining()) {
+            return ScrollbackPage(total = 0, start = 0, lines = emptyList())
+        }
+
+        val total = buffer.readVarint()
+        val pageStart = buffer.readVarint()
+        val lineCount = buffer.readVarint()
+        val lines = List(lineCount) { decodeLine(buffer) }
+        return ScrollbackPage(total = total, start = pageStart, lines = lines)
+    }
+
+    /**
+     * Save the whole history (scrollback, then the screen) to [file], e.g.
+     * a document from `openFileDescriptor(uri, "w")`. The caller closes it.
+     * Needs the native `exporters` feature.
+     *
+     * @param format One of AvtNative's EXPORT_ constants
+     * @return Bytes written
+     */
+    fun exportScrollback(file: ParcelFileDescriptor, format: Int = AvtNative.EXPORT_TEXT): Long {
+        require(format in AvtNative.EXPORT_TEXT..AvtNative.EXPORT_HTML) { "unknown format $format" }
+        val written = AvtNative.vtExportScrollback(handle, file.fd, format)
+        if (written < 0) {
+            throw IOException("transcript export failed")
+        }
+        return written
+    }
+
+    /**
+     * The screen as an SVG document, for sharing a moment without
+     * rasterizing it. Needs the native `exporters` feature.
+     */
+    fun exportSvg(theme: Theme = currentTheme): String =
+        AvtNative.vtExportSvg(handle, AvtStyles.paletteOf(theme))
+
+    /**
+     * The screen, after the scrollback if [withScrollback], as a styled
+     * HTML page for the clipboard or a share sheet. Needs the native
+     * `exporters` feature.
+     */
+    fun exportHtml(theme: Theme = currentTheme, withScrollback: Boolean = false): String =
+        AvtNative.vtExportHtml(handle, AvtStyles.paletteOf(theme), withScrollback)
+
+    /** [exportHtml] as text with ANSI color and style sequences. */
+    fun exportAnsi(withScrollback: Boolean = false): String =
+        AvtNative.vtExportAnsi(handle, withScrollback)
+
+    /**
+     * Draw the screen into [buffer] for `Bitmap.copyPixelsFromBuffer` on a
+     * [width] x [height] `ARGB_8888` bitmap, e.g. a screenshot to share.
+     * The font is bundled, so the pixels don't depend on the device; colors
+     * are those of [themeId], registered or built in, and bold is bright as
+     * [setBoldAsBright] set. [buffer] must be direct and hold
+     * `4 * width * height` bytes. Needs the native `renderer` feature.
+     */
+    fun renderBitmap(buffer: ByteBuffer, width: Int, height: Int, themeId: String = "asciinema") {
+        val drawn = AvtNative.vtRenderBitmap(handle, buffer, width, height, themeId)
+        require(drawn) { "invalid handle, unknown theme $themeId, or buffer not direct or too small" }
+    }
+
+    /**
+     * [renderBitmap] for an always-on display at [nowMillis], shifted and
+     * dimmed as [burnIn] with [protection] says. Needs the native
+     * `renderer` feature.
+     */
+    fun renderBitmapProtected(
+        buffer: ByteBuffer,
+        width: Int,
+        height: Int,
+        nowMillis: Long,
+        protection: AvtBurnInProtection = AvtBurnInProtection.AMBIENT,
+        themeId: String = "asciinema"
+    ) {
+        val drawn = AvtNative.vtRenderBitmapProtected(
+            handle, buffer, width, height, themeId, nowMillis, protection.toInts()
+        )
+        require(drawn) { "invalid handle, time or theme $themeId, or buffer not direct or too small" }
+    }
+
+    /**
+     * Text of a selection for the clipboard, rows counted through the
+     * scrollback and then the screen (see [AvtNative.vtExtractText]).
+     *
+     * @param mode AvtNative.SELECT_LINEAR or AvtNative.SELECT_BLOCK
+     */
+    fun extractText(
+        startRow: Int,
+        startCol: Int,
+        endRow: Int,
+        endCol: Int,
+        mode: Int = AvtNative.SELECT_LINEAR
+    ): String {
+        val bytes = AvtNative.vtExtractText(handle, startRow, startCol, endRow, endCol, mode)
+        return String(bytes, Charsets.UTF_8)
+    }
+
+    /**
+     * Where [query] matches in the scrollback and the screen, in reading
+     * order, rows counted as for [extractText] (see [AvtNative.vtSearch]).
+     *
+     * @param flags Bitwise OR of AvtNative's SEARCH_ constants
+     * @throws IllegalArgumentException for unknown flags or an unsupported
+     *   pattern
+     */
+    fun search(query: String, flags: Int = 0): List<SearchMatch> {
+        val buffer = ByteBuffer.wrap(AvtNative.vtSearch(handle, query, flags))
+        require(buffer.hasRemaining()) { "cannot search for \"$query\" with flags $flags" }
+        return List(buffer.readVarint()) {
+            val row = buffer.readVarint()
+            val colStart = buffer.readVarint()
+            SearchMatch(row, colStart until buffer.readVarint())
+        }
+    }
+
+    /** A match of [search]: [columns] of history row [row]. */
+    data class SearchMatch(val row: Int, val columns: IntRange)
+
+    /** A page of [scrollbackLines]. */
+    data class ScrollbackPage(
+        /** Scrollback lines kept when the page was taken */
+        val total: Int,
+        /** Index of the first of [lines] */
+        val start: Int,
+        val lines: List<TerminalLine>
+    )
+
     override fun close() {
         if (handle != 0L) {
             AvtNative.vtFree(handle)
@@ -106,48 +707,135 @@ class AvtVirtualTerminal(
     /**
      * Decode binary snapshot format.
      *
-     * Matches the encoding in lib.rs encode_snapshot()
+     * Must behave like the reference decoder in snapshot.rs decode(), or
+     * for [interned] styles.rs decode_screen()
      */
-    private fun decodeSnapshot(bytes: ByteArray): TerminalFrame {
+    private fun decodeSnapshot(bytes: ByteArray, interned: Boolean = false): TerminalFrame {
         val buffer = ByteBuffer.wrap(bytes)
+        if (interned) {
+            readStyleUpdate(buffer)
+        } else {
+            skipWireHeader(buffer, Protocol.SNAPSHOT_HEADER)
+        }
 
         // Read size
         val cols = buffer.readVarint()
         val rows = buffer.readVarint()
 
         // Read cursor
-        val cursorCol = buffer.readVarint()
-        val cursorRow = buffer.readVarint()
-        val cursorVisible = buffer.get() == 1.toByte()
+        val (cursor, altScreenActive) = readCursor(buffer)
 
         // Read lines
         val lines = mutableListOf<TerminalLine>()
         for (lineIdx in 0 until rows) {
-            lines.add(decodeLine(buffer))
+            lines.add(decodeLine(buffer, interned))
+        }
+
+        // URIs of the links the lines use
+        val links = HashMap<Int, String>()
+        repeat(buffer.readVarint()) {
+            val id = buffer.readVarint()
+            val uriBytes = ByteArray(buffer.readVarint())
+            buffer.get(uriBytes)
+            links[id] = String(uriBytes, Charsets.UTF_8)
+        }
+
+        // Images shown, written only when there are any
+        val images = if (buffer.hasRemaining()) {
+            List(buffer.readVarint()) { readImagePlacement(buffer) }
+        } else {
+            emptyList()
         }
 
         return TerminalFrame(
             cols = cols,
             rows = rows,
             lines = lines,
-            cursor = Cursor(
-                row = cursorRow,
-                col = cursorCol,
-                visible = cursorVisible
-            ),
+            cursor = cursor,
             theme = currentTheme,
-            title = null
+            title = null,
+            links = links,
+            images = images,
+            altScreenActive = altScreenActive
         )
     }
 
-    private fun decodeLine(buffer: ByteBuffer): TerminalLine {
+    /**
+     * Decode the interned region format into a full frame, blank outside
+     * the region.
+     *
+     * Must behave like the reference decoder in styles.rs decode_region()
+     */
+    private fun decodeRegion(bytes: ByteArray): TerminalFrame {
+        val buffer = ByteBuffer.wrap(bytes)
+        readStyleUpdate(buffer)
+        val cols = buffer.readVarint()
+        val rows = buffer.readVarint()
+        val (cursor, altScreenActive) = readCursor(buffer)
+
+        val firstRow = buffer.readVarint()
+        val lines = MutableList(rows) { TerminalLine.EMPTY }
+        repeat(buffer.readVarint()) { i ->
+            val line = decodeLine(buffer, interned = true)
+            if (firstRow + i < rows) lines[firstRow + i] = line
+        }
+
+        val links = HashMap<Int, String>()
+        repeat(buffer.readVarint()) {
+            val id = buffer.readVarint()
+            val uriBytes = ByteArray(buffer.readVarint())
+            buffer.get(uriBytes)
+            links[id] = String(uriBytes, Charsets.UTF_8)
+        }
+
+        return TerminalFrame(
+            cols = cols,
+            rows = rows,
+            lines = lines,
+            cursor = cursor,
+            theme = currentTheme,
+            title = null,
+            links = links,
+            altScreenActive = altScreenActive
+        )
+    }
+
+    /**
+     * Cursor column, row and flags: bit 0 visible, bits 1-2 the shape
+     * (block, underline, bar), bit 3 steady. Bit 4, returned second, is
+     * set while the alternate screen is shown.
+     */
+    private fun readCursor(buffer: ByteBuffer): Pair<Cursor, Boolean> {
+        val col = buffer.readVarint()
+        val row = buffer.readVarint()
+        val flags = Snapshot.CursorFlags(buffer.get().toInt())
+        val cursor = Cursor(
+            row = row,
+            col = col,
+            visible = flags.visible,
+            shape = CursorShape.entries.getOrElse(flags.shape) { CursorShape.BLOCK },
+            blink = !flags.steady
+        )
+        return cursor to flags.altScreen
+    }
+
+    /** @param interned Styles are ids into [styleCache] */
+    private fun decodeLine(buffer: ByteBuffer, interned: Boolean = false): TerminalLine {
+        val attribute = LineAttribute.entries.getOrElse(buffer.get().toInt()) { LineAttribute.SINGLE }
         val runCount = buffer.readVarint()
         val runs = mutableListOf<TextRun>()
 
         for (i in 0 until runCount) {
             val colStart = buffer.readVarint()
             val textLen = buffer.readVarint()
-            val style = decodeCellStyle(buffer)
+            // Columns covered, and whether a link id follows
+            val extent = buffer.readVarint()
+            val linkId = if (extent and Snapshot.EXTENT_LINK != 0) buffer.readVarint() else 0
+            val style = if (interned) {
+                styleCache.getOrElse(buffer.readVarint()) { CellStyle.DEFAULT }
+            } else {
+                decodeCellStyle(buffer)
+            }
 
             val textBytes = ByteArray(textLen)
             buffer.get(textBytes)
@@ -156,67 +844,289 @@ class AvtVirtualTerminal(
             runs.add(TextRun(
                 colStart = colStart,
                 text = text,
-                style = style
+                style = style,
+                cells = extent shr 1,
+                linkId = linkId
             ))
         }
 
-        return TerminalLine(runs = runs)
+        return TerminalLine(runs = runs, attribute = attribute)
     }
 
     private fun decodeCellStyle(buffer: ByteBuffer): CellStyle {
         // Decode foreground
         val fgType = buffer.get().toInt()
         val foreground = when (fgType) {
-            0 -> Color.Indexed(buffer.get().toInt() and 0xFF)
-            1 -> Color.Rgb(
+            Snapshot.COLOR_INDEXED -> Color.Indexed(buffer.get().toInt() and 0xFF)
+            Snapshot.COLOR_RGB -> Color.Rgb(
                 r = buffer.get().toInt() and 0xFF,
                 g = buffer.get().toInt() and 0xFF,
                 b = buffer.get().toInt() and 0xFF
             )
-            else -> null // 2 = None
+            else -> null // Snapshot.COLOR_DEFAULT
         }
 
         // Decode background
         val bgType = buffer.get().toInt()
         val background = when (bgType) {
-            0 -> Color.Indexed(buffer.get().toInt() and 0xFF)
-            1 -> Color.Rgb(
+            Snapshot.COLOR_INDEXED -> Color.Indexed(buffer.get().toInt() and 0xFF)
+            Snapshot.COLOR_RGB -> Color.Rgb(
                 r = buffer.get().toInt() and 0xFF,
                 g = buffer.get().toInt() and 0xFF,
                 b = buffer.get().toInt() and 0xFF
             )
-            else -> null // 2 = None
+            else -> null // Snapshot.COLOR_DEFAULT
         }
 
         // Decode attributes
         val attrs = buffer.get().toInt() and 0xFF
 
+        return cellStyle(foreground ?: Color.Default, background ?: Color.Default, attrs)
+    }
+
+    /**
+     * Apply a style table update to [styleCache], starting over for a new
+     * epoch.
+     * @throws IllegalStateException if an earlier update was missed
+     */
+    private fun readStyleUpdate(buffer: ByteBuffer) {
+        val epoch = buffer.readVarint()
+        val firstId = buffer.readVarint()
+        val count = buffer.readVarint()
+        if (epoch != styleEpoch) {
+            styleEpoch = epoch
+            styleCache.clear()
+        }
+        check(firstId == styleCache.size) { "missed a style update" }
+        repeat(count) {
+            styleCache.add(buffer.readTableStyle())
+        }
+    }
+
+    private fun ByteBuffer.readTableStyle(): CellStyle {
+        val foreground = readTableColor()
+        val background = readTableColor()
+        return cellStyle(foreground, background, get().toInt() and 0xFF)
+    }
+
+    /** Style-table color: default, indexed (below 16 and from 16) or RGB */
+    private fun ByteBuffer.readTableColor(): Color = when (get().toInt()) {
+        Styles.COLOR_ANSI, Styles.COLOR_INDEXED -> Color.Indexed(get().toInt() and 0xFF)
+        Styles.COLOR_RGB -> Color.Rgb(
+            r = get().toInt() and 0xFF,
+            g = get().toInt() and 0xFF,
+            b = get().toInt() and 0xFF
+        )
+        else -> Color.Default
+    }
+
+    private fun cellStyle(foreground: Color, background: Color, attrs: Int): CellStyle {
+        val flags = Snapshot.Attrs(attrs)
         return CellStyle(
-            foreground = foreground ?: Color.Default,
-            background = background ?: Color.Default,
-            bold = (attrs and 0x01) != 0,
-            italic = (attrs and 0x02) != 0,
-            underline = (attrs and 0x04) != 0,
-            strikethrough = (attrs and 0x08) != 0,
-            blink = (attrs and 0x10) != 0,
-            reverse = (attrs and 0x20) != 0
+            foreground = foreground,
+            background = background,
+            bold = flags.bold,
+            italic = flags.italic,
+            underline = flags.underline,
+            strikethrough = flags.strikethrough,
+            blink = flags.blink,
+            reverse = flags.inverse,
+            faint = flags.faint
+        )
+    }
+
+    /**
+     * Decode the rest of a damage diff ([Diff.KIND_DAMAGE]) after its trace
+     * IDs: the changed cells as rectangles one row high, grouped by row.
+     */
+    private fun decodeDamageDiff(buffer: ByteBuffer): TerminalDiff {
+        val rectCount = buffer.readVarint()
+        val damage = LinkedHashMap<Int, MutableList<IntRange>>()
+        repeat(rectCount) {
+            val row = buffer.readVarint()
+            val colStart = buffer.readVarint()
+            damage.getOrPut(row) { ArrayList(1) }.add(colStart until buffer.readVarint())
+        }
+
+        val cursorChange = Diff.CursorChange(buffer.get().toInt())
+        val screenChange = Diff.ScreenChange(buffer.get().toInt())
+        return TerminalDiff(
+            dirtyLines = damage.keys,
+            cursorChanged = cursorChange.moved,
+            cursorStyleChanged = cursorChange.restyled,
+            resized = screenChange.resized,
+            altScreenChanged = screenChange.screenSwitched,
+            modesChanged = screenChange.modesChanged,
+            damage = damage,
+            imageChanges = readImageChanges(buffer, screenChange)
         )
     }
 
+    /** An `image` of the snapshot's image table; see snapshot.rs. */
+    private fun readImagePlacement(buffer: ByteBuffer) = ImagePlacement(
+        imageId = buffer.readVarint(),
+        col = buffer.readVarint(),
+        row = buffer.readVarint(),
+        rows = buffer.readVarint(),
+        cols = buffer.readVarint(),
+        imageRow = buffer.readVarint(),
+        imageRows = buffer.readVarint()
+    )
+
+    /**
+     * The image changes ending a diff whose `resized` byte has
+     * [Diff.IMAGES_CHANGED], or null if it hasn't; ops this decoder doesn't
+     * know are skipped, as in diff.rs decode_images().
+     */
+    private fun readImageChanges(
+        buffer: ByteBuffer,
+        screenChange: Diff.ScreenChange
+    ): List<ImageChange>? {
+        if (!screenChange.imagesChanged) return null
+        val changes = ArrayList<ImageChange>()
+        repeat(buffer.readVarint()) {
+            val op = buffer.get().toInt() and 0xFF
+            val placement = readImagePlacement(buffer)
+            val kind = when (op) {
+                Diff.IMAGE_ADDED -> ImageChange.Kind.ADDED
+                Diff.IMAGE_MOVED -> ImageChange.Kind.MOVED
+                Diff.IMAGE_REMOVED -> ImageChange.Kind.REMOVED
+                else -> null
+            }
+            kind?.let { changes.add(ImageChange(it, placement)) }
+        }
+        return changes
+    }
+
+    /**
+     * Skip the header [AvtNative.vtSetWireFormat] puts before snapshots
+     * ([Protocol.SNAPSHOT_HEADER]) and diffs ([Diff.KIND_VERSIONED]), if
+     * [buffer] starts with [tag]; see protocol.rs.
+     */
+    private fun skipWireHeader(buffer: ByteBuffer, tag: Int) {
+        if (buffer.hasRemaining() && buffer.get(buffer.position()).toInt() == tag) {
+            buffer.get()
+            buffer.get() // Version
+            buffer.readVarint() // Features
+        }
+    }
+
     /**
      * Decode binary diff format.
      *
-     * TODO: Implement this to match the encoding in lib.rs
+     * Must behave like the reference decoder in diff.rs decode_interned();
+     * a truncated diff, or one after a missed style update, falls back to a
+     * full redraw.
      */
     private fun decodeDiff(bytes: ByteArray): TerminalDiff {
-        // TODO: Decode the binary format:
-        // - Read hasDiff flag
-        // - Read dirty line indices
-        // - Read cursor changed flag
-        // - Read resized flag
-        //
-        // For now, return full redraw
-        return TerminalDiff.FULL
+        val buffer = ByteBuffer.wrap(bytes)
+
+        return try {
+            skipWireHeader(buffer, Diff.KIND_VERSIONED)
+            val kind = buffer.get().toInt()
+            when (kind) {
+                Diff.KIND_NONE -> return TerminalDiff.NONE
+                Diff.KIND_CURSOR -> return TerminalDiff(cursorChanged = true)
+                Diff.KIND_TRACED -> {
+                    // Trace IDs (vtFeedTraced); not used here
+                    val traceCount = buffer.readVarint()
+                    buffer.position(buffer.position() + traceCount * 8)
+                }
+                Diff.KIND_DAMAGE -> {
+                    val traceCount = buffer.readVarint()
+                    buffer.position(buffer.position() + traceCount * 8)
+                    return decodeDamageDiff(buffer)
+                }
+                Diff.KIND_CONTENT, Diff.KIND_SPANS, Diff.KIND_INTERNED -> {
+                    val traceCount = buffer.readVarint()
+                    buffer.position(buffer.position() + traceCount * 8)
+                    if (kind == Diff.KIND_INTERNED) {
+                        readStyleUpdate(buffer)
+                    }
+                    return decodeContentDiff(
+                        buffer,
+                        withSpans = kind != Diff.KIND_CONTENT,
+                        interned = kind == Diff.KIND_INTERNED
+                    )
+                }
+            }
+
+            val lineCount = buffer.readVarint()
+            val dirtyLines = HashSet<Int>(minOf(lineCount, bytes.size))
+            repeat(lineCount) {
+                dirtyLines.add(buffer.readVarint())
+            }
+
+            val cursorChange = Diff.CursorChange(buffer.get().toInt())
+            val screenChange = Diff.ScreenChange(buffer.get().toInt())
+            TerminalDiff(
+                dirtyLines = dirtyLines,
+                cursorChanged = cursorChange.moved,
+                cursorStyleChanged = cursorChange.restyled,
+                resized = screenChange.resized,
+                altScreenChanged = screenChange.screenSwitched,
+                modesChanged = screenChange.modesChanged,
+                imageChanges = readImageChanges(buffer, screenChange)
+            )
+        } catch (e: RuntimeException) {
+            android.util.Log.e("AvtVT", "Error decoding diff", e)
+            TerminalDiff.FULL
+        }
+    }
+
+    private fun decodeContentDiff(
+        buffer: ByteBuffer,
+        withSpans: Boolean,
+        interned: Boolean
+    ): TerminalDiff {
+        val cols = buffer.readVarint()
+        val rows = buffer.readVarint()
+        val (cursor, altScreenActive) = readCursor(buffer)
+        val cursorChange = Diff.CursorChange(buffer.get().toInt())
+        val screenChange = Diff.ScreenChange(buffer.get().toInt())
+
+        val lineCount = buffer.readVarint()
+        val lines = HashMap<Int, TerminalLine>(minOf(lineCount, buffer.remaining()))
+        val spans = HashMap<Int, IntRange>()
+        repeat(lineCount) {
+            val row = buffer.readVarint()
+            if (withSpans) {
+                val start = buffer.readVarint()
+                spans[row] = start until buffer.readVarint()
+            }
+            lines[row] = decodeLine(buffer, interned)
+        }
+        val imageChanges = readImageChanges(buffer, screenChange)
+
+        return TerminalDiff(
+            dirtyLines = lines.keys,
+            cursorChanged = cursorChange.moved,
+            cursorStyleChanged = cursorChange.restyled,
+            resized = screenChange.resized,
+            altScreenChanged = screenChange.screenSwitched,
+            modesChanged = screenChange.modesChanged,
+            content = DiffContent(
+                cols = cols,
+                rows = rows,
+                cursor = cursor,
+                lines = lines,
+                spans = spans,
+                altScreenActive = altScreenActive
+            ),
+            imageChanges = imageChanges
+        )
+    }
+
+    /**
+     * Helper to write a varint, as the batch format of vtFeedBatch takes.
+     */
+    private fun java.io.ByteArrayOutputStream.writeVarint(value: Long) {
+        var rest = value
+        while (rest >= 0x80) {
+            write(((rest and 0x7F) or 0x80).toInt())
+            rest = rest ushr 7
+        }
+        write(rest.toInt())
     }
 
     /**
@@ -0,0 +1,47 @@
+package uk.adedamola.asciicast.vt.avt
+
+/**
+ * What one VT's native side did and how long it took, cumulative since
+ * the VT was created. Compare [parseNanos], [snapshotNanos] and
+ * [diffNanos] over a janky stretch with the frame time to tell whether it
+ * was the parser, the encoders or the UI.
+ */
+data class AvtVtStats(
+    val bytesFed: Long,
+    val feeds: Long,
+    val parseNanos: Long,
+    val snapshots: Long,
+    val snapshotNanos: Long,
+    /** Diff polls, those with nothing to report included */
+    val diffs: Long,
+    val diffNanos: Long,
+    /** Most lines one diff reported dirty */
+    val maxDirtyLines: Long
+) {
+    /** The counts since [earlier]; [maxDirtyLines] stays the overall one. */
+    operator fun minus(earlier: AvtVtStats) = AvtVtStats(
+        bytesFed - earlier.bytesFed,
+        feeds - earlier.feeds,
+        parseNanos - earlier.parseNanos,
+        snapshots - earlier.snapshots,
+        snapshotNanos - earlier.snapshotNanos,
+        diffs - earlier.diffs,
+        diffNanos - earlier.diffNanos,
+        maxDirtyLines
+    )
+
+    /** One line per counter, for logs and bug reports. */
+    fun dump(): String = buildString {
+        append("fed: $bytesFed bytes in $feeds feeds, ${parseNanos / 1000} us parsing\n")
+        append("snapshots: $snapshots, ${snapshotNanos / 1000} us\n")
+        append("diffs: $diffs, ${diffNanos / 1000} us, at most $maxDirtyLines dirty lines")
+    }
+
+    companion object {
+        /** From [AvtNative.vtStats]; all zero if [values] is empty. */
+        fun of(values: LongArray): AvtVtStats {
+            val at = { i: Int -> values.getOrElse(i) { 0L } }
+            return AvtVtStats(at(0), at(1), at(2), at(3), at(4), at(5), at(6), at(7))
+        }
+    }
+}
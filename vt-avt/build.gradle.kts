@@ -36,11 +36,28 @@ android {
 
 dependencies {
     api(project(":vt-api"))
+    implementation("androidx.annotation:annotation:1.7.1")
 
     testImplementation("junit:junit:4.13.2")
     androidTestImplementation("androidx.test.ext:junit:1.1.5")
 }
 
+// Regenerate AvtSchema.kt from the Rust format constants (see
+// rust/src/schema.rs); `cargo test` fails while it's out of date
+tasks.register<Exec>("generateAvtSchema") {
+    workingDir = file("rust")
+    environment("BLESS", "1")
+    commandLine("cargo", "test", "schema_tests")
+}
+
+// Regenerate AvtHandles.kt from the JNI functions (see
+// rust/src/binding_tests.rs); `cargo test` fails while it's out of date
+tasks.register<Exec>("generateAvtHandles") {
+    workingDir = file("rust")
+    environment("BLESS", "1")
+    commandLine("cargo", "test", "binding_tests")
+}
+
 // TODO: Add Rust build tasks using cargo-ndk
 // tasks.register<Exec>("buildRustLibs") {
 //     workingDir = file("rust")
@@ -0,0 +1,74 @@
+/*
+ * C ABI for asciicast_vt_avt. Mirrors src/ffi.rs.
+ *
+ * Decode a snapshot (the bytes vtSnapshot returns) into an opaque screen,
+ * read it through the accessors, then release it with avt_screen_free.
+ * Run text pointers stay valid until the screen is freed.
+ */
+#ifndef ASCIICAST_VT_AVT_H
+#define ASCIICAST_VT_AVT_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AvtScreen AvtScreen;
+
+#define AVT_COLOR_DEFAULT 0
+#define AVT_COLOR_INDEXED 1
+#define AVT_COLOR_RGB 2
+
+typedef struct {
+    uint8_t tag;  /* AVT_COLOR_* */
+    uint8_t r;    /* palette index for AVT_COLOR_INDEXED */
+    uint8_t g;
+    uint8_t b;
+} AvtColor;
+
+typedef struct {
+    uint32_t col;
+    uint32_t row;
+    bool visible;
+    uint8_t shape; /* 0 block, 1 underline, 2 bar */
+    bool blink;
+} AvtCursor;
+
+typedef struct {
+    uint32_t col;
+    const uint8_t *text; /* UTF-8, not NUL-terminated */
+    size_t text_len;
+    uint32_t cells; /* columns covered, twice the chars for wide text */
+    AvtColor fg;
+    AvtColor bg;
+    uint8_t attrs; /* bold 0x01, italic 0x02, underline 0x04, strike 0x08, blink 0x10, inverse 0x20 */
+    uint32_t link; /* OSC 8 link id, 0 for none */
+} AvtRun;
+
+/* Returns NULL if data is NULL or malformed. */
+AvtScreen *avt_snapshot_decode(const uint8_t *data, size_t len);
+void avt_screen_free(AvtScreen *screen);
+
+uint32_t avt_screen_cols(const AvtScreen *screen);
+uint32_t avt_screen_rows(const AvtScreen *screen);
+AvtCursor avt_screen_cursor(const AvtScreen *screen);
+/* True while the alternate screen was shown (modes 47, 1047, 1049) */
+bool avt_screen_alt_screen(const AvtScreen *screen);
+
+/* 0 single, 1 double width, 2 double height top, 3 double height bottom */
+uint8_t avt_screen_line_attr(const AvtScreen *screen, uint32_t row);
+uint32_t avt_screen_run_count(const AvtScreen *screen, uint32_t row);
+/* Returns false if row or index is out of range. */
+bool avt_screen_run(const AvtScreen *screen, uint32_t row, uint32_t index, AvtRun *out);
+/* URI of a run's link, not NUL-terminated, length in *len; NULL if the
+ * snapshot doesn't list the id. Valid until avt_screen_free. */
+const uint8_t *avt_screen_link(const AvtScreen *screen, uint32_t link, size_t *len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
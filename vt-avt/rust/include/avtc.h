@@ -0,0 +1,69 @@
+/*
+ * Generated from src/capi.rs; don't edit. Regenerate with
+ * `BLESS=1 cargo test capi_tests`.
+ *
+ * The terminal core for C callers (see src/capi.rs). Create a VT with
+ * avtc_new, feed it output, copy out snapshots and diffs in the
+ * library's binary formats and release it with avtc_free; one thread
+ * at a time per VT. asciicast_vt_avt.h decodes the snapshots.
+ */
+#ifndef AVTC_H
+#define AVTC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AvtcVt AvtcVt;
+
+/* A new VT of `cols` by `rows` cells, with unlimited scrollback. */
+AvtcVt *avtc_new(uint32_t cols, uint32_t rows);
+
+/* Release a VT; null is ignored. */
+void avtc_free(AvtcVt *vt);
+
+/* Feed `len` bytes of output. Returns false if the engine panicked. */
+bool avtc_feed(AvtcVt *vt, const uint8_t *data, size_t len);
+
+/* Resize, rewrapping soft-wrapped lines to the new width. */
+bool avtc_resize(AvtcVt *vt, uint32_t cols, uint32_t rows);
+
+/* Clear everything, scrollback included, at a new size. */
+bool avtc_reset(AvtcVt *vt, uint32_t cols, uint32_t rows);
+
+/* The screen in the snapshot format, copied into `buf` if `cap` bytes
+ * hold it. Returns its length, 0 if the engine panicked. */
+size_t avtc_snapshot_into(AvtcVt *vt, uint8_t *buf, size_t cap);
+
+/* What changed since the last diff, in the diff format, copied into `buf`
+ * if `cap` bytes hold it; kept for the next call if not. Returns its
+ * length, 0 for no change (or a panic). */
+size_t avtc_poll_diff_into(AvtcVt *vt, uint8_t *buf, size_t cap);
+
+/* The screen as ANSI that replays it into a fresh VT of the same size,
+ * copied into `buf` if `cap` bytes hold it. Returns its length. */
+size_t avtc_dump_into(AvtcVt *vt, uint8_t *buf, size_t cap);
+
+/* Lines scrolled off the top and still kept. */
+size_t avtc_scrollback_len(const AvtcVt *vt);
+
+/* Send snapshots and diffs behind a versioned header, leaving out the
+ * features not in `features`, as `vtSetWireFormat` does (see `protocol`).
+ * Returns the features it will send. */
+uint32_t avtc_set_wire_format(AvtcVt *vt, uint32_t version, uint32_t features);
+
+/* The newest snapshot and diff protocol version the library writes. */
+uint32_t avtc_protocol_version(void);
+
+/* Hash of the wire formats' constants, as `vtSchemaHash` returns. */
+int64_t avtc_schema_hash(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,274 @@
+//! Activity state of interactive sessions (PTY, SSH).
+//!
+//! The throttle's idle detection (`vtPollIdle`) is for a renderer slowing
+//! its polls and counts only changes to the screen. This is the session's:
+//! input counts too, the end of the session is a state of its own, and
+//! changes are pushed as events rather than polled.
+//!
+//! A session that goes quiet is either idle, with the user away and the
+//! program waiting, or gone. The app dims the previews of idle sessions
+//! and sends keep-alives before a server drops the connection, so the VT
+//! queues a `VtEvent::Activity` whenever the state changes:
+//!
+//! - `Idle` once neither output nor input (`vtNoteInput`) has happened
+//!   for the timeout (`vtSetActivityTimeout`). There is none by default,
+//!   so playback never goes idle.
+//! - `Active` at the first output or input after that.
+//! - `Disconnected` when the app says the session ended
+//!   (`vtSetDisconnected`), and `Active` again when it reconnects. Output
+//!   still draining after the end doesn't count as activity.
+//!
+//! Nothing runs in the background. The timeout is checked when events are
+//! taken, which the app does every frame anyway, and while nothing is
+//! drawn `vtActivityDeadline` says when to take them next, for one
+//! timer instead of a poll. Feeds of nothing but padding (see `scan::is_ignored`)
+//! aren't activity, so NUL keep-alives from a server don't keep a session
+//! awake.
+
+use crate::events::VtEvent;
+use crate::{handles, Instant, VtHandle};
+use jni::objects::JClass;
+use jni::sys::{jboolean, jlong, JNI_FALSE};
+use jni::JNIEnv;
+use std::time::Duration;
+
+/// As `VtEvent::Activity::state`, by code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Active = 0,
+    Idle = 1,
+    Disconnected = 2,
+}
+
+impl State {
+    pub fn from_code(code: u8) -> Option<State> {
+        match code {
+            0 => Some(State::Active),
+            1 => Some(State::Idle),
+            2 => Some(State::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Activity {
+    timeout: Option<Duration>,
+    /// Last output or input
+    last: Instant,
+    state: State,
+}
+
+impl Activity {
+    pub fn new(now: Instant) -> Self {
+        Activity {
+            timeout: None,
+            last: now,
+            state: State::Active,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Go idle after `timeout` without output or input, or never for
+    /// `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Output or input at `now`.
+    pub fn note(&mut self, now: Instant) -> Option<VtEvent> {
+        let event = (self.state == State::Idle).then(|| self.event(State::Active, now));
+        self.last = self.last.max(now);
+        event
+    }
+
+    /// The event going idle by `now` queues, if the session has.
+    pub fn check(&mut self, now: Instant) -> Option<VtEvent> {
+        let due = self.deadline(now)?.is_zero();
+        due.then(|| self.event(State::Idle, now))
+    }
+
+    /// Time from `now` until the session goes idle, if it's active with a
+    /// timeout.
+    pub fn deadline(&self, now: Instant) -> Option<Duration> {
+        let timeout = self.timeout.filter(|_| self.state == State::Active)?;
+        Some(timeout.saturating_sub(now.saturating_duration_since(self.last)))
+    }
+
+    /// The session ended, or was reconnected (then counting as activity).
+    pub fn set_disconnected(&mut self, disconnected: bool, now: Instant) -> Option<VtEvent> {
+        match (self.state, disconnected) {
+            (State::Disconnected, false) => {
+                let event = self.event(State::Active, now);
+                self.last = self.last.max(now);
+                Some(event)
+            }
+            (State::Active | State::Idle, true) => Some(self.event(State::Disconnected, now)),
+            _ => None,
+        }
+    }
+
+    fn event(&mut self, state: State, now: Instant) -> VtEvent {
+        self.state = state;
+        let quiet = now.saturating_duration_since(self.last);
+        VtEvent::Activity {
+            state,
+            quiet_ms: quiet.as_millis() as u64,
+        }
+    }
+}
+
+// JNI functions
+
+/// Queue `Idle` after `millis` without output or input; 0 or less for
+/// never, the default.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetActivityTimeout(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    millis: jlong,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let timeout = (millis > 0).then(|| Duration::from_millis(millis as u64));
+        vt.set_activity_timeout(timeout);
+    })
+}
+
+/// The user typed, or the app otherwise sent the session input.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNoteInput(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.note_input();
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetDisconnected(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    disconnected: jboolean,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_disconnected(disconnected != JNI_FALSE);
+    })
+}
+
+/// Milliseconds until the session goes idle, when to take events next;
+/// -1 if it can't now (no timeout, already idle, disconnected) or for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtActivityDeadline(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -1;
+        };
+
+        match vt.activity_deadline() {
+            // Rounded up, so a timer set for it finds the session idle
+            Some(deadline) => deadline.as_micros().div_ceil(1000) as jlong,
+            None => -1,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn quiet_sessions_go_idle_and_wake_on_activity() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut activity = Activity::new(start);
+        assert_eq!(activity.check(at(60_000)), None);
+
+        activity.set_timeout(Some(Duration::from_secs(30)));
+        activity.note(at(10_000));
+        assert_eq!(activity.deadline(at(25_000)), Some(Duration::from_secs(15)));
+        assert_eq!(activity.check(at(39_999)), None);
+        let idle = VtEvent::Activity {
+            state: State::Idle,
+            quiet_ms: 30_000,
+        };
+        assert_eq!(activity.check(at(40_000)), Some(idle));
+        // Once, and no deadline while idle
+        assert_eq!(activity.check(at(50_000)), None);
+        assert_eq!(activity.deadline(at(50_000)), None);
+
+        let active = VtEvent::Activity {
+            state: State::Active,
+            quiet_ms: 45_000,
+        };
+        assert_eq!(activity.note(at(55_000)), Some(active));
+        assert_eq!(activity.note(at(56_000)), None);
+
+        let gone = activity.set_disconnected(true, at(57_000)).unwrap();
+        assert_eq!(activity.state(), State::Disconnected);
+        assert!(matches!(
+            gone,
+            VtEvent::Activity {
+                quiet_ms: 1_000,
+                ..
+            }
+        ));
+        assert_eq!(activity.note(at(58_000)), None);
+        assert_eq!(activity.check(at(120_000)), None);
+        assert_eq!(activity.set_disconnected(true, at(121_000)), None);
+        assert!(activity.set_disconnected(false, at(122_000)).is_some());
+        assert_eq!(
+            activity.deadline(at(122_000)),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn feeds_and_input_are_activity_and_padding_is_not() {
+        let mut vt = AvtState::with_backend(fake(10, 1));
+        vt.set_activity_timeout(Some(Duration::ZERO));
+        let states = |vt: &mut AvtState<_>| -> Vec<State> {
+            vt.take_events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    VtEvent::Activity { state, .. } => Some(state),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(states(&mut vt), [State::Idle]);
+        vt.feed(b"\0\0");
+        assert_eq!(states(&mut vt), []);
+        vt.feed(b"$ ");
+        vt.note_input();
+        assert_eq!(states(&mut vt), [State::Active, State::Idle]);
+        vt.note_input();
+        vt.set_disconnected(true);
+        assert_eq!(states(&mut vt), [State::Active, State::Disconnected]);
+    }
+}
@@ -0,0 +1,482 @@
+//! Live broadcasts in asciinema's live-stream protocol (ALiS).
+//!
+//! An asciinema server relays a live session over a WebSocket as binary
+//! messages. The app reads them off the socket and passes each one to
+//! `Live::feed`, which drives a VT of its own. That VT gets a borrowed
+//! handle like a player's, so live and recorded sessions go through the
+//! same snapshots, diffs and rendering.
+//!
+//! Numbers are little-endian and times are `f32` seconds since the stream
+//! started:
+//!
+//! ```text
+//! magic   "ALiS" 0x01                          first message
+//! init    0x01 cols:u16 rows:u16 time:f32 theme len:u32 data[len]
+//! output  'o' time:f32 len:u32 data[len]
+//! resize  'r' time:f32 cols:u16 rows:u16
+//! eot     0x04 time:f32
+//! ```
+//!
+//! `theme` is a format byte of 0 (none), 8 or 16, followed by that many
+//! palette colors plus a foreground and a background before them, 3 RGB
+//! bytes each. An init resets the terminal to its size and feeds `data`,
+//! the screen so far. A viewer can join at any point, because the server
+//! starts each connection with an init, and it sends another after an eot
+//! when the session resumes. Messages of other types are skipped so newer
+//! servers can add them.
+
+use crate::backend::{AvtBackend, TerminalBackend};
+use crate::cast::seconds_to_micros;
+use crate::handles::{self, Kind};
+use crate::AvtState;
+use jni::objects::{JByteArray, JClass, JIntArray};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+pub const MAGIC: &[u8] = b"ALiS\x01";
+
+/// Size of the VT until the first init
+const COLS: usize = 80;
+const ROWS: usize = 24;
+
+/// One message, borrowing its data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet<'a> {
+    Magic,
+    Init {
+        cols: usize,
+        rows: usize,
+        time_us: i64,
+        /// `[fg, bg, color0, ...]` as for `Palette::from_ints`
+        theme: Option<Vec<i32>>,
+        data: &'a [u8],
+    },
+    Output {
+        time_us: i64,
+        data: &'a [u8],
+    },
+    Resize {
+        time_us: i64,
+        cols: usize,
+        rows: usize,
+    },
+    Eot {
+        time_us: i64,
+    },
+    Other(u8),
+}
+
+impl<'a> Packet<'a> {
+    /// `None` if the message is cut short, not followed by its end, or
+    /// has a zero size or an invalid time.
+    pub fn parse(message: &'a [u8]) -> Option<Self> {
+        if message == MAGIC {
+            return Some(Packet::Magic);
+        }
+        let (&kind, body) = message.split_first()?;
+        let mut reader = Reader(body);
+        let packet = match kind {
+            0x01 => {
+                let (cols, rows) = reader.size()?;
+                let time_us = reader.time()?;
+                let theme = reader.theme()?;
+                let data = reader.data()?;
+                Packet::Init {
+                    cols,
+                    rows,
+                    time_us,
+                    theme,
+                    data,
+                }
+            }
+            b'o' => {
+                let time_us = reader.time()?;
+                Packet::Output {
+                    time_us,
+                    data: reader.data()?,
+                }
+            }
+            b'r' => {
+                let time_us = reader.time()?;
+                let (cols, rows) = reader.size()?;
+                Packet::Resize {
+                    time_us,
+                    cols,
+                    rows,
+                }
+            }
+            0x04 => Packet::Eot {
+                time_us: reader.time()?,
+            },
+            _ => return Some(Packet::Other(kind)),
+        };
+        reader.0.is_empty().then_some(packet)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn time(&mut self) -> Option<i64> {
+        let seconds = f32::from_le_bytes(self.take(4)?.try_into().ok()?);
+        seconds_to_micros(seconds as f64)
+    }
+
+    fn size(&mut self) -> Option<(usize, usize)> {
+        let (cols, rows) = (self.u16()? as usize, self.u16()? as usize);
+        (cols > 0 && rows > 0).then_some((cols, rows))
+    }
+
+    fn theme(&mut self) -> Option<Option<Vec<i32>>> {
+        let colors = match self.take(1)?[0] {
+            0 => return Some(None),
+            format @ (8 | 16) => format as usize + 2,
+            _ => return None,
+        };
+        let rgb = self.take(colors * 3)?;
+        let theme = rgb
+            .chunks(3)
+            .map(|c| (c[0] as i32) << 16 | (c[1] as i32) << 8 | c[2] as i32)
+            .collect();
+        Some(Some(theme))
+    }
+
+    fn data(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+/// What feeding a message did, see `Live::feed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fed {
+    Applied,
+    /// The session ended, or paused until another init
+    Ended,
+    /// Of a type we don't know, or output before any init
+    Skipped,
+    Malformed,
+}
+
+impl Fed {
+    fn code(self) -> jint {
+        match self {
+            Fed::Applied => 0,
+            Fed::Ended => 1,
+            Fed::Skipped => 2,
+            Fed::Malformed => -2,
+        }
+    }
+}
+
+/// A live session's terminal, as of the last message fed.
+pub struct Live<B = AvtBackend> {
+    vt: AvtState<B>,
+    /// Between an init and an eot
+    live: bool,
+    time_us: Option<i64>,
+    theme: Option<Vec<i32>>,
+}
+
+impl Live {
+    pub fn new() -> Self {
+        Live::with_vt(AvtState::new(COLS, ROWS))
+    }
+}
+
+impl Default for Live {
+    fn default() -> Self {
+        Live::new()
+    }
+}
+
+registered!(Live, Kind::Live);
+
+impl<B: TerminalBackend> Live<B> {
+    pub fn with_vt(vt: AvtState<B>) -> Self {
+        Live {
+            vt,
+            live: false,
+            time_us: None,
+            theme: None,
+        }
+    }
+
+    pub fn vt(&self) -> &AvtState<B> {
+        &self.vt
+    }
+
+    pub fn vt_mut(&mut self) -> &mut AvtState<B> {
+        &mut self.vt
+    }
+
+    /// Stream time of the last message with one, `None` before the first.
+    pub fn time_us(&self) -> Option<i64> {
+        self.time_us
+    }
+
+    /// The theme of the last init, if it had one.
+    pub fn theme(&self) -> Option<&[i32]> {
+        self.theme.as_deref()
+    }
+
+    /// Apply one WebSocket message. The VT is reset in place by an init,
+    /// so its handle stays valid for the whole broadcast.
+    pub fn feed(&mut self, message: &[u8]) -> Fed {
+        let Some(packet) = Packet::parse(message) else {
+            return Fed::Malformed;
+        };
+        match packet {
+            Packet::Magic => Fed::Applied,
+            Packet::Init {
+                cols,
+                rows,
+                time_us,
+                theme,
+                data,
+            } => {
+                self.at(time_us);
+                self.vt.reset(cols, rows);
+                self.vt.feed(data);
+                self.theme = theme;
+                self.live = true;
+                Fed::Applied
+            }
+            Packet::Output { time_us, data } if self.live => {
+                self.at(time_us);
+                self.vt.feed(data);
+                Fed::Applied
+            }
+            Packet::Resize {
+                time_us,
+                cols,
+                rows,
+            } if self.live => {
+                self.at(time_us);
+                self.vt.resize(cols, rows);
+                Fed::Applied
+            }
+            Packet::Eot { time_us } => {
+                self.time_us = Some(time_us);
+                self.live = false;
+                Fed::Ended
+            }
+            Packet::Output { .. } | Packet::Resize { .. } | Packet::Other(_) => Fed::Skipped,
+        }
+    }
+
+    fn at(&mut self, time_us: i64) {
+        self.time_us = Some(time_us);
+        self.vt.set_event_time(Some(time_us.max(0) as u64));
+    }
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamNew(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jlong {
+    jni_guard!(env, {
+        handles::add(Live::new())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        if let Some(mut live) = handles::take::<Live>(&mut env, handle) {
+            handles::registry().forget(live.vt_mut());
+        }
+    })
+}
+
+/// VT handle for the stream's terminal, valid until `streamFree`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamVt(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(live) = handles::object::<Live>(&mut env, handle) else {
+            return 0;
+        };
+
+        handles::registry().insert_borrowed(live.vt_mut())
+    })
+}
+
+/// Apply one message from the relay. Returns 0 when applied, 1 at the
+/// end of the session, 2 when skipped, -2 for a malformed message, -1 for
+/// an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamFeedPacket(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    packet: JByteArray,
+) -> jint {
+    jni_guard!(env, {
+        let Some(live) = handles::object::<Live>(&mut env, handle) else {
+            return -1;
+        };
+
+        let Ok(packet) = env.convert_byte_array(packet) else {
+            return -1;
+        };
+        live.feed(&packet).code()
+    })
+}
+
+/// Stream time in microseconds of the last message, -1 before the first
+/// or for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamTime(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(live) = handles::object_ref::<Live>(&mut env, handle) else {
+            return -1;
+        };
+
+        live.time_us().unwrap_or(-1)
+    })
+}
+
+/// `[fg, bg, color0, ...]` from the last init, as `Palette::from_ints`
+/// takes it. Empty if it had no theme, or for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamTheme<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Some(live) = handles::object_ref::<Live>(&mut env, handle) else {
+            return JIntArray::default();
+        };
+
+        crate::int_array(&env, live.theme().unwrap_or_default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::{fake, FakeBackend};
+
+    fn row0(live: &Live<FakeBackend>) -> String {
+        let screen = live.vt().screen();
+        let text: String = screen.lines[0]
+            .runs
+            .iter()
+            .map(|run| &run.text[..])
+            .collect();
+        text.trim_end().to_string()
+    }
+
+    fn init(cols: u16, rows: u16, theme: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut message = vec![0x01];
+        message.extend(cols.to_le_bytes());
+        message.extend(rows.to_le_bytes());
+        message.extend(0.5f32.to_le_bytes());
+        message.extend(theme);
+        message.extend((data.len() as u32).to_le_bytes());
+        message.extend(data);
+        message
+    }
+
+    fn output(time: f32, data: &[u8]) -> Vec<u8> {
+        let mut message = vec![b'o'];
+        message.extend(time.to_le_bytes());
+        message.extend((data.len() as u32).to_le_bytes());
+        message.extend(data);
+        message
+    }
+
+    #[test]
+    fn packets_drive_the_terminal() {
+        let mut live = Live::with_vt(AvtState::with_backend(fake(80, 24)));
+        assert_eq!(live.feed(MAGIC), Fed::Applied);
+        // Output before the first init has no screen to go on
+        assert_eq!(live.feed(&output(0.25, b"lost")), Fed::Skipped);
+        assert_eq!(live.time_us(), None);
+
+        assert_eq!(live.feed(&init(10, 2, &[0], b"$ ")), Fed::Applied);
+        assert_eq!(live.feed(&output(1.5, b"ls")), Fed::Applied);
+        assert_eq!(live.time_us(), Some(1_500_000));
+        assert_eq!(row0(&live), "$ ls");
+
+        let mut resize = vec![b'r'];
+        resize.extend(2f32.to_le_bytes());
+        resize.extend([20, 0, 4, 0]);
+        assert_eq!(live.feed(&resize), Fed::Applied);
+        let screen = live.vt().screen();
+        assert_eq!((screen.cols, screen.rows), (20, 4));
+
+        assert_eq!(live.feed(b"x\0\0\0\0"), Fed::Skipped);
+        let mut eot = vec![0x04];
+        eot.extend(3f32.to_le_bytes());
+        assert_eq!(live.feed(&eot), Fed::Ended);
+        assert_eq!(live.feed(&output(3.5, b"!")), Fed::Skipped);
+
+        // Resuming starts over with the new screen
+        assert_eq!(live.feed(&init(10, 2, &[0], b"again")), Fed::Applied);
+        assert_eq!(row0(&live), "again");
+        assert_eq!(live.time_us(), Some(500_000));
+    }
+
+    #[test]
+    fn malformed_packets_are_refused() {
+        let mut theme = vec![8];
+        theme.extend([0xff, 0x80, 0x00, 0x10, 0x20, 0x30]);
+        theme.extend([0; 8 * 3]);
+        assert_eq!(
+            Packet::parse(&init(4, 3, &theme, b"")),
+            Some(Packet::Init {
+                cols: 4,
+                rows: 3,
+                time_us: 500_000,
+                theme: Some([0xff8000, 0x102030].into_iter().chain([0; 8]).collect()),
+                data: b"",
+            })
+        );
+
+        let good = output(1.0, b"abc");
+        assert_eq!(Packet::parse(&good[..good.len() - 1]), None);
+        assert_eq!(Packet::parse(&[&good[..], b"z"].concat()), None);
+        assert_eq!(Packet::parse(&output(f32::NAN, b"")), None);
+        assert_eq!(Packet::parse(&init(0, 3, &[0], b"")), None);
+        assert_eq!(Packet::parse(&init(4, 3, &[7], b"")), None);
+        assert_eq!(Packet::parse(b""), None);
+
+        let mut live = Live::with_vt(AvtState::with_backend(fake(8, 1)));
+        assert_eq!(live.feed(b"ALiS\x02"), Fed::Skipped);
+        assert_eq!(live.feed(&good[..3]), Fed::Malformed);
+    }
+}
@@ -0,0 +1,185 @@
+//! Allocation counts per subsystem, to tell which module a memory
+//! regression reported from the field comes from. Built with the
+//! `alloc-stats` feature, which is meant for debug builds: every
+//! allocation then takes a few atomic adds.
+//!
+//! `Counting` wraps the system allocator and becomes the global one. Each
+//! thread has a current `Subsystem`, set for the extent of a scope by
+//! `alloc_scope!` (scopes nest), and allocations count against it; those
+//! outside any scope count as `Other`. Frees aren't attributed, since a
+//! buffer is often freed by another subsystem than the one that allocated
+//! it (an encoded snapshot by the JNI layer), so live and peak bytes are
+//! kept for the whole process only. The counters are process-wide too,
+//! like the allocator: `vtAllocStats` takes no handle.
+
+use jni::objects::{JClass, JLongArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subsystem {
+    /// The backend: avt's screen and scrollback, its own parser included
+    Grid,
+    /// The wrapper's scan of each feed and what it tracks from the
+    /// sequences (links, line attributes, events)
+    Parser,
+    /// Snapshots and diffs, capture included
+    Encoder,
+    Other,
+}
+
+pub const SUBSYSTEMS: usize = 4;
+
+/// Names of the subsystems, by discriminant
+pub const NAMES: [&str; SUBSYSTEMS] = ["grid", "parser", "encoder", "other"];
+
+thread_local! {
+    static CURRENT: Cell<u8> = const { Cell::new(Subsystem::Other as u8) };
+}
+
+static ALLOCATIONS: [AtomicU64; SUBSYSTEMS] = [const { AtomicU64::new(0) }; SUBSYSTEMS];
+static BYTES: [AtomicU64; SUBSYSTEMS] = [const { AtomicU64::new(0) }; SUBSYSTEMS];
+static LIVE: AtomicU64 = AtomicU64::new(0);
+static PEAK: AtomicU64 = AtomicU64::new(0);
+
+/// Restores the thread's previous subsystem when dropped.
+pub struct Scope {
+    previous: u8,
+}
+
+/// Count this thread's allocations against `subsystem` until the scope is
+/// dropped.
+pub fn enter(subsystem: Subsystem) -> Scope {
+    let previous = CURRENT.with(|current| current.replace(subsystem as u8));
+    Scope { previous }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Counters as of a `stats` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// Allocations (reallocations included) by subsystem
+    pub allocations: [u64; SUBSYSTEMS],
+    /// Bytes those asked for
+    pub bytes: [u64; SUBSYSTEMS],
+    pub live_bytes: u64,
+    pub peak_bytes: u64,
+}
+
+pub fn stats() -> Stats {
+    let load = |counters: &[AtomicU64; SUBSYSTEMS]| counters.each_ref().map(|c| c.load(Relaxed));
+    Stats {
+        allocations: load(&ALLOCATIONS),
+        bytes: load(&BYTES),
+        live_bytes: LIVE.load(Relaxed),
+        peak_bytes: PEAK.load(Relaxed),
+    }
+}
+
+/// The system allocator, counting.
+pub struct Counting;
+
+impl Counting {
+    fn record(&self, size: usize) {
+        // A thread being torn down has no current subsystem left
+        let index = CURRENT
+            .try_with(Cell::get)
+            .unwrap_or(Subsystem::Other as u8) as usize;
+        ALLOCATIONS[index].fetch_add(1, Relaxed);
+        BYTES[index].fetch_add(size as u64, Relaxed);
+        let live = LIVE.fetch_add(size as u64, Relaxed) + size as u64;
+        PEAK.fetch_max(live, Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.record(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.record(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size() as u64, Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            LIVE.fetch_sub(layout.size() as u64, Relaxed);
+            self.record(new_size);
+        }
+        new
+    }
+}
+
+// JNI functions
+
+/// `[allocations, bytes]` of each subsystem in `NAMES` order, then
+/// `[live_bytes, peak_bytes]`, see `stats`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtAllocStats<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let stats = stats();
+        let mut values: Vec<jlong> = (0..SUBSYSTEMS)
+            .flat_map(|i| [stats.allocations[i] as jlong, stats.bytes[i] as jlong])
+            .collect();
+        values.extend([stats.live_bytes as jlong, stats.peak_bytes as jlong]);
+        crate::long_array(&env, &values)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_count_against_the_innermost_scope() {
+        // Other tests allocate concurrently, so only look for growth
+        let before = stats();
+        let buffers = {
+            let _encoder = enter(Subsystem::Encoder);
+            let outer = Vec::<u8>::with_capacity(1 << 20);
+            let inner = {
+                let _grid = enter(Subsystem::Grid);
+                Vec::<u8>::with_capacity(1 << 21)
+            };
+            (outer, inner, Vec::<u8>::with_capacity(1 << 22))
+        };
+        let after = stats();
+        let grew = |i: usize| after.bytes[i] - before.bytes[i];
+
+        assert!(grew(Subsystem::Encoder as usize) >= (1 << 20) + (1 << 22));
+        assert!(grew(Subsystem::Grid as usize) >= 1 << 21);
+        assert!(after.peak_bytes >= after.live_bytes);
+        drop(buffers);
+        assert_eq!(CURRENT.with(Cell::get), Subsystem::Other as u8);
+    }
+}
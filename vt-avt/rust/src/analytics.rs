@@ -0,0 +1,217 @@
+//! Recording analytics for the detail screen and the seek bar's activity
+//! strip: how long a cast plays, what's in it and when it's busy.
+//!
+//! Everything is in one pass over the events, without replaying them, so
+//! it's cheap enough to run when a recording is opened. Times are playback
+//! times (the idle time limit applied, as by `player::seek`), which is what
+//! the seek bar shows, except for the idle gaps: those are the pauses as
+//! recorded, the ones the limit cuts short.
+
+use crate::cast::{Cast, EventKind};
+use crate::{handles, player, write_varint, write_varint_u64};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+/// Shortest pause between events counted as an idle gap
+pub const MIN_GAP_US: i64 = 1_000_000;
+
+/// Most histogram buckets, far more than a seek bar has pixels
+pub const MAX_BUCKETS: usize = 4096;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// Playback time of the last event
+    pub duration_us: i64,
+    /// The same as recorded, without the idle time limit
+    pub recorded_us: i64,
+    pub output_events: u64,
+    pub input_events: u64,
+    pub markers: u64,
+    pub resizes: u64,
+    /// Events of codes we don't interpret
+    pub other_events: u64,
+    /// UTF-8 bytes of all output
+    pub output_bytes: u64,
+    pub gaps: Gaps,
+    /// Length of each histogram bucket; the last may end past the duration
+    pub bucket_us: i64,
+    /// Output bytes in each bucket of playback time, from 0
+    pub histogram: Vec<u64>,
+}
+
+/// Pauses of at least `MIN_GAP_US` between events, as recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Gaps {
+    pub count: u64,
+    pub total_us: i64,
+    pub longest_us: i64,
+    /// Playback time where the longest starts
+    pub longest_at_us: i64,
+}
+
+/// `cast` analyzed, output bucketed into `buckets` (1 to `MAX_BUCKETS`)
+/// stretches of playback time.
+pub fn analyze(cast: &Cast, buckets: usize) -> Analysis {
+    let schedule = player::schedule(cast, player::idle_limit(&cast.header));
+    let duration_us = schedule.last().copied().unwrap_or(0);
+    let buckets = buckets.clamp(1, MAX_BUCKETS);
+    // A little over an even split, so the last event is in the last bucket
+    let bucket_us = duration_us / buckets as i64 + 1;
+    let mut analysis = Analysis {
+        duration_us,
+        bucket_us,
+        histogram: vec![0; buckets],
+        ..Analysis::default()
+    };
+
+    let mut prev = None;
+    for (event, &at) in cast.events.iter().zip(&schedule) {
+        match &event.kind {
+            EventKind::Output(data) => {
+                analysis.output_events += 1;
+                analysis.output_bytes += data.len() as u64;
+                let bucket = ((at / bucket_us) as usize).min(buckets - 1);
+                analysis.histogram[bucket] += data.len() as u64;
+            }
+            EventKind::Input(_) => analysis.input_events += 1,
+            EventKind::Marker(_) => analysis.markers += 1,
+            EventKind::Resize { .. } => analysis.resizes += 1,
+            EventKind::Other { .. } => analysis.other_events += 1,
+        }
+
+        // Out of order times are taken as no pause, as by the player
+        let time_us = prev.map_or(event.time_us, |(prev_us, _)| event.time_us.max(prev_us));
+        if let Some((prev_us, prev_at)) = prev {
+            let gap = time_us - prev_us;
+            if gap >= MIN_GAP_US {
+                let gaps = &mut analysis.gaps;
+                gaps.count += 1;
+                gaps.total_us += gap;
+                if gap > gaps.longest_us {
+                    gaps.longest_us = gap;
+                    gaps.longest_at_us = prev_at;
+                }
+            }
+        }
+        analysis.recorded_us = time_us.max(0);
+        prev = Some((time_us, at));
+    }
+    analysis
+}
+
+impl Analysis {
+    /// Varints in field order, the gaps' fields in theirs, then the
+    /// histogram's length and its buckets.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for value in [self.duration_us, self.recorded_us] {
+            write_varint_u64(&mut buf, value as u64);
+        }
+        for value in [
+            self.output_events,
+            self.input_events,
+            self.markers,
+            self.resizes,
+            self.other_events,
+            self.output_bytes,
+            self.gaps.count,
+        ] {
+            write_varint_u64(&mut buf, value);
+        }
+        for value in [
+            self.gaps.total_us,
+            self.gaps.longest_us,
+            self.gaps.longest_at_us,
+            self.bucket_us,
+        ] {
+            write_varint_u64(&mut buf, value as u64);
+        }
+        write_varint(&mut buf, self.histogram.len());
+        for &bytes in &self.histogram {
+            write_varint_u64(&mut buf, bytes);
+        }
+        buf
+    }
+}
+
+// JNI functions
+
+/// `analyze` of the cast `handle` into `buckets`, encoded; empty for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castAnalyze<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    buckets: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let analysis = analyze(cast, buckets.max(1) as usize);
+        env.byte_array_from_slice(&analysis.encode())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_events_and_buckets_output_in_playback_time() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24, \"idle_time_limit\": 2}\n\
+            [0.0, \"o\", \"$ \"]\n\
+            [0.5, \"i\", \"l\"]\n\
+            [0.6, \"o\", \"ls\\r\\n\"]\n\
+            [30.6, \"m\", \"later\"]\n\
+            [31.0, \"o\", \"\xc3\xa9\"]\n\
+            [31.0, \"r\", \"100x30\"]\n\
+            [32.5, \"x\", 1]\n\
+            [32.0, \"o\", \"done\"]\n",
+        )
+        .unwrap();
+
+        let analysis = analyze(&cast, 4);
+        assert_eq!(analysis.duration_us, 4_500_000);
+        assert_eq!(analysis.recorded_us, 32_500_000);
+        let counts = [
+            analysis.output_events,
+            analysis.input_events,
+            analysis.markers,
+            analysis.resizes,
+            analysis.other_events,
+        ];
+        assert_eq!(counts, [4, 1, 1, 1, 1]);
+        assert_eq!(analysis.output_bytes, 12);
+        // 30s recorded, playing as 2s, and the 1.5s to the "x" event; the
+        // "o" after it is out of order, so not a pause
+        assert_eq!(
+            analysis.gaps,
+            Gaps {
+                count: 2,
+                total_us: 31_500_000,
+                longest_us: 30_000_000,
+                longest_at_us: 600_000,
+            }
+        );
+        assert_eq!(analysis.bucket_us, 1_125_001);
+        assert_eq!(analysis.histogram, [6, 0, 2, 4]);
+
+        let encoded = analysis.encode();
+        assert_eq!(&encoded[encoded.len() - 5..], [4, 6, 0, 2, 4]);
+    }
+
+    #[test]
+    fn empty_casts_have_empty_buckets() {
+        let cast = Cast::parse(b"{\"version\": 2, \"width\": 80, \"height\": 24}\n").unwrap();
+        let analysis = analyze(&cast, 0);
+        assert_eq!(analysis.duration_us, 0);
+        assert_eq!(analysis.histogram, [0]);
+        assert_eq!(analyze(&cast, usize::MAX).histogram.len(), MAX_BUCKETS);
+    }
+}
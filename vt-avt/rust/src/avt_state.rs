@@ -0,0 +1,1508 @@
+//! The terminal core: `AvtState` and what it's made of, without the JNI.
+//!
+//! A VT is a backend (avt's screen and scrollback, see `backend`) with what
+//! this crate tracks beside it: line attributes, links, images, the cursor
+//! style, dirty rows and queued events, plus every snapshot and diff
+//! encoder on top. None of that depends on the JVM, so other bindings use
+//! the same engine: the JNI functions (in `lib.rs` and each feature's
+//! module) look VTs up by handle and call in here, `capi` does the same for
+//! C callers, and tests drive it directly over a fake backend.
+
+use crate::backend::{AvtBackend, Retention, TerminalBackend};
+use crate::config::{CursorQuery, TermConfig};
+use crate::diff::{Content, Diff};
+use crate::events::VtEvent;
+#[cfg(feature = "images")]
+use crate::images::Images;
+use crate::lineattr::{LineAttrs, LineOp};
+use crate::links::Links;
+use crate::predict::{Predicted, Predictor};
+use crate::quirks::Quirks;
+use crate::scan::Scanner;
+#[cfg(feature = "images")]
+use crate::snapshot::ImagePlacement;
+use crate::snapshot::{CursorShape, Screen};
+use crate::throttle::Throttle;
+use crate::traffic::Traffic;
+use crate::Instant;
+use crate::{
+    activity, burnin, delta, diff, epoch, events, fanout, framehash, limits, links, modes,
+    palette, panes, perf, persist, protocol, scan, sequences, shrink, snapshot, styles, themes,
+    watch, write_varint,
+};
+#[cfg(feature = "images")]
+use crate::images;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Instance profile, chosen when the VT is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VtMode {
+    #[default]
+    Full,
+    /// For home-screen widgets: no scrollback, and only content changes
+    /// produce diffs (cursor movement alone is never reported)
+    Ticker,
+}
+
+impl VtMode {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(VtMode::Full),
+            1 => Some(VtMode::Ticker),
+            _ => None,
+        }
+    }
+}
+
+/// Whether playback shows the cursor the recording asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorPolicy {
+    #[default]
+    Real,
+    /// Show a cursor until the recording shows one itself: recordings
+    /// that hide it at the start and never show it look broken while the
+    /// author is typing
+    Auto,
+    Always,
+}
+
+impl CursorPolicy {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(CursorPolicy::Real),
+            1 => Some(CursorPolicy::Auto),
+            2 => Some(CursorPolicy::Always),
+            _ => None,
+        }
+    }
+}
+
+/// Longest a synchronized update (mode 2026) may hold back diffs, so an
+/// app that dies mid-frame can't freeze the player
+pub(crate) const SYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Wrapper around a terminal backend (avt by default) with dirty tracking
+pub struct AvtState<B = AvtBackend> {
+    pub(crate) vt: B,
+    pub(crate) mode: VtMode,
+    scanner: Scanner,
+    pub(crate) line_attrs: LineAttrs,
+    links: Links,
+    /// Inline images on screen, see `images`
+    #[cfg(feature = "images")]
+    images: Images,
+    /// Kept across resets, see `limits`
+    limits: limits::Limits,
+    /// Trailing bytes of a UTF-8 sequence split across feeds
+    utf8_partial: Vec<u8>,
+    pub(crate) dirty_lines: HashSet<usize>,
+    cursor_changed: bool,
+    resized: bool,
+    /// The alternate screen is shown (modes 47, 1047 and 1049)
+    alt_screen: bool,
+    /// `alt_screen` changed since the last diff
+    screen_switched: bool,
+    /// `modes` bits the program has set
+    modes: u32,
+    /// `modes` changed since the last diff
+    modes_changed: bool,
+    throttle: Throttle,
+    /// Start of the synchronized update in progress, if any
+    pub(crate) sync_since: Option<Instant>,
+    /// Resize requested while the backend was mid-sequence, and whether
+    /// it reflows
+    pending_resize: Option<(usize, usize, bool)>,
+    /// Baselines for `snapshot_delta`
+    snapshots: delta::History,
+    /// Frames and acks of each registered consumer, see `fanout`
+    consumers: fanout::Consumers,
+    /// Screens for readers on other threads, while any are open, see
+    /// `epoch`
+    pub(crate) published: Option<epoch::Publisher<Screen>>,
+    /// Capabilities to answer queries with; `None` during playback
+    config: Option<TermConfig>,
+    /// Query replies not yet taken by the session
+    responses: Vec<u8>,
+    /// Sequences the recording terminal supported
+    quirks: Quirks,
+    /// Queue replayed input events, see `set_input_events`
+    input_events: bool,
+    /// Sequences fed that nothing acts on, kept across resets
+    unknown: sequences::UnknownLog,
+    /// Trace IDs of feeds not yet reported by a diff
+    traces: Vec<u64>,
+    /// Local echo shown ahead of the program's
+    predictor: Predictor,
+    /// Feed counts for throughput display
+    traffic: Traffic,
+    /// Kept across resets, see `perf`
+    perf: perf::Counters,
+    /// What the client negotiated; kept across resets, see `protocol`
+    wire: protocol::Wire,
+    /// Idle and disconnect tracking for interactive sessions
+    activity: activity::Activity,
+    cursor_policy: CursorPolicy,
+    /// Resolves indexed colors in `screen`, see `themes`
+    theme: Option<palette::Palette>,
+    /// Brightens bold text in `screen`, before `theme`
+    bold_as_bright: bool,
+    /// The output has shown the cursor (DECTCEM set) since the last reset
+    cursor_shown: bool,
+    /// Set by DECSCUSR; avt doesn't track it
+    cursor_shape: CursorShape,
+    cursor_blink: bool,
+    /// Shape, blink and visibility as of the last diff, `None` before the
+    /// first
+    reported_cursor_style: Option<(CursorShape, bool, bool)>,
+    /// Events not yet taken by the app, oldest first, with the time each
+    /// was queued (see `set_event_time`)
+    events: VecDeque<(u64, VtEvent)>,
+    /// Playback time to stamp events with, `None` outside playback
+    event_time: Option<u64>,
+    /// What events are stamped relative to outside playback
+    created: Instant,
+    /// Kept for `encode_snapshot_reused`
+    snapshot_buf: Vec<u8>,
+    /// Lines as of the last `poll_diff_spans` or `poll_diff_damage`;
+    /// emptied by a resize and by the other polls, which take dirty rows
+    /// without updating it
+    reported: Vec<snapshot::Line>,
+    /// The image table as of the last diff
+    #[cfg(feature = "images")]
+    reported_images: Vec<ImagePlacement>,
+    /// Style ids of the interned snapshot and diff forms
+    styles: styles::Interner,
+    watchers: watch::Watchers,
+    /// Watch events queued since creation, resets included
+    watch_hits: u64,
+    /// Row hashes as of the last `frame_hash`
+    frame_rows: framehash::RowHashes,
+    /// When each row last changed, for `burn_in`
+    row_ages: burnin::RowAges,
+}
+
+impl AvtState {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        AvtState::with_backend(AvtBackend::new(cols, rows))
+    }
+
+    pub fn with_mode(cols: usize, rows: usize, mode: VtMode) -> Self {
+        let scrollback_limit = match mode {
+            VtMode::Full => None,
+            VtMode::Ticker => Some(0),
+        };
+        let backend = AvtBackend::with_scrollback_limit(cols, rows, scrollback_limit);
+        let mut state = AvtState::with_backend(backend);
+        state.mode = mode;
+        state
+    }
+}
+
+impl<B: TerminalBackend> AvtState<B> {
+    pub fn with_backend(backend: B) -> Self {
+        let rows = backend.size().1;
+        AvtState {
+            vt: backend,
+            mode: VtMode::Full,
+            scanner: Scanner::new(),
+            line_attrs: LineAttrs::new(rows),
+            links: Links::new(rows),
+            #[cfg(feature = "images")]
+            images: Images::new(rows),
+            limits: limits::Limits::default(),
+            utf8_partial: Vec::new(),
+            dirty_lines: (0..rows).collect(),
+            cursor_changed: true,
+            resized: false,
+            alt_screen: false,
+            screen_switched: false,
+            modes: 0,
+            modes_changed: false,
+            throttle: Throttle::new(Instant::now()),
+            sync_since: None,
+            pending_resize: None,
+            snapshots: delta::History::default(),
+            consumers: fanout::Consumers::default(),
+            published: None,
+            config: None,
+            responses: Vec::new(),
+            quirks: Quirks::default(),
+            input_events: false,
+            unknown: sequences::UnknownLog::default(),
+            traces: Vec::new(),
+            predictor: Predictor::default(),
+            traffic: Traffic::new(Instant::now()),
+            perf: perf::Counters::default(),
+            wire: protocol::Wire::default(),
+            activity: activity::Activity::new(Instant::now()),
+            cursor_policy: CursorPolicy::Real,
+            theme: None,
+            bold_as_bright: false,
+            cursor_shown: false,
+            cursor_shape: CursorShape::Block,
+            cursor_blink: true,
+            reported_cursor_style: None,
+            events: VecDeque::new(),
+            event_time: None,
+            created: Instant::now(),
+            snapshot_buf: Vec::new(),
+            reported: Vec::new(),
+            #[cfg(feature = "images")]
+            reported_images: Vec::new(),
+            styles: styles::Interner::default(),
+            watchers: watch::Watchers::default(),
+            watch_hits: 0,
+            frame_rows: framehash::RowHashes::default(),
+            row_ages: burnin::RowAges::default(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.vt
+    }
+
+    /// The profile the VT was created with.
+    pub fn mode(&self) -> VtMode {
+        self.mode
+    }
+
+    /// Answer queries in fed output from `config` (interactive sessions),
+    /// or not at all with `None`.
+    pub fn set_config(&mut self, config: Option<TermConfig>) {
+        self.config = config;
+        self.responses.clear();
+    }
+
+    pub fn config(&self) -> Option<&TermConfig> {
+        self.config.as_ref()
+    }
+
+    pub fn config_mut(&mut self) -> Option<&mut TermConfig> {
+        self.config.as_mut()
+    }
+
+    /// Emulate the terminal a recording was made in, see `quirks`. Kept
+    /// across resets.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Override the cursor's visibility during playback; interactive
+    /// sessions (with a config) always show the real state. Kept across
+    /// resets.
+    pub fn set_cursor_policy(&mut self, policy: CursorPolicy) {
+        if policy != self.cursor_policy {
+            self.cursor_policy = policy;
+            self.cursor_changed = true;
+            self.push_event(VtEvent::OptionChanged(events::OPTION_CURSOR_POLICY));
+        }
+    }
+
+    /// Resolve indexed colors with `theme`, or leave them to the client
+    /// for `None`, see `themes`. Kept across resets.
+    pub fn set_theme(&mut self, theme: Option<palette::Palette>) {
+        if theme != self.theme {
+            self.theme = theme;
+            self.option_changed(events::OPTION_THEME);
+        }
+    }
+
+    /// Show bold text in colors 0-7 as 8-15 in snapshots and diffs, see
+    /// `themes`. Kept across resets.
+    pub fn set_bold_as_bright(&mut self, on: bool) {
+        if on != self.bold_as_bright {
+            self.bold_as_bright = on;
+            self.option_changed(events::OPTION_BOLD_AS_BRIGHT);
+        }
+    }
+
+    /// Redraw every row with `option`'s new value, and say so.
+    fn option_changed(&mut self, option: u8) {
+        self.invalidate(None);
+        self.push_event(VtEvent::OptionChanged(option));
+    }
+
+    /// Queue the input events of replayed casts (`player::apply`) as
+    /// `VtEvent::Input`, for showing keystrokes, or skip them as by
+    /// default. Kept across resets.
+    pub fn set_input_events(&mut self, on: bool) {
+        self.input_events = on;
+    }
+
+    pub fn input_events(&self) -> bool {
+        self.input_events
+    }
+
+    /// Sequences fed since the VT was created that neither avt nor the
+    /// wrapper acts on.
+    pub fn unknown_sequences(&self) -> &sequences::UnknownLog {
+        &self.unknown
+    }
+
+    fn forces_cursor(&self) -> bool {
+        self.config.is_none()
+            && match self.cursor_policy {
+                CursorPolicy::Real => false,
+                CursorPolicy::Auto => !self.cursor_shown,
+                CursorPolicy::Always => true,
+            }
+    }
+
+    /// Replies to queries fed since the last call, to write back to the
+    /// program.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Queue a `VtEvent::Watch` tagged `tag` when text matching `pattern`
+    /// appears, see `watch`. False when too many are set. Kept across
+    /// resets.
+    pub fn watch(&mut self, tag: u32, pattern: watch::Pattern) -> bool {
+        self.watchers.add(tag, pattern, &self.vt)
+    }
+
+    /// Returns whether a watch was set for `tag`.
+    pub fn unwatch(&mut self, tag: u32) -> bool {
+        self.watchers.remove(tag)
+    }
+
+    /// Watch events queued since the VT was created, however many were
+    /// taken or dropped since.
+    pub fn watch_hits(&self) -> u64 {
+        self.watch_hits
+    }
+
+    /// Events queued since the last call, see `events`.
+    pub fn take_events(&mut self) -> Vec<VtEvent> {
+        self.check_activity();
+        self.events.drain(..).map(|(_, event)| event).collect()
+    }
+
+    /// `take_events` with the time, in microseconds, each was queued.
+    pub fn take_timed_events(&mut self) -> Vec<(u64, VtEvent)> {
+        self.check_activity();
+        self.events.drain(..).collect()
+    }
+
+    /// Queue `VtEvent::Activity` `Idle` after `timeout` without output or
+    /// input, or never for `None`, see `activity`. Kept across resets.
+    pub fn set_activity_timeout(&mut self, timeout: Option<Duration>) {
+        self.activity.set_timeout(timeout);
+    }
+
+    /// Input was sent to the session, which counts as activity.
+    pub fn note_input(&mut self) {
+        if let Some(event) = self.activity.note(Instant::now()) {
+            self.push_event(event);
+        }
+    }
+
+    /// The session ended, or was reconnected.
+    pub fn set_disconnected(&mut self, disconnected: bool) {
+        if let Some(event) = self.activity.set_disconnected(disconnected, Instant::now()) {
+            self.push_event(event);
+        }
+    }
+
+    /// How long until the session goes idle, if it can.
+    pub fn activity_deadline(&self) -> Option<Duration> {
+        self.activity.deadline(Instant::now())
+    }
+
+    fn check_activity(&mut self) {
+        if let Some(event) = self.activity.check(Instant::now()) {
+            self.push_event(event);
+        }
+    }
+
+    /// Stamp the events queued from now on with `time_us` of playback time,
+    /// or for `None` with the time since the VT was created.
+    pub fn set_event_time(&mut self, time_us: Option<u64>) {
+        self.event_time = time_us;
+    }
+
+    fn event_time(&self) -> u64 {
+        self.event_time
+            .unwrap_or_else(|| self.created.elapsed().as_micros() as u64)
+    }
+
+    /// Queue `event`, dropping the oldest past `events::MAX_QUEUED`.
+    pub fn push_event(&mut self, event: VtEvent) {
+        self.events.push_back((self.event_time(), event));
+        self.trim_events();
+    }
+
+    fn trim_events(&mut self) {
+        let excess = self.events.len().saturating_sub(events::MAX_QUEUED);
+        self.events.drain(..excess);
+    }
+
+    pub fn reset(&mut self, cols: usize, rows: usize) {
+        self.vt.reset(cols, rows);
+        self.scanner = Scanner::new();
+        self.line_attrs = LineAttrs::new(rows);
+        self.links = Links::new(rows);
+        #[cfg(feature = "images")]
+        self.images.clear(rows);
+        self.utf8_partial.clear();
+        self.sync_since = None;
+        self.pending_resize = None;
+        self.responses.clear();
+        self.predictor.clear();
+        self.traffic = Traffic::new(Instant::now());
+        self.cursor_shown = false;
+        self.cursor_shape = CursorShape::Block;
+        self.cursor_blink = true;
+        self.screen_switched |= self.alt_screen;
+        self.modes_changed |= self.modes != 0;
+        self.modes = 0;
+        self.alt_screen = false;
+        self.reported_cursor_style = None;
+        self.events.clear();
+        self.watchers.clear_rows();
+        self.reported.clear();
+        self.dirty_lines = (0..rows).collect();
+        self.cursor_changed = true;
+        self.resized = true;
+        self.throttle.note_change(Instant::now());
+        self.publish();
+    }
+
+    /// Resize, rewrapping soft-wrapped lines to the new width, see
+    /// `resize_with`.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.resize_with(cols, rows, true);
+    }
+
+    /// Resize, with `reflow` rewrapping soft-wrapped lines, scrollback
+    /// included, to the new width and moving the cursor along with its
+    /// text (what avt does), or else cutting or padding each row (see
+    /// `TerminalBackend::resize_clipped`). A resize that arrives while an
+    /// escape sequence is half-fed is applied as soon as the sequence
+    /// completes, since resizing mid-sequence would corrupt it.
+    pub fn resize_with(&mut self, cols: usize, rows: usize, reflow: bool) {
+        alloc_scope!(Grid);
+        if !self.scanner.is_ground() {
+            self.pending_resize = Some((cols, rows, reflow));
+            return;
+        }
+        self.pending_resize = None;
+        if reflow {
+            self.vt.resize(cols, rows);
+        } else {
+            self.vt.resize_clipped(cols, rows);
+        }
+        self.line_attrs.resize(rows);
+        self.links.resize(rows);
+        #[cfg(feature = "images")]
+        self.images.resize(rows);
+        self.predictor.clear();
+        self.reported.clear();
+        self.dirty_lines = (0..rows).collect();
+        self.cursor_changed = true;
+        self.resized = true;
+        self.throttle.note_change(Instant::now());
+        self.publish();
+    }
+
+    /// Show typed `input` before the program echoes it, see `predict`.
+    pub fn predict_input(&mut self, input: &[u8]) {
+        let cols = self.vt.size().0;
+        let input = String::from_utf8_lossy(input);
+        if self.predictor.predict(&input, self.vt.cursor(), cols) {
+            self.dirty_lines.insert(self.vt.cursor().row);
+            self.cursor_changed = true;
+        }
+    }
+
+    pub fn predictions(&self) -> &[Predicted] {
+        self.predictor.pending()
+    }
+
+    /// Drop all predictions, e.g. when prediction is switched off.
+    pub fn clear_predictions(&mut self) {
+        if let Some(p) = self.predictor.pending().first() {
+            self.dirty_lines.insert(p.row);
+            self.cursor_changed = true;
+        }
+        self.predictor.clear();
+    }
+
+    /// Send `rows` (every row for `None`) again with the next diff, whole,
+    /// e.g. once a dialog that covered them is gone.
+    pub fn invalidate(&mut self, rows: Option<Range<usize>>) {
+        let count = self.vt.size().1;
+        let rows = rows.map_or(0..count, |rows| rows.start..rows.end.min(count));
+        self.dirty_lines.extend(rows);
+        // Span and damage polls would find those rows unchanged
+        self.reported.clear();
+        self.throttle.note_change(Instant::now());
+    }
+
+    /// `feed`, tagging the batch with `trace_id`. The next diff echoes the
+    /// ID (see `diff`), so the app can measure input-to-pixel latency.
+    pub fn feed_traced(&mut self, bytes: &[u8], trace_id: u64) {
+        self.feed(bytes);
+        self.traces.push(trace_id);
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.feed_at(bytes, Instant::now());
+    }
+
+    /// `feed`, counting the bytes in the throughput stats as arriving at
+    /// `at` (see `batch`).
+    pub fn feed_at(&mut self, bytes: &[u8], at: Instant) {
+        alloc_scope!(Parser);
+        let _timer = self.perf.time(perf::Timing::Parse);
+        self.perf.note_feed(bytes.len());
+        self.traffic.record(at, bytes.len());
+
+        // Feeds of nothing but padding (NULs, XON/XOFF) change nothing, so
+        // skip the VT and don't report a cursor change
+        if self.scanner.is_ground()
+            && self.utf8_partial.is_empty()
+            && bytes.iter().all(|&b| scan::is_ignored(b))
+        {
+            self.traffic.record_ignored(bytes.len());
+            return;
+        }
+        if let Some(event) = self.activity.note(at) {
+            self.push_event(event);
+        }
+
+        // Completing or flushing a split UTF-8 sequence prints something
+        let mut cells_changed = !self.utf8_partial.is_empty();
+        let now = self.event_time();
+
+        // Line attributes depend on the cursor row at the moment each
+        // sequence is processed, so split the feed around those sequences
+        let vt = &mut self.vt;
+        let line_attrs = &mut self.line_attrs;
+        let links = &mut self.links;
+        #[cfg(feature = "images")]
+        let images = &mut self.images;
+        let partial = &mut self.utf8_partial;
+        let sync_since = &mut self.sync_since;
+        let config = &self.config;
+        let responses = &mut self.responses;
+        let quirks = self.quirks;
+        let cursor_shown = &mut self.cursor_shown;
+        let cursor_shape = &mut self.cursor_shape;
+        let cursor_blink = &mut self.cursor_blink;
+        let events = &mut self.events;
+        let unknown = &mut self.unknown;
+        let alt_screen = &mut self.alt_screen;
+        let screen_switched = &mut self.screen_switched;
+        let modes = &mut self.modes;
+        let modes_changed = &mut self.modes_changed;
+        let mut start = 0;
+
+        self.scanner.scan(bytes, |end, action| {
+            cells_changed |= !action.is_cursor_only();
+            *cursor_shown |= shows_cursor(&action);
+            if let Some((shape, blink)) = cursor_style(&action) {
+                *cursor_shape = shape;
+                *cursor_blink = blink;
+            }
+            events.extend(VtEvent::from_action(&action).map(|event| (now, event)));
+            unknown.record(&action, now);
+            let new_modes = modes::apply(*modes, &action);
+            if new_modes != *modes {
+                *modes = new_modes;
+                *modes_changed = true;
+            }
+            if let Some(on) = sync_update(&action) {
+                *sync_since = if on { Some(sync_since.unwrap_or_else(Instant::now)) } else { None };
+            }
+            if let Some(config) = config {
+                config.answer(&action, responses);
+                if let Some(query) = CursorQuery::from_action(&action) {
+                    // The reply is the cursor as of the query
+                    feed_utf8(vt, partial, &bytes[start..end]);
+                    start = end;
+                    query.answer(&vt.cursor(), vt.size().0, responses);
+                    return;
+                }
+            }
+            if quirks.ignores(&action) {
+                // The final byte is always in this feed; CAN in its place
+                // makes the VT abandon the sequence
+                let split = (end - 1).max(start);
+                feed_utf8(vt, partial, &bytes[start..split]);
+                feed_utf8(vt, partial, b"\x18");
+                start = end;
+                return;
+            }
+            // Links start and end at the cursor as of their sequence
+            if let Some(uri) = links::osc8(&action) {
+                feed_utf8(vt, partial, &bytes[start..end]);
+                start = end;
+                links.handle(uri, vt);
+                return;
+            }
+            // So do images, which then move the cursor below them
+            #[cfg(feature = "images")]
+            if let Some(payload) = images::payload(&action) {
+                feed_utf8(vt, partial, &bytes[start..end]);
+                start = end;
+                cells_changed |= images.place(payload, vt, |row| {
+                    line_attrs.apply(LineOp::LineFeed, row);
+                    links.apply(LineOp::LineFeed, row);
+                });
+                events.extend(images.take_events().into_iter().map(|event| (now, event)));
+                return;
+            }
+            // Scrollback is the primary screen's, so the backend gets to
+            // set it aside before the final byte makes the switch
+            if let Some(on) = alt_screen_mode(&action).filter(|&on| on != *alt_screen) {
+                let split = (end - 1).max(start);
+                feed_utf8(vt, partial, &bytes[start..split]);
+                start = split;
+                vt.switch_screen(on);
+                *alt_screen = on;
+                *screen_switched = true;
+            }
+            let Some((op, hold)) = LineOp::from_action(&action) else {
+                return;
+            };
+            #[cfg(feature = "images")]
+            let images_tracked = images.is_tracking();
+            #[cfg(not(feature = "images"))]
+            let images_tracked = false;
+            if op.needs_tracking() && !line_attrs.is_tracking() && !links.is_tracking() && !images_tracked {
+                return;
+            }
+
+            let split = end.saturating_sub(hold).max(start);
+            feed_utf8(vt, partial, &bytes[start..split]);
+            start = split;
+            let row = vt.cursor().row;
+            line_attrs.apply(op, row);
+            links.apply(op, row);
+            #[cfg(feature = "images")]
+            images.apply(op, row);
+        });
+
+        feed_utf8(vt, partial, &bytes[start..]);
+        self.links.flush(&self.vt);
+        self.traffic.record_ignored(self.scanner.take_ignored());
+        self.trim_events();
+
+        // Feeds that only move the cursor (prompt redraws, typing without
+        // echo) leave every line clean. Otherwise mark all lines dirty for
+        // simplicity; a more optimized version would track actual changes
+        if self.scanner.take_printed() || cells_changed {
+            self.dirty_lines.extend(0..self.vt.size().1);
+            if self.links.is_tracking() {
+                self.links.prune(&self.vt);
+            }
+            #[cfg(feature = "images")]
+            if self.images.is_tracking() {
+                self.images.prune(&self.vt);
+                self.evict_over_total();
+            }
+            #[cfg(feature = "images")]
+            for event in self.images.take_events() {
+                self.push_event(event);
+            }
+            if !self.watchers.is_empty() {
+                for hit in self.watchers.check(&self.vt) {
+                    self.watch_hits += 1;
+                    self.push_event(hit);
+                }
+            }
+        }
+        if let Some(row) = self.predictor.reconcile(&self.vt) {
+            self.dirty_lines.insert(row);
+        }
+        self.cursor_changed = true;
+        if let Some((cols, rows, reflow)) = self.pending_resize {
+            self.resize_with(cols, rows, reflow);
+        }
+        self.throttle.note_change(Instant::now());
+        self.publish();
+    }
+
+    /// Whether nothing is half-fed (an escape sequence or a UTF-8
+    /// character), so a dump taken now leaves nothing in flight.
+    pub fn is_at_boundary(&self) -> bool {
+        self.scanner.is_ground() && self.utf8_partial.is_empty()
+    }
+
+    /// Replace the state with `dump` replayed into a fresh terminal of the
+    /// given size: the inverse of `dump_ansi`.
+    pub fn restore(&mut self, cols: usize, rows: usize, dump: &[u8]) {
+        self.reset(cols, rows);
+        self.feed(dump);
+    }
+
+    /// What `persist` saves besides the screen and scrollback; `None` mid
+    /// escape sequence, since the parser can't be saved.
+    pub(crate) fn kept(&self) -> Option<persist::Kept> {
+        if !self.scanner.is_ground() {
+            return None;
+        }
+        let mut dirty_rows: Vec<_> = self.dirty_lines.iter().copied().collect();
+        dirty_rows.sort_unstable();
+        Some(persist::Kept {
+            mode: self.mode,
+            utf8_partial: self.utf8_partial.clone(),
+            dirty_rows,
+            cursor_changed: self.cursor_changed,
+            resized: self.resized,
+            cursor_policy: self.cursor_policy,
+            theme: self.theme.clone(),
+            bold_as_bright: self.bold_as_bright,
+            input_events: self.input_events,
+            cursor_shown: self.cursor_shown,
+        })
+    }
+
+    /// `restore`, with `scrollback` above the screen and `kept` applied
+    /// after, quietly: options restored aren't announced as changed. The
+    /// mode is the VT's own.
+    pub(crate) fn restore_whole(
+        &mut self,
+        cols: usize,
+        rows: usize,
+        scrollback: &[String],
+        dump: &[u8],
+        kept: persist::Kept,
+    ) {
+        self.reset(cols, rows);
+        self.vt.restore_scrollback(scrollback);
+        self.feed(dump);
+        self.cursor_policy = kept.cursor_policy;
+        self.theme = kept.theme;
+        self.bold_as_bright = kept.bold_as_bright;
+        self.input_events = kept.input_events;
+        self.cursor_shown |= kept.cursor_shown;
+        self.utf8_partial = kept.utf8_partial;
+        self.dirty_lines = kept.dirty_rows.into_iter().filter(|&row| row < rows).collect();
+        self.cursor_changed = kept.cursor_changed;
+        self.resized = kept.resized;
+    }
+
+    /// Cap `poll_diff` at `max_per_second` diffs; 0 removes the cap.
+    /// Polls inside the interval return `None` and changes accumulate.
+    pub fn set_update_budget(&mut self, max_per_second: u32) {
+        self.throttle.set_budget(max_per_second);
+    }
+
+    /// Arm idle detection: see `poll_idle`. 0 disables it.
+    pub fn set_idle_timeout(&mut self, millis: u32) {
+        self.throttle.set_idle_timeout(millis);
+    }
+
+    /// True once after `millis` without feeds, resizes or resets.
+    pub fn poll_idle(&mut self) -> bool {
+        self.throttle.poll_idle(Instant::now())
+    }
+
+    /// Give back the buffer capacity a burst of output left allocated,
+    /// returning the bytes freed, as far as they can be counted. Done on
+    /// its own at the first diff poll after `throttle::COMPACT_AFTER`
+    /// without changes.
+    pub fn compact(&mut self) -> usize {
+        self.snapshot_buf.clear();
+        let mut freed = self.scanner.compact()
+            + shrink(&mut self.snapshot_buf)
+            + shrink(&mut self.utf8_partial)
+            + shrink(&mut self.responses)
+            + shrink(&mut self.traces)
+            + shrink(&mut self.reported);
+
+        let before = self.events.capacity();
+        self.events.shrink_to_fit();
+        freed += (before - self.events.capacity()) * std::mem::size_of::<(u64, VtEvent)>();
+        let before = self.dirty_lines.capacity();
+        self.dirty_lines.shrink_to_fit();
+        freed += before.saturating_sub(self.dirty_lines.capacity()) * std::mem::size_of::<usize>();
+        freed
+    }
+
+    /// Feeds since the last reset, see `traffic`.
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    /// Limit the scrollback kept, see `TerminalBackend::set_retention`.
+    /// Kept across resets.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.vt.set_retention(retention);
+    }
+
+    /// Limit the memory the VT holds, see `limits`. Kept across resets.
+    pub fn set_limits(&mut self, limits: limits::Limits) {
+        self.limits = limits;
+        let screen = self.vt.size().1 * self.vt.line_bytes();
+        self.vt.set_retention(limits.retention(screen));
+        #[cfg(feature = "images")]
+        {
+            let evicted = self.images.set_max_bytes(limits.image_bytes());
+            if self.evict_over_total() || evicted {
+                self.invalidate(None);
+            }
+        }
+    }
+
+    /// Evict images until `max_total_bytes` fits.
+    #[cfg(feature = "images")]
+    fn evict_over_total(&mut self) -> bool {
+        let Some(total) = self.limits.max_total_bytes else {
+            return false;
+        };
+        let others = self.memory_usage().total() - self.images.bytes();
+        self.images.evict_to(total.saturating_sub(others))
+    }
+
+    pub fn limits(&self) -> limits::Limits {
+        self.limits
+    }
+
+    /// Time spent and work done since the VT was created, see `perf`.
+    pub fn stats(&self) -> perf::Stats {
+        self.perf.stats()
+    }
+
+    /// Send snapshots and diffs as `wire` says, see `protocol`. Kept
+    /// across resets.
+    pub fn set_wire(&mut self, wire: protocol::Wire) {
+        self.wire = wire;
+    }
+
+    pub fn wire(&self) -> protocol::Wire {
+        self.wire
+    }
+
+    /// What each part of the VT holds, see `limits::Usage`.
+    pub fn memory_usage(&self) -> limits::Usage {
+        let line_bytes = self.vt.line_bytes();
+        let reported: usize = self
+            .reported
+            .iter()
+            .map(|line| {
+                std::mem::size_of::<snapshot::Line>()
+                    + line.runs.iter().map(|run| std::mem::size_of::<snapshot::Run>() + run.text.len()).sum::<usize>()
+            })
+            .sum();
+        #[cfg(feature = "images")]
+        let (images, images_held, images_evicted) =
+            (self.images.bytes(), self.images.held(), self.images.evictions() as usize);
+        #[cfg(not(feature = "images"))]
+        let (images, images_held, images_evicted) = (0, 0, 0);
+        limits::Usage {
+            screen: self.vt.size().1 * line_bytes,
+            scrollback: self.vt.scrollback_len() * line_bytes,
+            images,
+            images_held,
+            images_evicted,
+            links: self.links.bytes(),
+            history: self.snapshots.bytes(),
+            buffers: self.snapshot_buf.capacity()
+                + self.responses.capacity()
+                + self.utf8_partial.capacity()
+                + self.traces.capacity() * std::mem::size_of::<u64>()
+                + self.events.capacity() * std::mem::size_of::<(u64, VtEvent)>()
+                + self.dirty_lines.capacity() * std::mem::size_of::<usize>()
+                + reported,
+        }
+    }
+
+    /// The visible screen, with any pending predictions over it and the
+    /// cursor as `set_cursor_policy` shows it.
+    pub fn screen(&self) -> Screen {
+        let linked = self.links.is_tracking();
+        let mut screen = if self.predictor.is_empty() && !linked {
+            Screen::capture(&self.vt, &self.line_attrs)
+        } else {
+            Screen::capture_with(&self.vt, &self.line_attrs, |row, cells| {
+                self.links.overlay(row, cells);
+                self.predictor.overlay(row, cells);
+            })
+        };
+        if linked {
+            screen.links = self.links.table(&screen);
+        }
+        #[cfg(feature = "images")]
+        if self.images.is_tracking() {
+            screen.images = self.images.table();
+        }
+        screen.cursor = self.shown_cursor();
+        screen.alt_screen = self.alt_screen;
+        if self.bold_as_bright {
+            themes::brighten_bold(&mut screen);
+        }
+        if let Some(theme) = &self.theme {
+            themes::resolve_indexed(&mut screen, theme);
+        }
+        screen
+    }
+
+    /// `screen` without the features the client didn't negotiate, for
+    /// what's sent to it.
+    fn sent_screen(&self) -> Screen {
+        let mut screen = self.screen();
+        self.wire.restrict(&mut screen);
+        screen
+    }
+
+    /// The cursor as `screen` has it.
+    fn shown_cursor(&self) -> snapshot::Cursor {
+        let mut cursor = self.predictor.cursor(self.vt.cursor());
+        cursor.visible |= self.forces_cursor();
+        cursor.shape = self.cursor_shape;
+        cursor.blink = self.cursor_blink;
+        cursor
+    }
+
+    /// 64-bit hash of the frame `screen` shows, see `framehash`; rows are
+    /// hashed again only once they change.
+    pub fn frame_hash(&mut self) -> u64 {
+        alloc_scope!(Encoder);
+        let (cols, rows) = self.vt.size();
+        let cursor = self.shown_cursor();
+        let mut header = Vec::new();
+        for value in [cols, rows, cursor.col, cursor.row] {
+            write_varint(&mut header, value);
+        }
+        header.push(snapshot::header_flags(&cursor, self.alt_screen));
+
+        let mut row_hashes = std::mem::take(&mut self.frame_rows);
+        row_hashes.invalidate(self.dirty_lines.iter().copied());
+        let mut cells = Vec::with_capacity(cols);
+        let hash = row_hashes.hash(&header, rows, |row| {
+            self.vt.row_cells(row, &mut cells);
+            self.links.overlay(row, &mut cells);
+            self.predictor.overlay(row, &mut cells);
+            snapshot::Line::of_cells(self.line_attrs.get(row), &cells)
+        });
+        self.frame_rows = row_hashes;
+        hash
+    }
+
+    /// The shift and row fades `protection` calls for at `now_ms`, see
+    /// `burnin`. Rows are compared with how they were at the last call, so
+    /// call it every ambient frame with a time that only moves forward.
+    pub fn burn_in(&mut self, protection: &burnin::Protection, now_ms: u64) -> burnin::Frame {
+        self.frame_hash();
+        self.row_ages.observe(self.frame_rows.rows(), now_ms);
+        let (dx, dy) = protection.offset(now_ms);
+        burnin::Frame {
+            margin: protection.shift_px as usize,
+            dx,
+            dy,
+            dims: self.row_ages.dims(protection, now_ms),
+        }
+    }
+
+    /// `modes` bits the program has set: bracketed paste, mouse reporting
+    /// and application keys.
+    pub fn modes(&self) -> u32 {
+        self.modes
+    }
+
+    /// Whether the alternate screen is shown.
+    pub fn alt_screen(&self) -> bool {
+        self.alt_screen
+    }
+
+    /// URI of the OSC 8 link at `row`, `col`, see `links`.
+    pub fn link_at(&self, row: usize, col: usize) -> Option<&str> {
+        self.links.at(row, col)
+    }
+
+    /// Pixels of inline image `id`, see `images`.
+    #[cfg(feature = "images")]
+    pub fn image(&mut self, id: u32) -> Option<&images::Image> {
+        self.images.get(id)
+    }
+
+    /// `image`, scaled to its cells at `cell_width` × `cell_height` pixels
+    /// each, see `images::scale`.
+    #[cfg(feature = "images")]
+    pub fn image_scaled(
+        &mut self,
+        id: u32,
+        cell_width: usize,
+        cell_height: usize,
+    ) -> Option<images::Image> {
+        self.images.get_scaled(id, cell_width, cell_height)
+    }
+
+    pub fn encode_snapshot(&self) -> Vec<u8> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Snapshot);
+        let mut buf = Vec::new();
+        self.wire.snapshot_header(&mut buf);
+        self.sent_screen().encode_into(&mut buf);
+        buf
+    }
+
+    /// `encode_snapshot` into a buffer kept between calls, for callers that
+    /// copy it out anyway (see `direct`).
+    pub fn encode_snapshot_reused(&mut self) -> &[u8] {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Snapshot);
+        let screen = self.sent_screen();
+        self.snapshot_buf.clear();
+        self.wire.snapshot_header(&mut self.snapshot_buf);
+        screen.encode_into(&mut self.snapshot_buf);
+        &self.snapshot_buf
+    }
+
+    /// Snapshot with styles as ids, starting a new style epoch, see `styles`.
+    pub fn encode_snapshot_interned(&mut self) -> Vec<u8> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Snapshot);
+        styles::encode_screen(&self.sent_screen(), &mut self.styles)
+    }
+
+    /// Rows `first_row..first_row + row_count` in the interned region form
+    /// (see `styles`); starts a new style epoch.
+    pub fn encode_snapshot_region(&mut self, first_row: usize, row_count: usize) -> Vec<u8> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Snapshot);
+        let rows = first_row..first_row.saturating_add(row_count);
+        styles::encode_region(&self.sent_screen(), rows, &mut self.styles)
+    }
+
+    /// The screen as a delta against a snapshot delta issued earlier, see
+    /// `delta`; an unknown `baseline_seq` (0 for none) gives every row.
+    pub fn snapshot_delta(&mut self, baseline_seq: u64) -> Vec<u8> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Snapshot);
+        let screen = self.sent_screen();
+        self.snapshots.delta(&screen, baseline_seq)
+    }
+
+    /// Register a consumer of the screen, see `fanout`; `None` if there
+    /// are too many.
+    pub fn add_consumer(&mut self) -> Option<u32> {
+        self.consumers.add()
+    }
+
+    pub fn remove_consumer(&mut self, id: u32) -> bool {
+        self.consumers.remove(id)
+    }
+
+    /// Consumer `id`'s next frame, `None` if the screen is unchanged since
+    /// its last one, mid synchronized update, or for an unknown id.
+    pub fn poll_consumer(&mut self, id: u32) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        if self
+            .sync_since
+            .is_some_and(|since| since.elapsed() < SYNC_TIMEOUT)
+        {
+            return None;
+        }
+        let screen = self.screen();
+        self.consumers.frame(id, &screen)
+    }
+
+    pub fn ack_consumer(&mut self, id: u32, seq: u64) -> bool {
+        self.consumers.ack(id, seq)
+    }
+
+    /// A reader of the screen for another thread, see `epoch`; `None` if
+    /// `epoch::MAX_READERS` are open.
+    pub fn open_reader(&mut self) -> Option<epoch::Reader<Screen>> {
+        if self.published.is_none() {
+            self.published = Some(epoch::Publisher::new(self.screen()));
+        }
+        self.published.as_ref()?.reader()
+    }
+
+    /// Give readers the screen, unless mid synchronized update; stop once
+    /// none are left.
+    fn publish(&mut self) {
+        if self.published.as_ref().is_some_and(|p| !p.has_readers()) {
+            self.published = None;
+        }
+        let syncing = self
+            .sync_since
+            .is_some_and(|since| since.elapsed() < SYNC_TIMEOUT);
+        if self.published.is_none() || syncing {
+            return;
+        }
+        let screen = self.screen();
+        if let Some(published) = &mut self.published {
+            published.publish(screen);
+        }
+    }
+
+    /// ANSI sequence that recreates the current screen in a fresh terminal.
+    pub fn dump_ansi(&self) -> String {
+        let mut out = self.vt.dump();
+
+        // avt doesn't know about line attributes, so re-apply them and
+        // put the cursor back where the dump left it
+        if self.line_attrs.is_tracking() {
+            let rows = self.vt.size().1;
+            for row in 0..rows {
+                if let Some(final_byte) = self.line_attrs.get(row).esc_final() {
+                    out.push_str(&format!("\x1b[{};1H\x1b#{}", row + 1, final_byte as char));
+                }
+            }
+            let cursor = self.vt.cursor();
+            out.push_str(&format!("\x1b[{};{}H", cursor.row + 1, cursor.col + 1));
+        }
+        if (self.cursor_shape, self.cursor_blink) != (CursorShape::Block, true) {
+            let style = 2 * self.cursor_shape as u8 + 1 + !self.cursor_blink as u8;
+            out.push_str(&format!("\x1b[{} q", style));
+        }
+        out.push_str(&modes::to_ansi(self.modes));
+
+        out
+    }
+
+    /// The visible screen and modes as JSON, see `Screen::to_json`.
+    pub fn dump_json(&self) -> String {
+        let modes = [
+            ("cursor_key_app", self.vt.modes().cursor_key_app),
+            ("synchronized_update", self.sync_since.is_some()),
+        ];
+        self.screen().to_json(&modes).to_string()
+    }
+
+    /// Multiplexer panes and status bar on the visible screen.
+    pub fn pane_layout(&self) -> panes::Layout {
+        panes::detect(&self.vt)
+    }
+
+    /// The visible screen cropped to `rect` (usually a detected pane).
+    pub fn region(&self, rect: panes::Rect) -> Screen {
+        panes::crop(&self.screen(), rect)
+    }
+
+    /// SHA-256 of the encoded snapshot; equal hashes mean identical screens.
+    pub fn state_hash(&self) -> [u8; 32] {
+        // The whole screen, whatever the client negotiated
+        Sha256::digest(self.screen().encode()).into()
+    }
+
+    pub fn poll_diff(&mut self) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Diff);
+        self.reported.clear();
+        let diff = self.take_diff()?.encode();
+        Some(self.wire.frame_diff(diff))
+    }
+
+    /// Like `poll_diff`, in the content form: changed rows and the cursor
+    /// come with the diff, so the client needs no snapshot to apply it.
+    pub fn poll_diff_content(&mut self) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Diff);
+        self.reported.clear();
+        let mut diff = self.take_diff()?;
+        let mut screen = self.sent_screen();
+        diff.lines.retain(|&row| row < screen.lines.len());
+        let lines = diff
+            .lines
+            .iter()
+            .map(|&row| std::mem::take(&mut screen.lines[row]))
+            .collect();
+        diff.content = Some(Content {
+            cols: screen.cols,
+            rows: screen.rows,
+            cursor: screen.cursor,
+            alt_screen: screen.alt_screen,
+            lines,
+            spans: None,
+        });
+        Some(self.wire.frame_diff(diff.encode()))
+    }
+
+    /// Like `poll_diff_content`, with only the lines that changed since the
+    /// last call and only their changed columns, see `diff`.
+    pub fn poll_diff_spans(&mut self) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Diff);
+        let diff = self.take_span_diff()?.encode();
+        Some(self.wire.frame_diff(diff))
+    }
+
+    /// `poll_diff_spans` with styles as ids, see `styles`.
+    pub fn poll_diff_interned(&mut self) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Diff);
+        let diff = self.take_span_diff()?;
+        if self.styles.len() > styles::MAX_STYLES {
+            self.styles.restart();
+        }
+        let diff = diff.encode_interned(&mut self.styles);
+        Some(self.wire.frame_diff(diff))
+    }
+
+    /// Like `poll_diff`, with the cells that changed since the last span or
+    /// damage poll as rectangles instead of whole rows, see `diff`. Rows
+    /// that were redrawn the same are left out.
+    pub fn poll_diff_damage(&mut self) -> Option<Vec<u8>> {
+        alloc_scope!(Encoder);
+        let _timer = self.perf.time(perf::Timing::Diff);
+        let mut diff = self.take_diff()?;
+        let screen = self.sent_screen();
+        let mut damage = Vec::new();
+        for &row in &diff.lines {
+            let Some(line) = screen.lines.get(row) else {
+                continue;
+            };
+            match self.reported.get(row) {
+                Some(old) => {
+                    let spans = diff::changed_spans(old, line, screen.cols);
+                    damage.extend(spans.into_iter().map(|span| (row, span)));
+                }
+                None => damage.push((row, 0..screen.cols)),
+            }
+        }
+
+        diff.lines = damage.iter().map(|(row, _)| *row).collect();
+        diff.lines.dedup();
+        diff.damage = Some(damage);
+        self.reported = screen.lines;
+        Some(self.wire.frame_diff(diff.encode()))
+    }
+
+    fn take_span_diff(&mut self) -> Option<Diff> {
+        let mut diff = self.take_diff()?;
+        let screen = self.sent_screen();
+        let mut rows = Vec::new();
+        let mut lines = Vec::new();
+        let mut spans = Vec::new();
+        for &row in &diff.lines {
+            let Some(line) = screen.lines.get(row) else {
+                continue;
+            };
+            let span = match self.reported.get(row) {
+                Some(old) => diff::changed_span(old, line, screen.cols),
+                None => Some(0..screen.cols),
+            };
+            if let Some(span) = span {
+                rows.push(row);
+                lines.push(diff::crop(line, &span));
+                spans.push(span);
+            }
+        }
+
+        diff.lines = rows;
+        diff.content = Some(Content {
+            cols: screen.cols,
+            rows: screen.rows,
+            cursor: screen.cursor,
+            alt_screen: screen.alt_screen,
+            lines,
+            spans: Some(spans),
+        });
+        self.reported = screen.lines;
+        Some(diff)
+    }
+
+    fn take_diff(&mut self) -> Option<Diff> {
+        // Hold back half-drawn frames until the app ends its update
+        if let Some(since) = self.sync_since {
+            if since.elapsed() < SYNC_TIMEOUT {
+                return None;
+            }
+            self.sync_since = None;
+        }
+        if self.mode == VtMode::Ticker {
+            self.cursor_changed = false;
+        }
+        if self.dirty_lines.is_empty()
+            && !self.cursor_changed
+            && !self.resized
+            && !self.screen_switched
+            && !self.modes_changed
+        {
+            if self.throttle.poll_compact(Instant::now()) {
+                self.compact();
+            }
+            return None;
+        }
+        if !self.throttle.take_diff(Instant::now()) {
+            return None;
+        }
+
+        let mut lines: Vec<_> = self.dirty_lines.iter().copied().collect();
+        lines.sort_unstable();
+        self.perf.note_dirty_lines(lines.len());
+        let mut cursor_style_changed = false;
+        if self.cursor_changed {
+            let visible = self.vt.cursor().visible || self.forces_cursor();
+            let style = Some((self.cursor_shape, self.cursor_blink, visible));
+            cursor_style_changed = style != self.reported_cursor_style;
+            self.reported_cursor_style = style;
+        }
+        #[cfg(feature = "images")]
+        let images = self.take_image_changes();
+        #[cfg(not(feature = "images"))]
+        let images = None;
+        let diff = Diff {
+            lines,
+            cursor_changed: self.cursor_changed,
+            cursor_style_changed,
+            resized: self.resized,
+            screen_switched: self.screen_switched,
+            modes_changed: self.modes_changed,
+            traces: std::mem::take(&mut self.traces),
+            content: None,
+            damage: None,
+            images,
+        };
+
+        // Clear dirty state
+        self.frame_rows.invalidate(self.dirty_lines.iter().copied());
+        self.dirty_lines.clear();
+        self.cursor_changed = false;
+        self.resized = false;
+        self.screen_switched = false;
+        self.modes_changed = false;
+
+        Some(diff)
+    }
+
+    /// How the images shown changed since the last diff, if they did and
+    /// the client takes them.
+    #[cfg(feature = "images")]
+    fn take_image_changes(&mut self) -> Option<Vec<diff::ImageChange>> {
+        let tracked = self.images.is_tracking() || !self.reported_images.is_empty();
+        if !tracked || !self.wire.has(protocol::FEATURE_IMAGES) {
+            return None;
+        }
+        let table = self.images.table();
+        if table == self.reported_images {
+            return None;
+        }
+        let changes = diff::image_changes(&self.reported_images, &table);
+        self.reported_images = table;
+        Some(changes)
+    }
+}
+
+/// `Some(true)`/`Some(false)` for DEC private mode 2026 (synchronized
+/// update) begin/end.
+fn sync_update(action: &scan::Action) -> Option<bool> {
+    let scan::Action::Csi(csi) = action else {
+        return None;
+    };
+    if csi.marker != Some(b'?') || !csi.params().contains(&2026) {
+        return None;
+    }
+    match csi.final_byte {
+        b'h' => Some(true),
+        b'l' => Some(false),
+        _ => None,
+    }
+}
+
+/// DECTCEM set: `CSI ? 25 h`
+fn shows_cursor(action: &scan::Action) -> bool {
+    matches!(action, scan::Action::Csi(csi)
+        if csi.marker == Some(b'?') && csi.final_byte == b'h' && csi.params().contains(&25))
+}
+
+/// `Some(true)` when `action` switches to the alternate screen (DEC private
+/// mode 47, 1047 or 1049 set), `Some(false)` when it switches back (the mode
+/// reset, or RIS).
+fn alt_screen_mode(action: &scan::Action) -> Option<bool> {
+    match action {
+        scan::Action::Csi(csi)
+            if csi.marker == Some(b'?')
+                && csi.params().iter().any(|p| matches!(p, 47 | 1047 | 1049)) =>
+        {
+            match csi.final_byte {
+                b'h' => Some(true),
+                b'l' => Some(false),
+                _ => None,
+            }
+        }
+        scan::Action::Esc {
+            intermediate: None,
+            final_byte: b'c',
+        } => Some(false),
+        _ => None,
+    }
+}
+
+/// Shape and blink set by DECSCUSR (`CSI Ps SP q`) or reset by RIS.
+fn cursor_style(action: &scan::Action) -> Option<(CursorShape, bool)> {
+    let ps = match action {
+        scan::Action::Csi(csi)
+            if csi.marker.is_none() && csi.intermediates() == b" " && csi.final_byte == b'q' =>
+        {
+            csi.params().first().copied().unwrap_or(0)
+        }
+        scan::Action::Esc {
+            intermediate: None,
+            final_byte: b'c',
+        } => 0,
+        _ => return None,
+    };
+    match ps {
+        0 | 1 => Some((CursorShape::Block, true)),
+        2 => Some((CursorShape::Block, false)),
+        3 => Some((CursorShape::Underline, true)),
+        4 => Some((CursorShape::Underline, false)),
+        5 => Some((CursorShape::Bar, true)),
+        6 => Some((CursorShape::Bar, false)),
+        _ => None,
+    }
+}
+
+/// Feed UTF-8 bytes to the VT. An incomplete sequence at the end is kept
+/// in `partial` and completed by the next call; invalid bytes become U+FFFD.
+fn feed_utf8(vt: &mut impl TerminalBackend, partial: &mut Vec<u8>, bytes: &[u8]) {
+    alloc_scope!(Grid);
+    decode_utf8(partial, bytes, |text| vt.feed_str(text));
+}
+
+/// Decode a chunk of a UTF-8 stream into `out`, as for `feed_utf8`.
+pub(crate) fn decode_utf8(partial: &mut Vec<u8>, mut bytes: &[u8], mut out: impl FnMut(&str)) {
+    if let Some(&lead) = partial.first() {
+        let needed = match lead {
+            0xf0..=0xf7 => 4,
+            0xe0..=0xef => 3,
+            _ => 2,
+        };
+        while partial.len() < needed {
+            match bytes.first() {
+                Some(&b) if b & 0xc0 == 0x80 => {
+                    partial.push(b);
+                    bytes = &bytes[1..];
+                }
+                Some(_) => break,
+                None => return,
+            }
+        }
+        out(&String::from_utf8_lossy(partial));
+        partial.clear();
+    }
+
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                out(text);
+                return;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                out(std::str::from_utf8(valid).unwrap_or_default());
+                match e.error_len() {
+                    None => {
+                        partial.extend_from_slice(rest);
+                        return;
+                    }
+                    Some(len) => {
+                        out("\u{FFFD}");
+                        bytes = &rest[len..];
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,748 @@
+//! Terminal emulator abstraction.
+//!
+//! The wrapper (dirty tracking, line attributes, snapshots, JNI) talks to
+//! the emulator only through `TerminalBackend`, so avt can be swapped for
+//! another emulator, or a minimal parser for the streaming server, without
+//! touching those layers.
+
+use crate::snapshot::{self, Color, Cursor, Style};
+use avt::Vt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+    /// Columns `ch` takes: 1, or 2 for a wide char, whose second column
+    /// is a cell of width 0
+    pub width: u8,
+    /// OSC 8 link id, 0 for none (see `links`)
+    pub link: u32,
+}
+
+/// Terminal modes clients care about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modes {
+    /// DECCKM: cursor keys send `ESC O` instead of `ESC [`
+    pub cursor_key_app: bool,
+}
+
+/// How much scrollback to keep; with both limits, the tighter one holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Retention {
+    pub max_lines: Option<usize>,
+    /// Approximate memory taken by the lines' cells, at the width when set
+    pub max_bytes: Option<usize>,
+}
+
+pub trait TerminalBackend {
+    /// Feed decoded text, escape sequences included.
+    fn feed_str(&mut self, text: &str);
+
+    /// Resize, rewrapping soft-wrapped lines if the backend reflows.
+    fn resize(&mut self, cols: usize, rows: usize);
+
+    /// Resize without reflowing: each row is cut or padded to `cols`, a
+    /// soft-wrapped row parting from its continuation. Backends whose
+    /// `resize` doesn't reflow needn't override this.
+    fn resize_clipped(&mut self, cols: usize, rows: usize) {
+        self.resize(cols, rows);
+    }
+
+    /// Discard all state and start over at the given size.
+    fn reset(&mut self, cols: usize, rows: usize);
+
+    /// `(cols, rows)`
+    fn size(&self) -> (usize, usize);
+
+    fn cursor(&self) -> Cursor;
+
+    /// Replace `out` with the cells of visible row `row`, left to right.
+    fn row_cells(&self, row: usize, out: &mut Vec<Cell>);
+
+    /// Text of visible row `row`, trailing blanks included.
+    fn row_text(&self, row: usize) -> String {
+        let mut cells = Vec::new();
+        self.row_cells(row, &mut cells);
+        cells.iter().map(|c| c.ch).collect()
+    }
+
+    fn modes(&self) -> Modes;
+
+    /// ANSI sequence that recreates the screen in a fresh instance.
+    fn dump(&self) -> String;
+
+    /// Lines kept above the visible screen.
+    fn scrollback_len(&self) -> usize {
+        0
+    }
+
+    /// Like `row_cells`, for scrollback line `index`, 0 being the oldest.
+    fn scrollback_cells(&self, index: usize, out: &mut Vec<Cell>) {
+        let _ = index;
+        out.clear();
+    }
+
+    /// Approximate memory taken by a line's cells, screen or scrollback,
+    /// at the current width.
+    fn line_bytes(&self) -> usize {
+        self.size().0 * std::mem::size_of::<Cell>()
+    }
+
+    /// Limit the scrollback kept from now on. Backends without scrollback
+    /// ignore this; others may drop what they have kept so far.
+    fn set_retention(&mut self, retention: Retention) {
+        let _ = retention;
+    }
+
+    /// The output is about to switch to the alternate screen (`true`) or
+    /// back. Only the primary screen has scrollback, so a backend that
+    /// hides it while the alternate screen is shown keeps it available to
+    /// `scrollback_cells` until the switch back.
+    fn switch_screen(&mut self, alternate: bool) {
+        let _ = alternate;
+    }
+
+    /// Put `lines` (ANSI for one line each, see `push_clipped`), oldest
+    /// first, into the scrollback of a backend just reset, before its
+    /// screen is fed. By default they're printed and scrolled off the top,
+    /// leaving the screen blank with the cursor home.
+    fn restore_scrollback(&mut self, lines: &[String]) {
+        let rows = self.size().1;
+        if lines.is_empty() || rows == 0 {
+            return;
+        }
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line);
+            text.push_str("\r\n");
+        }
+        text.extend(std::iter::repeat_n('\n', rows - 1));
+        text.push_str("\x1b[H");
+        self.feed_str(&text);
+    }
+}
+
+/// The default backend: upstream avt.
+pub struct AvtBackend {
+    vt: Vt,
+    scrollback_limit: Option<usize>,
+    /// The primary screen's scrollback while the alternate screen is
+    /// shown, when avt keeps it out of `lines`. Frozen: no line scrolls
+    /// into it until the switch back.
+    primary_scrollback: Option<Vec<avt::Line>>,
+}
+
+impl AvtBackend {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self::with_scrollback_limit(cols, rows, None)
+    }
+
+    /// `None` keeps avt's default (unbounded) scrollback.
+    pub fn with_scrollback_limit(cols: usize, rows: usize, limit: Option<usize>) -> Self {
+        let mut builder = Vt::builder();
+        builder.size(cols, rows);
+        if let Some(limit) = limit {
+            builder.scrollback_limit(limit);
+        }
+        AvtBackend {
+            vt: builder.build(),
+            scrollback_limit: limit,
+            primary_scrollback: None,
+        }
+    }
+
+    fn line(&self, row: usize) -> Option<&avt::Line> {
+        self.vt.view().nth(row)
+    }
+
+    fn cells_of(line: &avt::Line, out: &mut Vec<Cell>) {
+        out.clear();
+        // avt keeps a wide char in its first column and a blank in the
+        // second
+        let mut spilled = false;
+        out.extend(line.cells().iter().map(|cell| {
+            let width = if spilled { 0 } else { cell.width().clamp(1, 2) as u8 };
+            spilled = width == 2;
+            Cell {
+                ch: cell.char(),
+                style: style_of(cell.pen()),
+                width,
+                link: 0,
+            }
+        }));
+    }
+}
+
+impl TerminalBackend for AvtBackend {
+    fn feed_str(&mut self, text: &str) {
+        self.vt.feed_str(text);
+    }
+
+    fn resize(&mut self, cols: usize, rows: usize) {
+        self.vt.feed_str(&format!("\x1b[8;{};{}t", rows, cols));
+    }
+
+    /// avt always reflows, so this rebuilds the VT from its lines, each
+    /// cut to `cols` and ending in CRLF, up to the cursor or the last row
+    /// with text: the bottom rows scroll into the scrollback if they no
+    /// longer fit, and scrollback comes back into view if there are rows
+    /// to spare. The cursor moves with its row; its visibility and DECCKM
+    /// are kept, while the pen and other modes start over. The alternate
+    /// screen, which programs redraw on resize anyway, is reflowed.
+    fn resize_clipped(&mut self, cols: usize, rows: usize) {
+        if self.primary_scrollback.is_some() || cols == 0 || rows == 0 {
+            self.resize(cols, rows);
+            return;
+        }
+
+        let cursor = self.vt.cursor();
+        let scrollback = self.scrollback_len();
+        let last_text = self.vt.view().enumerate()
+            .filter(|(_, line)| !line.text().trim_end().is_empty())
+            .last()
+            .map_or(0, |(row, _)| row);
+        let count = scrollback + last_text.max(cursor.row) + 1;
+
+        let mut repaint = String::new();
+        let mut cells = Vec::new();
+        for (index, line) in self.vt.lines().take(count).enumerate() {
+            AvtBackend::cells_of(line, &mut cells);
+            push_clipped(&cells, cols, &mut repaint);
+            if index + 1 < count {
+                repaint.push_str("\r\n");
+            }
+        }
+        // Text below the cursor may push its row out of view
+        let row = (scrollback + cursor.row).saturating_sub(count.saturating_sub(rows));
+        repaint.push_str(&format!("\x1b[{};{}H", row + 1, cursor.col.min(cols - 1) + 1));
+        if !cursor.visible {
+            repaint.push_str("\x1b[?25l");
+        }
+        if self.vt.cursor_key_app_mode() {
+            repaint.push_str("\x1b[?1h");
+        }
+
+        *self = AvtBackend::with_scrollback_limit(cols, rows, self.scrollback_limit);
+        self.vt.feed_str(&repaint);
+    }
+
+    fn reset(&mut self, cols: usize, rows: usize) {
+        *self = AvtBackend::with_scrollback_limit(cols, rows, self.scrollback_limit);
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.vt.size()
+    }
+
+    fn cursor(&self) -> Cursor {
+        let cursor = self.vt.cursor();
+        Cursor {
+            col: cursor.col,
+            row: cursor.row,
+            visible: cursor.visible,
+            ..Cursor::default()
+        }
+    }
+
+    fn row_cells(&self, row: usize, out: &mut Vec<Cell>) {
+        match self.line(row) {
+            Some(line) => AvtBackend::cells_of(line, out),
+            None => out.clear(),
+        }
+    }
+
+    fn row_text(&self, row: usize) -> String {
+        self.line(row).map(|line| line.text()).unwrap_or_default()
+    }
+
+    fn modes(&self) -> Modes {
+        Modes {
+            cursor_key_app: self.vt.cursor_key_app_mode(),
+        }
+    }
+
+    fn dump(&self) -> String {
+        self.vt.dump()
+    }
+
+    fn scrollback_len(&self) -> usize {
+        match &self.primary_scrollback {
+            Some(lines) => lines.len(),
+            None => self.vt.lines().count().saturating_sub(self.vt.size().1),
+        }
+    }
+
+    fn scrollback_cells(&self, index: usize, out: &mut Vec<Cell>) {
+        let line = match &self.primary_scrollback {
+            Some(lines) => lines.get(index),
+            None => self.vt.lines().take(self.scrollback_len()).nth(index),
+        };
+        match line {
+            Some(line) => AvtBackend::cells_of(line, out),
+            None => out.clear(),
+        }
+    }
+
+    /// avt fixes the limit when it's built, so a new limit rebuilds the VT
+    /// from a dump: the screen stays and the scrollback is dropped.
+    fn line_bytes(&self) -> usize {
+        self.size().0.max(1) * std::mem::size_of::<avt::Cell>()
+    }
+
+    fn set_retention(&mut self, retention: Retention) {
+        let (cols, rows) = self.size();
+        let line_bytes = self.line_bytes();
+        let limit = match (retention.max_lines, retention.max_bytes) {
+            (lines, None) => lines,
+            (None, Some(bytes)) => Some(bytes / line_bytes),
+            (Some(lines), Some(bytes)) => Some(lines.min(bytes / line_bytes)),
+        };
+        if limit == self.scrollback_limit {
+            return;
+        }
+
+        let dump = self.vt.dump();
+        *self = AvtBackend::with_scrollback_limit(cols, rows, limit);
+        self.vt.feed_str(&dump);
+    }
+
+    /// avt gives the alternate screen a buffer of its own without
+    /// scrollback and hides the primary one, so copy the primary's
+    /// scrollback on the way in (the retention limits bound it).
+    fn switch_screen(&mut self, alternate: bool) {
+        self.primary_scrollback = None;
+        if alternate {
+            let lines = self.vt.lines().take(self.scrollback_len());
+            self.primary_scrollback = Some(lines.cloned().collect());
+        }
+    }
+}
+
+/// Print the cells that fit in `cols`, blanking a wide char that would
+/// wrap, and trailing unstyled blanks left out.
+pub(crate) fn push_clipped(cells: &[Cell], cols: usize, out: &mut String) {
+    let end = cells.iter().rposition(|c| c.ch != ' ' || c.style != Style::default());
+    let mut style = Style::default();
+    for (col, cell) in cells[..end.map_or(0, |end| end + 1)].iter().enumerate() {
+        if col >= cols {
+            break;
+        }
+        if cell.width == 0 {
+            continue;
+        }
+        if cell.style != style {
+            style = cell.style;
+            style.push_sgr(out);
+        }
+        out.push(if col + cell.width as usize > cols { ' ' } else { cell.ch });
+    }
+    if style != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+}
+
+fn style_of(pen: &avt::Pen) -> Style {
+    let mut attrs = 0;
+    if pen.is_bold() { attrs |= snapshot::ATTR_BOLD; }
+    if pen.is_italic() { attrs |= snapshot::ATTR_ITALIC; }
+    if pen.is_underline() { attrs |= snapshot::ATTR_UNDERLINE; }
+    if pen.is_strikethrough() { attrs |= snapshot::ATTR_STRIKETHROUGH; }
+    if pen.is_blink() { attrs |= snapshot::ATTR_BLINK; }
+    if pen.is_inverse() { attrs |= snapshot::ATTR_INVERSE; }
+    if pen.is_faint() { attrs |= snapshot::ATTR_FAINT; }
+
+    Style {
+        fg: color_of(pen.foreground()),
+        bg: color_of(pen.background()),
+        attrs,
+    }
+}
+
+fn color_of(color: Option<avt::Color>) -> Color {
+    match color {
+        Some(avt::Color::Indexed(idx)) => Color::Indexed(idx),
+        Some(avt::Color::RGB(rgb)) => Color::Rgb(rgb.r, rgb.g, rgb.b),
+        None => Color::Default,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::lineattr::LineAttr;
+    use crate::cast::{Cast, EventKind};
+    use crate::config::TermConfig;
+    use crate::width::{char_width, str_width};
+    use crate::{diff, AvtState, CursorPolicy, VtMode};
+
+    /// Prints text on row 0 and ignores escape sequences other than
+    /// DECTCEM, and OSC strings.
+    pub(crate) struct FakeBackend {
+        cols: usize,
+        rows: usize,
+        text: String,
+        visible: bool,
+        /// Set by tests; never fed
+        pub(crate) scrollback: Vec<String>,
+        /// As of the last `switch_screen`
+        alternate: bool,
+    }
+
+    impl TerminalBackend for FakeBackend {
+        fn feed_str(&mut self, text: &str) {
+            match (text.rfind("\x1b[?25h"), text.rfind("\x1b[?25l")) {
+                (Some(show), hide) if hide < Some(show) => self.visible = true,
+                (_, Some(_)) => self.visible = false,
+                _ => {}
+            }
+            let (mut in_escape, mut in_osc) = (false, false);
+            let mut prev = '\0';
+            for ch in text.chars() {
+                let after_esc = std::mem::replace(&mut prev, ch) == '\x1b';
+                match ch {
+                    _ if in_osc => in_osc = !(ch == '\x07' || after_esc && ch == '\\'),
+                    '\x1b' => in_escape = true,
+                    ']' if in_escape && after_esc => (in_escape, in_osc) = (false, true),
+                    _ if in_escape => in_escape = !ch.is_ascii_alphanumeric(),
+                    _ if str_width(&self.text) + char_width(ch) <= self.cols => self.text.push(ch),
+                    _ => {}
+                }
+            }
+        }
+
+        fn resize(&mut self, cols: usize, rows: usize) {
+            self.cols = cols;
+            self.rows = rows;
+        }
+
+        fn reset(&mut self, cols: usize, rows: usize) {
+            self.resize(cols, rows);
+            self.text.clear();
+            self.visible = true;
+        }
+
+        fn size(&self) -> (usize, usize) {
+            (self.cols, self.rows)
+        }
+
+        fn cursor(&self) -> Cursor {
+            Cursor {
+                col: str_width(&self.text),
+                row: 0,
+                visible: self.visible,
+                ..Cursor::default()
+            }
+        }
+
+        fn row_cells(&self, row: usize, out: &mut Vec<Cell>) {
+            let text = if row == 0 { self.text.as_str() } else { "" };
+            cells_of_text(text, out);
+            let blank = Cell {
+                ch: ' ',
+                style: Style::default(),
+                width: 1,
+                link: 0,
+            };
+            out.extend(std::iter::repeat_n(blank, self.cols.saturating_sub(out.len())));
+        }
+
+        fn modes(&self) -> Modes {
+            Modes::default()
+        }
+
+        fn dump(&self) -> String {
+            self.text.clone()
+        }
+
+        fn scrollback_len(&self) -> usize {
+            self.scrollback.len()
+        }
+
+        fn scrollback_cells(&self, index: usize, out: &mut Vec<Cell>) {
+            out.clear();
+            if let Some(text) = self.scrollback.get(index) {
+                cells_of_text(text, out);
+            }
+        }
+
+        fn set_retention(&mut self, retention: Retention) {
+            if let Some(max) = retention.max_lines {
+                let excess = self.scrollback.len().saturating_sub(max);
+                self.scrollback.drain(..excess);
+            }
+        }
+
+        fn switch_screen(&mut self, alternate: bool) {
+            self.alternate = alternate;
+        }
+
+        fn restore_scrollback(&mut self, lines: &[String]) {
+            for line in lines {
+                let mut printed = fake(self.cols, 1);
+                printed.feed_str(line);
+                self.scrollback.push(printed.text);
+            }
+        }
+    }
+
+    /// Replace `out` with unstyled cells of `text`, laid out as an
+    /// emulator would: zero-width chars dropped, wide ones spilling into
+    /// a second column.
+    fn cells_of_text(text: &str, out: &mut Vec<Cell>) {
+        out.clear();
+        for ch in text.chars() {
+            let width = char_width(ch) as u8;
+            let cell = |ch, width| Cell {
+                ch,
+                style: Style::default(),
+                width,
+                link: 0,
+            };
+            match width {
+                0 => {}
+                1 => out.push(cell(ch, 1)),
+                _ => out.extend([cell(ch, 2), cell(' ', 0)]),
+            }
+        }
+    }
+
+    pub(crate) fn fake(cols: usize, rows: usize) -> FakeBackend {
+        FakeBackend {
+            cols,
+            rows,
+            text: String::new(),
+            visible: true,
+            scrollback: Vec::new(),
+            alternate: false,
+        }
+    }
+
+    #[test]
+    fn wrapper_runs_on_any_backend() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.feed(b"\x1b#6h\xc3");
+        state.feed(b"\xa9");
+
+        let screen = snapshot::decode(&state.encode_snapshot()).unwrap();
+        assert_eq!((screen.cols, screen.rows), (4, 2));
+        assert_eq!(screen.cursor.col, 2);
+        assert_eq!(screen.lines[0].attr, LineAttr::DoubleWidth);
+        assert_eq!(screen.lines[0].runs[0].text, "h\u{e9}  ");
+        assert_eq!(state.backend().row_text(1), "    ");
+    }
+
+    #[test]
+    fn ticker_mode_never_reports_cursor() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.mode = VtMode::Ticker;
+        state.poll_diff();
+        assert_eq!(state.poll_diff(), None);
+
+        state.feed(b"x");
+        let diff = diff::decode(&state.poll_diff().unwrap()).unwrap();
+        assert!(!diff.cursor_changed);
+        assert_eq!(diff.lines, vec![0, 1]);
+    }
+
+    #[test]
+    fn cursor_motion_leaves_lines_clean() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.feed(b"ab");
+        state.poll_diff();
+
+        state.feed(b"\x1b[1;1H\r\x1b[?25h");
+        assert_eq!(state.poll_diff(), Some(vec![2]));
+
+        state.feed(b"\x1b[Hc");
+        assert_eq!(diff::decode(&state.poll_diff().unwrap()).unwrap().lines, vec![0, 1]);
+    }
+
+    #[test]
+    fn invalidated_rows_are_sent_again() {
+        let mut state = AvtState::with_backend(fake(4, 3));
+        state.feed(b"ab");
+        state.poll_diff_damage();
+        assert_eq!(state.poll_diff(), None);
+
+        state.invalidate(Some(1..9));
+        assert_eq!(diff::decode(&state.poll_diff().unwrap()).unwrap().lines, vec![1, 2]);
+        state.invalidate(Some(0..1));
+        let diff = diff::decode(&state.poll_diff_damage().unwrap()).unwrap();
+        assert_eq!(diff.damage.unwrap(), [(0, 0..4)]);
+        state.invalidate(None);
+        assert_eq!(diff::decode(&state.poll_diff().unwrap()).unwrap().lines, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn compaction_frees_what_a_burst_left() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        let mut title = b"\x1b]0;".to_vec();
+        title.resize(10_000, b'x');
+        state.feed(&title);
+        state.feed(b"\x07");
+        state.encode_snapshot_reused();
+
+        assert!(state.compact() >= 10_000);
+        assert_eq!(state.compact(), 0);
+        assert!(!state.encode_snapshot_reused().is_empty());
+    }
+
+    #[test]
+    fn synchronized_update_withholds_diffs() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.poll_diff();
+
+        state.feed(b"\x1b[?2026hab");
+        assert_eq!(state.poll_diff(), None);
+        state.feed(b"c\x1b[?2026l");
+        assert_eq!(diff::decode(&state.poll_diff().unwrap()).unwrap().lines, vec![0, 1]);
+
+        // An update that never ends is released after the timeout
+        state.feed(b"\x1b[?2026hd");
+        assert_eq!(state.poll_diff(), None);
+        state.sync_since = crate::Instant::now().checked_sub(crate::avt_state::SYNC_TIMEOUT);
+        assert!(state.poll_diff().is_some());
+        assert_eq!(state.sync_since, None);
+    }
+
+    #[test]
+    fn resize_waits_for_split_sequence() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.feed(b"ab\x1b[");
+        state.resize(6, 3);
+        state.resize(8, 3);
+        assert_eq!(state.backend().size(), (4, 2));
+
+        state.feed(b"1mc");
+        assert_eq!(state.backend().size(), (8, 3));
+
+        let diff = diff::decode(&state.poll_diff().unwrap()).unwrap();
+        assert!(diff.resized);
+        assert_eq!(diff.lines, vec![0, 1, 2]);
+        assert_eq!(state.poll_diff(), None);
+    }
+
+    #[test]
+    fn update_budget_holds_back_changes() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.set_update_budget(1);
+        assert!(state.poll_diff().is_some());
+
+        state.feed(b"x");
+        assert_eq!(state.poll_diff(), None);
+        assert_eq!(state.dirty_lines.len(), 2);
+
+        state.set_update_budget(0);
+        assert!(state.poll_diff().is_some());
+    }
+
+    #[test]
+    fn padding_only_feeds_produce_no_diff() {
+        // NUL padding and XON/XOFF between lines, as serial consoles send
+        let cast = Cast::parse(include_bytes!("../fixtures/casts/nul_padding.cast")).unwrap();
+        let mut state = AvtState::with_backend(fake(20, 4));
+        state.poll_diff();
+
+        let mut diffs = 0;
+        for event in &cast.events {
+            if let EventKind::Output(text) = &event.kind {
+                state.feed(text.as_bytes());
+                diffs += state.poll_diff().is_some() as usize;
+            }
+        }
+        // Only the three events with text in them
+        assert_eq!(diffs, 3);
+        assert_eq!(state.traffic().ignored(), 38);
+    }
+
+    #[test]
+    fn cursor_policy_overrides_hidden_cursor_in_playback_only() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.set_cursor_policy(CursorPolicy::Auto);
+        state.feed(b"\x1b[?25l$ ");
+        assert!(state.screen().cursor.visible);
+
+        // Interactive sessions show the real state
+        state.set_config(Some(TermConfig::default()));
+        assert!(!state.screen().cursor.visible);
+        state.set_config(None);
+
+        // Once the recording shows the cursor, Auto trusts it
+        state.feed(b"\x1b[?25h\x1b[?25l");
+        assert!(!state.screen().cursor.visible);
+        state.set_cursor_policy(CursorPolicy::Always);
+        assert!(state.screen().cursor.visible);
+    }
+
+    #[test]
+    fn cursor_style_changes_are_flagged_once() {
+        let mut state = AvtState::with_backend(fake(8, 2));
+        state.poll_diff();
+
+        state.feed(b"\x1b[6 q");
+        let cursor = state.screen().cursor;
+        assert_eq!((cursor.shape, cursor.blink), (snapshot::CursorShape::Bar, false));
+        assert!(state.dump_ansi().ends_with("\x1b[6 q"));
+        assert!(diff::decode(&state.poll_diff().unwrap()).unwrap().cursor_style_changed);
+        state.feed(b"x");
+        assert!(!diff::decode(&state.poll_diff().unwrap()).unwrap().cursor_style_changed);
+        state.feed(b"\x1b[?25l");
+        assert!(diff::decode(&state.poll_diff().unwrap()).unwrap().cursor_style_changed);
+
+        state.feed(b"\x1bc");
+        assert_eq!(state.screen().cursor.shape, snapshot::CursorShape::Block);
+        assert!(state.screen().cursor.blink);
+    }
+
+    #[test]
+    fn traces_ride_the_first_diff_showing_the_feed() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.poll_diff();
+
+        state.feed_traced(b"\x1b[?2026ha", 1);
+        state.feed_traced(b"b\x1b[?2026l", 2);
+        let diff = diff::decode(&state.poll_diff().unwrap()).unwrap();
+        assert_eq!(diff.traces, [1, 2]);
+
+        state.feed(b"c");
+        assert!(diff::decode(&state.poll_diff().unwrap()).unwrap().traces.is_empty());
+    }
+
+    #[test]
+    fn alternate_screen_switches_are_flagged() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.poll_diff();
+
+        state.feed(b"\x1b[?1049h");
+        assert!(state.alt_screen() && state.backend().alternate);
+        let diff = diff::decode(&state.poll_diff().unwrap()).unwrap();
+        assert!(diff.screen_switched && !diff.resized);
+        assert!(snapshot::decode(&state.encode_snapshot()).unwrap().alt_screen);
+
+        // Already there: no switch
+        state.feed(b"\x1b[?47h");
+        assert!(!diff::decode(&state.poll_diff().unwrap()).unwrap().screen_switched);
+
+        state.feed(b"\x1b[?1049l");
+        let diff = diff::decode(&state.poll_diff_content().unwrap()).unwrap();
+        assert!(diff.screen_switched && !diff.content.unwrap().alt_screen);
+        assert!(!state.backend().alternate);
+    }
+
+    #[test]
+    fn clipped_rows_keep_what_fits() {
+        let mut cells = Vec::new();
+        cells_of_text("a漢b  ", &mut cells);
+        cells[4].style.attrs = snapshot::ATTR_BOLD;
+        let clipped = |cols| {
+            let mut out = String::new();
+            push_clipped(&cells, cols, &mut out);
+            out
+        };
+
+        // Trailing blanks are left out; a wide char that would wrap is blanked
+        assert_eq!(clipped(8), "a漢b\x1b[0;1m \x1b[0m");
+        assert_eq!(clipped(4), "a漢b");
+        assert_eq!(clipped(2), "a ");
+    }
+}
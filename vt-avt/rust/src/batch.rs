@@ -0,0 +1,153 @@
+//! Batched feeds: many output events in one JNI call.
+//!
+//! At high playback speeds a frame can cover hundreds of output events,
+//! and one `vtFeed` each is mostly JNI overhead. `vtFeedBatch` takes them
+//! packed into one array and returns the diff they add up to:
+//!
+//! ```text
+//! batch  := flags:u8 record*
+//! record := delta_us? len bytes:[u8; len]
+//! ```
+//!
+//! Records run to the end of the array. With `FLAG_TIMED` set each record
+//! starts with the microseconds since the previous one (the first's is
+//! ignored), and the throughput stats count the records spread out over
+//! that span up to now, rather than as one burst; screen state is the same
+//! either way.
+
+use crate::backend::TerminalBackend;
+use crate::snapshot::{DecodeError, Reader};
+use crate::{handles, AvtState, Instant, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::JNIEnv;
+use std::time::Duration;
+
+/// Records carry a time delta
+pub const FLAG_TIMED: u8 = 0x01;
+
+/// One record: microseconds since the previous one, and its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub delta_us: u64,
+    pub bytes: &'a [u8],
+}
+
+/// Split a batch into its records, under the rules of `snapshot::decode`.
+/// Untimed records have a delta of 0.
+pub fn decode(batch: &[u8]) -> Result<Vec<Record<'_>>, DecodeError> {
+    let mut r = Reader::new(batch);
+    let timed = r.byte()? & FLAG_TIMED != 0;
+    let mut records = Vec::new();
+    while r.remaining() > 0 {
+        let delta_us = if timed { r.varint()? as u64 } else { 0 };
+        let len = r.varint()?;
+        records.push(Record {
+            delta_us,
+            bytes: r.take(len)?,
+        });
+    }
+    Ok(records)
+}
+
+/// Feed every record of `batch`, or none if it is malformed. The last
+/// record counts as arriving `now`. Returns the records fed.
+pub fn feed<B: TerminalBackend>(
+    vt: &mut AvtState<B>,
+    batch: &[u8],
+    now: Instant,
+) -> Result<usize, DecodeError> {
+    let records = decode(batch)?;
+    let mut behind: u64 = records.iter().skip(1).map(|r| r.delta_us).sum();
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            behind -= record.delta_us;
+        }
+        let at = now
+            .checked_sub(Duration::from_micros(behind))
+            .unwrap_or(now);
+        vt.feed_at(record.bytes, at);
+    }
+    Ok(records.len())
+}
+
+// JNI functions
+
+/// Feed a batch (see module docs) and return the diff it produced, in the
+/// `vtPollDiffContent` form. Empty for an invalid handle or malformed batch, or
+/// when there is no diff to report (nothing changed, or one is held back
+/// by the update budget or a synchronized update; poll for it as usual).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFeedBatch<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    batch: JByteArray<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+        let Ok(batch) = env.convert_byte_array(batch) else {
+            return JByteArray::default();
+        };
+
+        if feed(vt, &batch, Instant::now()).is_err() {
+            return JByteArray::default();
+        }
+        match vt.poll_diff_content() {
+            Some(diff) => env.byte_array_from_slice(&diff).unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn feeds_every_record_or_none() {
+        let mut vt = AvtState::with_backend(fake(8, 1));
+        vt.poll_diff();
+
+        let batch = [0, 2, b'a', b'b', 0, 1, b'c'];
+        assert_eq!(feed(&mut vt, &batch, Instant::now()), Ok(3));
+        assert_eq!(vt.backend().row_text(0), "abc     ");
+        assert!(vt.poll_diff().is_some());
+
+        // Truncated in its last record, so nothing is fed
+        let batch = [FLAG_TIMED, 0, 1, b'd', 10, 2, b'e'];
+        assert_eq!(
+            feed(&mut vt, &batch, Instant::now()),
+            Err(DecodeError::Truncated { offset: 7 })
+        );
+        assert_eq!(vt.backend().row_text(0), "abc     ");
+        assert_eq!(vt.poll_diff(), None);
+    }
+
+    #[test]
+    fn timed_records_are_counted_over_their_span() {
+        let batch = [FLAG_TIMED, 5, 1, b'a', 0x80, 0x89, 0x7a, 1, b'b'];
+        assert_eq!(
+            decode(&batch).unwrap(),
+            [
+                Record {
+                    delta_us: 5,
+                    bytes: b"a"
+                },
+                Record {
+                    delta_us: 2_000_000,
+                    bytes: b"b"
+                },
+            ]
+        );
+
+        let mut vt = AvtState::with_backend(fake(8, 1));
+        let now = Instant::now() + Duration::from_secs(2);
+        feed(&mut vt, &batch, now).unwrap();
+        let history = vt.traffic().history(now);
+        let bytes: Vec<_> = history.iter().rev().map(|b| b.bytes).take(3).collect();
+        assert_eq!(bytes, [1, 0, 1]);
+    }
+}
@@ -0,0 +1,297 @@
+//! vtdbg: feed a cast or raw bytes through the wrapper and print the
+//! snapshots/diffs the Kotlin side would receive.
+//!
+//! Usage: vtdbg [options] <file|->
+//!   --raw           treat input as raw terminal bytes instead of a .cast
+//!   --size COLSxROWS  terminal size for raw input (default 80x24)
+//!   --diffs         print the diff polled after every output event
+//!   --dump DIR      write snapshot.bin and diff-NNNNNN.bin payloads to DIR
+//!   --json          print the final screen as JSON instead of the snapshot
+//!                   listing, for piping into other tools
+//!   --compare       compare against alacritty_terminal after every event
+//!                   (needs the `differential` feature)
+
+use asciicast_vt_avt::cast::{self, Cast, EventKind};
+use asciicast_vt_avt::diff;
+use asciicast_vt_avt::snapshot::{self, Color, ATTR_NAMES, LINE_ATTR_NAMES};
+use asciicast_vt_avt::{to_hex, AvtState};
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+struct Options {
+    input: String,
+    raw: bool,
+    size: (usize, usize),
+    diffs: bool,
+    dump: Option<PathBuf>,
+    json: bool,
+    compare: bool,
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: vtdbg [--raw] [--size COLSxROWS] [--diffs] [--dump DIR] [--json] [--compare] <file|->");
+    ExitCode::from(2)
+}
+
+fn parse_args() -> Option<Options> {
+    let mut options = Options {
+        input: String::new(),
+        raw: false,
+        size: (80, 24),
+        diffs: false,
+        dump: None,
+        json: false,
+        compare: false,
+    };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--raw" => options.raw = true,
+            "--diffs" => options.diffs = true,
+            "--json" => options.json = true,
+            "--compare" => options.compare = true,
+            "--dump" => options.dump = Some(PathBuf::from(args.next()?)),
+            "--size" => {
+                let value = args.next()?;
+                let (cols, rows) = value.split_once('x')?;
+                options.size = (cols.parse().ok()?, rows.parse().ok()?);
+            }
+            _ if arg.starts_with("--") => return None,
+            _ if options.input.is_empty() => options.input = arg,
+            _ => return None,
+        }
+    }
+
+    if options.input.is_empty() {
+        None
+    } else {
+        Some(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(options) = parse_args() else {
+        return usage();
+    };
+
+    let bytes = if options.input == "-" {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf).map(|_| buf)
+    } else {
+        fs::read(&options.input)
+    };
+    let bytes = match bytes {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("vtdbg: {}: {}", options.input, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(dir) = &options.dump {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("vtdbg: {}: {}", dir.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let result = if options.compare {
+        compare(&options, &bytes)
+    } else {
+        run(&options, &bytes)
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("vtdbg: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options, bytes: &[u8]) -> Result<(), String> {
+    let mut diff_index = 0;
+
+    let state = if options.raw {
+        let (cols, rows) = options.size;
+        let mut state = AvtState::new(cols, rows);
+        state.poll_diff();
+        state.feed(bytes);
+        report_diff(options, &mut state, None, &mut diff_index)?;
+        state
+    } else {
+        let cast = Cast::parse(bytes).map_err(|e| e.to_string())?;
+        let mut state = AvtState::new(cast.header.cols, cast.header.rows);
+        state.poll_diff();
+        for event in &cast.events {
+            match &event.kind {
+                EventKind::Output(data) => state.feed(data.as_bytes()),
+                EventKind::Resize { cols, rows } => state.resize(*cols, *rows),
+                _ => continue,
+            }
+            report_diff(options, &mut state, Some(event.time_us), &mut diff_index)?;
+        }
+        state
+    };
+
+    let snapshot = state.encode_snapshot();
+    if let Some(dir) = &options.dump {
+        write_payload(&dir.join("snapshot.bin"), &snapshot)?;
+    }
+
+    if options.json {
+        println!("{}", state.dump_json());
+        return Ok(());
+    }
+
+    print_snapshot(&snapshot)?;
+    println!("state hash {}", to_hex(&state.state_hash()));
+    Ok(())
+}
+
+#[cfg(feature = "differential")]
+fn compare(options: &Options, bytes: &[u8]) -> Result<(), String> {
+    use asciicast_vt_avt::differential::{Differential, Report};
+
+    // (event time, report after the event) for every step fed
+    let mut steps: Vec<(Option<i64>, Report)> = Vec::new();
+
+    if options.raw {
+        let (cols, rows) = options.size;
+        let mut d = Differential::new(cols, rows);
+        d.feed(bytes);
+        steps.push((None, d.compare()));
+    } else {
+        let cast = Cast::parse(bytes).map_err(|e| e.to_string())?;
+        let mut d = Differential::new(cast.header.cols, cast.header.rows);
+        for event in &cast.events {
+            match &event.kind {
+                EventKind::Output(data) => d.feed(data.as_bytes()),
+                EventKind::Resize { cols, rows } => d.resize(*cols, *rows),
+                _ => continue,
+            }
+            steps.push((Some(event.time_us), d.compare()));
+        }
+    }
+
+    let total = steps.len();
+    let final_match = steps.last().is_none_or(|(_, r)| r.is_empty());
+    let diverged: Vec<_> = steps.into_iter().filter(|(_, r)| !r.is_empty()).collect();
+
+    if let Some((time_us, report)) = diverged.first() {
+        let time = time_us.map(cast::format_time).unwrap_or_else(|| "-".to_string());
+        println!("first divergence at t={}", time);
+        for row in &report.rows {
+            println!("  row {:3} avt   {:?}", row.row, row.avt);
+            println!("          other {:?}", row.other);
+        }
+        if let Some((avt, other)) = report.cursor {
+            println!("  cursor avt {:?} other {:?}", avt, other);
+        }
+    }
+
+    println!(
+        "{} of {} events diverged; final screens {}",
+        diverged.len(),
+        total,
+        if final_match { "match" } else { "differ" }
+    );
+
+    if diverged.is_empty() {
+        Ok(())
+    } else {
+        Err("avt and alacritty_terminal disagree".to_string())
+    }
+}
+
+#[cfg(not(feature = "differential"))]
+fn compare(_options: &Options, _bytes: &[u8]) -> Result<(), String> {
+    Err("--compare needs a build with --features differential".to_string())
+}
+
+fn report_diff(
+    options: &Options,
+    state: &mut AvtState,
+    time_us: Option<i64>,
+    index: &mut usize,
+) -> Result<(), String> {
+    if !options.diffs && options.dump.is_none() {
+        return Ok(());
+    }
+    let Some(diff) = state.poll_diff() else {
+        return Ok(());
+    };
+
+    if let Some(dir) = &options.dump {
+        write_payload(&dir.join(format!("diff-{:06}.bin", index)), &diff)?;
+    }
+    if options.diffs {
+        let time = time_us.map(cast::format_time).unwrap_or_else(|| "-".to_string());
+        println!("diff #{} t={} {}", index, time, describe_diff(&diff)?);
+    }
+
+    *index += 1;
+    Ok(())
+}
+
+fn write_payload(path: &std::path::Path, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn color_name(color: Color) -> String {
+    match color {
+        Color::Default => "default".to_string(),
+        Color::Indexed(idx) => idx.to_string(),
+        Color::Rgb(r, g, b) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+    }
+}
+
+fn print_snapshot(bytes: &[u8]) -> Result<(), String> {
+    let screen = snapshot::decode(bytes).map_err(|e| format!("snapshot: {}", e))?;
+
+    println!(
+        "snapshot {}x{} cursor {},{}{} ({} bytes)",
+        screen.cols,
+        screen.rows,
+        screen.cursor.col,
+        screen.cursor.row,
+        if screen.cursor.visible { "" } else { " hidden" },
+        bytes.len()
+    );
+
+    for (row, line) in screen.lines.iter().enumerate() {
+        let attr_name = LINE_ATTR_NAMES[line.attr as usize];
+        println!("  row {:3} [{}] {} runs", row, attr_name, line.runs.len());
+
+        for run in &line.runs {
+            let mut style = format!("fg={} bg={}", color_name(run.style.fg), color_name(run.style.bg));
+            for (bit, name) in ATTR_NAMES.iter().enumerate() {
+                if run.style.attrs & (1 << bit) != 0 {
+                    style.push(' ');
+                    style.push_str(name);
+                }
+            }
+            println!("    col {:3} {} {:?}", run.col, style, run.text);
+        }
+    }
+
+    Ok(())
+}
+
+fn describe_diff(bytes: &[u8]) -> Result<String, String> {
+    let diff = diff::decode(bytes).map_err(|e| format!("diff: {}", e))?;
+    let lines: Vec<_> = diff.lines.iter().map(|i| i.to_string()).collect();
+
+    let mut out = format!("lines=[{}]", lines.join(","));
+    if diff.cursor_changed {
+        out.push_str(" cursor");
+    }
+    if diff.resized {
+        out.push_str(" resized");
+    }
+    Ok(out)
+}
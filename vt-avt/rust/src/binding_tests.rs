@@ -0,0 +1,650 @@
+//! `AvtNative.kt` against the JNI functions it declares, and the handle
+//! classes generated over them.
+//!
+//! The Kotlin externs are written by hand, and nothing but a crash at the
+//! first call notices one that no longer matches its Rust function: a
+//! renamed function throws `UnsatisfiedLinkError`, and a parameter added on
+//! one side only reads garbage. The Rust signatures are the description
+//! both sides follow, so this reads every `Java_..._AvtNative_*` function
+//! in `src/` and checks each has an extern of the same name, parameter
+//! types and return type, and that nothing is declared that isn't
+//! exported. Each failure comes with the extern the Rust function implies,
+//! ready to paste.
+//!
+//! The handle kinds without a hand-written class get one generated from
+//! `WRAPPERS` and those signatures, in `AvtHandles.kt`: `Closeable`, each
+//! method a call of its extern on a handle that throws once closed, and
+//! annotated with the thread it's confined to. Like `AvtSchema.kt` (see
+//! `schema`), the test fails while the checked-in file differs, and
+//! `BLESS=1 cargo test binding_tests` (or `./gradlew generateAvtHandles`)
+//! regenerates it.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const SRC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+const KOTLIN: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../android/src/main/kotlin/uk/adedamola/asciicast/vt/avt/AvtNative.kt"
+);
+const HANDLES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../android/src/main/kotlin/uk/adedamola/asciicast/vt/avt/AvtHandles.kt"
+);
+const PREFIX: &str = "fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_";
+
+/// A Kotlin class owning handles of one kind.
+struct Wrapper {
+    class: &'static str,
+    /// KDoc, down to the thread safety line
+    doc: &'static str,
+    /// `androidx.annotation` thread annotation
+    thread: &'static str,
+    /// Prefix of its functions' names, dropped from the methods' names
+    prefix: &'static str,
+    /// Companion functions by name, each the function making a handle
+    factories: &'static [(&'static str, &'static str)],
+    free: &'static str,
+    /// Functions taking the handle first, each a method
+    methods: &'static [&'static str],
+}
+
+const WRAPPERS: &[Wrapper] = &[
+    Wrapper {
+        class: "AvtCast",
+        doc: "A recording parsed into memory, to analyze, cut and export.\n\n\
+              Thread safety: one thread at a time; most calls walk the whole\n\
+              recording.",
+        thread: "WorkerThread",
+        prefix: "cast",
+        factories: &[("open", "castOpen")],
+        free: "castFree",
+        methods: &[
+            "castNormalizeTiming",
+            "castScanDimensions",
+            "castSplitByMarkers",
+            "castSplitByCommands",
+            "castAppendWithGap",
+            "castRebase",
+            "castCheckMonotonic",
+            "castFindStalls",
+            "castSequenceStats",
+            "castHighlights",
+            "castAnalyze",
+            "castSampleFrames",
+            "castPosterTime",
+            "castSearch",
+            "castSimilarity",
+            "castMarkers",
+            "castWriteEncrypted",
+            "castExportSigned",
+        ],
+    },
+    Wrapper {
+        class: "AvtEditSession",
+        doc: "Edits of a copy of an [AvtCast], with undo and redo.\n\n\
+              Thread safety: one thread at a time; an undo replays the edits\n\
+              left.",
+        thread: "WorkerThread",
+        prefix: "edit",
+        factories: &[("open", "editSessionNew")],
+        free: "editSessionFree",
+        methods: &[
+            "editTrim",
+            "editSplice",
+            "editRedact",
+            "editIdleCap",
+            "editUndo",
+            "editRedo",
+            "editCommit",
+        ],
+    },
+    Wrapper {
+        class: "AvtEdl",
+        doc: "An edit decision list: segments of source casts, redactions and\n\
+              an idle cap, rendered on demand.\n\n\
+              Thread safety: one thread at a time.",
+        thread: "WorkerThread",
+        prefix: "edl",
+        factories: &[("create", "edlNew"), ("load", "edlLoad")],
+        free: "edlFree",
+        methods: &[
+            "edlSave",
+            "edlAddSegment",
+            "edlAddRedaction",
+            "edlSetIdleCap",
+            "edlExport",
+        ],
+    },
+    Wrapper {
+        class: "AvtLibrary",
+        doc: "The recording library's index, kept in a file. Needs the native\n\
+              `library` feature.\n\n\
+              Thread safety: one thread at a time, off the main thread.",
+        thread: "WorkerThread",
+        prefix: "library",
+        factories: &[("open", "libraryOpen")],
+        free: "libraryFree",
+        methods: &[
+            "librarySave",
+            "libraryAdd",
+            "libraryRemove",
+            "libraryAddBookmark",
+            "libraryQuery",
+            "librarySearch",
+        ],
+    },
+    Wrapper {
+        class: "AvtSyncReceiver",
+        doc: "A screen mirrored from another device, one frame at a time.\n\n\
+              Thread safety: one thread at a time.",
+        thread: "AnyThread",
+        prefix: "syncReceiver",
+        factories: &[("create", "syncReceiverNew")],
+        free: "syncReceiverFree",
+        methods: &["syncReceiverApply", "syncReceiverSnapshot"],
+    },
+    Wrapper {
+        class: "AvtNetConnection",
+        doc: "A raw TCP or telnet console, pumped into a terminal. Needs the\n\
+              native `net` feature.\n\n\
+              Thread safety: one thread at a time; pumping blocks.",
+        thread: "WorkerThread",
+        prefix: "net",
+        factories: &[("connect", "netConnect")],
+        free: "netFree",
+        methods: &["netPump", "netSend"],
+    },
+    Wrapper {
+        class: "AvtSshSession",
+        doc: "A shell on a remote PTY over SSH, pumped into a terminal. Needs\n\
+              the native `ssh` feature.\n\n\
+              Thread safety: one thread at a time; connecting and pumping\n\
+              block.",
+        thread: "WorkerThread",
+        prefix: "ssh",
+        factories: &[("connect", "sshConnect")],
+        free: "sshFree",
+        methods: &["sshPump", "sshSend", "sshResize", "sshHostKey"],
+    },
+    Wrapper {
+        class: "AvtRecorder",
+        doc: "A recording being written from a terminal's session; [close]\n\
+              stops it.\n\n\
+              Thread safety: the terminal's thread only.",
+        thread: "AnyThread",
+        prefix: "recorder",
+        factories: &[
+            ("start", "recorderStart"),
+            ("startEncrypted", "recorderStartEncrypted"),
+        ],
+        free: "recorderStop",
+        methods: &[
+            "recorderOutput",
+            "recorderInput",
+            "recorderResize",
+            "recorderMarker",
+        ],
+    },
+];
+
+/// Functions whose `Long` or `LongArray` is new handles of a class, which
+/// the caller then owns.
+const OWNED_RESULTS: &[(&str, &str)] = &[
+    ("castSplitByMarkers", "AvtCast"),
+    ("castSplitByCommands", "AvtCast"),
+    ("castAppendWithGap", "AvtCast"),
+    ("editCommit", "AvtCast"),
+];
+
+/// Extern parameters that are handles of a class, passed as the class
+/// (and `LongArray`s of them as lists).
+const HANDLE_PARAMS: &[(&str, &str)] = &[
+    ("castHandle", "AvtCast"),
+    ("bHandle", "AvtCast"),
+    ("castHandles", "AvtCast"),
+];
+
+/// Kotlin type of a JNI type, `None` for objects, which map to any class.
+fn kotlin_type(rust: &str) -> Option<&'static str> {
+    let kotlin = match rust.trim_end_matches("<'a>") {
+        "jlong" | "VtHandle" => "Long",
+        "jint" => "Int",
+        "jboolean" => "Boolean",
+        "jdouble" => "Double",
+        "jfloat" => "Float",
+        "JByteArray" => "ByteArray",
+        "JIntArray" => "IntArray",
+        "JLongArray" => "LongArray",
+        "JDoubleArray" => "DoubleArray",
+        "JString" => "String",
+        "JByteBuffer" => "ByteBuffer",
+        "()" => "Unit",
+        "JObject" => return None,
+        other => panic!("no Kotlin type for {}", other),
+    };
+    Some(kotlin)
+}
+
+/// Parameter names and types, and the return type, of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Signature {
+    params: Vec<(String, String)>,
+    returns: String,
+}
+
+/// `name: Type` pairs from a comma separated list.
+fn params(list: &str) -> Vec<(String, String)> {
+    list.split(',')
+        .filter_map(|param| param.split_once(':'))
+        .map(|(name, ty)| (name.trim().to_string(), ty.trim().to_string()))
+        .collect()
+}
+
+/// The JNI functions in `source`, by name after the prefix.
+fn exported(source: &str) -> Vec<(String, Signature)> {
+    let mut found = Vec::new();
+    let mut rest = source;
+    while let Some(at) = rest.find(PREFIX) {
+        rest = &rest[at + PREFIX.len()..];
+        let name_end = rest.find(['<', '(']).unwrap();
+        let open = rest.find('(').unwrap();
+        let close = open + rest[open..].find(')').unwrap();
+        let body = close + rest[close..].find('{').unwrap();
+        let returns = rest[close + 1..body].trim().trim_start_matches("->").trim();
+        // The env and class come first
+        let params = params(&rest[open + 1..close]).split_off(2);
+        let returns = if returns.is_empty() { "()" } else { returns };
+        found.push((
+            rest[..name_end].to_string(),
+            Signature {
+                params,
+                returns: returns.to_string(),
+            },
+        ));
+    }
+    found
+}
+
+/// The `external fun` declarations in `source`.
+fn declared(source: &str) -> Vec<(String, Signature)> {
+    let mut found = Vec::new();
+    let mut rest = source;
+    while let Some(at) = rest.find("external fun ") {
+        rest = &rest[at + "external fun ".len()..];
+        let open = rest.find('(').unwrap();
+        let close = open + rest[open..].find(')').unwrap();
+        let after = rest[close + 1..].trim_start();
+        let returns = match after.strip_prefix(':') {
+            Some(ty) => ty.split_whitespace().next().unwrap(),
+            None => "Unit",
+        };
+        found.push((
+            rest[..open].trim().to_string(),
+            Signature {
+                params: params(&rest[open + 1..close]),
+                returns: returns.to_string(),
+            },
+        ));
+    }
+    found
+}
+
+fn matches(rust: &str, kotlin: &str) -> bool {
+    let kotlin = kotlin.trim_end_matches('?');
+    match kotlin_type(rust) {
+        Some(expected) => kotlin == expected,
+        None => !is_value(kotlin),
+    }
+}
+
+/// Whether `kotlin` is a primitive or an array, which no object maps to.
+fn is_value(kotlin: &str) -> bool {
+    matches!(
+        kotlin,
+        "Long" | "Int" | "Boolean" | "Double" | "Float" | "Unit"
+    ) || kotlin.ends_with("Array")
+}
+
+/// The extern `name` with `signature` needs, objects as `Any`.
+fn extern_for(name: &str, signature: &Signature) -> String {
+    let params: Vec<String> = signature
+        .params
+        .iter()
+        .map(|(param, ty)| {
+            let mut camel = String::new();
+            let mut upper = false;
+            for c in param.trim_start_matches('_').chars() {
+                if c == '_' {
+                    upper = true;
+                } else if upper {
+                    camel.extend(c.to_uppercase());
+                    upper = false;
+                } else {
+                    camel.push(c);
+                }
+            }
+            format!("{}: {}", camel, kotlin_type(ty).unwrap_or("Any"))
+        })
+        .collect();
+    let returns = match kotlin_type(&signature.returns) {
+        Some("Unit") => String::new(),
+        ty => format!(": {}", ty.unwrap_or("Any")),
+    };
+    format!("external fun {}({}){}", name, params.join(", "), returns)
+}
+
+/// Every JNI function in `src/`, by name after the prefix.
+fn rust_functions() -> BTreeMap<String, Signature> {
+    let mut rust = BTreeMap::new();
+    for entry in fs::read_dir(SRC).unwrap() {
+        let path = entry.unwrap().path();
+        // This file names the prefix itself
+        if path.extension().is_none_or(|ext| ext != "rs") || path.ends_with("binding_tests.rs") {
+            continue;
+        }
+        for (name, signature) in exported(&fs::read_to_string(&path).unwrap()) {
+            assert!(
+                rust.insert(name.clone(), signature).is_none(),
+                "{} exported twice",
+                name
+            );
+        }
+    }
+    rust
+}
+
+/// A Kotlin function head, its parameters on their own lines when it
+/// would be longer than `width` with the ` =` after it.
+fn head(name: &str, params: &[String], returns: &str, width: usize) -> String {
+    let returns = if returns.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", returns)
+    };
+    let line = format!("    fun {}({}){}", name, params.join(", "), returns);
+    if line.len() + 2 <= width {
+        return line;
+    }
+    let params: String = params
+        .iter()
+        .map(|param| format!("        {},\n", param))
+        .collect();
+    format!("    fun {}(\n{}    ){}", name, params, returns)
+}
+
+/// The Kotlin parameters of `params`, and the arguments passing them.
+fn wrapped_params(params: &[(String, String)]) -> (Vec<String>, Vec<String>) {
+    params
+        .iter()
+        .map(
+            |(name, ty)| match HANDLE_PARAMS.iter().find(|(param, _)| param == name) {
+                Some((_, class)) if ty == "LongArray" => {
+                    let name = name.replace("Handle", "");
+                    (
+                        format!("{}: List<{}>", name, class),
+                        format!("LongArray({0}.size) {{ {0}[it].handle }}", name),
+                    )
+                }
+                Some((_, class)) => {
+                    let name = name.replace("Handle", "");
+                    (format!("{}: {}", name, class), format!("{}.handle", name))
+                }
+                None => (format!("{}: {}", name, ty), name.clone()),
+            },
+        )
+        .unzip()
+}
+
+/// The call of `native` with `args` at an indent of 8, its arguments on
+/// their own lines when it would be longer than `width`.
+fn call(native: &str, args: &[String], width: usize) -> String {
+    let line = format!("AvtNative.{}({})", native, args.join(", "));
+    if line.len() + 8 <= width {
+        return line;
+    }
+    let args: String = args
+        .iter()
+        .map(|arg| format!("            {},\n", arg))
+        .collect();
+    format!("AvtNative.{}(\n{}        )", native, args)
+}
+
+/// A function returning `call`, as a new handle of `class` if it is one.
+fn wrapped_fun(
+    out: &mut String,
+    head_of: impl Fn(&str) -> String,
+    call: String,
+    returns: &str,
+    class: Option<&str>,
+) {
+    let (returns, body) = match (class, returns) {
+        (Some(class), "LongArray") => (
+            format!("List<{}>", class),
+            format!("{}.map(::{})", call, class),
+        ),
+        (Some(class), _) => (
+            format!("{}?", class),
+            format!(
+                "{}\n            .takeIf {{ it != 0L }}\n            ?.let(::{})",
+                call, class
+            ),
+        ),
+        (None, "Unit") => {
+            let _ = writeln!(out, "{} {{\n        {}\n    }}", head_of(""), call);
+            return;
+        }
+        (None, returns) => (returns.to_string(), call),
+    };
+    let _ = writeln!(out, "{} =\n        {}", head_of(&returns), body);
+}
+
+/// `AvtHandles.kt`, the `WRAPPERS` over the externs in `kotlin`.
+fn handles_kotlin(kotlin: &BTreeMap<String, Signature>) -> String {
+    let extern_named = |name: &str| {
+        kotlin
+            .get(name)
+            .unwrap_or_else(|| panic!("{} isn't declared in AvtNative.kt", name))
+    };
+    let mut out = String::from(
+        "// Generated from rust/src/binding_tests.rs; don't edit. Regenerate with\n\
+         // `BLESS=1 cargo test binding_tests` or `./gradlew generateAvtHandles`.\n\n\
+         package uk.adedamola.asciicast.vt.avt\n\n\
+         import androidx.annotation.AnyThread\n\
+         import androidx.annotation.WorkerThread\n\
+         import java.io.Closeable\n",
+    );
+    for wrapper in WRAPPERS {
+        let class = wrapper.class;
+        out.push_str("\n/**\n");
+        for line in wrapper.doc.lines() {
+            let _ = writeln!(out, " {}", format!("* {}", line).trim_end());
+        }
+        let _ = writeln!(out, " */\n@{}", wrapper.thread);
+        let _ = writeln!(
+            out,
+            "class {} internal constructor(private var raw: Long) : Closeable {{",
+            class
+        );
+        out.push_str("    companion object {\n");
+        for (i, (name, native)) in wrapper.factories.iter().enumerate() {
+            let signature = extern_named(native);
+            let (params, args) = wrapped_params(&signature.params);
+            let mut function = String::new();
+            let _ = writeln!(
+                function,
+                "    /** [AvtNative.{}], null if that fails. */",
+                native
+            );
+            wrapped_fun(
+                &mut function,
+                // Indented once more below
+                |returns| head(name, &params, returns, 96),
+                call(native, &args, 96),
+                &signature.returns,
+                Some(class),
+            );
+            if i > 0 {
+                out.push('\n');
+            }
+            for line in function.lines() {
+                let _ = writeln!(out, "    {}", line);
+            }
+        }
+        out.push_str("    }\n\n");
+        let _ = writeln!(
+            out,
+            "    /** For [AvtNative] functions taking the handle; throws once closed. */\n    \
+             internal val handle: Long\n        \
+             get() {{\n            \
+             check(raw != 0L) {{ \"{} is closed\" }}\n            \
+             return raw\n        \
+             }}",
+            class
+        );
+        for native in wrapper.methods {
+            let signature = extern_named(native);
+            let name = native.strip_prefix(wrapper.prefix).unwrap();
+            let name = name[..1].to_lowercase() + &name[1..];
+            let (params, args) = wrapped_params(&signature.params[1..]);
+            let args: Vec<String> = std::iter::once("handle".to_string()).chain(args).collect();
+            let owned = OWNED_RESULTS
+                .iter()
+                .find(|(function, _)| function == native)
+                .map(|(_, class)| *class);
+            let _ = writeln!(out, "\n    /** [AvtNative.{}] */", native);
+            wrapped_fun(
+                &mut out,
+                |returns| head(&name, &params, returns, 100),
+                call(native, &args, 100),
+                &signature.returns,
+                owned,
+            );
+        }
+        let _ = writeln!(
+            out,
+            "\n    override fun close() {{\n        \
+             if (raw != 0L) {{\n            \
+             AvtNative.{}(raw)\n            \
+             raw = 0L\n        \
+             }}\n    \
+             }}\n}}",
+            wrapper.free
+        );
+    }
+    out
+}
+
+#[test]
+fn kotlin_handles_are_generated_from_the_jni_functions() {
+    let kotlin_source = fs::read_to_string(Path::new(KOTLIN)).unwrap();
+    let kotlin: BTreeMap<_, _> = declared(&kotlin_source).into_iter().collect();
+    for wrapper in WRAPPERS {
+        for native in wrapper.methods.iter().chain([&wrapper.free]) {
+            assert_eq!(
+                kotlin[*native].params.first().map(|(_, ty)| &ty[..]),
+                Some("Long"),
+                "{} doesn't take a handle",
+                native
+            );
+        }
+    }
+    let generated = handles_kotlin(&kotlin);
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(HANDLES, &generated).unwrap();
+        return;
+    }
+    let checked_in = fs::read_to_string(HANDLES).unwrap_or_default();
+    if let Some((line, (want, got))) = generated
+        .lines()
+        .zip(checked_in.lines())
+        .enumerate()
+        .find(|(_, (want, got))| want != got)
+    {
+        panic!(
+            "AvtHandles.kt line {} is {:?}, the externs give {:?}; regenerate it with BLESS=1",
+            line + 1,
+            got,
+            want
+        );
+    }
+    assert_eq!(
+        checked_in.lines().count(),
+        generated.lines().count(),
+        "AvtHandles.kt is out of date; regenerate it with BLESS=1"
+    );
+}
+
+#[test]
+fn kotlin_externs_match_the_jni_functions() {
+    let rust = rust_functions();
+    let kotlin_source = fs::read_to_string(Path::new(KOTLIN)).unwrap();
+    let mut kotlin = BTreeMap::new();
+    let mut problems = Vec::new();
+    for (name, signature) in declared(&kotlin_source) {
+        if kotlin.insert(name.clone(), signature).is_some() {
+            problems.push(format!("{}: declared twice, JNI can't overload", name));
+        }
+    }
+
+    for (name, signature) in &rust {
+        let Some(declared) = kotlin.get(name) else {
+            problems.push(format!(
+                "{}: not declared, add {}",
+                name,
+                extern_for(name, signature)
+            ));
+            continue;
+        };
+        let types_match = declared.params.len() == signature.params.len()
+            && signature
+                .params
+                .iter()
+                .zip(&declared.params)
+                .all(|((_, rust), (_, kotlin))| matches(rust, kotlin))
+            && matches(&signature.returns, &declared.returns);
+        if !types_match {
+            problems.push(format!(
+                "{}: should be {}",
+                name,
+                extern_for(name, signature)
+            ));
+        }
+    }
+    for name in kotlin.keys().filter(|name| !rust.contains_key(*name)) {
+        problems.push(format!("{}: declared but not exported", name));
+    }
+    assert!(
+        problems.is_empty(),
+        "AvtNative.kt:\n{}",
+        problems.join("\n")
+    );
+}
+
+#[test]
+fn reads_signatures_from_either_side() {
+    let rust = "#[no_mangle]\npub extern \"system\" fn Java_uk_adedamola_asciicast_vt_avt_\
+        AvtNative_vtFeedAt<'a>(\n    mut env: JNIEnv<'a>,\n    _class: JClass<'a>,\n    \
+        handle: VtHandle,\n    trace_id: jlong,\n    data: JByteArray<'a>,\n) -> jboolean {\n}";
+    let (name, signature) = exported(rust).remove(0);
+    assert_eq!(name, "vtFeedAt");
+    assert_eq!(
+        extern_for(&name, &signature),
+        "external fun vtFeedAt(handle: Long, traceId: Long, data: ByteArray): Boolean"
+    );
+
+    let kotlin = "    /** Feed. */\n    external fun vtFeedAt(\n        handle: Long,\n        \
+        traceId: Long,\n        data: ByteArray?\n    ): Boolean\n\n    external fun vtFree(handle: Long)\n";
+    let declared = declared(kotlin);
+    assert_eq!(declared[0].0, "vtFeedAt");
+    assert!(signature
+        .params
+        .iter()
+        .zip(&declared[0].1.params)
+        .all(|((_, rust), (_, kotlin))| matches(rust, kotlin)));
+    assert_eq!(declared[1].1.returns, "Unit");
+    assert!(matches("JObject<'a>", "AvtCastSource"));
+    assert!(!matches("JObject<'a>", "Long"));
+}
@@ -0,0 +1,216 @@
+//! Screen-burn protection for always-on displays.
+//!
+//! A player left in ambient mode shows the same prompt or status line for
+//! hours, which marks OLED panels. Two countermeasures, both driven by a
+//! time the caller supplies so a frame drawn twice comes out the same:
+//!
+//! - The whole frame is shifted by up to `shift_px` pixels, one pixel per
+//!   `shift_period_ms`, walking the edge of a square around its resting
+//!   place so no pixel stays lit for long.
+//! - Rows unchanged for `dim_after_ms` have their text faded `dim_level`
+//!   (of 255) of the way to its background. A row is "unchanged" by its
+//!   hash (see `framehash`), so a redraw of the same text doesn't count.
+//!
+//! `vtBurnIn` gives both to renderers drawing resolved colors themselves;
+//! `render` applies them to bitmaps.
+
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JIntArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+/// How far and how soon to act; the default does nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Protection {
+    /// Largest shift from the resting place, in pixels; 0 for none
+    pub shift_px: u32,
+    /// Time between one-pixel steps
+    pub shift_period_ms: u64,
+    /// Time a row must be unchanged to be dimmed; 0 for never
+    pub dim_after_ms: u64,
+    /// How far dimmed text fades to its background, of 255
+    pub dim_level: u8,
+}
+
+impl Protection {
+    /// `[shiftPx, shiftPeriodMs, dimAfterMs, dimLevel]`. Missing or
+    /// negative entries are 0; `dimLevel` is clamped to 255.
+    pub fn from_ints(values: &[i32]) -> Self {
+        let value = |i: usize| values.get(i).map_or(0, |&v| v.max(0) as u32);
+        Protection {
+            shift_px: value(0),
+            shift_period_ms: value(1) as u64,
+            dim_after_ms: value(2) as u64,
+            dim_level: value(3).min(255) as u8,
+        }
+    }
+
+    /// Pixels to move the frame by at `now_ms`, each within `±shift_px`.
+    pub fn offset(&self, now_ms: u64) -> (i32, i32) {
+        let shift = self.shift_px as i32;
+        if shift == 0 || self.shift_period_ms == 0 {
+            return (0, 0);
+        }
+        // One step along the 8 * shift pixels of the square's edge
+        let side = 2 * shift as u64;
+        let step = now_ms / self.shift_period_ms % (4 * side);
+        let along = (step % side) as i32;
+        match step / side {
+            0 => (-shift + along, -shift),
+            1 => (shift, -shift + along),
+            2 => (shift - along, shift),
+            _ => (-shift, shift - along),
+        }
+    }
+}
+
+/// When each row last changed, by its hash.
+#[derive(Debug, Clone, Default)]
+pub struct RowAges {
+    rows: Vec<(u64, u64)>,
+}
+
+impl RowAges {
+    /// Note the row `hashes` as of `now_ms`. A row seen for the first
+    /// time, or at a time before its last change (after a seek), starts
+    /// its age at `now_ms`.
+    pub fn observe(&mut self, hashes: impl IntoIterator<Item = u64>, now_ms: u64) {
+        let mut count = 0;
+        for (row, hash) in hashes.into_iter().enumerate() {
+            count += 1;
+            match self.rows.get_mut(row) {
+                Some(seen) if seen.0 == hash && seen.1 <= now_ms => {}
+                Some(seen) => *seen = (hash, now_ms),
+                None => self.rows.push((hash, now_ms)),
+            }
+        }
+        self.rows.truncate(count);
+    }
+
+    /// How far to fade each row at `now_ms`, see `Protection::dim_level`.
+    pub fn dims(&self, protection: &Protection, now_ms: u64) -> Vec<u8> {
+        self.rows
+            .iter()
+            .map(|&(_, since)| {
+                let idle = now_ms.saturating_sub(since);
+                if protection.dim_after_ms > 0 && idle >= protection.dim_after_ms {
+                    protection.dim_level
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+}
+
+/// What to apply to one frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Pixels kept clear on every side so the shifted frame fits
+    pub margin: usize,
+    pub dx: i32,
+    pub dy: i32,
+    /// Per row, see `Protection::dim_level`; missing rows aren't dimmed
+    pub dims: Vec<u8>,
+}
+
+impl Frame {
+    pub fn dim(&self, row: usize) -> u8 {
+        self.dims.get(row).copied().unwrap_or(0)
+    }
+}
+
+/// `level` (of 255) of the way from `color` to `toward`, both
+/// `0xAARRGGBB`, keeping `color`'s alpha.
+pub fn fade(color: u32, toward: u32, level: u8) -> u32 {
+    let level = level as u32;
+    let channel = |shift: u32| {
+        let (a, b) = (color >> shift & 0xff, toward >> shift & 0xff);
+        ((a * (255 - level) + b * level) / 255) << shift
+    };
+    color & 0xff000000 | channel(16) | channel(8) | channel(0)
+}
+
+// JNI functions
+
+/// `[dx, dy, dim0, dim1, ...]` for the screen of `handle` at `now_millis`
+/// under `protection` (see `Protection::from_ints`): the shift in pixels
+/// and each row's fade, see `AvtState::burn_in`. Empty for an invalid
+/// handle or a negative time.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtBurnIn<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    now_millis: jlong,
+    protection: JIntArray<'a>,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Ok(now_ms) = u64::try_from(now_millis) else {
+            return JIntArray::default();
+        };
+        let Some(protection) = protection_arg(&env, &protection) else {
+            return JIntArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JIntArray::default();
+        };
+
+        let frame = vt.burn_in(&protection, now_ms);
+        let mut values = vec![frame.dx, frame.dy];
+        values.extend(frame.dims.iter().map(|&dim| dim as i32));
+        crate::int_array(&env, &values)
+    })
+}
+
+/// `Protection::from_ints` of a Java `int[]`, `None` if it can't be read.
+pub fn protection_arg(env: &JNIEnv, values: &JIntArray) -> Option<Protection> {
+    let mut ints = vec![0; env.get_array_length(values).ok()? as usize];
+    env.get_int_array_region(values, 0, &mut ints).ok()?;
+    Some(Protection::from_ints(&ints))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn offsets_walk_the_edge_of_a_square() {
+        let protection = Protection::from_ints(&[1, 1000]);
+        let offsets: Vec<_> = (0..9).map(|s| protection.offset(s * 1000 + 500)).collect();
+        assert_eq!(
+            offsets,
+            [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+                (0, 1),
+                (-1, 1),
+                (-1, 0),
+                (-1, -1)
+            ]
+        );
+        assert_eq!(Protection::default().offset(12_345), (0, 0));
+    }
+
+    #[test]
+    fn rows_dim_once_unchanged_long_enough() {
+        let protection = Protection::from_ints(&[0, 0, 60_000, 128]);
+        let mut vt = AvtState::with_backend(fake(10, 2));
+        vt.feed(b"prompt");
+        assert_eq!(vt.burn_in(&protection, 0).dims, [0, 0]);
+        assert_eq!(vt.burn_in(&protection, 60_000).dims, [128, 128]);
+
+        // Redrawing the same text leaves the row as old as it was
+        vt.invalidate(None);
+        assert_eq!(vt.burn_in(&protection, 61_000).dims, [128, 128]);
+        vt.feed(b"!");
+        assert_eq!(vt.burn_in(&protection, 62_000).dims, [0, 128]);
+
+        assert_eq!(fade(0xffffffff, 0xff000000, 128), 0xff7f7f7f);
+    }
+}
@@ -0,0 +1,224 @@
+//! C ABI for the terminal core, so iOS and desktop clients link the same
+//! engine as the app.
+//!
+//! A VT is an opaque `AvtcVt` from `avtc_new`, used from one thread at a
+//! time and released with `avtc_free`. Output is in the same binary
+//! formats as the JNI functions return (see `snapshot` and `diff`), so
+//! `ffi`'s `avt_snapshot_decode` reads a snapshot back, as would a port of
+//! the Kotlin decoders; `avtc_schema_hash` is `schema::hash`, to check a
+//! decoder against the library.
+//!
+//! Bytes are copied out into the caller's buffer, the `_into` functions
+//! returning the length needed: a result larger than `cap` copied nothing,
+//! and the call can be made again with a buffer that large. A diff too
+//! large for its buffer is kept for the next call rather than lost.
+//!
+//! `include/avtc.h` is generated from this file (see `capi_tests`); a
+//! panic in the engine is caught and reported as failure, and the VT
+//! should then be freed.
+
+use crate::backend::TerminalBackend;
+use crate::{protocol, schema, AvtState};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// A VT, with a diff polled and not yet copied out.
+pub struct AvtcVt {
+    state: AvtState,
+    diff: Option<Vec<u8>>,
+}
+
+/// Copy `bytes` into `buf` if it holds them, returning their length.
+unsafe fn copy_into(bytes: &[u8], buf: *mut u8, cap: usize) -> usize {
+    if !buf.is_null() && bytes.len() <= cap {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    }
+    bytes.len()
+}
+
+/// `f`, or `default` if it panics.
+fn guarded<T>(default: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+/// A new VT of `cols` by `rows` cells, with unlimited scrollback.
+#[no_mangle]
+pub extern "C" fn avtc_new(cols: u32, rows: u32) -> *mut AvtcVt {
+    guarded(ptr::null_mut(), || {
+        Box::into_raw(Box::new(AvtcVt {
+            state: AvtState::new(cols as usize, rows as usize),
+            diff: None,
+        }))
+    })
+}
+
+/// Release a VT; null is ignored.
+///
+/// # Safety
+/// `vt` must be null or a pointer from `avtc_new` that hasn't been freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_free(vt: *mut AvtcVt) {
+    if !vt.is_null() {
+        drop(Box::from_raw(vt));
+    }
+}
+
+/// Feed `len` bytes of output. Returns false if the engine panicked.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`, and `data` point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_feed(vt: *mut AvtcVt, data: *const u8, len: usize) -> bool {
+    if data.is_null() {
+        return len == 0;
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    guarded(false, || {
+        (*vt).state.feed(bytes);
+        true
+    })
+}
+
+/// Resize, rewrapping soft-wrapped lines to the new width.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_resize(vt: *mut AvtcVt, cols: u32, rows: u32) -> bool {
+    guarded(false, || {
+        (*vt).state.resize(cols as usize, rows as usize);
+        true
+    })
+}
+
+/// Clear everything, scrollback included, at a new size.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_reset(vt: *mut AvtcVt, cols: u32, rows: u32) -> bool {
+    guarded(false, || {
+        let vt = &mut *vt;
+        vt.state.reset(cols as usize, rows as usize);
+        vt.diff = None;
+        true
+    })
+}
+
+/// The screen in the snapshot format, copied into `buf` if `cap` bytes
+/// hold it. Returns its length, 0 if the engine panicked.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`, and `buf` null or valid
+/// for `cap` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_snapshot_into(vt: *mut AvtcVt, buf: *mut u8, cap: usize) -> usize {
+    guarded(0, || copy_into(&(*vt).state.encode_snapshot(), buf, cap))
+}
+
+/// What changed since the last diff, in the diff format, copied into `buf`
+/// if `cap` bytes hold it; kept for the next call if not. Returns its
+/// length, 0 for no change (or a panic).
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`, and `buf` null or valid
+/// for `cap` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_poll_diff_into(vt: *mut AvtcVt, buf: *mut u8, cap: usize) -> usize {
+    guarded(0, || {
+        let vt = &mut *vt;
+        let Some(diff) = vt.diff.take().or_else(|| vt.state.poll_diff()) else {
+            return 0;
+        };
+        let len = copy_into(&diff, buf, cap);
+        if len > cap || buf.is_null() {
+            vt.diff = Some(diff);
+        }
+        len
+    })
+}
+
+/// The screen as ANSI that replays it into a fresh VT of the same size,
+/// copied into `buf` if `cap` bytes hold it. Returns its length.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`, and `buf` null or valid
+/// for `cap` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_dump_into(vt: *mut AvtcVt, buf: *mut u8, cap: usize) -> usize {
+    guarded(0, || {
+        copy_into((*vt).state.dump_ansi().as_bytes(), buf, cap)
+    })
+}
+
+/// Lines scrolled off the top and still kept.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_scrollback_len(vt: *const AvtcVt) -> usize {
+    (*vt).state.backend().scrollback_len()
+}
+
+/// Send snapshots and diffs behind a versioned header, leaving out the
+/// features not in `features`, as `vtSetWireFormat` does (see `protocol`).
+/// Returns the features it will send.
+///
+/// # Safety
+/// `vt` must be a live pointer from `avtc_new`.
+#[no_mangle]
+pub unsafe extern "C" fn avtc_set_wire_format(vt: *mut AvtcVt, version: u32, features: u32) -> u32 {
+    let wire = protocol::Wire::negotiate(version, features);
+    (*vt).state.set_wire(wire);
+    wire.features
+}
+
+/// The newest snapshot and diff protocol version the library writes.
+#[no_mangle]
+pub extern "C" fn avtc_protocol_version() -> u32 {
+    protocol::VERSION as u32
+}
+
+/// Hash of the wire formats' constants, as `vtSchemaHash` returns.
+#[no_mangle]
+pub extern "C" fn avtc_schema_hash() -> i64 {
+    schema::hash()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi;
+    use crate::snapshot;
+
+    #[test]
+    fn feeds_and_copies_out_snapshots_and_diffs() {
+        unsafe {
+            let vt = avtc_new(10, 2);
+            assert!(!vt.is_null());
+            assert!(avtc_feed(vt, b"hi".as_ptr(), 2));
+            let len = avtc_snapshot_into(vt, ptr::null_mut(), 0);
+            let mut buf = vec![0; len];
+            assert_eq!(avtc_snapshot_into(vt, buf.as_mut_ptr(), len), len);
+            let screen = ffi::avt_snapshot_decode(buf.as_ptr(), len);
+            assert_eq!(ffi::avt_screen_cols(screen), 10);
+            ffi::avt_screen_free(screen);
+            let decoded = snapshot::decode(&buf).unwrap();
+            assert_eq!(decoded.lines[0].runs[0].text.trim_end(), "hi");
+
+            // Too small a buffer keeps the diff for the next call
+            let len = avtc_poll_diff_into(vt, buf.as_mut_ptr(), 1);
+            assert!(len > 1);
+            let mut diff = vec![0; len];
+            assert_eq!(avtc_poll_diff_into(vt, diff.as_mut_ptr(), len), len);
+            assert_eq!(avtc_poll_diff_into(vt, diff.as_mut_ptr(), len), 0);
+
+            assert!(avtc_reset(vt, 4, 1));
+            assert_eq!(avtc_scrollback_len(vt), 0);
+            assert_eq!(avtc_schema_hash(), schema::hash());
+            avtc_free(vt);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! `include/avtc.h` against the functions of `capi`.
+//!
+//! The header is generated from `src/capi.rs` itself: each `avtc_*`
+//! function with its doc comment (up to its safety section) becomes a C
+//! declaration, its types mapped by `c_type`. This fails while the
+//! checked-in header differs; after changing `capi`, `BLESS=1 cargo test
+//! capi_tests` regenerates it.
+
+use std::fs;
+
+const SOURCE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/capi.rs");
+const HEADER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/include/avtc.h");
+
+/// C type of a Rust parameter or return type.
+fn c_type(rust: &str) -> &'static str {
+    match rust {
+        "" => "void",
+        "bool" => "bool",
+        "u32" => "uint32_t",
+        "i64" => "int64_t",
+        "usize" => "size_t",
+        "*const u8" => "const uint8_t *",
+        "*mut u8" => "uint8_t *",
+        "*mut AvtcVt" => "AvtcVt *",
+        "*const AvtcVt" => "const AvtcVt *",
+        other => panic!("no C type for {}", other),
+    }
+}
+
+/// `type name` with the pointer's star against the name.
+fn declaration(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+/// The C declarations of the `extern "C"` functions in `source`, each
+/// after its doc comment.
+fn declarations(source: &str) -> Vec<String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut found = Vec::new();
+    for (at, line) in lines.iter().enumerate() {
+        let Some((_, rest)) = line.split_once("extern \"C\" fn ") else {
+            continue;
+        };
+        // Parameters may be wrapped onto lines of their own
+        let mut signature = rest.to_string();
+        let mut next = at + 1;
+        while !signature.contains('{') {
+            signature.push_str(lines[next].trim());
+            next += 1;
+        }
+        let (name, rest) = signature.split_once('(').unwrap();
+        let (params, rest) = rest.split_once(')').unwrap();
+        let returns = rest
+            .split('{')
+            .next()
+            .unwrap()
+            .trim()
+            .trim_start_matches("->");
+        let params: Vec<String> = params
+            .split(',')
+            .filter_map(|param| param.split_once(':'))
+            .map(|(name, ty)| declaration(c_type(ty.trim()), name.trim()))
+            .collect();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+
+        // The doc comment above the attribute, without its safety section
+        let mut doc: Vec<&str> = lines[..at - 1]
+            .iter()
+            .rev()
+            .take_while(|line| line.starts_with("///"))
+            .map(|line| line.trim_start_matches("///").trim())
+            .collect();
+        doc.reverse();
+        if let Some(safety) = doc.iter().position(|line| *line == "# Safety") {
+            doc.truncate(safety);
+        }
+        while doc.last() == Some(&"") {
+            doc.pop();
+        }
+
+        let mut out = String::new();
+        match doc.as_slice() {
+            [] => {}
+            [line] => out.push_str(&format!("/* {} */\n", line)),
+            [first, rest @ ..] => {
+                out.push_str(&format!("/* {}\n", first));
+                for line in rest {
+                    out.push_str(&format!(" * {}\n", line).replace(" * \n", " *\n"));
+                }
+                out.truncate(out.len() - 1);
+                out.push_str(" */\n");
+            }
+        }
+        out.push_str(&format!(
+            "{}({});",
+            declaration(c_type(returns.trim()), name),
+            params
+        ));
+        found.push(out);
+    }
+    found
+}
+
+fn header(source: &str) -> String {
+    let mut out = String::from(
+        "/*\n \
+         * Generated from src/capi.rs; don't edit. Regenerate with\n \
+         * `BLESS=1 cargo test capi_tests`.\n \
+         *\n \
+         * The terminal core for C callers (see src/capi.rs). Create a VT with\n \
+         * avtc_new, feed it output, copy out snapshots and diffs in the\n \
+         * library's binary formats and release it with avtc_free; one thread\n \
+         * at a time per VT. asciicast_vt_avt.h decodes the snapshots.\n \
+         */\n\
+         #ifndef AVTC_H\n\
+         #define AVTC_H\n\n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {\n\
+         #endif\n\n\
+         typedef struct AvtcVt AvtcVt;\n",
+    );
+    for declaration in declarations(source) {
+        out.push('\n');
+        out.push_str(&declaration);
+        out.push('\n');
+    }
+    out.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+    out
+}
+
+#[test]
+fn header_is_generated_from_the_functions() {
+    let source = fs::read_to_string(SOURCE).unwrap();
+    let generated = header(&source);
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(HEADER, &generated).unwrap();
+        return;
+    }
+    let checked_in = fs::read_to_string(HEADER).unwrap_or_default();
+    assert!(
+        checked_in == generated,
+        "include/avtc.h is out of date; regenerate it with BLESS=1. It should read:\n{}",
+        generated
+    );
+}
+
+#[test]
+fn declarations_follow_the_signatures() {
+    let source = "\
+/// Two lines
+/// of doc.
+///
+/// # Safety
+/// Not in the header.
+#[no_mangle]
+pub unsafe extern \"C\" fn avtc_copy(
+    vt: *const AvtcVt,
+    buf: *mut u8,
+) -> usize {
+}
+
+#[no_mangle]
+pub extern \"C\" fn avtc_none() {
+}
+";
+    assert_eq!(
+        declarations(source),
+        [
+            "/* Two lines\n * of doc. */\nsize_t avtc_copy(const AvtcVt *vt, uint8_t *buf);",
+            "void avtc_none(void);",
+        ]
+    );
+}
@@ -0,0 +1,493 @@
+//! asciicast model: parsing and writing of .cast files.
+//!
+//! Times are kept as integer microseconds internally; the file format's
+//! float seconds are only used at the parse/write boundary. The JNI API
+//! takes and returns times as `jlong` microseconds too; `micros_arg`
+//! checks the ones passed in.
+//!
+//! Both v2 and v3 files are read into the same model. v3 (asciinema 3.x)
+//! puts the size under `term` in the header, writes each event's time as
+//! the interval since the one before, and allows `#` comment lines;
+//! `Timing` turns its intervals into times from the start on the way in
+//! and back on the way out, so a v3 file is written back as v3 and nothing
+//! past the parser sees a difference. The header object is kept as read
+//! either way.
+
+use crate::castfile::{self, CastFile};
+use crate::handles::{self, Kind};
+use crate::json::{self, JsonError, Value};
+use jni::objects::{JByteArray, JClass, JIntArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::fmt;
+use std::io::{self, Cursor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CastError {
+    Empty,
+    Json { line: usize, error: JsonError },
+    InvalidHeader(&'static str),
+    UnsupportedVersion(u32),
+    InvalidEvent { line: usize },
+    /// Reading a streamed file failed, see `castfile`
+    Io(io::ErrorKind),
+    /// A gzip or Zstandard file, in a build without the `compression`
+    /// feature
+    Compressed,
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CastError::Empty => write!(f, "empty asciicast file"),
+            CastError::Json { line, error } => write!(f, "line {}: {}", line, error),
+            CastError::InvalidHeader(reason) => write!(f, "invalid header: {}", reason),
+            CastError::UnsupportedVersion(v) => write!(f, "unsupported asciicast version: {}", v),
+            CastError::InvalidEvent { line } => write!(f, "line {}: invalid event", line),
+            CastError::Io(kind) => write!(f, "read failed: {}", kind),
+            CastError::Compressed => {
+                write!(f, "compressed cast, but built without the `compression` feature")
+            }
+        }
+    }
+}
+
+/// Cast header. `fields` holds the full JSON object so that rewriting a
+/// file preserves keys we don't interpret (env, theme, title, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub cols: usize,
+    pub rows: usize,
+    pub fields: Value,
+}
+
+impl Header {
+    pub(crate) fn from_json(fields: Value) -> Result<Self, CastError> {
+        if !matches!(fields, Value::Object(_)) {
+            return Err(CastError::InvalidHeader("not an object"));
+        }
+
+        let version = fields
+            .get("version")
+            .and_then(Value::as_f64)
+            .ok_or(CastError::InvalidHeader("missing version"))?;
+        // `as` would take 2.7 for 2 and -1 for 0
+        if version.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&version) {
+            return Err(CastError::InvalidHeader("version not an integer"));
+        }
+        let version = version as u32;
+        let (cols, rows) = match version {
+            2 => (fields.get("width"), fields.get("height")),
+            3 => {
+                let term = fields.get("term");
+                let size = |key| term.and_then(|term| term.get(key));
+                (size("cols"), size("rows"))
+            }
+            _ => return Err(CastError::UnsupportedVersion(version)),
+        };
+        let cols = cols.and_then(Value::as_f64).unwrap_or(80.0) as usize;
+        let rows = rows.and_then(Value::as_f64).unwrap_or(24.0) as usize;
+
+        Ok(Header {
+            version,
+            cols,
+            rows,
+            fields,
+        })
+    }
+
+    /// Whether `line` of the file is a comment rather than an event.
+    pub(crate) fn is_comment(&self, line: &str) -> bool {
+        self.version >= 3 && line.trim_start().starts_with('#')
+    }
+}
+
+/// Event times as a header's version writes them: from the start in v2,
+/// since the previous event in v3. The default is v2's.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    relative: bool,
+    last_us: i64,
+}
+
+impl Timing {
+    pub fn new(header: &Header) -> Self {
+        Timing {
+            relative: header.version >= 3,
+            last_us: 0,
+        }
+    }
+
+    /// The time from the start of an event that says `time_us`. `None`
+    /// if the intervals add up past `MAX_TIME_US`.
+    pub fn read(&mut self, time_us: i64) -> Option<i64> {
+        if !self.relative {
+            return Some(time_us);
+        }
+        let time_us = self.last_us + time_us;
+        self.last_us = time_us;
+        (time_us.abs() <= MAX_TIME_US).then_some(time_us)
+    }
+
+    /// `event` as a line (without trailing newline), with its time as
+    /// this version writes it.
+    pub fn line(&mut self, event: &Event) -> String {
+        if !self.relative {
+            return event.to_line();
+        }
+        let interval = event.time_us - self.last_us;
+        self.last_us = event.time_us;
+        event.line_at(interval)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Output(String),
+    Input(String),
+    Marker(String),
+    Resize { cols: usize, rows: usize },
+    /// Event codes we don't interpret, kept verbatim for rewriting
+    Other { code: String, data: Value },
+}
+
+impl EventKind {
+    pub fn code(&self) -> &str {
+        match self {
+            EventKind::Output(_) => "o",
+            EventKind::Input(_) => "i",
+            EventKind::Marker(_) => "m",
+            EventKind::Resize { .. } => "r",
+            EventKind::Other { code, .. } => code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub time_us: i64,
+    pub kind: EventKind,
+}
+
+impl Event {
+    pub(crate) fn from_json(value: &Value, line: usize) -> Result<Self, CastError> {
+        let items = value.as_array().ok_or(CastError::InvalidEvent { line })?;
+        let (time, code) = match items {
+            [time, code, ..] => (time.as_f64(), code.as_str()),
+            _ => return Err(CastError::InvalidEvent { line }),
+        };
+        let (time, code) = match (time, code) {
+            (Some(t), Some(c)) => (t, c),
+            _ => return Err(CastError::InvalidEvent { line }),
+        };
+        let data = items.get(2).cloned().unwrap_or(Value::Null);
+        let text = data.as_str().map(str::to_string);
+
+        let kind = match (code, text) {
+            ("o", Some(text)) => EventKind::Output(text),
+            ("i", Some(text)) => EventKind::Input(text),
+            ("m", Some(text)) => EventKind::Marker(text),
+            ("r", Some(text)) => match parse_size(&text) {
+                Some((cols, rows)) => EventKind::Resize { cols, rows },
+                None => EventKind::Other {
+                    code: code.to_string(),
+                    data,
+                },
+            },
+            _ => EventKind::Other {
+                code: code.to_string(),
+                data,
+            },
+        };
+
+        Ok(Event {
+            time_us: seconds_to_micros(time).ok_or(CastError::InvalidEvent { line })?,
+            kind,
+        })
+    }
+
+    /// Serialize as a single NDJSON line (without trailing newline), time
+    /// from the start as v2 writes it; see `Timing` for v3.
+    pub fn to_line(&self) -> String {
+        self.line_at(self.time_us)
+    }
+
+    fn line_at(&self, time_us: i64) -> String {
+        let mut out = String::new();
+        out.push('[');
+        out.push_str(&format_time(time_us));
+        out.push_str(", ");
+        json::write_string(&mut out, self.kind.code());
+        out.push_str(", ");
+        match &self.kind {
+            EventKind::Output(text) | EventKind::Input(text) | EventKind::Marker(text) => {
+                json::write_string(&mut out, text);
+            }
+            EventKind::Resize { cols, rows } => {
+                json::write_string(&mut out, &format!("{}x{}", cols, rows));
+            }
+            EventKind::Other { data, .. } => data.write(&mut out),
+        }
+        out.push(']');
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cast {
+    pub header: Header,
+    pub events: Vec<Event>,
+}
+
+registered!(Cast, Kind::Cast);
+
+impl Cast {
+    pub fn parse(bytes: &[u8]) -> Result<Cast, CastError> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l))
+            .filter(|(_, l)| !l.trim().is_empty());
+
+        let (header_line, header_text) = lines.next().ok_or(CastError::Empty)?;
+        let header_json = json::parse(header_text).map_err(|error| CastError::Json {
+            line: header_line,
+            error,
+        })?;
+        let header = Header::from_json(header_json)?;
+
+        let mut timing = Timing::new(&header);
+        let mut events = Vec::new();
+        for (line, event_text) in lines {
+            if header.is_comment(event_text) {
+                continue;
+            }
+            let value = json::parse(event_text).map_err(|error| CastError::Json { line, error })?;
+            let mut event = Event::from_json(&value, line)?;
+            event.time_us = timing
+                .read(event.time_us)
+                .ok_or(CastError::InvalidEvent { line })?;
+            events.push(event);
+        }
+
+        Ok(Cast { header, events })
+    }
+
+    /// Largest `(cols, rows)` reached by the header size or any resize
+    /// event, each maximized on its own: the grid that fits every frame.
+    pub fn max_dimensions(&self) -> (usize, usize) {
+        self.events
+            .iter()
+            .fold((self.header.cols, self.header.rows), |(cols, rows), event| {
+                match event.kind {
+                    EventKind::Resize { cols: c, rows: r } => (cols.max(c), rows.max(r)),
+                    _ => (cols, rows),
+                }
+            })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = self.header.fields.to_string();
+        out.push('\n');
+        let mut timing = Timing::new(&self.header);
+        for event in &self.events {
+            out.push_str(&timing.line(event));
+            out.push('\n');
+        }
+        out.into_bytes()
+    }
+}
+
+/// Latest time, in microseconds either side of zero, a cast can hold:
+/// past it float seconds no longer keep every microsecond. Adding two
+/// times in range can't overflow an `i64`.
+pub const MAX_TIME_US: i64 = 1 << 53;
+
+/// Float seconds as whole microseconds. `None` for NaN, an infinity, or a
+/// time past `MAX_TIME_US`.
+pub fn seconds_to_micros(seconds: f64) -> Option<i64> {
+    let micros = (seconds * 1_000_000.0).round();
+    (micros.abs() <= MAX_TIME_US as f64).then_some(micros as i64)
+}
+
+/// A time or duration in microseconds passed in over JNI. `None` if it is
+/// negative or past `MAX_TIME_US`.
+pub fn micros_arg(time_us: jlong) -> Option<i64> {
+    (0..=MAX_TIME_US).contains(&time_us).then_some(time_us)
+}
+
+/// Format microseconds the way asciinema writes times: seconds with six decimals.
+pub fn format_time(time_us: i64) -> String {
+    let sign = if time_us < 0 { "-" } else { "" };
+    let abs = time_us.unsigned_abs();
+    format!("{}{}.{:06}", sign, abs / 1_000_000, abs % 1_000_000)
+}
+
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (cols, rows) = size.split_once(['x', 'X'])?;
+    Some((cols.trim().parse().ok()?, rows.trim().parse().ok()?))
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    cast_bytes: JByteArray,
+) -> jlong {
+    jni_guard!(env, {
+        let bytes = match env.convert_byte_array(cast_bytes) {
+            Ok(b) => b,
+            Err(_) => return 0,
+        };
+
+        // Compressed casts are parsed as they're inflated rather than after
+        let cast = if castfile::is_compressed(&bytes) {
+            CastFile::new(Box::new(Cursor::new(bytes))).and_then(|mut file| file.finish())
+        } else {
+            Cast::parse(&bytes)
+        };
+        match cast {
+            Ok(cast) => handles::add(cast),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Cast>(&mut env, handle));
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castScanDimensions<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JIntArray::default();
+        };
+
+        let (cols, rows) = cast.max_dimensions();
+        crate::int_array(&env, &[cols as i32, rows as i32])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = concat!(
+        "{\"version\": 2, \"width\": 80, \"height\": 24, \"title\": \"demo\"}\n",
+        "[0.5, \"o\", \"$ \"]\n",
+        "[1.25, \"i\", \"ls\\r\"]\n",
+        "\n",
+        "[1.3, \"r\", \"100x30\"]\n",
+        "[2.0, \"m\", \"chapter\"]\n",
+        "[3.0, \"z\", {\"custom\": true}]\n",
+    );
+
+    #[test]
+    fn parses_header_and_events() {
+        let cast = Cast::parse(SAMPLE.as_bytes()).unwrap();
+        assert_eq!((cast.header.cols, cast.header.rows), (80, 24));
+        assert_eq!(cast.events.len(), 5);
+        assert_eq!(cast.events[1].time_us, 1_250_000);
+        assert_eq!(cast.events[1].kind, EventKind::Input("ls\r".into()));
+        assert_eq!(cast.events[2].kind, EventKind::Resize { cols: 100, rows: 30 });
+        assert_eq!(cast.events[4].kind.code(), "z");
+    }
+
+    #[test]
+    fn max_dimensions_cover_every_resize() {
+        let cast = Cast::parse(SAMPLE.as_bytes()).unwrap();
+        assert_eq!(cast.max_dimensions(), (100, 30));
+
+        let narrow = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"r\", \"40x50\"]\n\
+            [2.0, \"r\", \"90x10\"]\n";
+        assert_eq!(Cast::parse(narrow).unwrap().max_dimensions(), (90, 50));
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let cast = Cast::parse(SAMPLE.as_bytes()).unwrap();
+        let written = cast.write();
+        assert_eq!(Cast::parse(&written).unwrap(), cast);
+        assert!(String::from_utf8(written).unwrap().contains("[1.250000, \"i\", \"ls\\r\"]"));
+    }
+
+    #[test]
+    fn reads_and_writes_v3() {
+        let text = concat!(
+            "{\"version\": 3, \"term\": {\"cols\": 100, \"rows\": 30, \"type\": \"xterm\"}}\n",
+            "# recorded on the train\n",
+            "[0.5, \"o\", \"$ \"]\n",
+            "[0.75, \"i\", \"ls\\r\"]\n",
+            "[1.0, \"r\", \"120x40\"]\n",
+            "[0.25, \"x\", \"0\"]\n",
+        );
+        let cast = Cast::parse(text.as_bytes()).unwrap();
+        assert_eq!((cast.header.version, cast.header.cols, cast.header.rows), (3, 100, 30));
+        let times: Vec<_> = cast.events.iter().map(|event| event.time_us).collect();
+        assert_eq!(times, [500_000, 1_250_000, 2_250_000, 2_500_000]);
+        assert_eq!(cast.events[2].kind, EventKind::Resize { cols: 120, rows: 40 });
+        assert_eq!(cast.events[3].kind.code(), "x");
+
+        // Written back as intervals, without the comment
+        let written = String::from_utf8(cast.write()).unwrap();
+        assert!(written.contains("\n[0.750000, \"i\", \"ls\\r\"]\n[1.000000, \"r\""));
+        assert_eq!(Cast::parse(written.as_bytes()).unwrap(), cast);
+        // Comments are only a v3 thing
+        assert!(Cast::parse(b"{\"version\": 2}\n# no\n").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(Cast::parse(b""), Err(CastError::Empty));
+        assert_eq!(
+            Cast::parse(b"{\"version\": 1}"),
+            Err(CastError::UnsupportedVersion(1))
+        );
+        for version in ["2.7", "-1", "1e10"] {
+            assert_eq!(
+                Cast::parse(format!("{{\"version\": {}}}", version).as_bytes()),
+                Err(CastError::InvalidHeader("version not an integer")),
+                "{}",
+                version
+            );
+        }
+        assert_eq!(
+            Cast::parse(b"{\"version\": 2}\n[1.0]\n"),
+            Err(CastError::InvalidEvent { line: 2 })
+        );
+        assert_eq!(
+            Cast::parse(b"{\"version\": 2}\n[1e300, \"o\", \"x\"]\n"),
+            Err(CastError::InvalidEvent { line: 2 })
+        );
+    }
+
+    #[test]
+    fn time_conversions_check_their_range() {
+        assert_eq!(seconds_to_micros(1.25), Some(1_250_000));
+        assert_eq!(seconds_to_micros(-0.5), Some(-500_000));
+        assert_eq!(seconds_to_micros(f64::NAN), None);
+        assert_eq!(seconds_to_micros(1e12), None);
+
+        assert_eq!(micros_arg(0), Some(0));
+        assert_eq!(micros_arg(MAX_TIME_US), Some(MAX_TIME_US));
+        assert_eq!(micros_arg(-1), None);
+        assert_eq!(micros_arg(jlong::MAX), None);
+    }
+}
@@ -0,0 +1,690 @@
+//! Streaming asciicast reader (v2 or v3) with batched event delivery.
+//!
+//! `Cast::parse` holds the whole recording in memory, which is what
+//! editing needs but more than playback does. `CastReader` parses one line
+//! at a time from any `BufRead`, so a long recording is read no faster than
+//! it plays, and `CastFile` hands events to Java in batches, binary
+//! encoded, so the app makes one JNI call per batch rather than parsing
+//! JSON in Kotlin.
+//!
+//! Batch layout (varints as in the snapshot format):
+//!
+//! ```text
+//! batch   := event_count event*
+//! event   := time_us code:u8 payload_len payload
+//! payload := text (o, i, m) | cols rows (r)
+//! ```
+//!
+//! Only output, input, marker and resize events are delivered; other codes
+//! are skipped. Times are microseconds from the start, negative ones
+//! clamped to 0. A batch with no events means the file is exhausted, either
+//! at its end or at the first malformed line, see `CastFile::error`.
+//!
+//! Files compressed with gzip or Zstandard (`.cast.gz`, `.cast.zst`) are
+//! recognized by their magic numbers and decompressed as they're read,
+//! see `decompressed`. Builds without the `compression` feature recognize
+//! them only to refuse them.
+//!
+//! The player screen can open in two steps: `castOpenHeader` reads just
+//! the header line, enough to lay out the chrome and the first (empty)
+//! frame at the recorded size, and `castFileFinish`, run as a background
+//! job, parses the rest into a `Cast` for seeking and checkpoint indexing.
+//!
+//! A recording still being written can be followed like `tail -f`:
+//! `castOpenFollow` reads it through a `Tail`, which hands on only whole
+//! lines, and each `castPollNewEvents` picks up where the last one
+//! stopped, delivering the events appended since. A line the recorder is
+//! halfway through writing waits for its newline.
+
+use crate::cast::{Cast, CastError, Event, EventKind, Header, Timing};
+use crate::handles::{self, Kind};
+#[cfg(feature = "compression")]
+use crate::{gzip, zstd};
+use crate::{json, write_varint, write_varint_u64};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+pub struct CastReader<R> {
+    reader: R,
+    header: Header,
+    timing: Timing,
+    /// Number of the last line read, from 1
+    line: usize,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: BufRead> CastReader<R> {
+    /// Read and check the header line.
+    pub fn new(reader: R) -> Result<Self, CastError> {
+        let mut cast = CastReader {
+            reader,
+            header: Header {
+                version: 0,
+                cols: 0,
+                rows: 0,
+                fields: json::Value::Null,
+            },
+            timing: Timing::default(),
+            line: 0,
+            buf: Vec::new(),
+            done: false,
+        };
+        let (line, text) = cast.next_line()?.ok_or(CastError::Empty)?;
+        let fields = json::parse(&text).map_err(|error| CastError::Json { line, error })?;
+        cast.header = Header::from_json(fields)?;
+        cast.timing = Timing::new(&cast.header);
+        Ok(cast)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The next non-blank line and its number.
+    fn next_line(&mut self) -> Result<Option<(usize, String)>, CastError> {
+        loop {
+            self.buf.clear();
+            let n = self
+                .reader
+                .read_until(b'\n', &mut self.buf)
+                .map_err(|e| CastError::Io(e.kind()))?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            let text = String::from_utf8_lossy(&self.buf);
+            if !text.trim().is_empty() {
+                return Ok(Some((self.line, text.into_owned())));
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, CastError> {
+        let (line, text) = loop {
+            match self.next_line()? {
+                Some((_, text)) if self.header.is_comment(&text) => continue,
+                Some(next) => break next,
+                None => return Ok(None),
+            }
+        };
+        let value = json::parse(&text).map_err(|error| CastError::Json { line, error })?;
+        let mut event = Event::from_json(&value, line)?;
+        event.time_us = self
+            .timing
+            .read(event.time_us)
+            .ok_or(CastError::InvalidEvent { line })?;
+        Ok(Some(event))
+    }
+}
+
+impl<R: BufRead> CastReader<R> {
+    /// Read on after the end of the input, once more has been written to
+    /// it. The stream stays ended after an error, since the bad line has
+    /// been read past.
+    fn resume(&mut self, error: bool) {
+        self.done = error;
+    }
+}
+
+/// Events in file order; stops after the first error.
+impl<R: BufRead> Iterator for CastReader<R> {
+    type Item = Result<Event, CastError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_event().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+/// The start of every gzip member
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// `0xfd2fb528` little-endian, the start of every Zstandard frame
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether `head`, the start of a file, is a gzip or Zstandard stream.
+pub fn is_compressed(head: &[u8]) -> bool {
+    head.starts_with(&GZIP_MAGIC) || head.starts_with(&ZSTD_MAGIC)
+}
+
+/// `reader` decompressing as it's read if it's a gzip or Zstandard stream
+/// (see `is_compressed`), else `reader` as it is. Without the
+/// `compression` feature a compressed stream is `CastError::Compressed`.
+pub fn decompressed(mut reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>, CastError> {
+    let io_error = |e: io::Error| CastError::Io(e.kind());
+    // A reader may fill its buffer with less than a magic number
+    let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+    while head.len() < ZSTD_MAGIC.len() {
+        let buf = reader.fill_buf().map_err(io_error)?;
+        if buf.is_empty() {
+            break;
+        }
+        let n = buf.len().min(ZSTD_MAGIC.len() - head.len());
+        head.extend_from_slice(&buf[..n]);
+        reader.consume(n);
+    }
+    let is_gzip = head.starts_with(&GZIP_MAGIC);
+    let is_zstd = head.starts_with(&ZSTD_MAGIC);
+    let reader: Box<dyn BufRead> = Box::new(Cursor::new(head).chain(reader));
+    #[cfg(feature = "compression")]
+    if is_gzip {
+        return Ok(Box::new(gzip::Decoder::new(reader).map_err(io_error)?));
+    } else if is_zstd {
+        return Ok(Box::new(zstd::Decoder::new(reader).map_err(io_error)?));
+    }
+    #[cfg(not(feature = "compression"))]
+    if is_gzip || is_zstd {
+        return Err(CastError::Compressed);
+    }
+    Ok(reader)
+}
+
+/// Reads only whole lines of `inner`: bytes after the last newline are
+/// held back until the rest of their line arrives, and whatever is read
+/// later, once the file has grown, carries on from there.
+pub struct Tail<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    /// Just past the last newline in `buf`
+    end: usize,
+}
+
+impl<R: Read> Tail<R> {
+    pub fn new(inner: R) -> Self {
+        Tail {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Tail<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let n = buf.len().min(out.len());
+        out[..n].copy_from_slice(&buf[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Tail<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.end {
+            self.buf.drain(..self.end);
+            (self.pos, self.end) = (0, 0);
+            let mut chunk = [0; 8192];
+            loop {
+                let n = match self.inner.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                self.buf.extend_from_slice(&chunk[..n]);
+                if let Some(at) = chunk[..n].iter().rposition(|&b| b == b'\n') {
+                    self.end = self.buf.len() - n + at + 1;
+                    break;
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..self.end])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.end);
+    }
+}
+
+/// A reader open for Java, over a file or an in-memory copy.
+pub struct CastFile {
+    reader: CastReader<Box<dyn BufRead>>,
+    error: Option<CastError>,
+}
+
+registered!(CastFile, Kind::CastFile);
+
+impl CastFile {
+    /// Read and check the header of `reader`, decompressed if need be.
+    pub fn new(reader: Box<dyn BufRead>) -> Result<Self, CastError> {
+        let reader = decompressed(reader)?;
+        Ok(CastFile {
+            reader: CastReader::new(reader)?,
+            error: None,
+        })
+    }
+
+    /// Follow `reader`, a recording still being written, see `Tail`. It
+    /// isn't decompressed, since a compressor holds back what it hasn't
+    /// flushed.
+    pub fn follow<R: Read + 'static>(reader: R) -> Result<Self, CastError> {
+        let reader: Box<dyn BufRead> = Box::new(Tail::new(reader));
+        Ok(CastFile {
+            reader: CastReader::new(reader)?,
+            error: None,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        self.reader.header()
+    }
+
+    /// What ended the stream early, if anything did.
+    pub fn error(&self) -> Option<&CastError> {
+        self.error.as_ref()
+    }
+
+    /// The events not yet read, of every code, as a `Cast`. Stops at the
+    /// first error, which `error` reports after.
+    pub fn finish(&mut self) -> Result<Cast, CastError> {
+        match self.reader.by_ref().collect() {
+            Ok(events) => Ok(Cast {
+                header: self.header().clone(),
+                events,
+            }),
+            Err(error) => {
+                self.error = Some(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    /// Encode the deliverable events written since the last poll as a
+    /// batch, empty if there are none yet or the stream ended at an error.
+    pub fn poll(&mut self) -> Vec<u8> {
+        self.reader.resume(self.error.is_some());
+        self.next_batch(usize::MAX)
+    }
+
+    /// Encode up to `max` deliverable events as a batch.
+    pub fn next_batch(&mut self, max: usize) -> Vec<u8> {
+        let mut events = Vec::new();
+        while events.len() < max {
+            match self.reader.next() {
+                Some(Ok(event)) => {
+                    if !matches!(event.kind, EventKind::Other { .. }) {
+                        events.push(event);
+                    }
+                }
+                Some(Err(error)) => {
+                    self.error = Some(error);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let mut buf = Vec::new();
+        write_varint(&mut buf, events.len());
+        for event in &events {
+            let mut payload = Vec::new();
+            match &event.kind {
+                EventKind::Output(text) | EventKind::Input(text) | EventKind::Marker(text) => {
+                    payload.extend_from_slice(text.as_bytes())
+                }
+                EventKind::Resize { cols, rows } => {
+                    write_varint(&mut payload, *cols);
+                    write_varint(&mut payload, *rows);
+                }
+                EventKind::Other { .. } => continue,
+            }
+            write_varint_u64(&mut buf, event.time_us.max(0) as u64);
+            buf.push(event.kind.code().as_bytes()[0]);
+            write_varint(&mut buf, payload.len());
+            buf.extend_from_slice(&payload);
+        }
+        buf
+    }
+}
+
+// JNI functions
+
+pub(crate) fn into_handle(file: Result<CastFile, CastError>) -> jlong {
+    match file {
+        Ok(file) => handles::add(file),
+        Err(_) => 0,
+    }
+}
+
+/// Open the cast at `path` for streaming. Returns 0 if the file can't be
+/// opened or its header is invalid.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jlong {
+    jni_guard!(env, {
+        let Ok(path) = env.get_string(&path) else {
+            return 0;
+        };
+        let path: String = path.into();
+        let Ok(file) = File::open(path) else {
+            return 0;
+        };
+
+        into_handle(CastFile::new(Box::new(BufReader::new(file))))
+    })
+}
+
+/// `castFileOpen` over a copy of `cast_bytes`, for casts that aren't files
+/// (assets, downloads).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileOpenBytes(
+    mut env: JNIEnv,
+    _class: JClass,
+    cast_bytes: JByteArray,
+) -> jlong {
+    jni_guard!(env, {
+        let Ok(bytes) = env.convert_byte_array(cast_bytes) else {
+            return 0;
+        };
+
+        into_handle(CastFile::new(Box::new(Cursor::new(bytes))))
+    })
+}
+
+/// `castFileOpen` over `fd`, which the handle takes ownership of (and
+/// which is closed on failure), reading only the header line.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenHeader(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+) -> jlong {
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        into_handle(CastFile::new(Box::new(BufReader::new(file))))
+    })
+}
+
+/// Follow the cast being written to `fd`, which the handle takes
+/// ownership of (and which is closed on failure); read its events with
+/// `castPollNewEvents`. Returns 0 if the header line isn't valid, or
+/// isn't all written yet.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenFollow(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+) -> jlong {
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        into_handle(CastFile::follow(file))
+    })
+}
+
+/// The events appended since the last poll of a `castOpenFollow` handle,
+/// as a batch, see `CastFile::poll`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castPollNewEvents<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(file) = handles::object::<CastFile>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let batch = file.poll();
+        env.byte_array_from_slice(&batch).unwrap_or_default()
+    })
+}
+
+/// Parse the events `handle` hasn't delivered into a cast handle (free
+/// with `castFree`), see `CastFile::finish`. Reads to the end of the file,
+/// so call it off the main thread. Returns 0 for an invalid handle or a
+/// malformed line.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileFinish(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(file) = handles::object::<CastFile>(&mut env, handle) else {
+            return 0;
+        };
+
+        match file.finish() {
+            Ok(cast) => handles::add(cast),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<CastFile>(&mut env, handle));
+    })
+}
+
+/// The header line as JSON, all keys included.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileHeader<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(file) = handles::object_ref::<CastFile>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        env.new_string(file.header().fields.to_string())
+            .unwrap_or_default()
+    })
+}
+
+/// The next batch of at most `max_count` events.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileNextEvents<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    max_count: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        if max_count <= 0 {
+            return JByteArray::default();
+        }
+        let Some(file) = handles::object::<CastFile>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let batch = file.next_batch(max_count as usize);
+        env.byte_array_from_slice(&batch).unwrap_or_default()
+    })
+}
+
+/// Why the stream ended early, or null if it hasn't.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFileError<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(file) = handles::object_ref::<CastFile>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        match file.error() {
+            Some(error) => env.new_string(error.to_string()).unwrap_or_default(),
+            None => JString::default(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Reader;
+
+    const SAMPLE: &[u8] = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+        [0.5, \"o\", \"$ \"]\n\
+        \n\
+        [1.25, \"i\", \"ls\\r\"]\n\
+        [1.3, \"r\", \"100x30\"]\n\
+        [1.4, \"x\", \"0\"]\n\
+        [2.0, \"m\", \"chapter\"]\n\
+        [3.0]\n\
+        [4.0, \"o\", \"never read\"]\n";
+
+    fn decode(batch: &[u8]) -> Vec<(usize, u8, Vec<u8>)> {
+        let mut r = Reader::new(batch);
+        let count = r.varint().unwrap();
+        (0..count)
+            .map(|_| {
+                let time = r.varint().unwrap();
+                let code = r.byte().unwrap();
+                let len = r.varint().unwrap();
+                (time, code, r.take(len).unwrap().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streams_events_and_stops_at_the_first_error() {
+        let reader = CastReader::new(SAMPLE).unwrap();
+        assert_eq!((reader.header().cols, reader.header().rows), (80, 24));
+
+        let events: Vec<_> = reader.collect();
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            events[2],
+            Ok(Event {
+                time_us: 1_300_000,
+                kind: EventKind::Resize {
+                    cols: 100,
+                    rows: 30
+                },
+            })
+        );
+        assert_eq!(events[5], Err(CastError::InvalidEvent { line: 8 }));
+    }
+
+    #[test]
+    fn batches_skip_other_codes() {
+        let mut file = CastFile::new(Box::new(SAMPLE)).unwrap();
+        assert_eq!(
+            decode(&file.next_batch(3)),
+            [
+                (500_000, b'o', b"$ ".to_vec()),
+                (1_250_000, b'i', b"ls\r".to_vec()),
+                (1_300_000, b'r', vec![100, 30]),
+            ]
+        );
+
+        // The exit event is skipped, then the bad line ends the stream
+        assert_eq!(
+            decode(&file.next_batch(3)),
+            [(2_000_000, b'm', b"chapter".to_vec())]
+        );
+        assert_eq!(file.error(), Some(&CastError::InvalidEvent { line: 8 }));
+        assert_eq!(file.next_batch(3), [0]);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_casts_are_read_as_they_inflate() {
+        let open = |bytes: &'static [u8]| CastFile::new(Box::new(bytes)).unwrap().finish().unwrap();
+        let gzip = open(include_bytes!("../fixtures/casts/session.cast.gz"));
+        let zstd = open(include_bytes!("../fixtures/casts/session.cast.zst"));
+        assert_eq!((gzip.header.cols, gzip.events.len()), (80, 3547));
+        assert_eq!(gzip, zstd);
+        assert!(is_compressed(&ZSTD_MAGIC) && !is_compressed(SAMPLE));
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn compressed_casts_need_the_compression_feature() {
+        let gzip: &[u8] = include_bytes!("../fixtures/casts/session.cast.gz");
+        assert!(matches!(CastFile::new(Box::new(gzip)), Err(CastError::Compressed)));
+        assert!(is_compressed(&ZSTD_MAGIC) && !is_compressed(SAMPLE));
+    }
+
+    #[test]
+    fn following_delivers_appended_lines_once_whole() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("follow-test-{}", std::process::id()));
+        let mut writer = File::create(&path).unwrap();
+        // v3 times are since the previous event, so timing carries across polls
+        writer
+            .write_all(b"{\"version\": 3, \"term\": {\"cols\": 80, \"rows\": 24}}\n")
+            .unwrap();
+        writer.write_all(b"[0.5, \"o\", \"a\"]\n[0.25, \"o\", \"b").unwrap();
+        let mut file = CastFile::follow(File::open(&path).unwrap()).unwrap();
+        assert_eq!(decode(&file.poll()), [(500_000, b'o', b"a".to_vec())]);
+        assert_eq!(file.poll(), [0]);
+
+        writer.write_all(b"c\"]\n\n[1.0, \"r\", \"100x30\"]\n").unwrap();
+        assert_eq!(
+            decode(&file.poll()),
+            [
+                (750_000, b'o', b"bc".to_vec()),
+                (1_750_000, b'r', vec![100, 30]),
+            ]
+        );
+
+        writer.write_all(b"[2.0]\n[1.0, \"o\", \"late\"]\n").unwrap();
+        assert_eq!(file.poll(), [0]);
+        assert_eq!(file.error(), Some(&CastError::InvalidEvent { line: 6 }));
+        assert_eq!(file.poll(), [0]);
+        std::fs::remove_file(&path).unwrap();
+
+        // A header still being written isn't enough to follow
+        assert!(CastFile::follow(&b"{\"version\": 2, \"wid"[..]).is_err());
+    }
+
+    #[test]
+    fn finish_parses_what_is_left() {
+        let bytes: &[u8] = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [0.5, \"o\", \"$ \"]\n\
+            [1.4, \"x\", \"0\"]\n\
+            [2.0, \"m\", \"chapter\"]\n";
+        let mut file = CastFile::new(Box::new(bytes)).unwrap();
+        assert_eq!(decode(&file.next_batch(1)).len(), 1);
+        let cast = file.finish().unwrap();
+        assert_eq!(cast.header, *file.header());
+        let codes: Vec<_> = cast.events.iter().map(|e| e.kind.code()).collect();
+        assert_eq!(codes, ["x", "m"]);
+        assert_eq!(file.next_batch(3), [0]);
+
+        let mut file = CastFile::new(Box::new(SAMPLE)).unwrap();
+        assert_eq!(file.finish(), Err(CastError::InvalidEvent { line: 8 }));
+        assert_eq!(file.error(), Some(&CastError::InvalidEvent { line: 8 }));
+    }
+}
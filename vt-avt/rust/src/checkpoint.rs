@@ -0,0 +1,349 @@
+//! Checkpoint index for fast backward seeking.
+//!
+//! `player::seek` replays a cast from the start, so every backward scrub in
+//! a long recording costs as much as playing it up to there. An index
+//! keeps saved states (see `state`) taken every so often along the way;
+//! a seek restores the last one at or before the target and replays only
+//! the events after it.
+//!
+//! The index fills in as seeks replay: whenever enough playback time or
+//! output has passed since the last checkpoint, a new one is saved. Only
+//! between sequences, though (`AvtState::is_at_boundary`), since a dump
+//! can't hold half an escape sequence. Nothing is saved ahead of time, so
+//! scrubbing forward through a recording indexes it as it goes.
+//!
+//! Restoring replays a dump, which recreates the screen but not the
+//! scrollback, so a seek through a checkpoint has only the scrollback
+//! produced since it.
+//!
+//! States of one cast differ in little more than the text on screen, so
+//! the first few saved are the samples a zstd dictionary is trained on,
+//! and every state is kept compressed against it. Until then they're held
+//! as saved. Samples too few or small to train on make the first state
+//! the dictionary, as raw content.
+
+use crate::backend::TerminalBackend;
+use crate::cast::{micros_arg, Cast, EventKind};
+use crate::handles::{self, Kind};
+use crate::{player, state, AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    /// Index of the first event not yet applied
+    next: usize,
+    time_us: i64,
+    /// Output bytes fed up to here
+    fed: usize,
+    /// Saved state, packed against the index's dictionary once it has one
+    state: Vec<u8>,
+    /// Length of the state unpacked
+    len: usize,
+}
+
+/// States saved before the dictionary is trained on them
+const SAMPLES: usize = 8;
+
+/// Largest dictionary trained
+const DICTIONARY_SIZE: usize = 16 << 10;
+
+/// Checkpoints for one cast, which every seek must be given. (A cast with
+/// a different number of events clears the index, as a safety net.)
+#[derive(Debug, Clone)]
+pub struct CheckpointIndex {
+    interval_us: Option<i64>,
+    interval_bytes: Option<usize>,
+    /// Ordered by `next`
+    checkpoints: Vec<Checkpoint>,
+    /// Event count of the cast the checkpoints were taken from
+    cast_len: usize,
+    /// Trained on the first `SAMPLES` states saved
+    dictionary: Option<Vec<u8>>,
+}
+
+registered!(CheckpointIndex, Kind::Checkpoints);
+
+impl CheckpointIndex {
+    /// Checkpoint every `interval_us` of playback time or `interval_bytes`
+    /// of output, whichever comes first. With neither, no checkpoints are
+    /// taken and seeks replay from the start.
+    pub fn new(interval_us: Option<i64>, interval_bytes: Option<usize>) -> Self {
+        CheckpointIndex {
+            interval_us,
+            interval_bytes,
+            checkpoints: Vec::new(),
+            cast_len: 0,
+            dictionary: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// Bytes of saved state held, the dictionary included.
+    pub fn memory(&self) -> usize {
+        let packed: usize = self.checkpoints.iter().map(|c| c.state.len()).sum();
+        packed + self.dictionary.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Train the dictionary on the states held and pack them against it.
+    /// Kept unpacked if zstd fails.
+    fn train(&mut self) {
+        let samples: Vec<&[u8]> = self.checkpoints.iter().map(|c| &c.state[..]).collect();
+        let dictionary = zstd::dict::from_samples(&samples, DICTIONARY_SIZE)
+            .unwrap_or_else(|_| samples[0].to_vec());
+        let Some(packed) = samples
+            .iter()
+            .map(|state| pack(&dictionary, state))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        for (c, state) in self.checkpoints.iter_mut().zip(packed) {
+            c.state = state;
+        }
+        self.dictionary = Some(dictionary);
+    }
+
+    /// Whether a checkpoint is due at `time_us` and `fed`, given those of
+    /// the last one.
+    fn due(&self, (last_us, last_fed): (i64, usize), time_us: i64, fed: usize) -> bool {
+        self.interval_us
+            .is_some_and(|interval| time_us - last_us >= interval)
+            || self
+                .interval_bytes
+                .is_some_and(|interval| fed - last_fed >= interval)
+    }
+
+    /// `player::seek` from the nearest checkpoint, adding checkpoints along
+    /// the way. Returns the events replayed; the VT events they queue are
+    /// dropped, as by `player::seek`.
+    pub fn seek<B: TerminalBackend>(
+        &mut self,
+        vt: &mut AvtState<B>,
+        cast: &Cast,
+        time_us: i64,
+    ) -> usize {
+        if cast.events.len() != self.cast_len {
+            self.checkpoints.clear();
+            self.dictionary = None;
+            self.cast_len = cast.events.len();
+        }
+        let schedule = player::schedule(cast, player::idle_limit(&cast.header));
+        let end = schedule.partition_point(|&at| at <= time_us);
+
+        // The checkpoint to start from and where new ones go after it
+        let mut pos = self.checkpoints.partition_point(|c| c.next <= end);
+        let restore = |vt: &mut AvtState<B>, c: &Checkpoint| {
+            let state = match &self.dictionary {
+                Some(dictionary) => unpack(dictionary, &c.state, c.len)?,
+                None => c.state.clone(),
+            };
+            state::restore(vt, &state).ok()
+        };
+        let (start, mut last) = match pos.checked_sub(1).map(|i| &self.checkpoints[i]) {
+            Some(c) if restore(vt, c).is_some() => (c.next, (c.time_us, c.fed)),
+            _ => {
+                pos = 0;
+                vt.reset(cast.header.cols, cast.header.rows);
+                (0, (0, 0))
+            }
+        };
+
+        let mut fed = last.1;
+        for next in start + 1..=end {
+            let kind = &cast.events[next - 1].kind;
+            player::apply(vt, kind, true);
+            if let EventKind::Output(data) = kind {
+                fed += data.len();
+            }
+
+            let time_us = schedule[next - 1];
+            if let Some(c) = self.checkpoints.get(pos).filter(|c| c.next == next) {
+                last = (c.time_us, c.fed);
+                pos += 1;
+            } else if vt.is_at_boundary() && self.due(last, time_us, fed) {
+                let state = state::save(vt);
+                let len = state.len();
+                let state = match &self.dictionary {
+                    Some(dictionary) => pack(dictionary, &state),
+                    None => Some(state),
+                };
+                if let Some(state) = state {
+                    self.checkpoints.insert(
+                        pos,
+                        Checkpoint {
+                            next,
+                            time_us,
+                            fed,
+                            state,
+                            len,
+                        },
+                    );
+                    pos += 1;
+                    if self.dictionary.is_none() && self.checkpoints.len() == SAMPLES {
+                        self.train();
+                    }
+                }
+                last = (time_us, fed);
+            }
+        }
+        vt.take_events();
+        end - start
+    }
+}
+
+/// `state` compressed against `dictionary`, or `None` if zstd fails.
+fn pack(dictionary: &[u8], state: &[u8]) -> Option<Vec<u8>> {
+    zstd::bulk::Compressor::with_dictionary(zstd::DEFAULT_COMPRESSION_LEVEL, dictionary)
+        .and_then(|mut compressor| compressor.compress(state))
+        .ok()
+}
+
+/// The `len` bytes `pack` compressed against `dictionary`, or `None` if
+/// `packed` is corrupt.
+fn unpack(dictionary: &[u8], packed: &[u8], len: usize) -> Option<Vec<u8>> {
+    zstd::bulk::Decompressor::with_dictionary(dictionary)
+        .and_then(|mut decompressor| decompressor.decompress(packed, len))
+        .ok()
+        .filter(|state| state.len() == len)
+}
+
+// JNI functions
+
+/// Non-positive intervals are unset, see `CheckpointIndex::new`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_checkpointIndexNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    interval_micros: jlong,
+    interval_bytes: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let interval_us = micros_arg(interval_micros).filter(|&us| us > 0);
+        let interval_bytes = (interval_bytes > 0).then_some(interval_bytes as usize);
+        handles::add(CheckpointIndex::new(interval_us, interval_bytes))
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_checkpointIndexFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    index: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<CheckpointIndex>(&mut env, index));
+    })
+}
+
+/// Number of checkpoints held, 0 for an invalid index.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_checkpointIndexLen(
+    mut env: JNIEnv,
+    _class: JClass,
+    index: jlong,
+) -> jint {
+    jni_guard!(env, {
+        let Some(index) = handles::object_ref::<CheckpointIndex>(&mut env, index) else {
+            return 0;
+        };
+
+        index.len() as jint
+    })
+}
+
+/// `vtSeek` through the checkpoint `index`. Returns the resulting
+/// snapshot, empty for an invalid handle or time.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSeekIndexed<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    cast_handle: jlong,
+    index: jlong,
+    time_micros: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JByteArray::default();
+        };
+        let Some(index) = handles::object::<CheckpointIndex>(&mut env, index) else {
+            return JByteArray::default();
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JByteArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        index.seek(vt, cast, time_us);
+        env.byte_array_from_slice(&vt.encode_snapshot())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn seeks_resume_from_the_nearest_checkpoint() {
+        let mut bytes = b"{\"version\": 2, \"width\": 8, \"height\": 1}\n".to_vec();
+        for (i, ch) in "abcdefgh".chars().enumerate() {
+            bytes.extend(format!("[{}.0, \"o\", \"{}\"]\n", i + 1, ch).bytes());
+        }
+        let cast = Cast::parse(&bytes).unwrap();
+        let mut index = CheckpointIndex::new(Some(2_000_000), None);
+        let mut vt = AvtState::with_backend(fake(8, 1));
+
+        // Walking forward replays everything once, checkpointing at 2s, 4s...
+        assert_eq!(index.seek(&mut vt, &cast, 7_500_000), 7);
+        assert_eq!(index.len(), 3);
+        assert_eq!(vt.backend().row_text(0), "abcdefg ");
+        assert_eq!(index.dictionary, None);
+
+        // ...so going back replays only from the 4s checkpoint
+        assert_eq!(index.seek(&mut vt, &cast, 5_000_000), 1);
+        assert_eq!(vt.backend().row_text(0), "abcde   ");
+
+        // Further on, new checkpoints are taken past the last one
+        assert_eq!(index.seek(&mut vt, &cast, 9_000_000), 2);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.seek(&mut vt, &cast, 1_000_000), 1);
+        assert_eq!(vt.backend().row_text(0), "a       ");
+    }
+
+    #[test]
+    fn states_are_packed_once_there_are_samples_to_train_on() {
+        let mut bytes = b"{\"version\": 2, \"width\": 24, \"height\": 2}\n".to_vec();
+        for i in 1..=24 {
+            bytes.extend(format!("[{}.0, \"o\", \"$ echo {}\\r\\n\"]\n", i, i).bytes());
+        }
+        let cast = Cast::parse(&bytes).unwrap();
+        let mut index = CheckpointIndex::new(Some(2_000_000), None);
+        let mut vt = AvtState::with_backend(fake(24, 2));
+
+        assert_eq!(index.seek(&mut vt, &cast, 24_000_000), 24);
+        assert_eq!(index.len(), 12);
+        assert!(index.dictionary.is_some());
+        // The dictionary and a dozen packed cost two thirds of them unpacked
+        let unpacked: usize = index.checkpoints.iter().map(|c| c.len).sum();
+        assert!(index.memory() < unpacked * 2 / 3);
+
+        // Packed states restore what replaying gave
+        let replayed = [0, 1].map(|row| vt.backend().row_text(row));
+        assert_eq!(index.seek(&mut vt, &cast, 11_000_000), 1);
+        assert_eq!(index.seek(&mut vt, &cast, 24_000_000), 0);
+        assert_eq!([0, 1].map(|row| vt.backend().row_text(row)), replayed);
+    }
+}
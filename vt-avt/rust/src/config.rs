@@ -0,0 +1,309 @@
+//! What the VT advertises to the programs it runs.
+//!
+//! Playback never answers anything: the recorded program got its answers
+//! when the cast was made. A live session has to answer queries itself,
+//! and the answers should match what the player can render. A
+//! `TermConfig` given when the VT is created holds those decisions, plus
+//! the environment the session's shell should start with. With one set,
+//! queries found in fed output are answered into a buffer the session
+//! drains with `vtTakeResponses` and writes back to the program:
+//!
+//! - DA1 (`CSI c`) and DA2 (`CSI > c`), with the configured parameters
+//! - DSR (`CSI 5 n`), always "OK"
+//! - ENQ, with the configured answerback, by default nothing (as xterm)
+//! - CPR (`CSI 6 n`) and DECXCPR (`CSI ? 6 n`), with the cursor as of the
+//!   query rather than the end of the feed
+//! - XTGETTCAP (`DCS + q` hex names `ST`): `TN` (terminal name), `Co` /
+//!   `colors`, and with truecolor the `RGB` and `Tc` flags, then any
+//!   configured capabilities, which override these. Unknown names get the
+//!   "not found" reply, one reply per name.
+//!
+//! The defaults advertise what the player renders: 256 colors, direct
+//! color with truecolor, and no graphics (no sixel in DA1), so apps don't
+//! send images that would show as nothing.
+
+use crate::scan::Action;
+use crate::snapshot::Cursor;
+use std::collections::BTreeMap;
+
+/// VT220 with ANSI color
+const DA1: [u16; 2] = [62, 22];
+/// VT220, firmware version 1
+const DA2: [u16; 3] = [1, 1, 0];
+
+/// A configured XTGETTCAP answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capability {
+    /// Boolean capability, set
+    Flag,
+    /// String or numeric capability
+    Value(String),
+    /// Answered "not found", even if built in
+    Absent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermConfig {
+    /// `TERM`, also the XTGETTCAP terminal name
+    pub term: String,
+    /// `COLORTERM`; `truecolor` or `24bit` advertise direct color
+    pub colorterm: Option<String>,
+    /// `LANG`, e.g. `en_US.UTF-8`
+    pub locale: String,
+    /// DA1 reply parameters, e.g. `[62, 22]`; add 4 to advertise sixel
+    pub da1: Vec<u16>,
+    /// DA2 reply parameters: terminal type, version, cartridge
+    pub da2: Vec<u16>,
+    /// XTGETTCAP answers by terminfo name, overriding the built-in ones
+    pub capabilities: BTreeMap<String, Capability>,
+    /// Sent for ENQ; empty sends nothing
+    pub answerback: String,
+}
+
+impl Default for TermConfig {
+    fn default() -> Self {
+        TermConfig {
+            term: "xterm-256color".to_string(),
+            colorterm: Some("truecolor".to_string()),
+            locale: "C.UTF-8".to_string(),
+            da1: DA1.to_vec(),
+            da2: DA2.to_vec(),
+            capabilities: BTreeMap::new(),
+            answerback: String::new(),
+        }
+    }
+}
+
+impl TermConfig {
+    pub fn truecolor(&self) -> bool {
+        matches!(self.colorterm.as_deref(), Some("truecolor" | "24bit"))
+    }
+
+    /// Environment variables for the session's shell.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("TERM", self.term.clone()), ("LANG", self.locale.clone())];
+        if let Some(colorterm) = &self.colorterm {
+            env.push(("COLORTERM", colorterm.clone()));
+        }
+        env
+    }
+
+    /// Append the reply to `action` to `out`, if it is a query.
+    pub(crate) fn answer(&self, action: &Action, out: &mut Vec<u8>) {
+        match action {
+            Action::Control(0x05) => out.extend_from_slice(self.answerback.as_bytes()),
+            Action::Csi(csi)
+                if csi.final_byte == b'c'
+                    && csi.intermediates().is_empty()
+                    && csi.param(0, 0) == 0 =>
+            {
+                let (marker, params) = match csi.marker {
+                    None => ("?", &self.da1),
+                    Some(b'>') => (">", &self.da2),
+                    _ => return,
+                };
+                let params: Vec<String> = params.iter().map(u16::to_string).collect();
+                out.extend_from_slice(format!("\x1b[{}{}c", marker, params.join(";")).as_bytes());
+            }
+            Action::Csi(csi)
+                if csi.final_byte == b'n'
+                    && csi.marker.is_none()
+                    && csi.intermediates().is_empty()
+                    && csi.params() == [5] =>
+            {
+                out.extend_from_slice(b"\x1b[0n");
+            }
+            Action::Dcs(payload) => {
+                if let Some(names) = payload.strip_prefix(b"+q") {
+                    for name in names.split(|&b| b == b';') {
+                        self.capability_reply(name, out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Value of a terminfo capability: `Some(None)` for a set flag.
+    fn capability(&self, name: &str) -> Option<Option<String>> {
+        match self.capabilities.get(name) {
+            Some(Capability::Flag) => return Some(None),
+            Some(Capability::Value(value)) => return Some(Some(value.clone())),
+            Some(Capability::Absent) => return None,
+            None => {}
+        }
+        let colors = if self.truecolor() { 1 << 24 } else { 256 };
+        match name {
+            "TN" | "name" => Some(Some(self.term.clone())),
+            "Co" | "colors" => Some(Some(colors.to_string())),
+            "RGB" | "Tc" if self.truecolor() => Some(None),
+            _ => None,
+        }
+    }
+
+    fn capability_reply(&self, hex_name: &[u8], out: &mut Vec<u8>) {
+        let value = decode_hex(hex_name)
+            .and_then(|name| String::from_utf8(name).ok())
+            .and_then(|name| self.capability(&name));
+        match value {
+            Some(value) => {
+                out.extend_from_slice(b"\x1bP1+r");
+                out.extend_from_slice(hex_name);
+                if let Some(value) = value {
+                    out.push(b'=');
+                    out.extend_from_slice(encode_hex(value.as_bytes()).as_bytes());
+                }
+            }
+            None => {
+                out.extend_from_slice(b"\x1bP0+r");
+                out.extend_from_slice(hex_name);
+            }
+        }
+        out.extend_from_slice(b"\x1b\\");
+    }
+}
+
+/// A request for the cursor position. `TermConfig::answer` leaves these
+/// alone: the VT answers them once it has fed the output before them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CursorQuery {
+    Cpr,
+    Decxcpr,
+}
+
+impl CursorQuery {
+    pub(crate) fn from_action(action: &Action) -> Option<CursorQuery> {
+        let Action::Csi(csi) = action else {
+            return None;
+        };
+        if csi.final_byte != b'n' || !csi.intermediates().is_empty() || csi.params() != [6] {
+            return None;
+        }
+        match csi.marker {
+            None => Some(CursorQuery::Cpr),
+            Some(b'?') => Some(CursorQuery::Decxcpr),
+            _ => None,
+        }
+    }
+
+    /// Append the reply for `cursor` (0-based, and up to `cols`, past the
+    /// last column while a wrap is pending) to `out`. DECXCPR's adds the
+    /// page, always 1.
+    pub(crate) fn answer(self, cursor: &Cursor, cols: usize, out: &mut Vec<u8>) {
+        let (row, col) = (cursor.row + 1, cursor.col.min(cols.saturating_sub(1)) + 1);
+        let reply = match self {
+            CursorQuery::Cpr => format!("\x1b[{};{}R", row, col),
+            CursorQuery::Decxcpr => format!("\x1b[?{};{};1R", row, col),
+        };
+        out.extend_from_slice(reply.as_bytes());
+    }
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::scan::Scanner;
+    use crate::AvtState;
+
+    fn answers(config: &TermConfig, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        Scanner::new().scan(input, |_, action| config.answer(&action, &mut out));
+        out
+    }
+
+    #[test]
+    fn answers_device_attributes_and_capabilities() {
+        let config = TermConfig::default();
+        assert_eq!(
+            answers(&config, b"\x1b[c\x1b[>c\x1b[=c"),
+            b"\x1b[?62;22c\x1b[>1;1;0c"
+        );
+
+        // TN;RGB;xx
+        assert_eq!(
+            answers(&config, b"\x1bP+q544E;524742;7878\x1b\\"),
+            b"\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\\
+              \x1bP1+r524742\x1b\\\
+              \x1bP0+r7878\x1b\\"
+                .as_slice()
+        );
+
+        let plain = TermConfig {
+            colorterm: None,
+            ..TermConfig::default()
+        };
+        assert_eq!(
+            answers(&plain, b"\x1bP+q524742\x1b\\"),
+            b"\x1bP0+r524742\x1b\\"
+        );
+        assert!(answers(&config, b"hi\x1b[1mthere").is_empty());
+        assert_eq!(
+            plain.env(),
+            [
+                ("TERM", "xterm-256color".to_string()),
+                ("LANG", "C.UTF-8".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_status_and_the_cursor_as_of_each_query() {
+        assert_eq!(
+            answers(&TermConfig::default(), b"\x1b[5n\x1b[6n"),
+            b"\x1b[0n"
+        );
+
+        // The fake prints each sequence's final byte
+        let mut vt = AvtState::with_backend(fake(8, 1));
+        vt.feed(b"ab\x1b[6n");
+        assert!(vt.take_responses().is_empty());
+
+        vt.set_config(Some(TermConfig::default()));
+        vt.feed(b"\x1b[6nc\x1b[?6n\x1b[5nxyz\x1b[6n");
+        assert_eq!(
+            vt.take_responses(),
+            b"\x1b[1;5R\x1b[?1;7;1R\x1b[0n\x1b[1;8R".as_slice()
+        );
+    }
+
+    #[test]
+    fn configured_responses_override_defaults() {
+        let mut config = TermConfig {
+            da1: vec![62, 4, 22],
+            da2: vec![41, 390, 0],
+            ..TermConfig::default()
+        };
+        config.capabilities.insert("RGB".into(), Capability::Absent);
+        config
+            .capabilities
+            .insert("Ms".into(), Capability::Value("x".into()));
+        config.capabilities.insert("XT".into(), Capability::Flag);
+
+        assert_eq!(
+            answers(&config, b"\x1b[0c\x1b[>0c"),
+            b"\x1b[?62;4;22c\x1b[>41;390;0c"
+        );
+        assert!(answers(&config, b"\x05").is_empty());
+        config.answerback = "vt-avt\r".into();
+        assert_eq!(answers(&config, b"a\x05b\x05"), b"vt-avt\rvt-avt\r");
+        // RGB;Ms;XT
+        assert_eq!(
+            answers(&config, b"\x1bP+q524742;4D73;5854\x1b\\"),
+            b"\x1bP0+r524742\x1b\\\x1bP1+r4D73=78\x1b\\\x1bP1+r5854\x1b\\".as_slice()
+        );
+    }
+}
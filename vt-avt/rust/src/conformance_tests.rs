@@ -0,0 +1,207 @@
+//! vttest-style conformance fixtures for `AvtState`.
+//!
+//! Each fixture feeds a byte sequence into a fresh terminal and checks the
+//! visible text (trailing blanks trimmed) and the cursor position. Expected
+//! results follow xterm; intentional deviations are listed in the README.
+
+use super::*;
+use crate::backend::TerminalBackend;
+use lineattr::LineAttr;
+
+struct Fixture {
+    name: &'static str,
+    cols: usize,
+    rows: usize,
+    input: &'static [u8],
+    screen: &'static [&'static str],
+    cursor: (usize, usize),
+}
+
+const FIXTURES: &[Fixture] = &[
+    // REP: CSI Ps b repeats the preceding graphic character
+    Fixture {
+        name: "rep repeats last character",
+        cols: 10,
+        rows: 2,
+        input: b"ab\x1b[3b",
+        screen: &["abbbb", ""],
+        cursor: (5, 0),
+    },
+    Fixture {
+        name: "rep default count is one",
+        cols: 10,
+        rows: 2,
+        input: b"x\x1b[b",
+        screen: &["xx", ""],
+        cursor: (2, 0),
+    },
+    Fixture {
+        name: "rep of multibyte character",
+        cols: 10,
+        rows: 2,
+        input: "\u{e9}\x1b[2b".as_bytes(),
+        screen: &["\u{e9}\u{e9}\u{e9}", ""],
+        cursor: (3, 0),
+    },
+    Fixture {
+        name: "rep wraps at right margin",
+        cols: 4,
+        rows: 3,
+        input: b"ab\x1b[5b",
+        screen: &["abbb", "bbb", ""],
+        cursor: (3, 1),
+    },
+    // DECALN: fill with E, home cursor
+    Fixture {
+        name: "decaln fills screen",
+        cols: 3,
+        rows: 2,
+        input: b"xy\r\n\x1b#8",
+        screen: &["EEE", "EEE"],
+        cursor: (0, 0),
+    },
+    // ICH / DCH
+    Fixture {
+        name: "ich shifts right and truncates",
+        cols: 6,
+        rows: 1,
+        input: b"abcdef\r\x1b[2@",
+        screen: &["  abcd"],
+        cursor: (0, 0),
+    },
+    Fixture {
+        name: "ich larger than line clears to end",
+        cols: 6,
+        rows: 1,
+        input: b"abcdef\r\x1b[2C\x1b[99@",
+        screen: &["ab"],
+        cursor: (2, 0),
+    },
+    Fixture {
+        name: "dch shifts left",
+        cols: 6,
+        rows: 1,
+        input: b"abcdef\r\x1b[C\x1b[2P",
+        screen: &["adef"],
+        cursor: (1, 0),
+    },
+    Fixture {
+        name: "dch larger than line clears to end",
+        cols: 6,
+        rows: 1,
+        input: b"abcdef\r\x1b[3C\x1b[99P",
+        screen: &["abc"],
+        cursor: (3, 0),
+    },
+    Fixture {
+        name: "ech erases without shifting",
+        cols: 6,
+        rows: 1,
+        input: b"abcdef\r\x1b[C\x1b[3X",
+        screen: &["a   ef"],
+        cursor: (1, 0),
+    },
+    // IL / DL
+    Fixture {
+        name: "il inserts at cursor row",
+        cols: 4,
+        rows: 3,
+        input: b"1\r\n2\r\n3\x1b[2;1H\x1b[L",
+        screen: &["1", "", "2"],
+        cursor: (0, 1),
+    },
+    Fixture {
+        name: "dl deletes at cursor row",
+        cols: 4,
+        rows: 3,
+        input: b"1\r\n2\r\n3\x1b[1;1H\x1b[M",
+        screen: &["2", "3", ""],
+        cursor: (0, 0),
+    },
+    Fixture {
+        name: "il outside scroll region is ignored",
+        cols: 4,
+        rows: 4,
+        input: b"1\r\n2\r\n3\r\n4\x1b[2;3r\x1b[4;1H\x1b[L",
+        screen: &["1", "2", "3", "4"],
+        cursor: (0, 3),
+    },
+    Fixture {
+        name: "dl within scroll region keeps lines below",
+        cols: 4,
+        rows: 4,
+        input: b"1\r\n2\r\n3\r\n4\x1b[2;3r\x1b[2;1H\x1b[M",
+        screen: &["1", "3", "", "4"],
+        cursor: (0, 1),
+    },
+];
+
+fn screen_text(state: &AvtState) -> Vec<String> {
+    let rows = state.vt.size().1;
+    (0..rows)
+        .map(|row| state.vt.row_text(row).trim_end().to_string())
+        .collect()
+}
+
+fn run(fixture: &Fixture) -> AvtState {
+    let mut state = AvtState::new(fixture.cols, fixture.rows);
+    state.feed(fixture.input);
+    state
+}
+
+#[test]
+fn fixtures_match_xterm() {
+    for fixture in FIXTURES {
+        let state = run(fixture);
+        assert_eq!(screen_text(&state), fixture.screen, "{}: screen", fixture.name);
+        let cursor = state.vt.cursor();
+        assert_eq!((cursor.col, cursor.row), fixture.cursor, "{}: cursor", fixture.name);
+    }
+}
+
+#[test]
+fn utf8_split_across_feeds() {
+    let bytes = "a\u{1F600}\u{e9}".as_bytes();
+    for split in 0..=bytes.len() {
+        let mut state = AvtState::new(10, 1);
+        state.feed(&bytes[..split]);
+        state.feed(&bytes[split..]);
+        assert_eq!(screen_text(&state), ["a\u{1F600}\u{e9}"], "split at {}", split);
+    }
+}
+
+#[test]
+fn invalid_utf8_becomes_replacement_character() {
+    let mut state = AvtState::new(10, 1);
+    state.feed(b"a\xffb\xe2\x82");
+    state.feed(b"c");
+    assert_eq!(screen_text(&state), ["a\u{FFFD}b\u{FFFD}c"]);
+}
+
+#[test]
+fn decaln_resets_line_attributes() {
+    let mut state = AvtState::new(4, 2);
+    state.feed(b"\x1b#6\x1b#8");
+    assert_eq!(state.line_attrs.get(0), LineAttr::Single);
+}
+
+#[test]
+fn double_height_pair_scrolls_with_content() {
+    let mut state = AvtState::new(10, 3);
+    state.feed(b"\x1b[2;1H\x1b#3Big\r\n\x1b#4Big\r\n");
+    assert_eq!(state.line_attrs.get(0), LineAttr::DoubleHeightTop);
+    assert_eq!(state.line_attrs.get(1), LineAttr::DoubleHeightBottom);
+    assert_eq!(state.line_attrs.get(2), LineAttr::Single);
+}
+
+#[test]
+fn snapshot_encodes_visible_rows_only() {
+    let mut state = AvtState::new(4, 2);
+    state.feed(b"1\r\n2\r\n3\r\n4");
+    assert_eq!(screen_text(&state), ["3", "4"]);
+
+    let snapshot = state.encode_snapshot();
+    // cols, rows, cursor col, cursor row, cursor flags, then first line header
+    assert_eq!(&snapshot[..5], &[4, 2, 1, 1, 1]);
+    assert_eq!(snapshot[5], LineAttr::Single as u8);
+}
@@ -0,0 +1,430 @@
+//! Encrypted casts, for recordings of sensitive work that must not be
+//! stored or played from plaintext on disk.
+//!
+//! The key is 32 bytes the app unwraps with an Android Keystore key; it
+//! never lives in a file. A recording is split into chunks, each sealed
+//! with XChaCha20-Poly1305 (draft-irtf-cfrg-xchacha) under a nonce made
+//! of a random per-file prefix, the chunk's index and whether it's the
+//! last, so chunks can't be reordered, dropped, or the file cut short at
+//! a chunk boundary without the open failing:
+//!
+//! ```text
+//! file   := magic:"AVTENC" version:u8 chunk_log2:u8 prefix:15 bytes chunk+
+//! chunk  := ciphertext tag:16 bytes
+//! nonce  := prefix index:u64be last:u8
+//! ```
+//!
+//! Every chunk but the last holds `1 << chunk_log2` bytes of plaintext,
+//! the last from 0 to that many, and each is authenticated with the 23
+//! header bytes as associated data. `EncryptedSource` decrypts a chunk at
+//! a time as the parser reaches it, so playback holds one chunk of
+//! plaintext and the file is only readable once `Sealer::finish` has
+//! sealed its last chunk.
+//!
+//! The AEAD itself is the `chacha20poly1305` crate's `XChaCha20Poly1305`;
+//! the tests check it against the XChaCha test vectors.
+
+use crate::castfile::into_handle;
+use crate::handles;
+use crate::source::{self, CastSource, FdSource};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::io::{self, ErrorKind, Read, Write};
+
+pub const MAGIC: &[u8; 6] = b"AVTENC";
+pub const VERSION: u8 = 1;
+pub const KEY_LEN: usize = 32;
+pub const TAG_LEN: usize = 16;
+pub const HEADER_LEN: usize = MAGIC.len() + 2 + PREFIX_LEN;
+
+/// Plaintext per chunk as written, 64 KiB
+pub const CHUNK_LOG2: u8 = 16;
+
+const PREFIX_LEN: usize = 15;
+
+/// Chunk sizes a reader accepts, 1 KiB to 16 MiB
+const CHUNK_LOG2_RANGE: std::ops::RangeInclusive<u8> = 10..=24;
+
+/// A key, zeroed when dropped.
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    /// None unless `bytes` is `KEY_LEN` long.
+    pub fn new(bytes: &[u8]) -> Option<Key> {
+        Some(Key(bytes.try_into().ok()?))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&self.0))
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        for byte in &mut self.0 {
+            // Volatile so the store isn't optimized away as dead
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// Writes a recording encrypted to `out`; `finish` seals the last chunk.
+/// Until then the file can't be opened.
+pub struct Sealer<W: Write> {
+    out: W,
+    key: Key,
+    header: [u8; HEADER_LEN],
+    index: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Sealer<W> {
+    /// Write the header, with a nonce prefix from the system's random
+    /// source.
+    pub fn new(out: W, key: Key) -> io::Result<Self> {
+        let mut prefix = [0; PREFIX_LEN];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut prefix)?;
+        Sealer::with_prefix(out, key, prefix)
+    }
+
+    fn with_prefix(mut out: W, key: Key, prefix: [u8; PREFIX_LEN]) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        header[..6].copy_from_slice(MAGIC);
+        header[6] = VERSION;
+        header[7] = CHUNK_LOG2;
+        header[8..].copy_from_slice(&prefix);
+        out.write_all(&header)?;
+        Ok(Sealer {
+            out,
+            key,
+            header,
+            index: 0,
+            buf: Vec::with_capacity(1 << CHUNK_LOG2),
+        })
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = nonce(&self.header, self.index, last);
+        seal(&self.key, &nonce, &self.header, &mut self.buf);
+        self.out.write_all(&self.buf)?;
+        self.buf.clear();
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Seal what's left as the last chunk, flush and return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for Sealer<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        // A full chunk is sealed only once more follows, since the last
+        // one has to be marked as such
+        if self.buf.len() == 1 << CHUNK_LOG2 && !bytes.is_empty() {
+            self.seal_chunk(false)?;
+        }
+        let n = bytes.len().min((1 << CHUNK_LOG2) - self.buf.len());
+        self.buf.extend_from_slice(&bytes[..n]);
+        Ok(n)
+    }
+
+    /// Flushes sealed chunks; the one being filled stays in memory.
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The plaintext of an encrypted cast in `inner`, one chunk decrypted at
+/// a time. A chunk that doesn't authenticate fails the read with
+/// `InvalidData`.
+pub struct EncryptedSource<S> {
+    inner: S,
+    key: Key,
+    header: [u8; HEADER_LEN],
+    /// Of the encrypted file
+    size: u64,
+    chunk_len: u64,
+    chunks: u64,
+    len: u64,
+    /// The chunk decrypted last, and its index
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl<S: CastSource> EncryptedSource<S> {
+    /// Check the header; fails with `InvalidData` if `inner` isn't an
+    /// encrypted cast this version reads.
+    pub fn new(mut inner: S, key: Key) -> io::Result<Self> {
+        let size = inner.len()?;
+        let mut header = [0; HEADER_LEN];
+        read_exact_at(&mut inner, 0, &mut header)?;
+        if &header[..6] != MAGIC || header[6] != VERSION || !CHUNK_LOG2_RANGE.contains(&header[7]) {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        let chunk_len = 1u64 << header[7];
+        let body = size.saturating_sub(HEADER_LEN as u64);
+        let chunks = body.div_ceil(chunk_len + TAG_LEN as u64);
+        let last = body - chunks.saturating_sub(1) * (chunk_len + TAG_LEN as u64);
+        if last < TAG_LEN as u64 {
+            // Cut short, or no chunk at all
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Ok(EncryptedSource {
+            inner,
+            key,
+            header,
+            size,
+            chunk_len,
+            chunks,
+            len: body - chunks * TAG_LEN as u64,
+            chunk: None,
+        })
+    }
+
+    fn load(&mut self, index: u64) -> io::Result<&[u8]> {
+        if self
+            .chunk
+            .as_ref()
+            .is_none_or(|(loaded, _)| *loaded != index)
+        {
+            let sealed_len = self.chunk_len + TAG_LEN as u64;
+            let start = HEADER_LEN as u64 + index * sealed_len;
+            let end = (start + sealed_len).min(self.size);
+            let mut buf = self.chunk.take().map(|(_, buf)| buf).unwrap_or_default();
+            buf.resize((end - start) as usize, 0);
+            read_exact_at(&mut self.inner, start, &mut buf)?;
+
+            let nonce = nonce(&self.header, index, index + 1 == self.chunks);
+            if !open(&self.key, &nonce, &self.header, &mut buf) {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            self.chunk = Some((index, buf));
+        }
+        Ok(self.chunk.as_ref().map_or(&[], |(_, buf)| buf))
+    }
+}
+
+impl<S: CastSource> CastSource for EncryptedSource<S> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.len {
+            return Ok(0);
+        }
+        let chunk_len = self.chunk_len;
+        let chunk = self.load(offset / chunk_len)?;
+        Ok(source::copy_at(chunk, offset % chunk_len, buf))
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+fn read_exact_at(
+    source: &mut impl CastSource,
+    mut offset: u64,
+    mut buf: &mut [u8],
+) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = source.read_at(offset, buf)?;
+        if n == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        offset += n as u64;
+        buf = &mut buf[n..];
+    }
+    Ok(())
+}
+
+fn nonce(header: &[u8; HEADER_LEN], index: u64, last: bool) -> [u8; 24] {
+    let mut nonce = [0; 24];
+    nonce[..PREFIX_LEN].copy_from_slice(&header[HEADER_LEN - PREFIX_LEN..]);
+    nonce[PREFIX_LEN..23].copy_from_slice(&index.to_be_bytes());
+    nonce[23] = last as u8;
+    nonce
+}
+
+/// Encrypt `data` in place and append its tag.
+pub fn seal(key: &Key, nonce: &[u8; 24], aad: &[u8], data: &mut Vec<u8>) {
+    key.cipher()
+        .encrypt_in_place(XNonce::from_slice(nonce), aad, data)
+        .expect("chunks are far below the cipher's limit");
+}
+
+/// Check and strip the tag `seal` appended, and decrypt `data` in place.
+/// Returns false, leaving `data` as it was, if it doesn't authenticate.
+pub fn open(key: &Key, nonce: &[u8; 24], aad: &[u8], data: &mut Vec<u8>) -> bool {
+    key.cipher()
+        .decrypt_in_place(XNonce::from_slice(nonce), aad, data)
+        .is_ok()
+}
+
+// JNI functions
+
+/// A cast file handle, as from `castFileOpen`, over the encrypted cast in
+/// `fd`, decrypted with `key` as it's read. The handle owns `fd`, which is
+/// closed on failure too. Returns 0 for a key that isn't `KEY_LEN` bytes,
+/// a file that isn't an encrypted cast, or a first chunk that doesn't
+/// decrypt (the wrong key); a later chunk that doesn't ends the events as
+/// a malformed line would, see `castFileError`.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenEncrypted<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    fd: jint,
+    key: JByteArray<'a>,
+) -> jlong {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let Some(key) = key_arg(&env, &key) else {
+            return 0;
+        };
+        let Ok(source) = EncryptedSource::new(FdSource::new(file), key) else {
+            return 0;
+        };
+
+        into_handle(source::open(source))
+    })
+}
+
+/// Write the cast `cast_handle` to `fd` encrypted with `key`. The
+/// descriptor stays open. Returns false for an invalid handle, fd or key,
+/// or a failed write.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castWriteEncrypted<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_handle: jlong,
+    fd: jint,
+    key: JByteArray<'a>,
+) -> jboolean {
+    use crate::cast::Cast;
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return JNI_FALSE;
+        }
+        let Some(key) = key_arg(&env, &key) else {
+            return JNI_FALSE;
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JNI_FALSE;
+        };
+
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let written = Sealer::new(BufWriter::new(&*file), key).and_then(|mut sealer| {
+            sealer.write_all(&cast.write())?;
+            sealer.finish()?.flush()
+        });
+        match written {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// The key in `key`, wiping the copy made on the way.
+pub(crate) fn key_arg(env: &JNIEnv, key: &JByteArray) -> Option<Key> {
+    let mut bytes = env.convert_byte_array(key).ok()?;
+    let key = Key::new(&bytes);
+    bytes.fill(0);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn seals_match_the_test_vectors() {
+        // draft-irtf-cfrg-xchacha, A.3.1
+        let key = Key::new(&(0x80..0xa0).collect::<Vec<u8>>()).unwrap();
+        let nonce: [u8; 24] = (0x40..0x58).collect::<Vec<u8>>().try_into().unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+                          only one tip for the future, sunscreen would be it.";
+        let mut data = plaintext.to_vec();
+        seal(&key, &nonce, &aad, &mut data);
+        assert_eq!(
+            data,
+            hex(
+                "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+                 731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+                 2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+                 21f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780a\
+                 cf49"
+            )
+        );
+        assert!(open(&key, &nonce, &aad, &mut data));
+        assert_eq!(data, plaintext);
+
+        data[0] ^= 1;
+        let sealed = data.clone();
+        assert!(!open(&key, &nonce, &aad, &mut data));
+        assert_eq!(data, sealed);
+    }
+
+    #[test]
+    fn containers_open_only_whole_and_with_the_key() {
+        let key = || Key::new(&[7; KEY_LEN]).unwrap();
+        let plaintext: Vec<u8> = (0..(3 << CHUNK_LOG2) + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut sealer = Sealer::with_prefix(Vec::new(), key(), [1; PREFIX_LEN]).unwrap();
+        for part in plaintext.chunks(1000) {
+            sealer.write_all(part).unwrap();
+        }
+        let sealed = sealer.finish().unwrap();
+        assert_eq!(sealed.len(), HEADER_LEN + plaintext.len() + 4 * TAG_LEN);
+
+        let read = |sealed: &[u8], key| -> io::Result<Vec<u8>> {
+            let mut source = EncryptedSource::new(Box::from(sealed), key)?;
+            let mut out = vec![0; source.len()? as usize];
+            read_exact_at(&mut source, 0, &mut out)?;
+            Ok(out)
+        };
+        assert_eq!(read(&sealed, key()).unwrap(), plaintext);
+        let wrong = Key::new(&[8; KEY_LEN]).unwrap();
+        assert!(read(&sealed, wrong).is_err());
+
+        // Cut at a chunk boundary, so the last chunk left isn't marked last
+        let cut = HEADER_LEN + 3 * ((1 << CHUNK_LOG2) + TAG_LEN);
+        assert!(read(&sealed[..cut], key()).is_err());
+        let mut flipped = sealed.clone();
+        flipped[HEADER_LEN + 5] ^= 0x80;
+        assert!(read(&flipped, key()).is_err());
+
+        let empty = Sealer::with_prefix(Vec::new(), key(), [2; PREFIX_LEN])
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(read(&empty, key()).unwrap(), []);
+        assert!(read(&empty[..HEADER_LEN], key()).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Snapshot deltas against a baseline the client already holds.
+//!
+//! ```text
+//! delta := seq baseline cols rows cursor_col cursor_row cursor_flags:u8
+//!          line_count (row line)*
+//! ```
+//!
+//! `line` and `cursor_flags` (alternate screen bit included) are as in the
+//! snapshot format. Every delta names the full screen it describes with
+//! `seq`, so a client can keep it and pass it as the next baseline, e.g.
+//! after restoring UI state. Rows not listed are unchanged
+//! from the baseline. When the baseline is unknown (never issued, no
+//! longer kept, or a different size), `baseline` is 0 and every row is
+//! listed. Rows are compared by a hash of their encoding, so only the last
+//! few baselines' hashes are kept, not the screens.
+
+use crate::snapshot::{self, Cursor, DecodeError, Line, Reader, Screen};
+use crate::write_varint;
+use std::collections::VecDeque;
+
+/// Baselines remembered per VT
+const KEPT: usize = 8;
+
+struct Baseline {
+    seq: u64,
+    size: (usize, usize),
+    hashes: Vec<u64>,
+}
+
+#[derive(Default)]
+pub struct History {
+    last_seq: u64,
+    issued: VecDeque<Baseline>,
+    /// Baseline the client confirmed, kept however many deltas follow it
+    acked: u64,
+}
+
+impl History {
+    /// Encode `screen` against baseline `baseline_seq` and remember it as
+    /// a new baseline.
+    pub fn delta(&mut self, screen: &Screen, baseline_seq: u64) -> Vec<u8> {
+        let size = (screen.cols, screen.rows);
+        let encoded: Vec<Vec<u8>> = screen
+            .lines
+            .iter()
+            .map(|line| {
+                let mut buf = Vec::new();
+                line.encode(&mut buf);
+                buf
+            })
+            .collect();
+        let hashes: Vec<u64> = encoded.iter().map(|line| fnv1a(line)).collect();
+
+        let baseline = self
+            .issued
+            .iter()
+            .find(|b| b.seq == baseline_seq && b.size == size);
+        let changed: Vec<usize> = (0..encoded.len())
+            .filter(|&row| baseline.is_none_or(|b| b.hashes[row] != hashes[row]))
+            .collect();
+
+        self.last_seq += 1;
+        let mut buf = Vec::new();
+        write_varint(&mut buf, self.last_seq as usize);
+        write_varint(&mut buf, baseline.map_or(0, |b| b.seq as usize));
+        write_varint(&mut buf, screen.cols);
+        write_varint(&mut buf, screen.rows);
+        write_varint(&mut buf, screen.cursor.col);
+        write_varint(&mut buf, screen.cursor.row);
+        buf.push(snapshot::header_flags(&screen.cursor, screen.alt_screen));
+        write_varint(&mut buf, changed.len());
+        for &row in &changed {
+            write_varint(&mut buf, row);
+            buf.extend_from_slice(&encoded[row]);
+        }
+
+        if self.issued.len() == KEPT {
+            let acked = self.acked;
+            let evict = self.issued.iter().position(|b| b.seq != acked).unwrap_or(0);
+            self.issued.remove(evict);
+        }
+        self.issued.push_back(Baseline {
+            seq: self.last_seq,
+            size,
+            hashes,
+        });
+        buf
+    }
+
+    /// Record that the client holds screen `seq`: it is kept until a newer
+    /// one is acked, and older baselines are forgotten. False if `seq` is
+    /// unknown or older than the current ack.
+    pub fn ack(&mut self, seq: u64) -> bool {
+        if seq <= self.acked || !self.issued.iter().any(|b| b.seq == seq) {
+            return false;
+        }
+        self.acked = seq;
+        self.issued.retain(|b| b.seq >= seq);
+        true
+    }
+
+    pub fn acked(&self) -> u64 {
+        self.acked
+    }
+
+    /// Bytes of the baselines' row hashes.
+    pub fn bytes(&self) -> usize {
+        self.issued.iter().map(|b| b.hashes.len() * std::mem::size_of::<u64>()).sum()
+    }
+}
+
+/// A decoded delta.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub seq: u64,
+    /// 0 when every row is included
+    pub baseline: u64,
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor: Cursor,
+    pub alt_screen: bool,
+    /// `(row, line)` in ascending row order
+    pub lines: Vec<(usize, Line)>,
+}
+
+impl Delta {
+    /// The full screen, given the baseline screen (ignored when the delta
+    /// includes every row).
+    pub fn apply(&self, baseline: &Screen) -> Screen {
+        let mut lines = if self.baseline == 0 {
+            vec![Line::default(); self.rows]
+        } else {
+            baseline.lines.clone()
+        };
+        lines.resize(self.rows, Line::default());
+        for (row, line) in &self.lines {
+            if let Some(slot) = lines.get_mut(*row) {
+                *slot = line.clone();
+            }
+        }
+        Screen {
+            cols: self.cols,
+            rows: self.rows,
+            cursor: self.cursor,
+            lines,
+            links: baseline.links.clone(),
+            alt_screen: self.alt_screen,
+            // Deltas don't carry images, and the baseline's may have moved
+            images: Vec::new(),
+        }
+    }
+}
+
+/// Decode a delta produced by `vtSnapshotDelta`, as leniently as
+/// `snapshot::decode`.
+pub fn decode(bytes: &[u8]) -> Result<Delta, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let seq = r.varint()? as u64;
+    let baseline = r.varint()? as u64;
+    let cols = r.varint()?;
+    let rows = r.varint()?;
+    let (col, row) = (r.varint()?, r.varint()?);
+    let flags = r.byte()?;
+
+    let count = r.varint()?;
+    let mut lines = Vec::with_capacity(count.min(r.remaining() / 3));
+    for _ in 0..count {
+        let row = r.varint()?;
+        lines.push((row, r.line()?));
+    }
+
+    Ok(Delta {
+        seq,
+        baseline,
+        cols,
+        rows,
+        cursor: Cursor::with_flags(col, row, flags),
+        alt_screen: flags & snapshot::ALT_SCREEN != 0,
+        lines,
+    })
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn sends_only_rows_changed_since_baseline() {
+        let mut state = AvtState::with_backend(fake(4, 3));
+        let mut history = History::default();
+
+        let full = decode(&history.delta(&state.screen(), 0)).unwrap();
+        assert_eq!((full.seq, full.baseline, full.lines.len()), (1, 0, 3));
+        let first = full.apply(&state.screen());
+
+        state.feed(b"hi");
+        let delta = decode(&history.delta(&state.screen(), full.seq)).unwrap();
+        assert_eq!((delta.seq, delta.baseline), (2, 1));
+        assert_eq!(
+            delta.lines.iter().map(|(row, _)| *row).collect::<Vec<_>>(),
+            [0]
+        );
+        assert_eq!(delta.apply(&first), state.screen());
+
+        // Unknown baselines fall back to every row
+        let delta = decode(&history.delta(&state.screen(), 99)).unwrap();
+        assert_eq!((delta.baseline, delta.lines.len()), (0, 3));
+        for _ in 0..KEPT {
+            history.delta(&state.screen(), 0);
+        }
+        let delta = decode(&history.delta(&state.screen(), 2)).unwrap();
+        assert_eq!(delta.baseline, 0);
+    }
+}
@@ -0,0 +1,802 @@
+//! Diff wire format returned by `vtPollDiff`.
+//!
+//! ```text
+//! diff := 0                                                  (no change)
+//!         | 1 body
+//!         | 2                                                  (cursor only)
+//!         | 3 trace_count (trace_id:u64le)* body               (traced)
+//!         | 4 trace_count (trace_id:u64le)* content            (with content)
+//!         | 5 trace_count (trace_id:u64le)* spans              (with spans)
+//!         | 6 trace_count (trace_id:u64le)* update spans       (interned)
+//!         | 7 trace_count (trace_id:u64le)* damage             (damage)
+//!         | 8 version:u8 features diff                         (versioned)
+//! body := line_count line_index* cursor_changed:u8 resized:u8 [images]
+//! damage := rect_count (line_index col_start col_end)*
+//!           cursor_changed:u8 resized:u8 [images]
+//! content := cols rows cursor_col cursor_row cursor_flags:u8
+//!            cursor_changed:u8 resized:u8 line_count (line_index line)*
+//!            [images]
+//! spans := cols rows cursor_col cursor_row cursor_flags:u8
+//!          cursor_changed:u8 resized:u8
+//!          line_count (line_index span_start span_end line)* [images]
+//! images := change_count (op:u8 image)*
+//! ```
+//!
+//! Line indices are visible rows in ascending order. Cursor-only frames are
+//! the most common kind while typing, so they get the one-byte form. Like the snapshot
+//! decoder, `decode` rejects truncated input and ignores trailing bytes.
+//!
+//! `cursor_changed` has bit 0 set when the cursor may have moved and bit 1
+//! when its shape, blink or visibility changed, so the renderer knows to
+//! restyle it; `cursor_flags` (as in the snapshot format) has the new
+//! state, and a client without it takes a fresh snapshot.
+//!
+//! `resized` has bit 0 set after a resize or reset and bit 1 when the
+//! output switched between the primary and alternate screen; the
+//! `ALT_SCREEN` bit of `cursor_flags` (and of the next snapshot) says which
+//! is shown. A switch redraws every row, so clients that only test the
+//! byte for nonzero, as before bit 1, refresh as after a resize. Bit 2 is
+//! set when the input modes `vtModes` reports changed (see `modes`).
+//!
+//! Bit 3 is set when the inline images shown changed (see `images`), and
+//! the diff then ends with `images`: the placements of the snapshot's
+//! image table (`image` as there) that were added (op 1), moved (op 2) or
+//! removed (op 3), removals first. A placement is known by its image and
+//! the image row at its top, so a moved one replaces the client's
+//! placement with the same `image_id` and `image_row`, whether it
+//! scrolled or lost rows, and a removed one is the placement as last
+//! sent. A client that negotiated no `FEATURE_IMAGES` (see `protocol`)
+//! never gets them. Decoders skip ops they don't know.
+//!
+//! A traced diff echoes the IDs passed to `vtFeedTraced` for feeds whose
+//! changes it is the first to report, so the app can time input to pixels.
+//! Diffs without traces keep the older forms.
+//!
+//! A client that negotiated a protocol version gets each diff behind a
+//! versioned header, see `protocol`; `decode` reads either.
+//!
+//! `vtPollDiffContent` returns the content form, which also carries each
+//! changed row (`line` as in the snapshot format) and the cursor, so a
+//! client can update its frame without a `vtSnapshot` call.
+//!
+//! `vtPollDiffSpans` narrows that down to what actually changed, for wide
+//! screens where a progress bar redraws a few cells of a 300-column row.
+//! It compares each line with the one it last sent and includes only lines
+//! that differ, each with the columns `[span_start, span_end)` from the
+//! first changed cell to the last and a `line` holding just the runs in
+//! that range (columns still counted from the line start). The client
+//! replaces those columns and keeps the rest of its row. A line whose
+//! attribute changed, and every line after a resize or in the first span
+//! diff, comes whole.
+//!
+//! `vtPollDiffInterned` sends span diffs with styles as ids, preceded by an
+//! `update` with the styles new since the last: the interned form of
+//! `styles`, read with `decode_interned`.
+//!
+//! `vtPollDiffDamage` is for renderers that keep their own cells and read
+//! the new ones from a snapshot: instead of rows it names the cells that
+//! changed since the last span or damage poll, as rectangles one row high
+//! with columns `[col_start, col_end)`, ordered by row and then column. A
+//! row gets one per stretch of changes, two stretches merging unless more
+//! than `DAMAGE_GAP` unchanged columns lie between them, so a clock and a
+//! progress bar on one row don't take the columns between them along.
+//! Rows come whole as for span diffs.
+
+use crate::snapshot::{self, Cursor, DecodeError, ImagePlacement, Line, Reader, Run, Style};
+use crate::styles::{Cache, Interner};
+use crate::{protocol, write_varint};
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Diff {
+    pub lines: Vec<usize>,
+    pub cursor_changed: bool,
+    /// The cursor's shape, blink or visibility changed
+    pub cursor_style_changed: bool,
+    pub resized: bool,
+    /// The output switched to or from the alternate screen
+    pub screen_switched: bool,
+    /// The input modes changed, see `modes`
+    pub modes_changed: bool,
+    /// Trace IDs of the feeds this diff reports
+    pub traces: Vec<u64>,
+    pub content: Option<Content>,
+    /// For damage diffs, the changed columns as `(row, cols)`, ordered;
+    /// `lines` then holds their rows
+    pub damage: Option<Vec<(usize, Range<usize>)>>,
+    /// How the image placements changed, if they did
+    pub images: Option<Vec<ImageChange>>,
+}
+
+/// Leading tags, see the module docs
+pub const KIND_NONE: u8 = 0;
+pub const KIND_LINES: u8 = 1;
+pub const KIND_CURSOR: u8 = 2;
+pub const KIND_TRACED: u8 = 3;
+pub const KIND_CONTENT: u8 = 4;
+pub const KIND_SPANS: u8 = 5;
+pub const KIND_INTERNED: u8 = 6;
+pub const KIND_DAMAGE: u8 = 7;
+pub const KIND_VERSIONED: u8 = 8;
+
+/// Bits of `cursor_changed`
+pub const CURSOR_MOVED: u8 = 0x01;
+pub const CURSOR_RESTYLED: u8 = 0x02;
+/// Bits of `resized`
+pub const RESIZED: u8 = 0x01;
+pub const SCREEN_SWITCHED: u8 = 0x02;
+pub const MODES_CHANGED: u8 = 0x04;
+pub const IMAGES_CHANGED: u8 = 0x08;
+
+/// Ops of `images` entries
+pub const IMAGE_ADDED: u8 = 1;
+pub const IMAGE_MOVED: u8 = 2;
+pub const IMAGE_REMOVED: u8 = 3;
+
+/// Most unchanged columns between two changes in one damage rectangle
+pub const DAMAGE_GAP: usize = 4;
+
+/// What changed, for diffs that carry it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Content {
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor: Cursor,
+    pub alt_screen: bool,
+    /// The rows named by `Diff::lines`, in the same order
+    pub lines: Vec<Line>,
+    /// For span diffs, the changed columns of each row in `lines`, which
+    /// then hold only the runs within them
+    pub spans: Option<Vec<Range<usize>>>,
+}
+
+/// An entry of `images`, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageChange {
+    Added(ImagePlacement),
+    Moved(ImagePlacement),
+    Removed(ImagePlacement),
+}
+
+impl ImageChange {
+    fn op(&self) -> u8 {
+        match self {
+            ImageChange::Added(_) => IMAGE_ADDED,
+            ImageChange::Moved(_) => IMAGE_MOVED,
+            ImageChange::Removed(_) => IMAGE_REMOVED,
+        }
+    }
+
+    fn placement(&self) -> &ImagePlacement {
+        match self {
+            ImageChange::Added(placement)
+            | ImageChange::Moved(placement)
+            | ImageChange::Removed(placement) => placement,
+        }
+    }
+}
+
+/// Which placement of a table `placement` is, see the module docs.
+fn image_key(placement: &ImagePlacement) -> (u32, usize) {
+    (placement.id, placement.image_row)
+}
+
+/// The changes from image table `old` to `new`, removals first.
+#[cfg(feature = "images")]
+pub(crate) fn image_changes(old: &[ImagePlacement], new: &[ImagePlacement]) -> Vec<ImageChange> {
+    let mut changes: Vec<_> = old
+        .iter()
+        .filter(|p| !new.iter().any(|n| image_key(n) == image_key(p)))
+        .map(|p| ImageChange::Removed(*p))
+        .collect();
+    for placement in new {
+        match old.iter().find(|o| image_key(o) == image_key(placement)) {
+            None => changes.push(ImageChange::Added(*placement)),
+            Some(old) if old != placement => changes.push(ImageChange::Moved(*placement)),
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+/// Apply `changes` to a client's image table, keeping it in the order of
+/// the placements' top rows.
+pub fn apply_images(table: &mut Vec<ImagePlacement>, changes: &[ImageChange]) {
+    for change in changes {
+        let key = image_key(change.placement());
+        table.retain(|placement| image_key(placement) != key);
+        if !matches!(change, ImageChange::Removed(_)) {
+            table.push(*change.placement());
+        }
+    }
+    table.sort_by_key(|placement| placement.row);
+}
+
+impl Diff {
+    pub fn encode(&self) -> Vec<u8> {
+        if let Some(content) = &self.content {
+            return self.encode_content(content, None);
+        }
+        if let Some(damage) = &self.damage {
+            return self.encode_damage(damage);
+        }
+        if self.lines.is_empty()
+            && self.cursor_changed
+            && !self.cursor_style_changed
+            && !self.resized
+            && !self.screen_switched
+            && !self.modes_changed
+            && self.images.is_none()
+            && self.traces.is_empty()
+        {
+            return vec![KIND_CURSOR];
+        }
+
+        let mut buf = if self.traces.is_empty() {
+            vec![KIND_LINES]
+        } else {
+            let mut buf = vec![KIND_TRACED];
+            write_varint(&mut buf, self.traces.len());
+            for trace in &self.traces {
+                buf.extend_from_slice(&trace.to_le_bytes());
+            }
+            buf
+        };
+        write_varint(&mut buf, self.lines.len());
+        for &line in &self.lines {
+            write_varint(&mut buf, line);
+        }
+        buf.push(self.cursor_byte());
+        buf.push(self.resized_byte());
+        self.encode_images(&mut buf);
+        buf
+    }
+
+    /// Encode in the interned form (tag 6), with the style entries this
+    /// diff adds to `styles`. Lines without a span count as whole. Diffs
+    /// without content have no interned form and encode as usual.
+    pub fn encode_interned(&self, styles: &mut Interner) -> Vec<u8> {
+        match &self.content {
+            Some(content) => self.encode_content(content, Some(styles)),
+            None => self.encode(),
+        }
+    }
+
+    fn encode_content(&self, content: &Content, mut styles: Option<&mut Interner>) -> Vec<u8> {
+        let tag = match (&styles, &content.spans) {
+            (Some(_), _) => KIND_INTERNED,
+            (None, Some(_)) => KIND_SPANS,
+            (None, None) => KIND_CONTENT,
+        };
+        let mut buf = vec![tag];
+        write_varint(&mut buf, self.traces.len());
+        for trace in &self.traces {
+            buf.extend_from_slice(&trace.to_le_bytes());
+        }
+
+        // Ahead of an update that is only known once the lines are written
+        let mut body = Vec::new();
+        write_varint(&mut body, content.cols);
+        write_varint(&mut body, content.rows);
+        write_varint(&mut body, content.cursor.col);
+        write_varint(&mut body, content.cursor.row);
+        body.push(snapshot::header_flags(&content.cursor, content.alt_screen));
+        body.push(self.cursor_byte());
+        body.push(self.resized_byte());
+        write_varint(&mut body, self.lines.len());
+        for (i, (&row, line)) in self.lines.iter().zip(&content.lines).enumerate() {
+            write_varint(&mut body, row);
+            match content.spans.as_ref().and_then(|spans| spans.get(i)) {
+                Some(span) => {
+                    write_varint(&mut body, span.start);
+                    write_varint(&mut body, span.end);
+                }
+                None if tag == KIND_INTERNED => {
+                    write_varint(&mut body, 0);
+                    write_varint(&mut body, content.cols);
+                }
+                None => {}
+            }
+            match &mut styles {
+                Some(styles) => styles.encode_line(line, &mut body),
+                None => line.encode(&mut body),
+            }
+        }
+        self.encode_images(&mut body);
+
+        if let Some(styles) = styles {
+            styles.encode_update(&mut buf);
+        }
+        buf.extend_from_slice(&body);
+        buf
+    }
+
+    fn encode_damage(&self, damage: &[(usize, Range<usize>)]) -> Vec<u8> {
+        let mut buf = vec![KIND_DAMAGE];
+        write_varint(&mut buf, self.traces.len());
+        for trace in &self.traces {
+            buf.extend_from_slice(&trace.to_le_bytes());
+        }
+        write_varint(&mut buf, damage.len());
+        for (row, cols) in damage {
+            write_varint(&mut buf, *row);
+            write_varint(&mut buf, cols.start);
+            write_varint(&mut buf, cols.end);
+        }
+        buf.push(self.cursor_byte());
+        buf.push(self.resized_byte());
+        self.encode_images(&mut buf);
+        buf
+    }
+
+    /// `images`, if they changed
+    fn encode_images(&self, buf: &mut Vec<u8>) {
+        let Some(changes) = &self.images else {
+            return;
+        };
+        write_varint(buf, changes.len());
+        for change in changes {
+            buf.push(change.op());
+            change.placement().encode(buf);
+        }
+    }
+
+    /// `cursor_changed` of the wire format
+    fn cursor_byte(&self) -> u8 {
+        let mut byte = 0;
+        if self.cursor_changed {
+            byte |= CURSOR_MOVED;
+        }
+        if self.cursor_style_changed {
+            byte |= CURSOR_RESTYLED;
+        }
+        byte
+    }
+
+    /// `resized` of the wire format
+    fn resized_byte(&self) -> u8 {
+        let mut byte = 0;
+        if self.resized {
+            byte |= RESIZED;
+        }
+        if self.screen_switched {
+            byte |= SCREEN_SWITCHED;
+        }
+        if self.modes_changed {
+            byte |= MODES_CHANGED;
+        }
+        if self.images.is_some() {
+            byte |= IMAGES_CHANGED;
+        }
+        byte
+    }
+}
+
+/// What a column shows: a char, its style and link, and which of the
+/// char's columns it is (1 for the right half of a wide char). `None` if
+/// unset.
+pub(crate) type Column = Option<(char, Style, u32, usize)>;
+
+/// Columns of `line`, left to right.
+pub(crate) fn cells(line: &Line) -> Vec<Column> {
+    let mut cells = Vec::new();
+    for run in &line.runs {
+        let width = run.char_width();
+        for (col, ch) in run.columns() {
+            if cells.len() < col + width {
+                cells.resize(col + width, None);
+            }
+            for half in 0..width {
+                cells[col + half] = Some((ch, run.style, run.link, half));
+            }
+        }
+    }
+    cells
+}
+
+/// The columns `old` and `new` differ in, from the first to the last, or
+/// `None` if they are the same. A changed attribute changes all `cols`.
+/// The span never cuts a wide char of `new` in half, since a char that
+/// moved differs in both its columns.
+pub(crate) fn changed_span(old: &Line, new: &Line, cols: usize) -> Option<Range<usize>> {
+    if old.attr != new.attr {
+        return Some(0..cols);
+    }
+    let (old, new) = (cells(old), cells(new));
+    let at = |cells: &[Column], col: usize| cells.get(col).copied().flatten();
+    let differs = |col: &usize| at(&old, *col) != at(&new, *col);
+    let len = old.len().max(new.len());
+    let start = (0..len).find(differs)?;
+    let end = (start..len).rev().find(differs).map_or(len, |col| col + 1);
+    Some(start..end)
+}
+
+/// Like `changed_span`, split where more than `DAMAGE_GAP` columns in a
+/// row are the same.
+pub(crate) fn changed_spans(old: &Line, new: &Line, cols: usize) -> Vec<Range<usize>> {
+    if old.attr != new.attr {
+        return std::iter::once(0..cols).collect();
+    }
+    let (old, new) = (cells(old), cells(new));
+    let at = |cells: &[Column], col: usize| cells.get(col).copied().flatten();
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for col in (0..old.len().max(new.len())).filter(|&col| at(&old, col) != at(&new, col)) {
+        match spans.last_mut() {
+            Some(span) if col - span.end <= DAMAGE_GAP => span.end = col + 1,
+            _ => spans.push(col..col + 1),
+        }
+    }
+    spans
+}
+
+/// `line` with only the runs in `span`, cut at its edges. A char belongs
+/// to the span its first column is in.
+pub(crate) fn crop(line: &Line, span: &Range<usize>) -> Line {
+    let runs = line
+        .runs
+        .iter()
+        .filter_map(|run| {
+            let mut kept = run.columns().filter(|(col, _)| span.contains(col)).peekable();
+            let &(col, _) = kept.peek()?;
+            let text: String = kept.map(|(_, ch)| ch).collect();
+            Some(Run {
+                col,
+                cells: text.chars().count() * run.char_width(),
+                text,
+                style: run.style,
+                link: run.link,
+            })
+        })
+        .collect();
+    Line {
+        attr: line.attr,
+        runs,
+    }
+}
+
+/// Decode a diff. A zero tag decodes as an empty diff. An interned diff
+/// decodes only if it starts an epoch; see `decode_interned`.
+pub fn decode(bytes: &[u8]) -> Result<Diff, DecodeError> {
+    decode_interned(bytes, &mut Cache::default())
+}
+
+/// `decode`, keeping the styles of interned diffs in `cache` for the next.
+pub fn decode_interned(bytes: &[u8], cache: &mut Cache) -> Result<Diff, DecodeError> {
+    let (_, bytes) = protocol::split_diff(bytes)?;
+    let mut r = Reader::new(bytes);
+    let mut traces = Vec::new();
+    let tag = r.byte()?;
+    match tag {
+        KIND_NONE => return Ok(Diff::default()),
+        KIND_CURSOR => {
+            return Ok(Diff {
+                cursor_changed: true,
+                ..Diff::default()
+            })
+        }
+        KIND_TRACED..=KIND_DAMAGE => {
+            let count = r.varint()?;
+            traces.reserve(count.min(r.remaining() / 8));
+            for _ in 0..count {
+                let bytes = r.take(8)?;
+                traces.push(u64::from_le_bytes(bytes.try_into().unwrap()));
+            }
+        }
+        _ => {}
+    }
+    if (KIND_CONTENT..=KIND_INTERNED).contains(&tag) {
+        return decode_content(&mut r, traces, tag, cache);
+    }
+    if tag == KIND_DAMAGE {
+        return decode_damage(&mut r, traces);
+    }
+
+    let count = r.varint()?;
+    let mut lines = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        lines.push(r.varint()?);
+    }
+
+    let cursor = r.byte()?;
+    let resized = r.byte()?;
+    Ok(Diff {
+        lines,
+        cursor_changed: cursor & CURSOR_MOVED != 0,
+        cursor_style_changed: cursor & CURSOR_RESTYLED != 0,
+        resized: resized & RESIZED != 0,
+        screen_switched: resized & SCREEN_SWITCHED != 0,
+        modes_changed: resized & MODES_CHANGED != 0,
+        traces,
+        content: None,
+        damage: None,
+        images: decode_images(&mut r, resized)?,
+    })
+}
+
+/// `images`, if the `resized` byte says they follow; unknown ops are
+/// skipped.
+fn decode_images(r: &mut Reader, resized: u8) -> Result<Option<Vec<ImageChange>>, DecodeError> {
+    if resized & IMAGES_CHANGED == 0 {
+        return Ok(None);
+    }
+    let count = r.varint()?;
+    let mut changes = Vec::with_capacity(count.min(r.remaining() / 8));
+    for _ in 0..count {
+        let op = r.byte()?;
+        let placement = r.image_placement()?;
+        changes.extend(match op {
+            IMAGE_ADDED => Some(ImageChange::Added(placement)),
+            IMAGE_MOVED => Some(ImageChange::Moved(placement)),
+            IMAGE_REMOVED => Some(ImageChange::Removed(placement)),
+            _ => None,
+        });
+    }
+    Ok(Some(changes))
+}
+
+fn decode_damage(r: &mut Reader, traces: Vec<u64>) -> Result<Diff, DecodeError> {
+    let count = r.varint()?;
+    let mut damage = Vec::with_capacity(count.min(r.remaining()));
+    for _ in 0..count {
+        damage.push((r.varint()?, r.varint()?..r.varint()?));
+    }
+    let mut lines: Vec<usize> = damage.iter().map(|(row, _)| *row).collect();
+    lines.dedup();
+
+    let cursor = r.byte()?;
+    let resized = r.byte()?;
+    Ok(Diff {
+        lines,
+        cursor_changed: cursor & CURSOR_MOVED != 0,
+        cursor_style_changed: cursor & CURSOR_RESTYLED != 0,
+        resized: resized & RESIZED != 0,
+        screen_switched: resized & SCREEN_SWITCHED != 0,
+        modes_changed: resized & MODES_CHANGED != 0,
+        traces,
+        content: None,
+        damage: Some(damage),
+        images: decode_images(r, resized)?,
+    })
+}
+
+fn decode_content(
+    r: &mut Reader,
+    traces: Vec<u64>,
+    tag: u8,
+    cache: &mut Cache,
+) -> Result<Diff, DecodeError> {
+    if tag == KIND_INTERNED {
+        cache.read_update(r)?;
+    }
+    let with_spans = tag != KIND_CONTENT;
+    let cols = r.varint()?;
+    let rows = r.varint()?;
+    let (col, row) = (r.varint()?, r.varint()?);
+    let flags = r.byte()?;
+    let cursor_byte = r.byte()?;
+    let resized = r.byte()?;
+    let count = r.varint()?;
+    let mut indices = Vec::with_capacity(count.min(r.remaining()));
+    let mut lines = Vec::with_capacity(count.min(r.remaining()));
+    let mut spans = Vec::new();
+    for _ in 0..count {
+        indices.push(r.varint()?);
+        if with_spans {
+            spans.push(r.varint()?..r.varint()?);
+        }
+        lines.push(if tag == KIND_INTERNED { cache.line(r)? } else { r.line()? });
+    }
+    let images = decode_images(r, resized)?;
+
+    Ok(Diff {
+        lines: indices,
+        cursor_changed: cursor_byte & CURSOR_MOVED != 0,
+        cursor_style_changed: cursor_byte & CURSOR_RESTYLED != 0,
+        resized: resized & RESIZED != 0,
+        screen_switched: resized & SCREEN_SWITCHED != 0,
+        modes_changed: resized & MODES_CHANGED != 0,
+        traces,
+        content: Some(Content {
+            cols,
+            rows,
+            cursor: Cursor::with_flags(col, row, flags),
+            alt_screen: flags & snapshot::ALT_SCREEN != 0,
+            lines,
+            spans: with_spans.then_some(spans),
+        }),
+        damage: None,
+        images,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn encodes_flags_after_lines() {
+        let diff = Diff {
+            lines: vec![0, 200],
+            cursor_changed: true,
+            resized: false,
+            ..Diff::default()
+        };
+        let bytes = diff.encode();
+        assert_eq!(bytes, vec![1, 2, 0, 0xc8, 0x01, 1, 0]);
+        assert_eq!(decode(&bytes).unwrap(), diff);
+        assert_eq!(decode(&[0]).unwrap(), Diff::default());
+    }
+
+    #[test]
+    fn cursor_only_diff_is_one_byte() {
+        let diff = Diff {
+            lines: vec![],
+            cursor_changed: true,
+            resized: false,
+            ..Diff::default()
+        };
+        assert_eq!(diff.encode(), vec![2]);
+        assert_eq!(decode(&[2]).unwrap(), diff);
+        assert_eq!(decode(&[1, 0, 1, 0]).unwrap(), diff);
+    }
+
+    #[test]
+    fn traces_precede_lines() {
+        let diff = Diff {
+            cursor_changed: true,
+            traces: vec![7, u64::MAX],
+            ..Diff::default()
+        };
+        let bytes = diff.encode();
+        assert_eq!(bytes[..2], [3, 2]);
+        assert_eq!(bytes[2..10], 7u64.to_le_bytes());
+        assert_eq!(bytes[18..], [0, 1, 0]);
+        assert_eq!(decode(&bytes).unwrap(), diff);
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn content_diff_carries_rows_and_cursor() {
+        let mut state = AvtState::with_backend(fake(4, 3));
+        state.poll_diff_content();
+        state.feed(b"ab");
+
+        let diff = decode(&state.poll_diff_content().unwrap()).unwrap();
+        let content = diff.content.unwrap();
+        let screen = state.screen();
+        assert_eq!((content.cols, content.rows), (4, 3));
+        assert_eq!(content.cursor, screen.cursor);
+        let rows: Vec<_> = diff.lines.iter().map(|&row| &screen.lines[row]).collect();
+        assert!(diff.lines.contains(&0));
+        assert_eq!(content.lines.iter().collect::<Vec<_>>(), rows);
+        assert_eq!(state.poll_diff_content(), None);
+    }
+
+    #[test]
+    fn span_diff_sends_changed_columns_only() {
+        let mut state = AvtState::with_backend(fake(8, 2));
+        let first = decode(&state.poll_diff_spans().unwrap()).unwrap();
+        assert_eq!(first.content.unwrap().spans, Some(vec![0..8, 0..8]));
+
+        state.feed(b"abc");
+        let bytes = state.poll_diff_spans().unwrap();
+        assert_eq!(bytes[0], 5);
+        let diff = decode(&bytes).unwrap();
+        assert_eq!(diff.lines, [0]);
+        let content = diff.content.unwrap();
+        assert_eq!(content.spans.unwrap().pop(), Some(0..3));
+        let run = &content.lines[0].runs[0];
+        assert_eq!((run.col, run.text.as_str()), (0, "abc"));
+
+        state.feed(b"d");
+        let content = decode(&state.poll_diff_spans().unwrap())
+            .unwrap()
+            .content
+            .unwrap();
+        assert_eq!(content.spans.unwrap().pop(), Some(3..4));
+        assert_eq!(content.lines[0].runs[0].col, 3);
+        assert_eq!(decode(&bytes).unwrap().encode(), bytes);
+    }
+
+    #[test]
+    fn spans_keep_wide_chars_whole() {
+        let mut state = AvtState::with_backend(fake(8, 1));
+        state.feed("a\u{4e2d}".as_bytes());
+        state.poll_diff_spans();
+
+        state.feed("\u{6587}".as_bytes());
+        let content = decode(&state.poll_diff_spans().unwrap())
+            .unwrap()
+            .content
+            .unwrap();
+        assert_eq!(content.spans.unwrap().pop(), Some(3..5));
+        let run = &content.lines[0].runs[0];
+        assert_eq!((run.col, run.text.as_str(), run.cells), (3, "\u{6587}", 2));
+
+        let line = state.screen().lines.swap_remove(0);
+        assert_eq!(crop(&line, &(2..4)).runs[0].col, 3);
+        assert!(crop(&line, &(2..3)).runs.is_empty());
+    }
+
+    #[test]
+    fn damage_diff_names_changed_cells() {
+        let mut state = AvtState::with_backend(fake(20, 2));
+        let first = decode(&state.poll_diff_damage().unwrap()).unwrap();
+        assert_eq!(first.damage, Some(vec![(0, 0..20), (1, 0..20)]));
+
+        state.feed(b"ab");
+        let bytes = state.poll_diff_damage().unwrap();
+        assert_eq!(bytes[0], 7);
+        let diff = decode(&bytes).unwrap();
+        assert_eq!(diff.lines, [0]);
+        assert_eq!(diff.damage, Some(vec![(0, 0..2)]));
+        assert_eq!(diff.encode(), bytes);
+
+        // A clock and a progress bar far apart, then changes close together
+        let line = |text: &str| {
+            let mut state = AvtState::with_backend(fake(20, 1));
+            state.feed(text.as_bytes());
+            state.screen().lines.swap_remove(0)
+        };
+        let old = line("12:00      [#  ]");
+        assert_eq!(changed_spans(&old, &line("12:01      [## ]"), 20), [4..5, 13..14]);
+        let close = changed_spans(&old, &line("02:01      [#  ]"), 20);
+        assert_eq!(close.iter().collect::<Vec<_>>(), [&(0..5)]);
+        assert!(changed_spans(&old, &old, 20).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "images")]
+    fn image_placements_change_by_entries() {
+        let mut state = AvtState::with_backend(fake(20, 3));
+        state.poll_diff_content();
+        // The 2×2 PNG of the `png` tests, 3 cells by 1
+        let image = concat!(
+            "ab\x1b]1337;File=inline=1;width=3;height=1:",
+            "iVBORw0KGgoAAAANSUhEUgAAAAIAAAACAgMAAAAAAAAAAAAACVBMVEX/AAAA/wAAAP8AAAAAAAAA",
+            "AXRSTlOAAAAAAAAAAAxJREFUeJxjFGBIAAAAmAByAAAAAAAAAABJRU5EAAAAAA==\x07",
+        );
+        state.feed(image.as_bytes());
+        let placed = state.screen().images[0];
+        let bytes = state.poll_diff_content().unwrap();
+        let diff = decode(&bytes).unwrap();
+        assert_eq!(diff.images, Some(vec![ImageChange::Added(placed)]));
+        assert_eq!(diff.encode(), bytes);
+
+        state.feed(b"\x1b[2J");
+        let diff = decode(&state.poll_diff_damage().unwrap()).unwrap();
+        assert_eq!(diff.images, Some(vec![ImageChange::Removed(placed)]));
+        state.feed(b"x");
+        let bytes = state.poll_diff().unwrap();
+        assert_eq!(decode(&bytes).unwrap().images, None);
+        assert_eq!(bytes[bytes.len() - 1] & IMAGES_CHANGED, 0);
+
+        // Scrolled a row, with its middle row printed over
+        let tall = ImagePlacement {
+            id: 2,
+            col: 0,
+            row: 0,
+            rows: 3,
+            cols: 4,
+            image_row: 0,
+            image_rows: 3,
+        };
+        let top = ImagePlacement { row: 1, rows: 1, ..tall };
+        let bottom = ImagePlacement { row: 3, image_row: 2, ..top };
+        let changes = image_changes(&[placed, tall], &[top, bottom]);
+        assert_eq!(
+            changes,
+            [
+                ImageChange::Removed(placed),
+                ImageChange::Moved(top),
+                ImageChange::Added(bottom)
+            ]
+        );
+        let mut table = vec![placed, tall];
+        apply_images(&mut table, &changes);
+        assert_eq!(table, [top, bottom]);
+        assert!(image_changes(&table, &table).is_empty());
+    }
+}
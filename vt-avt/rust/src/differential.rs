@@ -0,0 +1,120 @@
+//! Differential testing against alacritty_terminal (`differential` feature,
+//! desktop only).
+//!
+//! Feeds the same bytes to the wrapper and to a second emulator and reports
+//! rows whose visible text differs, plus cursor disagreements. If alacritty
+//! agrees with upstream avt but not with the wrapper, the bug is ours.
+
+use crate::backend::TerminalBackend;
+use crate::AvtState;
+use alacritty_terminal::event::VoidListener;
+use alacritty_terminal::grid::Dimensions;
+use alacritty_terminal::index::{Column, Line};
+use alacritty_terminal::term::cell::Flags;
+use alacritty_terminal::term::test::TermSize;
+use alacritty_terminal::term::{Config, Term};
+use alacritty_terminal::vte::ansi::Processor;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowMismatch {
+    pub row: usize,
+    pub avt: String,
+    pub other: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    pub rows: Vec<RowMismatch>,
+    /// `(avt, other)` cursor positions as `(col, row)` when they differ
+    pub cursor: Option<((usize, usize), (usize, usize))>,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty() && self.cursor.is_none()
+    }
+}
+
+pub struct Differential {
+    avt: AvtState,
+    term: Term<VoidListener>,
+    parser: Processor,
+}
+
+impl Differential {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Differential {
+            avt: AvtState::new(cols, rows),
+            term: Term::new(Config::default(), &TermSize::new(cols, rows), VoidListener),
+            parser: Processor::new(),
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.avt.feed(bytes);
+        self.parser.advance(&mut self.term, bytes);
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.avt.resize(cols, rows);
+        self.term.resize(TermSize::new(cols, rows));
+    }
+
+    /// Compare the visible grids. Trailing blanks are ignored on both sides.
+    pub fn compare(&self) -> Report {
+        let backend = self.avt.backend();
+        let avt_rows: Vec<String> = (0..backend.size().1)
+            .map(|row| backend.row_text(row).trim_end().to_string())
+            .collect();
+        let other_rows = self.other_rows();
+
+        let rows = avt_rows
+            .iter()
+            .zip(other_rows.iter())
+            .enumerate()
+            .filter(|(_, (a, o))| a != o)
+            .map(|(row, (a, o))| RowMismatch {
+                row,
+                avt: a.clone(),
+                other: o.clone(),
+            })
+            .collect();
+
+        let cursor = backend.cursor();
+        let avt_cursor = (cursor.col, cursor.row);
+        let point = self.term.grid().cursor.point;
+        let other_cursor = (point.column.0, point.line.0.max(0) as usize);
+
+        Report {
+            rows,
+            cursor: (avt_cursor != other_cursor).then_some((avt_cursor, other_cursor)),
+        }
+    }
+
+    fn other_rows(&self) -> Vec<String> {
+        let grid = self.term.grid();
+        (0..grid.screen_lines())
+            .map(|row| {
+                let line = &grid[Line(row as i32)];
+                let text: String = (0..grid.columns())
+                    .map(|col| &line[Column(col)])
+                    .filter(|cell| !cell.flags.contains(Flags::WIDE_CHAR_SPACER))
+                    .map(|cell| cell.c)
+                    .collect();
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agrees_on_common_sequences() {
+        let mut d = Differential::new(20, 4);
+        d.feed(b"\x1b[1;31mred\x1b[0m\r\n\ttab\x1b[2;10Hx\x1b[K\r\n\x1b[3@ins");
+        assert_eq!(d.compare(), Report::default());
+    }
+}
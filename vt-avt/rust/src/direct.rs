@@ -0,0 +1,144 @@
+//! Feeding and snapshotting through direct `ByteBuffer`s.
+//!
+//! `vtFeed` and `vtSnapshot` go through Java byte arrays: every feed is
+//! copied in, and every snapshot is a new array for the GC to collect. The
+//! direct variants use memory the app allocates once
+//! (`ByteBuffer.allocateDirect`) and reuses. Feeds are read in place, and
+//! snapshots are encoded into a buffer kept per VT and copied straight
+//! into the app's, so a steady frame loop allocates nothing on the Java
+//! heap.
+//!
+//! The app must not touch a buffer while a call is using it. Neither call
+//! moves the buffer's position or limit.
+
+use crate::{handles, VtHandle};
+use jni::objects::{JByteBuffer, JClass};
+use jni::sys::{jboolean, jint, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::ops::Range;
+
+/// `offset..offset + len`, if that lies within `capacity`.
+fn checked_range(capacity: usize, offset: jint, len: jint) -> Option<Range<usize>> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    (end <= capacity).then_some(start..end)
+}
+
+/// Copy `snapshot` to the front of `out`. Returns its length, or its
+/// length negated, copying nothing, if `out` is too small.
+fn copy_snapshot(snapshot: &[u8], out: &mut [u8]) -> jint {
+    let len = snapshot.len().min(jint::MAX as usize) as jint;
+    match out.get_mut(..snapshot.len()) {
+        Some(out) => {
+            out.copy_from_slice(snapshot);
+            len
+        }
+        None => -len,
+    }
+}
+
+/// The memory of a direct buffer, `None` for a heap buffer.
+///
+/// # Safety
+///
+/// The slice aliases Java memory: nothing else may use the buffer while it
+/// is held.
+pub(crate) unsafe fn buffer_bytes<'b>(env: &JNIEnv, buffer: &JByteBuffer) -> Option<&'b mut [u8]> {
+    let address = env.get_direct_buffer_address(buffer).ok()?;
+    let capacity = env.get_direct_buffer_capacity(buffer).ok()?;
+    if address.is_null() {
+        return None;
+    }
+    Some(std::slice::from_raw_parts_mut(address, capacity))
+}
+
+// JNI functions
+
+/// `vtFeed` of `len` bytes at `offset` in the direct `buffer`. Returns
+/// false, feeding nothing, for an invalid handle, a heap buffer or a range
+/// outside the buffer.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFeedDirect(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    buffer: JByteBuffer,
+    offset: jint,
+    len: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(bytes) = (unsafe { buffer_bytes(&env, &buffer) }) else {
+            return JNI_FALSE;
+        };
+        let Some(range) = checked_range(bytes.len(), offset, len) else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        vt.feed(&bytes[range]);
+        JNI_TRUE
+    })
+}
+
+/// Encode a snapshot to the start of the direct `buffer`. Returns its
+/// length; if it doesn't fit, nothing is written and the length needed is
+/// returned negated (always below -1, a snapshot being at least 5 bytes).
+/// -1 for an invalid handle or a heap buffer.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSnapshotInto(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    buffer: JByteBuffer,
+) -> jint {
+    jni_guard!(env, {
+        let Some(out) = (unsafe { buffer_bytes(&env, &buffer) }) else {
+            return -1;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -1;
+        };
+
+        copy_snapshot(vt.encode_snapshot_reused(), out)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn ranges_and_snapshots_must_fit() {
+        assert_eq!(checked_range(10, 2, 8), Some(2..10));
+        assert_eq!(checked_range(10, 0, 0), Some(0..0));
+        assert_eq!(checked_range(10, 3, 8), None);
+        assert_eq!(checked_range(10, -1, 2), None);
+        assert_eq!(checked_range(10, 2, -1), None);
+        assert_eq!(
+            checked_range(usize::MAX, jint::MAX, jint::MAX),
+            Some(0x7fff_ffff..0xffff_fffe)
+        );
+
+        let mut vt = AvtState::with_backend(fake(4, 1));
+        vt.feed(b"hi");
+        let snapshot = vt.encode_snapshot();
+        let mut out = vec![0xaa; snapshot.len() + 3];
+        assert_eq!(
+            copy_snapshot(vt.encode_snapshot_reused(), &mut out),
+            snapshot.len() as jint
+        );
+        assert_eq!(out[..snapshot.len()], snapshot);
+        assert_eq!(out[snapshot.len()..], [0xaa; 3]);
+
+        let mut short = vec![0; snapshot.len() - 1];
+        assert_eq!(
+            copy_snapshot(&snapshot, &mut short),
+            -(snapshot.len() as jint)
+        );
+        assert!(short.iter().all(|&b| b == 0));
+    }
+}
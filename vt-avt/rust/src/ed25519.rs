@@ -0,0 +1,99 @@
+//! Ed25519 signatures (RFC 8032), for signed exports, through
+//! `ed25519-dalek`. Verification is strict: a non-canonical S or a
+//! small-order key or R fails it, so a signature can't be altered into
+//! another that still checks.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+pub const SEED_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+pub fn public_key(seed: &[u8; SEED_LEN]) -> [u8; PUBLIC_KEY_LEN] {
+    SigningKey::from_bytes(seed).verifying_key().to_bytes()
+}
+
+pub fn sign(seed: &[u8; SEED_LEN], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    SigningKey::from_bytes(seed).sign(message).to_bytes()
+}
+
+pub fn verify(
+    public: &[u8; PUBLIC_KEY_LEN],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public) else {
+        return false;
+    };
+    key.verify_strict(message, &Signature::from_bytes(signature))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let bytes: Vec<u8> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn matches_rfc8032_vectors() {
+        let cases = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                &b""[..],
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                 5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                &[0x72][..],
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                 085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+        for (seed, message, public, signature) in cases {
+            let seed = hex::<32>(seed);
+            assert_eq!(public_key(&seed), hex::<32>(public));
+            let made = sign(&seed, message);
+            assert_eq!(made, hex::<64>(signature));
+            assert!(verify(&hex(public), message, &made));
+        }
+    }
+
+    #[test]
+    fn rejects_altered_messages_and_signatures() {
+        let seed = [7u8; SEED_LEN];
+        let public = public_key(&seed);
+        let signature = sign(&seed, b"cast");
+        assert!(verify(&public, b"cast", &signature));
+        assert!(!verify(&public, b"cask", &signature));
+        assert!(!verify(&public_key(&[8; SEED_LEN]), b"cast", &signature));
+
+        let mut flipped = signature;
+        flipped[40] ^= 1;
+        assert!(!verify(&public, b"cast", &flipped));
+        // S + L is the same scalar but not canonical
+        let mut high = signature;
+        high[63] |= 0xf0;
+        assert!(!verify(&public, b"cast", &high));
+    }
+
+    #[test]
+    fn rejects_small_order_keys() {
+        // The identity as key and R with S = 0 passes the cofactorless
+        // equation for any message; strict verification refuses it
+        let mut identity = [0u8; PUBLIC_KEY_LEN];
+        identity[0] = 1;
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[0] = 1;
+        assert!(!verify(&identity, b"cast", &signature));
+    }
+}
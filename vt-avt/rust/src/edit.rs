@@ -0,0 +1,811 @@
+//! Cast editing with undo history.
+//!
+//! An `EditSession` keeps the cast it was opened on plus the list of edits
+//! applied to it. Redo reapplies one edit; undo replays the remaining ones
+//! from the base, so history costs one `Edit` per step rather than a copy
+//! of the recording.
+//!
+//! `split` cuts a cast into clips (per chapter marker or per shell
+//! command) that are each a valid recording on their own; `append_with_gap`
+//! stitches takes together.
+
+use crate::cast::{micros_arg, Cast, Event, EventKind};
+use crate::handles::{self, Kind};
+use crate::shell::ShellTimeline;
+use jni::objects::{JClass, JLongArray, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// Event `index` is earlier than the one before it
+    NonMonotonic {
+        index: usize,
+    },
+    NegativeGap,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::NonMonotonic { index } => {
+                write!(f, "event {} is earlier than the previous one", index)
+            }
+            EditError::NegativeGap => write!(f, "negative gap"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Keep `[start_us, end_us]`, moved to start at 0. Output before the
+    /// start is folded into one event at 0 so the first frame still shows
+    /// the screen as it was.
+    Trim { start_us: i64, end_us: i64 },
+    /// Insert another recording at `at_us`, pushing later events back by
+    /// its duration. Resize events bracket it when the sizes differ.
+    Splice { at_us: i64, cast: Cast },
+    /// Mask every occurrence of `text` in output, input and markers with
+    /// `*`, one per character so the layout doesn't shift. Occurrences
+    /// split across two events are not found.
+    Redact { text: String },
+    /// Cap every pause between events at `max_us`.
+    IdleCap { max_us: i64 },
+}
+
+impl Edit {
+    pub fn apply(&self, cast: &mut Cast) {
+        match self {
+            &Edit::Trim { start_us, end_us } => trim(cast, start_us, end_us),
+            Edit::Splice { at_us, cast: other } => splice(cast, *at_us, other),
+            Edit::Redact { text } => redact(cast, text),
+            &Edit::IdleCap { max_us } => idle_cap(cast, max_us),
+        }
+    }
+}
+
+fn trim(cast: &mut Cast, start_us: i64, end_us: i64) {
+    let mut prologue = String::new();
+    let mut size = None;
+    let mut events = Vec::with_capacity(cast.events.len());
+
+    for event in cast.events.drain(..) {
+        if event.time_us > end_us {
+            break;
+        }
+        if event.time_us >= start_us {
+            events.push(Event {
+                time_us: event.time_us - start_us,
+                kind: event.kind,
+            });
+            continue;
+        }
+        match event.kind {
+            EventKind::Output(data) => prologue.push_str(&data),
+            EventKind::Resize { cols, rows } => {
+                // Content after a resize was drawn for the new size
+                size = Some((cols, rows));
+            }
+            _ => {}
+        }
+    }
+
+    let mut head = Vec::new();
+    if let Some((cols, rows)) = size {
+        head.push(Event {
+            time_us: 0,
+            kind: EventKind::Resize { cols, rows },
+        });
+    }
+    if !prologue.is_empty() {
+        head.push(Event {
+            time_us: 0,
+            kind: EventKind::Output(prologue),
+        });
+    }
+    head.append(&mut events);
+    cast.events = head;
+}
+
+/// Terminal size in effect just before `time_us`.
+pub(crate) fn size_at(cast: &Cast, time_us: i64) -> (usize, usize) {
+    cast.events
+        .iter()
+        .take_while(|event| event.time_us < time_us)
+        .fold(
+            (cast.header.cols, cast.header.rows),
+            |size, event| match event.kind {
+                EventKind::Resize { cols, rows } => (cols, rows),
+                _ => size,
+            },
+        )
+}
+
+fn splice(cast: &mut Cast, at_us: i64, other: &Cast) {
+    let duration = other.events.last().map_or(0, |event| event.time_us.max(0));
+    let size = size_at(cast, at_us);
+    let other_size = (other.header.cols, other.header.rows);
+
+    let split = cast.events.partition_point(|event| event.time_us < at_us);
+    let mut tail = cast.events.split_off(split);
+    for event in &mut tail {
+        event.time_us += duration;
+    }
+
+    let resize = |time_us, (cols, rows)| Event {
+        time_us,
+        kind: EventKind::Resize { cols, rows },
+    };
+    if other_size != size {
+        cast.events.push(resize(at_us, other_size));
+    }
+    cast.events.extend(other.events.iter().map(|event| Event {
+        time_us: at_us + event.time_us,
+        kind: event.kind.clone(),
+    }));
+    if other_size != size {
+        cast.events.push(resize(at_us + duration, size));
+    }
+    cast.events.append(&mut tail);
+}
+
+fn redact(cast: &mut Cast, text: &str) {
+    for event in &mut cast.events {
+        redact_event(event, text);
+    }
+}
+
+pub(crate) fn redact_event(event: &mut Event, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let EventKind::Output(data) | EventKind::Input(data) | EventKind::Marker(data) =
+        &mut event.kind
+    {
+        if data.contains(text) {
+            *data = data.replace(text, &"*".repeat(text.chars().count()));
+        }
+    }
+}
+
+fn idle_cap(cast: &mut Cast, max_us: i64) {
+    let mut prev = 0;
+    let mut at = 0;
+    for event in &mut cast.events {
+        let delta = (event.time_us - prev).max(0);
+        prev = event.time_us;
+        at += delta.min(max_us.max(0));
+        event.time_us = at;
+    }
+}
+
+/// Clips between consecutive `cuts` (sorted, in cast time), each trimmed
+/// like `Edit::Trim` so it starts on the screen as it was. Cuts at or
+/// before the first event or after the last are ignored.
+pub fn split(cast: &Cast, cuts: &[i64]) -> Vec<Cast> {
+    let (Some(first), Some(last)) = (cast.events.first(), cast.events.last()) else {
+        return vec![cast.clone()];
+    };
+    let mut bounds = vec![i64::MIN];
+    bounds.extend(
+        cuts.iter()
+            .copied()
+            .filter(|&cut| cut > first.time_us && cut <= last.time_us),
+    );
+    bounds.dedup();
+
+    let mut clips = Vec::with_capacity(bounds.len());
+    for (i, &start) in bounds.iter().enumerate() {
+        let mut clip = cast.clone();
+        // Times are whole microseconds, so this makes the range half-open
+        let end_us = bounds.get(i + 1).map_or(i64::MAX, |&next| next - 1);
+        if i == 0 {
+            clip.events.retain(|event| event.time_us <= end_us);
+        } else {
+            trim(&mut clip, start, end_us);
+        }
+        clips.push(clip);
+    }
+    clips
+}
+
+/// Events must never go back in time; players and `split` rely on it.
+pub fn check_monotonic(cast: &Cast) -> Result<(), EditError> {
+    match cast
+        .events
+        .windows(2)
+        .position(|pair| pair[1].time_us < pair[0].time_us)
+    {
+        Some(i) => Err(EditError::NonMonotonic { index: i + 1 }),
+        None => Ok(()),
+    }
+}
+
+/// Shift every event so the first one is at `first_us`.
+pub fn rebase(cast: &mut Cast, first_us: i64) {
+    let Some(first) = cast.events.first() else {
+        return;
+    };
+    let delta = first_us - first.time_us;
+    for event in &mut cast.events {
+        event.time_us += delta;
+    }
+}
+
+/// `a` followed by `b`, starting `gap_us` after `a`'s last event. `b` was
+/// recorded on a fresh terminal, so it starts with a reset (and a resize
+/// if `a` ended at a different size); the result keeps `a`'s header.
+pub fn append_with_gap(a: &Cast, b: &Cast, gap_us: i64) -> Result<Cast, EditError> {
+    if gap_us < 0 {
+        return Err(EditError::NegativeGap);
+    }
+    check_monotonic(a)?;
+    check_monotonic(b)?;
+
+    let end = a.events.last().map_or(0, |event| event.time_us);
+    let join = end + gap_us;
+    let mut out = a.clone();
+
+    let size = size_at(a, i64::MAX);
+    let (cols, rows) = (b.header.cols, b.header.rows);
+    if size != (cols, rows) {
+        out.events.push(Event {
+            time_us: join,
+            kind: EventKind::Resize { cols, rows },
+        });
+    }
+    out.events.push(Event {
+        time_us: join,
+        kind: EventKind::Output("\x1bc".to_string()),
+    });
+
+    // b's own timeline starts at 0, not at its first event
+    out.events.extend(b.events.iter().map(|event| Event {
+        time_us: join + event.time_us.max(0),
+        kind: event.kind.clone(),
+    }));
+    Ok(out)
+}
+
+/// One clip per chapter: cuts at every marker event.
+pub fn split_by_markers(cast: &Cast) -> Vec<Cast> {
+    let cuts: Vec<i64> = cast
+        .events
+        .iter()
+        .filter(|event| matches!(event.kind, EventKind::Marker(_)))
+        .map(|event| event.time_us)
+        .collect();
+    split(cast, &cuts)
+}
+
+/// One clip per shell command (OSC 133): cuts at the first event after
+/// each command finishes, so a clip runs from its prompt to the end of the
+/// command's output.
+pub fn split_by_commands(cast: &Cast) -> Vec<Cast> {
+    let times: Vec<i64> = cast.events.iter().map(|event| event.time_us).collect();
+    let shell = ShellTimeline::scan(cast, &times);
+    let cuts: Vec<i64> = shell
+        .commands
+        .iter()
+        .filter_map(|command| times.iter().copied().find(|&t| t > command.end_us))
+        .collect();
+    split(cast, &cuts)
+}
+
+pub struct EditSession {
+    base: Cast,
+    current: Cast,
+    edits: Vec<Edit>,
+    /// Edits in effect; the rest of `edits` can be redone
+    applied: usize,
+}
+
+registered!(EditSession, Kind::EditSession);
+
+impl EditSession {
+    pub fn new(cast: Cast) -> Self {
+        EditSession {
+            current: cast.clone(),
+            base: cast,
+            edits: Vec::new(),
+            applied: 0,
+        }
+    }
+
+    pub fn cast(&self) -> &Cast {
+        &self.current
+    }
+
+    /// Apply `edit`, dropping anything that could have been redone.
+    pub fn apply(&mut self, edit: Edit) {
+        self.edits.truncate(self.applied);
+        edit.apply(&mut self.current);
+        self.edits.push(edit);
+        self.applied += 1;
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if self.applied == 0 {
+            return false;
+        }
+        self.applied -= 1;
+        self.current = self.base.clone();
+        for edit in &self.edits[..self.applied] {
+            edit.apply(&mut self.current);
+        }
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.edits.get(self.applied) else {
+            return false;
+        };
+        edit.apply(&mut self.current);
+        self.applied += 1;
+        true
+    }
+}
+
+// JNI functions
+
+/// Opens a session on a copy of the cast behind `cast_handle`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editSessionNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    cast_handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return 0;
+        };
+
+        handles::add(EditSession::new(cast.clone()))
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editSessionFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<EditSession>(&mut env, handle));
+    })
+}
+
+fn apply_edit(env: &mut JNIEnv, handle: jlong, edit: Edit) {
+    let Some(session) = handles::object::<EditSession>(env, handle) else {
+        return;
+    };
+
+    session.apply(edit);
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editTrim(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    start_micros: jlong,
+    end_micros: jlong,
+) {
+    jni_guard!(env, {
+        let (Some(start_us), Some(end_us)) = (micros_arg(start_micros), micros_arg(end_micros))
+        else {
+            return;
+        };
+
+        apply_edit(&mut env, handle, Edit::Trim { start_us, end_us });
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editSplice(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    at_micros: jlong,
+    cast_handle: jlong,
+) {
+    jni_guard!(env, {
+        let Some(at_us) = micros_arg(at_micros) else {
+            return;
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return;
+        };
+
+        let cast = cast.clone();
+        apply_edit(&mut env, handle, Edit::Splice { at_us, cast });
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editRedact(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    text: JString,
+) {
+    jni_guard!(env, {
+        let text: String = match env.get_string(&text) {
+            Ok(s) => s.into(),
+            Err(_) => return,
+        };
+
+        apply_edit(&mut env, handle, Edit::Redact { text });
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editIdleCap(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    max_micros: jlong,
+) {
+    jni_guard!(env, {
+        let Some(max_us) = micros_arg(max_micros) else {
+            return;
+        };
+
+        apply_edit(&mut env, handle, Edit::IdleCap { max_us });
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editUndo(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(session) = handles::object::<EditSession>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if session.undo() {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editRedo(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(session) = handles::object::<EditSession>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if session.redo() {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+fn clip_handles<'a>(env: &JNIEnv<'a>, clips: Vec<Cast>) -> JLongArray<'a> {
+    let handles: Vec<jlong> = clips
+        .into_iter()
+        .map(handles::add)
+        .collect();
+    crate::long_array(env, &handles)
+}
+
+/// Cast handles for each chapter, each freed with `castFree`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSplitByMarkers<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        clip_handles(&env, split_by_markers(cast))
+    })
+}
+
+/// Cast handles for each shell command, each freed with `castFree`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSplitByCommands<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        clip_handles(&env, split_by_commands(cast))
+    })
+}
+
+/// New cast handle for `a` then `b`, or 0 if either is invalid, out of
+/// order, or `gap_micros` is negative or out of range (see
+/// `cast::micros_arg`).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castAppendWithGap(
+    mut env: JNIEnv,
+    _class: JClass,
+    a_handle: jlong,
+    b_handle: jlong,
+    gap_micros: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(gap_us) = micros_arg(gap_micros) else {
+            return 0;
+        };
+        let Some(a) = handles::object_ref::<Cast>(&mut env, a_handle) else {
+            return 0;
+        };
+        let Some(b) = handles::object_ref::<Cast>(&mut env, b_handle) else {
+            return 0;
+        };
+
+        match append_with_gap(a, b, gap_us) {
+            Ok(cast) => handles::add(cast),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castRebase(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    first_event_micros: jlong,
+) {
+    jni_guard!(env, {
+        let Some(first_event_us) = micros_arg(first_event_micros) else {
+            return;
+        };
+        let Some(cast) = handles::object::<Cast>(&mut env, handle) else {
+            return;
+        };
+
+        rebase(cast, first_event_us);
+    })
+}
+
+/// Index of the first event earlier than its predecessor, or -1.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castCheckMonotonic(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jint {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return -1;
+        };
+
+        match check_monotonic(cast) {
+            Err(EditError::NonMonotonic { index }) => index as jint,
+            _ => -1,
+        }
+    })
+}
+
+/// New cast handle with the edits in effect, freed with `castFree`. The
+/// session stays usable.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_editCommit(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(session) = handles::object_ref::<EditSession>(&mut env, handle) else {
+            return 0;
+        };
+
+        handles::add(session.cast().clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAST: &[u8] = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+        [1.0, \"o\", \"$ \"]\n\
+        [2.0, \"r\", \"100x30\"]\n\
+        [3.0, \"o\", \"export TOKEN=s3cret\"]\n\
+        [4.0, \"m\", \"s3cret\"]\n\
+        [30.0, \"o\", \"\\r\\n$ \"]\n";
+
+    fn times(cast: &Cast) -> Vec<i64> {
+        cast.events.iter().map(|e| e.time_us).collect()
+    }
+
+    #[test]
+    fn trim_folds_earlier_output_into_first_frame() {
+        let mut cast = Cast::parse(CAST).unwrap();
+        Edit::Trim {
+            start_us: 2_500_000,
+            end_us: 4_000_000,
+        }
+        .apply(&mut cast);
+        assert_eq!(times(&cast), [0, 0, 500_000, 1_500_000]);
+        assert_eq!(
+            cast.events[0].kind,
+            EventKind::Resize {
+                cols: 100,
+                rows: 30
+            }
+        );
+        assert_eq!(cast.events[1].kind, EventKind::Output("$ ".into()));
+    }
+
+    #[test]
+    fn splice_brackets_other_sizes_and_shifts_the_rest() {
+        let mut cast = Cast::parse(CAST).unwrap();
+        let other = Cast::parse(
+            b"{\"version\": 2, \"width\": 40, \"height\": 10}\n\
+            [0.5, \"o\", \"a\"]\n\
+            [1.0, \"o\", \"b\"]\n",
+        )
+        .unwrap();
+        Edit::Splice {
+            at_us: 3_000_000,
+            cast: other,
+        }
+        .apply(&mut cast);
+
+        assert_eq!(
+            times(&cast),
+            [
+                1_000_000, 2_000_000, 3_000_000, 3_500_000, 4_000_000, 4_000_000, 4_000_000,
+                5_000_000, 31_000_000
+            ]
+        );
+        assert_eq!(
+            cast.events[2].kind,
+            EventKind::Resize { cols: 40, rows: 10 }
+        );
+        assert_eq!(
+            cast.events[5].kind,
+            EventKind::Resize {
+                cols: 100,
+                rows: 30
+            }
+        );
+        assert_eq!(
+            cast.events[6].kind,
+            EventKind::Output("export TOKEN=s3cret".into())
+        );
+    }
+
+    #[test]
+    fn redact_and_idle_cap() {
+        let mut cast = Cast::parse(CAST).unwrap();
+        Edit::Redact {
+            text: "s3cret".into(),
+        }
+        .apply(&mut cast);
+        Edit::IdleCap { max_us: 2_000_000 }.apply(&mut cast);
+        assert_eq!(
+            cast.events[2].kind,
+            EventKind::Output("export TOKEN=******".into())
+        );
+        assert_eq!(cast.events[3].kind, EventKind::Marker("******".into()));
+        assert_eq!(
+            times(&cast),
+            [1_000_000, 2_000_000, 3_000_000, 4_000_000, 6_000_000]
+        );
+    }
+
+    #[test]
+    fn session_undo_and_redo() {
+        let original = Cast::parse(CAST).unwrap();
+        let mut session = EditSession::new(original.clone());
+        assert!(!session.undo());
+
+        session.apply(Edit::IdleCap { max_us: 1_000_000 });
+        session.apply(Edit::Redact {
+            text: "TOKEN".into(),
+        });
+        let edited = session.cast().clone();
+        assert_eq!(times(&edited)[4], 5_000_000);
+
+        assert!(session.undo());
+        assert!(session.undo());
+        assert_eq!(session.cast(), &original);
+        assert!(session.redo());
+        assert!(session.redo());
+        assert!(!session.redo());
+        assert_eq!(session.cast(), &edited);
+
+        // A new edit after undo discards the redo tail
+        session.undo();
+        session.apply(Edit::Trim {
+            start_us: 0,
+            end_us: 3_000_000,
+        });
+        assert!(!session.redo());
+        assert_eq!(session.cast().events.len(), 3);
+    }
+
+    #[test]
+    fn splits_into_valid_clips_per_marker_and_command() {
+        let cast = Cast::parse(CAST).unwrap();
+        let clips = split_by_markers(&cast);
+        assert_eq!(clips.len(), 2);
+        assert_eq!(times(&clips[0]), [1_000_000, 2_000_000, 3_000_000]);
+        // The second clip starts at the marker, on the screen as it was
+        assert_eq!(times(&clips[1]), [0, 0, 0, 26_000_000]);
+        assert_eq!(clips[1].events[2].kind, EventKind::Marker("s3cret".into()));
+        for clip in &clips {
+            assert_eq!(&Cast::parse(&clip.write()).unwrap(), clip);
+        }
+
+        let session = Cast::parse(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"o\", \"$ \\u001b]133;B\\u0007\"]\n\
+            [2.0, \"o\", \"\\u001b]133;C\\u0007ok\\r\\n\\u001b]133;D;0\\u0007\"]\n\
+            [3.0, \"o\", \"$ \\u001b]133;B\\u0007\"]\n\
+            [4.0, \"o\", \"\\u001b]133;C\\u0007\\u001b]133;D;1\\u0007\"]\n",
+        )
+        .unwrap();
+        let clips = split_by_commands(&session);
+        assert_eq!(clips.len(), 2);
+        assert_eq!(times(&clips[0]), [1_000_000, 2_000_000]);
+        assert_eq!(times(&clips[1]), [0, 0, 1_000_000]);
+        assert_eq!(
+            clips[1].events[0].kind,
+            EventKind::Output("$ \x1b]133;B\x07\x1b]133;C\x07ok\r\n\x1b]133;D;0\x07".into())
+        );
+    }
+
+    #[test]
+    fn append_with_gap_rebases_the_second_take() {
+        let a = Cast::parse(CAST).unwrap();
+        let mut b = Cast::parse(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [5.0, \"o\", \"take two\"]\n\
+            [6.0, \"o\", \"!\"]\n",
+        )
+        .unwrap();
+        rebase(&mut b, 0);
+        assert_eq!(times(&b), [0, 1_000_000]);
+
+        let joined = append_with_gap(&a, &b, 2_000_000).unwrap();
+        assert_eq!(
+            &times(&joined)[5..],
+            [32_000_000, 32_000_000, 32_000_000, 33_000_000]
+        );
+        // a ended at 100x30, b was recorded at 80x24
+        assert_eq!(
+            joined.events[5].kind,
+            EventKind::Resize { cols: 80, rows: 24 }
+        );
+        assert_eq!(joined.events[6].kind, EventKind::Output("\x1bc".into()));
+        assert_eq!(check_monotonic(&joined), Ok(()));
+
+        b.events[1].time_us = -1;
+        assert_eq!(
+            check_monotonic(&b),
+            Err(EditError::NonMonotonic { index: 1 })
+        );
+        assert_eq!(
+            append_with_gap(&a, &b, 0),
+            Err(EditError::NonMonotonic { index: 1 })
+        );
+        assert_eq!(append_with_gap(&a, &a, -1), Err(EditError::NegativeGap));
+    }
+}
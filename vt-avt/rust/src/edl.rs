@@ -0,0 +1,507 @@
+//! Edit decision lists: non-destructive cuts over one or more recordings.
+//!
+//! An `Edl` names segments of source casts plus transforms for the whole
+//! result (redaction, idle cap). Saving it stores only that, a few hundred
+//! bytes however long the source; the sources are handed in again when the
+//! list is rendered, streaming events for export or building the events a
+//! player needs.
+//!
+//! A segment that doesn't continue the previous one starts by resetting
+//! the terminal and replaying its source's earlier output, so each cut
+//! begins on the screen as it was at that point of the recording.
+
+use crate::cast::{micros_arg, Cast, Event, EventKind, Timing};
+use crate::edit::{redact_event, size_at};
+use crate::handles::{self, Kind};
+use crate::json::{self, JsonError, Value};
+use crate::player::Player;
+use jni::objects::{JByteArray, JClass, JLongArray, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use std::fmt;
+
+const VERSION: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdlError {
+    Json(JsonError),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for EdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdlError::Json(error) => write!(f, "{}", error),
+            EdlError::Invalid(reason) => write!(f, "invalid edit list: {}", reason),
+        }
+    }
+}
+
+/// `[start_us, end_us)` of source `source`, in the source's own time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// Index into the sources given to `render`
+    pub source: usize,
+    pub start_us: i64,
+    pub end_us: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Edl {
+    pub segments: Vec<Segment>,
+    /// Text masked everywhere, as with `Edit::Redact`
+    pub redact: Vec<String>,
+    pub idle_cap_us: Option<i64>,
+}
+
+registered!(Edl, Kind::Edl);
+
+impl Edl {
+    /// `{"version": 1, "segments": [[source, start_us, end_us], ...],
+    /// "redact": [...], "idle_cap_us": n}`, times as integer microseconds.
+    pub fn parse(bytes: &[u8]) -> Result<Edl, EdlError> {
+        let value = json::parse(&String::from_utf8_lossy(bytes)).map_err(EdlError::Json)?;
+        if value.get("version").and_then(Value::as_f64) != Some(VERSION) {
+            return Err(EdlError::Invalid("unsupported version"));
+        }
+
+        let segments = value
+            .get("segments")
+            .and_then(Value::as_array)
+            .ok_or(EdlError::Invalid("missing segments"))?
+            .iter()
+            .map(|segment| match segment.as_array() {
+                Some([source, start, end]) => Some(Segment {
+                    source: source.as_f64()? as usize,
+                    start_us: start.as_f64()? as i64,
+                    end_us: end.as_f64()? as i64,
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(EdlError::Invalid("bad segment"))?;
+
+        let redact = match value.get("redact") {
+            None => Vec::new(),
+            Some(texts) => texts
+                .as_array()
+                .and_then(|texts| {
+                    texts
+                        .iter()
+                        .map(|t| t.as_str().map(str::to_string))
+                        .collect()
+                })
+                .ok_or(EdlError::Invalid("bad redact list"))?,
+        };
+
+        let idle_cap_us = value
+            .get("idle_cap_us")
+            .and_then(Value::as_f64)
+            .map(|us| us as i64);
+
+        Ok(Edl {
+            segments,
+            redact,
+            idle_cap_us,
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let number = |n: i64| Value::Number(n as f64);
+        let mut fields = vec![
+            ("version".to_string(), Value::Number(VERSION)),
+            (
+                "segments".to_string(),
+                Value::Array(
+                    self.segments
+                        .iter()
+                        .map(|s| {
+                            Value::Array(vec![
+                                number(s.source as i64),
+                                number(s.start_us),
+                                number(s.end_us),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "redact".to_string(),
+                Value::Array(self.redact.iter().cloned().map(Value::String).collect()),
+            ),
+        ];
+        if let Some(cap) = self.idle_cap_us {
+            fields.push(("idle_cap_us".to_string(), number(cap)));
+        }
+        Value::Object(fields).to_string().into_bytes()
+    }
+
+    /// Header of the recording the list renders to: the first referenced
+    /// source's.
+    fn first_source<'c>(&self, sources: &[&'c Cast]) -> Option<&'c Cast> {
+        self.segments
+            .iter()
+            .find_map(|segment| sources.get(segment.source).copied())
+    }
+
+    /// Stream the edited events in order. Segments naming a missing source
+    /// are skipped.
+    pub fn render(&self, sources: &[&Cast], emit: impl FnMut(Event)) {
+        let Some(first) = self.first_source(sources) else {
+            return;
+        };
+        let mut out = Output {
+            redact: &self.redact,
+            idle_cap_us: self.idle_cap_us,
+            prev: 0,
+            at: 0,
+            emit,
+        };
+        let mut size = (first.header.cols, first.header.rows);
+        let mut offset = 0;
+        let mut prev: Option<&Segment> = None;
+
+        for segment in &self.segments {
+            let Some(cast) = sources.get(segment.source) else {
+                continue;
+            };
+            let start = cast
+                .events
+                .partition_point(|event| event.time_us < segment.start_us);
+
+            let continues =
+                prev.is_some_and(|p| p.source == segment.source && p.end_us == segment.start_us);
+            if !continues {
+                let segment_size = size_at(cast, segment.start_us);
+                if segment_size != size {
+                    size = segment_size;
+                    let (cols, rows) = size;
+                    out.push(offset, EventKind::Resize { cols, rows });
+                }
+
+                let mut prologue = String::new();
+                if prev.is_some() {
+                    prologue.push_str("\x1bc");
+                }
+                for event in &cast.events[..start] {
+                    if let EventKind::Output(data) = &event.kind {
+                        prologue.push_str(data);
+                    }
+                }
+                if !prologue.is_empty() {
+                    out.push(offset, EventKind::Output(prologue));
+                }
+            }
+
+            for event in cast.events[start..]
+                .iter()
+                .take_while(|event| event.time_us < segment.end_us)
+            {
+                if let EventKind::Resize { cols, rows } = event.kind {
+                    size = (cols, rows);
+                }
+                let time_us = offset + event.time_us - segment.start_us;
+                out.push(time_us, event.kind.clone());
+            }
+
+            offset += (segment.end_us - segment.start_us).max(0);
+            prev = Some(segment);
+        }
+    }
+
+    /// The edited recording as cast file bytes, written event by event.
+    pub fn export(&self, sources: &[&Cast]) -> Option<Vec<u8>> {
+        let first = self.first_source(sources)?;
+        let mut out = first.header.fields.to_string();
+        out.push('\n');
+        let mut timing = Timing::new(&first.header);
+        self.render(sources, |event| {
+            out.push_str(&timing.line(&event));
+            out.push('\n');
+        });
+        Some(out.into_bytes())
+    }
+
+    /// The edited events as a cast, for playback.
+    pub fn to_cast(&self, sources: &[&Cast]) -> Option<Cast> {
+        let header = self.first_source(sources)?.header.clone();
+        let mut events = Vec::new();
+        self.render(sources, |event| events.push(event));
+        Some(Cast { header, events })
+    }
+}
+
+struct Output<'a, F> {
+    redact: &'a [String],
+    idle_cap_us: Option<i64>,
+    /// Last event time before the idle cap
+    prev: i64,
+    at: i64,
+    emit: F,
+}
+
+impl<F: FnMut(Event)> Output<'_, F> {
+    fn push(&mut self, time_us: i64, kind: EventKind) {
+        let delta = (time_us - self.prev).max(0);
+        self.prev = time_us;
+        self.at += self.idle_cap_us.map_or(delta, |cap| delta.min(cap.max(0)));
+
+        let mut event = Event {
+            time_us: self.at,
+            kind,
+        };
+        for text in self.redact {
+            redact_event(&mut event, text);
+        }
+        (self.emit)(event);
+    }
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlNew(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jlong {
+    jni_guard!(env, { handles::add(Edl::default()) })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlLoad(
+    mut env: JNIEnv,
+    _class: JClass,
+    edl_bytes: JByteArray,
+) -> jlong {
+    jni_guard!(env, {
+        let bytes = match env.convert_byte_array(edl_bytes) {
+            Ok(b) => b,
+            Err(_) => return 0,
+        };
+
+        match Edl::parse(&bytes) {
+            Ok(edl) => handles::add(edl),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Edl>(&mut env, handle));
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlSave<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(edl) = handles::object_ref::<Edl>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&edl.write()).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlAddSegment(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    source: jint,
+    start_micros: jlong,
+    end_micros: jlong,
+) {
+    jni_guard!(env, {
+        let Some(edl) = handles::object::<Edl>(&mut env, handle) else {
+            return;
+        };
+        let (Some(start_us), Some(end_us)) = (micros_arg(start_micros), micros_arg(end_micros))
+        else {
+            return;
+        };
+        if source < 0 {
+            return;
+        }
+
+        edl.segments.push(Segment {
+            source: source as usize,
+            start_us,
+            end_us,
+        });
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlAddRedaction(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    text: JString,
+) {
+    jni_guard!(env, {
+        let Some(edl) = handles::object::<Edl>(&mut env, handle) else {
+            return;
+        };
+        let text: String = match env.get_string(&text) {
+            Ok(s) => s.into(),
+            Err(_) => return,
+        };
+
+        edl.redact.push(text);
+    })
+}
+
+/// A non-positive or out of range `max_micros` removes the cap.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlSetIdleCap(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    max_micros: jlong,
+) {
+    jni_guard!(env, {
+        let Some(edl) = handles::object::<Edl>(&mut env, handle) else {
+            return;
+        };
+
+        edl.idle_cap_us = micros_arg(max_micros).filter(|&us| us > 0);
+    })
+}
+
+/// Cast handles (from `castOpen`) in source index order. `None` if the
+/// array can't be read or contains an invalid handle.
+fn sources<'c>(env: &mut JNIEnv, cast_handles: &JLongArray) -> Option<Vec<&'c Cast>> {
+    let len = env.get_array_length(cast_handles).ok()? as usize;
+    let mut ids = vec![0; len];
+    env.get_long_array_region(cast_handles, 0, &mut ids).ok()?;
+    ids.into_iter()
+        .map(|handle| handles::object_ref::<Cast>(env, handle))
+        .collect()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlExport<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    cast_handles: JLongArray<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(edl) = handles::object_ref::<Edl>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        match sources(&mut env, &cast_handles).and_then(|sources| edl.export(&sources)) {
+            Some(out) => env.byte_array_from_slice(&out).unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+/// Player handle (free with `playerFree`) for the edited recording.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_edlPlayer(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    cast_handles: JLongArray,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(edl) = handles::object_ref::<Edl>(&mut env, handle) else {
+            return 0;
+        };
+
+        match sources(&mut env, &cast_handles).and_then(|sources| edl.to_cast(&sources)) {
+            Some(cast) => handles::add(Player::from_cast(cast)),
+            None => 0,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAST: &[u8] = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+        [1.0, \"o\", \"one \"]\n\
+        [2.0, \"o\", \"two \"]\n\
+        [3.0, \"r\", \"40x10\"]\n\
+        [4.0, \"o\", \"four \"]\n\
+        [20.0, \"o\", \"pin 1234\"]\n";
+
+    fn segment(start: f64, end: f64) -> Segment {
+        Segment {
+            source: 0,
+            start_us: (start * 1e6) as i64,
+            end_us: (end * 1e6) as i64,
+        }
+    }
+
+    #[test]
+    fn renders_cuts_with_prologues_and_transforms() {
+        let cast = Cast::parse(CAST).unwrap();
+        let edl = Edl {
+            segments: vec![segment(0.0, 2.0), segment(2.0, 3.0), segment(4.0, 30.0)],
+            redact: vec!["1234".into()],
+            idle_cap_us: Some(2_000_000),
+        };
+
+        let out = edl.to_cast(&[&cast]).unwrap();
+        let events: Vec<_> = out.events.iter().map(|e| (e.time_us, &e.kind)).collect();
+        assert_eq!(
+            events,
+            [
+                (1_000_000, &EventKind::Output("one ".into())),
+                // The second segment continues the first: no prologue
+                (2_000_000, &EventKind::Output("two ".into())),
+                (3_000_000, &EventKind::Resize { cols: 40, rows: 10 }),
+                (3_000_000, &EventKind::Output("\x1bcone two ".into())),
+                (3_000_000, &EventKind::Output("four ".into())),
+                (5_000_000, &EventKind::Output("pin ****".into())),
+            ]
+        );
+
+        let exported = edl.export(&[&cast]).unwrap();
+        assert_eq!(Cast::parse(&exported).unwrap(), out);
+        assert_eq!(Edl::default().to_cast(&[&cast]), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let edl = Edl {
+            segments: vec![
+                segment(0.5, 1.25),
+                Segment {
+                    source: 1,
+                    ..segment(0.0, 9.0)
+                },
+            ],
+            redact: vec!["hunter2".into()],
+            idle_cap_us: None,
+        };
+        let saved = edl.write();
+        assert_eq!(Edl::parse(&saved), Ok(edl));
+        assert!(String::from_utf8(saved)
+            .unwrap()
+            .contains("[0, 500000, 1250000]"));
+
+        assert_eq!(
+            Edl::parse(b"{\"version\": 2, \"segments\": []}"),
+            Err(EdlError::Invalid("unsupported version"))
+        );
+        assert_eq!(
+            Edl::parse(b"{\"version\": 1, \"segments\": [[0, 1]]}"),
+            Err(EdlError::Invalid("bad segment"))
+        );
+    }
+}
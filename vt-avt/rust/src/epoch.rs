@@ -0,0 +1,263 @@
+//! Snapshots read on another thread while the VT is fed.
+//!
+//! A VT is used from one thread at a time (see `handles`), so a renderer
+//! encoding a snapshot and a feeder applying a large chunk of output wait
+//! for each other, and whichever came second shows up as a long frame.
+//! After `vtOpenReader`, each feed, resize and reset instead publishes the
+//! screen as an immutable `Screen`, and `readerSnapshot` encodes the
+//! latest one on the reader's own thread, never waiting for the feeder or
+//! taking a lock. Nothing is published during a synchronized update (mode
+//! 2026), so a reader keeps the last complete screen, as `poll_diff` does.
+//!
+//! Screens replaced while a reader may still be encoding one are freed
+//! later, by epoch: publishing advances a global epoch and retires the old
+//! screen under the epoch it was current in, and a reader pins the epoch
+//! it started in for the length of its read. A retired screen is freed
+//! once every pinned epoch is newer, since a reader that started after it
+//! was replaced can only have loaded its successor. Reads are a store and
+//! two loads; only the publisher ever touches the retired list.
+//!
+//! Publishing costs a `screen()` per feed, so a VT stops once its last
+//! reader is freed. Readers are kept across resets.
+
+use crate::snapshot::Screen;
+use crate::{handles, VtHandle};
+use handles::Kind;
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering::SeqCst};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Most readers open on one VT
+pub const MAX_READERS: usize = 8;
+
+/// A reader slot no reader has
+const FREE: u64 = u64::MAX;
+/// A reader slot whose reader isn't reading
+const IDLE: u64 = u64::MAX - 1;
+
+struct Shared<T> {
+    current: AtomicPtr<T>,
+    epoch: AtomicU64,
+    /// The epoch each reader's read started in, `IDLE` or `FREE`
+    pins: [AtomicU64; MAX_READERS],
+    /// Replaced values and the epoch they were current in, oldest first
+    retired: Mutex<Vec<(u64, Box<T>)>>,
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // No reader or publisher is left to see it
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
+
+/// The writing side: owned by the VT, replacing the value readers see.
+pub struct Publisher<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// One reader of a `Publisher`'s values, on any thread.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    slot: usize,
+}
+
+impl<T: Send + Sync> Publisher<T> {
+    pub fn new(value: T) -> Self {
+        Publisher {
+            shared: Arc::new(Shared {
+                current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+                epoch: AtomicU64::new(0),
+                pins: std::array::from_fn(|_| AtomicU64::new(FREE)),
+                retired: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// A new reader, `None` if `MAX_READERS` are open.
+    pub fn reader(&self) -> Option<Reader<T>> {
+        let slot = self
+            .shared
+            .pins
+            .iter()
+            .position(|pin| pin.compare_exchange(FREE, IDLE, SeqCst, SeqCst).is_ok())?;
+        Some(Reader {
+            shared: Arc::clone(&self.shared),
+            slot,
+        })
+    }
+
+    /// Whether any reader is still open.
+    pub fn has_readers(&self) -> bool {
+        Arc::strong_count(&self.shared) > 1
+    }
+
+    /// Make `value` what readers see from now on, and free the replaced
+    /// values no reader can still be reading.
+    pub fn publish(&mut self, value: T) {
+        let shared = &self.shared;
+        let old = shared.current.swap(Box::into_raw(Box::new(value)), SeqCst);
+        let epoch = shared.epoch.fetch_add(1, SeqCst);
+        let mut retired = shared
+            .retired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        retired.push((epoch, unsafe { Box::from_raw(old) }));
+
+        let oldest_pin = shared
+            .pins
+            .iter()
+            .map(|pin| pin.load(SeqCst))
+            .filter(|&pin| pin < IDLE)
+            .min();
+        retired.retain(|&(epoch, _)| oldest_pin.is_some_and(|pin| pin <= epoch));
+    }
+
+    /// Values replaced but not yet freed.
+    pub fn retired(&self) -> usize {
+        self.shared
+            .retired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
+
+impl<T: Send + Sync> Reader<T> {
+    /// `f` of the value published last, which stays alive until it returns.
+    pub fn read<R>(&mut self, f: impl FnOnce(&T) -> R) -> R {
+        let shared = &self.shared;
+        let pin = &shared.pins[self.slot];
+        pin.store(shared.epoch.load(SeqCst), SeqCst);
+        // Loaded after pinning, so it's one the publisher keeps
+        let value = unsafe { &*shared.current.load(SeqCst) };
+        let result = f(value);
+        pin.store(IDLE, SeqCst);
+        result
+    }
+}
+
+impl<T> Drop for Reader<T> {
+    fn drop(&mut self) {
+        self.shared.pins[self.slot].store(FREE, SeqCst);
+    }
+}
+
+registered!(Reader<Screen>, Kind::Reader);
+
+// JNI functions
+
+/// Open a reader of the VT's screen for another thread, see the module
+/// docs; valid after the VT is freed, until `readerFree`. 0 for an invalid
+/// handle or when `MAX_READERS` are open.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtOpenReader(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        match vt.open_reader() {
+            Some(reader) => handles::add(reader),
+            None => 0,
+        }
+    })
+}
+
+/// The screen last published, in the snapshot format (see `snapshot`);
+/// empty for an invalid reader. Any thread, one call at a time per reader.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_readerSnapshot<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    reader: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(reader) = handles::object::<Reader<Screen>>(&mut env, reader) else {
+            return JByteArray::default();
+        };
+
+        let bytes = reader.read(Screen::encode);
+        env.byte_array_from_slice(&bytes).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_readerFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    reader: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Reader<Screen>>(&mut env, reader));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+    use std::thread;
+
+    #[test]
+    fn retired_values_outlive_the_reads_that_started_before() {
+        let mut publisher = Publisher::new(0);
+        let mut reader = publisher.reader().unwrap();
+        let mut other = publisher.reader().unwrap();
+        // Another read starts and the value is replaced under it
+        reader.read(|&value| {
+            assert_eq!(value, 0);
+            publisher.publish(1);
+            publisher.publish(2);
+            assert_eq!(publisher.retired(), 2);
+            assert_eq!(other.read(|&value| value), 2);
+        });
+        assert_eq!(reader.read(|&value| value), 2);
+        publisher.publish(3);
+        assert_eq!(publisher.retired(), 0);
+
+        drop((reader, other));
+        assert!(!publisher.has_readers());
+        let readers: Vec<_> = (0..MAX_READERS).map_while(|_| publisher.reader()).collect();
+        assert_eq!(readers.len(), MAX_READERS);
+        assert!(publisher.reader().is_none());
+    }
+
+    #[test]
+    fn readers_follow_the_vt_from_another_thread() {
+        let mut vt = AvtState::with_backend(fake(12, 2));
+        let mut reader = vt.open_reader().unwrap();
+        let text = |reader: &mut Reader<Screen>| {
+            reader.read(|screen| screen.lines[0].runs[0].text.trim_end().to_string())
+        };
+        let reading = thread::spawn(move || {
+            let mut seen = 0;
+            while seen < 8 {
+                let now = text(&mut reader).len();
+                assert!(now >= seen, "went back from {} to {}", seen, now);
+                seen = now;
+            }
+            reader
+        });
+        for _ in 0..8 {
+            vt.feed(b"x");
+        }
+        let mut reader = reading.join().unwrap();
+
+        // Mid synchronized update the last whole screen stays
+        vt.feed(b"\x1b[?2026hy");
+        assert_eq!(text(&mut reader), "xxxxxxxx");
+
+        // Freeing the last reader stops publishing
+        drop(reader);
+        vt.feed(b"\x1b[?2026l");
+        assert!(vt.published.is_none());
+    }
+}
@@ -0,0 +1,630 @@
+//! Terminal events for the app, in one versioned wire format.
+//!
+//! Besides the screen, fed output produces things the app acts on rather
+//! than draws: a bell to flash, a title for the toolbar, a clipboard write
+//! to confirm. They queue up on the VT as they happen (see
+//! `AvtState::take_events`) and `vtTakeEvents` hands them over in a batch:
+//!
+//! ```text
+//! batch   := version:u8 event_count event*
+//! event   := tag:u8 payload_len payload
+//! payload := (empty)                        tag 1, bell
+//!          | text                           tag 2, title
+//!          | text                           tag 3, marker
+//!          | cols rows                      tag 4, resize
+//!          | protocol:u8 data               tag 5, image
+//!          | selection_len selection data   tag 6, clipboard
+//!          | title_len title body           tag 7, notification
+//!          | watch_tag row col text         tag 8, watch hit
+//!          | state:u8 quiet_ms              tag 9, activity
+//!          | text                           tag 10, input
+//!          | option:u8                      tag 11, option changed
+//!          | image_id cols rows             tag 12, image added
+//!          | image_id                       tag 13, image removed
+//! ```
+//!
+//! Varints are as in the snapshot format and text is UTF-8, the last field
+//! of a payload running to its end. Markers, resizes and input come from
+//! the recording during playback, watch hits from matching screen text (see
+//! `watch`), activity changes from output and input timing (see
+//! `activity`), option changes from the app setting a VT's theme,
+//! bold-as-bright or cursor policy to something new, images added and
+//! removed from placing and dropping decoded images (see `images`), the
+//! rest from escape sequences in the output.
+//!
+//! The payload length is what leaves room to grow: decoders skip tags they
+//! don't know and ignore payload bytes past the fields they do, so new
+//! event kinds get new tags and payloads gain fields at the end without a
+//! version change. `VERSION` changes only when existing fields do, and
+//! `decode`, the contract for client decoders, rejects versions it doesn't
+//! know. Otherwise it follows `snapshot::decode`: truncation and oversized
+//! varints are errors and invalid UTF-8 is replaced with U+FFFD.
+//!
+//! `vtPollEvents` takes the same events with the time each was queued, for
+//! apps that list markers or show when a bell rang:
+//!
+//! ```text
+//! timed_batch := 2 event_count (tag:u8 time_us:u64le payload_len payload)*
+//! ```
+//!
+//! `time_us` is the playback time of the recording event that queued it
+//! when a `Player` applies the recording, otherwise microseconds since the
+//! VT was created. A timed batch has its own version byte, `VERSION_TIMED`,
+//! read by `decode_timed`.
+
+use crate::activity::State;
+use crate::scan::Action;
+use crate::snapshot::{DecodeError, Reader};
+use crate::{handles, write_varint, write_varint_u64, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::JNIEnv;
+
+/// Wire format version, the first byte of every batch
+pub const VERSION: u8 = 1;
+
+/// Version byte of the batches `vtPollEvents` returns
+pub const VERSION_TIMED: u8 = 2;
+
+/// Events kept before the oldest are dropped, for apps that never take them
+pub const MAX_QUEUED: usize = 256;
+
+/// Event tags, see the module docs
+pub const TAG_BELL: u8 = 1;
+pub const TAG_TITLE: u8 = 2;
+pub const TAG_MARKER: u8 = 3;
+pub const TAG_RESIZE: u8 = 4;
+pub const TAG_IMAGE: u8 = 5;
+pub const TAG_CLIPBOARD: u8 = 6;
+pub const TAG_NOTIFICATION: u8 = 7;
+pub const TAG_WATCH: u8 = 8;
+pub const TAG_ACTIVITY: u8 = 9;
+pub const TAG_INPUT: u8 = 10;
+pub const TAG_OPTION: u8 = 11;
+pub const TAG_IMAGE_ADDED: u8 = 12;
+pub const TAG_IMAGE_REMOVED: u8 = 13;
+
+/// Image protocols, as `VtEvent::Image::protocol`
+pub const IMAGE_SIXEL: u8 = 1;
+/// iTerm2 inline images (OSC 1337 File=)
+pub const IMAGE_ITERM: u8 = 2;
+
+/// Options, as `VtEvent::OptionChanged`: the theme (`vtSetTheme`)
+pub const OPTION_THEME: u8 = 1;
+/// Bold-as-bright (`vtSetBoldAsBright`)
+pub const OPTION_BOLD_AS_BRIGHT: u8 = 2;
+/// The cursor policy (`vtSetCursorPolicy`)
+pub const OPTION_CURSOR_POLICY: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VtEvent {
+    Bell,
+    /// Window title (OSC 0 or 2)
+    Title(String),
+    /// Marker event in the recording
+    Marker(String),
+    /// Resize event in the recording
+    Resize {
+        cols: usize,
+        rows: usize,
+    },
+    /// An image sequence: the DCS payload for sixel, the OSC payload from
+    /// `File=` for iTerm2. Snapshots place it too (see `images`)
+    Image {
+        protocol: u8,
+        data: Vec<u8>,
+    },
+    /// Clipboard write (OSC 52), `data` still base64 encoded as sent.
+    /// Clipboard reads are never queued.
+    Clipboard {
+        selection: String,
+        data: String,
+    },
+    /// Desktop notification (OSC 9, or OSC 777 notify)
+    Notification {
+        title: String,
+        body: String,
+    },
+    /// Text matching the watch `tag`'s pattern appeared at `row`, `col`
+    Watch {
+        tag: u32,
+        row: usize,
+        col: usize,
+        text: String,
+    },
+    /// An interactive session went idle, became active again or was
+    /// disconnected, `quiet_ms` after its last output or input
+    Activity {
+        state: State,
+        quiet_ms: u64,
+    },
+    /// Input event in the recording, when the VT queues them (see
+    /// `AvtState::set_input_events`)
+    Input(String),
+    /// An option of the VT, one of the `OPTION_*`, was set to a new value;
+    /// the next diff shows it applied
+    OptionChanged(u8),
+    /// Image `id` was decoded and given `cols` × `rows` cells; `vtImage`
+    /// has its pixels and the next diff its placement
+    ImageAdded {
+        id: u32,
+        cols: usize,
+        rows: usize,
+    },
+    /// No cell shows image `id` any more, so its pixels can go
+    ImageRemoved(u32),
+}
+
+impl VtEvent {
+    /// The event `action` in fed output stands for, if any.
+    pub(crate) fn from_action(action: &Action) -> Option<VtEvent> {
+        match action {
+            Action::Control(0x07) => Some(VtEvent::Bell),
+            Action::Osc(osc) => from_osc(osc),
+            Action::Dcs(payload) if is_sixel(payload) => Some(VtEvent::Image {
+                protocol: IMAGE_SIXEL,
+                data: payload.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            VtEvent::Bell => TAG_BELL,
+            VtEvent::Title(_) => TAG_TITLE,
+            VtEvent::Marker(_) => TAG_MARKER,
+            VtEvent::Resize { .. } => TAG_RESIZE,
+            VtEvent::Image { .. } => TAG_IMAGE,
+            VtEvent::Clipboard { .. } => TAG_CLIPBOARD,
+            VtEvent::Notification { .. } => TAG_NOTIFICATION,
+            VtEvent::Watch { .. } => TAG_WATCH,
+            VtEvent::Activity { .. } => TAG_ACTIVITY,
+            VtEvent::Input(_) => TAG_INPUT,
+            VtEvent::OptionChanged(_) => TAG_OPTION,
+            VtEvent::ImageAdded { .. } => TAG_IMAGE_ADDED,
+            VtEvent::ImageRemoved(_) => TAG_IMAGE_REMOVED,
+        }
+    }
+
+    fn encode_payload(&self, buf: &mut Vec<u8>) {
+        match self {
+            VtEvent::Bell => {}
+            VtEvent::Title(text) | VtEvent::Marker(text) | VtEvent::Input(text) => {
+                buf.extend_from_slice(text.as_bytes())
+            }
+            VtEvent::Resize { cols, rows } => {
+                write_varint(buf, *cols);
+                write_varint(buf, *rows);
+            }
+            VtEvent::Image { protocol, data } => {
+                buf.push(*protocol);
+                buf.extend_from_slice(data);
+            }
+            VtEvent::Clipboard {
+                selection: first,
+                data: rest,
+            }
+            | VtEvent::Notification {
+                title: first,
+                body: rest,
+            } => {
+                write_varint(buf, first.len());
+                buf.extend_from_slice(first.as_bytes());
+                buf.extend_from_slice(rest.as_bytes());
+            }
+            VtEvent::Watch {
+                tag,
+                row,
+                col,
+                text,
+            } => {
+                write_varint(buf, *tag as usize);
+                write_varint(buf, *row);
+                write_varint(buf, *col);
+                buf.extend_from_slice(text.as_bytes());
+            }
+            VtEvent::Activity { state, quiet_ms } => {
+                buf.push(*state as u8);
+                write_varint_u64(buf, *quiet_ms);
+            }
+            VtEvent::OptionChanged(option) => buf.push(*option),
+            VtEvent::ImageAdded { id, cols, rows } => {
+                write_varint(buf, *id as usize);
+                write_varint(buf, *cols);
+                write_varint(buf, *rows);
+            }
+            VtEvent::ImageRemoved(id) => write_varint(buf, *id as usize),
+        }
+    }
+
+    /// Decode the payload of a `tag` event, `None` for unknown tags.
+    fn decode_payload(tag: u8, payload: &[u8]) -> Result<Option<VtEvent>, DecodeError> {
+        let mut r = Reader::new(payload);
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let event = match tag {
+            TAG_BELL => VtEvent::Bell,
+            TAG_TITLE => VtEvent::Title(text(payload)),
+            TAG_MARKER => VtEvent::Marker(text(payload)),
+            TAG_INPUT => VtEvent::Input(text(payload)),
+            TAG_RESIZE => VtEvent::Resize {
+                cols: r.varint()?,
+                rows: r.varint()?,
+            },
+            TAG_IMAGE => VtEvent::Image {
+                protocol: r.byte()?,
+                data: payload[1..].to_vec(),
+            },
+            TAG_CLIPBOARD | TAG_NOTIFICATION => {
+                let len = r.varint()?;
+                let first = text(r.take(len)?);
+                let rest = text(r.take(r.remaining())?);
+                match tag {
+                    TAG_CLIPBOARD => VtEvent::Clipboard {
+                        selection: first,
+                        data: rest,
+                    },
+                    _ => VtEvent::Notification {
+                        title: first,
+                        body: rest,
+                    },
+                }
+            }
+            TAG_WATCH => VtEvent::Watch {
+                tag: r.varint()? as u32,
+                row: r.varint()?,
+                col: r.varint()?,
+                text: text(r.take(r.remaining())?),
+            },
+            // A state from a newer version is skipped like a new tag
+            TAG_ACTIVITY => match State::from_code(r.byte()?) {
+                Some(state) => VtEvent::Activity {
+                    state,
+                    quiet_ms: r.varint_u64()?,
+                },
+                None => return Ok(None),
+            },
+            TAG_OPTION => VtEvent::OptionChanged(r.byte()?),
+            TAG_IMAGE_ADDED => VtEvent::ImageAdded {
+                id: r.varint()? as u32,
+                cols: r.varint()?,
+                rows: r.varint()?,
+            },
+            TAG_IMAGE_REMOVED => VtEvent::ImageRemoved(r.varint()? as u32),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+fn from_osc(osc: &[u8]) -> Option<VtEvent> {
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    let (command, rest) = split(osc)?;
+    match command {
+        b"0" | b"2" => Some(VtEvent::Title(text(rest))),
+        b"52" => {
+            let (selection, data) = split(rest)?;
+            (data != b"?").then(|| VtEvent::Clipboard {
+                selection: text(selection),
+                data: text(data),
+            })
+        }
+        // ConEmu's OSC 9 commands (`9;4;...` progress and the like)
+        // aren't notifications
+        b"9" if split(rest).is_some_and(|(n, _)| is_number(n)) => None,
+        b"9" => Some(VtEvent::Notification {
+            title: String::new(),
+            body: text(rest),
+        }),
+        b"777" => {
+            let notify = rest.strip_prefix(b"notify;")?;
+            let (title, body) = split(notify).unwrap_or((notify, b""));
+            Some(VtEvent::Notification {
+                title: text(title),
+                body: text(body),
+            })
+        }
+        b"1337" => rest.starts_with(b"File=").then(|| VtEvent::Image {
+            protocol: IMAGE_ITERM,
+            data: rest.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+/// Split at the first `;`.
+fn split(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = bytes.iter().position(|&b| b == b';')?;
+    Some((&bytes[..i], &bytes[i + 1..]))
+}
+
+fn is_number(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(u8::is_ascii_digit)
+}
+
+/// Sixel data is a DCS with numeric parameters and final `q`.
+pub(crate) fn is_sixel(payload: &[u8]) -> bool {
+    let params = payload
+        .iter()
+        .take_while(|&&b| b.is_ascii_digit() || b == b';')
+        .count();
+    payload.get(params) == Some(&b'q')
+}
+
+/// Encode `events` as a batch.
+pub fn encode(events: &[VtEvent]) -> Vec<u8> {
+    encode_with(VERSION, events.iter().map(|event| (None, event)))
+}
+
+/// Encode `events` and their times as a timed batch.
+pub fn encode_timed(events: &[(u64, VtEvent)]) -> Vec<u8> {
+    encode_with(
+        VERSION_TIMED,
+        events
+            .iter()
+            .map(|(time_us, event)| (Some(*time_us), event)),
+    )
+}
+
+fn encode_with<'a>(
+    version: u8,
+    events: impl ExactSizeIterator<Item = (Option<u64>, &'a VtEvent)>,
+) -> Vec<u8> {
+    let mut buf = vec![version];
+    write_varint(&mut buf, events.len());
+    let mut payload = Vec::new();
+    for (time_us, event) in events {
+        payload.clear();
+        event.encode_payload(&mut payload);
+        buf.push(event.tag());
+        if let Some(time_us) = time_us {
+            buf.extend_from_slice(&time_us.to_le_bytes());
+        }
+        write_varint(&mut buf, payload.len());
+        buf.extend_from_slice(&payload);
+    }
+    buf
+}
+
+/// Decode a batch produced by `vtTakeEvents`, skipping unknown tags.
+pub fn decode(bytes: &[u8]) -> Result<Vec<VtEvent>, DecodeError> {
+    let events = decode_with(bytes, VERSION)?;
+    Ok(events.into_iter().map(|(_, event)| event).collect())
+}
+
+/// Decode a timed batch produced by `vtPollEvents`, as `decode` does.
+pub fn decode_timed(bytes: &[u8]) -> Result<Vec<(u64, VtEvent)>, DecodeError> {
+    decode_with(bytes, VERSION_TIMED)
+}
+
+/// Events of a batch at `expected` version; times are 0 in untimed ones.
+fn decode_with(bytes: &[u8], expected: u8) -> Result<Vec<(u64, VtEvent)>, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let version = r.byte()?;
+    if version != expected {
+        return Err(DecodeError::UnsupportedVersion { version });
+    }
+    let count = r.varint()?;
+
+    // Every event takes at least two bytes, which bounds the allocation
+    let mut events = Vec::with_capacity(count.min(bytes.len() / 2));
+    for _ in 0..count {
+        let tag = r.byte()?;
+        let time_us = match version {
+            VERSION_TIMED => u64::from_le_bytes(r.take(8)?.try_into().unwrap()),
+            _ => 0,
+        };
+        let len = r.varint()?;
+        let payload = r.take(len)?;
+        events.extend(VtEvent::decode_payload(tag, payload)?.map(|event| (time_us, event)));
+    }
+    Ok(events)
+}
+
+// JNI functions
+
+/// Events queued since the last call, encoded as a batch. Empty for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtTakeEvents<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&encode(&vt.take_events()))
+            .unwrap_or_default()
+    })
+}
+
+/// `vtTakeEvents` as a timed batch, each event with the time it was
+/// queued. Empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollEvents<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&encode_timed(&vt.take_timed_events()))
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    fn every_kind() -> Vec<VtEvent> {
+        vec![
+            VtEvent::Bell,
+            VtEvent::Title("vim — ~/src".to_string()),
+            VtEvent::Marker(String::new()),
+            VtEvent::Resize {
+                cols: 300,
+                rows: 24,
+            },
+            VtEvent::Image {
+                protocol: IMAGE_SIXEL,
+                data: b"0;1q#0~-".to_vec(),
+            },
+            VtEvent::Clipboard {
+                selection: "c".to_string(),
+                data: "aGk=".to_string(),
+            },
+            VtEvent::Notification {
+                title: "build".to_string(),
+                body: "done; 0 errors".to_string(),
+            },
+            VtEvent::Watch {
+                tag: 300,
+                row: 2,
+                col: 0,
+                text: "FAILED".to_string(),
+            },
+            VtEvent::Activity {
+                state: State::Idle,
+                quiet_ms: 300_000,
+            },
+            VtEvent::Input("\u{1b}[A\r".to_string()),
+            VtEvent::OptionChanged(OPTION_BOLD_AS_BRIGHT),
+            VtEvent::ImageAdded {
+                id: 300,
+                cols: 3,
+                rows: 1,
+            },
+            VtEvent::ImageRemoved(300),
+        ]
+    }
+
+    #[test]
+    fn every_kind_round_trips_and_truncation_is_an_error() {
+        let events = every_kind();
+        let bytes = encode(&events);
+        assert_eq!(bytes[0], VERSION);
+        assert_eq!(decode(&bytes), Ok(events));
+        assert_eq!(decode(&encode(&[])), Ok(vec![]));
+
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoded {} bytes", len);
+        }
+        let mut newer = bytes.clone();
+        newer[0] = VERSION + 1;
+        assert_eq!(
+            decode(&newer),
+            Err(DecodeError::UnsupportedVersion {
+                version: VERSION + 1
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_tags_and_extra_payload_bytes_are_skipped() {
+        // An unknown tag 99, then a resize with a field from the future
+        let bytes = [
+            VERSION, 3, 99, 2, 0xff, 0xff, TAG_RESIZE, 3, 80, 24, 7, TAG_BELL, 0,
+        ];
+        assert_eq!(
+            decode(&bytes),
+            Ok(vec![VtEvent::Resize { cols: 80, rows: 24 }, VtEvent::Bell])
+        );
+
+        let title = [VERSION, 1, TAG_TITLE, 2, 0xc3, 0x28];
+        assert_eq!(
+            decode(&title),
+            Ok(vec![VtEvent::Title("\u{fffd}(".to_string())])
+        );
+    }
+
+    #[test]
+    fn output_sequences_queue_events() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.feed(b"\x07\x1b]2;make\x07\x1b]52;c;?\x07\x1b]52;p;aGk=\x1b\\");
+        vt.feed(b"\x1b]9;4;1;50\x07\x1b]9;done\x07\x1b]777;notify;ci;green\x07");
+        vt.feed(b"\x1bP0;1q#0~\x1b\\\x1b]1337;File=inline=1:AAAA\x07\x1bP+q544e\x1b\\");
+        assert_eq!(
+            vt.take_events(),
+            [
+                VtEvent::Bell,
+                VtEvent::Title("make".to_string()),
+                VtEvent::Clipboard {
+                    selection: "p".to_string(),
+                    data: "aGk=".to_string(),
+                },
+                VtEvent::Notification {
+                    title: String::new(),
+                    body: "done".to_string(),
+                },
+                VtEvent::Notification {
+                    title: "ci".to_string(),
+                    body: "green".to_string(),
+                },
+                VtEvent::Image {
+                    protocol: IMAGE_SIXEL,
+                    data: b"0;1q#0~".to_vec(),
+                },
+                #[cfg(feature = "images")]
+                VtEvent::ImageAdded {
+                    id: 1,
+                    cols: 1,
+                    rows: 1,
+                },
+                // Not shown: no size, and not a PNG to take one from
+                VtEvent::Image {
+                    protocol: IMAGE_ITERM,
+                    data: b"File=inline=1:AAAA".to_vec(),
+                },
+            ]
+        );
+        assert_eq!(vt.take_events(), []);
+
+        for _ in 0..MAX_QUEUED + 1 {
+            vt.feed(b"\x1b]0;t\x07");
+        }
+        vt.feed(b"\x07");
+        let events = vt.take_events();
+        assert_eq!(events.len(), MAX_QUEUED);
+        assert_eq!(events.last(), Some(&VtEvent::Bell));
+    }
+
+    #[test]
+    fn timed_batches_carry_when_events_were_queued() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.set_event_time(Some(1_500_000));
+        vt.feed(b"\x07");
+        vt.push_event(VtEvent::Marker("intro".to_string()));
+        vt.set_event_time(None);
+        vt.feed(b"\x1b]2;make\x07");
+
+        let events = vt.take_timed_events();
+        assert_eq!(
+            events[..2],
+            [
+                (1_500_000, VtEvent::Bell),
+                (1_500_000, VtEvent::Marker("intro".to_string()))
+            ]
+        );
+        assert_eq!(events[2].1, VtEvent::Title("make".to_string()));
+        assert!(events[2].0 < 60_000_000);
+
+        let bytes = encode_timed(&events);
+        assert_eq!(bytes[..4], [VERSION_TIMED, 3, TAG_BELL, 0x60]);
+        assert_eq!(decode_timed(&bytes), Ok(events));
+        assert_eq!(
+            decode(&bytes),
+            Err(DecodeError::UnsupportedVersion {
+                version: VERSION_TIMED
+            })
+        );
+        assert!(decode_timed(&bytes[..10]).is_err());
+    }
+}
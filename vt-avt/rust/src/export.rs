@@ -0,0 +1,501 @@
+//! Cast export: rewriting recordings before they leave the device.
+
+use crate::cast::{micros_arg, Cast, CastError, EventKind};
+use crate::json::Value;
+use crate::scan::{Action, Scanner};
+use crate::{guard, to_hex, write_varint, write_varint_u64};
+use jni::objects::{JByteArray, JClass, JLongArray};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// What to do with `i` (input) events on export.
+///
+/// Input events can contain passwords typed with echo off, so anything
+/// shared defaults to dropping them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputPrivacy {
+    Keep,
+    #[default]
+    Strip,
+    /// Replace the data with a token, an HMAC keyed with the salt and the
+    /// event's index, so equal keystrokes get different tokens. Content and
+    /// repetition are hidden; the timing and number of input events are
+    /// not.
+    Hash,
+}
+
+impl InputPrivacy {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(InputPrivacy::Keep),
+            1 => Some(InputPrivacy::Strip),
+            2 => Some(InputPrivacy::Hash),
+            _ => None,
+        }
+    }
+}
+
+/// Play `[start_us, end_us)` of the recording `speed` times faster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedRegion {
+    pub start_us: i64,
+    pub end_us: i64,
+    pub speed: f64,
+}
+
+impl SpeedRegion {
+    /// `[start_us, end_us, play_us, ...]`, each region played in `play_us`;
+    /// trailing values that don't make a full triple are ignored, and so
+    /// are non-positive play times.
+    pub fn from_triples(values: &[i64]) -> Vec<SpeedRegion> {
+        values
+            .chunks_exact(3)
+            .map(|r| SpeedRegion {
+                start_us: r[0],
+                end_us: r[1],
+                speed: match r[2] {
+                    play_us if play_us > 0 => (r[1] - r[0]) as f64 / play_us as f64,
+                    _ => 0.0,
+                },
+            })
+            .collect()
+    }
+}
+
+/// Export options. `Default` is what share exports use.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub input_privacy: InputPrivacy,
+    /// Key, with each event's index, of the HMAC of hashed input, so tokens
+    /// can't be matched across exports
+    pub salt: u64,
+    /// Regions played faster (or slower) in the export. Empty regions and
+    /// non-positive speeds are ignored; where regions overlap the earlier
+    /// one wins.
+    pub speed_regions: Vec<SpeedRegion>,
+}
+
+pub fn scrub_input(cast: &mut Cast, privacy: InputPrivacy, salt: u64) {
+    match privacy {
+        InputPrivacy::Keep => {}
+        InputPrivacy::Strip => cast
+            .events
+            .retain(|e| !matches!(e.kind, EventKind::Input(_))),
+        InputPrivacy::Hash => {
+            for (index, event) in cast.events.iter_mut().enumerate() {
+                if let EventKind::Input(data) = &mut event.kind {
+                    *data = hash_input(data, salt, index);
+                }
+            }
+        }
+    }
+}
+
+/// Retime events so each speed region takes `1 / speed` of its recorded
+/// duration; everything after a region moves up accordingly.
+pub fn apply_speed_regions(cast: &mut Cast, regions: &[SpeedRegion]) {
+    let mut regions: Vec<SpeedRegion> = regions
+        .iter()
+        .copied()
+        .filter(|r| r.end_us > r.start_us && r.speed > 0.0)
+        .collect();
+    regions.sort_by_key(|r| r.start_us);
+    let mut kept: Vec<SpeedRegion> = Vec::with_capacity(regions.len());
+    for mut region in regions {
+        if let Some(last) = kept.last() {
+            region.start_us = region.start_us.max(last.end_us);
+        }
+        if region.end_us > region.start_us {
+            kept.push(region);
+        }
+    }
+    if kept.is_empty() {
+        return;
+    }
+
+    for event in &mut cast.events {
+        let t = event.time_us;
+        let saved: f64 = kept
+            .iter()
+            .take_while(|r| r.start_us < t)
+            .map(|r| (t.min(r.end_us) - r.start_us) as f64 * (1.0 - 1.0 / r.speed))
+            .sum();
+        event.time_us = t - saved.round() as i64;
+    }
+}
+
+/// 64 bits of HMAC-SHA256 of `data` keyed with `salt` and the event `index`.
+fn hash_input(data: &str, salt: u64, index: usize) -> String {
+    let key = [salt.to_le_bytes(), (index as u64).to_le_bytes()].concat();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    to_hex(&mac.finalize().into_bytes()[..8])
+}
+
+/// Output of an export: the rewritten cast plus metadata gathered while
+/// replaying it.
+#[derive(Debug, Clone, Default)]
+pub struct ExportResult {
+    pub cast: Vec<u8>,
+    /// Times of events that rang the bell, so the app can mix an audio
+    /// cue into generated videos
+    pub bell_times_us: Vec<i64>,
+    /// Window title (OSC 0/2) in effect when the export starts, else the
+    /// first one set, else the header's `title`: for proposing filenames
+    pub title: Option<String>,
+    /// First command line run, from shell integration marks (OSC 133 B to
+    /// C), else the header's `command`
+    pub command: Option<String>,
+}
+
+impl ExportResult {
+    /// Binary form handed to Kotlin:
+    /// `[varint bellCount][varint bellDeltaMicros...][varint titleLen][title]
+    /// [varint commandLen][command][cast bytes...]`, each bell time
+    /// delta-encoded against the previous one and missing strings empty.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.cast.len() + 64);
+        write_varint(&mut buf, self.bell_times_us.len());
+        let mut previous = 0;
+        for &time in &self.bell_times_us {
+            write_varint_u64(&mut buf, (time - previous).max(0) as u64);
+            previous = time;
+        }
+        for text in [&self.title, &self.command] {
+            let text = text.as_deref().unwrap_or_default();
+            write_varint(&mut buf, text.len());
+            buf.extend_from_slice(text.as_bytes());
+        }
+        buf.extend_from_slice(&self.cast);
+        buf
+    }
+}
+
+/// Replays a cast's output the way an export would, collecting metadata.
+#[derive(Default)]
+struct ExportReplay {
+    scanner: Scanner,
+    meta: ReplayMeta,
+}
+
+#[derive(Default)]
+struct ReplayMeta {
+    start_us: i64,
+    bell_times_us: Vec<i64>,
+    title: Option<String>,
+    command: Option<String>,
+    /// Echoed text since the OSC 133 B mark, until C ends the command line
+    command_line: Option<Vec<u8>>,
+}
+
+impl ExportReplay {
+    fn run(cast: &Cast) -> Self {
+        let mut replay = ExportReplay::default();
+        replay.meta.start_us = cast.events.first().map_or(0, |e| e.time_us);
+        for event in &cast.events {
+            if let EventKind::Output(data) = &event.kind {
+                replay.output(event.time_us, data.as_bytes());
+            }
+        }
+        replay
+    }
+
+    fn output(&mut self, time_us: i64, data: &[u8]) {
+        let meta = &mut self.meta;
+        if meta.command.is_some() {
+            self.scanner
+                .scan(data, |_, action| meta.action(time_us, action));
+            return;
+        }
+
+        // Until the first command is found, go byte by byte to pick out
+        // the printable ones
+        for (i, &b) in data.iter().enumerate() {
+            let ground = self.scanner.is_ground();
+            self.scanner
+                .scan(&data[i..=i], |_, action| meta.action(time_us, action));
+            if let Some(line) = &mut meta.command_line {
+                match b {
+                    // Drop the last character: continuation bytes, then its lead
+                    0x08 => while line.pop().is_some_and(|b| b & 0xc0 == 0x80) {},
+                    0x20.. if ground && b != 0x7f => line.push(b),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl ReplayMeta {
+    fn action(&mut self, time_us: i64, action: Action) {
+        match action {
+            // Several bells in one event would just overlap the same cue
+            Action::Control(0x07) if self.bell_times_us.last() != Some(&time_us) => {
+                self.bell_times_us.push(time_us);
+            }
+            Action::Osc(osc) => self.osc(time_us, osc),
+            _ => {}
+        }
+    }
+
+    fn osc(&mut self, time_us: i64, osc: &[u8]) {
+        let title = osc.strip_prefix(b"0;").or_else(|| osc.strip_prefix(b"2;"));
+        if let Some(title) = title {
+            if time_us <= self.start_us || self.title.is_none() {
+                self.title = Some(String::from_utf8_lossy(title).into_owned());
+            }
+            return;
+        }
+
+        if self.command.is_some() {
+            return;
+        }
+        match osc {
+            b"133;B" => self.command_line = Some(Vec::new()),
+            b"133;C" => {
+                if let Some(line) = self.command_line.take() {
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    self.command = (!line.is_empty()).then_some(line);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse, apply export options, re-serialize and collect export metadata.
+pub fn export(bytes: &[u8], options: &ExportOptions) -> Result<ExportResult, CastError> {
+    let mut cast = Cast::parse(bytes)?;
+    scrub_input(&mut cast, options.input_privacy, options.salt);
+    apply_speed_regions(&mut cast, &options.speed_regions);
+    let meta = ExportReplay::run(&cast).meta;
+    let header_text = |key| {
+        cast.header
+            .fields
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+
+    Ok(ExportResult {
+        title: meta.title.or_else(|| header_text("title")),
+        command: meta.command.or_else(|| header_text("command")),
+        cast: cast.write(),
+        bell_times_us: meta.bell_times_us,
+    })
+}
+
+/// Parse, apply export options and re-serialize a cast file.
+pub fn rewrite(bytes: &[u8], options: &ExportOptions) -> Result<Vec<u8>, CastError> {
+    export(bytes, options).map(|result| result.cast)
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castRewrite<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_bytes: JByteArray<'a>,
+    input_privacy: jint,
+    salt: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let bytes = match env.convert_byte_array(cast_bytes) {
+            Ok(b) => b,
+            Err(_) => return JByteArray::default(),
+        };
+
+        let options = ExportOptions {
+            input_privacy: InputPrivacy::from_code(input_privacy).unwrap_or_default(),
+            salt: salt as u64,
+            ..ExportOptions::default()
+        };
+
+        match rewrite(&bytes, &options) {
+            Ok(out) => env.byte_array_from_slice(&out).unwrap_or_default(),
+            Err(e) => {
+                guard::throw_invalid(&mut env, e);
+                JByteArray::default()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castExport<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_bytes: JByteArray<'a>,
+    input_privacy: jint,
+    salt: jlong,
+    speed_regions: JLongArray<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let bytes = match env.convert_byte_array(cast_bytes) {
+            Ok(b) => b,
+            Err(_) => return JByteArray::default(),
+        };
+        let mut regions = vec![0; env.get_array_length(&speed_regions).unwrap_or(0) as usize];
+        if env
+            .get_long_array_region(&speed_regions, 0, &mut regions)
+            .is_err()
+        {
+            guard::throw_invalid(&mut env, "speed regions can't be read");
+            return JByteArray::default();
+        }
+        if let Some(us) = regions.iter().find(|&&us| micros_arg(us).is_none()) {
+            guard::throw_invalid(&mut env, format!("speed region time {} out of range", us));
+            return JByteArray::default();
+        }
+
+        let options = ExportOptions {
+            input_privacy: InputPrivacy::from_code(input_privacy).unwrap_or_default(),
+            salt: salt as u64,
+            speed_regions: SpeedRegion::from_triples(&regions),
+        };
+
+        match export(&bytes, &options) {
+            Ok(result) => env.byte_array_from_slice(&result.encode()).unwrap_or_default(),
+            Err(e) => {
+                guard::throw_invalid(&mut env, e);
+                JByteArray::default()
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAST: &[u8] = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+        [0.1, \"o\", \"Password: \"]\n\
+        [0.9, \"i\", \"hunter2\\r\"]\n\
+        [1.0, \"o\", \"\\r\\n$ \"]\n";
+
+    #[test]
+    fn share_default_strips_input() {
+        let out = rewrite(CAST, &ExportOptions::default()).unwrap();
+        let cast = Cast::parse(&out).unwrap();
+        assert_eq!(cast.events.len(), 2);
+        assert!(!String::from_utf8(out).unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn hash_keeps_timing_but_not_content() {
+        let options = ExportOptions {
+            input_privacy: InputPrivacy::Hash,
+            salt: 42,
+            ..ExportOptions::default()
+        };
+        let cast = Cast::parse(&rewrite(CAST, &options).unwrap()).unwrap();
+        let input = &cast.events[1];
+        assert_eq!(input.time_us, 900_000);
+        match &input.kind {
+            EventKind::Input(data) => {
+                assert_eq!(data.len(), 16);
+                assert_ne!(data, "hunter2\r");
+                assert_eq!(data, &hash_input("hunter2\r", 42, 1));
+                assert_ne!(data, &hash_input("hunter2\r", 43, 1));
+            }
+            other => panic!("expected input event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hash_tells_equal_keystrokes_apart() {
+        let cast = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [0.1, \"i\", \"y\"]\n\
+            [0.2, \"i\", \"y\"]\n";
+        let options = ExportOptions {
+            input_privacy: InputPrivacy::Hash,
+            ..ExportOptions::default()
+        };
+        let cast = Cast::parse(&rewrite(cast, &options).unwrap()).unwrap();
+        let [EventKind::Input(first), EventKind::Input(second)] =
+            [&cast.events[0].kind, &cast.events[1].kind]
+        else {
+            panic!("expected input events, got {:?}", cast.events);
+        };
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn keep_is_lossless() {
+        let options = ExportOptions {
+            input_privacy: InputPrivacy::Keep,
+            salt: 0,
+            ..ExportOptions::default()
+        };
+        let out = rewrite(CAST, &options).unwrap();
+        assert_eq!(Cast::parse(&out).unwrap(), Cast::parse(CAST).unwrap());
+    }
+
+    #[test]
+    fn collects_bell_times_but_not_osc_terminators() {
+        let cast = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [0.5, \"o\", \"\\u001b]0;title\\u0007\"]\n\
+            [1.5, \"o\", \"\\u0007\\u0007\"]\n\
+            [2.0, \"o\", \"\\u001b]2;split\"]\n\
+            [2.5, \"o\", \"\\u0007done\\u0007\"]\n";
+        let result = export(cast, &ExportOptions::default()).unwrap();
+        assert_eq!(result.bell_times_us, vec![1_500_000, 2_500_000]);
+
+        let encoded = result.encode();
+        assert_eq!(&encoded[..1], &[2]);
+        assert!(encoded.ends_with(&result.cast));
+    }
+
+    #[test]
+    fn speed_regions_retime_export_and_bells() {
+        let cast = b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"o\", \"make\"]\n\
+            [5.0, \"o\", \"\\u0007\"]\n\
+            [9.0, \"o\", \"done\"]\n\
+            [10.0, \"o\", \"$ \"]\n";
+        let options = ExportOptions {
+            // The overlapping and the empty region are dropped
+            speed_regions: SpeedRegion::from_triples(&[
+                1_000_000, 9_000_000, 2_000_000, 2_000_000, 3_000_000, 500_000, 9_000_000,
+                9_000_000, 1,
+            ]),
+            ..ExportOptions::default()
+        };
+        let result = export(cast, &options).unwrap();
+        let times: Vec<i64> = Cast::parse(&result.cast)
+            .unwrap()
+            .events
+            .iter()
+            .map(|e| e.time_us)
+            .collect();
+        assert_eq!(times, [1_000_000, 2_000_000, 3_000_000, 4_000_000]);
+        assert_eq!(result.bell_times_us, [2_000_000]);
+    }
+
+    #[test]
+    fn collects_title_and_first_command_for_filenames() {
+        let cast = b"{\"version\": 2, \"width\": 80, \"height\": 24, \"command\": \"zsh\"}\n\
+            [0.0, \"o\", \"\\u001b]2;old\\u0007\\u001b]2;main.rs\\u0007\"]\n\
+            [0.5, \"o\", \"$ \\u001b]133;B\\u0007\"]\n\
+            [1.0, \"o\", \"vin\\bm \\u001b[1mmain.rs\\u001b[0m\"]\n\
+            [1.5, \"o\", \"\\r\\n\\u001b]133;C\\u0007\\u001b]2;vim\\u0007\"]\n\
+            [2.0, \"o\", \"\\u001b]133;B\\u0007ls\\u001b]133;C\\u0007\"]\n";
+        let result = export(cast, &ExportOptions::default()).unwrap();
+        assert_eq!(result.title.as_deref(), Some("main.rs"));
+        assert_eq!(result.command.as_deref(), Some("vim main.rs"));
+
+        let encoded = result.encode();
+        assert!(encoded.starts_with(b"\x00\x07main.rs\x0bvim main.rs"));
+
+        // Without any marks the header is used
+        let plain = b"{\"version\": 2, \"width\": 80, \"height\": 24, \"command\": \"zsh\"}\n\
+            [0.5, \"o\", \"$ \"]\n";
+        let result = export(plain, &ExportOptions::default()).unwrap();
+        assert_eq!(result.title, None);
+        assert_eq!(result.command.as_deref(), Some("zsh"));
+    }
+}
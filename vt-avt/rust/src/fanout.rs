@@ -0,0 +1,208 @@
+//! Several consumers of one VT's screen, each at its own pace.
+//!
+//! Sharing a session live means the local renderer, a remote viewer and a
+//! recorder all follow the same VT. `poll_diff` can't serve them: its
+//! dirty rows are taken by whoever polls first. Instead each consumer
+//! registered with `vtAddConsumer` gets frames as `sync::Sender` makes
+//! them, deltas against the newest screen that consumer acked, with seq
+//! numbers and acks of its own. A slow viewer then only delays itself,
+//! and one that loses frames catches up with the next.
+//!
+//! A consumer is given a frame only when the screen changed since its
+//! last one, and none during a synchronized update (mode 2026), as with
+//! `poll_diff`. Consumers are kept across `vtReset`; the first frame after
+//! one lists every row that differs from the acked screen, as any other.
+
+use crate::delta::fnv1a;
+use crate::snapshot::Screen;
+use crate::sync::Sender;
+use crate::{handles, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+
+/// Most consumers per VT
+pub const MAX_CONSUMERS: usize = 16;
+
+struct Consumer {
+    id: u32,
+    sender: Sender,
+    /// Hash of the screen last framed, `None` before the first frame
+    last: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct Consumers {
+    consumers: Vec<Consumer>,
+    last_id: u32,
+}
+
+impl Consumers {
+    /// Register a consumer, whose first frame lists every row. Returns its
+    /// id, or `None` with `MAX_CONSUMERS` already registered.
+    pub fn add(&mut self) -> Option<u32> {
+        if self.consumers.len() == MAX_CONSUMERS {
+            return None;
+        }
+        self.last_id += 1;
+        self.consumers.push(Consumer {
+            id: self.last_id,
+            sender: Sender::default(),
+            last: None,
+        });
+        Some(self.last_id)
+    }
+
+    /// False if `id` isn't registered.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.consumers.len();
+        self.consumers.retain(|consumer| consumer.id != id);
+        self.consumers.len() != len
+    }
+
+    /// A frame bringing consumer `id` to `screen`, or `None` if it already
+    /// got this screen or isn't registered.
+    pub fn frame(&mut self, id: u32, screen: &Screen) -> Option<Vec<u8>> {
+        let consumer = self.get(id)?;
+        let hash = fnv1a(&screen.encode());
+        if consumer.last == Some(hash) {
+            return None;
+        }
+        consumer.last = Some(hash);
+        Some(consumer.sender.frame(screen))
+    }
+
+    /// Consumer `id` applied its frame `seq`. False for an unknown
+    /// consumer or an unknown or stale ack.
+    pub fn ack(&mut self, id: u32, seq: u64) -> bool {
+        self.get(id)
+            .is_some_and(|consumer| consumer.sender.ack(seq))
+    }
+
+    fn get(&mut self, id: u32) -> Option<&mut Consumer> {
+        self.consumers.iter_mut().find(|consumer| consumer.id == id)
+    }
+}
+
+// JNI functions
+
+/// Register a consumer of the VT's screen. Returns its id, or 0 for an
+/// invalid handle or with `MAX_CONSUMERS` registered.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtAddConsumer(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jint {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.add_consumer().map_or(0, |id| id as jint)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRemoveConsumer(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    id: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if vt.remove_consumer(id as u32) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Consumer `id`'s next frame, a `delta` against the screen it last
+/// acked; empty if nothing changed, or for an invalid handle or id.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollConsumer<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    id: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        match vt.poll_consumer(id as u32) {
+            Some(frame) => env.byte_array_from_slice(&frame).unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+/// Consumer `id` applied its frame `seq`; false for an invalid handle or
+/// id, or an unknown or stale seq, which are harmless.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtAckConsumer(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    id: jint,
+    seq: jlong,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if seq > 0 && vt.ack_consumer(id as u32, seq as u64) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::fake;
+    use crate::delta;
+    use crate::sync::Receiver;
+    use crate::AvtState;
+
+    #[test]
+    fn consumers_follow_the_screen_independently() {
+        let mut vt = AvtState::with_backend(fake(6, 2));
+        let renderer = vt.add_consumer().unwrap();
+        let viewer = vt.add_consumer().unwrap();
+        let (mut local, mut remote) = (Receiver::default(), Receiver::default());
+
+        let seq = local.apply(&vt.poll_consumer(renderer).unwrap()).unwrap();
+        assert!(vt.ack_consumer(renderer, seq.unwrap()));
+        assert_eq!(vt.poll_consumer(renderer), None);
+
+        // The viewer's first frame is still due, and lists every row
+        vt.feed(b"ab");
+        let first = vt.poll_consumer(viewer).unwrap();
+        assert_eq!(delta::decode(&first).unwrap().lines.len(), 2);
+        let frame = delta::decode(&vt.poll_consumer(renderer).unwrap()).unwrap();
+        assert_eq!((frame.seq, frame.baseline), (2, 1));
+        assert_eq!(frame.lines.len(), 1);
+
+        // The viewer lost its first frame and never acked: the next one
+        // is complete again
+        vt.feed(b"cd");
+        remote.apply(&vt.poll_consumer(viewer).unwrap()).unwrap();
+        assert_eq!(remote.screen(), Some(&vt.screen()));
+        assert!(!vt.ack_consumer(viewer, 7));
+
+        assert!(vt.remove_consumer(viewer));
+        assert!(!vt.remove_consumer(viewer));
+        assert_eq!(vt.poll_consumer(viewer), None);
+        assert!(vt.poll_consumer(renderer).is_some());
+    }
+}
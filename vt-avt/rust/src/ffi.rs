@@ -0,0 +1,278 @@
+//! C ABI for non-JVM clients. Declarations live in
+//! `include/asciicast_vt_avt.h`; keep the two in sync.
+//!
+//! A decoded screen is an opaque pointer owned by the caller and released
+//! with `avt_screen_free`. Text pointers handed out by accessors stay valid
+//! until then.
+
+use crate::snapshot::{self, Color, Screen};
+use std::ptr;
+
+pub const AVT_COLOR_DEFAULT: u8 = 0;
+pub const AVT_COLOR_INDEXED: u8 = 1;
+pub const AVT_COLOR_RGB: u8 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvtColor {
+    /// One of `AVT_COLOR_*`
+    pub tag: u8,
+    /// Palette index for `AVT_COLOR_INDEXED`, red for `AVT_COLOR_RGB`
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AvtCursor {
+    pub col: u32,
+    pub row: u32,
+    pub visible: bool,
+    /// 0 block, 1 underline, 2 bar
+    pub shape: u8,
+    pub blink: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AvtRun {
+    pub col: u32,
+    /// UTF-8, not NUL-terminated
+    pub text: *const u8,
+    pub text_len: usize,
+    /// Columns covered, twice the chars for wide text
+    pub cells: u32,
+    pub fg: AvtColor,
+    pub bg: AvtColor,
+    /// Same bits as the snapshot format (bold 0x01 ... inverse 0x20)
+    pub attrs: u8,
+    /// OSC 8 link id, 0 for none; see `avt_screen_link`
+    pub link: u32,
+}
+
+impl From<Color> for AvtColor {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Default => AvtColor::default(),
+            Color::Indexed(idx) => AvtColor {
+                tag: AVT_COLOR_INDEXED,
+                r: idx,
+                ..AvtColor::default()
+            },
+            Color::Rgb(r, g, b) => AvtColor {
+                tag: AVT_COLOR_RGB,
+                r,
+                g,
+                b,
+            },
+        }
+    }
+}
+
+/// Decode a snapshot. Returns null if `data` is null or malformed.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn avt_snapshot_decode(data: *const u8, len: usize) -> *mut Screen {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    match snapshot::decode(bytes) {
+        Ok(screen) => Box::into_raw(Box::new(screen)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `screen` must be null or a pointer from `avt_snapshot_decode` that
+/// hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_free(screen: *mut Screen) {
+    if !screen.is_null() {
+        drop(Box::from_raw(screen));
+    }
+}
+
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_cols(screen: *const Screen) -> u32 {
+    (*screen).cols as u32
+}
+
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_rows(screen: *const Screen) -> u32 {
+    (*screen).rows as u32
+}
+
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_cursor(screen: *const Screen) -> AvtCursor {
+    let cursor = (*screen).cursor;
+    AvtCursor {
+        col: cursor.col as u32,
+        row: cursor.row as u32,
+        visible: cursor.visible,
+        shape: cursor.shape as u8,
+        blink: cursor.blink,
+    }
+}
+
+/// Whether the alternate screen was shown.
+///
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_alt_screen(screen: *const Screen) -> bool {
+    (*screen).alt_screen
+}
+
+/// Line attribute of `row` (0 single, 1 double width, 2 double height
+/// top, 3 double height bottom); 0 if `row` is out of range.
+///
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_line_attr(screen: *const Screen, row: u32) -> u8 {
+    let screen = &*screen;
+    screen.lines.get(row as usize).map_or(0, |line| line.attr as u8)
+}
+
+/// Number of runs on `row`; 0 if `row` is out of range.
+///
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_run_count(screen: *const Screen, row: u32) -> u32 {
+    let screen = &*screen;
+    screen.lines.get(row as usize).map_or(0, |line| line.runs.len() as u32)
+}
+
+/// Fill `out` with run `index` of `row`. Returns false if either is out
+/// of range.
+///
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode` and `out`
+/// must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_run(
+    screen: *const Screen,
+    row: u32,
+    index: u32,
+    out: *mut AvtRun,
+) -> bool {
+    let screen = &*screen;
+    let Some(run) = screen
+        .lines
+        .get(row as usize)
+        .and_then(|line| line.runs.get(index as usize))
+    else {
+        return false;
+    };
+
+    *out = AvtRun {
+        col: run.col as u32,
+        text: run.text.as_ptr(),
+        text_len: run.text.len(),
+        cells: run.cells as u32,
+        fg: run.style.fg.into(),
+        bg: run.style.bg.into(),
+        attrs: run.style.attrs,
+        link: run.link,
+    };
+    true
+}
+
+/// URI of link `link` (see `AvtRun`), setting `len` to its length in
+/// bytes. Returns null, leaving `len` alone, for an id the snapshot
+/// doesn't list.
+///
+/// # Safety
+/// `screen` must be a live pointer from `avt_snapshot_decode` and `len`
+/// valid for writes. The URI lives as long as `screen`.
+#[no_mangle]
+pub unsafe extern "C" fn avt_screen_link(
+    screen: *const Screen,
+    link: u32,
+    len: *mut usize,
+) -> *const u8 {
+    let screen = &*screen;
+    match screen.links.iter().find(|(id, _)| *id == link) {
+        Some((_, uri)) => {
+            *len = uri.len();
+            uri.as_ptr()
+        }
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineattr::LineAttr;
+    use crate::snapshot::{Cursor, Line, Run, Style};
+
+    #[test]
+    fn decode_and_read_back() {
+        let screen = Screen {
+            cols: 4,
+            rows: 1,
+            cursor: Cursor {
+                col: 2,
+                row: 0,
+                ..Cursor::default()
+            },
+            lines: vec![Line {
+                attr: LineAttr::DoubleWidth,
+                runs: vec![Run {
+                    col: 1,
+                    text: "ok".to_string(),
+                    cells: 2,
+                    style: Style {
+                        fg: Color::Rgb(1, 2, 3),
+                        bg: Color::Indexed(4),
+                        attrs: 0x01,
+                    },
+                    link: 1,
+                }],
+            }],
+            links: vec![(1, "https://example.com/".to_string())],
+            alt_screen: true,
+            images: Vec::new(),
+        };
+        let bytes = screen.encode();
+
+        unsafe {
+            let decoded = avt_snapshot_decode(bytes.as_ptr(), bytes.len());
+            assert!(!decoded.is_null());
+            assert_eq!((avt_screen_cols(decoded), avt_screen_rows(decoded)), (4, 1));
+            assert_eq!(avt_screen_cursor(decoded).col, 2);
+            assert!(avt_screen_alt_screen(decoded));
+            assert_eq!(avt_screen_line_attr(decoded, 0), 1);
+            assert_eq!(avt_screen_run_count(decoded, 0), 1);
+            assert_eq!(avt_screen_run_count(decoded, 5), 0);
+
+            let mut run = std::mem::MaybeUninit::<AvtRun>::uninit();
+            assert!(avt_screen_run(decoded, 0, 0, run.as_mut_ptr()));
+            let run = run.assume_init();
+            assert_eq!(std::slice::from_raw_parts(run.text, run.text_len), b"ok");
+            assert_eq!(run.cells, 2);
+            let mut len = 0;
+            let uri = avt_screen_link(decoded, run.link, &mut len);
+            assert_eq!(std::slice::from_raw_parts(uri, len), b"https://example.com/");
+            assert!(avt_screen_link(decoded, 2, &mut len).is_null());
+            assert_eq!((run.fg.tag, run.fg.g), (AVT_COLOR_RGB, 2));
+            assert_eq!((run.bg.tag, run.bg.r), (AVT_COLOR_INDEXED, 4));
+            assert!(!avt_screen_run(decoded, 0, 1, &mut std::mem::zeroed()));
+
+            avt_screen_free(decoded);
+            assert!(avt_snapshot_decode(bytes.as_ptr(), 3).is_null());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! The binary formats, byte for byte, against golden files.
+//!
+//! Snapshots, diffs and sync payloads are kept on disk and move between
+//! devices with backups and sync, so every ABI has to write the same
+//! bytes: 32-bit armeabi-v7a as well as the 64-bit targets, where anything
+//! sized by `usize` would show. Each case encodes fixed input, without the
+//! real emulator, compares it with `fixtures/formats/<case>.bin`, and
+//! decodes the file again. CI runs these on the Android targets too (see
+//! the README). After a deliberate format change,
+//! `BLESS=1 cargo test format_tests` rewrites the files.
+
+use super::*;
+use crate::backend::tests::fake;
+use crate::lineattr::LineAttr;
+use crate::snapshot::{
+    Color, Cursor, CursorShape, Line, Run, Screen, Style, ATTR_BOLD, ATTR_UNDERLINE,
+};
+use std::fs;
+use std::path::Path;
+
+const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/formats");
+
+/// `bytes` must be the golden file `name`, or become it when blessing.
+fn golden(name: &str, bytes: &[u8]) {
+    let path = Path::new(DIR).join(format!("{}.bin", name));
+    if std::env::var_os("BLESS").is_some() {
+        fs::create_dir_all(DIR).unwrap();
+        fs::write(&path, bytes).unwrap();
+        return;
+    }
+    let expected = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    if let Some(at) =
+        (0..bytes.len().max(expected.len())).find(|&i| bytes.get(i) != expected.get(i))
+    {
+        panic!(
+            "{}: {} bytes against {} golden, first difference at {}",
+            name,
+            bytes.len(),
+            expected.len(),
+            at
+        );
+    }
+}
+
+fn screen() -> Screen {
+    let run = |col, text: &str, cells, style, link| Run {
+        col,
+        text: text.to_string(),
+        cells,
+        style,
+        link,
+    };
+    let red = Style {
+        fg: Color::Indexed(1),
+        bg: Color::Default,
+        attrs: ATTR_BOLD,
+    };
+    let truecolor = Style {
+        fg: Color::Rgb(255, 128, 0),
+        bg: Color::Indexed(240),
+        attrs: ATTR_UNDERLINE,
+    };
+    Screen {
+        cols: 8,
+        rows: 3,
+        cursor: Cursor {
+            col: 4,
+            row: 0,
+            visible: true,
+            shape: CursorShape::Bar,
+            blink: true,
+        },
+        lines: vec![
+            Line {
+                attr: LineAttr::Single,
+                runs: vec![
+                    run(0, "$ ", 2, Style::default(), 0),
+                    run(2, "ls", 2, red, 0),
+                    run(4, "    ", 4, Style::default(), 0),
+                ],
+            },
+            Line {
+                attr: LineAttr::DoubleWidth,
+                runs: vec![run(0, "日本", 4, truecolor, 7)],
+            },
+            Line::default(),
+        ],
+        links: vec![(7, "https://example.com/".to_string())],
+        alt_screen: true,
+        images: Vec::new(),
+    }
+}
+
+#[test]
+fn screens_encode_the_same_everywhere() {
+    let screen = screen();
+    let bytes = screen.encode();
+    golden("snapshot", &bytes);
+    assert_eq!(snapshot::decode(&bytes).unwrap(), screen);
+
+    let bytes = styles::encode_screen(&screen, &mut styles::Interner::default());
+    golden("snapshot_interned", &bytes);
+    let decoded = styles::decode_screen(&bytes, &mut styles::Cache::default()).unwrap();
+    assert_eq!(decoded, screen);
+    let region = styles::encode_region(&screen, 1..3, &mut styles::Interner::default());
+    golden("region", &region);
+
+    let mut history = delta::History::default();
+    history.delta(&screen, 0);
+    let mut next = screen.clone();
+    next.lines[2] = next.lines[0].clone();
+    golden("delta", &history.delta(&next, 1));
+}
+
+#[test]
+fn diffs_encode_the_same_everywhere() {
+    let mut vt = AvtState::with_backend(fake(6, 2));
+    vt.feed(b"hi");
+    let bytes = vt.poll_diff_content().unwrap();
+    golden("diff_content", &bytes);
+    assert_eq!(diff::decode(&bytes).unwrap().lines, [0, 1]);
+
+    vt.feed(b"!");
+    golden("diff_spans", &vt.poll_diff_spans().unwrap());
+    vt.feed(b"?");
+    golden("diff_damage", &vt.poll_diff_damage().unwrap());
+}
+
+#[test]
+fn stored_payloads_encode_the_same_everywhere() {
+    // Varints at the edges of 32 and 64 bits
+    let mut varints = Vec::new();
+    for value in [0, 127, 128, u32::MAX as usize] {
+        write_varint(&mut varints, value);
+    }
+    for value in [u32::MAX as u64 + 1, 1 << 53, u64::MAX] {
+        write_varint_u64(&mut varints, value);
+    }
+    golden("varints", &varints);
+
+    let items = [viewsync::Item {
+        kind: viewsync::KIND_ANNOTATION,
+        cast: "9f86d081884c7d65".to_string(),
+        id: "c0ffee".to_string(),
+        modified_ms: 1_760_000_000_000,
+        deleted: false,
+        time_us: 5_000_000_000,
+        duration_us: 1 << 40,
+        text: "the build breaks here".to_string(),
+        extra: vec![0xff],
+    }];
+    let bytes = viewsync::encode(&items);
+    golden("sync", &bytes);
+    assert_eq!(viewsync::decode(&bytes).unwrap(), items);
+}
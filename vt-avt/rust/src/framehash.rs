@@ -0,0 +1,103 @@
+//! A 64-bit hash of the visible frame, for cheap change detection.
+//!
+//! `vtFrameHash` covers what a snapshot shows: each row's runs (text,
+//! styles, link ids) and line attribute, the size, the cursor and the
+//! alternate screen flag. Equal hashes mean the same frame, FNV-1a
+//! collisions aside, so a player can skip recomposing a frame it already
+//! shows and an exporter can drop repeats. `AvtState::state_hash`
+//! is the one to compare screens with in tests.
+//!
+//! Row hashes are kept between calls, and a row is hashed again only once
+//! the VT marks it dirty, so a frame where little changed costs little
+//! more than its cursor.
+
+use crate::delta::fnv1a;
+use crate::snapshot::Line;
+use crate::{handles, VtHandle};
+use jni::objects::JClass;
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+/// Hashes of the rows of the last frame hashed, `None` once dirty.
+#[derive(Debug, Clone, Default)]
+pub struct RowHashes {
+    rows: Vec<Option<u64>>,
+}
+
+impl RowHashes {
+    /// Hash `rows` again next time.
+    pub fn invalidate(&mut self, rows: impl IntoIterator<Item = usize>) {
+        for row in rows {
+            if let Some(hash) = self.rows.get_mut(row) {
+                *hash = None;
+            }
+        }
+    }
+
+    /// Each row's hash as of the last `hash`, 0 for rows dirty since.
+    pub fn rows(&self) -> impl Iterator<Item = u64> + '_ {
+        self.rows.iter().map(|hash| hash.unwrap_or(0))
+    }
+
+    /// The frame's hash given its encoded snapshot `header` and `rows`
+    /// rows, taking the rows not kept from `line`.
+    pub fn hash(&mut self, header: &[u8], rows: usize, mut line: impl FnMut(usize) -> Line) -> u64 {
+        self.rows.resize(rows, None);
+        let mut buf = Vec::with_capacity(header.len() + rows * 8);
+        buf.extend_from_slice(header);
+        let mut encoded = Vec::new();
+        for (row, hash) in self.rows.iter_mut().enumerate() {
+            let hash = hash.get_or_insert_with(|| {
+                encoded.clear();
+                line(row).encode(&mut encoded);
+                fnv1a(&encoded)
+            });
+            buf.extend_from_slice(&hash.to_le_bytes());
+        }
+        fnv1a(&buf)
+    }
+}
+
+// JNI functions
+
+/// Hash of the visible frame, see `AvtState::frame_hash`; 0 for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFrameHash(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.frame_hash() as jlong
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn hashes_follow_the_frame_however_it_was_fed() {
+        let mut fed_once = AvtState::with_backend(fake(6, 2));
+        fed_once.feed(b"abc");
+        let mut fed_twice = AvtState::with_backend(fake(6, 2));
+        fed_twice.feed(b"ab");
+        let before = fed_twice.frame_hash();
+        assert_eq!(fed_twice.frame_hash(), before);
+
+        // A diff taken in between still leaves the row to be hashed again
+        fed_twice.feed(b"c");
+        fed_twice.poll_diff();
+        assert_ne!(fed_twice.frame_hash(), before);
+        assert_eq!(fed_twice.frame_hash(), fed_once.frame_hash());
+
+        fed_once.resize(6, 3);
+        assert_ne!(fed_once.frame_hash(), fed_twice.frame_hash());
+    }
+}
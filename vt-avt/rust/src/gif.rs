@@ -0,0 +1,465 @@
+//! Animated GIF export of a stretch of a recording, for sharing clips.
+//!
+//! `castExportGif` seeks to the start of the clip, then steps through it
+//! at the frame rate asked for, draws each frame with `render` and writes
+//! a looping GIF89a to a file descriptor. A frame that looks the same as
+//! the one before (same `AvtState::frame_hash`) only lengthens its delay,
+//! so a mostly idle terminal makes a small file.
+//!
+//! Cells are `MAX_CELL_WIDTH` pixels wide, or fewer to keep the image
+//! within the width asked for, and half again as tall. Each frame has its
+//! own color table: the exact colors when there are at most 256, which
+//! with terminal palettes is nearly always, otherwise the nearest of a
+//! 6×7×6 color cube. Frame times are rounded to the GIF's hundredths of a
+//! second, which is also why the frame rate is capped at `MAX_FPS`.
+
+use crate::backend::TerminalBackend;
+use crate::burnin::Frame;
+use crate::cast::{micros_arg, Cast};
+use crate::palette::{Options, Palette};
+use crate::render::render;
+use crate::{handles, player, AvtState};
+use jni::objects::{JClass, JIntArray, JObject, JValue};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufWriter, ErrorKind, Write};
+
+pub const MAX_CELL_WIDTH: usize = 12;
+pub const MAX_FPS: u32 = 50;
+
+/// Codes the LZW table holds at most
+const MAX_CODES: u16 = 4096;
+
+/// What to export; times are playback time in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clip {
+    pub start_us: i64,
+    pub end_us: i64,
+    pub fps: u32,
+    /// Widest image to make, in pixels; 0 for no limit
+    pub max_width: usize,
+}
+
+/// Write `clip` of `cast` to `out` as a GIF, replaying it on `vt`, and
+/// return the bytes written. `progress(frames_done, frames)` is called
+/// after every frame; returning false stops the export with an
+/// `Interrupted` error, leaving `out` with part of a file.
+pub fn export<B: TerminalBackend>(
+    vt: &mut AvtState<B>,
+    cast: &Cast,
+    clip: &Clip,
+    palette: &Palette,
+    out: impl Write,
+    mut progress: impl FnMut(usize, usize) -> bool,
+) -> io::Result<u64> {
+    if clip.end_us <= clip.start_us || !(1..=MAX_FPS).contains(&clip.fps) {
+        return Err(ErrorKind::InvalidInput.into());
+    }
+    let (cols, rows) = (cast.header.cols.max(1), cast.header.rows.max(1));
+    let cell_width = match clip.max_width {
+        0 => MAX_CELL_WIDTH,
+        max => (max / cols).clamp(1, MAX_CELL_WIDTH),
+    };
+    let (width, height) = (cols * cell_width, rows * (cell_width * 3 / 2).max(1));
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(ErrorKind::InvalidInput.into());
+    }
+
+    let schedule = player::schedule(cast, player::idle_limit(&cast.header));
+    let mut next = player::seek(vt, cast, clip.start_us);
+    let span = (clip.end_us - clip.start_us) as u64;
+    let frames = (span * clip.fps as u64).div_ceil(1_000_000) as usize;
+    let centis = |time_us: i64| (time_us - clip.start_us + 5_000) / 10_000;
+
+    let mut gif = Writer::new(out, width, height)?;
+    let options = Options::new(0, 1.0);
+    let mut pixels = vec![0; width * height * 4];
+    // The frame waiting for its delay, as of when it's shown
+    let mut shown: Option<(u64, i64)> = None;
+    for frame in 0..frames {
+        let at = clip.start_us + (frame as u64 * 1_000_000 / clip.fps as u64) as i64;
+        while next < schedule.len() && schedule[next] <= at {
+            player::apply(vt, &cast.events[next].kind, true);
+            next += 1;
+        }
+        vt.take_events();
+
+        let hash = vt.frame_hash();
+        if shown.is_none_or(|(shown_hash, _)| shown_hash != hash) {
+            if let Some((_, since)) = shown {
+                gif.frame(&pixels, centis(at) - centis(since))?;
+            }
+            let still = Frame::default();
+            render(&vt.screen(), palette, &options, &still, width, height, &mut pixels);
+            shown = Some((hash, at));
+        }
+        if !progress(frame + 1, frames) {
+            return Err(ErrorKind::Interrupted.into());
+        }
+    }
+    if let Some((_, since)) = shown {
+        gif.frame(&pixels, centis(clip.end_us) - centis(since))?;
+    }
+    gif.finish()
+}
+
+struct Writer<W: Write> {
+    out: BufWriter<W>,
+    width: u16,
+    height: u16,
+    written: u64,
+}
+
+impl<W: Write> Writer<W> {
+    fn new(out: W, width: usize, height: usize) -> io::Result<Self> {
+        let mut writer = Writer {
+            out: BufWriter::new(out),
+            width: width as u16,
+            height: height as u16,
+            written: 0,
+        };
+        let mut head = b"GIF89a".to_vec();
+        head.extend(writer.width.to_le_bytes());
+        head.extend(writer.height.to_le_bytes());
+        // No global color table, background 0, square pixels
+        head.extend([0, 0, 0]);
+        // Loop forever
+        head.extend(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+        writer.write(&head)?;
+        Ok(writer)
+    }
+
+    /// `pixels` as R, G, B, A bytes, shown for `centis` hundredths of a
+    /// second.
+    fn frame(&mut self, pixels: &[u8], centis: i64) -> io::Result<()> {
+        let (colors, indices) = quantize(pixels);
+        let bits = (usize::BITS - (colors.len() - 1).leading_zeros()).max(1);
+
+        let delay = centis.clamp(1, u16::MAX as i64) as u16;
+        let mut buf = vec![0x21, 0xf9, 0x04, 0x04];
+        buf.extend(delay.to_le_bytes());
+        buf.extend([0, 0]);
+        buf.push(0x2c);
+        for value in [0, 0, self.width, self.height] {
+            buf.extend(value.to_le_bytes());
+        }
+        buf.push(0x80 | (bits - 1) as u8);
+        for i in 0..1 << bits {
+            let rgb = colors.get(i).copied().unwrap_or(0);
+            buf.extend(&rgb.to_be_bytes()[1..]);
+        }
+
+        let min_code_size = bits.max(2) as u8;
+        buf.push(min_code_size);
+        for block in lzw(min_code_size, &indices).chunks(255) {
+            buf.push(block.len() as u8);
+            buf.extend_from_slice(block);
+        }
+        buf.push(0);
+        self.write(&buf)
+    }
+
+    fn finish(mut self) -> io::Result<u64> {
+        self.write(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.written)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// The colors of `pixels` as `0xRRGGBB`, at most 256, and each pixel's
+/// index among them.
+fn quantize(pixels: &[u8]) -> (Vec<u32>, Vec<u8>) {
+    let rgb = |pixel: &[u8]| u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
+    let mut index = HashMap::new();
+    let mut colors = Vec::new();
+    for pixel in pixels.chunks_exact(4) {
+        let color = rgb(pixel);
+        if let Entry::Vacant(entry) = index.entry(color) {
+            if colors.len() == 256 {
+                return cube(pixels);
+            }
+            entry.insert(colors.len() as u8);
+            colors.push(color);
+        }
+    }
+    let indices = pixels
+        .chunks_exact(4)
+        .map(|pixel| index[&rgb(pixel)])
+        .collect();
+    (colors, indices)
+}
+
+/// `quantize` to the nearest of 6 reds, 7 greens and 6 blues.
+fn cube(pixels: &[u8]) -> (Vec<u32>, Vec<u8>) {
+    let level = |value: u8, levels: u32| (value as u32 * (levels - 1) + 127) / 255;
+    let colors = (0..252)
+        .map(|i| {
+            let (r, g, b) = (i / 42, i / 6 % 7, i % 6);
+            ((r * 51) << 16) | ((g * 255 / 6) << 8) | (b * 51)
+        })
+        .collect();
+    let indices = pixels
+        .chunks_exact(4)
+        .map(|pixel| (level(pixel[0], 6) * 42 + level(pixel[1], 7) * 6 + level(pixel[2], 6)) as u8)
+        .collect();
+    (colors, indices)
+}
+
+/// `indices` LZW-compressed as GIF image data, before splitting into
+/// sub-blocks.
+fn lzw(min_code_size: u8, indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut acc, mut acc_bits) = (0u32, 0);
+    let mut emit = |code: u16, width: u32| {
+        acc |= (code as u32) << acc_bits;
+        acc_bits += width;
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let (mut next, mut width) = (end + 1, min_code_size as u32 + 1);
+    emit(clear, width);
+    let mut prefix = None;
+    for &index in indices {
+        let Some(code) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&longer) = table.get(&(code, index)) {
+            prefix = Some(longer);
+            continue;
+        }
+        emit(code, width);
+        if next < MAX_CODES {
+            table.insert((code, index), next);
+            next += 1;
+            // The decoder adds this code only after reading the next one
+            if next - 1 == 1 << width {
+                width += 1;
+            }
+        } else {
+            emit(clear, width);
+            table.clear();
+            (next, width) = (end + 1, min_code_size as u32 + 1);
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(code) = prefix {
+        emit(code, width);
+        if next == 1 << width && width < 12 {
+            width += 1;
+        }
+    }
+    emit(end, width);
+    emit(0, 7);
+    out
+}
+
+// JNI functions
+
+/// Export `start_micros` to `end_micros` of the cast `cast_handle` to `fd`
+/// as a GIF, see `export`, with `palette` as for `vtResolveStyles`.
+/// `progress`, if not null, is an `AvtProgress` told of every frame, which
+/// stops the export by returning false. The descriptor stays open. Returns
+/// the bytes written, or -1 for an invalid argument, a failed write or a
+/// stopped export (after which the file holds part of a GIF).
+#[cfg(unix)]
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castExportGif<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_handle: jlong,
+    start_micros: jlong,
+    end_micros: jlong,
+    fps: jint,
+    palette: JIntArray<'a>,
+    max_width_px: jint,
+    fd: jint,
+    progress: JObject<'a>,
+) -> jlong {
+    use std::fs::File;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        let (Some(start_us), Some(end_us)) = (micros_arg(start_micros), micros_arg(end_micros))
+        else {
+            return -1;
+        };
+        let (Ok(fps), Ok(max_width)) = (u32::try_from(fps), usize::try_from(max_width_px)) else {
+            return -1;
+        };
+        let Some(palette) = Palette::from_java(&env, &palette) else {
+            return -1;
+        };
+        if fd < 0 {
+            return -1;
+        }
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return -1;
+        };
+
+        let clip = Clip {
+            start_us,
+            end_us,
+            fps,
+            max_width,
+        };
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let report = |done: usize, frames: usize| {
+            if progress.is_null() {
+                return true;
+            }
+            let args = [JValue::Int(done as jint), JValue::Int(frames as jint)];
+            // A throwing callback stops the export and its exception stays
+            // pending for the caller
+            env.call_method(&progress, "onProgress", "(II)Z", &args)
+                .and_then(|keep_going| keep_going.z())
+                .unwrap_or(false)
+        };
+        match export(&mut vt, cast, &clip, &palette, &*file, report) {
+            Ok(written) => written.min(jlong::MAX as u64) as jlong,
+            Err(_) => -1,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    /// The indices `lzw` compressed, decoded the way GIF readers do.
+    fn unlzw(min_code_size: u8, data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear).map(|i| vec![i as u8]).collect();
+            table.extend([vec![], vec![]]);
+        };
+        reset(&mut table);
+        let (mut width, mut pos) = (min_code_size as usize + 1, 0);
+        let mut out = Vec::new();
+        let mut prev: Option<Vec<u8>> = None;
+        loop {
+            let code = (0..width).fold(0, |code, bit| {
+                let b = pos + bit;
+                code | ((data[b / 8] as usize >> (b % 8)) & 1) << bit
+            });
+            pos += width;
+            if code == clear {
+                reset(&mut table);
+                width = min_code_size as usize + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [&prev[..], &prev[..1]].concat(),
+                (None, None) => panic!("code {} out of the table", code),
+            };
+            if let Some(prev) = prev {
+                if table.len() < MAX_CODES as usize {
+                    table.push([&prev[..], &entry[..1]].concat());
+                    if table.len() == 1 << width && width < 12 {
+                        width += 1;
+                    }
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn frames_compress_and_decode_back() {
+        // Long enough to fill the table and clear it
+        let mut indices: Vec<u8> = (0..20_000u32).map(|i| (i * i / 7 % 4) as u8).collect();
+        indices.extend([3; 3000]);
+        for min_code_size in [2, 3, 8] {
+            assert_eq!(unlzw(min_code_size, &lzw(min_code_size, &indices)), indices);
+        }
+        assert_eq!(unlzw(2, &lzw(2, &[])), []);
+
+        let pixels = [[1, 2, 3, 255], [9, 9, 9, 255], [1, 2, 3, 255]].concat();
+        assert_eq!(quantize(&pixels), (vec![0x010203, 0x090909], vec![0, 1, 0]));
+        let many: Vec<u8> = (0..300u32)
+            .flat_map(|i| [i as u8, (i >> 8) as u8, 0, 255])
+            .collect();
+        let (colors, indices) = quantize(&many);
+        assert_eq!((colors.len(), indices[299]), (252, 42));
+    }
+
+    #[test]
+    fn clips_hold_unchanged_frames() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 4, \"height\": 1}\n\
+              [0.5, \"o\", \"a\"]\n\
+              [1.0, \"o\", \"b\"]\n",
+        )
+        .unwrap();
+        let clip = Clip {
+            start_us: 0,
+            end_us: 2_000_000,
+            fps: 10,
+            max_width: 24,
+        };
+        let mut vt = AvtState::with_backend(fake(4, 1));
+        let mut out = Vec::new();
+        let mut calls = 0;
+        let written = export(
+            &mut vt,
+            &cast,
+            &clip,
+            &Palette::default(),
+            &mut out,
+            |done, frames| {
+                calls += 1;
+                assert_eq!((done, frames), (calls, 20));
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(written as usize, out.len());
+        assert!(out.starts_with(b"GIF89a\x18\x00\x09\x00") && out.ends_with(b"\x3b"));
+        // Blank, then "a", then "ab", half a second, half and a second
+        let delays: Vec<u16> = out
+            .windows(4)
+            .filter(|w| w[..3] == [0x21, 0xf9, 0x04])
+            .map(|w| w.as_ptr() as usize - out.as_ptr() as usize)
+            .map(|at| u16::from_le_bytes([out[at + 4], out[at + 5]]))
+            .collect();
+        assert_eq!(delays, [50, 50, 100]);
+
+        let stop = export(
+            &mut vt,
+            &cast,
+            &clip,
+            &Palette::default(),
+            io::sink(),
+            |done, _| done < 3,
+        );
+        assert_eq!(stop.unwrap_err().kind(), ErrorKind::Interrupted);
+    }
+}
@@ -0,0 +1,79 @@
+//! Keeping Rust panics from crossing into the JVM.
+//!
+//! A panic unwinding out of an `extern "system"` function aborts the
+//! process, taking the whole app down. Every JNI entry point runs its body
+//! in `jni_guard!`, which catches the panic, throws `AvtNativeException`
+//! with the panic message and returns the type's default (0, false, null)
+//! in place of a result. The caller sees an exception it can recover from,
+//! and the VT it was using should be treated as broken and freed. Either
+//! way, it then releases the batons of the VTs the call looked up (see
+//! `worker`).
+
+use jni::JNIEnv;
+use std::any::Any;
+use std::fmt;
+
+const EXCEPTION: &str = "uk/adedamola/asciicast/vt/avt/AvtNativeException";
+const INVALID_ARGUMENT: &str = "java/lang/IllegalArgumentException";
+
+/// Run a JNI function body, turning a panic into `AvtNativeException`.
+/// `$env` must be the function's (mutable) `JNIEnv`.
+macro_rules! jni_guard {
+    ($env:ident, $body:block) => {{
+        let result = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| $body)) {
+            Ok(result) => result,
+            Err(payload) => {
+                $crate::guard::throw_panic(&mut $env, payload);
+                Default::default()
+            }
+        };
+        $crate::worker::release_held();
+        result
+    }};
+}
+
+pub(crate) fn throw_panic(env: &mut JNIEnv, payload: Box<dyn Any + Send>) {
+    let message = message(payload.as_ref());
+    // A body that panics after a JNI call failed can leave an exception
+    // pending, and another can't be thrown over it
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    let _ = env.throw_new(EXCEPTION, format!("native panic: {}", message));
+}
+
+/// Throw `IllegalArgumentException` saying why a call rejected its input,
+/// unless an exception is already pending.
+pub(crate) fn throw_invalid(env: &mut JNIEnv, error: impl fmt::Display) {
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    let _ = env.throw_new(INVALID_ARGUMENT, error.to_string());
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn panic_messages_are_extracted() {
+        let caught = |f: fn()| {
+            let payload = panic::catch_unwind(f).unwrap_err();
+            message(payload.as_ref()).to_string()
+        };
+        assert_eq!(caught(|| panic!("static")), "static");
+        assert_eq!(caught(|| panic!("row {} of {}", 3, 2)), "row 3 of 2");
+        assert_eq!(caught(|| panic::panic_any(7)), "unknown panic");
+    }
+}
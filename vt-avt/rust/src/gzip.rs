@@ -0,0 +1,114 @@
+//! Streaming gzip (RFC 1952) and zlib (RFC 1950) decompression, through
+//! `flate2` and its miniz_oxide backend.
+//!
+//! Casts downloaded from servers are often `.cast.gz`. `Decoder` inflates
+//! one as it's read, so a long recording is never inflated in memory as a
+//! whole. Concatenated members are read as one stream, the way `gzip -d`
+//! does, and each member's CRC-32 and length are checked at its end.
+//!
+//! `Decoder::zlib` reads a zlib stream instead, the DEFLATE data PNG
+//! images hold, checking its Adler-32 at the end.
+
+use crate::castfile::GZIP_MAGIC;
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Decompresses a gzip or zlib stream as it's read.
+pub struct Decoder<D>(BufReader<D>);
+
+fn invalid(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+impl<R: BufRead> Decoder<MultiGzDecoder<R>> {
+    /// Check that `inner` starts as a gzip stream; its header is read at
+    /// the first read.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        if !inner.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            return Err(invalid("not a gzip stream"));
+        }
+        Ok(Decoder(BufReader::new(MultiGzDecoder::new(inner))))
+    }
+}
+
+impl<R: BufRead> Decoder<ZlibDecoder<R>> {
+    /// Check that `inner` starts as a zlib stream. The stream ends after
+    /// its checksum.
+    pub fn zlib(mut inner: R) -> io::Result<Self> {
+        // CMF and FLG: DEFLATE, with the pair a multiple of 31
+        match *inner.fill_buf()? {
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && u16::from_be_bytes([cmf, flg]).is_multiple_of(31) => {}
+            _ => return Err(invalid("not a zlib stream")),
+        }
+        Ok(Decoder(BufReader::new(ZlibDecoder::new(inner))))
+    }
+}
+
+impl<D: Read> Read for Decoder<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<D: Read> BufRead for Decoder<D> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `printf 'hello hello hello\n' | gzip -9n`
+    const HELLO: [u8; 29] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00, 0x00,
+    ];
+
+    fn inflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Decoder::new(bytes)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn inflates_members_and_checks_them() {
+        assert_eq!(inflate(&HELLO).unwrap(), b"hello hello hello\n");
+        let twice = [HELLO, HELLO].concat();
+        assert_eq!(inflate(&twice).unwrap(), b"hello hello hello\n".repeat(2));
+
+        let mut corrupt = HELLO;
+        corrupt[22] ^= 1;
+        assert!(inflate(&corrupt).is_err());
+        assert!(inflate(&HELLO[..20]).is_err());
+        assert!(inflate(b"hello").is_err());
+    }
+
+    #[test]
+    fn inflates_zlib_streams() {
+        // `zlib.compress(b"hello hello hello\n", 9)`
+        let mut zlib = [
+            0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x40,
+            0xb5, 0x06, 0x87,
+        ];
+        let mut out = Vec::new();
+        Decoder::zlib(&zlib[..])
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"hello hello hello\n");
+
+        zlib[16] ^= 1;
+        assert!(Decoder::zlib(&zlib[..])
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+        assert!(Decoder::zlib(&HELLO[..]).is_err());
+    }
+}
@@ -0,0 +1,660 @@
+//! Registries of live native handles, one per kind of object.
+//!
+//! A handle is `generation << 32 | kind << 24 | slot`, slots counting from
+//! 1 so no handle is 0. Freeing a handle bumps its slot's generation, so a
+//! stale handle from Kotlin (used after `vtFree`, or after its slot went to
+//! another VT) fails the lookup instead of reaching freed memory, and so
+//! does any made-up value or a handle of another kind. By default such
+//! calls do nothing and return their "invalid handle" value, like handle 0;
+//! `vtSetStrictHandles` makes them throw `IllegalStateException` as well,
+//! to find the caller's bug.
+//!
+//! VTs have the registry below, with their workers and borrowed entries.
+//! Every other kind is `Registered` with one of its own (see
+//! `registered!`) and looked up through `object`, so no JNI function casts
+//! a handle back to a pointer itself.
+//!
+//! Lookups take the kind's lock for a few instructions. The object is used
+//! outside it, so as before a handle must not be used from two threads at
+//! once; a VT's own worker thread aside (see `worker`), whose baton a
+//! lookup takes for the rest of the call.
+//!
+//! For debug screens and session switchers, `vtListHandles` enumerates the
+//! live native objects of a `Kind` from its registry, each with a label
+//! the app sets.
+
+use crate::worker::{self, Worker};
+use crate::{AvtState, VtHandle};
+use jni::objects::{JClass, JLongArray, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+static REGISTRY: Mutex<Registry<AvtState>> = Mutex::new(Registry::new());
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Give `$type` a registry of its own for handles of `$kind`.
+macro_rules! registered {
+    ($type:ty, $kind:expr) => {
+        impl $crate::handles::Registered for $type {
+            const KIND: $crate::handles::Kind = $kind;
+
+            fn registry() -> std::sync::MutexGuard<'static, $crate::handles::Registry<Self>> {
+                static REGISTRY: std::sync::Mutex<$crate::handles::Registry<$type>> =
+                    std::sync::Mutex::new($crate::handles::Registry::of($kind));
+                REGISTRY.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+            }
+        }
+    };
+}
+
+/// A native object handed to Kotlin by handle, see `registered!`.
+pub(crate) trait Registered: Sized + 'static {
+    const KIND: Kind;
+
+    fn registry() -> MutexGuard<'static, Registry<Self>>;
+}
+
+/// Native objects `vtListHandles` enumerates, by code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// VTs, a player's included
+    Vt = 0,
+    Player = 1,
+    /// Hardware console streams (`stream`)
+    Stream = 2,
+    /// Network consoles (`net`)
+    Connection = 3,
+    Recorder = 4,
+    Journal = 5,
+    /// Live broadcasts (`alis`)
+    Live = 6,
+    /// Snapshot readers (`epoch`)
+    Reader = 7,
+    /// SSH sessions (`ssh`)
+    Session = 8,
+    /// Edit decision lists (`edl`)
+    Edl = 9,
+    /// Seek checkpoints of a cast (`checkpoint`)
+    Checkpoints = 10,
+    /// Recording library indexes (`library`)
+    Library = 11,
+    /// Screens mirrored from another device (`sync`)
+    SyncReceiver = 12,
+    /// Parsed recordings (`cast`), clips and edit results included
+    Cast = 13,
+    /// Casts streamed from a file or source (`castfile`)
+    CastFile = 14,
+    /// Edits with undo history (`edit`)
+    EditSession = 15,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Vt => "VT",
+            Kind::Player => "player",
+            Kind::Stream => "stream",
+            Kind::Connection => "connection",
+            Kind::Recorder => "recorder",
+            Kind::Journal => "journal",
+            Kind::Live => "live broadcast",
+            Kind::Reader => "snapshot reader",
+            Kind::Session => "SSH session",
+            Kind::Edl => "EDL",
+            Kind::Checkpoints => "checkpoint index",
+            Kind::Library => "library",
+            Kind::SyncReceiver => "sync receiver",
+            Kind::Cast => "cast",
+            Kind::CastFile => "cast file",
+            Kind::EditSession => "edit session",
+        }
+    }
+
+    pub fn from_code(code: jint) -> Option<Kind> {
+        match code {
+            0 => Some(Kind::Vt),
+            1 => Some(Kind::Player),
+            2 => Some(Kind::Stream),
+            3 => Some(Kind::Connection),
+            4 => Some(Kind::Recorder),
+            5 => Some(Kind::Journal),
+            6 => Some(Kind::Live),
+            7 => Some(Kind::Reader),
+            8 => Some(Kind::Session),
+            9 => Some(Kind::Edl),
+            10 => Some(Kind::Checkpoints),
+            11 => Some(Kind::Library),
+            12 => Some(Kind::SyncReceiver),
+            13 => Some(Kind::Cast),
+            14 => Some(Kind::CastFile),
+            15 => Some(Kind::EditSession),
+            _ => None,
+        }
+    }
+}
+
+struct Entry<T> {
+    ptr: *mut T,
+    /// False for VTs owned by something else (a player's), which can't be
+    /// freed through their handle
+    owned: bool,
+    label: String,
+    /// Parsing `vtFeedAsync`'s bytes, see `worker`
+    worker: Option<Worker>,
+}
+
+// The registry only stores and compares the pointers; whoever holds a
+// handle is responsible for using it from one thread at a time
+unsafe impl<T> Send for Entry<T> {}
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<Entry<T>>,
+}
+
+pub struct Registry<T> {
+    /// `Kind` code, in each handle
+    kind: u8,
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Registry<T> {
+    pub const fn new() -> Self {
+        Self::of(Kind::Vt)
+    }
+
+    pub const fn of(kind: Kind) -> Self {
+        Registry {
+            kind: kind as u8,
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert_entry(&mut self, entry: Entry<T>) -> VtHandle {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index].entry = Some(entry);
+        self.handle(index)
+    }
+
+    fn handle(&self, index: usize) -> VtHandle {
+        let generation = self.slots[index].generation as u64;
+        (generation << 32 | (self.kind as u64) << 24 | (index as u64 + 1)) as VtHandle
+    }
+
+    fn borrowed(&self, ptr: *mut T) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.entry
+                .as_ref()
+                .is_some_and(|e| !e.owned && e.ptr == ptr)
+        })
+    }
+
+    pub fn insert(&mut self, value: Box<T>) -> VtHandle {
+        self.insert_entry(Entry {
+            ptr: Box::into_raw(value),
+            owned: true,
+            label: String::new(),
+            worker: None,
+        })
+    }
+
+    /// Handle for a value owned elsewhere, the same one each time for the
+    /// same pointer. `forget` it before the value goes away.
+    pub fn insert_borrowed(&mut self, ptr: *mut T) -> VtHandle {
+        match self.borrowed(ptr) {
+            Some(index) => self.handle(index),
+            None => self.insert_entry(Entry {
+                ptr,
+                owned: false,
+                label: String::new(),
+                worker: None,
+            }),
+        }
+    }
+
+    fn index(&self, handle: VtHandle) -> Option<usize> {
+        if (handle as u64 >> 24) as u8 != self.kind {
+            return None;
+        }
+        let index = (handle as u64 & 0xff_ffff).checked_sub(1)? as usize;
+        let slot = self.slots.get(index)?;
+        (slot.generation == (handle as u64 >> 32) as u32 && slot.entry.is_some()).then_some(index)
+    }
+
+    pub fn get(&self, handle: VtHandle) -> Option<*mut T> {
+        let index = self.index(handle)?;
+        self.slots[index].entry.as_ref().map(|e| e.ptr)
+    }
+
+    /// The shared state of the value's worker, if it has one.
+    pub fn worker(&self, handle: VtHandle) -> Option<Arc<worker::Shared>> {
+        let index = self.index(handle)?;
+        let worker = self.slots[index].entry.as_ref()?.worker.as_ref()?;
+        Some(Arc::clone(worker.shared()))
+    }
+
+    /// Take the value's worker, to stop it.
+    pub fn take_worker(&mut self, handle: VtHandle) -> Option<Worker> {
+        self.entry_mut(handle)?.worker.take()
+    }
+
+    /// Live handles, by slot.
+    pub fn handles(&self) -> Vec<VtHandle> {
+        (0..self.slots.len())
+            .filter(|&index| self.slots[index].entry.is_some())
+            .map(|index| self.handle(index))
+            .collect()
+    }
+
+    fn label_mut(&mut self, handle: VtHandle) -> Option<&mut String> {
+        self.entry_mut(handle).map(|e| &mut e.label)
+    }
+
+    fn entry_mut(&mut self, handle: VtHandle) -> Option<&mut Entry<T>> {
+        let index = self.index(handle)?;
+        self.slots[index].entry.as_mut()
+    }
+
+    fn release(&mut self, index: usize) -> Entry<T> {
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(index);
+        slot.entry.take().unwrap()
+    }
+
+    /// Take back an owned value; `None` (and nothing changes) for unknown
+    /// handles and borrowed ones.
+    pub fn remove(&mut self, handle: VtHandle) -> Option<Box<T>> {
+        let index = self.index(handle)?;
+        if !self.slots[index].entry.as_ref()?.owned {
+            return None;
+        }
+        let entry = self.release(index);
+        Some(unsafe { Box::from_raw(entry.ptr) })
+    }
+
+    /// Invalidate the handle of a borrowed value.
+    pub fn forget(&mut self, ptr: *mut T) {
+        if let Some(index) = self.borrowed(ptr) {
+            self.release(index);
+        }
+    }
+}
+
+pub(crate) fn registry() -> MutexGuard<'static, Registry<AvtState>> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What `list` and the labels need of a registry, whatever it holds.
+trait Listing {
+    fn handles(&self) -> Vec<jlong>;
+    fn label_mut(&mut self, handle: jlong) -> Option<&mut String>;
+}
+
+impl<T> Listing for Registry<T> {
+    fn handles(&self) -> Vec<jlong> {
+        Registry::handles(self)
+    }
+
+    fn label_mut(&mut self, handle: jlong) -> Option<&mut String> {
+        Registry::label_mut(self, handle)
+    }
+}
+
+/// Run `f` on the registry of `kind`; `None` for the kinds whose feature
+/// isn't built.
+fn with_registry<R>(kind: Kind, f: impl FnOnce(&mut dyn Listing) -> R) -> Option<R> {
+    match kind {
+        Kind::Vt => Some(f(&mut *registry())),
+        Kind::Player => Some(f(&mut *crate::player::Player::registry())),
+        Kind::Stream => Some(f(&mut *crate::stream::Stream::registry())),
+        #[cfg(feature = "net")]
+        Kind::Connection => Some(f(&mut *crate::net::Connection::registry())),
+        Kind::Recorder => Some(f(&mut *crate::record::FileRecorder::registry())),
+        Kind::Journal => Some(f(&mut *crate::journal::FileJournal::registry())),
+        Kind::Live => Some(f(&mut *crate::alis::Live::registry())),
+        Kind::Reader => Some(f(&mut *crate::epoch::Reader::<crate::snapshot::Screen>::registry())),
+        #[cfg(feature = "ssh")]
+        Kind::Session => Some(f(&mut *crate::ssh::Session::registry())),
+        Kind::Edl => Some(f(&mut *crate::edl::Edl::registry())),
+        Kind::Checkpoints => Some(f(&mut *crate::checkpoint::CheckpointIndex::registry())),
+        #[cfg(feature = "library")]
+        Kind::Library => Some(f(&mut *crate::library::Library::registry())),
+        Kind::SyncReceiver => Some(f(&mut *crate::sync::Receiver::registry())),
+        Kind::Cast => Some(f(&mut *crate::cast::Cast::registry())),
+        Kind::CastFile => Some(f(&mut *crate::castfile::CastFile::registry())),
+        Kind::EditSession => Some(f(&mut *crate::edit::EditSession::registry())),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Live handles of `kind`, by slot.
+pub fn list(kind: Kind) -> Vec<jlong> {
+    with_registry(kind, |registry| registry.handles()).unwrap_or_default()
+}
+
+/// Label a live object; false if `handle` isn't one of `kind`.
+pub fn set_label(kind: Kind, handle: jlong, label: &str) -> bool {
+    with_registry(kind, |registry| registry.label_mut(handle).map(|l| *l = label.to_string()))
+        .flatten()
+        .is_some()
+}
+
+/// The label of a live object, empty until set; `None` if `handle` isn't
+/// one of `kind`.
+pub fn label(kind: Kind, handle: jlong) -> Option<String> {
+    with_registry(kind, |registry| registry.label_mut(handle).cloned()).flatten()
+}
+
+/// Register a new object, returning its handle.
+pub(crate) fn add<T: Registered>(value: T) -> jlong {
+    T::registry().insert(Box::new(value))
+}
+
+/// The object behind `handle`, as `get` for VTs: `None` for 0, and for
+/// stale or bogus handles or another kind's, which also throw in strict
+/// mode.
+pub(crate) fn object<'h, T: Registered>(env: &mut JNIEnv, handle: jlong) -> Option<&'h mut T> {
+    if handle == 0 {
+        return None;
+    }
+    let ptr = T::registry().get(handle);
+    if ptr.is_none() {
+        invalid(env, T::KIND, handle);
+    }
+    ptr.map(|ptr| unsafe { &mut *ptr })
+}
+
+/// `object` for reading, where two handles may name the same object.
+pub(crate) fn object_ref<'h, T: Registered>(env: &mut JNIEnv, handle: jlong) -> Option<&'h T> {
+    if handle == 0 {
+        return None;
+    }
+    let ptr = T::registry().get(handle);
+    if ptr.is_none() {
+        invalid(env, T::KIND, handle);
+    }
+    ptr.map(|ptr| unsafe { &*ptr })
+}
+
+/// Unregister and return an object, as for `object` when there is none.
+pub(crate) fn take<T: Registered>(env: &mut JNIEnv, handle: jlong) -> Option<Box<T>> {
+    if handle == 0 {
+        return None;
+    }
+    let value = T::registry().remove(handle);
+    if value.is_none() {
+        invalid(env, T::KIND, handle);
+    }
+    value
+}
+
+/// Register a new VT, returning its handle.
+pub(crate) fn insert(state: Box<AvtState>) -> VtHandle {
+    registry().insert(state)
+}
+
+/// The VT behind `handle`. `None` for 0, and for stale or bogus handles,
+/// which also throw in strict mode. Waits for the VT's worker, if any, to
+/// finish its slice, and keeps it waiting until the JNI call returns.
+pub(crate) fn get<'h>(env: &mut JNIEnv, handle: VtHandle) -> Option<&'h mut AvtState> {
+    if handle == 0 {
+        return None;
+    }
+    let (ptr, worker) = {
+        let registry = registry();
+        (registry.get(handle), registry.worker(handle))
+    };
+    if ptr.is_none() {
+        invalid(env, Kind::Vt, handle);
+    }
+    if let Some(shared) = worker {
+        worker::hold(shared);
+    }
+    ptr.map(|ptr| unsafe { &mut *ptr })
+}
+
+/// Unregister and return an owned VT, as for `get` when there is none. Its
+/// worker is stopped first, leaving what's queued.
+pub(crate) fn remove(env: &mut JNIEnv, handle: VtHandle) -> Option<Box<AvtState>> {
+    if handle == 0 {
+        return None;
+    }
+    let worker = registry().take_worker(handle);
+    if let Some(worker) = worker {
+        worker.stop(false);
+    }
+    let state = registry().remove(handle);
+    if state.is_none() {
+        invalid(env, Kind::Vt, handle);
+    }
+    state
+}
+
+/// Give the owned VT behind `handle` a worker parsing from a ring of
+/// `capacity` bytes; false for a borrowed VT or one that has a worker.
+pub(crate) fn start_worker(env: &mut JNIEnv, handle: VtHandle, capacity: usize) -> bool {
+    let mut registry = registry();
+    let Some(entry) = registry.entry_mut(handle) else {
+        drop(registry);
+        invalid(env, Kind::Vt, handle);
+        return false;
+    };
+    if !entry.owned || entry.worker.is_some() {
+        return false;
+    }
+    entry.worker = Some(Worker::start(
+        entry.ptr,
+        |vt, bytes| vt.feed(bytes),
+        capacity,
+    ));
+    true
+}
+
+/// The shared state of the worker of the VT behind `handle`, without
+/// waiting for it; `None` as for `get`, and without a worker.
+pub(crate) fn worker(env: &mut JNIEnv, handle: VtHandle) -> Option<Arc<worker::Shared>> {
+    if handle == 0 {
+        return None;
+    }
+    let registry = registry();
+    if registry.get(handle).is_none() {
+        drop(registry);
+        invalid(env, Kind::Vt, handle);
+        return None;
+    }
+    registry.worker(handle)
+}
+
+/// Take the worker of the VT behind `handle`, to stop it.
+pub(crate) fn take_worker(env: &mut JNIEnv, handle: VtHandle) -> Option<Worker> {
+    if handle == 0 {
+        return None;
+    }
+    let mut registry = registry();
+    if registry.get(handle).is_none() {
+        drop(registry);
+        invalid(env, Kind::Vt, handle);
+        return None;
+    }
+    registry.take_worker(handle)
+}
+
+fn invalid(env: &mut JNIEnv, kind: Kind, handle: jlong) {
+    if STRICT.load(Ordering::Relaxed) {
+        let message = format!("invalid or freed {} handle {:#x}", kind.name(), handle);
+        let _ = env.throw_new("java/lang/IllegalStateException", message);
+    }
+}
+
+// JNI functions
+
+/// Throw `IllegalStateException` on stale or bogus VT handles, for debug
+/// builds. Off by default.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetStrictHandles(
+    mut env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    jni_guard!(env, {
+        STRICT.store(enabled != JNI_FALSE, Ordering::Relaxed);
+    })
+}
+
+/// Live handles of the kind with code `kind`, see `list`; empty for an
+/// unknown kind.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtListHandles<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    kind: jint,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let handles = Kind::from_code(kind).map(list).unwrap_or_default();
+        crate::long_array(&env, &handles)
+    })
+}
+
+/// Returns false for an unknown kind or a handle that isn't live.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetHandleLabel(
+    mut env: JNIEnv,
+    _class: JClass,
+    kind: jint,
+    handle: jlong,
+    label: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(kind) = Kind::from_code(kind) else {
+            return JNI_FALSE;
+        };
+        let Ok(label) = env.get_string(&label) else {
+            return JNI_FALSE;
+        };
+        let label: String = label.into();
+
+        if set_label(kind, handle, &label) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Null for an unknown kind or a handle that isn't live.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtHandleLabel<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    kind: jint,
+    handle: jlong,
+) -> JString<'a> {
+    jni_guard!(env, {
+        match Kind::from_code(kind).and_then(|kind| label(kind, handle)) {
+            Some(label) => env.new_string(label).unwrap_or_default(),
+            None => JString::default(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::CheckpointIndex;
+
+    #[test]
+    fn stale_and_bogus_handles_are_rejected() {
+        let mut registry = Registry::new();
+        let a = registry.insert(Box::new(1));
+        assert_ne!(a, 0);
+        assert_eq!(registry.get(a).map(|p| unsafe { *p }), Some(1));
+
+        assert_eq!(registry.remove(a).as_deref(), Some(&1));
+        assert!(registry.get(a).is_none());
+        assert!(registry.remove(a).is_none());
+
+        // The slot is reused under a new generation
+        let b = registry.insert(Box::new(2));
+        assert_eq!(b as u64 & 0xffff_ffff, a as u64 & 0xffff_ffff);
+        assert_ne!(a, b);
+        assert!(registry.get(a).is_none());
+        assert!(registry.get(0x1234_5678_9abc).is_none());
+        assert!(registry.get(-1).is_none());
+
+        // Borrowed values keep one handle and can't be freed through it
+        let mut owner = 3;
+        let c = registry.insert_borrowed(&mut owner);
+        assert_eq!(registry.insert_borrowed(&mut owner), c);
+        assert!(registry.remove(c).is_none());
+        assert!(registry.get(c).is_some());
+        registry.forget(&mut owner);
+        assert!(registry.get(c).is_none());
+        assert_eq!(registry.remove(b).as_deref(), Some(&2));
+    }
+
+    #[test]
+    fn handles_of_another_kind_are_rejected() {
+        let mut vts = Registry::new();
+        let mut edls = Registry::of(Kind::Edl);
+        let vt = vts.insert(Box::new(1));
+        let edl = edls.insert(Box::new(2));
+        // The same slot in each, told apart by the kind
+        assert_eq!(vt as u64 & 0xff_ffff, edl as u64 & 0xff_ffff);
+        assert!(edls.get(vt).is_none());
+        assert!(vts.get(edl).is_none());
+        assert!(vts.remove(edl).is_none());
+        assert_eq!(edls.remove(edl).as_deref(), Some(&2));
+    }
+
+    #[test]
+    fn live_objects_are_listed_with_labels() {
+        let mut registry = Registry::new();
+        let a = registry.insert(Box::new(1));
+        let b = registry.insert(Box::new(2));
+        registry.remove(a);
+        assert_eq!(registry.handles(), [b]);
+        registry.entry_mut(b).unwrap().label = "main".into();
+        assert_eq!(registry.entry_mut(b).unwrap().label, "main");
+
+        // Other tests don't register checkpoint indexes, but VTs come and go
+        let first = add(CheckpointIndex::new(None, None));
+        let second = add(CheckpointIndex::new(None, None));
+        assert!(set_label(Kind::Checkpoints, second, "ssh prod"));
+        assert!(!set_label(Kind::Journal, second, "wrong kind"));
+        assert_eq!(list(Kind::Checkpoints), [first, second]);
+        assert_eq!(label(Kind::Checkpoints, second).as_deref(), Some("ssh prod"));
+
+        CheckpointIndex::registry().remove(first);
+        assert_eq!(list(Kind::Checkpoints), [second]);
+        assert_eq!(label(Kind::Checkpoints, first), None);
+        CheckpointIndex::registry().remove(second);
+        assert!(list(Kind::Checkpoints).is_empty());
+        assert_eq!(Kind::from_code(Kind::Journal as jint), Some(Kind::Journal));
+        assert_eq!(Kind::from_code(Kind::Live as jint), Some(Kind::Live));
+        assert_eq!(Kind::from_code(Kind::Reader as jint), Some(Kind::Reader));
+        assert_eq!(Kind::from_code(Kind::SyncReceiver as jint), Some(Kind::SyncReceiver));
+        assert_eq!(Kind::from_code(Kind::EditSession as jint), Some(Kind::EditSession));
+        assert_eq!(Kind::from_code(16), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! Highlight reels: the busiest stretches of a recording.
+//!
+//! The cast is replayed and every output event's damage diff (see `diff`)
+//! counted as cells changed, in one-second buckets of cast time. Clips of
+//! `CLIP_SECONDS` are then picked greedily by the cells changed inside
+//! them, the busiest first and none overlapping, until they add up to the
+//! target; the last one is cut short to fit. Seconds where nothing
+//! changed are never picked, so a mostly idle cast gets a shorter reel.
+
+use crate::backend::TerminalBackend;
+use crate::cast::{micros_arg, Cast, EventKind};
+use crate::{diff, handles, player, AvtState};
+use jni::objects::{JClass, JLongArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+/// Length of a picked clip, long enough to follow what happens in it
+pub const CLIP_SECONDS: usize = 5;
+
+const SECOND_US: i64 = 1_000_000;
+
+/// Cells changed in each second of `cast`, replayed into `vt`.
+pub fn activity<B: TerminalBackend>(vt: &mut AvtState<B>, cast: &Cast) -> Vec<u64> {
+    let mut seconds = Vec::new();
+    // Damage is measured against the rows last reported, so report them all
+    vt.invalidate(None);
+    vt.poll_diff_damage();
+    for event in &cast.events {
+        player::apply(vt, &event.kind, true);
+        let Some(bytes) = vt.poll_diff_damage() else {
+            continue;
+        };
+        // A resize redraws everything without anything happening
+        if !matches!(event.kind, EventKind::Output(_)) {
+            continue;
+        }
+        let Ok(changes) = diff::decode(&bytes) else {
+            continue;
+        };
+        let cells: usize = changes
+            .damage
+            .iter()
+            .flatten()
+            .map(|(_, cols)| cols.len())
+            .sum();
+        let second = (event.time_us.max(0) / SECOND_US) as usize;
+        if seconds.len() <= second {
+            seconds.resize(second + 1, 0);
+        }
+        seconds[second] += cells as u64;
+    }
+    seconds
+}
+
+/// Time ranges, in order and merged where they touch, adding up to at
+/// most `target_us` of the busiest clips in `activity`. A cast no longer
+/// than the target is its own reel.
+pub fn pick(activity: &[u64], duration_us: i64, target_us: i64) -> Vec<(i64, i64)> {
+    if duration_us <= target_us {
+        return vec![(0, duration_us)];
+    }
+    let clip = CLIP_SECONDS.min(activity.len().max(1));
+    let mut windows: Vec<(u64, usize)> = (0..=activity.len().saturating_sub(clip))
+        .map(|start| (activity[start..start + clip].iter().sum(), start))
+        .filter(|&(cells, _)| cells > 0)
+        .collect();
+    // Busiest first, earlier first among equals
+    windows.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let mut picked: Vec<(i64, i64)> = Vec::new();
+    let mut total = 0;
+    for (_, start) in windows {
+        if total >= target_us {
+            break;
+        }
+        let start_us = start as i64 * SECOND_US;
+        let end_us = ((start + clip) as i64 * SECOND_US).min(duration_us);
+        // Whole clips can't overlap, or a cut one could be just the quiet
+        // lead-in to a clip already picked
+        if picked.iter().any(|&(s, e)| start_us < e && s < end_us) {
+            continue;
+        }
+        let end_us = end_us.min(start_us + target_us - total);
+        total += end_us - start_us;
+        picked.push((start_us, end_us));
+    }
+
+    picked.sort_unstable();
+    let mut merged: Vec<(i64, i64)> = Vec::new();
+    for (start, end) in picked {
+        match merged.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// JNI functions
+
+/// `[startMicros, endMicros, ...]` of the busiest stretches of the cast
+/// `handle`, adding up to at most `target_micros`. Empty for an invalid
+/// handle or time.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castHighlights<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    target_micros: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(target_us) = micros_arg(target_micros) else {
+            return JLongArray::default();
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        let seconds = activity(&mut vt, cast);
+        let duration_us = cast.events.last().map_or(0, |event| event.time_us);
+        let values: Vec<jlong> = pick(&seconds, duration_us, target_us)
+            .into_iter()
+            .flat_map(|(start, end)| [start, end])
+            .collect();
+        crate::long_array(&env, &values)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn counts_changed_cells_per_second() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [0.5, \"o\", \"hello\"]\n\
+            [0.7, \"o\", \"ab\"]\n\
+            [2.1, \"o\", \"c\"]\n\
+            [2.5, \"r\", \"20x4\"]\n",
+        )
+        .unwrap();
+        let mut vt = AvtState::with_backend(fake(10, 2));
+        assert_eq!(activity(&mut vt, &cast), [7, 0, 1]);
+    }
+
+    #[test]
+    fn picks_the_busiest_clips_up_to_the_target() {
+        let mut seconds = vec![0; 60];
+        seconds[10..15].fill(5);
+        seconds[40..45].fill(9);
+        seconds[45..47].fill(1);
+        seconds[20] = 1;
+
+        assert_eq!(pick(&seconds, 60 * SECOND_US, 0), []);
+        assert_eq!(
+            pick(&seconds, 60 * SECOND_US, 12 * SECOND_US),
+            [
+                (10 * SECOND_US, 15 * SECOND_US),
+                (40 * SECOND_US, 47 * SECOND_US)
+            ]
+        );
+        // Idle seconds are never filler
+        assert_eq!(pick(&seconds, 60 * SECOND_US, 50 * SECOND_US).len(), 3);
+        assert_eq!(
+            pick(&seconds, 30 * SECOND_US, 30 * SECOND_US),
+            [(0, 30 * SECOND_US)]
+        );
+    }
+}
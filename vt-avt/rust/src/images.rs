@@ -0,0 +1,712 @@
+//! Sixel and iTerm2 inline images.
+//!
+//! Tools like `img2sixel` and `imgcat` print images as Sixel (DCS `q`, see
+//! `sixel`) or iTerm2's `OSC 1337 ; File=args:base64 ST` (PNG, see `png`).
+//! avt ignores both, so whatever was printed next landed where the image
+//! should be. The wrapper decodes each image to RGBA under an id instead,
+//! blanks the cells it covers from the cursor and moves the cursor below
+//! it at the image's column, a row at a time, scrolling at the bottom
+//! margin, as the VT340 does. A cell is taken to be `CELL_WIDTH` ×
+//! `CELL_HEIGHT` pixels, the VT340's; an image is clamped to the screen,
+//! and a renderer scales it into its cells.
+//!
+//! As with links, each row holds the slices of images on it, moved with
+//! their rows as the screen scrolls (see `lineattr::RowTable`). A slice
+//! goes when its cells change, as when text is printed over it, or its
+//! row is erased or scrolled off, and an image once no row shows it. Snapshots end with a table of
+//! the slices shown (see `snapshot`), and diffs say how it changed (see
+//! `diff`); `vtImage` gives an image's pixels, and `vtImageScaled` the
+//! same shrunk to the renderer's cells and premultiplied, ready for an
+//! Android `Bitmap` (see `scale`). An image given cells queues
+//! `VtEvent::ImageAdded` and one no row shows any more `ImageRemoved`, so
+//! a renderer knows when to fetch pixels and when to drop them.
+//!
+//! Images are held within a byte budget (`MAX_STORED`, or the app's limit,
+//! see `limits`). Past it, the least recently placed or fetched image
+//! loses its pixels, but not its cells: it stays in the table as an image
+//! with none, which a renderer draws as a placeholder, so the layout
+//! doesn't shift. `vtMemoryUsage` counts the images held and evicted.
+//!
+//! iTerm2 images are shown only with `inline=1`. `width` and `height` are
+//! in cells, `Npx`, `N%` of the screen or `auto`, the aspect ratio kept
+//! unless `preserveAspectRatio=0`. An image in another format (JPEG, GIF)
+//! still takes the cells its arguments size, but has no pixels.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::events::VtEvent;
+use crate::lineattr::{LineOp, RowTable};
+use crate::scan::Action;
+use crate::snapshot::ImagePlacement;
+use crate::{handles, png, sixel, write_varint, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jint;
+use jni::JNIEnv;
+
+/// Assumed size of a cell, in pixels
+pub const CELL_WIDTH: usize = 10;
+pub const CELL_HEIGHT: usize = 20;
+
+/// Most pixels an image may have, 16 MiB as RGBA
+pub const MAX_PIXELS: usize = 2048 * 2048;
+
+/// Most RGBA bytes held unless the app sets a limit (see `limits`); the
+/// least recently used images go first
+pub const MAX_STORED: usize = 32 * 1024 * 1024;
+
+/// Pixels, 4 bytes each, row by row.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// An image sequence's payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload<'a> {
+    /// The DCS payload, `P1;P2;P3 q data`
+    Sixel(&'a [u8]),
+    /// The OSC payload after `1337;File=`
+    Iterm(&'a [u8]),
+}
+
+/// The image `action` shows, if it's an image sequence.
+pub fn payload<'a>(action: &Action<'a>) -> Option<Payload<'a>> {
+    match *action {
+        Action::Dcs(payload) if crate::events::is_sixel(payload) => Some(Payload::Sixel(payload)),
+        Action::Osc(osc) => osc.strip_prefix(b"1337;File=").map(Payload::Iterm),
+        _ => None,
+    }
+}
+
+/// Cell row `row` of image `id`, in columns `col..col + cols`, and the
+/// chars those held once it was placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slice {
+    id: u32,
+    col: usize,
+    cols: usize,
+    row: usize,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Stored {
+    id: u32,
+    image: Image,
+    /// Size in cells
+    cols: usize,
+    rows: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Images {
+    /// Least recently placed or fetched first
+    stored: Vec<Stored>,
+    rows: RowTable<Vec<Slice>>,
+    last_id: u32,
+    max_bytes: usize,
+    /// Images whose pixels were dropped for the budget
+    evictions: u64,
+    /// `ImageAdded` and `ImageRemoved` not yet taken
+    events: Vec<VtEvent>,
+}
+
+impl Images {
+    pub fn new(rows: usize) -> Self {
+        Images {
+            stored: Vec::new(),
+            rows: RowTable::new(rows),
+            last_id: 0,
+            max_bytes: MAX_STORED,
+            evictions: 0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Drop every image and slice, for a reset. Ids carry on from the last
+    /// so a client can't take a new image for an old one, and no events
+    /// are queued for the images dropped.
+    pub fn clear(&mut self, rows: usize) {
+        self.stored.clear();
+        self.rows = RowTable::new(rows);
+        self.events.clear();
+    }
+
+    /// The events queued since the last call, see the module docs.
+    pub fn take_events(&mut self) -> Vec<VtEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Hold at most `max_bytes` of RGBA from now on, evicting the least
+    /// recently used images past it now. False if none were.
+    pub fn set_max_bytes(&mut self, max_bytes: usize) -> bool {
+        self.max_bytes = max_bytes;
+        self.evict_to(max_bytes)
+    }
+
+    /// RGBA bytes held.
+    pub fn bytes(&self) -> usize {
+        self.stored.iter().map(|s| s.image.rgba.len()).sum()
+    }
+
+    /// Images shown that still have their pixels.
+    pub fn held(&self) -> usize {
+        self.stored.iter().filter(|s| !s.image.rgba.is_empty()).count()
+    }
+
+    /// Images that lost their pixels to the budget, since the VT was
+    /// created.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn resize(&mut self, rows: usize) {
+        self.rows.resize(rows);
+    }
+
+    /// True while any image is held.
+    pub fn is_tracking(&self) -> bool {
+        !self.stored.is_empty()
+    }
+
+    /// Image `id`, if still shown, making it the most recently used; one
+    /// with no pixels if its format isn't decoded or it was evicted.
+    pub fn get(&mut self, id: u32) -> Option<&Image> {
+        let i = self.stored.iter().position(|stored| stored.id == id)?;
+        let stored = self.stored.remove(i);
+        self.stored.push(stored);
+        self.stored.last().map(|stored| &stored.image)
+    }
+
+    /// Image `id` as `get` gives it, scaled to its cells at `cell_width` ×
+    /// `cell_height` pixels each, see `scale`.
+    pub fn get_scaled(&mut self, id: u32, cell_width: usize, cell_height: usize) -> Option<Image> {
+        self.get(id)?;
+        let stored = self.stored.last()?;
+        let width = stored.cols.saturating_mul(cell_width);
+        let height = stored.rows.saturating_mul(cell_height);
+        Some(scale(&stored.image, width, height))
+    }
+
+    /// Decode `payload` and give it the cells from `backend`'s cursor, the
+    /// cursor ending below them. `line_feed` is called with the cursor row
+    /// before each line feed that moves it down, for other row tables to
+    /// follow. False if there's nothing to show.
+    pub fn place(
+        &mut self,
+        payload: Payload,
+        backend: &mut impl TerminalBackend,
+        mut line_feed: impl FnMut(usize),
+    ) -> bool {
+        let (screen_cols, screen_rows) = backend.size();
+        if screen_cols == 0 || screen_rows == 0 {
+            return false;
+        }
+        let col = backend.cursor().col.min(screen_cols.saturating_sub(1));
+        let Some((image, cols, rows)) = decode(payload, screen_cols, screen_rows) else {
+            return false;
+        };
+        let cols = cols.clamp(1, screen_cols - col);
+        let rows = rows.clamp(1, screen_rows);
+
+        self.release_unused();
+        self.last_id = self.last_id.wrapping_add(1).max(1);
+        let id = self.last_id;
+        self.stored.push(Stored {
+            id,
+            image,
+            cols,
+            rows,
+        });
+        self.events.push(VtEvent::ImageAdded { id, cols, rows });
+        self.evict_to(self.max_bytes);
+
+        let erase = format!("\x1b[{}X", cols);
+        for image_row in 0..rows {
+            backend.feed_str(&erase);
+            let row = backend.cursor().row;
+            if let Some(slices) = self.rows.get_mut(row) {
+                slices.retain(|s| s.col + s.cols <= col || s.col >= col + cols);
+                slices.push(Slice {
+                    id,
+                    col,
+                    cols,
+                    row: image_row,
+                    text: String::new(),
+                });
+            }
+            self.rows.apply(LineOp::LineFeed, row);
+            line_feed(row);
+            backend.feed_str("\n");
+        }
+        // What the slices cover once placed, for `prune` to compare with
+        let mut cells = Vec::new();
+        for (row, slices) in self.rows.iter_mut().enumerate() {
+            for slice in slices.iter_mut().filter(|s| s.id == id) {
+                backend.row_cells(row, &mut cells);
+                slice.text = text_of(&cells, slice.col, slice.col + slice.cols);
+            }
+        }
+        true
+    }
+
+    /// Move slices with their rows, see `LineOp`.
+    pub fn apply(&mut self, op: LineOp, cursor_row: usize) {
+        self.rows.apply(op, cursor_row);
+    }
+
+    /// Drop slices whose cells changed, and the images no row shows any
+    /// more.
+    pub fn prune(&mut self, backend: &impl TerminalBackend) {
+        let mut cells = Vec::new();
+        for (row, slices) in self.rows.iter_mut().enumerate() {
+            if slices.is_empty() {
+                continue;
+            }
+            backend.row_cells(row, &mut cells);
+            slices.retain(|s| text_of(&cells, s.col, s.col + s.cols) == s.text);
+        }
+        self.release_unused();
+    }
+
+    /// The slices shown, runs of them on consecutive rows as one
+    /// placement, in the order of their top rows.
+    pub fn table(&self) -> Vec<ImagePlacement> {
+        let mut placements: Vec<ImagePlacement> = Vec::new();
+        for (row, slices) in self.rows.iter().enumerate() {
+            for slice in slices {
+                let Some(stored) = self.stored.iter().find(|stored| stored.id == slice.id) else {
+                    continue;
+                };
+                let continues = placements.iter_mut().find(|p| {
+                    p.id == slice.id
+                        && p.col == slice.col
+                        && p.row + p.rows == row
+                        && p.image_row + p.rows == slice.row
+                });
+                match continues {
+                    Some(placement) => placement.rows += 1,
+                    None => placements.push(ImagePlacement {
+                        id: slice.id,
+                        col: slice.col,
+                        row,
+                        rows: 1,
+                        cols: stored.cols,
+                        image_row: slice.row,
+                        image_rows: stored.rows,
+                    }),
+                }
+            }
+        }
+        placements
+    }
+
+    fn release_unused(&mut self) {
+        let rows = &self.rows;
+        let events = &mut self.events;
+        self.stored.retain(|stored| {
+            let shown = rows.iter().flatten().any(|slice| slice.id == stored.id);
+            if !shown {
+                events.push(VtEvent::ImageRemoved(stored.id));
+            }
+            shown
+        });
+    }
+
+    /// Drop the pixels of the least recently used images until at most
+    /// `max_bytes` are held, keeping their cells as placeholders; an image
+    /// larger than that keeps none. False if none were evicted.
+    pub fn evict_to(&mut self, max_bytes: usize) -> bool {
+        let mut total = self.bytes();
+        let mut evicted = false;
+        for stored in &mut self.stored {
+            if total <= max_bytes {
+                break;
+            }
+            if stored.image.rgba.is_empty() {
+                continue;
+            }
+            total -= stored.image.rgba.len();
+            stored.image = Image::default();
+            self.evictions += 1;
+            evicted = true;
+        }
+        evicted
+    }
+}
+
+/// Chars of `cells` in columns `start..end`.
+fn text_of(cells: &[Cell], start: usize, end: usize) -> String {
+    let end = end.min(cells.len());
+    cells[start.min(end)..end]
+        .iter()
+        .map(|cell| cell.ch)
+        .collect()
+}
+
+/// `payload`'s image and its size in cells, on a screen of `screen_cols` ×
+/// `screen_rows`.
+fn decode(
+    payload: Payload,
+    screen_cols: usize,
+    screen_rows: usize,
+) -> Option<(Image, usize, usize)> {
+    let (image, width, height) = match payload {
+        Payload::Sixel(payload) => {
+            let image = sixel::decode(payload)?;
+            let (width, height) = (image.width, image.height);
+            (image, width, height)
+        }
+        Payload::Iterm(payload) => {
+            let colon = payload.iter().position(|&b| b == b':')?;
+            let mut args = Args::default();
+            for arg in payload[..colon].split(|&b| b == b';') {
+                let text = String::from_utf8_lossy(arg);
+                let Some((key, value)) = text.split_once('=') else {
+                    continue;
+                };
+                match key {
+                    "inline" => args.inline = value == "1",
+                    "width" => args.width = Size::parse(value),
+                    "height" => args.height = Size::parse(value),
+                    "preserveAspectRatio" => args.keep_aspect = value != "0",
+                    _ => {}
+                }
+            }
+            if !args.inline {
+                return None;
+            }
+            let image = png::decode(&base64(&payload[colon + 1..])).unwrap_or_default();
+            let width = args.width.pixels(CELL_WIDTH, screen_cols);
+            let height = args.height.pixels(CELL_HEIGHT, screen_rows);
+            let (width, height) = match (width, height) {
+                (Some(width), None) if args.keep_aspect && image.width > 0 => {
+                    (width, image.height * width / image.width)
+                }
+                (None, Some(height)) if args.keep_aspect && image.height > 0 => {
+                    (image.width * height / image.height, height)
+                }
+                (width, height) => (width.unwrap_or(image.width), height.unwrap_or(image.height)),
+            };
+            (image, width, height)
+        }
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((
+        image,
+        width.div_ceil(CELL_WIDTH),
+        height.div_ceil(CELL_HEIGHT),
+    ))
+}
+
+/// The arguments of an iTerm2 image that size and show it.
+#[derive(Debug)]
+struct Args {
+    inline: bool,
+    width: Size,
+    height: Size,
+    keep_aspect: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            inline: false,
+            width: Size::Auto,
+            height: Size::Auto,
+            keep_aspect: true,
+        }
+    }
+}
+
+/// An iTerm2 `width` or `height`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Auto,
+    Cells(usize),
+    Pixels(usize),
+    Percent(usize),
+}
+
+impl Size {
+    fn parse(value: &str) -> Size {
+        let number = |digits: &str| digits.parse().ok();
+        let size = if let Some(px) = value.strip_suffix("px") {
+            number(px).map(Size::Pixels)
+        } else if let Some(percent) = value.strip_suffix('%') {
+            number(percent).map(Size::Percent)
+        } else {
+            number(value).map(Size::Cells)
+        };
+        size.unwrap_or(Size::Auto)
+    }
+
+    /// Pixels along a side where cells are `cell` pixels and the screen
+    /// `screen` cells; `None` for auto.
+    fn pixels(self, cell: usize, screen: usize) -> Option<usize> {
+        match self {
+            Size::Auto => None,
+            Size::Cells(n) => Some(n.min(screen) * cell),
+            Size::Pixels(n) => Some(n.min(screen * cell)),
+            Size::Percent(n) => Some(screen * cell * n.min(100) / 100),
+        }
+    }
+}
+
+/// Standard base64, skipping padding, whitespace and anything else
+/// outside the alphabet.
+fn base64(text: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for &b in text {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => continue,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    out
+}
+
+/// `image` box-filtered down to `width` × `height` pixels, as
+/// premultiplied RGBA (Android's `RGBA_8888`, which
+/// `Bitmap.copyPixelsFromBuffer` takes). Never scaled up: an axis already
+/// within its size keeps its pixels. Each pixel averages the ones it
+/// covers, weighted by alpha, so transparent pixels don't darken the edges.
+/// An image with no pixels stays so.
+pub fn scale(image: &Image, width: usize, height: usize) -> Image {
+    if image.width == 0 || image.height == 0 {
+        return Image::default();
+    }
+    let width = width.clamp(1, image.width);
+    let height = height.clamp(1, image.height);
+    // Source pixels `start..end` that output pixel `i` of `out` covers
+    let covered = |i: usize, out: usize, of: usize| {
+        let start = i * of / out;
+        start..((i + 1) * of / out).max(start + 1)
+    };
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let rows = covered(y, height, image.height);
+        for x in 0..width {
+            let cols = covered(x, width, image.width);
+            let mut sum = [0u64; 4];
+            for row in rows.clone() {
+                let start = (row * image.width + cols.start) * 4;
+                let end = (row * image.width + cols.end) * 4;
+                for pixel in image.rgba[start..end].chunks_exact(4) {
+                    let alpha = pixel[3] as u64;
+                    for (sum, &value) in sum.iter_mut().zip(&pixel[..3]) {
+                        *sum += value as u64 * alpha;
+                    }
+                    sum[3] += alpha * 255;
+                }
+            }
+            let count = (rows.len() * cols.len()) as u64 * 255;
+            rgba.extend(sum.map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    Image {
+        width,
+        height,
+        rgba,
+    }
+}
+
+/// `width height rgba`, the pixels of an image.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(image.rgba.len() + 8);
+    write_varint(&mut buf, image.width);
+    write_varint(&mut buf, image.height);
+    buf.extend_from_slice(&image.rgba);
+    buf
+}
+
+// JNI functions
+
+/// Image `id` of a snapshot's image table, `encode`d: width and height
+/// both 0 for a format that isn't decoded or an evicted image. Empty for
+/// an invalid handle or an image no longer shown.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtImage<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    id: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        match vt.image(id as u32) {
+            Some(image) => env
+                .byte_array_from_slice(&encode(image))
+                .unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+/// `vtImage` for a renderer with cells of `cell_width` × `cell_height`
+/// pixels: no larger than the image's cells at that size, premultiplied,
+/// see `scale`. Empty as for `vtImage`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtImageScaled<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    id: jint,
+    cell_width: jint,
+    cell_height: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let cell = |size: jint| usize::try_from(size).unwrap_or(0);
+        match vt.image_scaled(id as u32, cell(cell_width), cell(cell_height)) {
+            Some(image) => env
+                .byte_array_from_slice(&encode(&image))
+                .unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn images_take_their_cells_until_printed_over() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        // The 2×2 PNG of the `png` tests, stretched to 3 cells by 1
+        vt.feed(
+            concat!(
+                "ab\x1b]1337;File=inline=1;width=3;height=1:",
+                "iVBORw0KGgoAAAANSUhEUgAAAAIAAAACAgMAAAAAAAAAAAAACVBMVEX/AAAA/wAAAP8AAAAAAAAA",
+                "AXRSTlOAAAAAAAAAAAxJREFUeJxjFGBIAAAAmAByAAAAAAAAAABJRU5EAAAAAA==\x07",
+            )
+            .as_bytes(),
+        );
+        let screen = vt.screen();
+        assert_eq!(
+            screen.images,
+            [ImagePlacement {
+                id: 1,
+                col: 2,
+                row: 0,
+                rows: 1,
+                cols: 3,
+                image_row: 0,
+                image_rows: 1,
+            }]
+        );
+        let image = vt.image(1).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(&image.rgba[..4], [255, 0, 0, 128]);
+        let decoded = crate::snapshot::decode(&screen.encode()).unwrap();
+        assert_eq!(decoded.images, screen.images);
+        let added = VtEvent::ImageAdded {
+            id: 1,
+            cols: 3,
+            rows: 1,
+        };
+        assert_eq!(vt.take_events().last(), Some(&added));
+
+        vt.feed(b"xyz");
+        assert!(vt.screen().images.is_empty());
+        assert!(vt.image(1).is_none());
+        assert_eq!(vt.take_events(), [VtEvent::ImageRemoved(1)]);
+    }
+
+    #[test]
+    fn iterm_images_are_sized_by_their_arguments() {
+        let size = |args: &str| {
+            let payload = format!("{}:AAAA", args);
+            decode(Payload::Iterm(payload.as_bytes()), 80, 24).map(|(_, cols, rows)| (cols, rows))
+        };
+        // Not a PNG, so sized by the arguments alone
+        assert_eq!(size("inline=1;width=10;height=2"), Some((10, 2)));
+        assert_eq!(size("inline=1;width=95px;height=50%"), Some((10, 12)));
+        assert_eq!(size("inline=1;width=200;height=30"), Some((80, 24)));
+        assert_eq!(size("inline=1;width=auto;height=2"), None);
+        assert_eq!(size("width=10;height=2"), None);
+
+        assert_eq!(base64(b"aGVs\nbG8="), b"hello");
+    }
+
+    #[test]
+    fn images_scale_down_to_premultiplied_pixels() {
+        // Opaque red and half-transparent white over two transparent pixels
+        let image = Image {
+            width: 2,
+            height: 2,
+            rgba: [[255, 0, 0, 255], [255, 255, 255, 128], [0; 4], [9, 9, 9, 0]].concat(),
+        };
+        let half = scale(&image, 1, 1);
+        assert_eq!((half.width, half.height), (1, 1));
+        assert_eq!(half.rgba, [96, 32, 32, 96]);
+        // Never up, but premultiplied all the same
+        let same = scale(&image, 10, 10);
+        assert_eq!((same.width, same.height), (2, 2));
+        assert_eq!(same.rgba[4..], [128, 128, 128, 128, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(scale(&image, 2, 1).rgba, [128, 0, 0, 128, 64, 64, 64, 64]);
+        assert_eq!(scale(&Image::default(), 1, 1), Image::default());
+
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.feed(
+            concat!(
+                "\x1b]1337;File=inline=1;width=1;height=1:",
+                "iVBORw0KGgoAAAANSUhEUgAAAAIAAAACAgMAAAAAAAAAAAAACVBMVEX/AAAA/wAAAP8AAAAAAAAA",
+                "AXRSTlOAAAAAAAAAAAxJREFUeJxjFGBIAAAAmAByAAAAAAAAAABJRU5EAAAAAA==\x07",
+            )
+            .as_bytes(),
+        );
+        let scaled = vt.image_scaled(1, 1, 2).unwrap();
+        assert_eq!((scaled.width, scaled.height), (1, 2));
+        assert_eq!(vt.image_scaled(1, 0, 0).unwrap().rgba.len(), 4);
+        assert_eq!(vt.image_scaled(2, 1, 1), None);
+    }
+
+    #[test]
+    fn least_recently_used_images_become_placeholders() {
+        let mut vt = AvtState::with_backend(fake(20, 4));
+        let image = concat!(
+            "\x1b]1337;File=inline=1;width=3;height=1:",
+            "iVBORw0KGgoAAAANSUhEUgAAAAIAAAACAgMAAAAAAAAAAAAACVBMVEX/AAAA/wAAAP8AAAAAAAAA",
+            "AXRSTlOAAAAAAAAAAAxJREFUeJxjFGBIAAAAmAByAAAAAAAAAABJRU5EAAAAAA==\x07",
+        );
+        vt.feed(format!("ab{}   {}", image, image).as_bytes());
+        // Fetched, so the newer image is now the least recently used
+        assert!(vt.image(1).is_some());
+
+        vt.set_limits(crate::limits::Limits {
+            max_image_bytes: Some(16),
+            ..vt.limits()
+        });
+        assert_eq!(vt.image(1).unwrap().rgba.len(), 16);
+        assert_eq!(vt.image(2), Some(&Image::default()));
+        // Still in its cells
+        assert_eq!(vt.screen().images.len(), 2);
+        let usage = vt.memory_usage();
+        assert_eq!((usage.images, usage.images_held, usage.images_evicted), (16, 1, 1));
+    }
+}
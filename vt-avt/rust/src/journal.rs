@@ -0,0 +1,401 @@
+//! Crash-resilient journal of an interactive session's output.
+//!
+//! The output of a local or SSH session lives only in its VT, so a native
+//! crash or the app being killed loses it. A journal also keeps it in a
+//! ring file of fixed size, and `recover` rebuilds the VT from the file
+//! after a restart.
+//!
+//! ```text
+//! journal := magic:[u8; 8] capacity:u64le checkpoint:u64le end:u64le
+//!            ring:[u8; capacity]
+//! record  := kind:u8 len:u32le payload:[u8; len]
+//! ```
+//!
+//! Records are at offsets that grow for the life of the journal, each
+//! stored from `offset % capacity` of the ring and wrapping around its
+//! end. `end` is the offset after the last record and `checkpoint` the
+//! offset of the newest `KIND_CHECKPOINT` record, whose payload is
+//! `cols:u32le rows:u32le` and a `dump_ansi` of the VT. Recovery restores
+//! that and replays the `KIND_OUTPUT` records after it, up to `end`; the
+//! output before it may still be in the ring, but where its records start
+//! isn't known.
+//!
+//! A checkpoint is written when the VT changes size, once a quarter of the
+//! ring has been used since the last one and the VT is between sequences
+//! (a dump can't capture a half-fed one), and in place of output that
+//! would use more than half. Output records take at most an eighth of the
+//! ring, longer output being split, and a checkpoint at most a quarter, so
+//! the newest checkpoint is never overwritten before the next is written;
+//! a screen whose dump is larger is an error.
+//!
+//! The header is rewritten with a single write after each record, so a
+//! process that dies mid-record leaves a journal that ends at the record
+//! before. Nothing is synced to disk: what was written survives the
+//! process, which is what the journal is for, but not a power loss.
+
+use crate::backend::TerminalBackend;
+use crate::handles::{self, Kind};
+use crate::{AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const MAGIC: &[u8; 8] = b"AVTJRNL1";
+pub const HEADER_LEN: u64 = 32;
+
+/// Smallest ring `Journal::create` accepts
+pub const MIN_CAPACITY: u64 = 4096;
+
+pub const KIND_OUTPUT: u8 = 1;
+pub const KIND_CHECKPOINT: u8 = 2;
+
+const RECORD_HEADER: u64 = 5;
+
+pub struct Journal<F> {
+    file: F,
+    capacity: u64,
+    checkpoint: u64,
+    end: u64,
+    /// VT size as of the newest checkpoint
+    size: (usize, usize),
+}
+
+impl<F: Write + Seek> Journal<F> {
+    /// Start a journal in `file` with a ring of `capacity` bytes, from a
+    /// checkpoint of `state`. Whatever `file` held is overwritten.
+    pub fn create<B: TerminalBackend>(
+        file: F,
+        capacity: u64,
+        state: &AvtState<B>,
+    ) -> io::Result<Self> {
+        if capacity < MIN_CAPACITY {
+            return Err(invalid_input("journal capacity too small"));
+        }
+        let mut journal = Journal {
+            file,
+            capacity,
+            checkpoint: 0,
+            end: 0,
+            size: state.backend().size(),
+        };
+        journal.file.seek(SeekFrom::Start(0))?;
+        journal.file.write_all(MAGIC)?;
+        journal.file.write_all(&capacity.to_le_bytes())?;
+        journal.write_checkpoint(state)?;
+        Ok(journal)
+    }
+
+    /// Journal `bytes` of output, which `state` has just been fed.
+    pub fn output<B: TerminalBackend>(
+        &mut self,
+        bytes: &[u8],
+        state: &AvtState<B>,
+    ) -> io::Result<()> {
+        if state.backend().size() != self.size {
+            // The dump already shows the output
+            return self.write_checkpoint(state);
+        }
+        let max = (self.capacity / 8 - RECORD_HEADER) as usize;
+        for chunk in bytes.chunks(max) {
+            let used = self.end + RECORD_HEADER + chunk.len() as u64 - self.checkpoint;
+            if used > self.capacity / 2 {
+                // The dump shows the rest of the output too
+                return self.write_checkpoint(state);
+            }
+            self.append(KIND_OUTPUT, &[chunk])?;
+        }
+
+        if self.end - self.checkpoint > self.capacity / 4 && state.is_at_boundary() {
+            self.write_checkpoint(state)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+
+    fn write_checkpoint<B: TerminalBackend>(&mut self, state: &AvtState<B>) -> io::Result<()> {
+        let (cols, rows) = state.backend().size();
+        let dump = state.dump_ansi();
+        if RECORD_HEADER + 8 + dump.len() as u64 > self.capacity / 4 {
+            return Err(invalid_input("screen too large for the journal"));
+        }
+        let size = [cols as u32, rows as u32].map(u32::to_le_bytes).concat();
+        let start = self.end;
+        self.append(KIND_CHECKPOINT, &[&size, dump.as_bytes()])?;
+        self.checkpoint = start;
+        self.size = (cols, rows);
+        self.write_header()
+    }
+
+    /// Write a record at `end` and move `end` past it.
+    fn append(&mut self, kind: u8, parts: &[&[u8]]) -> io::Result<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let mut record = Vec::with_capacity(RECORD_HEADER as usize + len);
+        record.push(kind);
+        record.extend_from_slice(&(len as u32).to_le_bytes());
+        for part in parts {
+            record.extend_from_slice(part);
+        }
+
+        let mut at = self.end % self.capacity;
+        let mut rest = &record[..];
+        while !rest.is_empty() {
+            let n = rest.len().min((self.capacity - at) as usize);
+            self.file.seek(SeekFrom::Start(HEADER_LEN + at))?;
+            self.file.write_all(&rest[..n])?;
+            rest = &rest[n..];
+            at = 0;
+        }
+        self.end += record.len() as u64;
+        if kind == KIND_OUTPUT {
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut fields = [0; 16];
+        fields[..8].copy_from_slice(&self.checkpoint.to_le_bytes());
+        fields[8..].copy_from_slice(&self.end.to_le_bytes());
+        self.file.seek(SeekFrom::Start(16))?;
+        self.file.write_all(&fields)
+    }
+}
+
+/// Rebuild `vt` from the journal in `file`: the newest checkpoint and the
+/// output since. `vt` is reset to the checkpoint's size first.
+pub fn recover<B: TerminalBackend>(
+    file: &mut (impl Read + Seek),
+    vt: &mut AvtState<B>,
+) -> io::Result<()> {
+    let mut header = [0; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    let field = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+    let (capacity, checkpoint, end) = (field(8), field(16), field(24));
+    if &header[..8] != MAGIC || capacity < MIN_CAPACITY || end < checkpoint {
+        return Err(invalid_data("not a journal"));
+    }
+    if end - checkpoint > capacity {
+        return Err(invalid_data("journal overwrote its checkpoint"));
+    }
+
+    let mut ring = Ring { file, capacity };
+    let mut offset = checkpoint;
+    let mut first = true;
+    while offset < end {
+        let record = ring.read(offset, RECORD_HEADER as usize)?;
+        let len = u32::from_le_bytes(record[1..].try_into().unwrap()) as u64;
+        if offset + RECORD_HEADER + len > end {
+            return Err(invalid_data("record runs past the end of the journal"));
+        }
+        let payload = ring.read(offset + RECORD_HEADER, len as usize)?;
+        match (record[0], first) {
+            (KIND_CHECKPOINT, true) if payload.len() >= 8 => {
+                let cols = u32::from_le_bytes(payload[..4].try_into().unwrap());
+                let rows = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+                vt.restore(cols as usize, rows as usize, &payload[8..]);
+            }
+            (KIND_OUTPUT, false) => vt.feed(&payload),
+            _ => return Err(invalid_data("unexpected journal record")),
+        }
+        offset += RECORD_HEADER + len;
+        first = false;
+    }
+    if first {
+        return Err(invalid_data("journal has no checkpoint"));
+    }
+    Ok(())
+}
+
+struct Ring<'a, R> {
+    file: &'a mut R,
+    capacity: u64,
+}
+
+impl<R: Read + Seek> Ring<'_, R> {
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut out = vec![0; len];
+        let mut at = offset % self.capacity;
+        let mut filled = 0;
+        while filled < len {
+            let n = (len - filled).min((self.capacity - at) as usize);
+            self.file.seek(SeekFrom::Start(HEADER_LEN + at))?;
+            self.file.read_exact(&mut out[filled..filled + n])?;
+            filled += n;
+            at = 0;
+        }
+        Ok(out)
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) type FileJournal = Journal<std::fs::File>;
+
+registered!(FileJournal, Kind::Journal);
+
+// JNI functions
+
+/// Start journaling the VT `vt_handle` to the file `fd`, which the journal
+/// takes ownership of (and which is closed on failure), with a ring of
+/// `capacity` bytes. Returns a journal handle for `journalFeed`, or 0 for
+/// an invalid VT, a capacity below `MIN_CAPACITY` or a failed write.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_journalOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+    capacity: jlong,
+    vt_handle: VtHandle,
+) -> jlong {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let Some(vt) = handles::get(&mut env, vt_handle) else {
+            return 0;
+        };
+
+        match Journal::create(file, capacity.max(0) as u64, vt) {
+            Ok(journal) => handles::add(journal),
+            Err(_) => 0,
+        }
+    })
+}
+
+/// Feed `bytes` to the VT `vt_handle` and journal them. The VT is fed
+/// even if the write fails, which returns false.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_journalFeed(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    vt_handle: VtHandle,
+    bytes: JByteArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(journal) = handles::object::<FileJournal>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, vt_handle) else {
+            return JNI_FALSE;
+        };
+
+        vt.feed(&bytes);
+        match journal.output(&bytes, vt) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// Close the journal's file and free the handle. The file is left as is,
+/// for `sessionRecover`; delete it after a clean exit.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_journalClose(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<FileJournal>(&mut env, handle));
+    })
+}
+
+/// Rebuild the VT `vt_handle` from the journal in `fd`, see `recover`.
+/// `fd` stays open. Returns false for an invalid handle or fd, or a file
+/// that isn't a readable journal; the VT may then be partly rebuilt.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sessionRecover(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+    vt_handle: VtHandle,
+) -> jboolean {
+    use std::fs::File;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return JNI_FALSE;
+        }
+        let Some(vt) = handles::get(&mut env, vt_handle) else {
+            return JNI_FALSE;
+        };
+
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        match recover(&mut *file, vt) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use std::io::Cursor;
+
+    #[test]
+    fn recovery_replays_the_tail_after_the_ring_wraps() {
+        let mut live = AvtState::with_backend(fake(40, 2));
+        let mut journal = Journal::create(Cursor::new(Vec::new()), MIN_CAPACITY, &live).unwrap();
+        for i in 0..2000 {
+            let bytes = if i % 500 == 0 { "\x1b[2J" } else { "ab" };
+            live.feed(bytes.as_bytes());
+            journal.output(bytes.as_bytes(), &live).unwrap();
+        }
+        // An output larger than a record is split
+        let long = "x".repeat(MIN_CAPACITY as usize / 4);
+        live.feed(long.as_bytes());
+        journal.output(long.as_bytes(), &live).unwrap();
+        assert!(journal.end > 3 * MIN_CAPACITY);
+
+        let mut file = journal.into_inner();
+        let mut recovered = AvtState::with_backend(fake(4, 4));
+        recover(&mut file, &mut recovered).unwrap();
+        assert_eq!(recovered.backend().size(), (40, 2));
+        assert_eq!(recovered.backend().row_text(0), live.backend().row_text(0));
+    }
+
+    #[test]
+    fn recovery_rejects_what_it_cannot_trust() {
+        let live = AvtState::with_backend(fake(8, 2));
+        let mut vt = AvtState::with_backend(fake(8, 2));
+        assert!(Journal::create(Cursor::new(Vec::new()), 1024, &live).is_err());
+
+        let journal = Journal::create(Cursor::new(Vec::new()), MIN_CAPACITY, &live).unwrap();
+        let bytes = journal.into_inner().into_inner();
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert!(recover(&mut Cursor::new(corrupt), &mut vt).is_err());
+        // `end` past the records written
+        let mut corrupt = bytes.clone();
+        corrupt[24] += 1;
+        assert!(recover(&mut Cursor::new(corrupt), &mut vt).is_err());
+        assert!(recover(&mut Cursor::new(&bytes[..20]), &mut vt).is_err());
+        assert!(recover(&mut Cursor::new(bytes), &mut vt).is_ok());
+    }
+}
@@ -1,114 +1,164 @@
 use jni::JNIEnv;
-use jni::objects::{JClass, JByteArray};
-use jni::sys::{jlong, jint};
-use std::collections::HashSet;
-use avt::{Vt, Pen};
-
-/// Wrapper around avt::Vt with dirty tracking
-struct AvtState {
-    vt: Vt,
-    dirty_lines: HashSet<usize>,
-    cursor_changed: bool,
-    resized: bool,
-}
-
-impl AvtState {
-    fn new(cols: usize, rows: usize) -> Self {
-        AvtState {
-            vt: Vt::builder()
-                .size(cols, rows)
-                .build(),
-            dirty_lines: (0..rows).collect(),
-            cursor_changed: true,
-            resized: false,
-        }
-    }
-
-    fn reset(&mut self, cols: usize, rows: usize) {
-        self.vt = Vt::builder()
-            .size(cols, rows)
-            .build();
-        self.dirty_lines = (0..rows).collect();
-        self.cursor_changed = true;
-        self.resized = true;
-    }
+use jni::objects::{JClass, JByteArray, JDoubleArray, JIntArray, JLongArray, JString};
+use jni::sys::{jboolean, jdouble, jfloat, jint, jlong, JNI_FALSE, JNI_TRUE};
+use config::{Capability, TermConfig};
+use quirks::Profile;
 
-    fn resize(&mut self, cols: usize, rows: usize) {
-        self.vt.feed_str(&format!("\x1b[8;{};{}t", rows, cols));
-        self.dirty_lines = (0..rows).collect();
-        self.cursor_changed = true;
-        self.resized = true;
-    }
+// First, so `jni_guard!` and `registered!` are in scope in the modules
+// below
+#[macro_use]
+mod guard;
+#[macro_use]
+pub mod handles;
 
-    fn feed(&mut self, bytes: &[u8]) {
-        // Mark all lines as potentially dirty for simplicity
-        // A more optimized version would track actual changes
-        for row in 0..self.vt.lines().count().min(self.vt.size().1) {
-            self.dirty_lines.insert(row);
-        }
-
-        for &byte in bytes {
-            self.vt.feed(byte as char);
-        }
-
-        self.cursor_changed = true;
-    }
+/// In `alloc-stats` builds, count allocations against
+/// `alloc_stats::Subsystem::$name` until the end of the enclosing block.
+macro_rules! alloc_scope {
+    ($name:ident) => {
+        #[cfg(feature = "alloc-stats")]
+        let _scope = $crate::alloc_stats::enter($crate::alloc_stats::Subsystem::$name);
+    };
+}
 
-    fn encode_snapshot(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        let size = self.vt.size();
+pub mod activity;
+pub mod alis;
+pub mod analytics;
+pub mod avt_state;
+#[cfg(feature = "alloc-stats")]
+pub mod alloc_stats;
+pub mod backend;
+pub mod batch;
+pub mod burnin;
+pub mod capi;
+pub mod cast;
+pub mod castfile;
+pub mod checkpoint;
+pub mod config;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+pub mod delta;
+pub mod diff;
+#[cfg(feature = "differential")]
+pub mod differential;
+pub mod direct;
+pub mod edit;
+#[cfg(feature = "signing")]
+pub mod ed25519;
+pub mod edl;
+pub mod epoch;
+pub mod events;
+pub mod export;
+pub mod fanout;
+pub mod ffi;
+pub mod framehash;
+#[cfg(feature = "compression")]
+pub mod gzip;
+#[cfg(feature = "renderer")]
+pub mod gif;
+pub mod highlights;
+#[cfg(feature = "images")]
+pub mod images;
+pub mod journal;
+pub mod json;
+#[cfg(feature = "library")]
+pub mod library;
+pub mod lineattr;
+pub mod limits;
+pub mod links;
+pub mod modes;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod palette;
+pub mod perf;
+pub mod panes;
+pub mod persist;
+pub mod player;
+#[cfg(feature = "images")]
+pub mod png;
+pub mod pool;
+pub mod poster;
+pub mod predict;
+pub mod protocol;
+pub mod quirks;
+pub mod record;
+#[cfg(feature = "renderer")]
+pub mod render;
+pub mod sampling;
+pub mod scan;
+pub mod schema;
+pub mod scratch;
+pub mod scrollback;
+pub mod search;
+pub mod selection;
+pub mod sequences;
+pub mod shell;
+#[cfg(feature = "signing")]
+pub mod signed;
+pub mod similarity;
+#[cfg(feature = "images")]
+pub mod sixel;
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "ssh")]
+pub mod ssh;
+pub mod state;
+pub mod stream;
+pub mod sync;
+pub mod stalls;
+pub mod stress;
+pub mod styles;
+#[cfg(feature = "exporters")]
+pub mod svg;
+pub mod text;
+pub mod themes;
+pub mod throttle;
+pub mod traffic;
+pub mod viewsync;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "exporters")]
+pub mod transcript;
+pub mod watch;
+pub mod worker;
+pub mod width;
+pub mod xterm;
+#[cfg(feature = "compression")]
+pub mod zstd;
 
-        // Write size
-        write_varint(&mut buf, size.0);
-        write_varint(&mut buf, size.1);
+pub use avt_state::{AvtState, CursorPolicy, VtMode};
 
-        // Write cursor
-        let cursor = self.vt.cursor();
-        write_varint(&mut buf, cursor.col);
-        write_varint(&mut buf, cursor.row);
-        buf.push(if cursor.visible { 1 } else { 0 });
+// `std::time::Instant::now` panics on wasm32-unknown-unknown; `web-time`'s
+// reads `performance.now()` there and is std's everywhere else
+#[cfg(not(feature = "wasm"))]
+pub(crate) use std::time::Instant;
+#[cfg(feature = "wasm")]
+pub(crate) use web_time::Instant;
+pub(crate) use avt_state::decode_utf8;
 
-        // Encode lines
-        for line in self.vt.lines().take(size.1) {
-            encode_line(&mut buf, line);
-        }
+// Helper functions for encoding
+/// Shrink `buf` to its length, returning the bytes freed.
+fn shrink<T>(buf: &mut Vec<T>) -> usize {
+    let before = buf.capacity();
+    buf.shrink_to_fit();
+    (before - buf.capacity()) * std::mem::size_of::<T>()
+}
 
-        buf
+/// Lowercase hex of `bytes`, as hashes are shown.
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0x0f) as usize] as char);
     }
+    out
+}
 
-    fn poll_diff(&mut self) -> Option<Vec<u8>> {
-        if self.dirty_lines.is_empty() && !self.cursor_changed && !self.resized {
-            return None;
-        }
-
-        let mut buf = Vec::new();
-        buf.push(1); // has diff
-
-        // Write dirty line count and indices
-        write_varint(&mut buf, self.dirty_lines.len());
-        let mut sorted: Vec<_> = self.dirty_lines.iter().copied().collect();
-        sorted.sort_unstable();
-        for idx in sorted {
-            write_varint(&mut buf, idx);
-        }
-
-        // Write cursor changed flag
-        buf.push(if self.cursor_changed { 1 } else { 0 });
-
-        // Write resize flag
-        buf.push(if self.resized { 1 } else { 0 });
-
-        // Clear dirty state
-        self.dirty_lines.clear();
-        self.cursor_changed = false;
-        self.resized = false;
-
-        Some(buf)
-    }
+fn write_varint(buf: &mut Vec<u8>, value: usize) {
+    write_varint_u64(buf, value as u64);
 }
 
-// Helper functions for encoding
-fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+fn write_varint_u64(buf: &mut Vec<u8>, mut value: u64) {
     loop {
         let mut byte = (value & 0x7F) as u8;
         value >>= 7;
@@ -122,173 +172,453 @@ fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
     }
 }
 
-fn encode_line(buf: &mut Vec<u8>, line: &avt::Line) {
-    let cells = line.cells();
-    let mut runs = Vec::new();
-    let mut current_pen: Option<Pen> = None;
-    let mut current_text = String::new();
-    let mut current_start = 0;
+#[cfg(test)]
+mod binding_tests;
+#[cfg(test)]
+mod capi_tests;
+#[cfg(test)]
+mod conformance_tests;
+#[cfg(test)]
+mod format_tests;
+#[cfg(test)]
+mod golden_tests;
+#[cfg(test)]
+mod proptests;
+#[cfg(all(test, feature = "renderer"))]
+mod render_tests;
+#[cfg(test)]
+mod schema_tests;
+#[cfg(test)]
+mod session_tests;
+#[cfg(test)]
+mod size_tests;
 
-    for (col, cell) in cells.iter().enumerate() {
-        if current_pen.as_ref() != Some(cell.pen()) {
-            if !current_text.is_empty() {
-                runs.push((current_start, current_text.clone(), current_pen.unwrap()));
-                current_text.clear();
-            }
-            current_pen = Some(*cell.pen());
-            current_start = col;
-        }
-        current_text.push(cell.char());
-    }
+// JNI functions
 
-    if !current_text.is_empty() {
-        runs.push((current_start, current_text, current_pen.unwrap()));
-    }
+type VtHandle = jlong;
 
-    // Write run count
-    write_varint(buf, runs.len());
+/// Copy `values` into a new Java int array; empty (null) on failure.
+fn int_array<'a>(env: &JNIEnv<'a>, values: &[jint]) -> JIntArray<'a> {
+    let Ok(array) = env.new_int_array(values.len() as i32) else {
+        return JIntArray::default();
+    };
+    match env.set_int_array_region(&array, 0, values) {
+        Ok(()) => array,
+        Err(_) => JIntArray::default(),
+    }
+}
 
-    // Write each run
-    for (col_start, text, pen) in runs {
-        write_varint(buf, col_start);
-        write_varint(buf, text.len());
-        encode_pen(buf, pen);
-        buf.extend_from_slice(text.as_bytes());
+fn long_array<'a>(env: &JNIEnv<'a>, values: &[jlong]) -> JLongArray<'a> {
+    let Ok(array) = env.new_long_array(values.len() as i32) else {
+        return JLongArray::default();
+    };
+    match env.set_long_array_region(&array, 0, values) {
+        Ok(()) => array,
+        Err(_) => JLongArray::default(),
     }
 }
 
-fn encode_pen(buf: &mut Vec<u8>, pen: Pen) {
-    // Encode foreground color
-    match pen.foreground() {
-        Some(avt::Color::Indexed(idx)) => {
-            buf.push(0);
-            buf.push(idx);
-        }
-        Some(avt::Color::RGB(rgb)) => {
-            buf.push(1);
-            buf.push(rgb.r);
-            buf.push(rgb.g);
-            buf.push(rgb.b);
-        }
-        None => {
-            buf.push(2);
-        }
+fn double_array<'a>(env: &JNIEnv<'a>, values: &[jdouble]) -> JDoubleArray<'a> {
+    let Ok(array) = env.new_double_array(values.len() as i32) else {
+        return JDoubleArray::default();
+    };
+    match env.set_double_array_region(&array, 0, values) {
+        Ok(()) => array,
+        Err(_) => JDoubleArray::default(),
     }
+}
 
-    // Encode background color
-    match pen.background() {
-        Some(avt::Color::Indexed(idx)) => {
-            buf.push(0);
-            buf.push(idx);
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+) -> VtHandle {
+    jni_guard!(env, {
+        let vt = Box::new(AvtState::new(cols as usize, rows as usize));
+        handles::insert(vt)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNewWithMode(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+    mode: jint,
+) -> VtHandle {
+    jni_guard!(env, {
+        let mode = VtMode::from_code(mode).unwrap_or_default();
+        let vt = Box::new(AvtState::with_mode(cols as usize, rows as usize, mode));
+        handles::insert(vt)
+    })
+}
+
+/// Like `vtNewWithMode`, for an interactive session: queries in fed output
+/// are answered from the given capabilities, see `config`. A null
+/// `color_term` leaves `COLORTERM` unset.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNewWithConfig(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+    mode: jint,
+    term: JString,
+    color_term: JString,
+    locale: JString,
+) -> VtHandle {
+    jni_guard!(env, {
+        let (term, locale): (String, String) =
+            match (env.get_string(&term), env.get_string(&locale)) {
+                (Ok(term), Ok(locale)) => (term.into(), locale.into()),
+                _ => return 0,
+            };
+        let colorterm = env.get_string(&color_term).ok().map(String::from);
+
+        let mode = VtMode::from_code(mode).unwrap_or_default();
+        let mut vt = Box::new(AvtState::with_mode(cols as usize, rows as usize, mode));
+        vt.set_config(Some(TermConfig {
+            term,
+            colorterm,
+            locale,
+            ..TermConfig::default()
+        }));
+        handles::insert(vt)
+    })
+}
+
+/// Replace the DA1 and DA2 reply parameters. False if the handle has no
+/// config.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetDeviceAttributes(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    da1: JIntArray,
+    da2: JIntArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let mut params = [Vec::new(), Vec::new()];
+        for (array, params) in [&da1, &da2].into_iter().zip(&mut params) {
+            let mut buf = vec![0; env.get_array_length(array).unwrap_or(0) as usize];
+            if env.get_int_array_region(array, 0, &mut buf).is_err() {
+                return JNI_FALSE;
+            }
+            *params = buf.iter().map(|&p| p.clamp(0, u16::MAX as jint) as u16).collect();
         }
-        Some(avt::Color::RGB(rgb)) => {
-            buf.push(1);
-            buf.push(rgb.r);
-            buf.push(rgb.g);
-            buf.push(rgb.b);
+
+        let Some(config) = vt.config_mut() else {
+            return JNI_FALSE;
+        };
+        let [da1, da2] = params;
+        config.da1 = da1;
+        config.da2 = da2;
+        JNI_TRUE
+    })
+}
+
+/// Override the XTGETTCAP answer for terminfo capability `name`: a value,
+/// a set flag (null `value`), or not found (`present` false). False if the
+/// handle has no config.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetCapability(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    name: JString,
+    value: JString,
+    present: jboolean,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let name: String = match env.get_string(&name) {
+            Ok(s) => s.into(),
+            Err(_) => return JNI_FALSE,
+        };
+        let capability = if present == JNI_FALSE {
+            Capability::Absent
+        } else {
+            match env.get_string(&value) {
+                Ok(value) => Capability::Value(value.into()),
+                Err(_) => Capability::Flag,
+            }
+        };
+
+        match vt.config_mut() {
+            Some(config) => {
+                config.capabilities.insert(name, capability);
+                JNI_TRUE
+            }
+            None => JNI_FALSE,
         }
-        None => {
-            buf.push(2);
+    })
+}
+
+/// Answer ENQ with `answerback`, or nothing when empty. False if the
+/// handle has no config or the string is invalid.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetAnswerback(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    answerback: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let answerback: String = match env.get_string(&answerback) {
+            Ok(s) => s.into(),
+            Err(_) => return JNI_FALSE,
+        };
+        match vt.config_mut() {
+            Some(config) => {
+                config.answerback = answerback;
+                JNI_TRUE
+            }
+            None => JNI_FALSE,
         }
-    }
+    })
+}
+
+/// Emulate a recording terminal's quirks: 0 xterm (the default), 1 Linux
+/// console, 2 tmux. False for an unknown profile.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetQuirkProfile(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    profile: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
 
-    // Encode attributes
-    let mut attrs = 0u8;
-    if pen.is_bold() { attrs |= 0x01; }
-    if pen.is_italic() { attrs |= 0x02; }
-    if pen.is_underline() { attrs |= 0x04; }
-    if pen.is_strikethrough() { attrs |= 0x08; }
-    if pen.is_blink() { attrs |= 0x10; }
-    if pen.is_inverse() { attrs |= 0x20; }
-    buf.push(attrs);
+        let Some(profile) = Profile::from_code(profile) else {
+            return JNI_FALSE;
+        };
+        vt.set_quirks(profile.quirks());
+        JNI_TRUE
+    })
 }
 
-// JNI functions
+/// Cursor visibility during playback: 0 as recorded (the default), 1
+/// shown until the recording shows it, 2 always shown. False for an
+/// unknown policy.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetCursorPolicy(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    policy: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
 
-type VtHandle = jlong;
+        let Some(policy) = CursorPolicy::from_code(policy) else {
+            return JNI_FALSE;
+        };
+        vt.set_cursor_policy(policy);
+        JNI_TRUE
+    })
+}
 
+/// Queue the recording's input events during playback, see
+/// `AvtState::set_input_events`.
 #[no_mangle]
-pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNew(
-    _env: JNIEnv,
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetInputEvents(
+    mut env: JNIEnv,
     _class: JClass,
-    cols: jint,
-    rows: jint,
-) -> VtHandle {
-    let vt = Box::new(AvtState::new(cols as usize, rows as usize));
-    Box::into_raw(vt) as jlong
+    handle: VtHandle,
+    on: jboolean,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_input_events(on != JNI_FALSE);
+    })
+}
+
+/// Replies to queries fed since the last call (empty without a config).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtTakeResponses<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&vt.take_responses()).unwrap_or_default()
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFree(
-    _env: JNIEnv,
+    mut env: JNIEnv,
     _class: JClass,
     handle: VtHandle,
 ) {
-    if handle == 0 {
-        return;
-    }
-
-    unsafe {
-        let _ = Box::from_raw(handle as *mut AvtState);
-    }
+    jni_guard!(env, {
+        handles::remove(&mut env, handle);
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtReset(
-    _env: JNIEnv,
+    mut env: JNIEnv,
     _class: JClass,
     handle: VtHandle,
     cols: jint,
     rows: jint,
 ) {
-    if handle == 0 {
-        return;
-    }
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
 
-    unsafe {
-        let vt = &mut *(handle as *mut AvtState);
         vt.reset(cols as usize, rows as usize);
-    }
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtResize(
-    _env: JNIEnv,
+    mut env: JNIEnv,
     _class: JClass,
     handle: VtHandle,
     cols: jint,
     rows: jint,
+    reflow: jboolean,
 ) {
-    if handle == 0 {
-        return;
-    }
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
 
-    unsafe {
-        let vt = &mut *(handle as *mut AvtState);
-        vt.resize(cols as usize, rows as usize);
-    }
+        vt.resize_with(cols as usize, rows as usize, reflow != 0);
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFeed(
-    env: JNIEnv,
+    mut env: JNIEnv,
     _class: JClass,
     handle: VtHandle,
     byte_array: JByteArray,
 ) {
-    if handle == 0 {
-        return;
-    }
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
 
-    let bytes = match env.convert_byte_array(byte_array) {
-        Ok(b) => b,
-        Err(_) => return,
-    };
+        let bytes = match env.convert_byte_array(byte_array) {
+            Ok(b) => b,
+            Err(_) => return,
+        };
 
-    unsafe {
-        let vt = &mut *(handle as *mut AvtState);
         vt.feed(&bytes);
-    }
+    })
+}
+
+/// Show typed input ahead of the program's echo, see `predict`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPredictInput(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    input: JByteArray,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let Ok(input) = env.convert_byte_array(input) else {
+            return;
+        };
+        vt.predict_input(&input);
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtClearPredictions(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.clear_predictions();
+    })
+}
+
+/// `invalidate` of `row_count` rows from `first_row`, or of every row from
+/// `first_row` for a negative `row_count`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtInvalidate(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    first_row: jint,
+    row_count: jint,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let first = first_row.max(0) as usize;
+        let end = if row_count < 0 {
+            usize::MAX
+        } else {
+            first.saturating_add(row_count as usize)
+        };
+        vt.invalidate(Some(first..end));
+    })
+}
+
+/// `vtFeed`, echoing `trace_id` in the diff that first reports the batch.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFeedTraced(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    byte_array: JByteArray,
+    trace_id: jlong,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let bytes = match env.convert_byte_array(byte_array) {
+            Ok(b) => b,
+            Err(_) => return,
+        };
+
+        vt.feed_traced(&bytes, trace_id as u64);
+    })
 }
 
 #[no_mangle]
@@ -297,18 +627,71 @@ pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSnapshot<'
     _class: JClass<'a>,
     handle: VtHandle,
 ) -> JByteArray<'a> {
-    if handle == 0 {
-        return JByteArray::default();
-    }
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
 
-    unsafe {
-        let vt = &*(handle as *const AvtState);
         let snapshot_bytes = vt.encode_snapshot();
-        match env.byte_array_from_slice(&snapshot_bytes) {
-            Ok(arr) => arr,
-            Err(_) => JByteArray::default(),
-        }
-    }
+        env.byte_array_from_slice(&snapshot_bytes).unwrap_or_default()
+    })
+}
+
+/// As `vtSnapshot`, with styles as ids; starts a new style epoch.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSnapshotInterned<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let snapshot_bytes = vt.encode_snapshot_interned();
+        env.byte_array_from_slice(&snapshot_bytes).unwrap_or_default()
+    })
+}
+
+/// As `vtSnapshotInterned`, with only rows `first_row..first_row +
+/// row_count` (the region form in `styles`); starts a new style epoch.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSnapshotRegion<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    first_row: jint,
+    row_count: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let region_bytes =
+            vt.encode_snapshot_region(first_row.max(0) as usize, row_count.max(0) as usize);
+        env.byte_array_from_slice(&region_bytes).unwrap_or_default()
+    })
+}
+
+/// Returns a `delta` payload: rows changed since the delta numbered
+/// `baseline_seq`, or every row if that is unknown.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSnapshotDelta<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    baseline_seq: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let delta = vt.snapshot_delta(baseline_seq.max(0) as u64);
+        env.byte_array_from_slice(&delta).unwrap_or_default()
+    })
 }
 
 #[no_mangle]
@@ -317,19 +700,415 @@ pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollDiff<'
     _class: JClass<'a>,
     handle: VtHandle,
 ) -> JByteArray<'a> {
-    if handle == 0 {
-        return JByteArray::default();
-    }
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
 
-    unsafe {
-        let vt = &mut *(handle as *mut AvtState);
         if let Some(diff_bytes) = vt.poll_diff() {
-            match env.byte_array_from_slice(&diff_bytes) {
-                Ok(arr) => arr,
-                Err(_) => JByteArray::default(),
-            }
+            env.byte_array_from_slice(&diff_bytes).unwrap_or_default()
+        } else {
+            JByteArray::default()
+        }
+    })
+}
+
+/// As `vtPollDiffContent`, with only the changed columns of changed rows.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollDiffSpans<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        if let Some(diff_bytes) = vt.poll_diff_spans() {
+            env.byte_array_from_slice(&diff_bytes).unwrap_or_default()
+        } else {
+            JByteArray::default()
+        }
+    })
+}
+
+/// As `vtPollDiff`, with changed cells as rectangles (tag 7, see `diff`).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollDiffDamage<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        if let Some(diff_bytes) = vt.poll_diff_damage() {
+            env.byte_array_from_slice(&diff_bytes).unwrap_or_default()
+        } else {
+            JByteArray::default()
+        }
+    })
+}
+
+/// As `vtPollDiffSpans`, with styles as ids (tag 6, see `styles`).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollDiffInterned<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        if let Some(diff_bytes) = vt.poll_diff_interned() {
+            env.byte_array_from_slice(&diff_bytes).unwrap_or_default()
         } else {
             JByteArray::default()
         }
+    })
+}
+
+/// As `vtPollDiff`, with the changed rows and cursor included.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollDiffContent<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        if let Some(diff_bytes) = vt.poll_diff_content() {
+            env.byte_array_from_slice(&diff_bytes).unwrap_or_default()
+        } else {
+            JByteArray::default()
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtDumpAnsi<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        env.new_string(vt.dump_ansi()).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRestore(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    cols: jint,
+    rows: jint,
+    dump: JString,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let dump: String = match env.get_string(&dump) {
+            Ok(s) => s.into(),
+            Err(_) => return,
+        };
+
+        vt.restore(cols as usize, rows as usize, dump.as_bytes());
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtDumpJson<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        env.new_string(vt.dump_json()).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetUpdateBudget(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    max_diffs_per_second: jint,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_update_budget(max_diffs_per_second.max(0) as u32);
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetIdleTimeout(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    idle_millis: jint,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_idle_timeout(idle_millis.max(0) as u32);
+    })
+}
+
+/// Bytes freed by `AvtState::compact`; 0 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtCompact(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.compact() as jlong
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPollIdle(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if vt.poll_idle() { JNI_TRUE } else { JNI_FALSE }
+    })
+}
+
+/// Bytes and feeds per second for the last `traffic::HISTORY` seconds,
+/// oldest first: `[bytes, feeds, bytes, feeds, ...]`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtTrafficHistory<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let values: Vec<jlong> = vt
+            .traffic()
+            .history(Instant::now())
+            .iter()
+            .flat_map(|bucket| [bucket.bytes as jlong, bucket.events as jlong])
+            .collect();
+        long_array(&env, &values)
+    })
+}
+
+/// `[bytes_per_second, feeds_per_second, millis_since_last_feed]`, the last
+/// -1 before the first feed.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtTrafficStats<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JDoubleArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JDoubleArray::default();
+        };
+
+        let now = Instant::now();
+        let (bytes, events) = vt.traffic().rates(now);
+        let since = vt
+            .traffic()
+            .since_last(now)
+            .map_or(-1.0, |since| since.as_millis() as f64);
+        double_array(&env, &[bytes, events, since])
+    })
+}
+
+/// Bytes dropped without effect (NUL padding and other ignored controls)
+/// since the VT was created or reset.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtIgnoredBytes(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.traffic().ignored() as jlong
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtResolveStyles<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    style_ids: JLongArray<'a>,
+    palette: JIntArray<'a>,
+    options: jint,
+    min_contrast: jfloat,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        resolve_styles(&env, &style_ids, &palette, options, min_contrast).unwrap_or_default()
+    })
+}
+
+fn resolve_styles<'a>(
+    env: &JNIEnv<'a>,
+    style_ids: &JLongArray,
+    palette: &JIntArray,
+    options: jint,
+    min_contrast: jfloat,
+) -> jni::errors::Result<JIntArray<'a>> {
+    let mut ids = vec![0; env.get_array_length(style_ids)? as usize];
+    env.get_long_array_region(style_ids, 0, &mut ids)?;
+    let mut colors = vec![0; env.get_array_length(palette)? as usize];
+    env.get_int_array_region(palette, 0, &mut colors)?;
+
+    let palette = palette::Palette::from_ints(&colors);
+    let options = palette::Options::new(options as u32, min_contrast);
+    let out: Vec<jint> = ids
+        .iter()
+        .flat_map(|&id| {
+            let style = snapshot::Style::from_id(id as u64);
+            let resolved = palette::resolve(style, &palette, &options);
+            [resolved.fg as jint, resolved.bg as jint, resolved.attrs as jint]
+        })
+        .collect();
+
+    Ok(int_array(env, &out))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtDetectPanes<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JIntArray::default();
+        };
+
+        let layout = vt.pane_layout();
+        let mut out = vec![layout.status_row.map_or(-1, |row| row as jint)];
+        for pane in &layout.panes {
+            out.extend([pane.col, pane.row, pane.cols, pane.rows].map(|v| v as jint));
+        }
+
+        int_array(&env, &out)
+    })
+}
+
+fn region_of(col: jint, row: jint, cols: jint, rows: jint) -> panes::Rect {
+    panes::Rect {
+        col: col.max(0) as usize,
+        row: row.max(0) as usize,
+        cols: cols.max(0) as usize,
+        rows: rows.max(0) as usize,
     }
 }
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRegionSnapshot<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    col: jint,
+    row: jint,
+    cols: jint,
+    rows: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let screen = vt.region(region_of(col, row, cols, rows));
+        env.byte_array_from_slice(&screen.encode()).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRegionText<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    col: jint,
+    row: jint,
+    cols: jint,
+    rows: jint,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let screen = vt.region(region_of(col, row, cols, rows));
+        env.new_string(panes::lines_text(&screen).join("\n")).unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRegionSearch<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    col: jint,
+    row: jint,
+    cols: jint,
+    rows: jint,
+    query: JString<'a>,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JIntArray::default();
+        };
+        let query: String = match env.get_string(&query) {
+            Ok(s) => s.into(),
+            Err(_) => return JIntArray::default(),
+        };
+
+        let screen = vt.region(region_of(col, row, cols, rows));
+        let hits: Vec<jint> = panes::search(&screen, &query)
+            .into_iter()
+            .flat_map(|(row, col)| [row as jint, col as jint])
+            .collect();
+
+        int_array(&env, &hits)
+    })
+}
@@ -0,0 +1,659 @@
+//! Recording library index, behind the `library` feature.
+//!
+//! Keeps per-recording metadata, the lines of text each recording printed
+//! and the user's bookmarks, keyed by the SHA-256 of the cast file so the
+//! same recording imported twice or renamed maps to one entry. The Room
+//! layer keeps what it lists and hands queries that would scan output text
+//! to this index.
+//!
+//! The index is one JSON file, read whole on open and rewritten on save
+//! through a temporary file, so a crash mid-save leaves the old one.
+
+use crate::cast::{micros_arg, Cast, CastError};
+use crate::handles::{self, Kind};
+use crate::json::{self, JsonError, Value};
+use crate::text::{text_lines, TextLine};
+use crate::to_hex;
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const VERSION: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LibraryError {
+    Io(String),
+    Json(JsonError),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::Io(message) => write!(f, "{}", message),
+            LibraryError::Json(error) => write!(f, "{}", error),
+            LibraryError::Invalid(reason) => write!(f, "invalid library index: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    pub time_us: i64,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Hex SHA-256 of the cast file
+    pub hash: String,
+    pub title: Option<String>,
+    /// Recording start from the header, Unix seconds
+    pub timestamp: Option<i64>,
+    pub cols: usize,
+    pub rows: usize,
+    pub duration_us: i64,
+    pub lines: Vec<TextLine>,
+    /// In time order
+    pub bookmarks: Vec<Bookmark>,
+}
+
+/// A line matching `Library::search`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit<'l> {
+    pub hash: &'l str,
+    pub time_us: i64,
+    pub text: &'l str,
+}
+
+/// Filters for `Library::query`; the default matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Case-insensitive substring of the title
+    pub title: Option<String>,
+    pub min_duration_us: Option<i64>,
+    pub max_duration_us: Option<i64>,
+    pub bookmarked: bool,
+}
+
+pub struct Library {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+registered!(Library, Kind::Library);
+
+impl Library {
+    /// Load the index at `path`, or start an empty one if there is none yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Library, LibraryError> {
+        let path = path.into();
+        let entries = match fs::read(&path) {
+            Ok(bytes) => parse(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(LibraryError::Io(format!("{}: {}", path.display(), e))),
+        };
+        Ok(Library { path, entries })
+    }
+
+    pub fn save(&self) -> Result<(), LibraryError> {
+        let io_error = |e: io::Error| LibraryError::Io(format!("{}: {}", self.path.display(), e));
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, write(&self.entries)).map_err(io_error)?;
+        fs::rename(&temp, &self.path).map_err(io_error)
+    }
+
+    /// Index a cast file, returning its hash. Adding one already indexed
+    /// keeps its bookmarks.
+    pub fn add(&mut self, bytes: &[u8]) -> Result<String, CastError> {
+        let hash = to_hex(&Sha256::digest(bytes));
+        if self.entries.contains_key(&hash) {
+            return Ok(hash);
+        }
+
+        let cast = Cast::parse(bytes)?;
+        let title = cast
+            .header
+            .fields
+            .get("title")
+            .and_then(Value::as_str)
+            .filter(|title| !title.is_empty())
+            .map(str::to_string);
+        let entry = Entry {
+            hash: hash.clone(),
+            title,
+            timestamp: cast
+                .header
+                .fields
+                .get("timestamp")
+                .and_then(Value::as_f64)
+                .map(|t| t as i64),
+            cols: cast.header.cols,
+            rows: cast.header.rows,
+            duration_us: cast.events.last().map_or(0, |e| e.time_us),
+            lines: text_lines(&cast),
+            bookmarks: Vec::new(),
+        };
+        self.entries.insert(hash.clone(), entry);
+        Ok(hash)
+    }
+
+    pub fn remove(&mut self, hash: &str) -> bool {
+        self.entries.remove(hash).is_some()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Entry> {
+        self.entries.get(hash)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    /// False if `hash` isn't indexed.
+    pub fn add_bookmark(&mut self, hash: &str, time_us: i64, label: &str) -> bool {
+        let Some(entry) = self.entries.get_mut(hash) else {
+            return false;
+        };
+        let at = entry.bookmarks.partition_point(|b| b.time_us <= time_us);
+        entry.bookmarks.insert(
+            at,
+            Bookmark {
+                time_us,
+                label: label.to_string(),
+            },
+        );
+        true
+    }
+
+    /// Entries matching every filter in `query`, by hash.
+    pub fn query(&self, query: &Query) -> Vec<&Entry> {
+        let title = query.title.as_ref().map(|t| t.to_lowercase());
+        self.entries
+            .values()
+            .filter(|entry| match &title {
+                Some(title) => entry
+                    .title
+                    .as_ref()
+                    .is_some_and(|t| t.to_lowercase().contains(title.as_str())),
+                None => true,
+            })
+            .filter(|entry| {
+                query
+                    .min_duration_us
+                    .is_none_or(|min| entry.duration_us >= min)
+            })
+            .filter(|entry| {
+                query
+                    .max_duration_us
+                    .is_none_or(|max| entry.duration_us <= max)
+            })
+            .filter(|entry| !query.bookmarked || !entry.bookmarks.is_empty())
+            .collect()
+    }
+
+    /// Output lines containing `query` (case-insensitive) across every
+    /// indexed recording: newest recording first, those without a
+    /// timestamp last, in time order within one. At most `limit` hits if
+    /// given.
+    pub fn search(&self, query: &str, limit: Option<usize>) -> Vec<Hit<'_>> {
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+        entries
+            .into_iter()
+            .flat_map(|entry| {
+                entry
+                    .lines
+                    .iter()
+                    .filter(|line| line.text.to_lowercase().contains(&query))
+                    .map(|line| Hit {
+                        hash: &entry.hash,
+                        time_us: line.time_us,
+                        text: &line.text,
+                    })
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+impl Entry {
+    /// Everything but the text lines, for listing.
+    pub fn metadata_json(&self) -> Value {
+        let number = |n: i64| Value::Number(n as f64);
+        let mut fields = vec![("hash".to_string(), Value::String(self.hash.clone()))];
+        if let Some(title) = &self.title {
+            fields.push(("title".to_string(), Value::String(title.clone())));
+        }
+        if let Some(timestamp) = self.timestamp {
+            fields.push(("timestamp".to_string(), number(timestamp)));
+        }
+        fields.extend([
+            ("cols".to_string(), number(self.cols as i64)),
+            ("rows".to_string(), number(self.rows as i64)),
+            ("duration_us".to_string(), number(self.duration_us)),
+            (
+                "bookmarks".to_string(),
+                timed_strings(self.bookmarks.iter().map(|b| (b.time_us, &b.label))),
+            ),
+        ]);
+        Value::Object(fields)
+    }
+}
+
+fn timed_strings<'s>(items: impl Iterator<Item = (i64, &'s String)>) -> Value {
+    Value::Array(
+        items
+            .map(|(time_us, text)| {
+                Value::Array(vec![
+                    Value::Number(time_us as f64),
+                    Value::String(text.clone()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn parse_timed_strings(value: Option<&Value>) -> Option<Vec<(i64, String)>> {
+    value?
+        .as_array()?
+        .iter()
+        .map(|item| match item.as_array() {
+            Some([time, text]) => Some((time.as_f64()? as i64, text.as_str()?.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `{"version": 1, "casts": [{"hash", "title", "timestamp", "cols", "rows",
+/// "duration_us", "lines": [[time_us, text], ...], "bookmarks": [[time_us,
+/// label], ...]}, ...]}`
+fn parse(bytes: &[u8]) -> Result<BTreeMap<String, Entry>, LibraryError> {
+    let value = json::parse(&String::from_utf8_lossy(bytes)).map_err(LibraryError::Json)?;
+    if value.get("version").and_then(Value::as_f64) != Some(VERSION) {
+        return Err(LibraryError::Invalid("unsupported version"));
+    }
+
+    let casts = value
+        .get("casts")
+        .and_then(Value::as_array)
+        .ok_or(LibraryError::Invalid("missing casts"))?;
+    casts
+        .iter()
+        .map(|cast| {
+            let field = |key| cast.get(key).and_then(Value::as_f64);
+            let entry = Entry {
+                hash: cast.get("hash").and_then(Value::as_str)?.to_string(),
+                title: cast
+                    .get("title")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                timestamp: field("timestamp").map(|t| t as i64),
+                cols: field("cols")? as usize,
+                rows: field("rows")? as usize,
+                duration_us: field("duration_us")? as i64,
+                lines: parse_timed_strings(cast.get("lines"))?
+                    .into_iter()
+                    .map(|(time_us, text)| TextLine { time_us, text })
+                    .collect(),
+                bookmarks: parse_timed_strings(cast.get("bookmarks"))?
+                    .into_iter()
+                    .map(|(time_us, label)| Bookmark { time_us, label })
+                    .collect(),
+            };
+            Some((entry.hash.clone(), entry))
+        })
+        .collect::<Option<_>>()
+        .ok_or(LibraryError::Invalid("bad cast entry"))
+}
+
+fn write(entries: &BTreeMap<String, Entry>) -> Vec<u8> {
+    let casts = entries
+        .values()
+        .map(|entry| {
+            let Value::Object(mut fields) = entry.metadata_json() else {
+                unreachable!()
+            };
+            fields.push((
+                "lines".to_string(),
+                timed_strings(entry.lines.iter().map(|l| (l.time_us, &l.text))),
+            ));
+            Value::Object(fields)
+        })
+        .collect();
+    Value::Object(vec![
+        ("version".to_string(), Value::Number(VERSION)),
+        ("casts".to_string(), Value::Array(casts)),
+    ])
+    .to_string()
+    .into_bytes()
+}
+
+// JNI functions
+
+/// Handle to the index at `path`, or 0 if it exists but can't be read.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryOpen(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jlong {
+    jni_guard!(env, {
+        let path: String = match env.get_string(&path) {
+            Ok(s) => s.into(),
+            Err(_) => return 0,
+        };
+
+        match Library::open(path) {
+            Ok(library) => handles::add(library),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Library>(&mut env, handle));
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_librarySave(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(library) = handles::object_ref::<Library>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if library.save().is_ok() {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Hash of the indexed cast, or null if it doesn't parse.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryAdd<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    cast_bytes: JByteArray<'a>,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(library) = handles::object::<Library>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let bytes = match env.convert_byte_array(cast_bytes) {
+            Ok(b) => b,
+            Err(_) => return JString::default(),
+        };
+
+        match library.add(&bytes) {
+            Ok(hash) => env.new_string(hash).unwrap_or_default(),
+            Err(_) => JString::default(),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryRemove(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    hash: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(library) = handles::object::<Library>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let hash: String = match env.get_string(&hash) {
+            Ok(s) => s.into(),
+            Err(_) => return JNI_FALSE,
+        };
+
+        if library.remove(&hash) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryAddBookmark(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    hash: JString,
+    time_micros: jlong,
+    label: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(library) = handles::object::<Library>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JNI_FALSE;
+        };
+
+        let (hash, label): (String, String) = match (env.get_string(&hash), env.get_string(&label))
+        {
+            (Ok(hash), Ok(label)) => (hash.into(), label.into()),
+            _ => return JNI_FALSE,
+        };
+
+        if library.add_bookmark(&hash, time_us, &label) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// JSON array of `Entry::metadata_json` for the entries matching the
+/// filters. A null or empty title and negative durations don't filter.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_libraryQuery<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    title: JString<'a>,
+    min_micros: jlong,
+    max_micros: jlong,
+    bookmarked_only: jboolean,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(library) = handles::object_ref::<Library>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let title: Option<String> = env
+            .get_string(&title)
+            .ok()
+            .map(String::from)
+            .filter(|title| !title.is_empty());
+        let query = Query {
+            title,
+            min_duration_us: (min_micros >= 0).then_some(min_micros),
+            max_duration_us: (max_micros >= 0).then_some(max_micros),
+            bookmarked: bookmarked_only != JNI_FALSE,
+        };
+
+        let results = library
+            .query(&query)
+            .iter()
+            .map(|e| e.metadata_json())
+            .collect();
+        env.new_string(Value::Array(results).to_string())
+            .unwrap_or_default()
+    })
+}
+
+/// JSON array of `[hash, timeMicros, text]` for each `Library::search`
+/// hit; `limit` of 0 or less for all.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_librarySearch<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    query: JString<'a>,
+    limit: jint,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(library) = handles::object_ref::<Library>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let query: String = match env.get_string(&query) {
+            Ok(s) => s.into(),
+            Err(_) => return JString::default(),
+        };
+
+        let hits = library
+            .search(&query, (limit > 0).then_some(limit as usize))
+            .iter()
+            .map(|hit| {
+                Value::Array(vec![
+                    Value::String(hit.hash.to_string()),
+                    Value::Number(hit.time_us as f64),
+                    Value::String(hit.text.to_string()),
+                ])
+            })
+            .collect();
+        env.new_string(Value::Array(hits).to_string())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAST: &[u8] =
+        b"{\"version\": 2, \"width\": 80, \"height\": 24, \"title\": \"Build demo\"}\n\
+        [0.5, \"o\", \"$ \\u001b[1mmake\\u001b[0m\\r\\n\"]\n\
+        [1.0, \"o\", \"\\r[=>  ] 10%\"]\n\
+        [2.0, \"o\", \"\\r[===>] 90%\\r\\n\"]\n\
+        [2.5, \"i\", \"x\"]\n\
+        [3.0, \"o\", \"\\r\\n\\u001b[31merror: oops\\u001b[0m\\r\\n\"]\n";
+
+    #[test]
+    fn persists_entries_and_bookmarks() {
+        let dir = std::env::temp_dir().join(format!("library-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.json");
+        let _ = fs::remove_file(&path);
+
+        let mut library = Library::open(&path).unwrap();
+        let hash = library.add(CAST).unwrap();
+        assert_eq!(library.add(CAST).unwrap(), hash);
+        assert!(library.add_bookmark(&hash, 3_000_000, "the error"));
+        assert!(library.add_bookmark(&hash, 1_000_000, "build"));
+        assert!(!library.add_bookmark("missing", 0, "x"));
+        library.save().unwrap();
+
+        let reopened = Library::open(&path).unwrap();
+        let entry = reopened.get(&hash).unwrap();
+        assert_eq!(entry, library.get(&hash).unwrap());
+        assert_eq!(entry.title.as_deref(), Some("Build demo"));
+        assert_eq!(entry.duration_us, 3_000_000);
+        assert_eq!(entry.bookmarks[0].label, "build");
+
+        let query = |query: Query| reopened.query(&query).len();
+        assert_eq!(query(Query::default()), 1);
+        assert_eq!(
+            query(Query {
+                title: Some("DEMO".into()),
+                ..Query::default()
+            }),
+            1
+        );
+        assert_eq!(
+            query(Query {
+                max_duration_us: Some(1_000_000),
+                ..Query::default()
+            }),
+            0
+        );
+        assert_eq!(
+            query(Query {
+                bookmarked: true,
+                ..Query::default()
+            }),
+            1
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn searches_newest_recordings_first() {
+        let cast = |timestamp: &str, lines: &str| {
+            format!(
+                "{{\"version\": 2, \"width\": 80, \"height\": 24{}}}\n{}",
+                timestamp, lines
+            )
+        };
+        let path = std::env::temp_dir().join("library-search-test.json");
+        let mut library = Library::open(&path).unwrap();
+        let old = library
+            .add(
+                cast(
+                    ", \"timestamp\": 1000",
+                    "[1.0, \"o\", \"Error: disk full\\r\\n\"]\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let new = library
+            .add(
+                cast(
+                    ", \"timestamp\": 2000",
+                    "[1.0, \"o\", \"ok\\r\\n\"]\n[2.0, \"o\", \"error: oops\\r\\n\"]\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let undated = library
+            .add(cast("", "[3.0, \"o\", \"no errors\\r\\n\"]\n").as_bytes())
+            .unwrap();
+
+        let hits: Vec<_> = library
+            .search("ERROR", None)
+            .iter()
+            .map(|hit| (hit.hash.to_string(), hit.time_us, hit.text.to_string()))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (new.clone(), 2_000_000, "error: oops".to_string()),
+                (old, 1_000_000, "Error: disk full".to_string()),
+                (undated, 3_000_000, "no errors".to_string()),
+            ]
+        );
+        assert_eq!(library.search("error", Some(1))[0].hash, new);
+        assert!(library.search("", None).is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+//! Memory limits per VT, and what each part takes.
+//!
+//! Scrollback and images are what grow without bound: a build log keeps
+//! adding lines and an `imgcat` loop adds up to `images::MAX_PIXELS` of
+//! RGBA each time. On a low-RAM device the app sets limits with
+//! `vtSetLimits` and shows `vtMemoryUsage` in its diagnostics.
+//!
+//! Each limit has its own eviction policy:
+//!
+//! - `max_scrollback_lines` caps the scrollback, the oldest lines going
+//!   first, as `vtSetScrollbackRetention` does (whose limits it replaces).
+//! - `max_image_bytes` caps the RGBA held, the least recently used images
+//!   losing their pixels first and an image over the cap keeping none
+//!   (see `images`); without it `images::MAX_STORED` does.
+//! - `max_total_bytes` covers everything `Usage` counts. The screen takes
+//!   its share first and the scrollback gets the rest as a byte limit, at
+//!   the width when set. Images go before anything else: after each feed
+//!   that prints, they are evicted until the total fits again.
+//!
+//! Setting limits applies them at once: lowering them evicts, and a new
+//! scrollback limit drops the scrollback kept so far, as a retention
+//! change does. They're kept across resets. The sizes are estimates from
+//! the cells and buffers held, not the allocator's own counts (for those,
+//! see `alloc_stats`).
+
+use crate::backend::Retention;
+#[cfg(feature = "images")]
+use crate::images;
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JLongArray};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+/// `None` for no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    pub max_scrollback_lines: Option<usize>,
+    pub max_image_bytes: Option<usize>,
+    pub max_total_bytes: Option<usize>,
+}
+
+impl Limits {
+    /// Most RGBA bytes images may hold, the total aside.
+    #[cfg(feature = "images")]
+    pub fn image_bytes(&self) -> usize {
+        self.max_image_bytes.unwrap_or(images::MAX_STORED)
+    }
+
+    /// The scrollback's share, for a screen of `screen_bytes`.
+    pub fn retention(&self, screen_bytes: usize) -> Retention {
+        Retention {
+            max_lines: self.max_scrollback_lines,
+            max_bytes: self
+                .max_total_bytes
+                .map(|total| total.saturating_sub(screen_bytes)),
+        }
+    }
+}
+
+/// Approximate bytes held by each part of a VT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    /// The visible screen's cells
+    pub screen: usize,
+    pub scrollback: usize,
+    /// Inline images' RGBA
+    pub images: usize,
+    /// Hyperlink URIs
+    pub links: usize,
+    /// Delta baselines (`vtSnapshotDelta`)
+    pub history: usize,
+    /// Encoding buffers, queued events and responses, diff state
+    pub buffers: usize,
+    /// Images holding pixels, and those evicted to placeholders so far;
+    /// counts, not part of `total`
+    pub images_held: usize,
+    pub images_evicted: usize,
+}
+
+impl Usage {
+    pub fn total(&self) -> usize {
+        self.screen + self.scrollback + self.images + self.links + self.history + self.buffers
+    }
+
+    /// In `vtMemoryUsage` order: screen, scrollback, images, links,
+    /// history, buffers, then the image counts.
+    pub fn to_array(&self) -> [usize; 8] {
+        [
+            self.screen,
+            self.scrollback,
+            self.images,
+            self.links,
+            self.history,
+            self.buffers,
+            self.images_held,
+            self.images_evicted,
+        ]
+    }
+}
+
+// JNI functions
+
+/// Set the VT's memory limits, see the module docs; negative means no
+/// limit.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetLimits(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    max_scrollback_lines: jint,
+    max_image_bytes: jlong,
+    max_total_bytes: jlong,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_limits(Limits {
+            max_scrollback_lines: usize::try_from(max_scrollback_lines).ok(),
+            max_image_bytes: usize::try_from(max_image_bytes).ok(),
+            max_total_bytes: usize::try_from(max_total_bytes).ok(),
+        });
+    })
+}
+
+/// `Usage::to_array`, empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtMemoryUsage<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let values = vt.memory_usage().to_array().map(|bytes| bytes as jlong);
+        crate::long_array(&env, &values)
+    })
+}
+
+#[cfg(all(test, feature = "images"))]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::backend::Cell;
+    use crate::AvtState;
+
+    /// The 2×2 PNG of the `png` tests, 16 bytes of RGBA.
+    const IMAGE: &str = concat!(
+        "\x1b]1337;File=inline=1;width=3;height=1:",
+        "iVBORw0KGgoAAAANSUhEUgAAAAIAAAACAgMAAAAAAAAAAAAACVBMVEX/AAAA/wAAAP8AAAAAAAAA",
+        "AXRSTlOAAAAAAAAAAAxJREFUeJxjFGBIAAAAmAByAAAAAAAAAABJRU5EAAAAAA==\x07",
+    );
+
+    #[test]
+    fn lowering_limits_evicts_the_oldest_first() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.vt.scrollback = (0..5).map(|n| n.to_string()).collect();
+        // Blanks printed over the first image's blank cells keep it
+        vt.feed(format!("ab{}   {}", IMAGE, IMAGE).as_bytes());
+        let usage = vt.memory_usage();
+        let line = 20 * std::mem::size_of::<Cell>();
+        assert_eq!(
+            (usage.screen, usage.scrollback, usage.images),
+            (2 * line, 5 * line, 32)
+        );
+
+        vt.set_limits(Limits {
+            max_scrollback_lines: Some(2),
+            max_image_bytes: Some(16),
+            max_total_bytes: None,
+        });
+        assert_eq!(vt.backend().scrollback, ["3", "4"]);
+        assert!(vt.image(1).unwrap().rgba.is_empty());
+        assert_eq!(vt.image(2).unwrap().rgba.len(), 16);
+        assert_eq!(vt.memory_usage().images, 16);
+
+        // Too little left for the image once everything else is counted
+        let usage = vt.memory_usage();
+        vt.set_limits(Limits {
+            max_total_bytes: Some(usage.total() - 8),
+            ..vt.limits()
+        });
+        assert!(vt.image(2).unwrap().rgba.is_empty());
+        let usage = vt.memory_usage();
+        assert_eq!((usage.images, usage.images_held, usage.images_evicted), (0, 0, 2));
+        // Kept across resets
+        vt.reset(20, 2);
+        assert_eq!(vt.limits().max_image_bytes, Some(16));
+    }
+}
@@ -0,0 +1,316 @@
+//! Per-row line attributes: DECDWL (double width) and DECDHL (double height).
+//!
+//! avt only models single-size lines, so the wrapper tracks `ESC # 3..6`
+//! itself and keeps the attributes attached to their rows as the screen
+//! scrolls or gets erased. Known approximations: attributes don't follow
+//! rows into scrollback, and the alternate screen shares the primary's table.
+//!
+//! `RowTable` does the moving, for any per-row value (see also `links`).
+
+use crate::scan::Action;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum LineAttr {
+    #[default]
+    Single = 0,
+    DoubleWidth = 1,
+    DoubleHeightTop = 2,
+    DoubleHeightBottom = 3,
+}
+
+impl LineAttr {
+    /// Final byte of the `ESC #` sequence that selects this attribute, or
+    /// `None` for single size (the default needs no sequence).
+    pub fn esc_final(self) -> Option<u8> {
+        match self {
+            LineAttr::Single => None,
+            LineAttr::DoubleWidth => Some(b'6'),
+            LineAttr::DoubleHeightTop => Some(b'3'),
+            LineAttr::DoubleHeightBottom => Some(b'4'),
+        }
+    }
+}
+
+/// Screen operations that move or reset line attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOp {
+    Set(LineAttr),
+    /// LF, VT, FF, IND and NEL: scroll up when on the bottom margin
+    LineFeed,
+    /// RI: scroll down when on the top margin
+    ReverseIndex,
+    ScrollUp(usize),
+    ScrollDown(usize),
+    InsertLines(usize),
+    DeleteLines(usize),
+    /// ED with its mode parameter
+    EraseDisplay(u16),
+    /// DECSTBM, 1-based inclusive; 0 means default
+    SetMargins(usize, usize),
+    /// DECALN and RIS reset every row to single size
+    ResetAll,
+}
+
+impl LineOp {
+    /// Map a scanner action to a line op, plus how many trailing bytes of
+    /// the sequence must be withheld from the VT so the cursor is read
+    /// before the sequence moves it.
+    pub fn from_action(action: &Action) -> Option<(LineOp, usize)> {
+        let op = match action {
+            Action::Control(0x0a..=0x0c) => return Some((LineOp::LineFeed, 1)),
+            Action::Esc {
+                intermediate: None,
+                final_byte,
+            } => match final_byte {
+                b'D' | b'E' => return Some((LineOp::LineFeed, 2)),
+                b'M' => return Some((LineOp::ReverseIndex, 2)),
+                b'c' => LineOp::ResetAll,
+                _ => return None,
+            },
+            Action::Esc {
+                intermediate: Some(b'#'),
+                final_byte,
+            } => match final_byte {
+                b'3' => LineOp::Set(LineAttr::DoubleHeightTop),
+                b'4' => LineOp::Set(LineAttr::DoubleHeightBottom),
+                b'5' => LineOp::Set(LineAttr::Single),
+                b'6' => LineOp::Set(LineAttr::DoubleWidth),
+                b'8' => LineOp::ResetAll,
+                _ => return None,
+            },
+            Action::Csi(csi) if csi.marker.is_none() && csi.intermediates().is_empty() => {
+                let n = csi.param(0, 1) as usize;
+                match csi.final_byte {
+                    b'S' => LineOp::ScrollUp(n),
+                    b'T' => LineOp::ScrollDown(n),
+                    b'L' => LineOp::InsertLines(n),
+                    b'M' => LineOp::DeleteLines(n),
+                    b'J' => LineOp::EraseDisplay(csi.params().first().copied().unwrap_or(0)),
+                    b'r' => LineOp::SetMargins(
+                        csi.params().first().copied().unwrap_or(0) as usize,
+                        csi.params().get(1).copied().unwrap_or(0) as usize,
+                    ),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some((op, 0))
+    }
+
+    /// Ops that only matter once some row carries an attribute.
+    pub fn needs_tracking(&self) -> bool {
+        !matches!(self, LineOp::Set(_) | LineOp::SetMargins(..) | LineOp::ResetAll)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LineAttrs {
+    attrs: RowTable<LineAttr>,
+}
+
+impl LineAttrs {
+    pub fn new(rows: usize) -> Self {
+        LineAttrs {
+            attrs: RowTable::new(rows),
+        }
+    }
+
+    pub fn resize(&mut self, rows: usize) {
+        self.attrs.resize(rows);
+    }
+
+    pub fn get(&self, row: usize) -> LineAttr {
+        self.attrs.get(row).copied().unwrap_or_default()
+    }
+
+    /// True if any row is not single size.
+    pub fn is_tracking(&self) -> bool {
+        self.attrs.rows.iter().any(|&a| a != LineAttr::Single)
+    }
+
+    /// Apply `op` with the cursor on `cursor_row`. Returns true if any
+    /// row's attribute changed.
+    pub fn apply(&mut self, op: LineOp, cursor_row: usize) -> bool {
+        let before = self.attrs.rows.clone();
+        match op {
+            LineOp::Set(attr) => {
+                if let Some(a) = self.attrs.get_mut(cursor_row) {
+                    *a = attr;
+                }
+            }
+            _ => self.attrs.apply(op, cursor_row),
+        }
+        self.attrs.rows != before
+    }
+}
+
+/// A value per visible row that moves with its row as the screen scrolls,
+/// rows scrolled in or erased getting the default. `LineOp::Set` is left
+/// to the owner.
+#[derive(Debug, Clone)]
+pub struct RowTable<T> {
+    rows: Vec<T>,
+    /// Scroll region, 0-based, `bottom` exclusive
+    top: usize,
+    bottom: usize,
+}
+
+impl<T: Clone + Default> RowTable<T> {
+    pub fn new(rows: usize) -> Self {
+        RowTable {
+            rows: vec![T::default(); rows],
+            top: 0,
+            bottom: rows,
+        }
+    }
+
+    pub fn resize(&mut self, rows: usize) {
+        self.rows.resize(rows, T::default());
+        self.top = 0;
+        self.bottom = rows;
+    }
+
+    pub fn get(&self, row: usize) -> Option<&T> {
+        self.rows.get(row)
+    }
+
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut T> {
+        self.rows.get_mut(row)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.rows.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.rows.iter_mut()
+    }
+
+    /// Move rows as `op` does with the cursor on `cursor_row`.
+    pub fn apply(&mut self, op: LineOp, cursor_row: usize) {
+        let rows = self.rows.len();
+        match op {
+            LineOp::Set(_) => {}
+            LineOp::LineFeed => {
+                if cursor_row + 1 == self.bottom {
+                    self.scroll_up(self.top, 1);
+                }
+            }
+            LineOp::ReverseIndex => {
+                if cursor_row == self.top {
+                    self.scroll_down(self.top, 1);
+                }
+            }
+            LineOp::ScrollUp(n) => self.scroll_up(self.top, n),
+            LineOp::ScrollDown(n) => self.scroll_down(self.top, n),
+            LineOp::InsertLines(n) => {
+                if (self.top..self.bottom).contains(&cursor_row) {
+                    self.scroll_down(cursor_row, n);
+                }
+            }
+            LineOp::DeleteLines(n) => {
+                if (self.top..self.bottom).contains(&cursor_row) {
+                    self.scroll_up(cursor_row, n);
+                }
+            }
+            LineOp::EraseDisplay(mode) => {
+                let range = match mode {
+                    0 => (cursor_row + 1).min(rows)..rows,
+                    1 => 0..cursor_row.min(rows),
+                    _ => 0..rows,
+                };
+                self.rows[range].fill(T::default());
+            }
+            LineOp::SetMargins(top, bottom) => {
+                let top = top.max(1) - 1;
+                let bottom = if bottom == 0 { rows } else { bottom.min(rows) };
+                if top + 1 < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                }
+            }
+            LineOp::ResetAll => {
+                self.rows.fill(T::default());
+                self.top = 0;
+                self.bottom = rows;
+            }
+        }
+    }
+
+    /// Shift rows `from..bottom` up by `n`, filling with defaults.
+    fn scroll_up(&mut self, from: usize, n: usize) {
+        let region = &mut self.rows[from.min(self.bottom)..self.bottom];
+        let n = n.min(region.len());
+        region.rotate_left(n);
+        let len = region.len();
+        region[len - n..].fill(T::default());
+    }
+
+    /// Shift rows `from..bottom` down by `n`, filling with defaults.
+    fn scroll_down(&mut self, from: usize, n: usize) {
+        let region = &mut self.rows[from.min(self.bottom)..self.bottom];
+        let n = n.min(region.len());
+        region.rotate_right(n);
+        region[..n].fill(T::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LineAttr::*;
+
+    fn attrs(a: &LineAttrs) -> Vec<LineAttr> {
+        a.attrs.iter().copied().collect()
+    }
+
+    #[test]
+    fn set_and_scroll_with_content() {
+        let mut a = LineAttrs::new(4);
+        assert!(a.apply(LineOp::Set(DoubleHeightTop), 2));
+        assert!(a.apply(LineOp::Set(DoubleHeightBottom), 3));
+        assert!(!a.apply(LineOp::LineFeed, 1));
+        assert!(a.apply(LineOp::LineFeed, 3));
+        assert_eq!(attrs(&a), vec![Single, DoubleHeightTop, DoubleHeightBottom, Single]);
+        assert!(a.apply(LineOp::ReverseIndex, 0));
+        assert_eq!(attrs(&a), vec![Single, Single, DoubleHeightTop, DoubleHeightBottom]);
+    }
+
+    #[test]
+    fn respects_scroll_margins() {
+        let mut a = LineAttrs::new(5);
+        a.apply(LineOp::Set(DoubleWidth), 0);
+        a.apply(LineOp::Set(DoubleWidth), 2);
+        a.apply(LineOp::SetMargins(2, 4), 0);
+        a.apply(LineOp::LineFeed, 3);
+        assert_eq!(attrs(&a), vec![DoubleWidth, DoubleWidth, Single, Single, Single]);
+    }
+
+    #[test]
+    fn insert_delete_and_erase() {
+        let mut a = LineAttrs::new(4);
+        a.apply(LineOp::Set(DoubleWidth), 1);
+        a.apply(LineOp::InsertLines(2), 0);
+        assert_eq!(attrs(&a), vec![Single, Single, Single, DoubleWidth]);
+        a.apply(LineOp::DeleteLines(1), 2);
+        assert_eq!(attrs(&a), vec![Single, Single, DoubleWidth, Single]);
+        a.apply(LineOp::EraseDisplay(0), 1);
+        assert!(!a.is_tracking());
+    }
+
+    #[test]
+    fn decaln_resets_everything() {
+        let mut a = LineAttrs::new(3);
+        a.apply(LineOp::Set(DoubleWidth), 1);
+        let (op, hold) = LineOp::from_action(&Action::Esc {
+            intermediate: Some(b'#'),
+            final_byte: b'8',
+        })
+        .unwrap();
+        assert_eq!(hold, 0);
+        assert!(a.apply(op, 0));
+        assert!(!a.is_tracking());
+    }
+}
@@ -0,0 +1,364 @@
+//! OSC 8 hyperlinks.
+//!
+//! `OSC 8 ; params ; URI ST` starts a link and `OSC 8 ; ; ST` ends it; the
+//! text printed in between is the link. avt ignores OSC 8, so the wrapper
+//! keeps the links itself: each visible row holds the column spans printed
+//! while a link was open, moved with their rows as the screen scrolls (see
+//! `lineattr::RowTable`). Snapshots mark linked runs with a link id and
+//! list the URIs of those ids (see `snapshot`); `vtLinkAt` answers taps.
+//!
+//! A link's text is taken to be what lies between the cursor where it
+//! opened and the cursor where it closed (or where a feed ended), so text
+//! that moves the cursor around inside a link gets approximate spans. A
+//! span whose text is later overwritten is dropped, and links don't follow
+//! rows into scrollback. `params` (such as `id=`) are ignored: links with
+//! the same URI share an id.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::lineattr::{LineOp, RowTable};
+use crate::scan::Action;
+use crate::snapshot::Screen;
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JString};
+use jni::sys::jint;
+use jni::JNIEnv;
+
+/// Distinct URIs held at once; links to more are left unmarked
+pub const MAX_LINKS: usize = 1024;
+
+/// Longest URI kept, in bytes
+pub const MAX_URI: usize = 4096;
+
+/// The URI an OSC 8 action opens, empty for one that closes the current
+/// link. `None` for any other action.
+pub fn osc8<'a>(action: &Action<'a>) -> Option<&'a [u8]> {
+    let Action::Osc(osc) = action else {
+        return None;
+    };
+    let rest = osc.strip_prefix(b"8;")?;
+    let params = rest.iter().position(|&b| b == b';')?;
+    Some(&rest[params + 1..])
+}
+
+/// Columns `start..end` of a row printed while link `id` was open, and the
+/// chars they held then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Span {
+    start: usize,
+    end: usize,
+    id: u32,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Links {
+    /// URIs by id - 1, `None` for a free id
+    uris: Vec<Option<String>>,
+    rows: RowTable<Vec<Span>>,
+    /// The open link's id, and the column and row its text starts at
+    open: Option<(u32, usize, usize)>,
+}
+
+impl Links {
+    pub fn new(rows: usize) -> Self {
+        Links {
+            uris: Vec::new(),
+            rows: RowTable::new(rows),
+            open: None,
+        }
+    }
+
+    pub fn resize(&mut self, rows: usize) {
+        self.rows.resize(rows);
+    }
+
+    /// True while a link is open or any row has one.
+    pub fn is_tracking(&self) -> bool {
+        self.open.is_some() || self.rows.iter().any(|spans| !spans.is_empty())
+    }
+
+    /// Bytes of the URIs held.
+    pub fn bytes(&self) -> usize {
+        self.uris.iter().flatten().map(String::len).sum()
+    }
+
+    pub fn uri(&self, id: u32) -> Option<&str> {
+        let index = (id as usize).checked_sub(1)?;
+        self.uris.get(index)?.as_deref()
+    }
+
+    /// The URI linked at `row`, `col`.
+    pub fn at(&self, row: usize, col: usize) -> Option<&str> {
+        let spans = self.rows.get(row)?;
+        let span = spans.iter().find(|s| (s.start..s.end).contains(&col))?;
+        self.uri(span.id)
+    }
+
+    /// Handle an OSC 8 `uri` (see `osc8`) with `backend` fed up to it.
+    pub fn handle(&mut self, uri: &[u8], backend: &impl TerminalBackend) {
+        self.mark(backend);
+        self.open = None;
+        if uri.is_empty() || uri.len() > MAX_URI {
+            return;
+        }
+        if let Some(id) = self.intern(&String::from_utf8_lossy(uri)) {
+            let cursor = backend.cursor();
+            self.open = Some((id, cursor.col, cursor.row));
+        }
+    }
+
+    /// Mark what the open link printed so far and carry on from the cursor,
+    /// at the end of a feed.
+    pub fn flush(&mut self, backend: &impl TerminalBackend) {
+        if let Some((id, ..)) = self.open {
+            self.mark(backend);
+            let cursor = backend.cursor();
+            self.open = Some((id, cursor.col, cursor.row));
+        }
+    }
+
+    /// Move spans with their rows, see `LineOp`. A reset closes the link.
+    pub fn apply(&mut self, op: LineOp, cursor_row: usize) {
+        self.rows.apply(op, cursor_row);
+        if op == LineOp::ResetAll {
+            self.open = None;
+        }
+    }
+
+    /// Drop spans whose text has since been overwritten.
+    pub fn prune(&mut self, backend: &impl TerminalBackend) {
+        let mut cells = Vec::new();
+        for (row, spans) in self.rows.iter_mut().enumerate() {
+            if spans.is_empty() {
+                continue;
+            }
+            backend.row_cells(row, &mut cells);
+            spans.retain(|span| text_of(&cells, span.start, span.end) == span.text);
+        }
+    }
+
+    /// Set the link of each cell of `row` that has one.
+    pub(crate) fn overlay(&self, row: usize, cells: &mut [Cell]) {
+        for span in self.rows.get(row).into_iter().flatten() {
+            let end = span.end.min(cells.len());
+            for cell in cells
+                .get_mut(span.start.min(end)..end)
+                .into_iter()
+                .flatten()
+            {
+                cell.link = span.id;
+            }
+        }
+    }
+
+    /// The (id, URI) of every link `screen` shows, by id.
+    pub fn table(&self, screen: &Screen) -> Vec<(u32, String)> {
+        let mut ids: Vec<u32> = screen
+            .lines
+            .iter()
+            .flat_map(|line| &line.runs)
+            .map(|run| run.link)
+            .filter(|&id| id != 0)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .filter_map(|id| Some((id, self.uri(id)?.to_string())))
+            .collect()
+    }
+
+    /// Give the open link the cells from where its text started to the
+    /// cursor.
+    fn mark(&mut self, backend: &impl TerminalBackend) {
+        let Some((id, col, row)) = self.open else {
+            return;
+        };
+        let cursor = backend.cursor();
+        let (cols, _) = backend.size();
+        if (cursor.row, cursor.col) <= (row, col) {
+            return;
+        }
+
+        let mut cells = Vec::new();
+        for r in row..=cursor.row {
+            let start = if r == row { col } else { 0 };
+            let end = if r == cursor.row {
+                cursor.col.min(cols)
+            } else {
+                cols
+            };
+            if start >= end {
+                continue;
+            }
+            backend.row_cells(r, &mut cells);
+            let text = text_of(&cells, start, end);
+            if let Some(spans) = self.rows.get_mut(r) {
+                insert(
+                    spans,
+                    Span {
+                        start,
+                        end,
+                        id,
+                        text,
+                    },
+                );
+            }
+        }
+    }
+
+    /// An id for `uri`, reusing the one it has if any. `None` when every
+    /// id is taken by a link still on screen.
+    fn intern(&mut self, uri: &str) -> Option<u32> {
+        if let Some(index) = self.uris.iter().position(|u| u.as_deref() == Some(uri)) {
+            return Some(index as u32 + 1);
+        }
+        if self.uris.len() >= MAX_LINKS && !self.uris.contains(&None) {
+            self.release_unused();
+        }
+        let index = match self.uris.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.uris.len() < MAX_LINKS => {
+                self.uris.push(None);
+                self.uris.len() - 1
+            }
+            None => return None,
+        };
+        self.uris[index] = Some(uri.to_string());
+        Some(index as u32 + 1)
+    }
+
+    /// Free the ids no span refers to.
+    fn release_unused(&mut self) {
+        let mut used = vec![false; self.uris.len()];
+        for span in self.rows.iter().flatten() {
+            used[span.id as usize - 1] = true;
+        }
+        for (uri, used) in self.uris.iter_mut().zip(used) {
+            if !used {
+                *uri = None;
+            }
+        }
+    }
+}
+
+/// Chars of `cells` in columns `start..end`.
+fn text_of(cells: &[Cell], start: usize, end: usize) -> String {
+    let end = end.min(cells.len());
+    cells[start.min(end)..end]
+        .iter()
+        .map(|cell| cell.ch)
+        .collect()
+}
+
+/// Add `span` to a row's spans, replacing those it overlaps and joining
+/// the one it continues.
+fn insert(spans: &mut Vec<Span>, span: Span) {
+    spans.retain(|s| s.end <= span.start || s.start >= span.end);
+    match spans
+        .iter_mut()
+        .find(|s| s.end == span.start && s.id == span.id)
+    {
+        Some(before) => {
+            before.end = span.end;
+            before.text.push_str(&span.text);
+        }
+        None => {
+            let at = spans.partition_point(|s| s.start < span.start);
+            spans.insert(at, span);
+        }
+    }
+}
+
+// JNI functions
+
+/// URI of the link at `row`, `col` of the screen, or null if there is none
+/// (or for an invalid handle).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtLinkAt<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    row: jint,
+    col: jint,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+        let (Ok(row), Ok(col)) = (usize::try_from(row), usize::try_from(col)) else {
+            return JString::default();
+        };
+
+        match vt.link_at(row, col) {
+            Some(uri) => env.new_string(uri).unwrap_or_default(),
+            None => JString::default(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn links_cover_the_text_printed_inside_them() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.feed(b"see \x1b]8;;https://a.example\x1b\\docs\x1b]8;;\x1b\\ and ");
+        vt.feed(b"\x1b]8;id=x;https://b.example\x07li");
+        vt.feed(b"nk\x1b]8;;\x07");
+
+        assert_eq!(vt.link_at(0, 3), None);
+        assert_eq!(vt.link_at(0, 4), Some("https://a.example"));
+        assert_eq!(vt.link_at(0, 7), Some("https://a.example"));
+        assert_eq!(vt.link_at(0, 8), None);
+        assert_eq!(vt.link_at(0, 13), Some("https://b.example"));
+        assert_eq!(vt.link_at(0, 16), Some("https://b.example"));
+        assert_eq!(vt.link_at(0, 17), None);
+
+        let screen = vt.screen();
+        let runs: Vec<_> = screen.lines[0]
+            .runs
+            .iter()
+            .map(|run| (run.text.as_str(), run.link))
+            .collect();
+        assert_eq!(
+            runs[..4],
+            [("see ", 0), ("docs", 1), (" and ", 0), ("link", 2)]
+        );
+        assert_eq!(
+            screen.links,
+            [
+                (1, "https://a.example".to_string()),
+                (2, "https://b.example".to_string())
+            ]
+        );
+        let decoded = crate::snapshot::decode(&vt.encode_snapshot()).unwrap();
+        assert_eq!(decoded, screen);
+    }
+
+    #[test]
+    fn spans_follow_scrolls_and_yield_to_new_text() {
+        let mut links = Links::new(3);
+        let mut backend = fake(8, 3);
+        backend.feed_str("ab");
+        links.handle(b"u", &backend);
+        backend.feed_str("cd");
+        links.handle(b"", &backend);
+        assert_eq!(links.at(0, 2), Some("u"));
+
+        links.apply(LineOp::ReverseIndex, 0);
+        assert_eq!((links.at(0, 2), links.at(1, 2)), (None, Some("u")));
+        links.apply(LineOp::ScrollUp(1), 2);
+        links.prune(&backend);
+        assert_eq!(links.at(0, 2), Some("u"));
+
+        backend.reset(8, 3);
+        backend.feed_str("abxy");
+        links.prune(&backend);
+        assert!(!links.is_tracking());
+        assert_eq!(osc8(&Action::Osc(b"8;;")), Some(&b""[..]));
+        assert_eq!(osc8(&Action::Osc(b"8")), None);
+    }
+}
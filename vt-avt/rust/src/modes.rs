@@ -0,0 +1,141 @@
+//! The input modes the program inside the VT has set.
+//!
+//! An interactive terminal has to know how the program wants its input:
+//! pasted text wrapped in `CSI 200~`/`CSI 201~` (bracketed paste, mode
+//! 2004), mouse events and in which encoding (modes 1000, 1002, 1003 and
+//! 1006), and cursor and keypad keys in their application forms (DECCKM,
+//! mode 1, and DECKPAM `ESC =` / DECKPNM `ESC >`). avt ignores most of
+//! these, so the wrapper tracks them from the sequences it scans: `vtModes`
+//! returns the bits below, and a diff has `MODES_CHANGED` set in its
+//! `resized` byte (see `diff`) when they changed since the last one.
+//!
+//! RIS and resets clear every mode, as do a fresh VT's. `dump_ansi` ends
+//! with the sequences that set them again, so a restored VT reports the
+//! same modes.
+
+use crate::scan::{Action, Csi};
+use crate::{handles, VtHandle};
+use jni::objects::JClass;
+use jni::sys::jint;
+use jni::JNIEnv;
+
+/// DECCKM: cursor keys send `ESC O` sequences
+pub const APP_CURSOR_KEYS: u32 = 0x01;
+/// DECKPAM: the keypad sends application sequences
+pub const APP_KEYPAD: u32 = 0x02;
+/// Mode 2004
+pub const BRACKETED_PASTE: u32 = 0x04;
+/// Mode 1000: button presses and releases
+pub const MOUSE_CLICKS: u32 = 0x08;
+/// Mode 1002: also motion with a button held
+pub const MOUSE_DRAG: u32 = 0x10;
+/// Mode 1003: also motion without a button
+pub const MOUSE_MOTION: u32 = 0x20;
+/// Mode 1006: mouse events in the SGR encoding
+pub const MOUSE_SGR: u32 = 0x40;
+
+/// DEC private mode numbers of the modes set with `CSI ? Pm h`
+const PRIVATE: [(u16, u32); 6] = [
+    (1, APP_CURSOR_KEYS),
+    (2004, BRACKETED_PASTE),
+    (1000, MOUSE_CLICKS),
+    (1002, MOUSE_DRAG),
+    (1003, MOUSE_MOTION),
+    (1006, MOUSE_SGR),
+];
+
+/// `modes` after `action`.
+pub fn apply(modes: u32, action: &Action) -> u32 {
+    match action {
+        Action::Csi(csi) => apply_csi(modes, csi),
+        Action::Esc {
+            intermediate: None,
+            final_byte,
+        } => match final_byte {
+            b'=' => modes | APP_KEYPAD,
+            b'>' => modes & !APP_KEYPAD,
+            // RIS
+            b'c' => 0,
+            _ => modes,
+        },
+        _ => modes,
+    }
+}
+
+fn apply_csi(modes: u32, csi: &Csi) -> u32 {
+    if csi.marker != Some(b'?') {
+        return modes;
+    }
+    let set = match csi.final_byte {
+        b'h' => true,
+        b'l' => false,
+        _ => return modes,
+    };
+    csi.params().iter().fold(modes, |modes, param| {
+        match PRIVATE.iter().find(|(mode, _)| mode == param) {
+            Some((_, bit)) if set => modes | bit,
+            Some((_, bit)) => modes & !bit,
+            None => modes,
+        }
+    })
+}
+
+/// Sequences that set `modes` in a fresh terminal.
+pub fn to_ansi(modes: u32) -> String {
+    let mut out = String::new();
+    for (mode, bit) in PRIVATE {
+        if modes & bit != 0 {
+            out.push_str(&format!("\x1b[?{}h", mode));
+        }
+    }
+    if modes & APP_KEYPAD != 0 {
+        out.push_str("\x1b=");
+    }
+    out
+}
+
+// JNI functions
+
+/// The VT's mode bits, see the module docs; 0 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtModes(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jint {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.modes() as jint
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::{diff, AvtState};
+
+    #[test]
+    fn tracks_modes_and_flags_changes_in_the_diff() {
+        let mut vt = AvtState::with_backend(fake(10, 2));
+        vt.poll_diff();
+        vt.feed(b"\x1b[?2004h\x1b[?1000;1006h\x1b=");
+        assert_eq!(vt.modes(), BRACKETED_PASTE | MOUSE_CLICKS | MOUSE_SGR | APP_KEYPAD);
+        let diff = diff::decode(&vt.poll_diff().unwrap()).unwrap();
+        assert!(diff.modes_changed);
+
+        // Setting a mode already set changes nothing
+        vt.feed(b"\x1b[?2004h");
+        assert!(!diff::decode(&vt.poll_diff().unwrap()).unwrap().modes_changed);
+
+        vt.feed(b"\x1b[?1000l\x1b>");
+        assert_eq!(vt.modes(), BRACKETED_PASTE | MOUSE_SGR);
+        assert!(vt.dump_ansi().ends_with("\x1b[?2004h\x1b[?1006h"));
+
+        vt.feed(b"\x1bc");
+        assert_eq!(vt.modes(), 0);
+    }
+}
@@ -0,0 +1,344 @@
+//! Raw TCP and telnet byte streams, behind the `net` feature.
+//!
+//! For serial consoles exposed over the network (ser2net, terminal
+//! servers): a `Connection` reads what the device prints and feeds it to a
+//! VT, which is then rendered like a recording. Reads happen on the
+//! caller's thread, one `pump` at a time, so the VT is never touched from
+//! two threads.
+//!
+//! In telnet mode IAC sequences are stripped from the stream. Option
+//! negotiation is answered as a dumb client would, accepting only the
+//! server's ECHO and SUPPRESS-GO-AHEAD and offering nothing, and only
+//! state changes are acknowledged (RFC 1143) so negotiation can't loop.
+
+use crate::backend::TerminalBackend;
+use crate::handles::{self, Kind};
+use crate::{AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    Iac,
+    /// After IAC WILL/WONT/DO/DONT
+    Option(u8),
+    /// Inside IAC SB ... IAC SE
+    Sub,
+    SubIac,
+}
+
+/// Telnet stream decoder.
+#[derive(Debug)]
+pub struct Telnet {
+    state: State,
+    /// Options the server has enabled on its side
+    remote: [bool; 256],
+}
+
+impl Default for Telnet {
+    fn default() -> Self {
+        Telnet {
+            state: State::Data,
+            remote: [false; 256],
+        }
+    }
+}
+
+impl Telnet {
+    /// Strip telnet commands from `input`, appending the data bytes to
+    /// `data` and any negotiation answers to `replies`. Commands may be
+    /// split across calls.
+    pub fn strip(&mut self, input: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL..=DONT) => State::Option(byte),
+                (State::Iac, SB) => State::Sub,
+                // NOP, GA and the rest carry nothing for a viewer
+                (State::Iac, _) => State::Data,
+                (State::Option(command), option) => {
+                    self.negotiate(command, option, replies);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => State::Sub,
+                (State::SubIac, SE) => State::Data,
+                (State::SubIac, _) => State::Sub,
+            };
+        }
+    }
+
+    fn negotiate(&mut self, command: u8, option: u8, replies: &mut Vec<u8>) {
+        let enabled = &mut self.remote[option as usize];
+        let reply = match command {
+            WILL if matches!(option, ECHO | SUPPRESS_GO_AHEAD) => {
+                (!mem::replace(enabled, true)).then_some(DO)
+            }
+            WILL => Some(DONT),
+            WONT => mem::replace(enabled, false).then_some(DONT),
+            // We enable nothing on our side, so DONT is already true
+            DO => Some(WONT),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            replies.extend_from_slice(&[IAC, reply, option]);
+        }
+    }
+}
+
+/// Encode user input for a telnet stream: IAC doubled, and a bare CR
+/// sent as CR NUL as RFC 854 requires.
+pub fn telnet_escape(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    for (i, &byte) in input.iter().enumerate() {
+        out.push(byte);
+        match byte {
+            IAC => out.push(IAC),
+            b'\r' if input.get(i + 1) != Some(&b'\n') => out.push(0),
+            _ => {}
+        }
+    }
+    out
+}
+
+pub struct Connection {
+    stream: TcpStream,
+    telnet: Option<Telnet>,
+    buf: Vec<u8>,
+}
+
+registered!(Connection, Kind::Connection);
+
+impl Connection {
+    pub fn connect(host: &str, port: u16, telnet: bool, timeout: Duration) -> io::Result<Self> {
+        let mut last = io::Error::new(ErrorKind::NotFound, "host has no addresses");
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(Connection::new(stream, telnet)),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    pub fn new(stream: TcpStream, telnet: bool) -> Self {
+        let _ = stream.set_nodelay(true);
+        Connection {
+            stream,
+            telnet: telnet.then(Telnet::default),
+            buf: vec![0; 4096],
+        }
+    }
+
+    /// Wait up to `timeout` for output and feed what arrives to `state`.
+    /// Returns the number of bytes read (0 if none arrived in time), or
+    /// `None` once the peer has closed the connection.
+    pub fn pump<B: TerminalBackend>(
+        &mut self,
+        state: &mut AvtState<B>,
+        timeout: Duration,
+    ) -> io::Result<Option<usize>> {
+        // A zero timeout would mean blocking forever
+        self.stream
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let n = match self.stream.read(&mut self.buf) {
+            Ok(0) => return Ok(None),
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(Some(0))
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => return Ok(Some(0)),
+            Err(e) => return Err(e),
+        };
+
+        match &mut self.telnet {
+            Some(telnet) => {
+                let mut data = Vec::with_capacity(n);
+                let mut replies = Vec::new();
+                telnet.strip(&self.buf[..n], &mut data, &mut replies);
+                if !replies.is_empty() {
+                    self.stream.write_all(&replies)?;
+                }
+                state.feed(&data);
+            }
+            None => state.feed(&self.buf[..n]),
+        }
+        Ok(Some(n))
+    }
+
+    /// Send user input (or `vtTakeResponses` answers) to the device.
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.telnet.is_some() {
+            self.stream.write_all(&telnet_escape(bytes))
+        } else {
+            self.stream.write_all(bytes)
+        }
+    }
+}
+
+// JNI functions
+
+/// Connect, waiting up to `timeout_ms`. Returns 0 on failure.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_netConnect(
+    mut env: JNIEnv,
+    _class: JClass,
+    host: JString,
+    port: jint,
+    telnet: jboolean,
+    timeout_ms: jint,
+) -> jlong {
+    jni_guard!(env, {
+        let Ok(host) = env.get_string(&host) else {
+            return 0;
+        };
+        let Ok(port) = u16::try_from(port) else {
+            return 0;
+        };
+        let host: String = host.into();
+        let timeout = Duration::from_millis(timeout_ms.max(1) as u64);
+        match Connection::connect(&host, port, telnet != JNI_FALSE, timeout) {
+            Ok(connection) => handles::add(connection),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_netFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Connection>(&mut env, handle));
+    })
+}
+
+/// Bytes read into the VT, 0 on timeout, -1 once closed or failed.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_netPump(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    vt: VtHandle,
+    timeout_ms: jint,
+) -> jint {
+    jni_guard!(env, {
+        let Some(connection) = handles::object::<Connection>(&mut env, handle) else {
+            return -1;
+        };
+        let Some(vt) = handles::get(&mut env, vt) else {
+            return -1;
+        };
+
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+        match connection.pump(vt, timeout) {
+            Ok(Some(n)) => n as jint,
+            Ok(None) | Err(_) => -1,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_netSend(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    bytes: JByteArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(connection) = handles::object::<Connection>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return JNI_FALSE;
+        };
+        match connection.send(&bytes) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use std::net::TcpListener;
+
+    #[test]
+    fn telnet_commands_are_stripped_and_answered() {
+        let mut telnet = Telnet::default();
+        let mut data = Vec::new();
+        let mut replies = Vec::new();
+        let stream = [
+            b"a".as_slice(),
+            &[IAC, WILL, ECHO, IAC, WILL, 24, IAC, DO, 31],
+            &[IAC, IAC, b'b', IAC, SB, 24, 1, IAC, IAC, IAC],
+            &[SE, b'c', IAC],
+            &[WILL, ECHO, IAC, 241],
+        ];
+        for chunk in stream {
+            telnet.strip(chunk, &mut data, &mut replies);
+        }
+        assert_eq!(data, [b'a', IAC, b'b', b'c']);
+        // The repeated WILL ECHO changes nothing, so it isn't answered
+        assert_eq!(replies, [IAC, DO, ECHO, IAC, DONT, 24, IAC, WONT, 31]);
+
+        assert_eq!(telnet_escape(b"\xff\r\r\n"), b"\xff\xff\r\0\r\n");
+    }
+
+    #[test]
+    fn pumps_a_raw_stream_into_the_vt() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut connection =
+            Connection::connect("127.0.0.1", port, false, Duration::from_secs(5)).unwrap();
+        let (mut device, _) = listener.accept().unwrap();
+
+        let mut state = AvtState::with_backend(fake(4, 1));
+        let idle = connection.pump(&mut state, Duration::from_millis(10));
+        assert_eq!(idle.unwrap(), Some(0));
+
+        device.write_all(b"ok").unwrap();
+        let n = connection.pump(&mut state, Duration::from_secs(5)).unwrap();
+        assert_eq!(n, Some(2));
+        assert_eq!(state.backend().row_text(0), "ok  ");
+
+        connection.send(b"x").unwrap();
+        let mut sent = [0];
+        device.read_exact(&mut sent).unwrap();
+        assert_eq!(&sent, b"x");
+
+        drop(device);
+        let closed = connection.pump(&mut state, Duration::from_secs(5));
+        assert_eq!(closed.unwrap(), None);
+    }
+}
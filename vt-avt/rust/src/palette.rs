@@ -0,0 +1,233 @@
+//! Style resolution for renderers.
+//!
+//! Turns style ids (`Style::id`) into final colors in one pass: palette
+//! lookup, bold-as-bright, inverse and a minimum contrast ratio, so clients
+//! can build their span caches without reimplementing these rules.
+
+use crate::snapshot::{Color, Style, ATTR_BOLD, ATTR_INVERSE};
+use jni::objects::JIntArray;
+use jni::JNIEnv;
+
+/// Options bit: bold text in colors 0-7 uses the bright variant (8-15)
+pub const RESOLVE_BOLD_AS_BRIGHT: u32 = 0x01;
+
+/// Colors as `0xRRGGBB`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub fg: u32,
+    pub bg: u32,
+    pub colors: [u32; 256],
+}
+
+impl Default for Palette {
+    /// xterm colors, matching `Theme.DEFAULT` on the Kotlin side
+    fn default() -> Self {
+        const BASE: [u32; 16] = [
+            0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+            0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+        ];
+        let mut colors = [0; 256];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = match i {
+                0..=15 => BASE[i],
+                16..=231 => {
+                    let i = i as u32 - 16;
+                    (i / 36 * 51) << 16 | (i % 36 / 6 * 51) << 8 | (i % 6 * 51)
+                }
+                _ => (8 + (i as u32 - 232) * 10) * 0x010101,
+            };
+        }
+        Palette {
+            fg: 0xcccccc,
+            bg: 0x000000,
+            colors,
+        }
+    }
+}
+
+impl Palette {
+    /// `[fg, bg, color0, color1, ...]` as `0xRRGGBB`. Missing entries keep
+    /// the default; extra entries are ignored.
+    pub fn from_ints(values: &[i32]) -> Self {
+        let mut palette = Palette::default();
+        let mut values = values.iter().map(|&v| v as u32 & 0xffffff);
+        if let Some(fg) = values.next() {
+            palette.fg = fg;
+        }
+        if let Some(bg) = values.next() {
+            palette.bg = bg;
+        }
+        for (slot, value) in palette.colors.iter_mut().zip(values) {
+            *slot = value;
+        }
+        palette
+    }
+
+    /// `from_ints` of a Java `int[]`, `None` if it can't be read.
+    pub fn from_java(env: &JNIEnv, values: &JIntArray) -> Option<Self> {
+        let mut ints = vec![0; env.get_array_length(values).ok()? as usize];
+        env.get_int_array_region(values, 0, &mut ints).ok()?;
+        Some(Palette::from_ints(&ints))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub bold_as_bright: bool,
+    /// WCAG contrast ratio the foreground must reach against the
+    /// background; 1.0 or less disables the adjustment
+    pub min_contrast: f32,
+}
+
+impl Options {
+    pub fn new(flags: u32, min_contrast: f32) -> Self {
+        Options {
+            bold_as_bright: flags & RESOLVE_BOLD_AS_BRIGHT != 0,
+            min_contrast,
+        }
+    }
+}
+
+/// Final colors for a style, as opaque ARGB (Android color ints).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolved {
+    pub fg: u32,
+    pub bg: u32,
+    /// `ATTR_*` bits, with `ATTR_INVERSE` cleared since fg and bg are
+    /// already swapped
+    pub attrs: u8,
+}
+
+pub fn resolve(style: Style, palette: &Palette, options: &Options) -> Resolved {
+    let bold = style.attrs & ATTR_BOLD != 0;
+    let mut fg = match style.fg {
+        Color::Indexed(idx) if options.bold_as_bright && bold && idx < 8 => {
+            palette.colors[idx as usize + 8]
+        }
+        color => rgb_of(color, palette, palette.fg),
+    };
+    let mut bg = rgb_of(style.bg, palette, palette.bg);
+
+    if style.attrs & ATTR_INVERSE != 0 {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    if options.min_contrast > 1.0 {
+        fg = ensure_contrast(fg, bg, options.min_contrast);
+    }
+
+    Resolved {
+        fg: 0xff000000 | fg,
+        bg: 0xff000000 | bg,
+        attrs: style.attrs & !ATTR_INVERSE,
+    }
+}
+
+fn rgb_of(color: Color, palette: &Palette, default: u32) -> u32 {
+    match color {
+        Color::Default => default,
+        Color::Indexed(idx) => palette.colors[idx as usize],
+        Color::Rgb(r, g, b) => (r as u32) << 16 | (g as u32) << 8 | b as u32,
+    }
+}
+
+fn luminance(rgb: u32) -> f32 {
+    let channel = |shift: u32| {
+        let c = (rgb >> shift & 0xff) as f32 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(16) + 0.7152 * channel(8) + 0.0722 * channel(0)
+}
+
+fn contrast(a: u32, b: u32) -> f32 {
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+fn mix(from: u32, to: u32, t: f32) -> u32 {
+    let channel = |shift: u32| {
+        let (f, t2) = ((from >> shift & 0xff) as f32, (to >> shift & 0xff) as f32);
+        ((f + (t2 - f) * t).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+/// Move `fg` toward black or white, whichever contrasts more with `bg`,
+/// just far enough to reach `min` (or as far as possible).
+fn ensure_contrast(fg: u32, bg: u32, min: f32) -> u32 {
+    if contrast(fg, bg) >= min {
+        return fg;
+    }
+    let target = if contrast(0xffffff, bg) >= contrast(0x000000, bg) {
+        0xffffff
+    } else {
+        0x000000
+    };
+
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..12 {
+        let mid = (lo + hi) / 2.0;
+        if contrast(mix(fg, target, mid), bg) >= min {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    mix(fg, target, hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(fg: Color, bg: Color, attrs: u8) -> Style {
+        Style { fg, bg, attrs }
+    }
+
+    #[test]
+    fn resolves_through_palette_and_options() {
+        let palette = Palette::default();
+        let plain = Options::new(0, 1.0);
+        let bright = Options::new(RESOLVE_BOLD_AS_BRIGHT, 1.0);
+
+        let default = resolve(Style::default(), &palette, &plain);
+        assert_eq!((default.fg, default.bg), (0xffcccccc, 0xff000000));
+
+        let bold_red = style(Color::Indexed(1), Color::Indexed(244), ATTR_BOLD);
+        assert_eq!(resolve(bold_red, &palette, &plain).fg, 0xffcd0000);
+        assert_eq!(resolve(bold_red, &palette, &bright).fg, 0xffff0000);
+        assert_eq!(resolve(bold_red, &palette, &plain).bg, 0xff808080);
+
+        let inverse = style(Color::Rgb(1, 2, 3), Color::Default, ATTR_INVERSE | ATTR_BOLD);
+        let resolved = resolve(inverse, &palette, &plain);
+        assert_eq!((resolved.fg, resolved.bg), (0xff000000, 0xff010203));
+        assert_eq!(resolved.attrs, ATTR_BOLD);
+    }
+
+    #[test]
+    fn min_contrast_adjusts_only_low_contrast_pairs() {
+        let palette = Palette::from_ints(&[0x333333, 0x222222]);
+        let options = Options::new(0, 4.5);
+
+        let resolved = resolve(Style::default(), &palette, &options);
+        assert!(contrast(resolved.fg & 0xffffff, 0x222222) >= 4.5);
+        assert!(resolved.fg & 0xffffff > 0x333333);
+
+        let readable = style(Color::Rgb(0xff, 0xff, 0xff), Color::Default, 0);
+        assert_eq!(resolve(readable, &palette, &options).fg, 0xffffffff);
+    }
+
+    #[test]
+    fn palette_overrides_keep_remaining_defaults() {
+        let palette = Palette::from_ints(&[0x111111, 0x222222, 0x333333]);
+        assert_eq!((palette.fg, palette.bg), (0x111111, 0x222222));
+        assert_eq!(palette.colors[0], 0x333333);
+        assert_eq!(palette.colors[1], Palette::default().colors[1]);
+        assert_eq!(palette.colors[16], 0x000000);
+        assert_eq!(palette.colors[231], 0xffffff);
+        assert_eq!(palette.colors[255], 0xeeeeee);
+    }
+}
@@ -0,0 +1,363 @@
+//! tmux/screen layout detection.
+//!
+//! Finds a multiplexer's status bar and pane rectangles on the visible
+//! grid, so the app can offer "zoom into pane" for recordings made inside
+//! tmux or screen. Purely heuristic, and only run when asked for.
+//!
+//! Multiplexer layouts are built by repeated splits, so the grid is cut
+//! recursively at full-height or full-width runs of box-drawing border
+//! characters; whatever can't be cut further is a pane.
+//!
+//! `crop` scopes a snapshot to one pane; text extraction, search and
+//! frame export of a pane all go through it.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::snapshot::{Color, Cursor, Line, Run, Screen};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub col: usize,
+    pub row: usize,
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl Rect {
+    /// The part of `self` inside a `cols` x `rows` screen.
+    pub fn clamp(self, cols: usize, rows: usize) -> Rect {
+        let col = self.col.min(cols);
+        let row = self.row.min(rows);
+        Rect {
+            col,
+            row,
+            cols: self.cols.min(cols - col),
+            rows: self.rows.min(rows - row),
+        }
+    }
+
+    fn contains(&self, col: usize, row: usize) -> bool {
+        (self.col..self.col + self.cols).contains(&col)
+            && (self.row..self.row + self.rows).contains(&row)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Layout {
+    /// Status bar row (top or bottom), if one was found
+    pub status_row: Option<usize>,
+    /// Panes in reading order; a single pane covering the screen when the
+    /// grid isn't split
+    pub panes: Vec<Rect>,
+}
+
+pub fn detect(backend: &impl TerminalBackend) -> Layout {
+    let grid: Vec<Vec<Cell>> = (0..backend.size().1)
+        .map(|row| {
+            let mut cells = Vec::new();
+            backend.row_cells(row, &mut cells);
+            cells
+        })
+        .collect();
+    detect_grid(&grid)
+}
+
+pub fn detect_grid(grid: &[Vec<Cell>]) -> Layout {
+    let rows = grid.len();
+    let cols = grid.iter().map(Vec::len).min().unwrap_or(0);
+    if rows == 0 || cols == 0 {
+        return Layout::default();
+    }
+
+    let status_row = [rows - 1, 0]
+        .into_iter()
+        .find(|&row| rows > 1 && is_status_bar(&grid[row][..cols]));
+    let top = if status_row == Some(0) { 1 } else { 0 };
+    let area = Rect {
+        col: 0,
+        row: top,
+        cols,
+        rows: rows - status_row.map_or(0, |_| 1),
+    };
+
+    let mut panes = Vec::new();
+    split(grid, area, &mut panes);
+    Layout { status_row, panes }
+}
+
+/// A row where nearly every cell shares one non-default background.
+fn is_status_bar(cells: &[Cell]) -> bool {
+    let bg = cells[0].style.bg;
+    let same = cells.iter().filter(|cell| cell.style.bg == bg).count();
+    bg != Color::Default && same * 10 >= cells.len() * 9
+}
+
+fn is_vertical_border(ch: char) -> bool {
+    matches!(ch, '│' | '┃' | '║' | '├' | '┤' | '┼' | '┬' | '┴')
+}
+
+fn is_horizontal_border(ch: char) -> bool {
+    matches!(ch, '─' | '━' | '═' | '├' | '┤' | '┼' | '┬' | '┴')
+}
+
+fn split(grid: &[Vec<Cell>], area: Rect, panes: &mut Vec<Rect>) {
+    if area.cols == 0 || area.rows == 0 {
+        return;
+    }
+    let (right, bottom) = (area.col + area.cols, area.row + area.rows);
+
+    // A border needs a pane on both sides, so never the first or last line
+    for col in area.col + 1..right.saturating_sub(1) {
+        if (area.row..bottom).all(|row| is_vertical_border(grid[row][col].ch)) {
+            split(grid, Rect { cols: col - area.col, ..area }, panes);
+            split(grid, Rect { col: col + 1, cols: right - col - 1, ..area }, panes);
+            return;
+        }
+    }
+    for row in area.row + 1..bottom.saturating_sub(1) {
+        if grid[row][area.col..right].iter().all(|cell| is_horizontal_border(cell.ch)) {
+            split(grid, Rect { rows: row - area.row, ..area }, panes);
+            split(grid, Rect { row: row + 1, rows: bottom - row - 1, ..area }, panes);
+            return;
+        }
+    }
+
+    panes.push(area);
+}
+
+/// The part of `screen` inside `rect`, as a screen of its own: runs are
+/// clipped and rebased, and the cursor is hidden unless it's inside.
+pub fn crop(screen: &Screen, rect: Rect) -> Screen {
+    let rect = rect.clamp(screen.cols, screen.rows.min(screen.lines.len()));
+    let lines = screen.lines[rect.row..rect.row + rect.rows]
+        .iter()
+        .map(|line| Line {
+            attr: line.attr,
+            runs: line.runs.iter().filter_map(|run| clip(run, rect)).collect(),
+        })
+        .collect();
+
+    let cursor = screen.cursor;
+    let cursor = if rect.contains(cursor.col, cursor.row) {
+        Cursor {
+            col: cursor.col - rect.col,
+            row: cursor.row - rect.row,
+            ..cursor
+        }
+    } else {
+        Cursor {
+            col: 0,
+            row: 0,
+            visible: false,
+            ..cursor
+        }
+    };
+
+    Screen {
+        cols: rect.cols,
+        rows: rect.rows,
+        cursor,
+        lines,
+        links: screen.links.clone(),
+        alt_screen: screen.alt_screen,
+        // A placement can't start left of the pane, so images stay out
+        images: Vec::new(),
+    }
+}
+
+/// The chars of `run` wholly inside `rect`; a wide char on its edge is
+/// dropped.
+fn clip(run: &Run, rect: Rect) -> Option<Run> {
+    let width = run.char_width();
+    let end = rect.col + rect.cols;
+    let mut kept = run
+        .columns()
+        .filter(|&(col, _)| col >= rect.col && col + width <= end)
+        .peekable();
+    let &(col, _) = kept.peek()?;
+    let text: String = kept.map(|(_, ch)| ch).collect();
+    Some(Run {
+        col: col - rect.col,
+        cells: text.chars().count() * width,
+        text,
+        style: run.style,
+        link: run.link,
+    })
+}
+
+/// Chars of `line` with their columns, gaps filled with blanks. The second
+/// column of a wide char has none.
+fn line_chars(line: &Line, cols: usize) -> Vec<(usize, char)> {
+    let mut slots = vec![Some(' '); cols];
+    for run in &line.runs {
+        let width = run.char_width();
+        for (col, ch) in run.columns().filter(|&(col, _)| col < cols) {
+            slots[col] = Some(ch);
+            for slot in slots.iter_mut().skip(col + 1).take(width - 1) {
+                *slot = None;
+            }
+        }
+    }
+    slots
+        .into_iter()
+        .enumerate()
+        .filter_map(|(col, ch)| Some((col, ch?)))
+        .collect()
+}
+
+/// Visible text of each line, gaps filled with blanks and trailing
+/// blanks trimmed.
+pub fn lines_text(screen: &Screen) -> Vec<String> {
+    screen
+        .lines
+        .iter()
+        .map(|line| {
+            let chars = line_chars(line, screen.cols);
+            let text: String = chars.into_iter().map(|(_, ch)| ch).collect();
+            text.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// `(row, col)` of each non-overlapping occurrence of `query`. Matches
+/// don't span lines.
+pub fn search(screen: &Screen, query: &str) -> Vec<(usize, usize)> {
+    let needle: Vec<char> = query.chars().collect();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits = Vec::new();
+    for (row, line) in screen.lines.iter().enumerate() {
+        let hay = line_chars(line, screen.cols);
+        let mut i = 0;
+        while i + needle.len() <= hay.len() {
+            if hay[i..i + needle.len()].iter().map(|&(_, ch)| ch).eq(needle.iter().copied()) {
+                hits.push((row, hay[i].0));
+                i += needle.len();
+            } else {
+                i += 1;
+            }
+        }
+    }
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Style;
+
+    fn grid(lines: &[&str], status_row: Option<usize>) -> Vec<Vec<Cell>> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let bg = if Some(row) == status_row { Color::Indexed(2) } else { Color::Default };
+                line.chars()
+                    .map(|ch| Cell {
+                        ch,
+                        style: Style { bg, ..Style::default() },
+                        width: 1,
+                        link: 0,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn rect(col: usize, row: usize, cols: usize, rows: usize) -> Rect {
+        Rect { col, row, cols, rows }
+    }
+
+    #[test]
+    fn finds_nested_tmux_panes_and_status_bar() {
+        let lines = [
+            "vim  │top  ",
+            "     │     ",
+            "     ├─────",
+            "     │$ ls ",
+            "[0] 0:bash*",
+        ];
+        let layout = detect_grid(&grid(&lines, Some(4)));
+        assert_eq!(layout.status_row, Some(4));
+        assert_eq!(
+            layout.panes,
+            vec![rect(0, 0, 5, 4), rect(6, 0, 5, 2), rect(6, 3, 5, 1)]
+        );
+    }
+
+    #[test]
+    fn status_bar_on_top_and_horizontal_first_split() {
+        let lines = ["[0] 0:zsh* ", "a  │b  ", "───┴───", "c      "];
+        let layout = detect_grid(&grid(&lines, Some(0)));
+        assert_eq!(layout.status_row, Some(0));
+        assert_eq!(
+            layout.panes,
+            vec![rect(0, 1, 3, 1), rect(4, 1, 3, 1), rect(0, 3, 7, 1)]
+        );
+    }
+
+    fn screen_of(grid: &[Vec<Cell>], cursor: (usize, usize)) -> Screen {
+        Screen {
+            cols: grid[0].len(),
+            rows: grid.len(),
+            cursor: Cursor {
+                col: cursor.0,
+                row: cursor.1,
+                ..Cursor::default()
+            },
+            lines: grid
+                .iter()
+                .map(|cells| Line {
+                    attr: Default::default(),
+                    runs: vec![Run {
+                        col: 0,
+                        text: cells.iter().map(|c| c.ch).collect(),
+                        cells: cells.len(),
+                        style: cells[0].style,
+                        link: 0,
+                    }],
+                })
+                .collect(),
+            links: Vec::new(),
+            alt_screen: false,
+            images: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pane_text_search_and_crop() {
+        let lines = [
+            "ls   │top  ",
+            "a b  │ok ok",
+            "     ├─────",
+            "     │$ ls ",
+            "[0] 0:bash*",
+        ];
+        let grid = grid(&lines, Some(4));
+        let screen = screen_of(&grid, (8, 3));
+        let panes = detect_grid(&grid).panes;
+
+        let right_top = crop(&screen, panes[1]);
+        assert_eq!(lines_text(&right_top), ["top", "ok ok"]);
+        assert!(!right_top.cursor.visible);
+        assert_eq!(search(&right_top, "ok"), [(1, 0), (1, 3)]);
+
+        let right_bottom = crop(&screen, panes[2]);
+        assert_eq!(right_bottom.cursor, Cursor { col: 2, row: 0, ..Cursor::default() });
+        assert_eq!(search(&right_bottom, "ls"), [(0, 2)]);
+        assert_eq!(search(&screen, "ls"), [(0, 0), (3, 8)]);
+
+        let left = crop(&screen, Rect { col: 0, row: 0, cols: 5, rows: 99 });
+        assert_eq!((left.cols, left.rows), (5, 5));
+        assert_eq!(lines_text(&left)[1], "a b");
+    }
+
+    #[test]
+    fn plain_screen_is_one_pane() {
+        let lines = ["$ cat table", "| a | b |  ", "|---|---|  "];
+        let layout = detect_grid(&grid(&lines, None));
+        assert_eq!(layout.status_row, None);
+        assert_eq!(layout.panes, vec![rect(0, 0, 11, 3)]);
+    }
+}
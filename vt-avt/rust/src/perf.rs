@@ -0,0 +1,199 @@
+//! Per-VT performance counters, to tell native jank from the app's own.
+//!
+//! A dropped frame during playback can be the parser falling behind a
+//! burst, snapshot or diff encoding, or Compose recomposing too much; only
+//! the native side can tell how long it took itself. Each VT counts the
+//! bytes and feeds it was given and the nanoseconds spent parsing them,
+//! encoding snapshots and encoding diffs, with the most lines one diff
+//! reported dirty. `vtStats` reads them; they're cumulative from the VT's
+//! creation, resets included, so the app diffs two reads for a window.
+//!
+//! With the `tracing` feature each timed call is also a span (`feed`,
+//! `snapshot`, `diff`, at trace level), for a subscriber such as
+//! `tracing-android` or Perfetto's to show beside the app's own trace
+//! sections. The counters are there either way; a timed call costs two
+//! clock reads.
+
+use crate::{handles, Instant, VtHandle};
+use jni::objects::{JClass, JLongArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+/// What a timed call counts towards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// A feed: the scan, the backend and what's tracked from the sequences
+    Parse,
+    /// Any form of snapshot, deltas included
+    Snapshot,
+    /// Any form of diff
+    Diff,
+}
+
+/// The counters as of a `Counters::stats` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub bytes_fed: u64,
+    pub feeds: u64,
+    pub parse_ns: u64,
+    pub snapshots: u64,
+    pub snapshot_ns: u64,
+    /// Diff polls, those with nothing to report included
+    pub diffs: u64,
+    pub diff_ns: u64,
+    /// Most lines one diff reported dirty
+    pub max_dirty_lines: u64,
+}
+
+impl Stats {
+    /// In `vtStats` order, as declared.
+    pub fn to_array(&self) -> [u64; 8] {
+        [
+            self.bytes_fed,
+            self.feeds,
+            self.parse_ns,
+            self.snapshots,
+            self.snapshot_ns,
+            self.diffs,
+            self.diff_ns,
+            self.max_dirty_lines,
+        ]
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    bytes_fed: AtomicU64,
+    feeds: AtomicU64,
+    parse_ns: AtomicU64,
+    snapshots: AtomicU64,
+    snapshot_ns: AtomicU64,
+    diffs: AtomicU64,
+    diff_ns: AtomicU64,
+    max_dirty_lines: AtomicU64,
+}
+
+/// One VT's counters. Shared with the timers running, so a timer can be
+/// held across the `&mut self` call it times.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    shared: Arc<Shared>,
+}
+
+impl Counters {
+    pub fn note_feed(&self, bytes: usize) {
+        self.shared.bytes_fed.fetch_add(bytes as u64, Relaxed);
+        self.shared.feeds.fetch_add(1, Relaxed);
+    }
+
+    pub fn note_dirty_lines(&self, lines: usize) {
+        self.shared.max_dirty_lines.fetch_max(lines as u64, Relaxed);
+    }
+
+    /// Time until the returned timer is dropped.
+    pub fn time(&self, timing: Timing) -> Timer {
+        Timer {
+            shared: Arc::clone(&self.shared),
+            timing,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            _span: match timing {
+                Timing::Parse => tracing::trace_span!("feed"),
+                Timing::Snapshot => tracing::trace_span!("snapshot"),
+                Timing::Diff => tracing::trace_span!("diff"),
+            }
+            .entered(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let s = &self.shared;
+        Stats {
+            bytes_fed: s.bytes_fed.load(Relaxed),
+            feeds: s.feeds.load(Relaxed),
+            parse_ns: s.parse_ns.load(Relaxed),
+            snapshots: s.snapshots.load(Relaxed),
+            snapshot_ns: s.snapshot_ns.load(Relaxed),
+            diffs: s.diffs.load(Relaxed),
+            diff_ns: s.diff_ns.load(Relaxed),
+            max_dirty_lines: s.max_dirty_lines.load(Relaxed),
+        }
+    }
+}
+
+/// Adds the time since it started to its counter when dropped.
+pub struct Timer {
+    shared: Arc<Shared>,
+    timing: Timing,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let ns = self.start.elapsed().as_nanos() as u64;
+        let s = &self.shared;
+        let (count, total) = match self.timing {
+            // Feeds are counted with their bytes, padding-only ones too
+            Timing::Parse => {
+                s.parse_ns.fetch_add(ns, Relaxed);
+                return;
+            }
+            Timing::Snapshot => (&s.snapshots, &s.snapshot_ns),
+            Timing::Diff => (&s.diffs, &s.diff_ns),
+        };
+        count.fetch_add(1, Relaxed);
+        total.fetch_add(ns, Relaxed);
+    }
+}
+
+// JNI functions
+
+/// `Stats::to_array` of the VT, see the module docs; empty for an invalid
+/// handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtStats<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let values = vt.stats().to_array().map(|value| value as jlong);
+        crate::long_array(&env, &values)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn counts_feeds_encodes_and_dirty_lines() {
+        let mut vt = AvtState::with_backend(fake(10, 3));
+        vt.feed(b"hello");
+        vt.feed(b"\0\0");
+        vt.encode_snapshot();
+        vt.poll_diff();
+        // Nothing changed, so no diff to encode
+        vt.poll_diff();
+
+        let stats = vt.stats();
+        assert_eq!((stats.bytes_fed, stats.feeds), (7, 2));
+        assert_eq!((stats.snapshots, stats.diffs), (1, 2));
+        assert_eq!(stats.max_dirty_lines, 3);
+        assert!(stats.parse_ns > 0);
+
+        // Kept across resets
+        vt.reset(10, 3);
+        assert_eq!(vt.stats().feeds, 2);
+    }
+}
@@ -0,0 +1,292 @@
+//! Whole VTs saved and restored, so playback survives process death.
+//!
+//! A saved state (see `state`) holds the screen, which is what a seek
+//! needs, but resuming a player the system killed also needs the
+//! scrollback, the options the app set and what the renderer hasn't been
+//! told yet. `vtSerialize` saves all of that around a saved state, and
+//! `vtDeserialize` makes a new VT of it:
+//!
+//! ```text
+//! vt     := "AVTP" version:u8 mode:u8 state_len state
+//!           scrollback_count (line_len line)* utf8_len utf8
+//!           flags:u8 cursor_policy:u8 dirty_count dirty_row* [theme]
+//! theme  := fg bg color*256
+//! ```
+//!
+//! `state` is a saved state as `vtSaveState` makes it, restored with the
+//! same compatibility rules; its dump replays the grid with its styles,
+//! line attributes, modes and cursor. Each scrollback line is ANSI (text
+//! and SGR) printed above the screen before the dump, as wide as the
+//! screen. `utf8` is the start of a character split across feeds. `flags`
+//! are bit 0 cursor changed, 1 resized, 2 bold-as-bright, 3 input events,
+//! 4 cursor shown since the last reset, and 5 a theme follows; with the
+//! dirty rows, they make the first diff after restoring the one the app
+//! would have polled next.
+//!
+//! The escape sequence parser can't be saved, so a VT with half a
+//! sequence fed isn't either: save between feeds, which usually end
+//! between sequences. Hyperlink and image tables, queued events,
+//! consumers, readers and interactive session state (the terminal
+//! config, predictions, activity) start over, as do retention limits,
+//! which the app sets again. Soft wraps in the scrollback become hard.
+
+use crate::backend::{push_clipped, TerminalBackend};
+use crate::palette::Palette;
+use crate::snapshot::{DecodeError, Reader};
+use crate::state::{self, Compat, StateError};
+use crate::{handles, write_varint, AvtState, CursorPolicy, VtHandle, VtMode};
+use jni::objects::{JByteArray, JClass};
+use jni::JNIEnv;
+
+const MAGIC: &[u8] = b"AVTP";
+
+/// Format version, after the magic
+pub const VERSION: u8 = 1;
+
+const FLAG_CURSOR_CHANGED: u8 = 0x01;
+const FLAG_RESIZED: u8 = 0x02;
+const FLAG_BOLD_AS_BRIGHT: u8 = 0x04;
+const FLAG_INPUT_EVENTS: u8 = 0x08;
+const FLAG_CURSOR_SHOWN: u8 = 0x10;
+const FLAG_THEME: u8 = 0x20;
+
+/// What a VT keeps besides its screen and scrollback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kept {
+    pub mode: VtMode,
+    pub utf8_partial: Vec<u8>,
+    pub dirty_rows: Vec<usize>,
+    pub cursor_changed: bool,
+    pub resized: bool,
+    pub cursor_policy: CursorPolicy,
+    pub theme: Option<Palette>,
+    pub bold_as_bright: bool,
+    pub input_events: bool,
+    pub cursor_shown: bool,
+}
+
+/// A decoded `vtSerialize` blob.
+pub struct Serialized<'a> {
+    /// Saved state of the screen, see `state`
+    pub state: &'a [u8],
+    pub scrollback: Vec<String>,
+    pub kept: Kept,
+}
+
+/// Save `vt` whole; `None` mid escape sequence.
+pub fn save<B: TerminalBackend>(vt: &AvtState<B>) -> Option<Vec<u8>> {
+    let kept = vt.kept()?;
+    let mut buf = MAGIC.to_vec();
+    buf.push(VERSION);
+    buf.push(kept.mode as u8);
+    let screen = state::save(vt);
+    write_varint(&mut buf, screen.len());
+    buf.extend_from_slice(&screen);
+
+    let backend = vt.backend();
+    let cols = backend.size().0;
+    let mut cells = Vec::new();
+    let mut line = String::new();
+    write_varint(&mut buf, backend.scrollback_len());
+    for index in 0..backend.scrollback_len() {
+        backend.scrollback_cells(index, &mut cells);
+        line.clear();
+        push_clipped(&cells, cols, &mut line);
+        write_varint(&mut buf, line.len());
+        buf.extend_from_slice(line.as_bytes());
+    }
+    write_varint(&mut buf, kept.utf8_partial.len());
+    buf.extend_from_slice(&kept.utf8_partial);
+
+    let flags = [
+        (kept.cursor_changed, FLAG_CURSOR_CHANGED),
+        (kept.resized, FLAG_RESIZED),
+        (kept.bold_as_bright, FLAG_BOLD_AS_BRIGHT),
+        (kept.input_events, FLAG_INPUT_EVENTS),
+        (kept.cursor_shown, FLAG_CURSOR_SHOWN),
+        (kept.theme.is_some(), FLAG_THEME),
+    ];
+    buf.push(
+        flags
+            .iter()
+            .filter(|(on, _)| *on)
+            .fold(0, |acc, (_, flag)| acc | flag),
+    );
+    buf.push(kept.cursor_policy as u8);
+    write_varint(&mut buf, kept.dirty_rows.len());
+    for &row in &kept.dirty_rows {
+        write_varint(&mut buf, row);
+    }
+    if let Some(theme) = &kept.theme {
+        for &color in [theme.fg, theme.bg].iter().chain(&theme.colors) {
+            write_varint(&mut buf, color as usize);
+        }
+    }
+    Some(buf)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Serialized<'_>, StateError> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(StateError::NotState);
+    };
+    let mut r = Reader::new(rest);
+    let version = r.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion { version }.into());
+    }
+    let mode = VtMode::from_code(r.byte()? as i32).unwrap_or_default();
+    let len = r.varint()?;
+    let state = r.take(len)?;
+    let scrollback = (0..r.varint()?)
+        .map(|_| {
+            let len = r.varint()?;
+            Ok(String::from_utf8_lossy(r.take(len)?).into_owned())
+        })
+        .collect::<Result<_, DecodeError>>()?;
+    let len = r.varint()?;
+    let utf8_partial = r.take(len)?.to_vec();
+    let flags = r.byte()?;
+    let cursor_policy = CursorPolicy::from_code(r.byte()? as i32).unwrap_or_default();
+    let dirty_rows = (0..r.varint()?)
+        .map(|_| r.varint())
+        .collect::<Result<_, _>>()?;
+    let theme = if flags & FLAG_THEME != 0 {
+        let mut values = [0u32; 258];
+        for value in &mut values {
+            *value = r.varint()? as u32 & 0xffffff;
+        }
+        let mut palette = Palette {
+            fg: values[0],
+            bg: values[1],
+            ..Palette::default()
+        };
+        palette.colors.copy_from_slice(&values[2..]);
+        Some(palette)
+    } else {
+        None
+    };
+
+    Ok(Serialized {
+        state,
+        scrollback,
+        kept: Kept {
+            mode,
+            utf8_partial,
+            dirty_rows,
+            cursor_changed: flags & FLAG_CURSOR_CHANGED != 0,
+            resized: flags & FLAG_RESIZED != 0,
+            cursor_policy,
+            theme,
+            bold_as_bright: flags & FLAG_BOLD_AS_BRIGHT != 0,
+            input_events: flags & FLAG_INPUT_EVENTS != 0,
+            cursor_shown: flags & FLAG_CURSOR_SHOWN != 0,
+        },
+    })
+}
+
+/// Restore `bytes` into `vt`, left as it was if the screen's dump format
+/// is incompatible (see `state::restore`).
+pub fn restore<B: TerminalBackend>(
+    vt: &mut AvtState<B>,
+    bytes: &[u8],
+) -> Result<Compat, StateError> {
+    let serialized = decode(bytes)?;
+    let saved = state::decode(serialized.state)?;
+    let compat = saved.header.compat();
+    if compat == Compat::Incompatible {
+        return Err(StateError::Incompatible(saved.header));
+    }
+    vt.restore_whole(
+        saved.cols,
+        saved.rows,
+        &serialized.scrollback,
+        saved.dump,
+        serialized.kept,
+    );
+    Ok(compat)
+}
+
+// JNI functions
+
+/// The VT whole, see the module docs; empty for an invalid handle or half
+/// an escape sequence fed.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSerialize<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        match save(vt) {
+            Some(bytes) => env.byte_array_from_slice(&bytes).unwrap_or_default(),
+            None => JByteArray::default(),
+        }
+    })
+}
+
+/// A new VT from `vtSerialize`'s bytes, of their size and mode; 0 if they
+/// aren't valid or the screen's dump format is incompatible.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtDeserialize(
+    mut env: JNIEnv,
+    _class: JClass,
+    bytes: JByteArray,
+) -> VtHandle {
+    jni_guard!(env, {
+        let Ok(bytes) = env.convert_byte_array(&bytes) else {
+            return 0;
+        };
+        let Ok(serialized) = decode(&bytes) else {
+            return 0;
+        };
+
+        let mut vt = Box::new(AvtState::with_mode(1, 1, serialized.kept.mode));
+        match restore(&mut vt, &bytes) {
+            Ok(_) => handles::insert(vt),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::themes;
+
+    #[test]
+    fn restores_scrollback_options_and_dirty_rows() {
+        let mut vt = AvtState::with_backend(fake(6, 2));
+        vt.vt.scrollback = vec!["older".into(), "old".into()];
+        vt.set_bold_as_bright(true);
+        vt.set_theme(themes::get("dracula"));
+        vt.set_cursor_policy(CursorPolicy::Always);
+        vt.set_input_events(true);
+        vt.poll_diff();
+        // Half an é
+        vt.feed(b"hi \xc3");
+        let bytes = save(&vt).unwrap();
+
+        let mut restored = AvtState::with_backend(fake(1, 1));
+        assert_eq!(restore(&mut restored, &bytes), Ok(Compat::Exact));
+        assert_eq!(restored.backend().size(), (6, 2));
+        assert_eq!(restored.backend().scrollback, ["older", "old"]);
+        assert_eq!(restored.kept(), vt.kept());
+        // Options restored aren't changes
+        assert!(restored.take_events().is_empty());
+        restored.feed(b"\xa9");
+        assert_eq!(restored.backend().row_text(0), "hi \u{e9}  ");
+
+        vt.feed(b"\xa9\x1b[");
+        assert!(save(&vt).is_none());
+        assert!(matches!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err(StateError::Decode(_))
+        ));
+        assert!(matches!(decode(b"AVTS"), Err(StateError::NotState)));
+    }
+}
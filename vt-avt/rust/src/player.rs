@@ -0,0 +1,1028 @@
+//! Native playback clock for casts.
+//!
+//! The render loop drives a `Player` with `tick(elapsed_us)`: every event
+//! scheduled up to that playback time is applied to the player's VT, and
+//! the result says how long until the next one, so the loop can sleep
+//! through long pauses instead of ticking every frame.
+//!
+//! The schedule is fixed at load time: event deltas are capped by the
+//! header's `idle_time_limit`, matching what asciinema's players do.
+//!
+//! Resizing mid-playback (device rotation) follows these rules:
+//!
+//! - `set_view_size` never applies or skips events; the next tick picks up
+//!   exactly where the schedule was.
+//! - While a view size is set, the recording's resize events only update
+//!   the recorded size. Clearing the view size returns to it.
+//! - Content is kept the way the backend resizes (avt reflows wrapped
+//!   lines); a resize in the middle of a split escape sequence waits for
+//!   the sequence to finish.
+//! - Any number of resizes between two polls yield one diff with
+//!   `resized` set and every row of the final size dirty.
+//!
+//! `tick_budgeted` bounds the work one call does, for huge seeks and
+//! casts with enormous events: it stops once a wall-clock budget is spent
+//! and the next call carries on from the same event.
+//!
+//! Shell integration timelines (`shell`) are scanned at load time too, in
+//! the same playback time.
+//!
+//! `set_pause_on` makes playback stop by itself at markers, watch hits
+//! (see `watch`) or the end of a command: the tick applies the event that
+//! triggers it and nothing after, and says why and at what playback time,
+//! so the app stops its clock exactly there rather than a few frames
+//! late. The next tick carries on from the following event.
+//!
+//! Input events (`"i"`, in casts recorded with `--stdin`) leave the screen
+//! alone. With `AvtState::set_input_events` they're queued as events in
+//! order with the output's, at their playback time, for an overlay
+//! showing keystrokes.
+//!
+//! Scrubbing doesn't need a player at all: `seek` rebuilds any VT's screen
+//! at a playback time in one call, replaying the cast from the start.
+//! `markers` gives the chapters (`"m"` events) in the same time, so a
+//! chapter list can seek to each.
+//! `quick_seek` does it while still reading the file, bounded by the
+//! output it replays, for gallery previews of casts not yet parsed.
+
+use crate::backend::{AvtBackend, TerminalBackend};
+use crate::cast::{micros_arg, seconds_to_micros, Cast, CastError, EventKind, Header, MAX_TIME_US};
+use crate::castfile::CastReader;
+use crate::checkpoint::CheckpointIndex;
+use crate::json::Value;
+use crate::shell::ShellTimeline;
+use crate::events::VtEvent;
+use crate::handles::{self, Kind};
+use crate::{write_varint, write_varint_u64, AvtState, Instant, VtHandle};
+use jni::objects::{JByteArray, JClass, JLongArray};
+use jni::sys::{jdouble, jint, jlong};
+use jni::JNIEnv;
+use std::io::BufRead;
+use std::time::Duration;
+
+/// Pause on `EventKind::Marker`
+pub const PAUSE_MARKER: u32 = 0x01;
+/// Pause on output that queued a `VtEvent::Watch`
+pub const PAUSE_WATCH: u32 = 0x02;
+/// Pause where a command finishes (OSC 133 `D`)
+pub const PAUSE_COMMAND: u32 = 0x04;
+/// Pause where a command finishes with a nonzero status
+pub const PAUSE_FAILED_COMMAND: u32 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    Marker,
+    Watch,
+    Command,
+    FailedCommand,
+}
+
+impl PauseReason {
+    /// The `PAUSE_*` flag that asks for it.
+    pub fn flag(self) -> u32 {
+        match self {
+            PauseReason::Marker => PAUSE_MARKER,
+            PauseReason::Watch => PAUSE_WATCH,
+            PauseReason::Command => PAUSE_COMMAND,
+            PauseReason::FailedCommand => PAUSE_FAILED_COMMAND,
+        }
+    }
+}
+
+/// Where playback paused itself, see `Player::set_pause_on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pause {
+    pub reason: PauseReason,
+    /// Playback time of the event that triggered it
+    pub at_us: i64,
+}
+
+/// Result of one `tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tick {
+    /// Events applied by this tick
+    pub applied: usize,
+    /// Playback time until the next event, `None` once all are applied.
+    /// From `paused.at_us` when paused.
+    pub next_event_in_us: Option<i64>,
+    pub paused: Option<Pause>,
+}
+
+/// Result of one `tick_budgeted`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetedTick {
+    pub tick: Tick,
+    /// Playback time caught up to: `elapsed_us`, or the last applied
+    /// event's time if the budget ran out or playback paused first
+    pub reached_us: i64,
+}
+
+pub struct Player<B = AvtBackend> {
+    cast: Cast,
+    /// Playback time of each event, idle time limit applied
+    schedule: Vec<i64>,
+    next: usize,
+    vt: AvtState<B>,
+    /// Size from the header and the resize events applied so far
+    recorded_size: (usize, usize),
+    view_size: Option<(usize, usize)>,
+    shell: ShellTimeline,
+    /// `PAUSE_*` flags
+    pause_on: u32,
+    /// Last event at each command's end, and whether the command failed
+    command_ends: Vec<(usize, bool)>,
+}
+
+impl Player {
+    pub fn load(bytes: &[u8]) -> Result<Self, CastError> {
+        Cast::parse(bytes).map(Player::from_cast)
+    }
+
+    pub fn from_cast(cast: Cast) -> Self {
+        let vt = AvtState::new(cast.header.cols, cast.header.rows);
+        Player::with_vt(cast, vt)
+    }
+}
+
+registered!(Player, Kind::Player);
+
+impl<B: TerminalBackend> Player<B> {
+    pub fn with_vt(cast: Cast, vt: AvtState<B>) -> Self {
+        let schedule = schedule(&cast, idle_limit(&cast.header));
+        let recorded_size = (cast.header.cols, cast.header.rows);
+        let shell = ShellTimeline::scan(&cast, &schedule);
+        let command_ends = shell
+            .commands
+            .iter()
+            .map(|command| {
+                let end = schedule.partition_point(|&at| at <= command.end_us);
+                (end.saturating_sub(1), command.failed())
+            })
+            .collect();
+        Player {
+            cast,
+            schedule,
+            next: 0,
+            vt,
+            recorded_size,
+            view_size: None,
+            shell,
+            pause_on: 0,
+            command_ends,
+        }
+    }
+
+    /// Pause playback at the events the `PAUSE_*` `flags` name; 0, the
+    /// default, never pauses.
+    pub fn set_pause_on(&mut self, flags: u32) {
+        self.pause_on = flags;
+    }
+
+    /// Pin the terminal to `size`, or follow the recording again with `None`.
+    pub fn set_view_size(&mut self, size: Option<(usize, usize)>) {
+        self.view_size = size;
+        let (cols, rows) = size.unwrap_or(self.recorded_size);
+        self.vt.resize(cols, rows);
+    }
+
+    pub fn shell(&self) -> &ShellTimeline {
+        &self.shell
+    }
+
+    pub fn vt(&self) -> &AvtState<B> {
+        &self.vt
+    }
+
+    pub fn vt_mut(&mut self) -> &mut AvtState<B> {
+        &mut self.vt
+    }
+
+    /// Apply every event scheduled at or before `elapsed_us` of playback
+    /// time. Playback time only moves forward; an earlier `elapsed_us`
+    /// applies nothing.
+    pub fn tick(&mut self, elapsed_us: i64) -> Tick {
+        self.advance(elapsed_us, None)
+    }
+
+    /// `tick`, but stop applying events once `budget` of wall-clock time is
+    /// spent. At least one due event is applied per call, so repeated calls
+    /// always make progress; when the budget ran out the next event is
+    /// already due (`next_event_in_us` is 0).
+    pub fn tick_budgeted(&mut self, elapsed_us: i64, budget: Duration) -> BudgetedTick {
+        let tick = self.advance(elapsed_us, Some(Instant::now() + budget));
+        let reached_us = match (tick.paused, self.schedule.get(self.next)) {
+            (Some(pause), _) => pause.at_us,
+            (None, Some(&at)) if at <= elapsed_us => self.schedule[self.next - 1],
+            _ => elapsed_us,
+        };
+        BudgetedTick { tick, reached_us }
+    }
+
+    fn advance(&mut self, elapsed_us: i64, deadline: Option<Instant>) -> Tick {
+        let start = self.next;
+        let mut paused = None;
+        while let Some(&at) = self.schedule.get(self.next) {
+            if at > elapsed_us {
+                break;
+            }
+            if self.next > start && deadline.is_some_and(|d| Instant::now() >= d) {
+                break;
+            }
+            let kind = &self.cast.events[self.next].kind;
+            if let &EventKind::Resize { cols, rows } = kind {
+                self.recorded_size = (cols, rows);
+            }
+            let is_marker = matches!(kind, EventKind::Marker(_));
+            let watch_hits = self.vt.watch_hits();
+            self.vt.set_event_time(Some(at.max(0) as u64));
+            apply(&mut self.vt, kind, self.view_size.is_none());
+            let watched = self.vt.watch_hits() > watch_hits;
+            self.next += 1;
+
+            if let Some(reason) = self.pause_reason(self.next - 1, is_marker, watched) {
+                paused = Some(Pause { reason, at_us: at });
+                break;
+            }
+        }
+
+        let now_us = paused.map_or(elapsed_us, |pause| pause.at_us);
+        Tick {
+            applied: self.next - start,
+            next_event_in_us: self
+                .schedule
+                .get(self.next)
+                .map(|&at| (at - now_us).max(0)),
+            paused,
+        }
+    }
+
+    /// Why to pause after event `index`, if `pause_on` asks to.
+    fn pause_reason(&self, index: usize, is_marker: bool, watched: bool) -> Option<PauseReason> {
+        if self.pause_on == 0 {
+            return None;
+        }
+        // Commands ending at the same event count as failed if any did
+        let from = self.command_ends.partition_point(|&(end, _)| end < index);
+        let ending = self.command_ends[from..].iter().take_while(|&&(end, _)| end == index);
+        let command = ending.map(|&(_, failed)| failed).max();
+        [
+            (is_marker, PauseReason::Marker),
+            (watched, PauseReason::Watch),
+            (command == Some(true), PauseReason::FailedCommand),
+            (command.is_some(), PauseReason::Command),
+        ]
+        .into_iter()
+        .find(|&(hit, reason)| hit && self.pause_on & reason.flag() != 0)
+        .map(|(_, reason)| reason)
+    }
+}
+
+/// Reset `vt` to the recording's starting size and apply every event
+/// scheduled at or before `time_us` of playback time, leaving it as a new
+/// `Player` would be after `tick(time_us)`. Returns the events applied.
+/// The events the replay queues (bells, titles) are dropped, since they
+/// didn't happen now.
+pub fn seek<B: TerminalBackend>(vt: &mut AvtState<B>, cast: &Cast, time_us: i64) -> usize {
+    vt.reset(cast.header.cols, cast.header.rows);
+    let schedule = schedule(cast, idle_limit(&cast.header));
+    let end = schedule.partition_point(|&at| at <= time_us);
+    for event in &cast.events[..end] {
+        apply(vt, &event.kind, true);
+    }
+    vt.take_events();
+    end
+}
+
+/// The markers of `cast` in order, at their playback times: where `seek`
+/// lands on each, having applied it.
+pub fn markers(cast: &Cast) -> Vec<(i64, &str)> {
+    let schedule = schedule(cast, idle_limit(&cast.header));
+    cast.events
+        .iter()
+        .zip(schedule)
+        .filter_map(|(event, at)| match &event.kind {
+            EventKind::Marker(label) => Some((at, label.as_str())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Varint count, then per marker a varint time delta in microseconds
+/// (relative to the previous marker), a varint label length and the label.
+pub fn encode_markers(markers: &[(i64, &str)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, markers.len());
+    let mut prev = 0;
+    for &(time_us, label) in markers {
+        write_varint_u64(&mut buf, (time_us - prev).max(0) as u64);
+        prev = time_us;
+        write_varint(&mut buf, label.len());
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf
+}
+
+/// `seek` for a cast still being read, for a preview before it is parsed
+/// and indexed: reset `vt` to the header's size and apply the events up to
+/// `time_us` of playback time, stopping short before the output fed would
+/// pass `max_bytes` or at a malformed line. Returns the events applied.
+pub fn quick_seek<B: TerminalBackend, R: BufRead>(
+    vt: &mut AvtState<B>,
+    reader: CastReader<R>,
+    time_us: i64,
+    max_bytes: usize,
+) -> usize {
+    vt.reset(reader.header().cols, reader.header().rows);
+    let mut clock = Clock::new(idle_limit(reader.header()));
+    let mut fed = 0;
+    let mut applied = 0;
+    for event in reader.map_while(Result::ok) {
+        if clock.at(event.time_us) > time_us {
+            break;
+        }
+        if let EventKind::Output(ref data) = event.kind {
+            fed += data.len();
+            if fed > max_bytes {
+                break;
+            }
+        }
+        apply(vt, &event.kind, true);
+        applied += 1;
+    }
+    vt.take_events();
+    applied
+}
+
+/// Feed output events, and resize events too when `follow_resizes`.
+/// Markers and resizes are queued as events either way, and input if `vt`
+/// asks for it (`AvtState::set_input_events`).
+pub(crate) fn apply<B: TerminalBackend>(vt: &mut AvtState<B>, kind: &EventKind, follow_resizes: bool) {
+    match *kind {
+        EventKind::Output(ref data) => vt.feed(data.as_bytes()),
+        EventKind::Resize { cols, rows } => {
+            vt.push_event(VtEvent::Resize { cols, rows });
+            if follow_resizes {
+                vt.resize(cols, rows);
+            }
+        }
+        EventKind::Marker(ref label) => vt.push_event(VtEvent::Marker(label.clone())),
+        EventKind::Input(ref data) if vt.input_events() => {
+            vt.push_event(VtEvent::Input(data.clone()))
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn idle_limit(header: &Header) -> Option<i64> {
+    header
+        .fields
+        .get("idle_time_limit")
+        .and_then(Value::as_f64)
+        .filter(|&limit| limit > 0.0)
+        .and_then(seconds_to_micros)
+}
+
+pub(crate) fn schedule(cast: &Cast, idle_limit: Option<i64>) -> Vec<i64> {
+    let mut clock = Clock::new(idle_limit);
+    cast.events.iter().map(|event| clock.at(event.time_us)).collect()
+}
+
+/// Rewrite `cast`'s event times to its playback times at `speed`, gaps
+/// capped by `idle_limit` (the header's `idle_time_limit` for `None`), so
+/// a scheduler can take them as they are. The header loses its limit,
+/// which would otherwise be applied again. Returns the new duration.
+pub fn normalize_timing(cast: &mut Cast, idle_limit: Option<i64>, speed: f64) -> i64 {
+    let idle_limit = idle_limit.or_else(|| self::idle_limit(&cast.header));
+    let times = schedule(cast, idle_limit);
+    for (event, at) in cast.events.iter_mut().zip(times) {
+        event.time_us = ((at as f64 / speed).round() as i64).min(MAX_TIME_US);
+    }
+    if let Value::Object(fields) = &mut cast.header.fields {
+        fields.retain(|(key, _)| key != "idle_time_limit");
+    }
+    cast.events.last().map_or(0, |event| event.time_us)
+}
+
+/// Playback times of events in file order: recorded deltas, negative ones
+/// taken as 0 and each capped by the idle limit.
+struct Clock {
+    idle_limit: Option<i64>,
+    prev: i64,
+    at: i64,
+}
+
+impl Clock {
+    fn new(idle_limit: Option<i64>) -> Self {
+        Clock {
+            idle_limit,
+            prev: 0,
+            at: 0,
+        }
+    }
+
+    /// Playback time of the next event, recorded at `time_us`.
+    fn at(&mut self, time_us: i64) -> i64 {
+        let delta = (time_us - self.prev).max(0);
+        self.prev = time_us;
+        self.at += self.idle_limit.map_or(delta, |limit| delta.min(limit));
+        self.at
+    }
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    cast_bytes: JByteArray,
+) -> jlong {
+    jni_guard!(env, {
+        let bytes = match env.convert_byte_array(cast_bytes) {
+            Ok(b) => b,
+            Err(_) => return 0,
+        };
+
+        match Player::load(&bytes) {
+            Ok(player) => handles::add(player),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        if let Some(mut player) = handles::take::<Player>(&mut env, handle) {
+            handles::registry().forget(player.vt_mut());
+        }
+    })
+}
+
+/// VT handle for the player's terminal, valid until `playerFree`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerVt(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return 0;
+        };
+
+        handles::registry().insert_borrowed(player.vt_mut())
+    })
+}
+
+/// Non-positive `cols` or `rows` clears the view size.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerSetViewSize(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    cols: jint,
+    rows: jint,
+) {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return;
+        };
+
+        let size = (cols > 0 && rows > 0).then_some((cols as usize, rows as usize));
+        player.set_view_size(size);
+    })
+}
+
+/// Playback time passed to the `playerTick*` functions, held to
+/// `0..=MAX_TIME_US` so that nothing downstream overflows.
+fn clamp_elapsed(elapsed_micros: jlong) -> i64 {
+    elapsed_micros.clamp(0, MAX_TIME_US)
+}
+
+/// Returns microseconds until the next event, or -1 when playback is done.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerTick(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    elapsed_micros: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return -1;
+        };
+
+        player.tick(clamp_elapsed(elapsed_micros)).next_event_in_us.unwrap_or(-1)
+    })
+}
+
+/// Pause playback at the events the `PAUSE_*` `flags` name, see
+/// `Player::set_pause_on`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerSetPauseOn(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    flags: jint,
+) {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return;
+        };
+
+        player.set_pause_on(flags as u32);
+    })
+}
+
+/// `[pauseReason, pausedAtMicros]` of `tick`: the `PAUSE_*` flag that
+/// paused it and when, or `[0, -1]`.
+fn pause_fields(tick: &Tick) -> [jlong; 2] {
+    match tick.paused {
+        Some(pause) => [pause.reason.flag() as jlong, pause.at_us],
+        None => [0, -1],
+    }
+}
+
+/// `playerTick` as `[nextEventInMicros, pauseReason, pausedAtMicros]`, see
+/// `pause_fields`; empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerTickPausable<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    elapsed_micros: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let tick = player.tick(clamp_elapsed(elapsed_micros));
+        let [reason, at] = pause_fields(&tick);
+        crate::long_array(&env, &[tick.next_event_in_us.unwrap_or(-1), reason, at])
+    })
+}
+
+/// `[reachedMicros, nextEventInMicros, pauseReason, pausedAtMicros]` after
+/// applying events for at most `max_micros`, see `Player::tick_budgeted`
+/// and `pause_fields`; the second is -1 when playback is done.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerTickBudgeted<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    elapsed_micros: jlong,
+    max_micros: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(player) = handles::object::<Player>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let budget = Duration::from_micros(max_micros.max(0) as u64);
+        let result = player.tick_budgeted(clamp_elapsed(elapsed_micros), budget);
+        let [reason, at] = pause_fields(&result.tick);
+        crate::long_array(
+            &env,
+            &[result.reached_us, result.tick.next_event_in_us.unwrap_or(-1), reason, at],
+        )
+    })
+}
+
+/// Working directory changes, as `ShellTimeline::encode_cwd`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerCwdTimeline<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(player) = handles::object_ref::<Player>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&player.shell().encode_cwd()).unwrap_or_default()
+    })
+}
+
+/// Commands and their exit status, as `ShellTimeline::encode_commands`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_playerExitStatusTimeline<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(player) = handles::object_ref::<Player>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&player.shell().encode_commands()).unwrap_or_default()
+    })
+}
+
+/// Reset the VT `handle` and replay the cast `cast_handle` (from
+/// `castOpen`) up to `time_micros` of playback time, see `seek`. Returns
+/// the resulting snapshot, empty for an invalid handle or time (see
+/// `cast::micros_arg`).
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSeek<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    cast_handle: jlong,
+    time_micros: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JByteArray::default();
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JByteArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        seek(vt, cast, time_us);
+        env.byte_array_from_slice(&vt.encode_snapshot()).unwrap_or_default()
+    })
+}
+
+/// The cast `cast_handle`'s markers, as `encode_markers`; empty for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castMarkers<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&encode_markers(&markers(cast)))
+            .unwrap_or_default()
+    })
+}
+
+/// `vtSeek` to marker `marker_index` of `markers`, through the checkpoint
+/// `index` unless it's 0 (see `vtSeekIndexed`). Returns the resulting
+/// snapshot, empty for an invalid handle or marker index.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSeekToMarker<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    cast_handle: jlong,
+    marker_index: jint,
+    index: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        if marker_index < 0 {
+            return JByteArray::default();
+        }
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JByteArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let Some(&(time_us, _)) = markers(cast).get(marker_index as usize) else {
+            return JByteArray::default();
+        };
+        if index == 0 {
+            seek(vt, cast, time_us);
+        } else {
+            let Some(index) = handles::object::<CheckpointIndex>(&mut env, index) else {
+                return JByteArray::default();
+            };
+            index.seek(vt, cast, time_us);
+        }
+        env.byte_array_from_slice(&vt.encode_snapshot()).unwrap_or_default()
+    })
+}
+
+/// `normalize_timing` of the cast `cast_handle` in place. A non-positive
+/// `idle_limit_micros` keeps the header's limit. Returns the new duration,
+/// or -1 for an invalid handle or a speed that isn't positive and finite.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castNormalizeTiming(
+    mut env: JNIEnv,
+    _class: JClass,
+    cast_handle: jlong,
+    idle_limit_micros: jlong,
+    speed: jdouble,
+) -> jlong {
+    jni_guard!(env, {
+        if !(speed.is_finite() && speed > 0.0) {
+            return -1;
+        }
+        let Some(cast) = handles::object::<Cast>(&mut env, cast_handle) else {
+            return -1;
+        };
+
+        let idle_limit = micros_arg(idle_limit_micros).filter(|&us| us > 0);
+        normalize_timing(cast, idle_limit, speed)
+    })
+}
+
+/// Reset the VT `handle` and replay the cast read from `fd`, up to
+/// `time_micros` of playback time or `max_bytes` of output, see
+/// `quick_seek`. Reads from the descriptor's offset and leaves it open.
+/// Returns the resulting snapshot, empty for an invalid handle, time,
+/// descriptor or header.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castQuickFrame<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    fd: jint,
+    time_micros: jlong,
+    max_bytes: jint,
+) -> JByteArray<'a> {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JByteArray::default();
+        };
+        if fd < 0 {
+            return JByteArray::default();
+        }
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let Ok(reader) = CastReader::new(BufReader::new(&*file)) else {
+            return JByteArray::default();
+        };
+        quick_seek(vt, reader, time_us, max_bytes.max(0) as usize);
+        env.byte_array_from_slice(&vt.encode_snapshot()).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    fn player(cast: &[u8]) -> Player<crate::backend::tests::FakeBackend> {
+        let cast = Cast::parse(cast).unwrap();
+        let vt = AvtState::with_backend(fake(10, 2));
+        Player::with_vt(cast, vt)
+    }
+
+    #[test]
+    fn tick_reports_time_to_next_event() {
+        let mut player = player(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [0.5, \"o\", \"a\"]\n\
+            [0.5, \"o\", \"b\"]\n\
+            [10.0, \"o\", \"c\"]\n",
+        );
+
+        assert_eq!(
+            player.tick(0),
+            Tick { applied: 0, next_event_in_us: Some(500_000), paused: None }
+        );
+        assert_eq!(
+            player.tick(600_000),
+            Tick { applied: 2, next_event_in_us: Some(9_400_000), paused: None }
+        );
+        assert_eq!(player.vt().backend().row_text(0).trim_end(), "ab");
+
+        assert_eq!(player.tick(100_000).applied, 0);
+        assert_eq!(
+            player.tick(10_000_000),
+            Tick { applied: 1, next_event_in_us: None, paused: None }
+        );
+        assert_eq!(
+            player.tick(20_000_000),
+            Tick { applied: 0, next_event_in_us: None, paused: None }
+        );
+    }
+
+    #[test]
+    fn budgeted_tick_resumes_where_it_stopped() {
+        let mut player = player(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [1.0, \"o\", \"a\"]\n\
+            [2.0, \"o\", \"b\"]\n\
+            [3.0, \"o\", \"c\"]\n\
+            [9.0, \"o\", \"d\"]\n",
+        );
+
+        // A spent budget still applies one event per call
+        let result = player.tick_budgeted(5_000_000, Duration::ZERO);
+        assert_eq!(
+            result.tick,
+            Tick { applied: 1, next_event_in_us: Some(0), paused: None }
+        );
+        assert_eq!(result.reached_us, 1_000_000);
+        assert_eq!(player.tick_budgeted(5_000_000, Duration::ZERO).reached_us, 2_000_000);
+
+        let result = player.tick_budgeted(5_000_000, Duration::from_secs(1));
+        assert_eq!(
+            result.tick,
+            Tick { applied: 1, next_event_in_us: Some(4_000_000), paused: None }
+        );
+        assert_eq!(result.reached_us, 5_000_000);
+        assert_eq!(player.vt().backend().row_text(0).trim_end(), "abc");
+    }
+
+    #[test]
+    fn pauses_only_where_asked() {
+        let bytes = b"{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [1.0, \"m\", \"step\"]\n\
+            [2.0, \"o\", \"\\u001b]133;D;0\\u0007\"]\n\
+            [3.0, \"o\", \"\\u001b]133;D;1\\u0007\"]\n\
+            [4.0, \"o\", \"FAIL\"]\n\
+            [5.0, \"o\", \"x\"]\n";
+        let new_player = |flags| {
+            let vt = AvtState::with_backend(fake(40, 2));
+            let mut player = Player::with_vt(Cast::parse(bytes).unwrap(), vt);
+            player.vt_mut().watch(1, crate::watch::Pattern::parse("FAIL").unwrap());
+            player.set_pause_on(flags);
+            player
+        };
+        let reason = |tick: Tick| tick.paused.map(|pause| pause.reason);
+
+        let mut player = new_player(PAUSE_WATCH | PAUSE_FAILED_COMMAND);
+        let tick = player.tick(10_000_000);
+        assert_eq!(tick.applied, 3);
+        assert_eq!(
+            tick.paused,
+            Some(Pause { reason: PauseReason::FailedCommand, at_us: 3_000_000 })
+        );
+        assert_eq!(tick.next_event_in_us, Some(1_000_000));
+        let tick = player.tick(10_000_000);
+        assert_eq!((tick.applied, reason(tick)), (1, Some(PauseReason::Watch)));
+        assert_eq!(player.tick(10_000_000).paused, None);
+
+        let mut player = new_player(PAUSE_MARKER | PAUSE_COMMAND);
+        assert_eq!(reason(player.tick(10_000_000)), Some(PauseReason::Marker));
+        assert_eq!(reason(player.tick(10_000_000)), Some(PauseReason::Command));
+        // A budgeted tick stops at the pause too
+        let result = player.tick_budgeted(10_000_000, Duration::from_secs(1));
+        assert_eq!(reason(result.tick), Some(PauseReason::Command));
+        assert_eq!(result.reached_us, 3_000_000);
+        assert_eq!(player.tick(10_000_000).applied, 2);
+    }
+
+    #[test]
+    fn idle_time_limit_caps_pauses() {
+        let mut player = player(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2, \"idle_time_limit\": 2}\n\
+            [1.0, \"o\", \"a\"]\n\
+            [60.0, \"r\", \"4x1\"]\n\
+            [61.5, \"o\", \"b\"]\n",
+        );
+        assert_eq!(player.schedule, vec![1_000_000, 3_000_000, 4_500_000]);
+
+        assert_eq!(player.tick(3_000_000).next_event_in_us, Some(1_500_000));
+        assert_eq!(player.vt().backend().size(), (4, 1));
+    }
+
+    #[test]
+    fn seek_matches_playing_up_to_the_same_time() {
+        let bytes = b"{\"version\": 2, \"width\": 10, \"height\": 2, \"idle_time_limit\": 1}\n\
+            [1.0, \"o\", \"a\"]\n\
+            [30.0, \"r\", \"4x1\"]\n\
+            [30.5, \"o\", \"b\"]\n\
+            [31.0, \"o\", \"c\"]\n";
+        let mut played = player(bytes);
+        played.tick(2_600_000);
+
+        let cast = Cast::parse(bytes).unwrap();
+        let mut vt = AvtState::with_backend(fake(10, 2));
+        vt.feed(b"stale");
+        assert_eq!(seek(&mut vt, &cast, 2_600_000), 3);
+        assert_eq!(vt.encode_snapshot(), played.vt().encode_snapshot());
+
+        // Seeking backwards starts over
+        assert_eq!(seek(&mut vt, &cast, 0), 0);
+        assert_eq!((vt.backend().size(), vt.backend().row_text(0)), ((10, 2), " ".repeat(10)));
+
+        // Without the parsed cast, the same screen or a byte-capped one
+        let mut quick = AvtState::with_backend(fake(10, 2));
+        let reader = CastReader::new(&bytes[..]).unwrap();
+        assert_eq!(quick_seek(&mut quick, reader, 2_600_000, 1024), 3);
+        assert_eq!(quick.encode_snapshot(), played.vt().encode_snapshot());
+        let reader = CastReader::new(&bytes[..]).unwrap();
+        assert_eq!(quick_seek(&mut quick, reader, 2_600_000, 1), 2);
+        assert_eq!(quick.backend().row_text(0), "a   ");
+    }
+
+    #[test]
+    fn input_is_queued_only_when_asked_for() {
+        let bytes = b"{\"version\": 2, \"width\": 10, \"height\": 1}\n\
+            [1.0, \"i\", \"l\"]\n\
+            [1.5, \"o\", \"l\"]\n\
+            [2.0, \"i\", \"s\\r\"]\n\
+            [2.5, \"m\", \"\"]\n";
+        let mut skipped = player(bytes);
+        skipped.tick(3_000_000);
+        assert_eq!(skipped.vt_mut().take_events(), [VtEvent::Marker(String::new())]);
+
+        let mut shown = player(bytes);
+        shown.vt_mut().set_input_events(true);
+        shown.tick(3_000_000);
+        assert_eq!(
+            shown.vt_mut().take_timed_events(),
+            [
+                (1_000_000, VtEvent::Input("l".to_string())),
+                (2_000_000, VtEvent::Input("s\r".to_string())),
+                (2_500_000, VtEvent::Marker(String::new())),
+            ]
+        );
+        assert_eq!(shown.vt().backend().row_text(0), skipped.vt().backend().row_text(0));
+
+        // Seeking drops them with the rest
+        let cast = Cast::parse(bytes).unwrap();
+        seek(shown.vt_mut(), &cast, 3_000_000);
+        assert!(shown.vt().input_events());
+        assert_eq!(shown.vt_mut().take_events(), []);
+    }
+
+    #[test]
+    fn markers_are_at_their_playback_times() {
+        let bytes = b"{\"version\": 2, \"width\": 10, \"height\": 1, \"idle_time_limit\": 2}\n\
+            [1.0, \"m\", \"intro\"]\n\
+            [1.5, \"o\", \"a\"]\n\
+            [60.0, \"o\", \"b\"]\n\
+            [60.0, \"m\", \"\"]\n\
+            [61.0, \"o\", \"c\"]\n";
+        let cast = Cast::parse(bytes).unwrap();
+        let markers = markers(&cast);
+        assert_eq!(markers, [(1_000_000, "intro"), (3_500_000, "")]);
+        assert_eq!(
+            encode_markers(&markers),
+            [2, 0xc0, 0x84, 0x3d, 5, b'i', b'n', b't', b'r', b'o', 0xa0, 0xcb, 0x98, 0x01, 0]
+        );
+
+        // Landing on a marker applies everything up to it
+        let mut vt = AvtState::with_backend(fake(10, 1));
+        assert_eq!(seek(&mut vt, &cast, markers[1].0), 4);
+        assert_eq!(vt.backend().row_text(0), format!("ab{}", " ".repeat(8)));
+    }
+
+    #[test]
+    fn view_size_overrides_recorded_resizes() {
+        let mut player = player(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [1.0, \"o\", \"ab\\u001b[\"]\n\
+            [2.0, \"o\", \"1mc\"]\n\
+            [3.0, \"r\", \"4x1\"]\n\
+            [4.0, \"o\", \"d\"]\n",
+        );
+        player.tick(1_000_000);
+
+        // Rotation between the two halves of an escape sequence
+        player.set_view_size(Some((6, 3)));
+        assert_eq!(player.vt().backend().size(), (10, 2));
+        assert_eq!(player.tick(2_000_000).applied, 1);
+        assert_eq!(player.vt().backend().size(), (6, 3));
+
+        assert_eq!(player.tick(3_000_000).next_event_in_us, Some(1_000_000));
+        assert_eq!(player.vt().backend().size(), (6, 3));
+        assert_eq!(player.recorded_size, (4, 1));
+
+        player.set_view_size(None);
+        assert_eq!(player.vt().backend().size(), (4, 1));
+        assert_eq!(player.tick(4_000_000).applied, 1);
+    }
+
+    #[test]
+    fn normalized_times_are_playback_times() {
+        let mut cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 10, \"height\": 2, \"idle_time_limit\": 2}\n\
+            [1.0, \"o\", \"a\"]\n\
+            [5.0, \"o\", \"b\"]\n\
+            [6.0, \"o\", \"c\"]\n",
+        )
+        .unwrap();
+
+        assert_eq!(normalize_timing(&mut cast, None, 2.0), 2_000_000);
+        let times: Vec<i64> = cast.events.iter().map(|e| e.time_us).collect();
+        assert_eq!(times, [500_000, 1_500_000, 2_000_000]);
+        assert_eq!(idle_limit(&cast.header), None);
+
+        // Slowing down afterwards isn't capped again; a new limit is
+        assert_eq!(normalize_timing(&mut cast, None, 0.5), 4_000_000);
+        assert_eq!(normalize_timing(&mut cast, Some(500_000), 1.0), 1_500_000);
+    }
+}
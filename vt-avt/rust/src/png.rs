@@ -0,0 +1,266 @@
+//! PNG decoding, for iTerm2 inline images (see `images`).
+//!
+//! Every color type and bit depth is read, 16-bit samples keeping their
+//! high byte, and palette transparency (`tRNS`) applied; a color key for
+//! the other types is ignored, leaving those pixels opaque. Interlaced
+//! (Adam7) images aren't decoded, nor are chunk CRCs checked: the zlib
+//! stream's checksum covers the pixels.
+
+use crate::gzip::Decoder;
+use crate::images::{Image, MAX_PIXELS};
+use std::io::{self, Read};
+
+/// `\x89PNG\r\n\x1a\n`, the start of every PNG
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn invalid(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes of a scanline, without its filter byte.
+    fn stride(&self) -> usize {
+        (self.width * self.channels() * self.depth as usize).div_ceil(8)
+    }
+}
+
+/// Decode `bytes` to RGBA.
+pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+    let mut rest = bytes
+        .strip_prefix(&SIGNATURE)
+        .ok_or_else(|| invalid("not a PNG"))?;
+    let mut header = None;
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut data = Vec::new();
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let body = rest
+            .get(8..8 + len)
+            .ok_or_else(|| invalid("truncated PNG chunk"))?;
+        rest = rest.get(12 + len..).unwrap_or_default();
+        match kind {
+            b"IHDR" if body.len() >= 13 => {
+                let width = u32::from_be_bytes(body[..4].try_into().unwrap()) as usize;
+                let height = u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize;
+                let (depth, color_type) = (body[8], body[9]);
+                let valid = match color_type {
+                    0 => matches!(depth, 1 | 2 | 4 | 8 | 16),
+                    3 => matches!(depth, 1 | 2 | 4 | 8),
+                    2 | 4 | 6 => matches!(depth, 8 | 16),
+                    _ => false,
+                };
+                if !valid {
+                    return Err(invalid("invalid PNG color type or bit depth"));
+                }
+                if body[12] != 0 {
+                    return Err(invalid("interlaced PNG"));
+                }
+                if width == 0 || height == 0 || width.saturating_mul(height) > MAX_PIXELS {
+                    return Err(invalid("PNG too large"));
+                }
+                header = Some(Header {
+                    width,
+                    height,
+                    depth,
+                    color_type,
+                });
+            }
+            b"PLTE" => {
+                palette = body
+                    .chunks_exact(3)
+                    .map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff])
+                    .collect();
+            }
+            b"tRNS" => {
+                for (entry, &alpha) in palette.iter_mut().zip(body) {
+                    entry[3] = alpha;
+                }
+            }
+            b"IDAT" => data.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header.ok_or_else(|| invalid("PNG has no header"))?;
+
+    // A filter byte before each scanline
+    let stride = header.stride();
+    let mut raw = Vec::with_capacity((stride + 1) * header.height);
+    Decoder::zlib(&data[..])?
+        .take(((stride + 1) * header.height) as u64)
+        .read_to_end(&mut raw)?;
+    if raw.len() < (stride + 1) * header.height {
+        return Err(invalid("truncated PNG data"));
+    }
+
+    let bpp = (header.channels() * header.depth as usize).div_ceil(8);
+    let mut rgba = Vec::with_capacity(header.width * header.height * 4);
+    let mut prev = vec![0; stride];
+    let mut line = vec![0; stride];
+    for scanline in raw.chunks_exact(stride + 1) {
+        line.copy_from_slice(&scanline[1..]);
+        unfilter(scanline[0], &mut line, &prev, bpp)?;
+        for x in 0..header.width {
+            rgba.extend_from_slice(&pixel(&header, &palette, &line, x));
+        }
+        std::mem::swap(&mut line, &mut prev);
+    }
+    Ok(Image {
+        width: header.width,
+        height: header.height,
+        rgba,
+    })
+}
+
+/// Undo scanline filter `filter` on `line`, with `prev` the line above.
+fn unfilter(filter: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> io::Result<()> {
+    for i in 0..line.len() {
+        let left = if i >= bpp { line[i - bpp] } else { 0 };
+        let up = prev[i];
+        let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(invalid("unknown PNG filter")),
+        };
+        line[i] = line[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Sample `index` of an unfiltered scanline, scaled to 8 bits.
+fn sample(line: &[u8], index: usize, depth: u8) -> u8 {
+    match depth {
+        8 => line[index],
+        16 => line[index * 2],
+        _ => {
+            let per_byte = 8 / depth as usize;
+            let shift = 8 - depth as usize * (index % per_byte + 1);
+            let max = (1u16 << depth) - 1;
+            let value = (line[index / per_byte] >> shift) as u16 & max;
+            (value * 255 / max) as u8
+        }
+    }
+}
+
+fn pixel(header: &Header, palette: &[[u8; 4]], line: &[u8], x: usize) -> [u8; 4] {
+    let channels = header.channels();
+    let at = |channel: usize| sample(line, x * channels + channel, header.depth);
+    match header.color_type {
+        0 => [at(0), at(0), at(0), 0xff],
+        2 => [at(0), at(1), at(2), 0xff],
+        3 => {
+            // The raw index, not scaled
+            let index = if header.depth == 8 {
+                line[x] as usize
+            } else {
+                let per_byte = 8 / header.depth as usize;
+                let shift = 8 - header.depth as usize * (x % per_byte + 1);
+                (line[x / per_byte] >> shift) as usize & ((1 << header.depth) - 1)
+            };
+            palette.get(index).copied().unwrap_or([0, 0, 0, 0xff])
+        }
+        4 => [at(0), at(0), at(0), at(1)],
+        _ => [at(0), at(1), at(2), at(3)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2×2 PNG of palette indices 0 1 / 1 2 at 2 bits, index 0 half
+    /// transparent
+    fn paletted() -> Vec<u8> {
+        let chunk = |kind: &[u8], body: &[u8]| {
+            let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(body);
+            // CRCs aren't checked
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        // `zlib.compress(bytes([1, 0x10, 0, 0x60]))`: row 0 Sub filtered,
+        // row 1 not
+        let idat = [
+            0x78, 0x9c, 0x63, 0x14, 0x60, 0x48, 0x00, 0x00, 0x00, 0x98, 0x00, 0x72,
+        ];
+        [
+            SIGNATURE.to_vec(),
+            chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 2, 2, 3, 0, 0, 0]),
+            chunk(b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            chunk(b"tRNS", &[128]),
+            chunk(b"IDAT", &idat),
+            chunk(b"IEND", &[]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn decodes_paletted_images_with_transparency() {
+        let image = decode(&paletted()).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.rgba,
+            [255, 0, 0, 128, 0, 255, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255]
+        );
+
+        assert!(decode(b"GIF89a").is_err());
+        let mut interlaced = paletted();
+        interlaced[8 + 8 + 12] = 1;
+        assert!(decode(&interlaced).is_err());
+    }
+
+    #[test]
+    fn unfilters_each_filter_type() {
+        let prev = [10, 20, 30, 40];
+        for (filter, expected) in [
+            (0, [1, 2, 3, 4]),
+            (1, [1, 2, 4, 6]),
+            (2, [11, 22, 33, 44]),
+            (3, [6, 12, 21, 30]),
+            (4, [11, 22, 33, 44]),
+        ] {
+            let mut line = [1, 2, 3, 4];
+            unfilter(filter, &mut line, &prev, 2).unwrap();
+            assert_eq!(line, expected, "filter {}", filter);
+        }
+        assert!(unfilter(5, &mut [0], &[0], 1).is_err());
+    }
+}
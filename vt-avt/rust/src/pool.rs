@@ -0,0 +1,168 @@
+//! Warm pool of VT instances.
+//!
+//! Building a VT allocates its grid and scrollback, which takes noticeable
+//! time on low-end devices right when a player opens. `vtPoolWarm` builds
+//! instances ahead of time, typically from a background thread at startup,
+//! and `vtNewPooled` hands one out instead of building it. Finished
+//! instances go back with `vtRecycle`, which resets them on the releasing
+//! thread, so taking one of the same size costs nothing.
+
+use crate::backend::{AvtBackend, Retention, TerminalBackend};
+use crate::{handles, AvtState, VtHandle, VtMode};
+use jni::objects::JClass;
+use jni::sys::jint;
+use jni::JNIEnv;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Idle instances kept; more are freed on recycle
+const CAPACITY: usize = 4;
+
+static POOL: Mutex<Pool> = Mutex::new(Pool::new(CAPACITY));
+
+pub struct Pool<B = AvtBackend> {
+    idle: Vec<Box<AvtState<B>>>,
+    capacity: usize,
+}
+
+impl<B: TerminalBackend> Pool<B> {
+    pub const fn new(capacity: usize) -> Self {
+        Pool {
+            idle: Vec::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.idle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idle.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.idle.len() >= self.capacity
+    }
+
+    /// Add a fresh instance, or drop it if the pool is full.
+    pub fn put(&mut self, state: Box<AvtState<B>>) {
+        if !self.is_full() {
+            self.idle.push(state);
+        }
+    }
+
+    /// Reset a used instance and keep it for reuse. Ticker-mode instances
+    /// are dropped and any retention limit lifted, since takers expect full
+    /// scrollback, and any query config is cleared, as takers expect a
+    /// playback VT.
+    pub fn recycle(&mut self, mut state: Box<AvtState<B>>) {
+        if self.is_full() || state.mode() != VtMode::Full {
+            return;
+        }
+        let (cols, rows) = state.backend().size();
+        state.reset(cols, rows);
+        state.set_retention(Retention::default());
+        state.set_config(None);
+        self.idle.push(state);
+    }
+
+    /// An idle instance at `cols` x `rows`, preferring one already that
+    /// size; `None` if the pool is empty.
+    pub fn take(&mut self, cols: usize, rows: usize) -> Option<Box<AvtState<B>>> {
+        let index = self
+            .idle
+            .iter()
+            .rposition(|state| state.backend().size() == (cols, rows))
+            .unwrap_or(self.idle.len().checked_sub(1)?);
+        let mut state = self.idle.swap_remove(index);
+        if state.backend().size() != (cols, rows) {
+            state.reset(cols, rows);
+        }
+        Some(state)
+    }
+}
+
+fn pool() -> MutexGuard<'static, Pool> {
+    POOL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// JNI functions
+
+/// Build instances until the pool holds `count` (at most its capacity).
+/// Slow; call off the main thread.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtPoolWarm(
+    mut env: JNIEnv,
+    _class: JClass,
+    count: jint,
+    cols: jint,
+    rows: jint,
+) {
+    jni_guard!(env, {
+        // Build outside the lock so takers aren't held up
+        let count = (count.max(0) as usize).min(CAPACITY);
+        while pool().len() < count {
+            let state = Box::new(AvtState::new(cols as usize, rows as usize));
+            pool().put(state);
+        }
+    })
+}
+
+/// Like `vtNew`, from the pool when it has an instance.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtNewPooled(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+) -> VtHandle {
+    jni_guard!(env, {
+        let (cols, rows) = (cols as usize, rows as usize);
+        let state = pool()
+            .take(cols, rows)
+            .unwrap_or_else(|| Box::new(AvtState::new(cols, rows)));
+        handles::insert(state)
+    })
+}
+
+/// Free a handle into the pool; the handle is invalid afterwards, as after
+/// `vtFree`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRecycle(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) {
+    jni_guard!(env, {
+        if let Some(state) = handles::remove(&mut env, handle) {
+            pool().recycle(state);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn reuses_reset_instances_of_matching_size() {
+        let mut pool = Pool::new(2);
+        assert!(pool.take(4, 2).is_none());
+
+        let mut used = Box::new(AvtState::with_backend(fake(4, 2)));
+        used.feed(b"hi");
+        pool.recycle(used);
+        pool.put(Box::new(AvtState::with_backend(fake(8, 3))));
+        pool.put(Box::new(AvtState::with_backend(fake(8, 3))));
+        assert_eq!(pool.len(), 2);
+
+        let state = pool.take(4, 2).unwrap();
+        assert_eq!(state.backend().row_text(0), "    ");
+
+        // No match: any instance, resized
+        let state = pool.take(6, 1).unwrap();
+        assert_eq!(state.backend().size(), (6, 1));
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Poster frames for the gallery.
+//!
+//! A frame at t=0 is usually an empty prompt. `poster_time` replays the
+//! cast and picks the first moment worth showing: when `MIN_FILL_PERCENT`
+//! of the screen has something on it, or when the first command (OSC 133,
+//! see `shell`) finishes, whichever comes first. Cells count as filled if
+//! they hold a character or a background color, so a full-screen TUI with
+//! colored panes qualifies before it prints much text.
+//!
+//! Only the first `MAX_SCAN_BYTES` of output are replayed. A cast that
+//! never fills the screen that far gets the end of its first command if
+//! it has one, otherwise the fullest screen seen.
+//!
+//! `castPosterAt` makes the frame at a given time from the cast's bytes in
+//! one call, for thumbnail workers that would otherwise juggle a cast and
+//! a VT handle per recording.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::cast::{micros_arg, Cast, EventKind};
+use crate::shell::ShellTimeline;
+use crate::snapshot::Color;
+use crate::{handles, player, AvtState};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+/// Share of the screen's cells, in percent, that makes a poster
+pub const MIN_FILL_PERCENT: usize = 20;
+
+/// Output replayed before settling for the best frame so far
+pub const MAX_SCAN_BYTES: usize = 4 * 1024 * 1024;
+
+/// Playback time, in microseconds, of a poster frame for `cast`, replaying
+/// it on `vt`. Leaves `vt` at that frame or a little past it; the events
+/// the replay queues are dropped.
+pub fn poster_time<B: TerminalBackend>(vt: &mut AvtState<B>, cast: &Cast) -> i64 {
+    let time_us = scan(vt, cast);
+    vt.take_events();
+    time_us
+}
+
+fn scan<B: TerminalBackend>(vt: &mut AvtState<B>, cast: &Cast) -> i64 {
+    let schedule = player::schedule(cast, player::idle_limit(&cast.header));
+    let shell = ShellTimeline::scan(cast, &schedule);
+    let first_command = shell.commands.first().map(|command| command.end_us);
+
+    vt.reset(cast.header.cols, cast.header.rows);
+    let mut cells = Vec::new();
+    let mut fed = 0;
+    // Filled cells and time of the fullest screen so far
+    let mut best = (0, 0);
+    for (event, &at) in cast.events.iter().zip(&schedule) {
+        if let Some(end) = first_command.filter(|&end| at > end) {
+            return end;
+        }
+        player::apply(vt, &event.kind, true);
+        let EventKind::Output(data) = &event.kind else {
+            continue;
+        };
+
+        let (cols, rows) = vt.backend().size();
+        let filled = filled_cells(vt.backend(), &mut cells);
+        if filled * 100 >= cols * rows * MIN_FILL_PERCENT {
+            return at;
+        }
+        if filled > best.0 {
+            best = (filled, at);
+        }
+        fed += data.len();
+        if fed > MAX_SCAN_BYTES {
+            break;
+        }
+    }
+    first_command.unwrap_or(best.1)
+}
+
+fn filled_cells(backend: &impl TerminalBackend, cells: &mut Vec<Cell>) -> usize {
+    let (_, rows) = backend.size();
+    (0..rows)
+        .map(|row| {
+            backend.row_cells(row, cells);
+            cells
+                .iter()
+                .filter(|cell| cell.ch != ' ' || cell.style.bg != Color::Default)
+                .count()
+        })
+        .sum()
+}
+
+// JNI functions
+
+/// Playback time in microseconds of a poster frame for the cast `handle`,
+/// see `poster_time`; pass it to `vtSeek`. -1 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castPosterTime(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return -1;
+        };
+
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        poster_time(&mut vt, cast)
+    })
+}
+
+/// Snapshot of the cast in `cast_bytes` at `time_micros` of playback time,
+/// replayed on a VT that lives only for the call, so a background worker
+/// can make thumbnails without handles to free. Empty if the cast doesn't
+/// parse or the time is invalid.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castPosterAt<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_bytes: JByteArray<'a>,
+    time_micros: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JByteArray::default();
+        };
+        let Ok(bytes) = env.convert_byte_array(cast_bytes) else {
+            return JByteArray::default();
+        };
+        let Ok(cast) = Cast::parse(&bytes) else {
+            return JByteArray::default();
+        };
+
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        player::seek(&mut vt, &cast, time_us);
+        env.byte_array_from_slice(&vt.encode_snapshot())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    fn poster(cast: &str) -> i64 {
+        let cast = Cast::parse(cast.as_bytes()).unwrap();
+        poster_time(&mut AvtState::with_backend(fake(10, 2)), &cast)
+    }
+
+    #[test]
+    fn first_screen_with_enough_on_it() {
+        // Four of the 20 cells in use make the poster
+        let cast = "{\"version\": 2, \"width\": 10, \"height\": 2, \"idle_time_limit\": 1}\n\
+            [0.5, \"o\", \"$ \"]\n\
+            [1.0, \"o\", \"ls\"]\n\
+            [9.0, \"o\", \"a\"]\n\
+            [9.5, \"o\", \"b\"]\n";
+        assert_eq!(poster(cast), 2_000_000);
+
+        // Never full enough: the fullest screen
+        let cast = "{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [0.5, \"o\", \"$ \"]\n\
+            [1.0, \"o\", \"ls\"]\n\
+            [2.0, \"o\", \"\\u001b[K\"]\n";
+        assert_eq!(poster(cast), 1_000_000);
+    }
+
+    #[test]
+    fn end_of_the_first_command_if_sooner() {
+        let cast = "{\"version\": 2, \"width\": 10, \"height\": 2}\n\
+            [0.5, \"o\", \"$ \\u001b]133;C\\u0007\"]\n\
+            [1.0, \"o\", \"x\"]\n\
+            [1.5, \"o\", \"\\u001b]133;D;0\\u0007\"]\n\
+            [3.0, \"o\", \"$ long prompt\"]\n";
+        assert_eq!(poster(cast), 1_500_000);
+    }
+}
@@ -0,0 +1,183 @@
+//! Local echo prediction for high-latency interactive sessions.
+//!
+//! Typed characters are shown straight away, before the remote echo
+//! arrives, styled with `ATTR_PREDICTED` so clients can mark them (e.g.
+//! underlined). Predictions are kept beside the real screen rather than
+//! written into it, and are checked against the real cells after every
+//! feed:
+//!
+//! - a cell that now shows the predicted character confirms it;
+//! - a cell the cursor hasn't reached yet on that row stays pending;
+//! - anything else means the prediction was wrong (no echo at a password
+//!   prompt, a redrawn line), and every pending prediction is dropped.
+//!
+//! Only printable ASCII is predicted, on the cursor's row and short of
+//! the margin. Backspace takes back the last pending prediction. Any other
+//! key, or a backspace with nothing to take back, stops predicting until
+//! the pending predictions are settled, since its effect can't be guessed.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::snapshot::{Cursor, ATTR_PREDICTED};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Predicted {
+    pub row: usize,
+    pub col: usize,
+    pub ch: char,
+}
+
+#[derive(Debug, Default)]
+pub struct Predictor {
+    /// In typing order, all on one row
+    pending: Vec<Predicted>,
+    /// Set by input whose echo can't be predicted
+    blocked: bool,
+}
+
+impl Predictor {
+    pub fn pending(&self) -> &[Predicted] {
+        &self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.blocked = false;
+    }
+
+    /// Predict the echo of typed `input`, the real cursor being at
+    /// `cursor` on a `cols` wide screen. Whether the predictions changed.
+    pub fn predict(&mut self, input: &str, cursor: Cursor, cols: usize) -> bool {
+        let mut changed = false;
+        for ch in input.chars() {
+            if self.blocked {
+                break;
+            }
+            match ch {
+                '\x08' | '\x7f' if !self.pending.is_empty() => {
+                    self.pending.pop();
+                    changed = true;
+                }
+                ' '..='~' => {
+                    let (row, col) = self
+                        .pending
+                        .last()
+                        .map_or((cursor.row, cursor.col), |p| (p.row, p.col + 1));
+                    if col + 1 >= cols {
+                        // Wrapping is up to the program (and avt's pending
+                        // wrap state), so don't guess
+                        self.blocked = true;
+                    } else {
+                        self.pending.push(Predicted { row, col, ch });
+                        changed = true;
+                    }
+                }
+                _ => self.blocked = true,
+            }
+        }
+        changed
+    }
+
+    /// Where the cursor shows while predictions are pending.
+    pub fn cursor(&self, real: Cursor) -> Cursor {
+        match self.pending.last() {
+            Some(p) => Cursor {
+                col: p.col + 1,
+                row: p.row,
+                ..real
+            },
+            None => real,
+        }
+    }
+
+    /// Settle predictions against the screen after a feed. Returns the row
+    /// whose predictions changed, if any.
+    pub fn reconcile(&mut self, backend: &impl TerminalBackend) -> Option<usize> {
+        let Some(row) = self.pending.first().map(|p| p.row) else {
+            self.blocked = false;
+            return None;
+        };
+        let cursor = backend.cursor();
+        let mut cells = Vec::new();
+        backend.row_cells(row, &mut cells);
+
+        let before = self.pending.len();
+        let mut wrong = false;
+        self.pending.retain(|p| {
+            let confirmed = cells.get(p.col).is_some_and(|cell| cell.ch == p.ch);
+            let reachable = cursor.row == p.row && cursor.col <= p.col;
+            wrong |= !confirmed && !reachable;
+            !confirmed
+        });
+        if wrong {
+            self.pending.clear();
+        }
+        if self.pending.is_empty() {
+            self.blocked = false;
+        }
+        (self.pending.len() != before).then_some(row)
+    }
+
+    /// Show pending predictions over the real cells of `row`.
+    pub(crate) fn overlay(&self, row: usize, cells: &mut [Cell]) {
+        for p in self.pending.iter().filter(|p| p.row == row) {
+            if let Some(cell) = cells.get_mut(p.col) {
+                cell.ch = p.ch;
+                cell.width = 1;
+                cell.style.attrs |= ATTR_PREDICTED;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    fn row0(state: &AvtState<crate::backend::tests::FakeBackend>) -> Vec<(String, bool)> {
+        state.screen().lines[0]
+            .runs
+            .iter()
+            .map(|run| (run.text.clone(), run.style.attrs & ATTR_PREDICTED != 0))
+            .collect()
+    }
+
+    #[test]
+    fn predictions_settle_against_the_echo() {
+        let mut state = AvtState::with_backend(fake(8, 2));
+        state.feed(b"$ ");
+        state.predict_input(b"lsx\x7f");
+        assert_eq!(
+            row0(&state),
+            [
+                ("$ ".to_string(), false),
+                ("ls".to_string(), true),
+                ("    ".to_string(), false)
+            ]
+        );
+        assert_eq!(state.screen().cursor.col, 4);
+
+        // Partial echo confirms "l", "s" is still ahead of the cursor
+        state.feed(b"l");
+        assert_eq!(state.predictions().len(), 1);
+        state.feed(b"s");
+        assert!(state.predictions().is_empty());
+
+        // No echo: the program moved on without printing the prediction
+        state.predict_input(b"a");
+        state.feed(b"*");
+        assert!(state.predictions().is_empty());
+
+        // Enter can't be predicted, so nothing after it is
+        state.predict_input(b"\rb");
+        assert!(state.predictions().is_empty());
+        state.feed(b"!");
+        state.predict_input(b"c");
+        assert_eq!(state.predictions().len(), 1);
+    }
+}
@@ -0,0 +1,216 @@
+//! Property tests for the binary wire formats.
+//!
+//! The snapshot and diff encodings are the contract between the native
+//! side and every client decoder, so beyond round trips these check that
+//! truncated input is always rejected and corrupted input never panics.
+
+use super::*;
+use crate::diff::{self, Content, Diff, ImageChange};
+use crate::lineattr::LineAttr;
+use crate::snapshot::{self, Color, Cursor, DecodeError, ImagePlacement, Line, Run, Screen, Style};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+fn arb_color() -> impl Strategy<Value = Color> {
+    prop_oneof![
+        Just(Color::Default),
+        any::<u8>().prop_map(Color::Indexed),
+        any::<(u8, u8, u8)>().prop_map(|(r, g, b)| Color::Rgb(r, g, b)),
+    ]
+}
+
+fn arb_style() -> impl Strategy<Value = Style> {
+    (arb_color(), arb_color(), any::<u8>()).prop_map(|(fg, bg, attrs)| Style { fg, bg, attrs })
+}
+
+fn arb_line_attr() -> impl Strategy<Value = LineAttr> {
+    prop_oneof![
+        Just(LineAttr::Single),
+        Just(LineAttr::DoubleWidth),
+        Just(LineAttr::DoubleHeightTop),
+        Just(LineAttr::DoubleHeightBottom),
+    ]
+}
+
+fn arb_run() -> impl Strategy<Value = Run> {
+    // Mostly small columns, with the occasional 5-byte varint
+    let col = prop_oneof![4 => 0usize..512, 1 => Just(u32::MAX as usize)];
+    let link = prop_oneof![3 => Just(0u32), 1 => any::<u32>()];
+    (col, "\\PC{0,12}", 0usize..64, arb_style(), link).prop_map(
+        |(col, text, cells, style, link)| Run {
+            col,
+            text,
+            cells,
+            style,
+            link,
+        },
+    )
+}
+
+fn arb_line() -> impl Strategy<Value = Line> {
+    (arb_line_attr(), vec(arb_run(), 0..6)).prop_map(|(attr, runs)| Line { attr, runs })
+}
+
+fn arb_screen() -> impl Strategy<Value = Screen> {
+    (1usize..200, 1usize..12)
+        .prop_flat_map(|(cols, rows)| {
+            (
+                Just(cols),
+                Just(rows),
+                (0..cols, 0..rows, any::<u8>()),
+                vec(arb_line(), rows),
+                vec((any::<u32>(), "\\PC{0,20}"), 0..3),
+            )
+        })
+        .prop_map(|(cols, rows, (col, row, flags), lines, links)| Screen {
+            cols,
+            rows,
+            cursor: Cursor::with_flags(col, row, flags),
+            lines,
+            links,
+            alt_screen: flags & snapshot::ALT_SCREEN != 0,
+            images: Vec::new(),
+        })
+}
+
+fn arb_image_change() -> impl Strategy<Value = ImageChange> {
+    let placement = (any::<u32>(), vec(0usize..1000, 6)).prop_map(|(id, values)| ImagePlacement {
+        id,
+        col: values[0],
+        row: values[1],
+        rows: values[2],
+        cols: values[3],
+        image_row: values[4],
+        image_rows: values[5],
+    });
+    (0u8..3, placement).prop_map(|(op, placement)| match op {
+        0 => ImageChange::Added(placement),
+        1 => ImageChange::Moved(placement),
+        _ => ImageChange::Removed(placement),
+    })
+}
+
+fn arb_diff() -> impl Strategy<Value = Diff> {
+    (
+        vec(0usize..10_000, 0..32),
+        (any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>()),
+        vec(any::<u64>(), 0..4),
+        option::of((arb_screen(), any::<bool>())),
+        option::of(vec(arb_image_change(), 0..3)),
+    )
+        .prop_map(|(mut lines, flags, traces, screen, images)| {
+            let (cursor_changed, cursor_style_changed, resized, screen_switched, modes_changed) =
+                flags;
+            lines.sort_unstable();
+            lines.dedup();
+            // Content diffs carry one line per index
+            let content = screen.map(|(screen, with_spans)| {
+                lines.truncate(screen.lines.len());
+                Content {
+                    cols: screen.cols,
+                    rows: screen.rows,
+                    cursor: screen.cursor,
+                    alt_screen: screen.alt_screen,
+                    lines: screen.lines[..lines.len()].to_vec(),
+                    spans: with_spans
+                        .then(|| lines.iter().map(|&i| i % 7..i % 7 + screen.cols).collect()),
+                }
+            });
+            Diff {
+                lines,
+                cursor_changed,
+                cursor_style_changed,
+                resized,
+                screen_switched,
+                modes_changed,
+                traces,
+                content,
+                damage: None,
+                images,
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn screen_round_trips(screen in arb_screen()) {
+        let bytes = screen.encode();
+        prop_assert_eq!(snapshot::decode(&bytes).unwrap(), screen);
+    }
+
+    #[test]
+    fn truncated_screen_is_rejected(screen in arb_screen(), cut in any::<Index>()) {
+        let bytes = screen.encode();
+        let len = cut.index(bytes.len());
+        let result = snapshot::decode(&bytes[..len]);
+        prop_assert!(matches!(result, Err(DecodeError::Truncated { .. })), "{:?}", result);
+    }
+
+    #[test]
+    fn corrupted_screen_decodes_consistently(
+        screen in arb_screen(),
+        at in any::<Index>(),
+        byte in any::<u8>(),
+    ) {
+        let mut bytes = screen.encode();
+        let i = at.index(bytes.len());
+        bytes[i] = byte;
+        // Whatever a corrupted payload decodes to must itself round-trip
+        if let Ok(decoded) = snapshot::decode(&bytes) {
+            prop_assert_eq!(snapshot::decode(&decoded.encode()).unwrap(), decoded);
+        }
+    }
+
+    #[test]
+    fn style_id_round_trips(style in arb_style()) {
+        prop_assert!(style.id() < 1 << 60);
+        prop_assert_eq!(Style::from_id(style.id()), style);
+    }
+
+    #[test]
+    fn diff_round_trips(diff in arb_diff()) {
+        prop_assert_eq!(diff::decode(&diff.encode()).unwrap(), diff);
+    }
+
+    #[test]
+    fn interned_screens_round_trip(screens in vec(arb_screen(), 1..4)) {
+        let mut interner = styles::Interner::default();
+        let mut cache = styles::Cache::default();
+        for screen in screens {
+            let bytes = styles::encode_screen(&screen, &mut interner);
+            prop_assert_eq!(styles::decode_screen(&bytes, &mut cache).unwrap(), screen);
+        }
+    }
+
+    #[test]
+    fn truncated_diff_is_rejected(diff in arb_diff(), cut in any::<Index>()) {
+        let bytes = diff.encode();
+        let len = cut.index(bytes.len());
+        let result = diff::decode(&bytes[..len]);
+        prop_assert!(matches!(result, Err(DecodeError::Truncated { .. })), "{:?}", result);
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in vec(any::<u8>(), 0..256)) {
+        let _ = snapshot::decode(&bytes);
+        let _ = diff::decode(&bytes);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn wrapper_snapshot_round_trips(chunks in vec(vec(any::<u8>(), 0..64), 1..8)) {
+        let mut state = AvtState::new(20, 5);
+        for chunk in &chunks {
+            state.feed(chunk);
+        }
+        let bytes = state.encode_snapshot();
+        let screen = snapshot::decode(&bytes).unwrap();
+        prop_assert_eq!(screen.rows, 5);
+        prop_assert_eq!(screen.encode(), bytes);
+    }
+}
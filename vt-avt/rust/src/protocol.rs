@@ -0,0 +1,248 @@
+//! Versioned snapshot and diff headers, and what each client negotiates.
+//!
+//! The app and the native library ship separately (a hotfix of the `.so`
+//! alone, the web viewer, C ABI users), and the snapshot and diff formats
+//! grow: links, images and cursor shapes each came after the first
+//! decoders. So a VT sends the formats as they always were until its
+//! client asks for more with `vtSetWireFormat`, naming the protocol
+//! version it decodes and the features it wants. From then on each
+//! snapshot (`vtSnapshot`) and diff (`vtPollDiff*`) starts with a header
+//! saying which version and features it was written with:
+//!
+//! ```text
+//! snapshot := 0 version:u8 features snapshot_v0
+//! diff     := 8 version:u8 features diff_v0       (KIND_VERSIONED)
+//! ```
+//!
+//! where `snapshot_v0` and `diff_v0` are the unversioned forms (see
+//! `snapshot` and `diff`) and `features` a varint of `FEATURE_*` bits. The
+//! unversioned snapshot starts with its column count, which clients never
+//! make 0 (`AvtVirtualTerminal` requires a positive size), and the
+//! unversioned diff with a kind below 8, so either decodes with or without
+//! a header. A feature the client didn't ask for, or that this library
+//! doesn't know, is left out of what it's sent: runs without links and no
+//! link table, no image table or image changes, or the default cursor
+//! shape. The version sent is the lower of the client's and `VERSION`,
+//! which `vtProtocolVersion` reports.
+//!
+//! A client asking for version 0 gets no header, only the features it
+//! named. The negotiation is kept across resets. Interned, region and
+//! delta snapshots have their own epochs and take the features but no
+//! header.
+
+use crate::snapshot::{CursorShape, DecodeError, Reader, Screen};
+use crate::{handles, write_varint, VtHandle};
+use jni::objects::JClass;
+use jni::sys::jint;
+use jni::JNIEnv;
+
+/// The newest protocol version this library writes.
+pub const VERSION: u8 = 1;
+
+/// Leading byte of a versioned snapshot
+pub const SNAPSHOT_HEADER: u8 = 0;
+
+/// Feature bits, see the module docs
+pub const FEATURE_LINKS: u32 = 0x01;
+pub const FEATURE_IMAGES: u32 = 0x02;
+pub const FEATURE_CURSOR_SHAPE: u32 = 0x04;
+/// Every feature this library knows
+pub const FEATURES: u32 = FEATURE_LINKS | FEATURE_IMAGES | FEATURE_CURSOR_SHAPE;
+
+/// What a VT's client has negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wire {
+    /// 0 for the unversioned formats
+    pub version: u8,
+    pub features: u32,
+}
+
+impl Default for Wire {
+    /// The formats as sent before any negotiation.
+    fn default() -> Self {
+        Wire {
+            version: 0,
+            features: FEATURES,
+        }
+    }
+}
+
+impl Wire {
+    /// What a client decoding `version` and wanting `features` is sent.
+    pub fn negotiate(version: u32, features: u32) -> Self {
+        Wire {
+            version: version.min(VERSION as u32) as u8,
+            features: features & FEATURES,
+        }
+    }
+
+    pub fn has(&self, feature: u32) -> bool {
+        self.features & feature != 0
+    }
+
+    /// Leave out of `screen` what the client didn't ask for.
+    pub fn restrict(&self, screen: &mut Screen) {
+        if !self.has(FEATURE_LINKS) {
+            screen.links.clear();
+            for run in screen.lines.iter_mut().flat_map(|line| &mut line.runs) {
+                run.link = 0;
+            }
+        }
+        if !self.has(FEATURE_IMAGES) {
+            screen.images.clear();
+        }
+        if !self.has(FEATURE_CURSOR_SHAPE) {
+            screen.cursor.shape = CursorShape::Block;
+            screen.cursor.blink = true;
+        }
+    }
+
+    /// Append the header a snapshot starts with, if versioned.
+    pub fn snapshot_header(&self, buf: &mut Vec<u8>) {
+        self.header(SNAPSHOT_HEADER, buf);
+    }
+
+    /// `diff` behind its header, if versioned.
+    pub fn frame_diff(&self, diff: Vec<u8>) -> Vec<u8> {
+        if self.version == 0 {
+            return diff;
+        }
+        let mut buf = Vec::with_capacity(diff.len() + 3);
+        self.header(crate::diff::KIND_VERSIONED, &mut buf);
+        buf.extend_from_slice(&diff);
+        buf
+    }
+
+    fn header(&self, tag: u8, buf: &mut Vec<u8>) {
+        if self.version > 0 {
+            buf.push(tag);
+            buf.push(self.version);
+            write_varint(buf, self.features as usize);
+        }
+    }
+}
+
+/// A payload's header: the version and features it was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub features: u32,
+}
+
+/// A snapshot's header, if it has one, and the unversioned snapshot after.
+pub fn split_snapshot(bytes: &[u8]) -> Result<(Option<Header>, &[u8]), DecodeError> {
+    split(bytes, SNAPSHOT_HEADER)
+}
+
+/// A diff's header, if it has one, and the unversioned diff after.
+pub fn split_diff(bytes: &[u8]) -> Result<(Option<Header>, &[u8]), DecodeError> {
+    split(bytes, crate::diff::KIND_VERSIONED)
+}
+
+fn split(bytes: &[u8], tag: u8) -> Result<(Option<Header>, &[u8]), DecodeError> {
+    if bytes.first() != Some(&tag) {
+        return Ok((None, bytes));
+    }
+    let mut r = Reader::new(&bytes[1..]);
+    let header = Header {
+        version: r.byte()?,
+        features: r.varint()? as u32,
+    };
+    let rest = r.take(r.remaining())?;
+    Ok((Some(header), rest))
+}
+
+// JNI functions
+
+/// `VERSION`, the newest protocol version this library writes.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtProtocolVersion(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    jni_guard!(env, { VERSION as jint })
+}
+
+/// Negotiate what the VT sends, see the module docs. Returns the features
+/// it will send, -1 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetWireFormat(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    version: jint,
+    features: jint,
+) -> jint {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -1;
+        };
+
+        let wire = Wire::negotiate(version.max(0) as u32, features as u32);
+        vt.set_wire(wire);
+        wire.features as jint
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::{diff, snapshot, AvtState};
+
+    #[test]
+    fn headers_only_once_negotiated() {
+        let mut vt = AvtState::with_backend(fake(6, 2));
+        vt.feed(b"hi");
+        let legacy = vt.encode_snapshot();
+        assert_eq!(split_snapshot(&legacy).unwrap(), (None, &legacy[..]));
+        vt.poll_diff();
+
+        // Newer than this library: the version comes down to ours
+        let wire = Wire::negotiate(99, FEATURE_LINKS | 0x100);
+        assert_eq!(wire.features, FEATURE_LINKS);
+        vt.set_wire(wire);
+        let bytes = vt.encode_snapshot();
+        let header = Header {
+            version: VERSION,
+            features: FEATURE_LINKS,
+        };
+        assert_eq!(split_snapshot(&bytes).unwrap(), (Some(header), &legacy[..]));
+        assert_eq!(
+            snapshot::decode(&bytes).unwrap(),
+            snapshot::decode(&legacy).unwrap()
+        );
+
+        vt.feed(b"!");
+        let bytes = vt.poll_diff().unwrap();
+        assert_eq!(bytes[0], diff::KIND_VERSIONED);
+        let (framed, body) = split_diff(&bytes).unwrap();
+        assert_eq!(framed, Some(header));
+        assert!(body[0] < diff::KIND_VERSIONED);
+        assert_eq!(diff::decode(&bytes).unwrap(), diff::decode(body).unwrap());
+
+        // Kept across resets
+        vt.reset(6, 2);
+        assert_eq!(vt.wire(), wire);
+    }
+
+    #[test]
+    fn features_not_asked_for_are_left_out() {
+        let mut vt = AvtState::with_backend(fake(6, 1));
+        vt.feed(b"a");
+        let mut screen = vt.screen();
+        screen.lines[0].runs[0].link = 1;
+        screen.links = vec![(1, "https://example.com".into())];
+        screen.cursor.shape = CursorShape::Bar;
+
+        let mut restricted = screen.clone();
+        Wire::negotiate(1, FEATURE_IMAGES).restrict(&mut restricted);
+        assert!(restricted.links.is_empty());
+        assert_eq!(restricted.lines[0].runs[0].link, 0);
+        assert_eq!(restricted.cursor.shape, CursorShape::Block);
+
+        let mut all = screen.clone();
+        Wire::default().restrict(&mut all);
+        assert_eq!(all, screen);
+    }
+}
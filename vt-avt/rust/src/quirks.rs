@@ -0,0 +1,145 @@
+//! Emulating the terminal a cast was recorded in.
+//!
+//! avt behaves like xterm. A recording made in another terminal can
+//! contain sequences that terminal ignored but avt acts on, and then
+//! renders differently from what the author saw. A quirk profile drops
+//! those sequences before avt sees them.
+//!
+//! | quirk             | xterm | linux | tmux |
+//! |-------------------|-------|-------|------|
+//! | alternate screen  | yes   | no    | yes  |
+//! | DECDHL / DECDWL   | yes   | no    | no   |
+//! | REP (`CSI b`)     | yes   | no    | yes  |
+//!
+//! The margin behaviors terminfo records are the same in all three (`am`
+//! and `xenl`, no `bw`), so wrapping needs no adjustment.
+
+use crate::scan::Action;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    #[default]
+    Xterm,
+    /// Linux virtual console
+    Linux,
+    Tmux,
+}
+
+impl Profile {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Profile::Xterm),
+            1 => Some(Profile::Linux),
+            2 => Some(Profile::Tmux),
+            _ => None,
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Profile::Xterm => Quirks::default(),
+            Profile::Linux => Quirks {
+                alt_screen: false,
+                line_attributes: false,
+                repeat: false,
+            },
+            Profile::Tmux => Quirks {
+                line_attributes: false,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+/// Sequences the emulated terminal supports; avt supports all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// Modes 47, 1047 and 1049
+    pub alt_screen: bool,
+    /// `ESC # 3` to `ESC # 6`
+    pub line_attributes: bool,
+    /// `CSI Ps b`
+    pub repeat: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            alt_screen: true,
+            line_attributes: true,
+            repeat: true,
+        }
+    }
+}
+
+impl Quirks {
+    /// Whether the emulated terminal would ignore `action`.
+    pub fn ignores(&self, action: &Action) -> bool {
+        match action {
+            Action::Csi(csi) if csi.marker == Some(b'?') && !csi.params().is_empty() => {
+                !self.alt_screen
+                    && matches!(csi.final_byte, b'h' | b'l')
+                    && csi.params().iter().all(|p| matches!(p, 47 | 1047 | 1049))
+            }
+            Action::Csi(csi) if csi.marker.is_none() && csi.intermediates().is_empty() => {
+                !self.repeat && csi.final_byte == b'b'
+            }
+            Action::Esc {
+                intermediate: Some(b'#'),
+                final_byte: b'3'..=b'6',
+            } => !self.line_attributes,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::lineattr::LineAttr;
+    use crate::scan::Scanner;
+    use crate::AvtState;
+
+    fn ignored(profile: Profile) -> Vec<&'static str> {
+        let sequences = [
+            ("alt", "\x1b[?1049h"),
+            ("alt", "\x1b[?47l"),
+            ("dwl", "\x1b#6"),
+            ("dhl", "\x1b#3"),
+            ("rep", "\x1b[3b"),
+            ("decaln", "\x1b#8"),
+            ("cursor", "\x1b[?25l"),
+            ("mixed", "\x1b[?1049;25h"),
+        ];
+        let quirks = profile.quirks();
+        let mut out = Vec::new();
+        for (name, bytes) in sequences {
+            Scanner::new().scan(bytes.as_bytes(), |_, action| {
+                if quirks.ignores(&action) {
+                    out.push(name);
+                }
+            });
+        }
+        out
+    }
+
+    #[test]
+    fn profiles_pin_ignored_sequences() {
+        assert!(ignored(Profile::Xterm).is_empty());
+        assert_eq!(ignored(Profile::Linux), ["alt", "alt", "dwl", "dhl", "rep"]);
+        assert_eq!(ignored(Profile::Tmux), ["dwl", "dhl"]);
+    }
+
+    #[test]
+    fn ignored_sequences_do_not_reach_the_screen() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.set_quirks(Profile::Tmux.quirks());
+        state.feed(b"\x1b#6");
+        assert_eq!(state.screen().lines[0].attr, LineAttr::Single);
+
+        state.set_quirks(Profile::Xterm.quirks());
+        state.feed(b"\x1b#6");
+        assert_eq!(state.screen().lines[0].attr, LineAttr::DoubleWidth);
+    }
+}
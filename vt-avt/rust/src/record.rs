@@ -0,0 +1,592 @@
+//! Recording a live or interactive stream to an asciicast v2 file.
+//!
+//! Recording usually starts partway through a stream, when the screen
+//! already shows output the recorder never saw. So the first event, at
+//! time 0, is a state dump: `dump_ansi` of the VT being watched, which
+//! recreates its screen in a fresh terminal. Output from then on follows
+//! as normal events, and the saved cast replays from its first frame.
+//!
+//! Events are written as they arrive, so a long stream doesn't build up in
+//! memory and a crash loses at most what the writer hadn't flushed.
+//!
+//! Streams the app terminates itself, like a local shell or an SSH
+//! channel, have no VT to backfill from and carry their own clock: the
+//! `rec*` functions start a recorder from just a size and take each
+//! event's time from the caller. Times that go backwards are written as
+//! the previous one, so the file stays valid asciicast v2.
+
+use crate::backend::TerminalBackend;
+use crate::cast::{micros_arg, Event, EventKind, Header, Timing};
+use crate::handles::{self, Kind};
+use crate::json::Value;
+use crate::{decode_utf8, AvtState, Instant, VtHandle};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Recorder<W: Write> {
+    out: W,
+    started: Instant,
+    /// Time of the last event written
+    last_us: i64,
+    timing: Timing,
+    /// Incomplete UTF-8 sequence at the end of the last output
+    partial: Vec<u8>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Write the header and the state dump of `state`.
+    pub fn start<B: TerminalBackend>(out: W, state: &AvtState<B>) -> io::Result<Self> {
+        let (cols, rows) = state.backend().size();
+        let mut recorder = Recorder::new(out, cols, rows)?;
+        let dump = state.dump_ansi();
+        if !dump.is_empty() {
+            recorder.write(0, EventKind::Output(dump))?;
+        }
+        Ok(recorder)
+    }
+
+    /// Write the header of an empty `cols` x `rows` terminal.
+    pub fn new(out: W, cols: usize, rows: usize) -> io::Result<Self> {
+        Recorder::with_version(out, cols, rows, 2)
+    }
+
+    /// `new`, writing asciicast `version` 2 or 3 (which asciinema 3.x
+    /// reads and writes).
+    pub fn with_version(mut out: W, cols: usize, rows: usize, version: u32) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut fields = vec![("version".to_string(), Value::Number(version as f64))];
+        match version {
+            2 => fields.extend([
+                ("width".to_string(), Value::Number(cols as f64)),
+                ("height".to_string(), Value::Number(rows as f64)),
+            ]),
+            3 => fields.push((
+                "term".to_string(),
+                Value::Object(vec![
+                    ("cols".to_string(), Value::Number(cols as f64)),
+                    ("rows".to_string(), Value::Number(rows as f64)),
+                ]),
+            )),
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        }
+        fields.push(("timestamp".to_string(), Value::Number(timestamp as f64)));
+        let header = Header {
+            version,
+            cols,
+            rows,
+            fields: Value::Object(fields),
+        };
+        writeln!(out, "{}", header.fields)?;
+
+        Ok(Recorder {
+            out,
+            started: Instant::now(),
+            last_us: 0,
+            timing: Timing::new(&header),
+            partial: Vec::new(),
+        })
+    }
+
+    /// Microseconds since `start`.
+    pub fn elapsed_us(&self) -> i64 {
+        self.started.elapsed().as_micros() as i64
+    }
+
+    fn write(&mut self, time_us: i64, kind: EventKind) -> io::Result<()> {
+        let time_us = time_us.max(self.last_us);
+        self.last_us = time_us;
+        writeln!(self.out, "{}", self.timing.line(&Event { time_us, kind }))
+    }
+
+    /// Record output bytes; a UTF-8 sequence split across calls is kept
+    /// whole in the later event.
+    pub fn output(&mut self, time_us: i64, bytes: &[u8]) -> io::Result<()> {
+        let mut text = String::new();
+        decode_utf8(&mut self.partial, bytes, |s| text.push_str(s));
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.write(time_us, EventKind::Output(text))
+    }
+
+    pub fn input(&mut self, time_us: i64, text: &str) -> io::Result<()> {
+        self.write(time_us, EventKind::Input(text.to_string()))
+    }
+
+    pub fn resize(&mut self, time_us: i64, cols: usize, rows: usize) -> io::Result<()> {
+        self.write(time_us, EventKind::Resize { cols, rows })
+    }
+
+    pub fn marker(&mut self, time_us: i64, label: &str) -> io::Result<()> {
+        self.write(time_us, EventKind::Marker(label.to_string()))
+    }
+
+    /// Flush and return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Where a recorder started from Java writes.
+pub enum Output {
+    Plain(BufWriter<File>),
+    /// Encrypted as it's written, see `crypt`. A recording cut off before
+    /// `recorderStop` won't open, since its last chunk was never sealed.
+    #[cfg(feature = "encryption")]
+    Sealed(crate::crypt::Sealer<BufWriter<File>>),
+}
+
+impl Output {
+    /// Flush, sealing the end of an encrypted recording.
+    fn close(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut out) => out.flush(),
+            #[cfg(feature = "encryption")]
+            Output::Sealed(out) => out.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(out) => out.write(bytes),
+            #[cfg(feature = "encryption")]
+            Output::Sealed(out) => out.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(out) => out.flush(),
+            #[cfg(feature = "encryption")]
+            Output::Sealed(out) => out.flush(),
+        }
+    }
+}
+
+pub(crate) type FileRecorder = Recorder<Output>;
+
+registered!(FileRecorder, Kind::Recorder);
+
+// JNI functions
+
+/// Start recording to a new file at `path`, backfilled from the VT
+/// `handle`. Returns 0 if the VT is invalid or the file can't be created.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderStart(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    path: JString,
+) -> jlong {
+    jni_guard!(env, {
+        let Ok(path) = env.get_string(&path) else {
+            return 0;
+        };
+        let path: String = path.into();
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        let Ok(file) = File::create(path) else {
+            return 0;
+        };
+        start(Output::Plain(BufWriter::new(file)), vt)
+    })
+}
+
+fn start<B: TerminalBackend>(out: Output, vt: &AvtState<B>) -> jlong {
+    register(Recorder::start(out, vt))
+}
+
+fn register(recorder: io::Result<FileRecorder>) -> jlong {
+    match recorder {
+        Ok(recorder) => handles::add(recorder),
+        Err(_) => 0,
+    }
+}
+
+/// `recorderStart` to `fd`, encrypted with `key` as it's written (see
+/// `crypt`). The recorder owns `fd`, which is closed on failure too.
+/// Returns 0 if the VT, fd or key is invalid or the header can't be
+/// written.
+#[cfg(all(unix, feature = "encryption"))]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderStartEncrypted(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    fd: jint,
+    key: JByteArray,
+) -> jlong {
+    use crate::crypt::{self, Sealer};
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let Some(key) = crypt::key_arg(&env, &key) else {
+            return 0;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        let Ok(sealer) = Sealer::new(BufWriter::new(file), key) else {
+            return 0;
+        };
+        start(Output::Sealed(sealer), vt)
+    })
+}
+
+/// Flush and close the file, freeing the recorder. Returns false if the
+/// final flush failed.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderStop(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+) -> jboolean {
+    jni_guard!(env, { stop(&mut env, recorder) })
+}
+
+fn stop(env: &mut JNIEnv, recorder: jlong) -> jboolean {
+    let Some(recorder) = handles::take::<FileRecorder>(env, recorder) else {
+        return JNI_FALSE;
+    };
+    match recorder.finish().and_then(Output::close) {
+        Ok(()) => JNI_TRUE,
+        Err(_) => JNI_FALSE,
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderOutput(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    bytes: JByteArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return JNI_FALSE;
+        };
+        let time = recorder.elapsed_us();
+        match recorder.output(time, &bytes) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderInput(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    text: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(text) = env.get_string(&text) else {
+            return JNI_FALSE;
+        };
+        let text: String = text.into();
+        let time = recorder.elapsed_us();
+        match recorder.input(time, &text) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderResize(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    cols: jint,
+    rows: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+        if cols <= 0 || rows <= 0 {
+            return JNI_FALSE;
+        }
+
+        let time = recorder.elapsed_us();
+        match recorder.resize(time, cols as usize, rows as usize) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recorderMarker(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    label: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(label) = env.get_string(&label) else {
+            return JNI_FALSE;
+        };
+        let label: String = label.into();
+        let time = recorder.elapsed_us();
+        match recorder.marker(time, &label) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// Start recording a `cols` x `rows` stream with no VT behind it to a new
+/// asciicast `version` 2 or 3 file at `path`. Returns 0 for an invalid
+/// size or version or if the file can't be created.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+    version: jint,
+    path: JString,
+) -> jlong {
+    jni_guard!(env, {
+        if cols <= 0 || rows <= 0 || !(2..=3).contains(&version) {
+            return 0;
+        }
+        let Ok(path) = env.get_string(&path) else {
+            return 0;
+        };
+        let path: String = path.into();
+
+        let Ok(file) = File::create(path) else {
+            return 0;
+        };
+        let out = Output::Plain(BufWriter::new(file));
+        register(Recorder::with_version(
+            out,
+            cols as usize,
+            rows as usize,
+            version as u32,
+        ))
+    })
+}
+
+/// `recNew` writing to `fd`, which the recorder owns and closes on
+/// failure too.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recNewFd(
+    mut env: JNIEnv,
+    _class: JClass,
+    cols: jint,
+    rows: jint,
+    version: jint,
+    fd: jint,
+) -> jlong {
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        if cols <= 0 || rows <= 0 || !(2..=3).contains(&version) {
+            return 0;
+        }
+
+        let out = Output::Plain(BufWriter::new(file));
+        register(Recorder::with_version(
+            out,
+            cols as usize,
+            rows as usize,
+            version as u32,
+        ))
+    })
+}
+
+/// Record output at `time_us` since the start. Returns false for an
+/// invalid recorder or time, or a failed write.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recWriteOutput(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    time_us: jlong,
+    bytes: JByteArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(time) = micros_arg(time_us) else {
+            return JNI_FALSE;
+        };
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return JNI_FALSE;
+        };
+        match recorder.output(time, &bytes) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recMarker(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    time_us: jlong,
+    label: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(time) = micros_arg(time_us) else {
+            return JNI_FALSE;
+        };
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(label) = env.get_string(&label) else {
+            return JNI_FALSE;
+        };
+        let label: String = label.into();
+        match recorder.marker(time, &label) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recResize(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+    time_us: jlong,
+    cols: jint,
+    rows: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(time) = micros_arg(time_us) else {
+            return JNI_FALSE;
+        };
+        let Some(recorder) = handles::object::<FileRecorder>(&mut env, recorder) else {
+            return JNI_FALSE;
+        };
+        if cols <= 0 || rows <= 0 {
+            return JNI_FALSE;
+        }
+
+        match recorder.resize(time, cols as usize, rows as usize) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// As `recorderStop`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_recFinish(
+    mut env: JNIEnv,
+    _class: JClass,
+    recorder: jlong,
+) -> jboolean {
+    jni_guard!(env, { stop(&mut env, recorder) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::cast::Cast;
+
+    #[test]
+    fn late_start_replays_from_the_first_frame() {
+        let mut live = AvtState::with_backend(fake(6, 2));
+        live.feed(b"before");
+
+        let mut recorder = Recorder::start(Vec::new(), &live).unwrap();
+        recorder.output(1_000, b"\r\xc3").unwrap();
+        recorder.output(2_000, b"\xa9").unwrap();
+        let cast = Cast::parse(&recorder.finish().unwrap()).unwrap();
+
+        assert_eq!((cast.header.cols, cast.header.rows), (6, 2));
+        let texts: Vec<_> = cast
+            .events
+            .iter()
+            .map(|event| match &event.kind {
+                EventKind::Output(text) => (event.time_us, text.clone()),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(texts[0], (0, live.dump_ansi()));
+        assert_eq!(
+            texts[1..],
+            [(1_000, "\r".to_string()), (2_000, "é".to_string())]
+        );
+
+        // Replaying the recording shows what the live VT showed
+        let mut replay = AvtState::with_backend(fake(6, 2));
+        for (_, text) in &texts {
+            replay.feed(text.as_bytes());
+        }
+        live.feed("\ré".as_bytes());
+        assert_eq!(replay.backend().row_text(0), live.backend().row_text(0));
+    }
+
+    #[test]
+    fn fed_streams_keep_times_in_order() {
+        let mut recorder = Recorder::new(Vec::new(), 100, 30).unwrap();
+        recorder.output(500, b"say \"hi\"\x1b[0m\n").unwrap();
+        recorder.marker(400, "late").unwrap();
+        recorder.resize(2_000, 120, 40).unwrap();
+        let bytes = recorder.finish().unwrap();
+
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.contains(r#"[0.000500, "o", "say \"hi\"\u001b[0m\n"]"#));
+        let cast = Cast::parse(&bytes).unwrap();
+        assert_eq!((cast.header.cols, cast.header.rows), (100, 30));
+        let times: Vec<_> = cast.events.iter().map(|event| event.time_us).collect();
+        assert_eq!(times, [500, 500, 2_000]);
+        assert_eq!(cast.events[1].kind, EventKind::Marker("late".into()));
+
+        let mut recorder = Recorder::with_version(Vec::new(), 100, 30, 3).unwrap();
+        recorder.output(500, b"a").unwrap();
+        recorder.output(2_000, b"b").unwrap();
+        let bytes = recorder.finish().unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("[0.001500, \"o\", \"b\"]"));
+        let cast = Cast::parse(&bytes).unwrap();
+        assert_eq!((cast.header.version, cast.header.cols), (3, 100));
+        assert_eq!(cast.events[1].time_us, 2_000);
+    }
+}
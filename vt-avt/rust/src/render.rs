@@ -0,0 +1,564 @@
+//! Software rendering of the screen to ARGB pixels.
+//!
+//! For screenshots to share, and for devices too slow to lay out a grid of
+//! Compose text every frame: `vtRenderBitmap` draws the visible screen into
+//! a direct `ByteBuffer` the app passes on to `Bitmap.copyPixelsFromBuffer`,
+//! pixel for pixel the same on every device since no system font is
+//! involved.
+//!
+//! Each cell is the bitmap size divided by the terminal size, and pixels
+//! left over at the right and bottom are background. Text is DejaVu Sans
+//! Mono, bundled in `fonts/` and rasterized with fontdue as large as fits
+//! the cell, wide chars centered across both of theirs. Block elements and
+//! light box drawing are drawn as shapes instead, so they join across
+//! cells; a char the font lacks gets its missing glyph box. Colors come
+//! from a theme of the `themes` registry, resolved as by `vtResolveStyles`.
+//! Bold draws thicker, italic slanted, faint halfway to the background,
+//! and blink not at all, a bitmap being one still frame.
+//!
+//! `vtRenderBitmapProtected` draws an always-on display's frame, shifted
+//! and with static rows dimmed as `burnin` calls for. The terminal gets
+//! the bitmap less the largest shift on every side, so it stays whole.
+//!
+//! Pixels are `ARGB_8888` in the byte order Android keeps them in memory:
+//! R, G, B, A.
+
+use crate::burnin::{self, Frame};
+use crate::direct::buffer_bytes;
+use crate::lineattr::LineAttr;
+use crate::palette::{self, Options, Palette};
+use crate::snapshot::{
+    CursorShape, Screen, ATTR_BOLD, ATTR_FAINT, ATTR_ITALIC, ATTR_STRIKETHROUGH, ATTR_UNDERLINE,
+};
+use crate::themes::{self, name_arg};
+use crate::{handles, VtHandle};
+use fontdue::{Font, FontSettings};
+use jni::objects::{JByteBuffer, JClass, JIntArray, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// DejaVu Sans Mono, see `fonts/LICENSE`
+const FONT_TTF: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+fn font() -> &'static Font {
+    static FONT: OnceLock<Font> = OnceLock::new();
+    FONT.get_or_init(|| {
+        Font::from_bytes(FONT_TTF, FontSettings::default()).expect("the bundled font parses")
+    })
+}
+
+/// A char's coverage of its box, 0 to 255 a pixel, rows from the top.
+struct Glyph {
+    width: usize,
+    coverage: Vec<u8>,
+}
+
+impl Glyph {
+    /// `ch` as large as fits a cell `cell_width` by `height`, centered in
+    /// a box `cells` wide, with the font's ascent and descent centered
+    /// too so every glyph shares the baseline.
+    fn rasterize(ch: char, cell_width: usize, cells: usize, height: usize) -> Glyph {
+        let font = font();
+        let width = cell_width * cells;
+        let line = font
+            .horizontal_line_metrics(1.0)
+            .expect("the bundled font has line metrics");
+        let em_height = line.ascent - line.descent;
+        let px = (height as f32 / em_height)
+            .min(cell_width as f32 / font.metrics('M', 1.0).advance_width);
+        let (metrics, bitmap) = font.rasterize(ch, px);
+        let baseline = ((height as f32 - px * em_height) / 2.0 + px * line.ascent).round() as isize;
+        let left =
+            ((width as f32 - metrics.advance_width) / 2.0).round() as isize + metrics.xmin as isize;
+        let top = baseline - metrics.ymin as isize - metrics.height as isize;
+
+        let mut coverage = vec![0; width * height];
+        for (row, values) in bitmap.chunks(metrics.width.max(1)).enumerate() {
+            let y = top + row as isize;
+            if !(0..height as isize).contains(&y) {
+                continue;
+            }
+            for (col, &value) in values.iter().enumerate() {
+                let x = left + col as isize;
+                if (0..width as isize).contains(&x) {
+                    coverage[y as usize * width + x as usize] = value;
+                }
+            }
+        }
+        Glyph { width, coverage }
+    }
+
+    fn at(&self, x: isize, y: usize) -> u32 {
+        match usize::try_from(x) {
+            Ok(x) if x < self.width => self
+                .coverage
+                .get(y * self.width + x)
+                .map_or(0, |&c| c as u32),
+            _ => 0,
+        }
+    }
+}
+
+/// Glyphs rasterized for one render, by char, cell width, cells and height
+type Glyphs = HashMap<(char, usize, usize, usize), Glyph>;
+
+/// Light box drawing chars, with the lines they draw from the middle of
+/// the cell: left, right, up, down
+const LINES: [(char, [bool; 4]); 15] = [
+    ('─', [true, true, false, false]),
+    ('│', [false, false, true, true]),
+    ('┌', [false, true, false, true]),
+    ('┐', [true, false, false, true]),
+    ('└', [false, true, true, false]),
+    ('┘', [true, false, true, false]),
+    ('├', [false, true, true, true]),
+    ('┤', [true, false, true, true]),
+    ('┬', [true, true, false, true]),
+    ('┴', [true, true, true, false]),
+    ('┼', [true, true, true, true]),
+    ('╭', [false, true, false, true]),
+    ('╮', [true, false, false, true]),
+    ('╯', [true, false, true, false]),
+    ('╰', [false, true, true, false]),
+];
+
+/// What a char draws in its cell.
+enum Shape<'a> {
+    Glyph(&'a Glyph),
+    /// The part `x` across and `y` down of the cell, in eighths
+    Block {
+        x: Range<usize>,
+        y: Range<usize>,
+    },
+    /// The whole cell, this many quarters of the way to the foreground
+    Shade(u32),
+    Lines([bool; 4]),
+}
+
+impl<'a> Shape<'a> {
+    /// Block elements and light box drawing as shapes, anything else from
+    /// the font (the font's own missing glyph box for a char it lacks).
+    fn of(
+        ch: char,
+        glyphs: &'a mut Glyphs,
+        cell_width: usize,
+        cells: usize,
+        height: usize,
+    ) -> Self {
+        let c = ch as usize;
+        let block = |x, y| Shape::Block { x, y };
+        match ch {
+            '\u{2580}' => block(0..8, 0..4),
+            '\u{2581}'..='\u{2588}' => block(0..8, 0x2588 - c..8),
+            '\u{2589}'..='\u{258f}' => block(0..0x2590 - c, 0..8),
+            '\u{2590}' => block(4..8, 0..8),
+            '\u{2591}'..='\u{2593}' => Shape::Shade(c as u32 - 0x2590),
+            '\u{2594}' => block(0..8, 0..1),
+            '\u{2595}' => block(7..8, 0..8),
+            _ => match LINES.iter().find(|&&(line, _)| line == ch) {
+                Some(&(_, lines)) => Shape::Lines(lines),
+                None => Shape::Glyph(
+                    glyphs
+                        .entry((ch, cell_width, cells, height))
+                        .or_insert_with(|| Glyph::rasterize(ch, cell_width, cells, height)),
+                ),
+            },
+        }
+    }
+
+    /// Quarters of the foreground at `(x, y)` in a `w` by `h` box, glyph
+    /// coverage rounded to them. Underline takes the bottom ninth of the
+    /// box and strikethrough the middle one.
+    fn level(&self, x: usize, y: usize, w: usize, h: usize, attrs: u8) -> u32 {
+        let gy = y * 9 / h;
+        let lit = match self {
+            _ if gy == 8 && attrs & ATTR_UNDERLINE != 0 => true,
+            _ if gy == 4 && attrs & ATTR_STRIKETHROUGH != 0 => true,
+            Shape::Glyph(glyph) => {
+                // Slant by leaning the top a quarter of the box right, and
+                // embolden by smearing a tenth of it
+                let col = if attrs & ATTR_ITALIC != 0 {
+                    x as isize - ((h - y) * w / (4 * h)) as isize
+                } else {
+                    x as isize
+                };
+                let mut coverage = glyph.at(col, y);
+                if attrs & ATTR_BOLD != 0 {
+                    coverage = coverage.max(glyph.at(col - (w / 10).max(1) as isize, y));
+                }
+                return (coverage * 4 + 127) / 255;
+            }
+            Shape::Block { x: xs, y: ys } => xs.contains(&(x * 8 / w)) && ys.contains(&(y * 8 / h)),
+            Shape::Shade(level) => return *level,
+            Shape::Lines([left, right, up, down]) => {
+                let stroke = (w.min(h) / 8).max(1);
+                let (mid_x, mid_y) = (w / 2 - stroke / 2, h / 2 - stroke / 2);
+                let across = (mid_y..mid_y + stroke).contains(&y);
+                let down_the_middle = (mid_x..mid_x + stroke).contains(&x);
+                across && (*left && x < mid_x + stroke || *right && x >= mid_x)
+                    || down_the_middle && (*up && y < mid_y + stroke || *down && y >= mid_y)
+            }
+        };
+        if lit {
+            4
+        } else {
+            0
+        }
+    }
+}
+
+/// `quarters` of the way from `from` to `to`, both `0xAARRGGBB`.
+fn mix(from: u32, to: u32, quarters: u32) -> u32 {
+    let channel = |shift: u32| {
+        let (a, b) = (from >> shift & 0xff, to >> shift & 0xff);
+        ((a * (4 - quarters) + b * quarters) / 4) << shift
+    };
+    0xff000000 | channel(16) | channel(8) | channel(0)
+}
+
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    width: usize,
+    /// Where `put` draws `(0, 0)`
+    origin: (usize, usize),
+}
+
+impl Canvas<'_> {
+    fn put(&mut self, x: usize, y: usize, argb: u32) {
+        let at = ((y + self.origin.1) * self.width + x + self.origin.0) * 4;
+        let [a, r, g, b] = argb.to_be_bytes();
+        self.pixels[at..at + 4].copy_from_slice(&[r, g, b, a]);
+    }
+
+    fn fill(&mut self, xs: Range<usize>, ys: Range<usize>, argb: u32) {
+        for y in ys {
+            for x in xs.clone() {
+                self.put(x, y, argb);
+            }
+        }
+    }
+}
+
+/// A char's cell, and the box its shape is scaled to, which double-height
+/// lines make twice as tall as the cell, starting at or above it
+struct Cell {
+    xs: Range<usize>,
+    ys: Range<usize>,
+    box_y: isize,
+    box_height: usize,
+}
+
+/// Draw `screen` as `width` by `height` pixels to the front of `out`,
+/// shifted and dimmed as `frame` says; see the module docs. False, drawing
+/// nothing, if `out` is too small.
+pub fn render(
+    screen: &Screen,
+    palette: &Palette,
+    options: &Options,
+    frame: &Frame,
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+) -> bool {
+    let fits = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .is_some_and(|len| len <= out.len());
+    if !fits {
+        return false;
+    }
+    let mut canvas = Canvas {
+        pixels: out,
+        width,
+        origin: (0, 0),
+    };
+    let default_bg = 0xff000000 | palette.bg;
+    canvas.fill(0..width, 0..height, default_bg);
+    let margin = frame.margin.min(width / 2).min(height / 2);
+    let reach = margin as isize;
+    let shift = |d: i32| (reach + (d as isize).clamp(-reach, reach)) as usize;
+    canvas.origin = (shift(frame.dx), shift(frame.dy));
+    let (width, height) = (width - 2 * margin, height - 2 * margin);
+    let (cell_w, cell_h) = (width / screen.cols.max(1), height / screen.rows.max(1));
+    if cell_w == 0 || cell_h == 0 {
+        return true;
+    }
+
+    let right = screen.cols * cell_w;
+    let cursor = &screen.cursor;
+    let mut cursor_drawn = !cursor.visible;
+    let mut glyphs = Glyphs::new();
+    for (row, line) in screen.lines.iter().enumerate().take(screen.rows) {
+        let scale = if line.attr == LineAttr::Single { 1 } else { 2 };
+        let y = row * cell_h;
+        let (box_y, box_height) = match line.attr {
+            LineAttr::DoubleHeightTop => (y as isize, cell_h * 2),
+            LineAttr::DoubleHeightBottom => (y as isize - cell_h as isize, cell_h * 2),
+            _ => (y as isize, cell_h),
+        };
+        for run in &line.runs {
+            let colors = palette::resolve(run.style, palette, options);
+            let mut fg = colors.fg;
+            if colors.attrs & ATTR_FAINT != 0 {
+                fg = mix(colors.bg, fg, 2);
+            }
+            fg = burnin::fade(fg, colors.bg, frame.dim(row));
+            let cells = run.char_width();
+            for (col, ch) in run.columns() {
+                let (mut fg, mut bg) = (fg, colors.bg);
+                let under_cursor = cursor.row == row && (col..col + cells).contains(&cursor.col);
+                if under_cursor && !cursor_drawn && cursor.shape == CursorShape::Block {
+                    std::mem::swap(&mut fg, &mut bg);
+                    cursor_drawn = true;
+                }
+                let x = (col * cell_w * scale).min(right);
+                let cell = Cell {
+                    xs: x..(x + cells * cell_w * scale).min(right),
+                    ys: y..y + cell_h,
+                    box_y,
+                    box_height,
+                };
+                let shape = Shape::of(ch, &mut glyphs, cell_w * scale, cells, box_height);
+                draw(&mut canvas, &shape, &cell, colors.attrs, fg, bg);
+            }
+        }
+    }
+
+    if !cursor_drawn && cursor.col < screen.cols && cursor.row < screen.rows {
+        let scale = match screen.lines.get(cursor.row) {
+            Some(line) if line.attr != LineAttr::Single => 2,
+            _ => 1,
+        };
+        let (w, h) = (cell_w * scale, cell_h);
+        let (x, y) = ((cursor.col * w).min(right), cursor.row * h);
+        let (xs, ys) = match cursor.shape {
+            CursorShape::Block => (x..x + w, y..y + h),
+            CursorShape::Underline => (x..x + w, y + h - (h / 9).max(1)..y + h),
+            CursorShape::Bar => (x..x + (w / 6).max(1), y..y + h),
+        };
+        canvas.fill(xs.start..xs.end.min(right), ys, 0xff000000 | palette.fg);
+    }
+    true
+}
+
+fn draw(canvas: &mut Canvas, shape: &Shape, cell: &Cell, attrs: u8, fg: u32, bg: u32) {
+    let width = cell.xs.len();
+    if width == 0 {
+        return;
+    }
+    for y in cell.ys.clone() {
+        let box_y = (y as isize - cell.box_y) as usize;
+        for x in cell.xs.clone() {
+            let level = shape.level(x - cell.xs.start, box_y, width, cell.box_height, attrs);
+            canvas.put(x, y, mix(bg, fg, level));
+        }
+    }
+}
+
+// JNI functions
+
+/// Draw the screen into the direct `buffer` as `width` by `height` pixels,
+/// see the module docs, in the colors of theme `theme_id`; bold is bright
+/// as `vtSetBoldAsBright` set. False, drawing nothing, for an invalid
+/// handle, an unknown theme, a heap buffer or one smaller than
+/// `4 * width * height` bytes.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRenderBitmap(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    buffer: JByteBuffer,
+    width: jint,
+    height: jint,
+    theme_id: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let (Ok(width), Ok(height)) = (usize::try_from(width), usize::try_from(height)) else {
+            return JNI_FALSE;
+        };
+        let Some(out) = (unsafe { buffer_bytes(&env, &buffer) }) else {
+            return JNI_FALSE;
+        };
+        let Some(palette) = name_arg(&mut env, &theme_id).and_then(|name| themes::get(&name))
+        else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let options = Options::new(0, 1.0);
+        let frame = Frame::default();
+        if render(&vt.screen(), &palette, &options, &frame, width, height, out) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// `vtRenderBitmap` for an always-on display at `now_millis`, shifted and
+/// dimmed as `vtBurnIn` with `protection` says. False, drawing nothing,
+/// also for a negative time.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRenderBitmapProtected(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    buffer: JByteBuffer,
+    width: jint,
+    height: jint,
+    theme_id: JString,
+    now_millis: jlong,
+    protection: JIntArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let (Ok(width), Ok(height)) = (usize::try_from(width), usize::try_from(height)) else {
+            return JNI_FALSE;
+        };
+        let Ok(now_ms) = u64::try_from(now_millis) else {
+            return JNI_FALSE;
+        };
+        let Some(out) = (unsafe { buffer_bytes(&env, &buffer) }) else {
+            return JNI_FALSE;
+        };
+        let Some(palette) = name_arg(&mut env, &theme_id).and_then(|name| themes::get(&name))
+        else {
+            return JNI_FALSE;
+        };
+        let Some(protection) = burnin::protection_arg(&env, &protection) else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let options = Options::new(0, 1.0);
+        let frame = vt.burn_in(&protection, now_ms);
+        if render(&vt.screen(), &palette, &options, &frame, width, height, out) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn cells_draw_glyphs_shapes_and_the_cursor() {
+        let mut vt = AvtState::with_backend(fake(3, 2));
+        vt.feed("A▀".as_bytes());
+        // 6 by 9 cells, and a column and row to spare
+        let (width, height) = (19, 19);
+        let mut out = vec![0; width * height * 4];
+        let (palette, options) = (Palette::default(), Options::new(0, 1.0));
+        assert!(render(
+            &vt.screen(),
+            &palette,
+            &options,
+            &Frame::default(),
+            width,
+            height,
+            &mut out
+        ));
+
+        let pixel = |x: usize, y: usize| {
+            let at = (y * width + x) * 4;
+            u32::from_be_bytes([out[at + 3], out[at], out[at + 1], out[at + 2]])
+        };
+        let (fg, bg) = (0xffcccccc, 0xff000000);
+        // The A inked within its cell, then either half of the block
+        let inked = (0..6)
+            .flat_map(|x| (0..9).map(move |y| (x, y)))
+            .filter(|&(x, y)| pixel(x, y) != bg)
+            .count();
+        assert!((1..6 * 9 / 2).contains(&inked));
+        assert_eq!([pixel(6, 0), pixel(11, 3), pixel(6, 5)], [fg, fg, bg]);
+        // A block cursor on the blank cell; nothing past the grid
+        assert_eq!([pixel(12, 0), pixel(17, 8)], [fg, fg]);
+        assert_eq!([pixel(18, 0), pixel(0, 18)], [bg, bg]);
+
+        assert!(!render(
+            &vt.screen(),
+            &palette,
+            &options,
+            &Frame::default(),
+            width,
+            height + 1,
+            &mut out
+        ));
+    }
+
+    #[test]
+    fn chars_past_ascii_come_from_the_font() {
+        // Pixels of a 12 by 24 cell of each char
+        let cell = |ch: char| {
+            let mut vt = AvtState::with_backend(fake(1, 1));
+            vt.feed(ch.to_string().as_bytes());
+            let mut out = vec![0; 12 * 24 * 4];
+            let (palette, options) = (Palette::default(), Options::new(0, 1.0));
+            assert!(render(
+                &vt.screen(),
+                &palette,
+                &options,
+                &Frame::default(),
+                12,
+                24,
+                &mut out
+            ));
+            out
+        };
+        // A private use char the font lacks draws its missing glyph box
+        let missing = cell('\u{e0b0}');
+        for ch in ['é', 'λ', 'Ж', '═', '→'] {
+            let drawn = cell(ch);
+            assert!(drawn != missing && drawn != cell(' '), "{:?}", ch);
+        }
+        assert!(missing != cell(' '));
+    }
+
+    #[test]
+    fn protected_frames_shift_and_dim() {
+        let mut vt = AvtState::with_backend(fake(1, 1));
+        vt.feed(b"\xe2\x96\x88");
+        // A full block in a 6 by 9 cell, moved into a 1 pixel margin
+        let (width, height) = (8, 11);
+        let mut out = vec![0; width * height * 4];
+        let (palette, options) = (Palette::default(), Options::new(0, 1.0));
+        let frame = Frame {
+            margin: 1,
+            dx: 1,
+            dy: -1,
+            dims: vec![255 / 2],
+        };
+        assert!(render(
+            &vt.screen(),
+            &palette,
+            &options,
+            &frame,
+            width,
+            height,
+            &mut out
+        ));
+
+        let pixel = |x: usize, y: usize| {
+            let at = (y * width + x) * 4;
+            u32::from_be_bytes([out[at + 3], out[at], out[at + 1], out[at + 2]])
+        };
+        let (dimmed, bg) = (0xff666666, 0xff000000);
+        assert_eq!(
+            [pixel(1, 0), pixel(2, 0), pixel(7, 8)],
+            [bg, dimmed, dimmed]
+        );
+        assert_eq!([pixel(2, 9), pixel(7, 10)], [bg, bg]);
+    }
+}
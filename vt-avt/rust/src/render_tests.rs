@@ -0,0 +1,303 @@
+//! The software renderer, pixel for pixel, against reference images.
+//!
+//! Renders depend on more than `render` itself: the width tables decide
+//! where each glyph goes and palette resolution decides its colors, and a
+//! change to either shows in every screenshot. Each case renders a fixed
+//! screen, without the real emulator, and compares it with
+//! `fixtures/renders/<case>.ppm` (binary PPM, so any image viewer opens
+//! it). Pixels count as different by how different they look rather than
+//! by their bytes, and a few may differ, so a nudge to a color isn't a
+//! failure but a moved or recolored glyph is. On a failure the render and
+//! a map of the differing pixels are written to `render_tests/` in the
+//! temp dir. After a deliberate change, `BLESS=1 cargo test
+//! render_tests` rewrites the references.
+
+use super::*;
+use crate::backend::tests::fake;
+use crate::burnin::Frame;
+use crate::lineattr::LineAttr;
+use crate::palette::{Options, Palette, RESOLVE_BOLD_AS_BRIGHT};
+use crate::render::render;
+use crate::snapshot::{
+    Color, Cursor, CursorShape, Line, Run, Screen, Style, ATTR_BOLD, ATTR_FAINT, ATTR_INVERSE,
+    ATTR_ITALIC, ATTR_STRIKETHROUGH, ATTR_UNDERLINE,
+};
+use crate::themes;
+use std::fs;
+use std::path::Path;
+
+const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/renders");
+
+/// Pixels in a cell; big enough for the font's glyphs to be legible
+const CELL: (usize, usize) = (8, 12);
+
+/// How much two images may differ and still match.
+#[derive(Debug, Clone, Copy)]
+struct Tolerance {
+    /// Largest `distance` between two pixels that look the same
+    distance: f64,
+    /// Pixels allowed to look different, of all of them
+    pixels: f64,
+}
+
+const TOLERANCE: Tolerance = Tolerance {
+    distance: 0.01,
+    pixels: 0.001,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Image {
+    width: usize,
+    height: usize,
+    /// R, G, B per pixel, rows from the top
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    fn pixel(&self, i: usize) -> [u8; 3] {
+        [self.rgb[3 * i], self.rgb[3 * i + 1], self.rgb[3 * i + 2]]
+    }
+
+    fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        bytes.extend(&self.rgb);
+        bytes
+    }
+
+    /// `to_ppm`'s output back; `None` for any other PPM.
+    fn from_ppm(bytes: &[u8]) -> Option<Image> {
+        let mut fields = Vec::new();
+        let mut at = 0;
+        while fields.len() < 4 {
+            let start = at + bytes[at..].iter().position(|b| !b.is_ascii_whitespace())?;
+            at = start
+                + bytes[start..]
+                    .iter()
+                    .position(|b| b.is_ascii_whitespace())?;
+            fields.push(std::str::from_utf8(&bytes[start..at]).ok()?);
+        }
+        let [magic, width, height, max] = fields[..] else {
+            return None;
+        };
+        let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+        let rgb = bytes.get(at + 1..)?.to_vec();
+        let valid = magic == "P6" && max == "255" && rgb.len() == width * height * 3;
+        valid.then_some(Image { width, height, rgb })
+    }
+}
+
+/// How different two colors look, from 0 for the same to 1 at most: the
+/// squared distance in YIQ (luma and two chroma axes), weighted by how
+/// sensitive the eye is to each, as pixelmatch measures it.
+fn distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let [r, g, b] = [0, 1, 2].map(|i| a[i] as f64 - b[i] as f64);
+    let y = r * 0.29889531 + g * 0.58662247 + b * 0.11448223;
+    let i = r * 0.59597799 - g * 0.27417610 - b * 0.32180189;
+    let q = r * 0.21147017 - g * 0.52261711 + b * 0.31114694;
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / 35215.0
+}
+
+/// Indices of the pixels of `actual` that look different from
+/// `expected`'s, or `Err` with both sizes if they aren't the same size.
+fn differences(
+    actual: &Image,
+    expected: &Image,
+    tolerance: &Tolerance,
+) -> Result<Vec<usize>, String> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return Err(format!(
+            "{}x{} against {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        ));
+    }
+    Ok((0..actual.width * actual.height)
+        .filter(|&i| distance(actual.pixel(i), expected.pixel(i)) > tolerance.distance)
+        .collect())
+}
+
+/// Whether `actual` matches `expected` within `tolerance`.
+fn matches(actual: &Image, expected: &Image, tolerance: &Tolerance) -> bool {
+    differences(actual, expected, tolerance).is_ok_and(|differing| {
+        differing.len() as f64 <= tolerance.pixels * (actual.width * actual.height) as f64
+    })
+}
+
+/// `actual` faded to grey, with the pixels in `differing` in red.
+fn difference_map(actual: &Image, differing: &[usize]) -> Image {
+    let mut map = actual.clone();
+    for pixel in map.rgb.chunks_mut(3) {
+        let grey = (pixel.iter().map(|&c| c as u32).sum::<u32>() / 3 / 4 + 96) as u8;
+        pixel.fill(grey);
+    }
+    for &i in differing {
+        map.rgb[3 * i..3 * i + 3].copy_from_slice(&[255, 0, 0]);
+    }
+    map
+}
+
+fn rendered(screen: &Screen, palette: &Palette) -> Image {
+    let (width, height) = (screen.cols * CELL.0, screen.rows * CELL.1);
+    let mut out = vec![0; width * height * 4];
+    let options = Options::new(RESOLVE_BOLD_AS_BRIGHT, 1.0);
+    assert!(render(
+        screen,
+        palette,
+        &options,
+        &Frame::default(),
+        width,
+        height,
+        &mut out
+    ));
+    let rgb = out
+        .chunks(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    Image { width, height, rgb }
+}
+
+/// `image` must match the reference `name`, or become it when blessing.
+fn reference(name: &str, image: &Image) {
+    let path = Path::new(DIR).join(format!("{}.ppm", name));
+    if std::env::var_os("BLESS").is_some() {
+        fs::create_dir_all(DIR).unwrap();
+        fs::write(&path, image.to_ppm()).unwrap();
+        return;
+    }
+    let bytes = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let expected =
+        Image::from_ppm(&bytes).unwrap_or_else(|| panic!("{}: not a P6 PPM", path.display()));
+    if matches(image, &expected, &TOLERANCE) {
+        return;
+    }
+
+    let out = std::env::temp_dir().join("render_tests");
+    fs::create_dir_all(&out).unwrap();
+    fs::write(out.join(format!("{}.ppm", name)), image.to_ppm()).unwrap();
+    match differences(image, &expected, &TOLERANCE) {
+        Ok(differing) => {
+            let map = difference_map(image, &differing);
+            fs::write(out.join(format!("{}.diff.ppm", name)), map.to_ppm()).unwrap();
+            panic!(
+                "{}: {} of {} pixels differ, see {}",
+                name,
+                differing.len(),
+                image.width * image.height,
+                out.display()
+            );
+        }
+        Err(sizes) => panic!("{}: {}, see {}", name, sizes, out.display()),
+    }
+}
+
+/// Every kind of color and the attributes drawn differently.
+fn styles() -> Screen {
+    let run = |col, text: &str, fg, bg, attrs| Run {
+        col,
+        text: text.to_string(),
+        cells: text.chars().count(),
+        style: Style { fg, bg, attrs },
+        link: 0,
+    };
+    let line = |runs| Line {
+        attr: LineAttr::Single,
+        runs,
+    };
+    use Color::{Default as Plain, Indexed, Rgb};
+    Screen {
+        cols: 16,
+        rows: 4,
+        cursor: Cursor {
+            col: 15,
+            row: 3,
+            visible: true,
+            shape: CursorShape::Block,
+            blink: false,
+        },
+        lines: vec![
+            line(vec![
+                run(0, "Red", Indexed(1), Plain, 0),
+                run(3, "Bold", Indexed(1), Plain, ATTR_BOLD),
+                run(7, "Cube", Indexed(208), Indexed(17), 0),
+                run(11, "Grey", Indexed(250), Indexed(236), 0),
+            ]),
+            line(vec![
+                run(0, "Rgb", Rgb(255, 128, 0), Rgb(0, 48, 96), 0),
+                run(3, " inverse ", Indexed(2), Plain, ATTR_INVERSE),
+                run(12, "dim", Plain, Plain, ATTR_FAINT),
+            ]),
+            line(vec![
+                run(0, "ital", Plain, Plain, ATTR_ITALIC),
+                run(5, "under", Indexed(6), Plain, ATTR_UNDERLINE),
+                run(11, "gone", Plain, Plain, ATTR_STRIKETHROUGH),
+            ]),
+            Line {
+                attr: LineAttr::DoubleWidth,
+                runs: vec![run(0, "Wide", Indexed(3), Plain, ATTR_BOLD)],
+            },
+        ],
+        links: Vec::new(),
+        alt_screen: false,
+        images: Vec::new(),
+    }
+}
+
+#[test]
+fn colors_and_attributes_render_as_before() {
+    reference("styles", &rendered(&styles(), &Palette::default()));
+}
+
+#[test]
+fn char_widths_place_glyphs_as_before() {
+    let mut vt = AvtState::with_backend(fake(16, 2));
+    // Wide CJK and emoji, a combining accent, then box drawing to line up
+    vt.feed("a日本b e\u{301}😀x│▀".as_bytes());
+    reference("widths", &rendered(&vt.screen(), &Palette::default()));
+}
+
+#[test]
+fn chars_past_ascii_render_in_a_theme_as_before() {
+    let mut vt = AvtState::with_backend(fake(16, 2));
+    // Accents, Greek and Cyrillic, double box drawing, arrows, and a
+    // private use (powerline) char the font lacks
+    vt.feed("éñü λπΩ ЖЯ\r\n╔═╗→↑ \u{e0b0}".as_bytes());
+    let theme = themes::get("solarized-dark").unwrap();
+    reference("unicode", &rendered(&vt.screen(), &theme));
+}
+
+#[test]
+fn small_changes_pass_and_moved_or_recolored_glyphs_fail() {
+    let expected = rendered(&styles(), &Palette::default());
+
+    // The foreground and color 1 (red) each a step off
+    let nudged = Palette::from_ints(&[0xcdcccb, 0x000000, 0x000000, 0xce0000]);
+    assert!(matches(
+        &rendered(&styles(), &nudged),
+        &expected,
+        &TOLERANCE
+    ));
+
+    let mut recolored = Palette::default();
+    recolored.colors[208] = 0x00afff;
+    assert!(!matches(
+        &rendered(&styles(), &recolored),
+        &expected,
+        &TOLERANCE
+    ));
+
+    let mut shifted = styles();
+    shifted.lines[2].runs[1].col += 1;
+    assert!(!matches(
+        &rendered(&shifted, &Palette::default()),
+        &expected,
+        &TOLERANCE
+    ));
+
+    let mut cropped = styles();
+    cropped.rows = 3;
+    assert!(!matches(
+        &rendered(&cropped, &Palette::default()),
+        &expected,
+        &TOLERANCE
+    ));
+    assert_eq!(Image::from_ppm(&expected.to_ppm()), Some(expected));
+}
@@ -0,0 +1,107 @@
+//! Frame sampling for animation export.
+//!
+//! Chooses the times at which an exporter captures frames: after each
+//! change, but never faster than a frame rate. Builds with spinners and
+//! progress bars redraw a line every few milliseconds; those in-place
+//! redraws (as classified by `stalls`) get a much lower rate of their own,
+//! while output that adds lines keeps the full rate.
+
+use crate::cast::{Cast, EventKind};
+use crate::handles;
+use crate::scan::Scanner;
+use crate::stalls::{classify, Kind};
+use jni::objects::{JClass, JLongArray};
+use jni::sys::{jdouble, jlong};
+use jni::JNIEnv;
+
+/// Capture times in cast time: each frame shows every event at or before
+/// it. A change is shown at most `1 / max_fps` after the previous frame,
+/// or `1 / redraw_fps` if it only redrew a line in place.
+pub fn sample_frames(cast: &Cast, max_fps: f64, redraw_fps: f64) -> Vec<i64> {
+    let interval = |fps: f64| {
+        if fps > 0.0 {
+            (1_000_000.0 / fps).round() as i64
+        } else {
+            0
+        }
+    };
+    let (min_interval, redraw_interval) = (interval(max_fps), interval(redraw_fps.min(max_fps)));
+
+    let mut scanner = Scanner::new();
+    let mut alt_screen = false;
+    let mut frames: Vec<i64> = Vec::new();
+    // When the changes not yet captured are due
+    let mut due: Option<i64> = None;
+
+    for event in &cast.events {
+        let kind = match &event.kind {
+            EventKind::Output(data) => classify(&mut scanner, &mut alt_screen, data.as_bytes()),
+            EventKind::Resize { .. } => Kind::Informative,
+            _ => continue,
+        };
+
+        let t = event.time_us;
+        if let Some(d) = due.filter(|&d| d < t) {
+            frames.push(d);
+            due = None;
+        }
+
+        let wait = match kind {
+            Kind::Redraw => redraw_interval,
+            Kind::Informative => min_interval,
+        };
+        let ready = frames.last().map_or(t, |&last| t.max(last + wait));
+        due = Some(due.map_or(ready, |d| d.min(ready)));
+    }
+    frames.extend(due);
+    frames
+}
+
+// JNI functions
+
+/// Frame capture times in microseconds, see `sample_frames`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSampleFrames<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    max_fps: jdouble,
+    redraw_fps: jdouble,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        crate::long_array(&env, &sample_frames(cast, max_fps, redraw_fps))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spinner_redraws_sampled_at_lower_rate() {
+        let mut cast = String::from("{\"version\": 2, \"width\": 80, \"height\": 24}\n");
+        cast.push_str("[0.0, \"o\", \"$ npm install\\r\\n\"]\n");
+        // A spinner frame every 10ms for 2s, with a log line at 1.005s
+        for i in 1..=200 {
+            let time = i as f64 / 100.0;
+            cast.push_str(&format!("[{:.3}, \"o\", \"\\r\\u001b[K|\"]\n", time));
+            if i == 100 {
+                cast.push_str("[1.005, \"o\", \"\\radded 1 package\\r\\n\"]\n");
+            }
+        }
+        let cast = Cast::parse(cast.as_bytes()).unwrap();
+
+        let frames = sample_frames(&cast, 30.0, 2.0);
+        assert_eq!(
+            frames,
+            [0, 500_000, 1_000_000, 1_033_333, 1_533_333, 2_033_333]
+        );
+
+        // Without the lower rate every frame slot gets used
+        assert!(sample_frames(&cast, 30.0, 30.0).len() > 50);
+    }
+}
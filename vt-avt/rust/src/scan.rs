@@ -0,0 +1,584 @@
+//! Side-band escape sequence scanner.
+//!
+//! avt consumes the byte stream but doesn't tell us about everything in it
+//! (bells, OSC strings, modes it ignores). The scanner runs over the same
+//! bytes with a small VT500-style state machine and reports the sequences
+//! the wrapper cares about. State carries across calls, so sequences split
+//! between two feeds are still recognized.
+
+const MAX_PARAMS: usize = 16;
+const MAX_INTERMEDIATES: usize = 2;
+/// OSC/DCS payloads beyond this are dropped rather than buffered
+const MAX_STRING_LEN: usize = 64 * 1024;
+/// The same for inline images (sixel DCS `q`, iTerm2 `OSC 1337;File=`),
+/// which are far larger
+const MAX_IMAGE_LEN: usize = 8 * 1024 * 1024;
+/// Bytes of a dropped string kept to tell what it was
+const HEAD_LEN: usize = 16;
+
+const ESC: u8 = 0x1b;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const BEL: u8 = 0x07;
+const DEL: u8 = 0x7f;
+
+/// True for bytes the VT drops in ground state: NUL, DEL, and C0 controls
+/// with no effect (ENQ is answered, see `config`, so it isn't one).
+pub fn is_ignored(b: u8) -> bool {
+    matches!(b, 0x00..=0x04 | 0x06 | 0x10..=0x1a | 0x1c..=0x1f | DEL)
+}
+
+/// A complete CSI sequence: `ESC [ <marker> <params> <intermediates> <final>`.
+#[derive(Debug, Clone, Default)]
+pub struct Csi {
+    /// Private marker (`?`, `>`, `<`, `=`) if present
+    pub marker: Option<u8>,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    intermediates: [u8; MAX_INTERMEDIATES],
+    intermediate_count: usize,
+    pub final_byte: u8,
+}
+
+impl Csi {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.param_count]
+    }
+
+    /// Parameter `index`, or `default` when missing or zero.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&p) if p != 0 => p,
+            _ => default,
+        }
+    }
+
+    pub fn intermediates(&self) -> &[u8] {
+        &self.intermediates[..self.intermediate_count]
+    }
+
+    fn clear(&mut self) {
+        *self = Csi::default();
+    }
+}
+
+#[derive(Debug)]
+pub enum Action<'a> {
+    /// C0 control executed in ground state (BEL, LF, ENQ, ...)
+    Control(u8),
+    Csi(&'a Csi),
+    Esc {
+        intermediate: Option<u8>,
+        final_byte: u8,
+    },
+    /// OSC payload without the introducer and terminator
+    Osc(&'a [u8]),
+    /// DCS payload (everything after `ESC P` up to ST)
+    Dcs(&'a [u8]),
+    /// A string that isn't passed on: SOS, PM and APC strings
+    /// (introducer `X`, `^` and `_`), which nothing acts on, and OSC and
+    /// DCS payloads past `MAX_STRING_LEN`, or `MAX_IMAGE_LEN` for images.
+    /// `head` is the start of the payload, at least `HEAD_LEN` bytes of it
+    /// if there are that many, and `len` its length.
+    Dropped {
+        introducer: u8,
+        head: &'a [u8],
+        len: usize,
+    },
+}
+
+impl Action<'_> {
+    /// True for items that can move or show/hide the cursor but never
+    /// change cell contents: cursor motion, tabs, SGR, DECSC/DECRC, OSC,
+    /// dropped strings.
+    pub fn is_cursor_only(&self) -> bool {
+        match self {
+            Action::Control(b) => {
+                matches!(*b, BEL | 0x08 | 0x09 | 0x0d | 0x0e | 0x0f) || is_ignored(*b)
+            }
+            Action::Csi(csi) if csi.intermediates().is_empty() => match csi.marker {
+                None => matches!(
+                    csi.final_byte,
+                    b'A'..=b'I' | b'Z' | b'`' | b'a' | b'd' | b'e' | b'f' | b'm'
+                ),
+                Some(b'?') => {
+                    matches!(csi.final_byte, b'h' | b'l') && csi.params().iter().all(|&p| p == 25)
+                }
+                _ => false,
+            },
+            Action::Esc {
+                intermediate: None,
+                final_byte: b'7' | b'8',
+            } => true,
+            Action::Osc(_) | Action::Dropped { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    CsiIgnore,
+    Osc,
+    OscEscape,
+    Dcs,
+    DcsEscape,
+    /// SOS/PM/APC strings: skipped entirely
+    Ignore,
+    IgnoreEscape,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    state: State,
+    csi: Csi,
+    esc_intermediate: Option<u8>,
+    string: Vec<u8>,
+    string_overflow: bool,
+    /// The current string's introducer and length, buffered or not
+    introducer: u8,
+    string_len: usize,
+    printed: bool,
+    /// `is_ignored` bytes seen in ground state
+    ignored: usize,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        Scanner {
+            state: State::Ground,
+            csi: Csi::default(),
+            esc_intermediate: None,
+            string: Vec::new(),
+            string_overflow: false,
+            introducer: 0,
+            string_len: 0,
+            printed: false,
+            ignored: 0,
+        }
+    }
+
+    /// True when the scanner is between sequences.
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground
+    }
+
+    /// Whether a printable byte, or a CSI sequence too malformed to
+    /// report, was scanned since the last call.
+    pub fn take_printed(&mut self) -> bool {
+        std::mem::take(&mut self.printed)
+    }
+
+    /// Free the string buffer if no sequence is using it, returning the
+    /// bytes freed.
+    pub fn compact(&mut self) -> usize {
+        if !self.is_ground() {
+            return 0;
+        }
+        std::mem::take(&mut self.string).capacity()
+    }
+
+    /// Number of ignored bytes scanned in ground state since the last call.
+    pub fn take_ignored(&mut self) -> usize {
+        std::mem::take(&mut self.ignored)
+    }
+
+    /// Scan `bytes`, calling `f(end, action)` for each recognized item,
+    /// where `end` is the offset just past the item's last byte.
+    pub fn scan(&mut self, bytes: &[u8], mut f: impl FnMut(usize, Action)) {
+        for (i, &b) in bytes.iter().enumerate() {
+            self.step(b, i + 1, &mut f);
+        }
+    }
+
+    fn step(&mut self, b: u8, end: usize, f: &mut dyn FnMut(usize, Action)) {
+        // CAN and SUB abort any sequence
+        if (b == CAN || b == SUB) && self.state != State::Ground {
+            self.state = State::Ground;
+            return;
+        }
+
+        match self.state {
+            State::Ground => match b {
+                ESC => self.enter_escape(),
+                0x00..=0x1f => {
+                    self.ignored += is_ignored(b) as usize;
+                    f(end, Action::Control(b))
+                }
+                DEL => self.ignored += 1,
+                _ => self.printed = true,
+            },
+
+            State::Escape => match b {
+                ESC => self.enter_escape(),
+                b'[' => {
+                    self.csi.clear();
+                    self.state = State::Csi;
+                }
+                b']' => self.enter_string(State::Osc, b),
+                b'P' => self.enter_string(State::Dcs, b),
+                b'X' | b'^' | b'_' => self.enter_string(State::Ignore, b),
+                0x20..=0x2f => {
+                    self.esc_intermediate = Some(b);
+                    self.state = State::EscapeIntermediate;
+                }
+                0x30..=0x7e => {
+                    self.state = State::Ground;
+                    f(
+                        end,
+                        Action::Esc {
+                            intermediate: None,
+                            final_byte: b,
+                        },
+                    );
+                }
+                0x00..=0x1f => f(end, Action::Control(b)),
+                _ => self.state = State::Ground,
+            },
+
+            State::EscapeIntermediate => match b {
+                ESC => self.enter_escape(),
+                0x20..=0x2f => {}
+                0x30..=0x7e => {
+                    self.state = State::Ground;
+                    f(
+                        end,
+                        Action::Esc {
+                            intermediate: self.esc_intermediate,
+                            final_byte: b,
+                        },
+                    );
+                }
+                0x00..=0x1f => f(end, Action::Control(b)),
+                _ => self.state = State::Ground,
+            },
+
+            State::Csi => match b {
+                ESC => self.enter_escape(),
+                b'0'..=b'9' => {
+                    if self.csi.intermediate_count > 0 {
+                        self.state = State::CsiIgnore;
+                    } else {
+                        if self.csi.param_count == 0 {
+                            self.csi.param_count = 1;
+                        }
+                        let p = &mut self.csi.params[self.csi.param_count - 1];
+                        *p = p.saturating_mul(10).saturating_add((b - b'0') as u16);
+                    }
+                }
+                b';' | b':' => {
+                    if self.csi.param_count == 0 {
+                        self.csi.param_count = 1;
+                    }
+                    if self.csi.param_count < MAX_PARAMS {
+                        self.csi.param_count += 1;
+                    } else {
+                        self.state = State::CsiIgnore;
+                    }
+                }
+                b'<'..=b'?' => {
+                    if self.csi.param_count == 0 && self.csi.marker.is_none() {
+                        self.csi.marker = Some(b);
+                    } else {
+                        self.state = State::CsiIgnore;
+                    }
+                }
+                0x20..=0x2f => {
+                    if self.csi.intermediate_count < MAX_INTERMEDIATES {
+                        self.csi.intermediates[self.csi.intermediate_count] = b;
+                        self.csi.intermediate_count += 1;
+                    } else {
+                        self.state = State::CsiIgnore;
+                    }
+                }
+                0x40..=0x7e => {
+                    self.csi.final_byte = b;
+                    self.state = State::Ground;
+                    f(end, Action::Csi(&self.csi));
+                }
+                0x00..=0x1f => f(end, Action::Control(b)),
+                _ => self.state = State::CsiIgnore,
+            },
+
+            State::CsiIgnore => match b {
+                ESC => self.enter_escape(),
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    self.printed = true;
+                }
+                0x00..=0x1f => f(end, Action::Control(b)),
+                _ => {}
+            },
+
+            State::Osc => match b {
+                BEL => {
+                    self.state = State::Ground;
+                    self.end_string(end, f);
+                }
+                ESC => self.state = State::OscEscape,
+                _ => self.push_string(b),
+            },
+
+            State::OscEscape => {
+                if b == b'\\' {
+                    self.state = State::Ground;
+                    self.end_string(end, f);
+                } else {
+                    // Unterminated OSC: xterm drops it and starts a new sequence
+                    self.enter_escape();
+                    self.step(b, end, f);
+                }
+            }
+
+            State::Dcs => match b {
+                ESC => self.state = State::DcsEscape,
+                _ => self.push_string(b),
+            },
+
+            State::DcsEscape => {
+                if b == b'\\' {
+                    self.state = State::Ground;
+                    self.end_string(end, f);
+                } else {
+                    self.enter_escape();
+                    self.step(b, end, f);
+                }
+            }
+
+            State::Ignore => match b {
+                ESC => self.state = State::IgnoreEscape,
+                _ => self.push_head(b),
+            },
+
+            State::IgnoreEscape => match b {
+                b'\\' => {
+                    self.state = State::Ground;
+                    self.end_string(end, f);
+                }
+                ESC => self.push_head(ESC),
+                _ => {
+                    self.push_head(ESC);
+                    self.push_head(b);
+                    self.state = State::Ignore;
+                }
+            },
+        }
+    }
+
+    fn enter_escape(&mut self) {
+        self.esc_intermediate = None;
+        self.state = State::Escape;
+    }
+
+    fn enter_string(&mut self, state: State, introducer: u8) {
+        self.string.clear();
+        self.string_overflow = false;
+        self.introducer = introducer;
+        self.string_len = 0;
+        self.state = state;
+    }
+
+    fn push_string(&mut self, b: u8) {
+        self.string_len += 1;
+        let len = self.string.len();
+        if len < MAX_STRING_LEN || len < MAX_IMAGE_LEN && self.is_image() {
+            self.string.push(b);
+        } else {
+            self.string_overflow = true;
+        }
+    }
+
+    /// Whether the string buffered so far starts an inline image.
+    fn is_image(&self) -> bool {
+        match self.introducer {
+            b']' => self.string.starts_with(b"1337;File="),
+            b'P' => {
+                let params = self
+                    .string
+                    .iter()
+                    .take_while(|&&b| b.is_ascii_digit() || b == b';')
+                    .count();
+                self.string.get(params) == Some(&b'q')
+            }
+            _ => false,
+        }
+    }
+
+    /// Count `b` in a string that isn't buffered past its head.
+    fn push_head(&mut self, b: u8) {
+        self.string_len += 1;
+        if self.string.len() < HEAD_LEN {
+            self.string.push(b);
+        }
+    }
+
+    fn end_string(&self, end: usize, f: &mut dyn FnMut(usize, Action)) {
+        let action = match self.introducer {
+            b']' if !self.string_overflow => Action::Osc(&self.string),
+            b'P' if !self.string_overflow => Action::Dcs(&self.string),
+            introducer => Action::Dropped {
+                introducer,
+                head: &self.string,
+                len: self.string_len,
+            },
+        };
+        f(end, action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        Control(u8),
+        Csi(Option<u8>, Vec<u16>, u8),
+        Esc(Option<u8>, u8),
+        Osc(Vec<u8>),
+        Dcs(Vec<u8>),
+        Dropped(u8, Vec<u8>, usize),
+    }
+
+    fn scan_all(scanner: &mut Scanner, chunks: &[&[u8]]) -> Vec<Seen> {
+        let mut seen = Vec::new();
+        for chunk in chunks {
+            scanner.scan(chunk, |_, action| {
+                seen.push(match action {
+                    Action::Control(b) => Seen::Control(b),
+                    Action::Csi(csi) => Seen::Csi(csi.marker, csi.params().to_vec(), csi.final_byte),
+                    Action::Esc {
+                        intermediate,
+                        final_byte,
+                    } => Seen::Esc(intermediate, final_byte),
+                    Action::Osc(data) => Seen::Osc(data.to_vec()),
+                    Action::Dcs(data) => Seen::Dcs(data.to_vec()),
+                    Action::Dropped {
+                        introducer,
+                        head,
+                        len,
+                    } => Seen::Dropped(introducer, head.to_vec(), len),
+                })
+            });
+        }
+        seen
+    }
+
+    #[test]
+    fn bel_terminating_osc_is_not_a_bell() {
+        let seen = scan_all(&mut Scanner::new(), &[b"\x1b]0;title\x07ding\x07"]);
+        assert_eq!(seen, vec![Seen::Osc(b"0;title".to_vec()), Seen::Control(0x07)]);
+    }
+
+    #[test]
+    fn sequences_split_across_chunks() {
+        let seen = scan_all(&mut Scanner::new(), &[b"a\x1b[?20", b"04h\x1b]2;x\x1b", b"\\"]);
+        assert_eq!(
+            seen,
+            vec![
+                Seen::Csi(Some(b'?'), vec![2004], b'h'),
+                Seen::Osc(b"2;x".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn csi_params_and_defaults() {
+        let mut scanner = Scanner::new();
+        let mut params = Vec::new();
+        scanner.scan(b"\x1b[;5H\x1b[38;2;1;2;3m", |_, action| {
+            if let Action::Csi(csi) = action {
+                params.push((csi.param(0, 1), csi.params().to_vec()));
+            }
+        });
+        assert_eq!(params[0], (1, vec![0, 5]));
+        assert_eq!(params[1], (38, vec![38, 2, 1, 2, 3]));
+    }
+
+    #[test]
+    fn esc_with_intermediate_and_dcs() {
+        let seen = scan_all(&mut Scanner::new(), &[b"\x1b#8\x1bP+q544e\x1b\\"]);
+        assert_eq!(
+            seen,
+            vec![Seen::Esc(Some(b'#'), b'8'), Seen::Dcs(b"+q544e".to_vec())]
+        );
+    }
+
+    #[test]
+    fn apc_and_oversized_strings_are_dropped_with_their_length() {
+        let mut osc = b"\x1b]52;c;".to_vec();
+        osc.resize(osc.len() + MAX_STRING_LEN, b'A');
+        osc.push(BEL);
+        let seen = scan_all(
+            &mut Scanner::new(),
+            &[b"\x1b_Ga=T,f=24;", &[b'A'; 40], b"\x1b\\\x1b^\x1bx\x1b\\", &osc],
+        );
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0], Seen::Dropped(b'_', b"Ga=T,f=24;AAAAAA".to_vec(), 50));
+        assert_eq!(seen[1], Seen::Dropped(b'^', b"\x1bx".to_vec(), 2));
+        let Seen::Dropped(b']', head, len) = &seen[2] else {
+            panic!("{:?}", seen[2]);
+        };
+        assert!(head.starts_with(b"52;c;"));
+        assert_eq!(*len, osc.len() - 3);
+
+        // Images get more room
+        let mut image = b"\x1b]1337;File=".to_vec();
+        image.resize(image.len() + MAX_STRING_LEN, b'A');
+        image.push(BEL);
+        let seen = scan_all(&mut Scanner::new(), &[&image]);
+        assert!(matches!(&seen[..], [Seen::Osc(osc)] if osc.len() == image.len() - 3));
+    }
+
+    #[test]
+    fn reports_end_offsets() {
+        let mut ends = Vec::new();
+        Scanner::new().scan(b"ab\x07c\x1b[2J", |end, _| ends.push(end));
+        assert_eq!(ends, vec![3, 8]);
+    }
+
+    #[test]
+    fn classifies_cursor_only_items() {
+        let mut scanner = Scanner::new();
+        let mut cursor_only = Vec::new();
+        scanner.scan(
+            b"\x1b[5;1H\r\x1b[?25l\x1b[1m\x1b7\x1b[K\n\x1b[?1049h\x1b#8",
+            |_, action| cursor_only.push(action.is_cursor_only()),
+        );
+        assert_eq!(cursor_only, [true, true, true, true, true, false, false, false, false]);
+        assert!(!scanner.take_printed());
+
+        scanner.scan(b"\x1b[Ho", |_, _| {});
+        assert!(scanner.take_printed());
+        assert!(!scanner.take_printed());
+    }
+
+    #[test]
+    fn ignored_bytes_are_counted_and_change_nothing() {
+        let mut scanner = Scanner::new();
+        let mut cursor_only = Vec::new();
+        scanner.scan(b"\0\x11\x7f\x1b[\0m\x05", |_, action| {
+            cursor_only.push(action.is_cursor_only())
+        });
+        // The NUL inside the CSI still executes, but isn't counted
+        assert_eq!(cursor_only, [true, true, true, true, false]);
+        assert_eq!(scanner.take_ignored(), 3);
+        assert!(!scanner.take_printed());
+    }
+
+    #[test]
+    fn can_aborts_sequence() {
+        let seen = scan_all(&mut Scanner::new(), &[b"\x1b[12\x18\x07"]);
+        assert_eq!(seen, vec![Seen::Control(0x07)]);
+    }
+}
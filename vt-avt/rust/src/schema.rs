@@ -0,0 +1,323 @@
+//! The wire formats' constants, for generating the Kotlin side.
+//!
+//! Tags, flag bits and versions of the snapshot, diff, style table, event
+//! and saved state formats are defined in their modules, and the Kotlin
+//! decoders used to repeat them as literals: a tag renumbered or a version
+//! bumped on one side decoded as garbage on the other. `FORMATS` names each
+//! of them once, by the Rust constant, and `kotlin` writes them out as
+//! `AvtSchema.kt`, the only place the Kotlin decoders take them from.
+//! Flag bytes are decoded there too: each of a format's `flags` becomes a
+//! value class with a property per bit or bit field, which the hand-written
+//! decoders read instead of masking the constants themselves.
+//! `schema_tests` fails while the checked-in file differs from what
+//! `kotlin` writes; `BLESS=1 cargo test schema_tests` (or the Gradle task
+//! `generateAvtSchema`) regenerates it.
+//!
+//! A library built from other formats than the Kotlin it's loaded by
+//! fails too: `HASH` covers every constant, and `AvtNative` checks it
+//! against `vtSchemaHash` when it loads the library. The exception is a
+//! library whose `vtProtocolVersion` is newer than the Kotlin side's
+//! `Protocol.VERSION`: each VT then negotiates its snapshots and diffs
+//! down to what the Kotlin decodes (see `protocol`). At the same version
+//! the formats have to match.
+
+use crate::activity::State;
+use crate::{diff, events, modes, persist, protocol, snapshot, state, styles};
+use jni::objects::JClass;
+use jni::sys::jlong;
+use jni::JNIEnv;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+
+/// One format's constants, a nested object of `AvtSchema`.
+pub struct Format {
+    /// Kotlin object name
+    pub name: &'static str,
+    /// Rust module defining the format
+    pub module: &'static str,
+    pub ints: &'static [(&'static str, u32)],
+    pub texts: &'static [(&'static str, &'static str)],
+    pub flags: &'static [Flags],
+}
+
+/// A flags value's decoder, a value class of the format's object.
+pub struct Flags {
+    /// Kotlin class name
+    pub name: &'static str,
+    pub doc: &'static str,
+    /// Boolean properties, each set by the bit of the named constant
+    pub bits: &'static [(&'static str, &'static str)],
+    /// Int properties, each the bits of a mask constant above a shift
+    /// constant
+    pub fields: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// `NAME = value` pairs, each value a Rust constant.
+macro_rules! ints {
+    ($($name:ident = $value:expr),* $(,)?) => {
+        &[$((stringify!($name), $value as u32)),*]
+    };
+}
+
+pub const FORMATS: &[Format] = &[
+    Format {
+        name: "Snapshot",
+        module: "snapshot",
+        ints: ints!(
+            ATTR_BOLD = snapshot::ATTR_BOLD,
+            ATTR_ITALIC = snapshot::ATTR_ITALIC,
+            ATTR_UNDERLINE = snapshot::ATTR_UNDERLINE,
+            ATTR_STRIKETHROUGH = snapshot::ATTR_STRIKETHROUGH,
+            ATTR_BLINK = snapshot::ATTR_BLINK,
+            ATTR_INVERSE = snapshot::ATTR_INVERSE,
+            ATTR_PREDICTED = snapshot::ATTR_PREDICTED,
+            ATTR_FAINT = snapshot::ATTR_FAINT,
+            CURSOR_VISIBLE = snapshot::CURSOR_VISIBLE,
+            CURSOR_SHAPE_SHIFT = snapshot::CURSOR_SHAPE_SHIFT,
+            CURSOR_SHAPE_MASK = snapshot::CURSOR_SHAPE_MASK,
+            CURSOR_STEADY = snapshot::CURSOR_STEADY,
+            ALT_SCREEN = snapshot::ALT_SCREEN,
+            COLOR_INDEXED = snapshot::COLOR_INDEXED,
+            COLOR_RGB = snapshot::COLOR_RGB,
+            COLOR_DEFAULT = snapshot::COLOR_DEFAULT,
+            EXTENT_LINK = snapshot::EXTENT_LINK,
+        ),
+        texts: &[],
+        flags: &[
+            Flags {
+                name: "Attrs",
+                doc: "A style's `ATTR_*` bits",
+                bits: &[
+                    ("bold", "ATTR_BOLD"),
+                    ("italic", "ATTR_ITALIC"),
+                    ("underline", "ATTR_UNDERLINE"),
+                    ("strikethrough", "ATTR_STRIKETHROUGH"),
+                    ("blink", "ATTR_BLINK"),
+                    ("inverse", "ATTR_INVERSE"),
+                    ("predicted", "ATTR_PREDICTED"),
+                    ("faint", "ATTR_FAINT"),
+                ],
+                fields: &[],
+            },
+            Flags {
+                name: "CursorFlags",
+                doc: "The cursor's flags byte",
+                bits: &[
+                    ("visible", "CURSOR_VISIBLE"),
+                    ("steady", "CURSOR_STEADY"),
+                    ("altScreen", "ALT_SCREEN"),
+                ],
+                fields: &[("shape", "CURSOR_SHAPE_SHIFT", "CURSOR_SHAPE_MASK")],
+            },
+        ],
+    },
+    Format {
+        name: "Diff",
+        module: "diff",
+        ints: ints!(
+            KIND_NONE = diff::KIND_NONE,
+            KIND_LINES = diff::KIND_LINES,
+            KIND_CURSOR = diff::KIND_CURSOR,
+            KIND_TRACED = diff::KIND_TRACED,
+            KIND_CONTENT = diff::KIND_CONTENT,
+            KIND_SPANS = diff::KIND_SPANS,
+            KIND_INTERNED = diff::KIND_INTERNED,
+            KIND_DAMAGE = diff::KIND_DAMAGE,
+            KIND_VERSIONED = diff::KIND_VERSIONED,
+            CURSOR_MOVED = diff::CURSOR_MOVED,
+            CURSOR_RESTYLED = diff::CURSOR_RESTYLED,
+            RESIZED = diff::RESIZED,
+            SCREEN_SWITCHED = diff::SCREEN_SWITCHED,
+            MODES_CHANGED = diff::MODES_CHANGED,
+            IMAGES_CHANGED = diff::IMAGES_CHANGED,
+            IMAGE_ADDED = diff::IMAGE_ADDED,
+            IMAGE_MOVED = diff::IMAGE_MOVED,
+            IMAGE_REMOVED = diff::IMAGE_REMOVED,
+        ),
+        texts: &[],
+        flags: &[
+            Flags {
+                name: "CursorChange",
+                doc: "A diff's cursor byte",
+                bits: &[("moved", "CURSOR_MOVED"), ("restyled", "CURSOR_RESTYLED")],
+                fields: &[],
+            },
+            Flags {
+                name: "ScreenChange",
+                doc: "A diff's `resized` byte",
+                bits: &[
+                    ("resized", "RESIZED"),
+                    ("screenSwitched", "SCREEN_SWITCHED"),
+                    ("modesChanged", "MODES_CHANGED"),
+                    ("imagesChanged", "IMAGES_CHANGED"),
+                ],
+                fields: &[],
+            },
+        ],
+    },
+    Format {
+        name: "Styles",
+        module: "styles",
+        ints: ints!(
+            COLOR_DEFAULT = styles::COLOR_DEFAULT,
+            COLOR_ANSI = styles::COLOR_ANSI,
+            COLOR_INDEXED = styles::COLOR_INDEXED,
+            COLOR_RGB = styles::COLOR_RGB,
+        ),
+        texts: &[],
+        flags: &[],
+    },
+    Format {
+        name: "Events",
+        module: "events",
+        ints: ints!(
+            VERSION = events::VERSION,
+            VERSION_TIMED = events::VERSION_TIMED,
+            TAG_BELL = events::TAG_BELL,
+            TAG_TITLE = events::TAG_TITLE,
+            TAG_MARKER = events::TAG_MARKER,
+            TAG_RESIZE = events::TAG_RESIZE,
+            TAG_IMAGE = events::TAG_IMAGE,
+            TAG_CLIPBOARD = events::TAG_CLIPBOARD,
+            TAG_NOTIFICATION = events::TAG_NOTIFICATION,
+            TAG_WATCH = events::TAG_WATCH,
+            TAG_ACTIVITY = events::TAG_ACTIVITY,
+            TAG_INPUT = events::TAG_INPUT,
+            TAG_OPTION = events::TAG_OPTION,
+            TAG_IMAGE_ADDED = events::TAG_IMAGE_ADDED,
+            TAG_IMAGE_REMOVED = events::TAG_IMAGE_REMOVED,
+            IMAGE_SIXEL = events::IMAGE_SIXEL,
+            IMAGE_ITERM = events::IMAGE_ITERM,
+            ACTIVITY_ACTIVE = State::Active,
+            ACTIVITY_IDLE = State::Idle,
+            ACTIVITY_DISCONNECTED = State::Disconnected,
+            OPTION_THEME = events::OPTION_THEME,
+            OPTION_BOLD_AS_BRIGHT = events::OPTION_BOLD_AS_BRIGHT,
+            OPTION_CURSOR_POLICY = events::OPTION_CURSOR_POLICY,
+        ),
+        texts: &[],
+        flags: &[],
+    },
+    Format {
+        name: "State",
+        module: "state",
+        ints: ints!(DUMP_FORMAT = state::DUMP_FORMAT),
+        texts: &[("AVT_VERSION", state::AVT_VERSION)],
+        flags: &[],
+    },
+    Format {
+        name: "Persist",
+        module: "persist",
+        ints: ints!(VERSION = persist::VERSION),
+        texts: &[],
+        flags: &[],
+    },
+    Format {
+        name: "Modes",
+        module: "modes",
+        ints: ints!(
+            APP_CURSOR_KEYS = modes::APP_CURSOR_KEYS,
+            APP_KEYPAD = modes::APP_KEYPAD,
+            BRACKETED_PASTE = modes::BRACKETED_PASTE,
+            MOUSE_CLICKS = modes::MOUSE_CLICKS,
+            MOUSE_DRAG = modes::MOUSE_DRAG,
+            MOUSE_MOTION = modes::MOUSE_MOTION,
+            MOUSE_SGR = modes::MOUSE_SGR,
+        ),
+        texts: &[],
+        flags: &[],
+    },
+    Format {
+        name: "Protocol",
+        module: "protocol",
+        ints: ints!(
+            VERSION = protocol::VERSION,
+            SNAPSHOT_HEADER = protocol::SNAPSHOT_HEADER,
+            FEATURE_LINKS = protocol::FEATURE_LINKS,
+            FEATURE_IMAGES = protocol::FEATURE_IMAGES,
+            FEATURE_CURSOR_SHAPE = protocol::FEATURE_CURSOR_SHAPE,
+            FEATURES = protocol::FEATURES,
+        ),
+        texts: &[],
+        flags: &[],
+    },
+];
+
+/// 64 bits of SHA-256 over every constant's format, name and value.
+pub fn hash() -> i64 {
+    let mut hasher = Sha256::new();
+    for format in FORMATS {
+        for (name, value) in format.ints {
+            hasher.update(format!("{}.{}={}\n", format.name, name, value).as_bytes());
+        }
+        for (name, value) in format.texts {
+            hasher.update(format!("{}.{}={:?}\n", format.name, name, value).as_bytes());
+        }
+    }
+    i64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap())
+}
+
+/// `AvtSchema.kt`, the constants and flag decoders as a Kotlin object.
+pub fn kotlin() -> String {
+    let mut out = String::new();
+    out.push_str(
+        "// Generated from rust/src/schema.rs; don't edit. Regenerate with\n\
+         // `BLESS=1 cargo test schema_tests` or `./gradlew generateAvtSchema`.\n\n\
+         package uk.adedamola.asciicast.vt.avt\n\n\
+         /**\n \
+         * Tags, flag bits and versions of the native wire formats, each\n \
+         * documented in the Rust module named on its object, and decoders\n \
+         * of their flags.\n \
+         */\n\
+         object AvtSchema {\n",
+    );
+    let _ = writeln!(
+        out,
+        "    /** Of every constant below; the library's is `AvtNative.vtSchemaHash` */"
+    );
+    let _ = writeln!(out, "    const val HASH = {}L", hash());
+    for format in FORMATS {
+        let _ = writeln!(out, "\n    /** rust/src/{}.rs */", format.module);
+        let _ = writeln!(out, "    object {} {{", format.name);
+        for (name, value) in format.ints {
+            let _ = writeln!(out, "        const val {} = {}", name, value);
+        }
+        for (name, value) in format.texts {
+            let _ = writeln!(out, "        const val {} = {:?}", name, value);
+        }
+        for flags in format.flags {
+            let _ = writeln!(out, "\n        /** {} */", flags.doc);
+            let _ = writeln!(out, "        @JvmInline");
+            let _ = writeln!(out, "        value class {}(val bits: Int) {{", flags.name);
+            for (property, bit) in flags.bits {
+                let _ = writeln!(
+                    out,
+                    "            val {}: Boolean get() = bits and {} != 0",
+                    property, bit
+                );
+            }
+            for (property, shift, mask) in flags.fields {
+                let _ = writeln!(
+                    out,
+                    "            val {}: Int get() = (bits shr {}) and {}",
+                    property, shift, mask
+                );
+            }
+            out.push_str("        }\n");
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+// JNI functions
+
+/// `hash` of the formats this library was built with.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSchemaHash(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jlong {
+    jni_guard!(env, { hash() })
+}
@@ -0,0 +1,92 @@
+//! `AvtSchema.kt` against the constants it's generated from.
+//!
+//! The Kotlin decoders take every tag, flag bit and version from
+//! `AvtSchema.kt`, so a format constant changed in Rust has to reach that
+//! file, or the app decodes the old layout. This fails until it has; after
+//! a deliberate change, `BLESS=1 cargo test schema_tests` regenerates the
+//! file (see `schema`).
+
+use crate::schema::{self, FORMATS};
+use std::collections::HashSet;
+use std::fs;
+
+const KOTLIN: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../android/src/main/kotlin/uk/adedamola/asciicast/vt/avt/AvtSchema.kt"
+);
+
+#[test]
+fn kotlin_schema_is_generated_from_the_formats() {
+    let generated = schema::kotlin();
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(KOTLIN, &generated).unwrap();
+        return;
+    }
+    let checked_in = fs::read_to_string(KOTLIN).unwrap_or_default();
+    if let Some((line, (want, got))) = generated
+        .lines()
+        .zip(checked_in.lines())
+        .enumerate()
+        .find(|(_, (want, got))| want != got)
+    {
+        panic!(
+            "AvtSchema.kt line {} is {:?}, the formats give {:?}; regenerate it with BLESS=1",
+            line + 1,
+            got,
+            want
+        );
+    }
+    assert_eq!(
+        checked_in.lines().count(),
+        generated.lines().count(),
+        "AvtSchema.kt is out of date; regenerate it with BLESS=1"
+    );
+}
+
+#[test]
+fn constants_are_named_once_per_format() {
+    for format in FORMATS {
+        let mut names = HashSet::new();
+        for name in format
+            .ints
+            .iter()
+            .map(|(name, _)| name)
+            .chain(format.texts.iter().map(|(name, _)| name))
+        {
+            assert!(names.insert(name), "{}.{} twice", format.name, name);
+        }
+    }
+    // Tags within a tag set are distinct
+    let events = FORMATS.iter().find(|f| f.name == "Events").unwrap();
+    let tags: Vec<_> = events
+        .ints
+        .iter()
+        .filter(|(name, _)| name.starts_with("TAG_"))
+        .map(|(_, v)| v)
+        .collect();
+    assert_eq!(tags.iter().collect::<HashSet<_>>().len(), tags.len());
+}
+
+#[test]
+fn decoders_read_their_formats_constants() {
+    for format in FORMATS {
+        let names: HashSet<_> = format.ints.iter().map(|(name, _)| *name).collect();
+        for flags in format.flags {
+            let constants = flags.bits.iter().map(|(_, bit)| bit).chain(
+                flags
+                    .fields
+                    .iter()
+                    .flat_map(|(_, shift, mask)| [shift, mask]),
+            );
+            for constant in constants {
+                assert!(
+                    names.contains(constant),
+                    "{}.{} reads {}, which isn't one of its constants",
+                    format.name,
+                    flags.name,
+                    constant
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Scratch files for exports that spool to disk.
+//!
+//! An Android app can't write to its working directory, so scratch files
+//! go in a directory the app hands over with `vtSetScratchDir`, normally
+//! under `Context.getCacheDir()`, and until it does creating one fails.
+//! Only files named `<pid>-<n>.tmp` are ours, so the directory can be
+//! shared with other caches.
+//!
+//! A scratch file is removed when dropped. One left behind by a process
+//! that crashed is removed by `cleanup_orphaned`, which the app calls at
+//! startup (`cleanupOrphanedTemp`); files of processes still running are
+//! kept, which `/proc` tells on Linux and Android.
+
+use jni::objects::{JClass, JString};
+use jni::sys::{jboolean, jint, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+static DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Put scratch files in `dir` from now on, creating it if need be.
+pub fn set_dir(dir: impl Into<PathBuf>) -> io::Result<()> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    *DIR.lock().unwrap_or_else(PoisonError::into_inner) = Some(dir);
+    Ok(())
+}
+
+/// The directory set with `set_dir`, if any.
+pub fn dir() -> Option<PathBuf> {
+    DIR.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+fn no_dir() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "no scratch directory set")
+}
+
+/// A file in the scratch directory, removed when dropped.
+#[derive(Debug)]
+pub struct ScratchFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl ScratchFile {
+    /// A new, empty file in the directory set with `set_dir`.
+    pub fn create() -> io::Result<ScratchFile> {
+        ScratchFile::create_in(&dir().ok_or_else(no_dir)?)
+    }
+
+    /// A new, empty file in `dir`.
+    pub fn create_in(dir: &Path) -> io::Result<ScratchFile> {
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("{}-{}.tmp", std::process::id(), n));
+            let mut options = OpenOptions::new();
+            match options.read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(ScratchFile { file, path }),
+                // Left by an earlier process with our pid
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Remove the scratch files of processes that are no longer running from
+/// the directory set with `set_dir`, returning how many.
+pub fn cleanup_orphaned() -> io::Result<usize> {
+    cleanup_orphaned_in(&dir().ok_or_else(no_dir)?)
+}
+
+/// `cleanup_orphaned` for `dir`.
+pub fn cleanup_orphaned_in(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(pid) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(owner)
+        else {
+            continue;
+        };
+        if pid != std::process::id() && !is_running(pid) && fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// The pid in a scratch file's name, `None` for other files.
+fn owner(name: &str) -> Option<u32> {
+    let (pid, n) = name.strip_suffix(".tmp")?.split_once('-')?;
+    n.parse::<u64>().ok()?;
+    pid.parse().ok()
+}
+
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// JNI functions
+
+/// Returns false if `path` can't be read or created.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetScratchDir(
+    mut env: JNIEnv,
+    _class: JClass,
+    path: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let path: String = match env.get_string(&path) {
+            Ok(s) => s.into(),
+            Err(_) => return JNI_FALSE,
+        };
+
+        if set_dir(path).is_ok() {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Files removed by `cleanup_orphaned`, or -1 without a scratch directory
+/// or if it can't be read.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_cleanupOrphanedTemp(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    jni_guard!(env, {
+        match cleanup_orphaned() {
+            Ok(removed) => removed.min(jint::MAX as usize) as jint,
+            Err(_) => -1,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn scratch_files_go_away_unless_their_process_runs() {
+        let dir = std::env::temp_dir().join(format!("scratch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut scratch = ScratchFile::create_in(&dir).unwrap();
+        scratch.file().write_all(b"frame").unwrap();
+        let path = scratch.path().to_owned();
+        assert_eq!(fs::read(&path).unwrap(), b"frame");
+        drop(scratch);
+        assert!(!path.exists());
+
+        // No such pid; and files that aren't ours
+        let orphan = dir.join(format!("{}-0.tmp", u32::MAX));
+        let others = [dir.join("thumbnails.db"), dir.join("1-x.tmp")];
+        for path in others.iter().chain([&orphan]) {
+            fs::write(path, b"").unwrap();
+        }
+        let live = ScratchFile::create_in(&dir).unwrap();
+        assert_eq!(cleanup_orphaned_in(&dir).unwrap(), 1);
+        assert!(!orphan.exists() && live.path().exists());
+        assert!(others.iter().all(|path| path.exists()));
+
+        drop(live);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
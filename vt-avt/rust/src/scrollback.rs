@@ -0,0 +1,182 @@
+//! Paged access to the lines scrolled off the top of the screen.
+//!
+//! A snapshot covers only the visible grid, and scrollback can run to
+//! thousands of lines, so the app fetches it a page at a time as the user
+//! scrolls up. Lines are numbered from the oldest kept; as output scrolls
+//! more off the top, and the retention limit drops the oldest, the numbers
+//! shift, so each page carries the total to re-anchor against.
+//!
+//! Layout, with `line` as in the snapshot format:
+//!
+//! ```text
+//! page := total start line_count line*line_count
+//! ```
+//!
+//! Scrolled-off lines are always `single`: line attributes are tracked for
+//! the visible rows only.
+
+use crate::backend::{Retention, TerminalBackend};
+use crate::lineattr::LineAttr;
+use crate::snapshot::{DecodeError, Line, Reader};
+use crate::{handles, write_varint, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Page {
+    /// Scrollback lines kept when the page was taken
+    pub total: usize,
+    /// Index of the first line in `lines`
+    pub start: usize,
+    pub lines: Vec<Line>,
+}
+
+impl Page {
+    /// Up to `count` lines from `start`, fewer near the end.
+    pub fn capture(backend: &impl TerminalBackend, start: usize, count: usize) -> Page {
+        let total = backend.scrollback_len();
+        let start = start.min(total);
+        let end = start.saturating_add(count).min(total);
+        let mut cells = Vec::new();
+        let lines = (start..end)
+            .map(|index| {
+                backend.scrollback_cells(index, &mut cells);
+                Line::of_cells(LineAttr::Single, &cells)
+            })
+            .collect();
+
+        Page {
+            total,
+            start,
+            lines,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, self.total);
+        write_varint(&mut buf, self.start);
+        write_varint(&mut buf, self.lines.len());
+        for line in &self.lines {
+            line.encode(&mut buf);
+        }
+        buf
+    }
+}
+
+/// Decode a page produced by `vtScrollbackLines`, under the same rules as
+/// `snapshot::decode`.
+pub fn decode(bytes: &[u8]) -> Result<Page, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let total = r.varint()?;
+    let start = r.varint()?;
+    let count = r.varint()?;
+
+    let mut lines = Vec::with_capacity(count.min(bytes.len() / 2));
+    for _ in 0..count {
+        lines.push(r.line()?);
+    }
+
+    Ok(Page {
+        total,
+        start,
+        lines,
+    })
+}
+
+// JNI functions
+
+/// Keep at most `max_lines` lines and about `max_bytes` bytes of
+/// scrollback; negative means no limit. A new limit drops the scrollback
+/// kept so far, so set it before feeding.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetScrollbackRetention(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    max_lines: jint,
+    max_bytes: jlong,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        vt.set_retention(Retention {
+            max_lines: usize::try_from(max_lines).ok(),
+            max_bytes: usize::try_from(max_bytes).ok(),
+        });
+    })
+}
+
+/// Scrollback lines kept, 0 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtScrollbackLen(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jint {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return 0;
+        };
+
+        vt.backend().scrollback_len().min(jint::MAX as usize) as jint
+    })
+}
+
+/// Up to `count` scrollback lines from `start` (0 = oldest), encoded as a
+/// `Page`. Empty for an invalid handle or range.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtScrollbackLines<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    start: jint,
+    count: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        if start < 0 || count < 0 {
+            return JByteArray::default();
+        }
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let page = Page::capture(vt.backend(), start as usize, count as usize);
+        env.byte_array_from_slice(&page.encode())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn pages_round_trip_and_clamp_to_what_is_kept() {
+        let mut backend = fake(4, 2);
+        backend.scrollback = ["one ", "two ", "six "].map(String::from).to_vec();
+        let mut state = AvtState::with_backend(backend);
+
+        let page = decode(&Page::capture(state.backend(), 1, 5).encode()).unwrap();
+        assert_eq!((page.total, page.start), (3, 1));
+        let texts: Vec<_> = page
+            .lines
+            .iter()
+            .map(|line| line.runs[0].text.as_str())
+            .collect();
+        assert_eq!(texts, ["two ", "six "]);
+
+        state.set_retention(Retention {
+            max_lines: Some(1),
+            max_bytes: None,
+        });
+        let page = Page::capture(state.backend(), 5, 1);
+        assert_eq!((page.total, page.start, page.lines.len()), (1, 1, 0));
+        assert_eq!(decode(&[1, 0]), Err(DecodeError::Truncated { offset: 2 }));
+    }
+}
@@ -0,0 +1,361 @@
+//! Find in terminal: matches of a query across the scrollback and screen.
+//!
+//! A search field would otherwise pull the whole history over JNI on every
+//! keystroke and search it in Kotlin. `vtSearch` scans it natively and
+//! returns only where the query matched. Rows count through the history
+//! as in `selection`: the scrollback from its oldest line, then the visible
+//! rows, so a match can be handed straight to `vtExtractText`.
+//!
+//! The query is literal text, or with `FLAG_REGEX` a pattern in the
+//! `watch` subset. Either is matched within one row, its trailing blanks
+//! trimmed, leftmost first and without overlaps. `FLAG_IGNORE_CASE` matches
+//! letters in either case; `FLAG_WHOLE_WORD` keeps only matches with no
+//! letter, digit or `_` right before or after them.
+//!
+//! `castSearch` searches a whole recording instead, for a "jump to" list:
+//! it replays the cast on a headless VT with the query as a watch (see
+//! `watch`), and each watch event is a hit at the playback time its text
+//! appeared. Text that was already on screen, say scrolled up a row, isn't
+//! a new hit.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::cast::Cast;
+use crate::events::VtEvent;
+use crate::watch::{Pattern, PatternError};
+use crate::{handles, player, write_varint, write_varint_u64, AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+pub const FLAG_IGNORE_CASE: i32 = 0x01;
+pub const FLAG_WHOLE_WORD: i32 = 0x02;
+pub const FLAG_REGEX: i32 = 0x04;
+
+const ALL_FLAGS: i32 = FLAG_IGNORE_CASE | FLAG_WHOLE_WORD | FLAG_REGEX;
+
+/// Matches returned by one search; the rest are left out
+pub const MAX_MATCHES: usize = 10_000;
+
+/// Hits returned by one `search_cast`; the replay stops there
+pub const MAX_CAST_HITS: usize = 1_000;
+
+/// Columns `col_start..col_end` of history row `row`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub row: usize,
+    pub col_start: usize,
+    pub col_end: usize,
+}
+
+/// A parsed query and how to match it.
+#[derive(Debug, Clone)]
+pub struct Query {
+    pattern: Pattern,
+    whole_word: bool,
+}
+
+impl Query {
+    /// `query` under the `FLAG_*` bits of `flags`.
+    pub fn new(query: &str, flags: i32) -> Result<Query, PatternError> {
+        let pattern = if flags & FLAG_REGEX != 0 {
+            Pattern::parse(query)?
+        } else {
+            Pattern::literal(query)
+        };
+        let pattern = if flags & FLAG_IGNORE_CASE != 0 {
+            pattern.ignoring_case()
+        } else {
+            pattern
+        };
+        Ok(Query {
+            pattern,
+            whole_word: flags & FLAG_WHOLE_WORD != 0,
+        })
+    }
+
+    /// Matches in `text`, as char indices.
+    fn find_all(&self, text: &[char], out: &mut Vec<(usize, usize)>) {
+        let mut from = 0;
+        while let Some(range) = self.pattern.find_at(text, from) {
+            if self.whole_word && !is_word_bounded(text, range.start, range.end) {
+                from = range.start + 1;
+                continue;
+            }
+            from = range.end;
+            out.push((range.start, range.end));
+        }
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_word_bounded(text: &[char], start: usize, end: usize) -> bool {
+    let before = start.checked_sub(1).map(|i| text[i]);
+    !before.is_some_and(is_word_char) && !text.get(end).copied().is_some_and(is_word_char)
+}
+
+/// The first `MAX_MATCHES` matches of `query` in `backend`'s history.
+pub fn search(backend: &impl TerminalBackend, query: &Query) -> Vec<Match> {
+    let scrollback = backend.scrollback_len();
+    let mut cells = Vec::new();
+    let mut chars = Vec::new();
+    let mut cols = Vec::new();
+    let mut found = Vec::new();
+    let mut matches = Vec::new();
+
+    for row in 0..scrollback + backend.size().1 {
+        if row < scrollback {
+            backend.scrollback_cells(row, &mut cells);
+        } else {
+            backend.row_cells(row - scrollback, &mut cells);
+        }
+        row_chars(&cells, &mut chars, &mut cols);
+
+        found.clear();
+        query.find_all(&chars, &mut found);
+        for &(start, end) in &found {
+            if matches.len() == MAX_MATCHES {
+                return matches;
+            }
+            let last = &cells[cols[end - 1]];
+            matches.push(Match {
+                row,
+                col_start: cols[start],
+                col_end: cols[end - 1] + last.width.max(1) as usize,
+            });
+        }
+    }
+    matches
+}
+
+/// The chars of a row, trailing blanks trimmed, and the column of each.
+fn row_chars(cells: &[Cell], chars: &mut Vec<char>, cols: &mut Vec<usize>) {
+    chars.clear();
+    cols.clear();
+    for (col, cell) in cells.iter().enumerate() {
+        if cell.width != 0 {
+            chars.push(cell.ch);
+            cols.push(col);
+        }
+    }
+    while chars.last() == Some(&' ') {
+        chars.pop();
+        cols.pop();
+    }
+}
+
+/// Where and when a query appeared in a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CastHit {
+    /// Playback time, for `vtSeek`
+    pub time_us: i64,
+    /// Screen row and char index in the row, as in `VtEvent::Watch`
+    pub row: usize,
+    pub col: usize,
+    /// Text of the row at that time, trailing blanks trimmed
+    pub line: String,
+}
+
+/// The first `MAX_CAST_HITS` times `query`, as literal text, appeared on
+/// screen while replaying `cast` on `vt`, in order. Leaves `vt` where the
+/// replay stopped.
+pub fn search_cast<B: TerminalBackend>(
+    vt: &mut AvtState<B>,
+    cast: &Cast,
+    query: &str,
+) -> Vec<CastHit> {
+    let schedule = player::schedule(cast, player::idle_limit(&cast.header));
+    vt.reset(cast.header.cols, cast.header.rows);
+    vt.watch(0, Pattern::literal(query));
+
+    let mut hits = Vec::new();
+    for (event, &at) in cast.events.iter().zip(&schedule) {
+        vt.set_event_time(Some(at.max(0) as u64));
+        player::apply(vt, &event.kind, true);
+        for (time_us, event) in vt.take_timed_events() {
+            let VtEvent::Watch { row, col, .. } = event else {
+                continue;
+            };
+            let line = vt.backend().row_text(row);
+            hits.push(CastHit {
+                time_us: time_us as i64,
+                row,
+                col,
+                line: line.trim_end().to_string(),
+            });
+        }
+        if hits.len() >= MAX_CAST_HITS {
+            hits.truncate(MAX_CAST_HITS);
+            break;
+        }
+    }
+
+    vt.unwatch(0);
+    vt.set_event_time(None);
+    hits
+}
+
+/// `hit_count (time_delta_us row col line_len line)*`: varints, each time
+/// in microseconds since the previous hit's (the first's since 0), and the
+/// line as UTF-8.
+pub fn encode_cast_hits(hits: &[CastHit]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, hits.len());
+    let mut prev = 0;
+    for hit in hits {
+        write_varint_u64(&mut buf, (hit.time_us - prev).max(0) as u64);
+        prev = hit.time_us;
+        write_varint(&mut buf, hit.row);
+        write_varint(&mut buf, hit.col);
+        write_varint(&mut buf, hit.line.len());
+        buf.extend_from_slice(hit.line.as_bytes());
+    }
+    buf
+}
+
+/// `match_count (row col_start col_end)*`, all varints.
+pub fn encode(matches: &[Match]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, matches.len());
+    for m in matches {
+        write_varint(&mut buf, m.row);
+        write_varint(&mut buf, m.col_start);
+        write_varint(&mut buf, m.col_end);
+    }
+    buf
+}
+
+// JNI functions
+
+/// Matches of `query` under the `FLAG_*` bits of `flags` in the history,
+/// see `search`, as `encode`. Empty for an invalid handle or flags, or a
+/// pattern outside the supported subset.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSearch<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    query: JString<'a>,
+    flags: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        if flags & !ALL_FLAGS != 0 {
+            return JByteArray::default();
+        }
+        let query: String = match env.get_string(&query) {
+            Ok(s) => s.into(),
+            Err(_) => return JByteArray::default(),
+        };
+        let Ok(query) = Query::new(&query, flags) else {
+            return JByteArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let matches = search(vt.backend(), &query);
+        env.byte_array_from_slice(&encode(&matches))
+            .unwrap_or_default()
+    })
+}
+
+/// When and where `query` appeared in the cast `handle`, see `search_cast`,
+/// as `encode_cast_hits`. Empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSearch<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    query: JString<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let query: String = match env.get_string(&query) {
+            Ok(s) => s.into(),
+            Err(_) => return JByteArray::default(),
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        let hits = search_cast(&mut vt, cast, &query);
+        env.byte_array_from_slice(&encode_cast_hits(&hits))
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    fn find(query: &str, flags: i32) -> Vec<(usize, usize, usize)> {
+        let mut backend = fake(12, 2);
+        backend.scrollback = ["Error: a.b  ", "errors a*b  "].map(String::from).to_vec();
+        backend.feed_str("中error 12");
+        search(&backend, &Query::new(query, flags).unwrap())
+            .iter()
+            .map(|m| (m.row, m.col_start, m.col_end))
+            .collect()
+    }
+
+    #[test]
+    fn literal_matches_across_scrollback_and_screen() {
+        assert_eq!(find("error", 0), [(1, 0, 5), (2, 2, 7)]);
+        assert_eq!(
+            find("ERROR", FLAG_IGNORE_CASE),
+            [(0, 0, 5), (1, 0, 5), (2, 2, 7)]
+        );
+        assert_eq!(
+            find("error", FLAG_IGNORE_CASE | FLAG_WHOLE_WORD),
+            [(0, 0, 5)]
+        );
+        // Special characters are literal, and a wide char spans two columns
+        assert_eq!(find("a*b", 0), [(1, 7, 10)]);
+        assert_eq!(find("中e", 0), [(2, 0, 3)]);
+        assert!(find("", 0).is_empty());
+    }
+
+    #[test]
+    fn regex_uses_the_watch_subset() {
+        assert_eq!(find("a.b", FLAG_REGEX), [(0, 7, 10), (1, 7, 10)]);
+        assert_eq!(find("\\d+$", FLAG_REGEX), [(2, 8, 10)]);
+        assert_eq!(
+            find("^E[a-z]+", FLAG_REGEX | FLAG_IGNORE_CASE),
+            [(0, 0, 5), (1, 0, 6)]
+        );
+        assert!(Query::new("(a)", FLAG_REGEX).is_err());
+
+        let encoded = encode(&[Match {
+            row: 300,
+            col_start: 1,
+            col_end: 2,
+        }]);
+        assert_eq!(encoded, [1, 0xac, 0x02, 1, 2]);
+    }
+
+    #[test]
+    fn cast_hits_are_timed_when_the_text_appears() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 20, \"height\": 2, \"idle_time_limit\": 2}\n\
+            [0.5, \"o\", \"$ t\"]\n\
+            [1.0, \"o\", \" FAIL\"]\n\
+            [9.0, \"o\", \"\\u001b[K\"]\n\
+            [9.5, \"o\", \" FAIL\"]\n",
+        )
+        .unwrap();
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        let hits = search_cast(&mut vt, &cast, "FAIL");
+
+        let found: Vec<_> = hits.iter().map(|h| (h.time_us, h.row, h.col)).collect();
+        // The idle time limit caps the gap before the second
+        assert_eq!(found, [(1_000_000, 0, 4), (3_500_000, 0, 9)]);
+        assert_eq!(hits[1].line, "$ t FAIL FAIL");
+        assert!(search_cast(&mut vt, &cast, "PASS").is_empty());
+
+        let encoded = encode_cast_hits(&hits[..1]);
+        assert_eq!(encoded[..5], [1, 0xc0, 0x84, 0x3d, 0]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Plain text of a selection, for copy to clipboard.
+//!
+//! Rows count through the whole history as `vtExportScrollback` writes
+//! it: the scrollback from its oldest line, then the visible rows, so
+//! screen row `r` is `scrollback_len + r`. A selection runs between two
+//! cells, both included, in one of two modes:
+//!
+//! - `MODE_LINEAR`: the text between them in reading order, from the first
+//!   cell to the end of its row, whole rows after, and the last row up to
+//!   the last cell
+//! - `MODE_BLOCK`: the rectangle with them at opposite corners
+//!
+//! A wide char is taken whole when either of its columns is selected.
+//! Trailing whitespace is trimmed from each row, and rows are joined with
+//! `\n` (soft wraps included: cells don't record them).
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::{handles, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jint;
+use jni::JNIEnv;
+
+pub const MODE_LINEAR: i32 = 0;
+pub const MODE_BLOCK: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Linear,
+    Block,
+}
+
+impl Mode {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            MODE_LINEAR => Some(Mode::Linear),
+            MODE_BLOCK => Some(Mode::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Text of the selection from cell `start` to cell `end`, each a history
+/// (row, col), in either order. Rows past the history are left out.
+pub fn extract_text(
+    backend: &impl TerminalBackend,
+    start: (usize, usize),
+    end: (usize, usize),
+    mode: Mode,
+) -> String {
+    let scrollback = backend.scrollback_len();
+    let total = scrollback + backend.size().1;
+    let (first, last) = if start <= end {
+        (start, end)
+    } else {
+        (end, start)
+    };
+    let (left, right) = (start.1.min(end.1), start.1.max(end.1));
+
+    let mut cells = Vec::new();
+    let mut text = String::new();
+    for row in first.0..=last.0.min(total.saturating_sub(1)) {
+        if row < scrollback {
+            backend.scrollback_cells(row, &mut cells);
+        } else {
+            backend.row_cells(row - scrollback, &mut cells);
+        }
+        let (from, to) = match mode {
+            Mode::Block => (left, right),
+            Mode::Linear if row == first.0 && row == last.0 => (first.1, last.1),
+            Mode::Linear if row == first.0 => (first.1, usize::MAX),
+            Mode::Linear if row == last.0 => (0, last.1),
+            Mode::Linear => (0, usize::MAX),
+        };
+        if row > first.0 {
+            text.push('\n');
+        }
+        push_cells(&cells, from, to, &mut text);
+    }
+    text
+}
+
+/// Append the chars of columns `from..=to` of `cells`, trimmed.
+fn push_cells(cells: &[Cell], mut from: usize, to: usize, out: &mut String) {
+    // From the right half of a wide char back to its left
+    while from > 0 && cells.get(from).is_some_and(|cell| cell.width == 0) {
+        from -= 1;
+    }
+    let line_start = out.len();
+    for cell in cells.iter().take(to.saturating_add(1)).skip(from) {
+        if cell.width != 0 {
+            out.push(cell.ch);
+        }
+    }
+    let trimmed = out[line_start..].trim_end().len();
+    out.truncate(line_start + trimmed);
+}
+
+// JNI functions
+
+/// UTF-8 text of the selection from `start_row`, `start_col` to `end_row`,
+/// `end_col` in `mode` (a `MODE_*` code), see `extract_text`. Empty for an
+/// invalid handle, mode or negative coordinate.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtExtractText<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    start_row: jint,
+    start_col: jint,
+    end_row: jint,
+    end_col: jint,
+    mode: jint,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(mode) = Mode::from_code(mode) else {
+            return JByteArray::default();
+        };
+        let coords = [start_row, start_col, end_row, end_col].map(usize::try_from);
+        let [Ok(start_row), Ok(start_col), Ok(end_row), Ok(end_col)] = coords else {
+            return JByteArray::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let text = extract_text(
+            vt.backend(),
+            (start_row, start_col),
+            (end_row, end_col),
+            mode,
+        );
+        env.byte_array_from_slice(text.as_bytes())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    fn history() -> crate::backend::tests::FakeBackend {
+        let mut backend = fake(6, 2);
+        backend.scrollback = ["one   ", "two 2 "].map(String::from).to_vec();
+        backend.feed_str("中文ab");
+        backend
+    }
+
+    #[test]
+    fn linear_runs_in_reading_order() {
+        let backend = history();
+        let text = |start, end| extract_text(&backend, start, end, Mode::Linear);
+
+        assert_eq!(text((0, 1), (2, 2)), "ne\ntwo 2\n中文");
+        assert_eq!(text((2, 2), (0, 1)), "ne\ntwo 2\n中文");
+        // Either half of a wide char takes it whole
+        assert_eq!(text((2, 1), (2, 2)), "中文");
+        assert_eq!(text((2, 3), (9, 9)), "文ab\n");
+        assert_eq!(text((1, 3), (1, 3)), "");
+    }
+
+    #[test]
+    fn block_takes_the_same_columns_of_every_row() {
+        let backend = history();
+        assert_eq!(
+            extract_text(&backend, (0, 2), (2, 4), Mode::Block),
+            "e\no 2\n文a"
+        );
+        assert_eq!(
+            extract_text(&backend, (2, 0), (0, 0), Mode::Block),
+            "o\nt\n中"
+        );
+        assert_eq!(Mode::from_code(2), None);
+    }
+}
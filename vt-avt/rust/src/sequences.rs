@@ -0,0 +1,521 @@
+//! Escape sequence statistics of a cast, for diagnostics.
+//!
+//! When a recording plays back wrong, the first question is what it
+//! uses. Counting every sequence in its output answers that without
+//! playing it: how often each CSI final byte, OSC command and DCS final
+//! byte appears, how many sequences nothing here acts on, and how much of
+//! the output is inline images. The app turns those into hints such as
+//! "this cast uses kitty graphics, which the player doesn't show".
+//!
+//! Sequences count as unknown when neither avt nor the wrapper does
+//! anything with them; see `is_known`.
+//!
+//! A VT also logs the unknown sequences it's fed as they come (see
+//! `UnknownLog`): each kind once, with a sample, how often it came and
+//! when first. Collected from users' recordings, those say which gaps
+//! are worth filling first.
+
+use crate::cast::{Cast, EventKind};
+use crate::events::{IMAGE_ITERM, IMAGE_SIXEL};
+use crate::scan::{Action, Csi, Scanner};
+use crate::{handles, write_varint, write_varint_u64, VtHandle};
+use jni::objects::{JByteArray, JClass, JLongArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::collections::BTreeMap;
+
+/// Kinds of unknown sequence an `UnknownLog` keeps
+pub const MAX_LOGGED: usize = 32;
+/// Bytes kept of each logged sample
+const MAX_SAMPLE: usize = 48;
+
+/// As `Key::kind`, by code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Csi = 0,
+    Osc = 1,
+    Dcs = 2,
+}
+
+/// What sequences are counted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub kind: Kind,
+    /// CSI private marker, or DCS or CSI intermediate (the last), or 0
+    pub prefix: u8,
+    /// CSI and DCS final byte, OSC command number (-1 for none)
+    pub id: i32,
+}
+
+impl Key {
+    /// `[kind, prefix, id]` packed as `kind << 40 | prefix << 32 | id`,
+    /// the id as its 32 bits.
+    pub fn code(&self) -> i64 {
+        (self.kind as i64) << 40 | (self.prefix as i64) << 32 | self.id as u32 as i64
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    pub counts: BTreeMap<Key, u64>,
+    /// Sequences of any kind nothing acts on, counted in `counts` too
+    pub unknown: u64,
+    /// Payload bytes of sixel images (DCS `q`)
+    pub sixel_bytes: u64,
+    /// Payload bytes of kitty graphics (APC `G`)
+    pub kitty_bytes: u64,
+    /// Payload bytes of iTerm2 images (OSC 1337 `File=`)
+    pub iterm_bytes: u64,
+}
+
+impl SequenceStats {
+    /// Count the sequences in `cast`'s output.
+    pub fn of(cast: &Cast) -> SequenceStats {
+        let mut stats = SequenceStats::default();
+        let mut scanner = Scanner::new();
+        for event in &cast.events {
+            if let EventKind::Output(data) = &event.kind {
+                scanner.scan(data.as_bytes(), |_, action| stats.add(&action));
+            }
+        }
+        stats
+    }
+
+    fn add(&mut self, action: &Action) {
+        match image(action) {
+            Some((IMAGE_SIXEL, len)) => self.sixel_bytes += len as u64,
+            Some((IMAGE_ITERM, len)) => self.iterm_bytes += len as u64,
+            Some((_, len)) => self.kitty_bytes += len as u64,
+            None => {}
+        }
+        self.unknown += is_unknown(action) as u64;
+        if let Some(key) = key(action) {
+            *self.counts.entry(key).or_default() += 1;
+        }
+    }
+}
+
+/// What a CSI, OSC or DCS sequence is counted by, dropped or not.
+fn key(action: &Action) -> Option<Key> {
+    let dcs = |payload| {
+        let (prefix, final_byte) = dcs_key(payload);
+        Key {
+            kind: Kind::Dcs,
+            prefix,
+            id: final_byte.map_or(-1, i32::from),
+        }
+    };
+    match action {
+        Action::Csi(csi) => Some(Key {
+            kind: Kind::Csi,
+            prefix: csi_prefix(csi),
+            id: csi.final_byte as i32,
+        }),
+        Action::Osc(payload)
+        | Action::Dropped {
+            introducer: b']',
+            head: payload,
+            ..
+        } => Some(osc_key(payload)),
+        Action::Dcs(payload)
+        | Action::Dropped {
+            introducer: b'P',
+            head: payload,
+            ..
+        } => Some(dcs(payload)),
+        _ => None,
+    }
+}
+
+/// Whether `action` is a sequence nothing acts on.
+pub(crate) fn is_unknown(action: &Action) -> bool {
+    match action {
+        Action::Control(_) => false,
+        Action::Esc {
+            intermediate,
+            final_byte,
+        } => !esc_is_known(*intermediate, *final_byte),
+        _ => key(action).is_none_or(|key| !is_known(&key)),
+    }
+}
+
+/// The image protocol of an image sequence and its payload length; kitty
+/// graphics as protocol 0, which nothing renders.
+fn image(action: &Action) -> Option<(u8, usize)> {
+    let (introducer, head, len) = match action {
+        Action::Osc(payload) => (b']', *payload, payload.len()),
+        Action::Dcs(payload) => (b'P', *payload, payload.len()),
+        &Action::Dropped {
+            introducer,
+            head,
+            len,
+        } => (introducer, head, len),
+        _ => return None,
+    };
+    match introducer {
+        b']' if head.starts_with(b"1337;File=") => Some((IMAGE_ITERM, len)),
+        b'P' if dcs_key(head) == (0, Some(b'q')) => Some((IMAGE_SIXEL, len)),
+        b'_' if head.first() == Some(&b'G') => Some((0, len)),
+        _ => None,
+    }
+}
+
+fn csi_prefix(csi: &Csi) -> u8 {
+    csi.intermediates()
+        .last()
+        .copied()
+        .or(csi.marker)
+        .unwrap_or(0)
+}
+
+/// OSC key: the number before the first `;`.
+fn osc_key(payload: &[u8]) -> Key {
+    let command = payload.split(|&b| b == b';').next().unwrap_or_default();
+    let id = std::str::from_utf8(command)
+        .ok()
+        .and_then(|command| command.parse().ok())
+        .unwrap_or(-1);
+    Key {
+        kind: Kind::Osc,
+        prefix: 0,
+        id,
+    }
+}
+
+/// DCS intermediate (or 0) and final byte, after the parameters.
+fn dcs_key(payload: &[u8]) -> (u8, Option<u8>) {
+    let params = payload
+        .iter()
+        .take_while(|&&b| b.is_ascii_digit() || b == b';' || b == b':')
+        .count();
+    let rest = &payload[params..];
+    let intermediates = rest
+        .iter()
+        .take_while(|&&b| (0x20..0x30).contains(&b))
+        .count();
+    let prefix = intermediates.checked_sub(1).map_or(0, |last| rest[last]);
+    let final_byte = rest
+        .get(intermediates)
+        .copied()
+        .filter(|b| (0x40..0x7f).contains(b));
+    (prefix, final_byte)
+}
+
+/// One kind of unknown sequence: a CSI, OSC or DCS `Key`, an escape
+/// (intermediate and final byte), or a dropped string (introducer and
+/// first byte).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signature {
+    Key(Key),
+    Esc(Option<u8>, u8),
+    String(u8, Option<u8>),
+}
+
+impl Signature {
+    fn of(action: &Action) -> Option<Signature> {
+        match *action {
+            Action::Control(_) => None,
+            Action::Esc {
+                intermediate,
+                final_byte,
+            } => Some(Signature::Esc(intermediate, final_byte)),
+            Action::Dropped {
+                introducer, head, ..
+            } if key(action).is_none() => {
+                Some(Signature::String(introducer, head.first().copied()))
+            }
+            _ => key(action).map(Signature::Key),
+        }
+    }
+}
+
+/// A kind of unknown sequence seen in fed output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unknown {
+    signature: Signature,
+    /// The first one as the scanner saw it (parameters re-encoded, `;`
+    /// separated; strings terminated by ST), up to `MAX_SAMPLE` bytes
+    pub sample: Vec<u8>,
+    pub count: u64,
+    /// Event time of the first (see `AvtState::set_event_time`)
+    pub first_us: u64,
+}
+
+/// The unknown sequences a VT was fed, by kind, for diagnostics.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnknownLog {
+    entries: Vec<Unknown>,
+    /// Unknown sequences of kinds past the first `MAX_LOGGED`
+    pub unlogged: u64,
+}
+
+impl UnknownLog {
+    /// Log `action`, fed at `at_us`, if it's unknown.
+    pub fn record(&mut self, action: &Action, at_us: u64) {
+        if !is_unknown(action) {
+            return;
+        }
+        let Some(signature) = Signature::of(action) else {
+            return;
+        };
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.signature == signature) {
+            entry.count += 1;
+        } else if self.entries.len() < MAX_LOGGED {
+            self.entries.push(Unknown {
+                signature,
+                sample: sample(action),
+                count: 1,
+                first_us: at_us,
+            });
+        } else {
+            self.unlogged += 1;
+        }
+    }
+
+    /// Kinds in the order they first came.
+    pub fn entries(&self) -> &[Unknown] {
+        &self.entries
+    }
+
+    /// `unlogged entry_count (count first_us sample_len sample)*`, in
+    /// varints.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint_u64(&mut buf, self.unlogged);
+        write_varint(&mut buf, self.entries.len());
+        for entry in &self.entries {
+            write_varint_u64(&mut buf, entry.count);
+            write_varint_u64(&mut buf, entry.first_us);
+            write_varint(&mut buf, entry.sample.len());
+            buf.extend_from_slice(&entry.sample);
+        }
+        buf
+    }
+}
+
+fn sample(action: &Action) -> Vec<u8> {
+    let mut out = vec![0x1b];
+    match action {
+        Action::Control(b) => return vec![*b],
+        Action::Csi(csi) => {
+            out.push(b'[');
+            out.extend(csi.marker);
+            let params: Vec<String> = csi.params().iter().map(u16::to_string).collect();
+            out.extend_from_slice(params.join(";").as_bytes());
+            out.extend_from_slice(csi.intermediates());
+            out.push(csi.final_byte);
+        }
+        Action::Esc {
+            intermediate,
+            final_byte,
+        } => {
+            out.extend(*intermediate);
+            out.push(*final_byte);
+        }
+        Action::Osc(payload) | Action::Dcs(payload) => {
+            out.push(if matches!(action, Action::Osc(_)) {
+                b']'
+            } else {
+                b'P'
+            });
+            out.extend_from_slice(payload);
+            out.extend_from_slice(b"\x1b\\");
+        }
+        // Only the head is left
+        Action::Dropped {
+            introducer, head, ..
+        } => {
+            out.push(*introducer);
+            out.extend_from_slice(head);
+        }
+    }
+    out.truncate(MAX_SAMPLE);
+    out
+}
+
+/// Whether avt or the wrapper acts on sequences with `key`.
+pub fn is_known(key: &Key) -> bool {
+    match key.kind {
+        Kind::Csi => {
+            let Ok(final_byte) = u8::try_from(key.id) else {
+                return false;
+            };
+            match key.prefix {
+                0 => b"@ABCDEFGHIJKLMPSTXZ`abcdefghlmnrstu".contains(&final_byte),
+                b'?' => matches!(final_byte, b'h' | b'l' | b'J' | b'K'),
+                b'>' => final_byte == b'c',
+                b' ' => final_byte == b'q',
+                b'!' => final_byte == b'p',
+                _ => false,
+            }
+        }
+        // Titles, cwd, links, notifications, clipboard, shell integration
+        Kind::Osc => matches!(key.id, 0 | 1 | 2 | 7 | 8 | 9 | 52 | 133 | 777 | 1337),
+        // Sixel images and XTGETTCAP
+        Kind::Dcs => matches!((key.prefix, key.id as u8), (0, b'q') | (b'+', b'q')),
+    }
+}
+
+fn esc_is_known(intermediate: Option<u8>, final_byte: u8) -> bool {
+    match intermediate {
+        None => b"78=>DEHMc".contains(&final_byte),
+        Some(b'#') => matches!(final_byte, b'3'..=b'6' | b'8'),
+        Some(b'(' | b')') => matches!(final_byte, b'0' | b'B'),
+        _ => false,
+    }
+}
+
+// JNI functions
+
+/// `[unknown, sixelBytes, kittyBytes, itermBytes]`, then `[key, count]`
+/// for each kind of sequence, where key is `Key::code`. Empty for an
+/// invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSequenceStats<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let stats = SequenceStats::of(cast);
+        let mut values: Vec<jlong> = [
+            stats.unknown,
+            stats.sixel_bytes,
+            stats.kitty_bytes,
+            stats.iterm_bytes,
+        ]
+        .map(|n| n as jlong)
+        .to_vec();
+        for (key, &count) in &stats.counts {
+            values.extend([key.code(), count as jlong]);
+        }
+        crate::long_array(&env, &values)
+    })
+}
+
+/// The VT's `UnknownLog`, as `UnknownLog::encode`; empty for an invalid
+/// handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtUnknownSequences<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let log = vt.unknown_sequences().encode();
+        env.byte_array_from_slice(&log).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn counts_sequences_unknowns_and_image_bytes() {
+        let mut apc = "\\u001b_Ga=T,f=100;".to_string();
+        apc.push_str(&"A".repeat(100));
+        let cast = Cast::parse(
+            format!(
+                "{{\"version\": 2, \"width\": 80, \"height\": 24}}\n\
+                [1.0, \"o\", \"\\u001b[1m\\u001b[?1049h\\u001b[2J\\u001b[0m\"]\n\
+                [1.5, \"i\", \"\\u001b[A\"]\n\
+                [2.0, \"o\", \"\\u001b]0;t\\u0007\\u001b]4;1;red\\u0007\\u001b[12y\"]\n\
+                [3.0, \"o\", \"\\u001bP0;1q#0~~\\u001b\\\\\\u001bP+q544e\\u001b\\\\\"]\n\
+                [4.0, \"o\", \"{}\\u001b\\\\\\u001b]1337;File=:AAAA\\u0007\\u001b%G\"]\n",
+                apc
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let stats = SequenceStats::of(&cast);
+        let count = |kind, prefix, id: u8| {
+            let key = Key {
+                kind,
+                prefix,
+                id: id as i32,
+            };
+            stats.counts.get(&key).copied().unwrap_or(0)
+        };
+        assert_eq!(count(Kind::Csi, 0, b'm'), 2);
+        assert_eq!(count(Kind::Csi, b'?', b'h'), 1);
+        // Input isn't output
+        assert_eq!(count(Kind::Csi, 0, b'A'), 0);
+        assert_eq!(count(Kind::Osc, 0, 0), 1);
+        assert_eq!(count(Kind::Osc, 0, 4), 1);
+        assert_eq!(count(Kind::Dcs, b'+', b'q'), 1);
+        assert_eq!(stats.counts.len(), 9);
+
+        // OSC 4, CSI y, APC G and ESC % G
+        assert_eq!(stats.unknown, 4);
+        assert_eq!(stats.sixel_bytes, "0;1q#0~~".len() as u64);
+        assert_eq!(stats.kitty_bytes, "Ga=T,f=100;".len() as u64 + 100);
+        assert_eq!(stats.iterm_bytes, "1337;File=:AAAA".len() as u64);
+    }
+
+    #[test]
+    fn vt_logs_each_kind_of_unknown_sequence_once() {
+        let mut vt = AvtState::with_backend(fake(20, 1));
+        vt.set_event_time(Some(1_000));
+        vt.feed(b"\x1b[1m\x1b[12;3y\x1b]4;1;red\x07\x1b_Ga=T;AAAA\x1b\\");
+        vt.set_event_time(Some(2_000));
+        vt.feed(b"\x1b[y\x1b[?5y\x1b_Gq\x1b\\\x1b%G");
+
+        let log = vt.unknown_sequences();
+        let entries: Vec<(&[u8], u64, u64)> = log
+            .entries()
+            .iter()
+            .map(|e| (e.sample.as_slice(), e.count, e.first_us))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (b"\x1b[12;3y".as_slice(), 2, 1_000),
+                (b"\x1b]4;1;red\x1b\\", 1, 1_000),
+                (b"\x1b_Ga=T;AAAA", 2, 1_000),
+                (b"\x1b[?5y", 1, 2_000),
+                (b"\x1b%G", 1, 2_000),
+            ]
+        );
+
+        // Past the limit, kinds are only counted
+        let mut log = UnknownLog::default();
+        let mut scanner = Scanner::new();
+        for final_byte in b'a'..=b'z' {
+            let bytes = [0x1b, b'[', b'=', final_byte, 0x1b, b'[', b'>', final_byte];
+            scanner.scan(&bytes, |_, action| log.record(&action, 0));
+        }
+        assert_eq!(log.entries().len(), MAX_LOGGED);
+        // >c is DA2
+        assert_eq!(log.unlogged, 2 * 26 - 1 - MAX_LOGGED as u64);
+        assert_eq!(log.encode()[0], log.unlogged as u8);
+    }
+
+    #[test]
+    fn keys_pack_into_one_long() {
+        let key = Key {
+            kind: Kind::Osc,
+            prefix: 0,
+            id: -1,
+        };
+        assert_eq!(key.code(), 1 << 40 | 0xffff_ffff);
+        let key = Key {
+            kind: Kind::Csi,
+            prefix: b'?',
+            id: b'h' as i32,
+        };
+        assert_eq!(key.code(), (b'?' as i64) << 32 | b'h' as i64);
+        assert!(is_known(&key));
+    }
+}
@@ -0,0 +1,253 @@
+//! Sessions and escape-sequence soup replayed through the wrapper, checked
+//! from a client's side.
+//!
+//! Fixtures in `fixtures/sessions/` are asciicast v2 files of vim, htop and
+//! tmux sessions. They are written by hand to send what those programs
+//! send (the alternate screen, scroll regions, paste and mouse modes, 256
+//! and RGB colours, synchronized updates, DEC line drawing, a resize) and
+//! are kept small enough to read. The soup is generated: text, controls
+//! and the sequences the wrapper and avt interpret, with random parameters
+//! and cut at random points.
+//!
+//! Two VTs are fed the same output, in whole events and in small chunks.
+//! One is polled for content diffs and one for span diffs, and each diff is
+//! applied to the screen a client took from the first snapshot. After
+//! every feed, the snapshot must decode and encode back to the same
+//! bytes, and the client of either VT must show what a fresh snapshot
+//! shows. Nothing may panic.
+
+use super::*;
+use crate::backend::TerminalBackend;
+use crate::cast::{Cast, EventKind};
+use crate::diff::{self, Column};
+use crate::lineattr::LineAttr;
+use crate::snapshot::{self, Line, Screen, Style};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::{select, Index};
+
+macro_rules! sessions {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_bytes!(concat!("../fixtures/sessions/", $name, ".cast")))),*]
+    };
+}
+
+const SESSIONS: &[(&str, &[u8])] = sessions!["htop", "tmux", "vim"];
+
+/// Feeds per output event: whole, and in chunks that cut sequences
+const CHUNKS: [usize; 3] = [usize::MAX, 7, 1];
+
+/// A VT, how it's polled, and the screen its client has.
+struct Follower<B: TerminalBackend> {
+    vt: AvtState<B>,
+    poll: fn(&mut AvtState<B>) -> Option<Vec<u8>>,
+    client: Screen,
+}
+
+impl<B: TerminalBackend> Follower<B> {
+    fn new(vt: AvtState<B>, poll: fn(&mut AvtState<B>) -> Option<Vec<u8>>) -> Self {
+        let client = snapshot::decode(&vt.encode_snapshot()).unwrap();
+        Follower { vt, poll, client }
+    }
+
+    fn feed(&mut self, bytes: &[u8], context: &str) {
+        self.vt.feed(bytes);
+        self.check(context);
+    }
+
+    fn resize(&mut self, cols: usize, rows: usize, context: &str) {
+        self.vt.resize(cols, rows);
+        self.check(context);
+    }
+
+    fn check(&mut self, context: &str) {
+        if let Some(bytes) = (self.poll)(&mut self.vt) {
+            apply(&mut self.client, &bytes);
+        }
+        let bytes = self.vt.encode_snapshot();
+        let screen = snapshot::decode(&bytes).unwrap();
+        assert_eq!(screen.encode(), bytes, "{}: snapshot round trip", context);
+        // Diffs are held back until a synchronized update ends
+        if self.vt.sync_since.is_none() {
+            assert_shows(&self.client, &screen, context);
+        }
+    }
+}
+
+/// The pair of followers every replay feeds.
+fn followers(cols: usize, rows: usize) -> [Follower<backend::AvtBackend>; 2] {
+    [
+        Follower::new(AvtState::new(cols, rows), AvtState::poll_diff_content),
+        Follower::new(AvtState::new(cols, rows), AvtState::poll_diff_spans),
+    ]
+}
+
+/// Apply a content or span diff to `screen`, as a client does.
+fn apply(screen: &mut Screen, bytes: &[u8]) {
+    let diff = diff::decode(bytes).unwrap();
+    let content = diff.content.expect("content diff");
+    screen.cols = content.cols;
+    screen.rows = content.rows;
+    screen.cursor = content.cursor;
+    screen.alt_screen = content.alt_screen;
+    screen.lines.resize_with(content.rows, Line::default);
+    for (i, (&row, line)) in diff.lines.iter().zip(content.lines).enumerate() {
+        screen.lines[row] = match content.spans.as_ref() {
+            Some(spans) => splice(&screen.lines[row], line, &spans[i]),
+            None => line,
+        };
+    }
+}
+
+/// `old` with the columns in `span` replaced by `new`'s runs.
+fn splice(old: &Line, new: Line, span: &std::ops::Range<usize>) -> Line {
+    let mut runs = diff::crop(old, &(0..span.start)).runs;
+    runs.extend(diff::crop(old, &(span.end..usize::MAX)).runs);
+    runs.extend(new.runs);
+    runs.sort_by_key(|run| run.col);
+    Line {
+        attr: new.attr,
+        runs,
+    }
+}
+
+/// A line's columns, unset ones as blanks and trailing blanks dropped, so
+/// lines that look the same compare equal however their runs are cut.
+fn columns(line: &Line) -> (LineAttr, Vec<Column>) {
+    let blank = Some((' ', Style::default(), 0, 0));
+    let mut cells: Vec<_> = diff::cells(line)
+        .into_iter()
+        .map(|cell| cell.or(blank))
+        .collect();
+    while cells.last() == Some(&blank) {
+        cells.pop();
+    }
+    (line.attr, cells)
+}
+
+fn assert_shows(client: &Screen, screen: &Screen, context: &str) {
+    assert_eq!(
+        (client.cols, client.rows, client.cursor, client.alt_screen),
+        (screen.cols, screen.rows, screen.cursor, screen.alt_screen),
+        "{}",
+        context
+    );
+    assert_eq!(client.lines.len(), screen.lines.len(), "{}", context);
+    for (row, (shown, fresh)) in client.lines.iter().zip(&screen.lines).enumerate() {
+        assert_eq!(columns(shown), columns(fresh), "{}: row {}", context, row);
+    }
+}
+
+fn replay(followers: &mut [Follower<impl TerminalBackend>], cast: &Cast, chunk: usize, name: &str) {
+    for (i, event) in cast.events.iter().enumerate() {
+        let context = format!("{} in chunks of {}, event {}", name, chunk, i);
+        for follower in followers.iter_mut() {
+            match event.kind {
+                EventKind::Output(ref data) => {
+                    for bytes in data.as_bytes().chunks(chunk) {
+                        follower.feed(bytes, &context);
+                    }
+                }
+                EventKind::Resize { cols, rows } => follower.resize(cols, rows, &context),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn sessions_keep_clients_in_step() {
+    for (name, bytes) in SESSIONS {
+        let cast = Cast::parse(bytes).unwrap();
+        for chunk in CHUNKS {
+            let mut followers = followers(cast.header.cols, cast.header.rows);
+            replay(&mut followers, &cast, chunk, name);
+        }
+    }
+}
+
+#[test]
+fn sessions_end_back_at_the_shell() {
+    for (name, bytes) in SESSIONS {
+        let cast = Cast::parse(bytes).unwrap();
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        for event in &cast.events {
+            player::apply(&mut vt, &event.kind, true);
+        }
+        assert!(!vt.screen().alt_screen, "{}", name);
+        assert_eq!(vt.modes(), 0, "{}", name);
+    }
+}
+
+/// One piece of soup: printable or multibyte text, a control, or a
+/// sequence. CSI `t` is left out, as its window operations can resize.
+fn arb_piece() -> impl Strategy<Value = String> {
+    let param = prop_oneof![
+        Just(String::new()),
+        (0u16..120).prop_map(|n| n.to_string()),
+        Just("9999".to_string())
+    ];
+    let params = vec(param, 0..4).prop_map(|params| params.join(";"));
+    let finals = select(&b"@ABCDEFGHIJKLMPSTXZ`abcdefghlmnqrsu"[..]);
+    let strs = |strs: &'static [&'static str]| select(strs).prop_map(String::from);
+    prop_oneof![
+        4 => "[ -~]{1,12}",
+        1 => strs(&["é", "日本", "🙂", "e\u{301}", "│", "\u{200b}", "\u{fe0f}"]),
+        2 => strs(&["\r", "\n", "\r\n", "\x08", "\t", "\x07", "\x0b", "\x0c", "\x0e", "\x0f", "\0"]),
+        4 => (option::of(Just("?")), params, finals).prop_map(|(marker, params, f)| {
+            format!("\x1b[{}{}{}", marker.unwrap_or(""), params, f as char)
+        }),
+        1 => (any::<u8>(), any::<(u8, u8, u8)>())
+            .prop_map(|(i, (r, g, b))| format!("\x1b[38;5;{};48;2;{};{};{}m", i, r, g, b)),
+        1 => (0u8..7).prop_map(|shape| format!("\x1b[{} q", shape)),
+        2 => strs(&[
+            "\x1b7", "\x1b8", "\x1bD", "\x1bE", "\x1bH", "\x1bM", "\x1bc", "\x1b=", "\x1b>",
+            "\x1b(0", "\x1b(B", "\x1b#3", "\x1b#4", "\x1b#5", "\x1b#6", "\x1b#8",
+        ]),
+        1 => "[a-z ]{0,8}".prop_map(|title| format!("\x1b]0;{}\x07", title)),
+        1 => "[a-z]{1,6}".prop_map(|text| {
+            format!("\x1b]8;;https://{}.example\x1b\\{}\x1b]8;;\x1b\\", text, text)
+        }),
+        2 => (select(&[1u16, 6, 7, 12, 25, 47, 1000, 1006, 1047, 1049, 2004, 2026][..]), any::<bool>())
+            .prop_map(|(mode, on)| format!("\x1b[?{}{}", mode, if on { 'h' } else { 'l' })),
+    ]
+}
+
+/// Output pieces, each fed in two parts cut at a random point, and the
+/// occasional resize.
+#[derive(Debug, Clone)]
+enum Step {
+    Output(String, Index),
+    Resize(usize, usize),
+}
+
+fn arb_step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        16 => (vec(arb_piece(), 1..6), any::<Index>())
+            .prop_map(|(pieces, cut)| Step::Output(pieces.concat(), cut)),
+        1 => (1usize..30, 1usize..8).prop_map(|(cols, rows)| Step::Resize(cols, rows)),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn escape_soup_keeps_clients_in_step(steps in vec(arb_step(), 1..32)) {
+        let mut followers = followers(20, 5);
+        for (i, step) in steps.iter().enumerate() {
+            let context = format!("step {}", i);
+            for follower in followers.iter_mut() {
+                match step {
+                    Step::Output(text, cut) => {
+                        let (head, tail) = text.as_bytes().split_at(cut.index(text.len() + 1));
+                        follower.feed(head, &context);
+                        follower.feed(tail, &context);
+                    }
+                    Step::Resize(cols, rows) => follower.resize(*cols, *rows, &context),
+                }
+            }
+        }
+    }
+}
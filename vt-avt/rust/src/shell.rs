@@ -0,0 +1,253 @@
+//! Shell integration timelines.
+//!
+//! Shells and terminals report state through OSC sequences that end up in
+//! recordings. The player scans a cast's output once and keeps the result
+//! in playback time (idle time limit applied), for seek bar overlays:
+//!
+//! - working directory from OSC 7 (`file://host/path`) and iTerm2's
+//!   OSC 1337 `CurrentDir=`
+//! - commands and their exit status from OSC 133 (FinalTerm semantic
+//!   prompts): `B` command start, `C` output start, `D;<status>` finished
+
+use crate::cast::{Cast, EventKind};
+use crate::scan::{Action, Scanner};
+use crate::{write_varint, write_varint_u64};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CwdChange {
+    pub time_us: i64,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub start_us: i64,
+    pub end_us: i64,
+    /// `None` when the shell didn't report one
+    pub exit_status: Option<u32>,
+}
+
+impl Command {
+    pub fn failed(&self) -> bool {
+        self.exit_status.is_some_and(|status| status != 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ShellTimeline {
+    /// Only actual changes: a prompt repeating the same directory is dropped
+    pub cwd: Vec<CwdChange>,
+    pub commands: Vec<Command>,
+    /// Start of the command in progress: `C`, or `B` until `C` arrives
+    command_start: Option<(i64, bool)>,
+}
+
+impl ShellTimeline {
+    /// Scan `cast`, where `schedule[i]` is the playback time of event `i`.
+    pub fn scan(cast: &Cast, schedule: &[i64]) -> Self {
+        let mut timeline = ShellTimeline::default();
+        let mut scanner = Scanner::new();
+
+        for (event, &time_us) in cast.events.iter().zip(schedule) {
+            let EventKind::Output(data) = &event.kind else {
+                continue;
+            };
+            scanner.scan(data.as_bytes(), |_, action| {
+                if let Action::Osc(osc) = action {
+                    timeline.osc(time_us, osc);
+                }
+            });
+        }
+
+        timeline
+    }
+
+    fn osc(&mut self, time_us: i64, osc: &[u8]) {
+        if let Some(mark) = osc.strip_prefix(b"133;") {
+            self.semantic_prompt(time_us, mark);
+            return;
+        }
+        let Some(path) = cwd_of(osc) else {
+            return;
+        };
+        if self.cwd.last().map(|c| &c.path) != Some(&path) {
+            self.cwd.push(CwdChange { time_us, path });
+        }
+    }
+
+    fn semantic_prompt(&mut self, time_us: i64, mark: &[u8]) {
+        let mut params = mark.split(|&b| b == b';');
+        match params.next() {
+            Some(b"A") => self.command_start = None,
+            Some(b"B") => self.command_start = Some((time_us, false)),
+            Some(b"C") => self.command_start = Some((time_us, true)),
+            Some(b"D") => {
+                let exit_status = params
+                    .next()
+                    .and_then(|p| std::str::from_utf8(p).ok())
+                    .and_then(|p| p.parse().ok());
+                // A bare `D` right after a prompt is an empty command line
+                let start = match self.command_start.take() {
+                    Some((start, executed)) if executed || exit_status.is_some() => start,
+                    None if exit_status.is_some() => time_us,
+                    _ => return,
+                };
+                self.commands.push(Command {
+                    start_us: start,
+                    end_us: time_us,
+                    exit_status,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Varint count, then per change a varint time delta in microseconds
+    /// (relative to the previous change) and a varint-length UTF-8 path.
+    pub fn encode_cwd(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, self.cwd.len());
+        let mut prev = 0;
+        for change in &self.cwd {
+            write_varint_u64(&mut buf, (change.time_us - prev).max(0) as u64);
+            prev = change.time_us;
+            write_varint(&mut buf, change.path.len());
+            buf.extend_from_slice(change.path.as_bytes());
+        }
+        buf
+    }
+
+    /// Varint count, then per command a varint start delta in microseconds
+    /// (relative to the previous command's start), a varint duration and a
+    /// varint exit status plus one, 0 meaning unknown.
+    pub fn encode_commands(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, self.commands.len());
+        let mut prev = 0;
+        for command in &self.commands {
+            write_varint_u64(&mut buf, (command.start_us - prev).max(0) as u64);
+            prev = command.start_us;
+            write_varint_u64(&mut buf, (command.end_us - command.start_us).max(0) as u64);
+            write_varint_u64(&mut buf, command.exit_status.map_or(0, |s| s as u64 + 1));
+        }
+        buf
+    }
+}
+
+fn cwd_of(osc: &[u8]) -> Option<String> {
+    let osc = String::from_utf8_lossy(osc);
+    if let Some(url) = osc.strip_prefix("7;") {
+        // file://host/path: the host is only informative
+        let path = match url.strip_prefix("file://") {
+            Some(rest) => &rest[rest.find('/')?..],
+            None if url.starts_with('/') => url,
+            None => return None,
+        };
+        return Some(percent_decode(path));
+    }
+    osc.strip_prefix("1337;CurrentDir=")
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(cast: &[u8]) -> ShellTimeline {
+        let cast = Cast::parse(cast).unwrap();
+        let schedule: Vec<i64> = cast.events.iter().map(|e| e.time_us).collect();
+        ShellTimeline::scan(&cast, &schedule)
+    }
+
+    #[test]
+    fn tracks_osc7_and_iterm_cwd_changes() {
+        let t = timeline(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"o\", \"\\u001b]7;file://box/home/me\\u0007$ \"]\n\
+            [2.0, \"o\", \"\\u001b]7;file://box/home/me\\u001b\\\\$ \"]\n\
+            [3.0, \"o\", \"\\u001b]7;file://box/tmp/my%20dir\"]\n\
+            [3.5, \"o\", \"\\u0007\"]\n\
+            [4.0, \"o\", \"\\u001b]1337;CurrentDir=/srv\\u0007\"]\n\
+            [5.0, \"o\", \"\\u001b]7;not-a-path\\u0007\"]\n",
+        );
+        let paths: Vec<_> = t.cwd.iter().map(|c| (c.time_us, c.path.as_str())).collect();
+        assert_eq!(
+            paths,
+            [
+                (1_000_000, "/home/me"),
+                (3_500_000, "/tmp/my dir"),
+                (4_000_000, "/srv")
+            ]
+        );
+
+        let encoded = t.encode_cwd();
+        assert_eq!(&encoded[..1], &[3]);
+        assert!(encoded.ends_with(b"\x04/srv"));
+    }
+
+    #[test]
+    fn collects_commands_and_exit_status() {
+        let t = timeline(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"o\", \"\\u001b]133;A\\u0007$ \\u001b]133;B\\u0007\"]\n\
+            [2.0, \"o\", \"\\u001b]133;C\\u0007\"]\n\
+            [2.5, \"o\", \"no such file\\r\\n\\u001b]133;D;1\\u0007\"]\n\
+            [3.0, \"o\", \"\\u001b]133;A\\u0007$ \\u001b]133;B\\u0007\"]\n\
+            [4.0, \"o\", \"\\u001b]133;D\\u0007\\u001b]133;A\\u0007$ \"]\n\
+            [5.0, \"o\", \"\\u001b]133;B\\u0007\"]\n\
+            [6.0, \"o\", \"\\u001b]133;D;0;aid=42\\u0007\"]\n\
+            [7.0, \"o\", \"\\u001b]133;C\\u0007\"]\n\
+            [8.0, \"o\", \"\\u001b]133;D\\u0007\"]\n",
+        );
+        assert_eq!(
+            t.commands,
+            [
+                Command {
+                    start_us: 2_000_000,
+                    end_us: 2_500_000,
+                    exit_status: Some(1)
+                },
+                Command {
+                    start_us: 5_000_000,
+                    end_us: 6_000_000,
+                    exit_status: Some(0)
+                },
+                Command {
+                    start_us: 7_000_000,
+                    end_us: 8_000_000,
+                    exit_status: None
+                },
+            ]
+        );
+        assert!(t.commands[0].failed());
+        assert!(!t.commands[2].failed());
+
+        let encoded = t.encode_commands();
+        assert_eq!(encoded[0], 3);
+        assert!(encoded.ends_with(&[0]));
+    }
+}
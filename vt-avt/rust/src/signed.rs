@@ -0,0 +1,400 @@
+//! Signed exports, so a shared cast can be checked for tampering.
+//!
+//! `export` writes the cast as usual and appends one more event, which
+//! players skip as an unknown code:
+//!
+//! ```text
+//! [<last time>, "x-signature", {"alg": "ed25519-sha256-tree",
+//!   "chunk_lines": 256, "leaves": ["<hex>", ...], "root": "<hex>",
+//!   "sig": "<hex>"}]
+//! ```
+//!
+//! Every line before it, header included, is split into chunks of
+//! `chunk_lines` lines. Each chunk hashes to a leaf, SHA-256(0x00 chunk),
+//! pairs of nodes to SHA-256(0x01 left right) with an odd one carried up,
+//! and the Ed25519 signature covers the root. The manifest lists every
+//! leaf, so `verify` can say which chunk changed rather than only that the
+//! file did. Lines added after the signature make the file unsigned.
+
+use crate::cast::{Cast, Event, EventKind, Timing};
+use crate::ed25519::{self, PUBLIC_KEY_LEN, SEED_LEN, SIGNATURE_LEN};
+use crate::handles;
+use crate::json::{self, Value};
+use crate::to_hex;
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use sha2::{Digest, Sha256};
+
+pub const EVENT_CODE: &str = "x-signature";
+pub const ALGORITHM: &str = "ed25519-sha256-tree";
+/// Lines per chunk as written, small enough to point near an edit
+pub const CHUNK_LINES: usize = 256;
+
+/// Prefixed to the signed message so the key can't be used to sign
+/// anything that parses as a cast root
+const DOMAIN: &[u8] = b"asciicast signed export v1\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Valid,
+    /// No signature event at the end
+    Unsigned,
+    /// The manifest isn't signed by the key
+    BadSignature,
+    /// The signature event can't be read
+    Malformed,
+    /// Signed by the key, but the chunk from 1-based `line` has changed
+    Altered {
+        line: usize,
+    },
+}
+
+impl Verdict {
+    /// As returned by `castVerifySignature`.
+    pub fn code(self) -> i64 {
+        match self {
+            Verdict::Valid => 0,
+            Verdict::Unsigned => -1,
+            Verdict::BadSignature => -2,
+            Verdict::Malformed => -3,
+            Verdict::Altered { line } => line as i64,
+        }
+    }
+}
+
+/// `cast` as a .cast file signed with `seed`. Signature events in the
+/// cast, say from an earlier signed export, are left out.
+pub fn export(cast: &Cast, seed: &[u8; SEED_LEN]) -> Vec<u8> {
+    let mut out = cast.header.fields.to_string();
+    out.push('\n');
+    let mut timing = Timing::new(&cast.header);
+    let mut last_us = 0;
+    for event in &cast.events {
+        if event.kind.code() == EVENT_CODE {
+            continue;
+        }
+        out.push_str(&timing.line(event));
+        out.push('\n');
+        last_us = event.time_us;
+    }
+    let manifest = manifest(out.as_bytes(), seed);
+    let line = timing.line(&Event {
+        time_us: last_us,
+        kind: EventKind::Other {
+            code: EVENT_CODE.to_string(),
+            data: manifest,
+        },
+    });
+    out.push_str(&line);
+    out.push('\n');
+    out.into_bytes()
+}
+
+/// The signature event's data for `content`.
+pub fn manifest(content: &[u8], seed: &[u8; SEED_LEN]) -> Value {
+    let leaves = leaves(content, CHUNK_LINES);
+    let root = root(&leaves);
+    let sig = ed25519::sign(seed, &message(CHUNK_LINES, &root));
+
+    let hex = |bytes: &[u8]| Value::String(to_hex(bytes));
+    Value::Object(vec![
+        ("alg".to_string(), Value::String(ALGORITHM.to_string())),
+        ("chunk_lines".to_string(), Value::Number(CHUNK_LINES as f64)),
+        (
+            "leaves".to_string(),
+            Value::Array(leaves.iter().map(|leaf| hex(leaf)).collect()),
+        ),
+        ("root".to_string(), hex(&root)),
+        ("sig".to_string(), hex(&sig)),
+    ])
+}
+
+/// Check the signed cast `bytes` against `public`.
+pub fn verify(bytes: &[u8], public: &[u8; PUBLIC_KEY_LEN]) -> Verdict {
+    let body = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let Some(split) = body.iter().rposition(|&b| b == b'\n') else {
+        return Verdict::Unsigned;
+    };
+    let (content, last) = (&body[..split + 1], &body[split + 1..]);
+
+    let event = std::str::from_utf8(last)
+        .ok()
+        .and_then(|line| json::parse(line).ok())
+        .and_then(|value| Event::from_json(&value, 0).ok());
+    let manifest = match event.map(|event| event.kind) {
+        Some(EventKind::Other { code, data }) if code == EVENT_CODE => data,
+        _ => return Verdict::Unsigned,
+    };
+    let Some(Manifest {
+        chunk_lines,
+        leaves: signed,
+        root: signed_root,
+        sig,
+    }) = Manifest::read(&manifest)
+    else {
+        return Verdict::Malformed;
+    };
+
+    if root(&signed) != signed_root
+        || !ed25519::verify(public, &message(chunk_lines, &signed_root), &sig)
+    {
+        return Verdict::BadSignature;
+    }
+    let actual = leaves(content, chunk_lines);
+    match (0..actual.len().max(signed.len())).find(|&i| actual.get(i) != signed.get(i)) {
+        Some(chunk) => Verdict::Altered {
+            line: chunk * chunk_lines + 1,
+        },
+        None => Verdict::Valid,
+    }
+}
+
+struct Manifest {
+    chunk_lines: usize,
+    leaves: Vec<[u8; 32]>,
+    root: [u8; 32],
+    sig: [u8; SIGNATURE_LEN],
+}
+
+impl Manifest {
+    fn read(value: &Value) -> Option<Manifest> {
+        if value.get("alg")?.as_str()? != ALGORITHM {
+            return None;
+        }
+        let chunk_lines = value.get("chunk_lines")?.as_f64()?;
+        if !(1.0..=u32::MAX as f64).contains(&chunk_lines) || chunk_lines.fract() != 0.0 {
+            return None;
+        }
+        let leaves = value
+            .get("leaves")?
+            .as_array()?
+            .iter()
+            .map(|leaf| unhex(leaf.as_str()?))
+            .collect::<Option<_>>()?;
+        Some(Manifest {
+            chunk_lines: chunk_lines as usize,
+            leaves,
+            root: unhex(value.get("root")?.as_str()?)?,
+            sig: unhex(value.get("sig")?.as_str()?)?,
+        })
+    }
+}
+
+fn unhex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+/// One leaf per `chunk_lines` lines of `content`.
+fn leaves(content: &[u8], chunk_lines: usize) -> Vec<[u8; 32]> {
+    let lines: Vec<&[u8]> = content.split_inclusive(|&b| b == b'\n').collect();
+    lines
+        .chunks(chunk_lines)
+        .map(|chunk| {
+            let mut hasher = Sha256::new();
+            hasher.update([0x00]);
+            for line in chunk {
+                hasher.update(line);
+            }
+            hasher.finalize().into()
+        })
+        .collect()
+}
+
+fn root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    let mut level = leaves.to_vec();
+    if level.is_empty() {
+        return Sha256::digest(b"").into();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update([0x01]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().into()
+                }
+                [odd] => *odd,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+fn message(chunk_lines: usize, root: &[u8; 32]) -> Vec<u8> {
+    let mut out = DOMAIN.to_vec();
+    out.extend_from_slice(&(chunk_lines as u32).to_be_bytes());
+    out.extend_from_slice(root);
+    out
+}
+
+/// The `N` bytes in `array`, wiping the copy made on the way.
+fn bytes_arg<const N: usize>(env: &JNIEnv, array: &JByteArray) -> Option<[u8; N]> {
+    let mut bytes = env.convert_byte_array(array).ok()?;
+    let out = bytes.as_slice().try_into().ok();
+    bytes.fill(0);
+    out
+}
+
+// JNI functions
+
+/// Write the cast `cast_handle` to `fd`, signed with the Ed25519 `seed`.
+/// The descriptor stays open. Returns false for an invalid handle, fd or
+/// seed, or a failed write.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castExportSigned<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_handle: jlong,
+    fd: jint,
+    seed: JByteArray<'a>,
+) -> jboolean {
+    use std::fs::File;
+    use std::io::Write;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return JNI_FALSE;
+        }
+        let Some(mut seed) = bytes_arg::<SEED_LEN>(&env, &seed) else {
+            return JNI_FALSE;
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JNI_FALSE;
+        };
+
+        let signed = export(cast, &seed);
+        seed.fill(0);
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        match file.write_all(&signed) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// The public key of the Ed25519 `seed`, to hand to whoever verifies.
+/// Null if the seed isn't 32 bytes.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_signPublicKey<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    seed: JByteArray<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(mut seed) = bytes_arg::<SEED_LEN>(&env, &seed) else {
+            return JByteArray::default();
+        };
+        let public = ed25519::public_key(&seed);
+        seed.fill(0);
+        env.byte_array_from_slice(&public).unwrap_or_default()
+    })
+}
+
+/// Check the signed cast in `fd` against `public_key`: 0 if it's intact,
+/// -1 if it isn't signed, -2 if the signature isn't the key's, -3 if it
+/// can't be read, or the 1-based line where the first changed chunk
+/// starts. The descriptor stays open.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castVerifySignature<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    fd: jint,
+    public_key: JByteArray<'a>,
+) -> jlong {
+    use std::fs::File;
+    use std::io::Read;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        let malformed = Verdict::Malformed.code();
+        if fd < 0 {
+            return malformed;
+        }
+        let Some(public) = bytes_arg::<PUBLIC_KEY_LEN>(&env, &public_key) else {
+            return malformed;
+        };
+
+        let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let mut bytes = Vec::new();
+        if file.read_to_end(&mut bytes).is_err() {
+            return malformed;
+        }
+        verify(&bytes, &public).code()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; SEED_LEN] = [3; SEED_LEN];
+
+    fn cast(events: usize) -> Cast {
+        let mut text = String::from("{\"version\": 2, \"width\": 80, \"height\": 24}\n");
+        for i in 0..events {
+            text.push_str(&format!("[{}.5, \"o\", \"line {}\\r\\n\"]\n", i, i));
+        }
+        Cast::parse(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn signed_exports_verify_and_point_at_changes() {
+        let public = ed25519::public_key(&SEED);
+        let signed = export(&cast(600), &SEED);
+        assert_eq!(verify(&signed, &public), Verdict::Valid);
+        assert_eq!(
+            verify(&signed, &ed25519::public_key(&[4; SEED_LEN])),
+            Verdict::BadSignature
+        );
+
+        // Line 300 (the header is line 1) is in the second chunk
+        let text = String::from_utf8(signed.clone()).unwrap();
+        let edited = text.replacen("line 298", "line 999", 1);
+        assert_eq!(
+            verify(edited.as_bytes(), &public),
+            Verdict::Altered {
+                line: CHUNK_LINES + 1
+            }
+        );
+        // Dropping the last event changes the last chunk
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.remove(lines.len() - 2);
+        let dropped = lines.join("\n");
+        assert_eq!(
+            verify(dropped.as_bytes(), &public),
+            Verdict::Altered {
+                line: 2 * CHUNK_LINES + 1
+            }
+        );
+
+        assert_eq!(verify(&cast(3).write(), &public), Verdict::Unsigned);
+        let forged = text.replacen("\"sig\": \"", "\"sig\": \"00", 1);
+        assert_eq!(verify(forged.as_bytes(), &public), Verdict::Malformed);
+    }
+
+    #[test]
+    fn re_signing_replaces_the_old_signature() {
+        let signed = export(&cast(2), &SEED);
+        let again = export(&Cast::parse(&signed).unwrap(), &SEED);
+        assert_eq!(again, signed);
+        assert_eq!(root(&[]), <[u8; 32]>::from(Sha256::digest(b"")));
+    }
+}
@@ -0,0 +1,162 @@
+//! Similarity between recordings, for grouping near-duplicate takes.
+//!
+//! Each recording's output text (see `text`) is cut into overlapping runs
+//! of words, shingles, and summarized as a MinHash signature: the smallest
+//! hash of any shingle under each of a fixed set of hash functions. The
+//! share of positions where two signatures agree estimates the Jaccard
+//! similarity of the shingle sets. Digits are folded together first, so
+//! timings, PIDs and dates that differ between takes don't count.
+
+use crate::cast::Cast;
+use crate::handles;
+use crate::text::text_lines;
+use jni::objects::JClass;
+use jni::sys::{jdouble, jlong};
+use jni::JNIEnv;
+
+/// Words per shingle
+const SHINGLE: usize = 3;
+/// Hash functions per signature; the estimate's error is about 1/sqrt of this
+const HASHES: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// `None` for a recording without any shingle
+    mins: Option<[u64; HASHES]>,
+}
+
+impl Signature {
+    pub fn of(cast: &Cast) -> Signature {
+        let words: Vec<String> = text_lines(cast)
+            .iter()
+            .flat_map(|line| line.text.split_whitespace().map(normalize))
+            .collect();
+        if words.is_empty() {
+            return Signature { mins: None };
+        }
+
+        let mut mins = [u64::MAX; HASHES];
+        for shingle in words.windows(SHINGLE.min(words.len())) {
+            let base = fnv1a(shingle);
+            for (i, min) in mins.iter_mut().enumerate() {
+                *min = (*min).min(mix(base ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+            }
+        }
+        Signature { mins: Some(mins) }
+    }
+
+    /// Estimated Jaccard similarity, 0.0 to 1.0. A recording without any
+    /// text is similar to nothing.
+    pub fn similarity(&self, other: &Signature) -> f64 {
+        match (&self.mins, &other.mins) {
+            (Some(a), Some(b)) => {
+                a.iter().zip(b).filter(|(x, y)| x == y).count() as f64 / HASHES as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+pub fn similarity(a: &Cast, b: &Cast) -> f64 {
+    Signature::of(a).similarity(&Signature::of(b))
+}
+
+/// Lowercase, with each run of digits replaced by a single `0`.
+fn normalize(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    for ch in word.chars().flat_map(char::to_lowercase) {
+        if ch.is_ascii_digit() {
+            if !out.ends_with('0') {
+                out.push('0');
+            }
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+fn fnv1a(words: &[String]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for word in words {
+        for &b in word.as_bytes().iter().chain(b" ") {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// JNI functions
+
+/// Estimated text similarity of two casts, 0.0 to 1.0.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castSimilarity(
+    mut env: JNIEnv,
+    _class: JClass,
+    a_handle: jlong,
+    b_handle: jlong,
+) -> jdouble {
+    jni_guard!(env, {
+        let Some(a) = handles::object_ref::<Cast>(&mut env, a_handle) else {
+            return 0.0;
+        };
+        let Some(b) = handles::object_ref::<Cast>(&mut env, b_handle) else {
+            return 0.0;
+        };
+
+        similarity(a, b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cast(lines: &[&str]) -> Cast {
+        let mut text = String::from("{\"version\": 2, \"width\": 80, \"height\": 24}\n");
+        for (i, line) in lines.iter().enumerate() {
+            text.push_str(&format!("[{}.0, \"o\", \"{}\\r\\n\"]\n", i, line));
+        }
+        Cast::parse(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn near_duplicate_takes_score_high() {
+        let demo = [
+            "$ cargo new hello",
+            "Created binary (application) package",
+            "$ cd hello && cargo run",
+            "Compiling hello v0.1.0 (/home/me/hello)",
+            "Finished dev profile in 1.52s",
+            "Running target/debug/hello",
+            "Hello, world!",
+        ];
+        let take = cast(&demo);
+
+        // Same demo, different timings and a typo'd extra command
+        let mut retake_lines = demo.to_vec();
+        retake_lines[4] = "Finished dev profile in 0.87s";
+        retake_lines.insert(2, "$ sl");
+        let retake = cast(&retake_lines);
+
+        let other = cast(&[
+            "$ git status",
+            "On branch main",
+            "nothing to commit, working tree clean",
+        ]);
+
+        assert_eq!(similarity(&take, &take), 1.0);
+        assert!(similarity(&take, &retake) > 0.6);
+        assert!(similarity(&take, &other) < 0.1);
+        assert_eq!(similarity(&take, &cast(&[])), 0.0);
+        assert_eq!(normalize("v0.1.10"), "v0.0.0");
+    }
+}
@@ -0,0 +1,250 @@
+//! Sixel decoding, for inline images (see `images`).
+//!
+//! A sixel image is `DCS P1 ; P2 ; P3 q data ST`. Each data char from `?`
+//! to `~` is a column of six pixels, less `?`, in the current color; `!n`
+//! repeats the next char n times, `$` returns to the left edge and `-` to
+//! the left edge of the next six rows. `#n` picks color register n (of
+//! 256, starting as the VT340's 16) and `#n;u;a;b;c` also sets it, in HLS
+//! for `u` 1 and RGB percentages for 2. `"an;ad;w;h` gives the size,
+//! which what's drawn can only extend; pixels are taken as square.
+//!
+//! Pixels never drawn are transparent, so the terminal's background shows
+//! through them, which is what P2 0 and 2 ask for as well. Images wider or
+//! taller than `MAX_SIDE` are cut off there.
+
+use crate::images::{Image, MAX_PIXELS};
+
+/// Widest and tallest an image is kept, in pixels
+pub const MAX_SIDE: usize = 4096;
+
+/// The VT340's color registers 0-15, as RGB percentages
+const VT340: [[u32; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+/// `count` columns of six pixels at `x` in the six rows from `band * 6`,
+/// each pixel set in `bits` drawn in `color`.
+struct Run {
+    x: usize,
+    band: usize,
+    count: usize,
+    bits: u8,
+    color: [u8; 4],
+}
+
+/// Decode a sixel DCS payload, `P1;P2;P3 q data`. `None` if it isn't one,
+/// or it draws nothing and has no size.
+pub fn decode(payload: &[u8]) -> Option<Image> {
+    let params = payload
+        .iter()
+        .take_while(|&&b| b.is_ascii_digit() || b == b';')
+        .count();
+    if payload.get(params) != Some(&b'q') {
+        return None;
+    }
+    let data = &payload[params + 1..];
+
+    let mut registers = [[0, 0, 0, 0xff]; 256];
+    for (register, &[r, g, b]) in registers.iter_mut().zip(&VT340) {
+        *register = rgb(r, g, b);
+    }
+    let mut color = registers[0];
+    let (mut x, mut band) = (0, 0);
+    // The size given, then what's drawn
+    let (mut width, mut height) = (0, 0);
+    let (mut drawn_width, mut drawn_height) = (0, 0);
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while let Some(&b) = data.get(i) {
+        i += 1;
+        let count = match b {
+            b'"' => {
+                let (p, _) = numbers(data, &mut i);
+                (width, height) = (p[2] as usize, p[3] as usize);
+                continue;
+            }
+            b'#' => {
+                let (p, n) = numbers(data, &mut i);
+                let register = &mut registers[p[0] as usize % 256];
+                if n >= 5 {
+                    *register = match p[1] {
+                        1 => hls(p[2], p[3], p[4]),
+                        _ => rgb(p[2], p[3], p[4]),
+                    };
+                }
+                color = *register;
+                continue;
+            }
+            b'$' => {
+                x = 0;
+                continue;
+            }
+            b'-' => {
+                x = 0;
+                band += 1;
+                continue;
+            }
+            b'!' => {
+                let (p, _) = numbers(data, &mut i);
+                p[0].max(1) as usize
+            }
+            _ => 1,
+        };
+        // A repeat's char follows it
+        let b = if b == b'!' {
+            let Some(&b) = data.get(i) else { break };
+            i += 1;
+            b
+        } else {
+            b
+        };
+        if !(b'?'..=b'~').contains(&b) {
+            continue;
+        }
+
+        let bits = b - b'?';
+        let count = count.min(MAX_SIDE.saturating_sub(x));
+        if bits != 0 && count > 0 && band * 6 < MAX_SIDE {
+            runs.push(Run {
+                x,
+                band,
+                count,
+                bits,
+                color,
+            });
+            drawn_height = drawn_height.max(band * 6 + 8 - bits.leading_zeros() as usize);
+        }
+        x += count;
+        drawn_width = drawn_width.max(x);
+    }
+
+    let width = width.max(drawn_width).min(MAX_SIDE);
+    let height = height.max(drawn_height).min(MAX_SIDE);
+    if width == 0 || height == 0 || width * height > MAX_PIXELS {
+        return None;
+    }
+    let mut rgba = vec![0; width * height * 4];
+    for run in &runs {
+        for bit in 0..6 {
+            let y = run.band * 6 + bit;
+            if run.bits & 1 << bit == 0 || y >= height {
+                continue;
+            }
+            let row = y * width;
+            for x in run.x..(run.x + run.count).min(width) {
+                rgba[(row + x) * 4..][..4].copy_from_slice(&run.color);
+            }
+        }
+    }
+    Some(Image {
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Up to five `;` separated numbers from `data[*i..]`, missing ones 0,
+/// and how many there were.
+fn numbers(data: &[u8], i: &mut usize) -> ([u32; 5], usize) {
+    let mut values = [0u32; 5];
+    let mut n = 0;
+    while let Some(&b) = data.get(*i) {
+        match b {
+            b'0'..=b'9' => {
+                if n < 5 {
+                    values[n] = values[n]
+                        .saturating_mul(10)
+                        .saturating_add((b - b'0') as u32);
+                }
+            }
+            b';' => n += 1,
+            _ => break,
+        }
+        *i += 1;
+    }
+    (values, (n + 1).min(5))
+}
+
+/// An opaque color from RGB percentages.
+fn rgb(r: u32, g: u32, b: u32) -> [u8; 4] {
+    let byte = |percent: u32| ((percent.min(100) * 255 + 50) / 100) as u8;
+    [byte(r), byte(g), byte(b), 0xff]
+}
+
+/// An opaque color from hue (degrees; 0 is blue on the VT340), lightness
+/// and saturation (percentages).
+fn hls(h: u32, l: u32, s: u32) -> [u8; 4] {
+    // Standard HSL puts red at 0, where the VT340 has blue. Parameters
+    // saturate at u32::MAX, so reduce before adding
+    let h = ((h % 360 + 240) % 360) as f32 / 60.0;
+    let (l, s) = (l.min(100) as f32 / 100.0, s.min(100) as f32 / 100.0);
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let byte = |v: f32| ((v + m) * 255.0).round() as u8;
+    [byte(r), byte(g), byte(b), 0xff]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_sixels_in_register_colors() {
+        // Red twice, then blue in the next six rows, in a size wider than
+        // what's drawn
+        let image = decode(b"0;1;0q\"1;1;4;1#1;2;100;0;0!2~-#2;1;0;50;100?@").unwrap();
+        assert_eq!((image.width, image.height), (4, 7));
+        let pixel = |x: usize, y: usize| &image.rgba[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(1, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 0]);
+        // `?` is empty, `@` the top pixel of its six
+        assert_eq!(pixel(0, 6), [0, 0, 0, 0]);
+        assert_eq!(pixel(1, 6), [0, 0, 255, 255]);
+
+        assert!(decode(b"0;1;0q").is_none());
+        assert!(decode(b"+q544e").is_none());
+    }
+
+    #[test]
+    fn vt340_hues_start_at_blue() {
+        assert_eq!(hls(0, 50, 100), [0, 0, 255, 255]);
+        assert_eq!(hls(120, 50, 100), [255, 0, 0, 255]);
+        assert_eq!(hls(240, 50, 100), [0, 255, 0, 255]);
+        assert_eq!(hls(0, 100, 0), [255, 255, 255, 255]);
+        assert_eq!(hls(360 + 120, 50, 100), hls(120, 50, 100));
+        assert_eq!(hls(u32::MAX, u32::MAX, u32::MAX), hls(u32::MAX % 360, 100, 100));
+    }
+
+    #[test]
+    fn out_of_range_colors_are_clamped() {
+        let image = decode(b"0;1;0q#1;1;99999999999;50;50~").unwrap();
+        assert_eq!(image.rgba[3], 255);
+        let image = decode(b"0;1;0q#1;2;99999999999;0;200~").unwrap();
+        assert_eq!(&image.rgba[..4], [255, 0, 255, 255]);
+    }
+}
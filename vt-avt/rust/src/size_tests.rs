@@ -0,0 +1,39 @@
+//! Size budget for the Android libraries.
+//!
+//! The library is loaded on cold start, so optional subsystems are cargo
+//! features that the default build leaves out (see Cargo.toml). This checks
+//! the release builds copied into `jniLibs` (see the README) against a
+//! budget per ABI, and prints their sizes with `--nocapture`. ABIs that
+//! haven't been built are skipped, so a desktop checkout passes as is.
+
+use std::fs;
+use std::path::Path;
+
+const JNI_LIBS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../android/src/main/jniLibs");
+const LIBRARY: &str = "libasciicast_vt_avt.so";
+
+/// Most bytes per ABI of a stripped release build with the features the app
+/// enables. Raise one only together with the feature that needs it.
+const BUDGETS: &[(&str, u64)] = &[
+    ("arm64-v8a", 1536 * 1024),
+    ("armeabi-v7a", 1024 * 1024),
+    ("x86", 1536 * 1024),
+    ("x86_64", 1792 * 1024),
+];
+
+#[test]
+fn android_libraries_fit_their_budgets() {
+    let mut over = Vec::new();
+    for &(abi, budget) in BUDGETS {
+        let path = Path::new(JNI_LIBS).join(abi).join(LIBRARY);
+        let Ok(meta) = fs::metadata(&path) else {
+            println!("{}: not built", abi);
+            continue;
+        };
+        println!("{}: {} of {} KiB", abi, meta.len() / 1024, budget / 1024);
+        if meta.len() > budget {
+            over.push(abi);
+        }
+    }
+    assert!(over.is_empty(), "over budget: {:?}", over);
+}
@@ -0,0 +1,1047 @@
+//! Snapshot wire format and its reference decoder.
+//!
+//! Layout (varints are unsigned LEB128):
+//!
+//! ```text
+//! snapshot := cols rows cursor_col cursor_row cursor_flags:u8 line*rows
+//!             link_count link* [image_count image*]
+//! line     := attr:u8 run_count run*
+//! run      := col_start text_len extent [link_id] style text:[u8; text_len]
+//! style    := color(fg) color(bg) attrs:u8
+//! color    := 0 index:u8 | 1 r:u8 g:u8 b:u8 | 2
+//! link     := link_id uri_len uri:[u8; uri_len]
+//! image    := image_id col row rows cols image_row image_rows
+//! ```
+//!
+//! Tag 2 is the terminal's default color, distinct from palette index 0 or
+//! 7 and from RGB black or white, so themes can recolor it; the tag is
+//! what carries that, and no sentinel value is reserved inside the others.
+//!
+//! `cursor_flags` holds `CURSOR_VISIBLE`, the `CursorShape` in bits 1-2 and
+//! `CURSOR_STEADY` for a cursor that doesn't blink (DECSCUSR), so the
+//! default, a visible blinking block, is 1 as before shapes were sent.
+//! Bit 4, `ALT_SCREEN`, is set while the alternate screen (modes 47, 1047
+//! and 1049) is shown, so a renderer can hide its scrollback then: full-
+//! screen programs draw there, and only the primary screen has history.
+//!
+//! `extent` is the run's `cell_count` shifted left one bit, with bit 0 set
+//! when a `link_id` follows.
+//!
+//! `cell_count` is the columns a run covers. Every char of a run takes the
+//! same number of columns, so a run of wide chars (CJK, emoji) covers
+//! twice as many as it has chars, and a renderer lays each char out at
+//! `col_start + i * cell_count / chars` whatever its font measures. A
+//! wide char's second column has no char of its own.
+//!
+//! A run inside an OSC 8 hyperlink carries the link's id (see `links`),
+//! and the snapshot ends with the URI of each id its runs use. Lines sent
+//! elsewhere (diffs, scrollback) carry ids alone; `vtLinkAt` resolves the
+//! link under a cell.
+//!
+//! When an inline image (Sixel or iTerm2, see `images`) is on screen, the
+//! link table is followed by the image table: rows `row..row + rows` from
+//! column `col` show cell rows `image_row..image_row + rows` of image
+//! `image_id`, which is `cols` cells wide and `image_rows` tall. An image
+//! partly scrolled off, or split by inserted lines, has a placement for
+//! each part. A renderer draws the pixels `vtImage` gives over the cells,
+//! scaled to them. Without images the table is left out, so a snapshot
+//! is as before.
+//!
+//! A client that negotiated a protocol version gets snapshots behind a
+//! versioned header, see `protocol`; `decode` reads either.
+//!
+//! `decode` is the contract for every client decoder (Kotlin, C ABI users):
+//! truncated input or a varint wider than 32 bits is an error, while an
+//! unknown line attribute or color tag decodes as the default, invalid UTF-8
+//! is replaced with U+FFFD, and bytes past the tables are ignored.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::json::Value;
+use crate::lineattr::{LineAttr, LineAttrs};
+use crate::{protocol, write_varint};
+use std::fmt::{self, Write as _};
+
+pub const ATTR_BOLD: u8 = 0x01;
+pub const ATTR_ITALIC: u8 = 0x02;
+pub const ATTR_UNDERLINE: u8 = 0x04;
+pub const ATTR_STRIKETHROUGH: u8 = 0x08;
+pub const ATTR_BLINK: u8 = 0x10;
+pub const ATTR_INVERSE: u8 = 0x20;
+/// Local echo prediction not yet confirmed by the program, see `predict`
+pub const ATTR_PREDICTED: u8 = 0x40;
+/// SGR 2, dim
+pub const ATTR_FAINT: u8 = 0x80;
+
+/// Names of the `ATTR_*` bits, lowest first, for debug output
+pub const ATTR_NAMES: [&str; 8] = [
+    "bold",
+    "italic",
+    "underline",
+    "strike",
+    "blink",
+    "inverse",
+    "predicted",
+    "faint",
+];
+/// Cursor flag bits, see the module docs
+pub const CURSOR_VISIBLE: u8 = 0x01;
+pub const CURSOR_STEADY: u8 = 0x08;
+/// Lowest bit of the two holding the `CursorShape`
+pub const CURSOR_SHAPE_SHIFT: u32 = 1;
+/// The two bits, once shifted down
+pub const CURSOR_SHAPE_MASK: u8 = 0x03;
+/// Bit of `cursor_flags` set while the alternate screen is shown
+pub const ALT_SCREEN: u8 = 0x10;
+
+/// Color tags, see the module docs
+pub const COLOR_INDEXED: u8 = 0;
+pub const COLOR_RGB: u8 = 1;
+pub const COLOR_DEFAULT: u8 = 2;
+
+/// Bit of a run's `extent` set when a `link_id` follows
+pub const EXTENT_LINK: usize = 0x01;
+
+/// Names of `LineAttr` values, by discriminant
+pub const LINE_ATTR_NAMES: [&str; 4] = ["single", "double-width", "double-top", "double-bottom"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    /// `ATTR_*` bits
+    pub attrs: u8,
+}
+
+impl Style {
+    /// Lossless packing into 60 bits: attrs in bits 0-7, then fg and bg as
+    /// 26-bit colors (wire tag in the top two bits, index or RGB below).
+    /// Clients key their style caches on this and pass it to
+    /// `vtResolveStyles`.
+    pub fn id(&self) -> u64 {
+        self.attrs as u64 | color_bits(self.fg) << 8 | color_bits(self.bg) << 34
+    }
+
+    /// Inverse of `id`. Unknown color tags decode as the default color.
+    pub fn from_id(id: u64) -> Style {
+        Style {
+            fg: color_from_bits(id >> 8),
+            bg: color_from_bits(id >> 34),
+            attrs: id as u8,
+        }
+    }
+
+    /// Append the SGR sequence that sets exactly this style, from a reset.
+    pub fn push_sgr(&self, out: &mut String) {
+        const CODES: [(u8, u8); 7] = [
+            (ATTR_BOLD, 1),
+            (ATTR_FAINT, 2),
+            (ATTR_ITALIC, 3),
+            (ATTR_UNDERLINE, 4),
+            (ATTR_BLINK, 5),
+            (ATTR_INVERSE, 7),
+            (ATTR_STRIKETHROUGH, 9),
+        ];
+
+        out.push_str("\x1b[0");
+        for (bit, code) in CODES {
+            if self.attrs & bit != 0 {
+                let _ = write!(out, ";{}", code);
+            }
+        }
+        push_sgr_color(self.fg, 30, out);
+        push_sgr_color(self.bg, 40, out);
+        out.push('m');
+    }
+}
+
+/// `base` is 30 for the foreground, 40 for the background.
+fn push_sgr_color(color: Color, base: u8, out: &mut String) {
+    let _ = match color {
+        Color::Default => Ok(()),
+        Color::Indexed(idx) if idx < 8 => write!(out, ";{}", base + idx),
+        Color::Indexed(idx) if idx < 16 => write!(out, ";{}", base + 52 + idx),
+        Color::Indexed(idx) => write!(out, ";{};5;{}", base + 8, idx),
+        Color::Rgb(r, g, b) => write!(out, ";{};2;{};{};{}", base + 8, r, g, b),
+    };
+}
+
+fn color_bits(color: Color) -> u64 {
+    match color {
+        Color::Indexed(idx) => idx as u64,
+        Color::Rgb(r, g, b) => 1 << 24 | (r as u64) << 16 | (g as u64) << 8 | b as u64,
+        Color::Default => 2 << 24,
+    }
+}
+
+fn color_from_bits(bits: u64) -> Color {
+    match bits >> 24 & 0x3 {
+        0 => Color::Indexed(bits as u8),
+        1 => Color::Rgb((bits >> 16) as u8, (bits >> 8) as u8, bits as u8),
+        _ => Color::Default,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub col: usize,
+    pub text: String,
+    /// Columns covered: the chars, or twice them for wide chars
+    pub cells: usize,
+    pub style: Style,
+    /// OSC 8 link id, 0 for none
+    pub link: u32,
+}
+
+impl Run {
+    /// Columns each char takes.
+    pub fn char_width(&self) -> usize {
+        match self.text.chars().count() {
+            0 => 1,
+            chars => (self.cells / chars).max(1),
+        }
+    }
+
+    /// Each char with the column it starts at.
+    pub fn columns(&self) -> impl Iterator<Item = (usize, char)> + '_ {
+        let width = self.char_width();
+        self.text
+            .chars()
+            .enumerate()
+            .map(move |(i, ch)| (self.col + i * width, ch))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Line {
+    pub attr: LineAttr,
+    pub runs: Vec<Run>,
+}
+
+impl Line {
+    pub(crate) fn of_cells(attr: LineAttr, cells: &[Cell]) -> Line {
+        Line {
+            attr,
+            runs: runs_of(cells),
+        }
+    }
+
+    /// Append the `line` production of the wire format.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        self.encode_with(buf, |buf, style| {
+            encode_color(buf, style.fg);
+            encode_color(buf, style.bg);
+            buf.push(style.attrs);
+        });
+    }
+
+    /// `encode` with each run's `style` written by `style` instead, for
+    /// formats that refer to styles by id (see `styles`).
+    pub(crate) fn encode_with(
+        &self,
+        buf: &mut Vec<u8>,
+        mut style: impl FnMut(&mut Vec<u8>, Style),
+    ) {
+        buf.push(self.attr as u8);
+        write_varint(buf, self.runs.len());
+        for run in &self.runs {
+            write_varint(buf, run.col);
+            write_varint(buf, run.text.len());
+            write_varint(buf, run.cells << 1 | if run.link != 0 { EXTENT_LINK } else { 0 });
+            if run.link != 0 {
+                write_varint(buf, run.link as usize);
+            }
+            style(buf, run.style);
+            buf.extend_from_slice(run.text.as_bytes());
+        }
+    }
+}
+
+/// Cursor shape set by DECSCUSR (`CSI Ps SP q`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorShape {
+    #[default]
+    Block = 0,
+    Underline = 1,
+    Bar = 2,
+}
+
+impl CursorShape {
+    /// Shape for a wire value; unknown values decode as the default.
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => CursorShape::Underline,
+            2 => CursorShape::Bar,
+            _ => CursorShape::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub col: usize,
+    pub row: usize,
+    pub visible: bool,
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor {
+            col: 0,
+            row: 0,
+            visible: true,
+            shape: CursorShape::Block,
+            blink: true,
+        }
+    }
+}
+
+impl Cursor {
+    /// `cursor_flags` of the wire format.
+    pub fn flags(&self) -> u8 {
+        let mut flags = (self.shape as u8) << CURSOR_SHAPE_SHIFT;
+        if self.visible {
+            flags |= CURSOR_VISIBLE;
+        }
+        if !self.blink {
+            flags |= CURSOR_STEADY;
+        }
+        flags
+    }
+
+    /// A cursor at `col`, `row` described by `flags`.
+    pub fn with_flags(col: usize, row: usize, flags: u8) -> Self {
+        Cursor {
+            col,
+            row,
+            visible: flags & CURSOR_VISIBLE != 0,
+            shape: CursorShape::from_u8((flags >> CURSOR_SHAPE_SHIFT) & 0x03),
+            blink: flags & CURSOR_STEADY == 0,
+        }
+    }
+}
+
+/// `cursor_flags` for `cursor` on the alternate screen or not.
+pub(crate) fn header_flags(cursor: &Cursor, alt_screen: bool) -> u8 {
+    cursor.flags() | if alt_screen { ALT_SCREEN } else { 0 }
+}
+
+/// A decoded snapshot: what a client sees after `vtSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor: Cursor,
+    pub lines: Vec<Line>,
+    /// (id, URI) of the links the runs use, by id
+    pub links: Vec<(u32, String)>,
+    /// The alternate screen is shown
+    pub alt_screen: bool,
+    /// Inline images shown, see `images`
+    pub images: Vec<ImagePlacement>,
+}
+
+/// Part of an inline image on screen, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImagePlacement {
+    pub id: u32,
+    pub col: usize,
+    pub row: usize,
+    pub rows: usize,
+    /// The image's size in cells
+    pub cols: usize,
+    /// The image's cell row shown at `row`
+    pub image_row: usize,
+    pub image_rows: usize,
+}
+
+impl ImagePlacement {
+    /// As an `image` of the image table.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, self.id as usize);
+        for value in [
+            self.col,
+            self.row,
+            self.rows,
+            self.cols,
+            self.image_row,
+            self.image_rows,
+        ] {
+            write_varint(buf, value);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Input ended at `offset` in the middle of a field
+    Truncated { offset: usize },
+    /// Varint starting at `offset` doesn't fit in 32 bits
+    VarintOverflow { offset: usize },
+    /// Leading version byte of a versioned format (see `events`) this
+    /// decoder doesn't know
+    UnsupportedVersion { version: u8 },
+    /// Style table update starting at id `found` when the client holds
+    /// `expected` styles of that epoch, so some were missed (see `styles`)
+    StyleGap { expected: usize, found: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { offset } => write!(f, "truncated at offset {}", offset),
+            DecodeError::VarintOverflow { offset } => write!(f, "varint overflow at offset {}", offset),
+            DecodeError::UnsupportedVersion { version } => write!(f, "unsupported version {}", version),
+            DecodeError::StyleGap { expected, found } => {
+                write!(f, "style update starts at {}, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Screen {
+    /// Capture the visible screen of `backend`.
+    pub fn capture(backend: &impl TerminalBackend, line_attrs: &LineAttrs) -> Screen {
+        Screen::capture_with(backend, line_attrs, |_, _| {})
+    }
+
+    /// `capture`, letting `overlay` edit each row's cells first.
+    pub(crate) fn capture_with(
+        backend: &impl TerminalBackend,
+        line_attrs: &LineAttrs,
+        mut overlay: impl FnMut(usize, &mut [Cell]),
+    ) -> Screen {
+        let (cols, rows) = backend.size();
+        let mut cells = Vec::with_capacity(cols);
+        let lines = (0..rows)
+            .map(|row| {
+                backend.row_cells(row, &mut cells);
+                overlay(row, &mut cells);
+                Line::of_cells(line_attrs.get(row), &cells)
+            })
+            .collect();
+
+        Screen {
+            cols,
+            rows,
+            cursor: backend.cursor(),
+            lines,
+            links: Vec::new(),
+            alt_screen: false,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    /// `encode`, appending to `buf`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        self.encode_header(buf);
+        for line in &self.lines {
+            line.encode(buf);
+        }
+        self.encode_tables(buf);
+    }
+
+    /// The link table and any image table, the fields after the lines.
+    pub(crate) fn encode_tables(&self, buf: &mut Vec<u8>) {
+        encode_link_table(&self.links, buf);
+        if self.images.is_empty() {
+            return;
+        }
+        write_varint(buf, self.images.len());
+        for image in &self.images {
+            image.encode(buf);
+        }
+    }
+
+    /// Size and cursor, the fields before the lines.
+    pub(crate) fn encode_header(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, self.cols);
+        write_varint(buf, self.rows);
+        write_varint(buf, self.cursor.col);
+        write_varint(buf, self.cursor.row);
+        buf.push(header_flags(&self.cursor, self.alt_screen));
+    }
+
+    /// Structured form for debugging and external tools. Colors are `null`
+    /// (default), a palette index, or `"#rrggbb"`; `modes` is included as
+    /// given.
+    pub fn to_json(&self, modes: &[(&str, bool)]) -> Value {
+        let number = |n: usize| Value::Number(n as f64);
+        let entry = |key: &str, value| (key.to_string(), value);
+        let color = |color| match color {
+            Color::Default => Value::Null,
+            Color::Indexed(idx) => Value::Number(idx as f64),
+            Color::Rgb(r, g, b) => Value::String(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+        };
+
+        let lines = self
+            .lines
+            .iter()
+            .map(|line| {
+                let runs = line
+                    .runs
+                    .iter()
+                    .map(|run| {
+                        let attrs = ATTR_NAMES
+                            .iter()
+                            .enumerate()
+                            .filter(|&(bit, _)| run.style.attrs & (1 << bit) != 0)
+                            .map(|(_, name)| Value::String(name.to_string()))
+                            .collect();
+                        let mut fields = vec![
+                            entry("col", number(run.col)),
+                            entry("text", Value::String(run.text.clone())),
+                            entry("cells", number(run.cells)),
+                            entry("fg", color(run.style.fg)),
+                            entry("bg", color(run.style.bg)),
+                            entry("attrs", Value::Array(attrs)),
+                        ];
+                        if run.link != 0 {
+                            fields.push(entry("link", number(run.link as usize)));
+                        }
+                        Value::Object(fields)
+                    })
+                    .collect();
+                Value::Object(vec![
+                    entry("attr", Value::String(LINE_ATTR_NAMES[line.attr as usize].to_string())),
+                    entry("runs", Value::Array(runs)),
+                ])
+            })
+            .collect();
+
+        let mut fields = vec![
+            entry("cols", number(self.cols)),
+            entry("rows", number(self.rows)),
+            entry(
+                "cursor",
+                Value::Object(vec![
+                    entry("col", number(self.cursor.col)),
+                    entry("row", number(self.cursor.row)),
+                    entry("visible", Value::Bool(self.cursor.visible)),
+                ]),
+            ),
+            entry(
+                "modes",
+                Value::Object(
+                    modes
+                        .iter()
+                        .map(|&(name, on)| entry(name, Value::Bool(on)))
+                        .collect(),
+                ),
+            ),
+            entry("lines", Value::Array(lines)),
+        ];
+        if !self.links.is_empty() {
+            let links = self
+                .links
+                .iter()
+                .map(|(id, uri)| entry(&id.to_string(), Value::String(uri.clone())))
+                .collect();
+            fields.push(entry("links", Value::Object(links)));
+        }
+        Value::Object(fields)
+    }
+}
+
+/// Attribute bits that show on a blank cell
+const BLANK_VISIBLE_ATTRS: u8 = ATTR_UNDERLINE | ATTR_STRIKETHROUGH | ATTR_INVERSE | ATTR_PREDICTED;
+
+/// What a blank cell in `style` looks like: the color it fills with and
+/// the attributes that draw on it. Foreground, bold, italic and blink
+/// don't show without a glyph.
+fn blank_look(style: &Style) -> (Color, u8) {
+    let fill = if style.attrs & ATTR_INVERSE != 0 { style.fg } else { style.bg };
+    (fill, style.attrs & BLANK_VISIBLE_ATTRS)
+}
+
+fn is_blank(text: &str) -> bool {
+    text.bytes().all(|b| b == b' ')
+}
+
+/// Split a row into runs of cells sharing a style, coalescing runs that
+/// only differ where it can't be seen: a blank takes the style of the run
+/// before it, and a blank run that of the cell after it, when they look
+/// the same (see `blank_look`). Syntax highlighting would otherwise split
+/// a line at every space between tokens.
+///
+/// Wide and narrow chars never share a run, and the second column of a
+/// wide char is skipped, whatever its cell holds.
+fn runs_of(cells: &[Cell]) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    // Char width of the last run, and the first column not yet covered
+    let mut run_width = 0;
+    let mut next_col = 0;
+
+    for (col, cell) in cells.iter().enumerate() {
+        if col < next_col {
+            continue;
+        }
+        let width = (cell.width as usize).max(1);
+        next_col = col + width;
+
+        let blank = cell.ch == ' ';
+        match runs.last_mut() {
+            Some(run) if run_width == width && run.link == cell.link && run.style == cell.style => {
+                run.text.push(cell.ch);
+                run.cells += width;
+            }
+            Some(run)
+                if run_width == width
+                    && run.link == cell.link
+                    && (blank || is_blank(&run.text))
+                    && blank_look(&run.style) == blank_look(&cell.style) =>
+            {
+                if !blank {
+                    run.style = cell.style;
+                }
+                run.text.push(cell.ch);
+                run.cells += width;
+            }
+            _ => {
+                run_width = width;
+                runs.push(Run {
+                    col,
+                    text: cell.ch.to_string(),
+                    cells: width,
+                    style: cell.style,
+                    link: cell.link,
+                });
+            }
+        }
+    }
+
+    runs
+}
+
+/// `link_count link*` for `links`.
+pub(crate) fn encode_link_table(links: &[(u32, String)], buf: &mut Vec<u8>) {
+    write_varint(buf, links.len());
+    for (id, uri) in links {
+        write_varint(buf, *id as usize);
+        write_varint(buf, uri.len());
+        buf.extend_from_slice(uri.as_bytes());
+    }
+}
+
+fn encode_color(buf: &mut Vec<u8>, color: Color) {
+    match color {
+        Color::Indexed(idx) => buf.extend_from_slice(&[COLOR_INDEXED, idx]),
+        Color::Rgb(r, g, b) => buf.extend_from_slice(&[COLOR_RGB, r, g, b]),
+        Color::Default => buf.push(COLOR_DEFAULT),
+    }
+}
+
+/// Cursor over an encoded payload.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, DecodeError> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(DecodeError::Truncated { offset: self.pos })?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<usize, DecodeError> {
+        let start = self.pos;
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(DecodeError::VarintOverflow { offset: start });
+            }
+        }
+        if value > u32::MAX as u64 {
+            return Err(DecodeError::VarintOverflow { offset: start });
+        }
+        Ok(value as usize)
+    }
+
+    /// A varint of up to 64 bits, for times and other counts that can
+    /// outgrow `varint`.
+    pub(crate) fn varint_u64(&mut self) -> Result<u64, DecodeError> {
+        let start = self.pos;
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(DecodeError::VarintOverflow { offset: start });
+            }
+        }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or(DecodeError::Truncated { offset: self.bytes.len() })?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub(crate) fn line(&mut self) -> Result<Line, DecodeError> {
+        self.line_with(|r| {
+            Ok(Style {
+                fg: r.color()?,
+                bg: r.color()?,
+                attrs: r.byte()?,
+            })
+        })
+    }
+
+    /// `line`, with each run's style read by `style`.
+    pub(crate) fn line_with(
+        &mut self,
+        mut style: impl FnMut(&mut Self) -> Result<Style, DecodeError>,
+    ) -> Result<Line, DecodeError> {
+        let attr = match self.byte()? {
+            1 => LineAttr::DoubleWidth,
+            2 => LineAttr::DoubleHeightTop,
+            3 => LineAttr::DoubleHeightBottom,
+            _ => LineAttr::Single,
+        };
+        let run_count = self.varint()?;
+        let mut runs = Vec::with_capacity(run_count.min(self.remaining()));
+        for _ in 0..run_count {
+            let col = self.varint()?;
+            let len = self.varint()?;
+            let extent = self.varint()?;
+            let link = if extent & EXTENT_LINK != 0 { self.varint()? as u32 } else { 0 };
+            let style = style(self)?;
+            let text = String::from_utf8_lossy(self.take(len)?).into_owned();
+            runs.push(Run {
+                col,
+                text,
+                cells: extent >> 1,
+                style,
+                link,
+            });
+        }
+        Ok(Line { attr, runs })
+    }
+
+    /// An `image` of the image table.
+    pub(crate) fn image_placement(&mut self) -> Result<ImagePlacement, DecodeError> {
+        Ok(ImagePlacement {
+            id: self.varint()? as u32,
+            col: self.varint()?,
+            row: self.varint()?,
+            rows: self.varint()?,
+            cols: self.varint()?,
+            image_row: self.varint()?,
+            image_rows: self.varint()?,
+        })
+    }
+
+    /// A link table, `link_count link*`.
+    pub(crate) fn links(&mut self) -> Result<Vec<(u32, String)>, DecodeError> {
+        let count = self.varint()?;
+        let mut links = Vec::with_capacity(count.min(self.remaining() / 2));
+        for _ in 0..count {
+            let id = self.varint()? as u32;
+            let len = self.varint()?;
+            links.push((id, String::from_utf8_lossy(self.take(len)?).into_owned()));
+        }
+        Ok(links)
+    }
+
+    fn color(&mut self) -> Result<Color, DecodeError> {
+        Ok(match self.byte()? {
+            COLOR_INDEXED => Color::Indexed(self.byte()?),
+            COLOR_RGB => Color::Rgb(self.byte()?, self.byte()?, self.byte()?),
+            _ => Color::Default,
+        })
+    }
+}
+
+/// Decode a snapshot produced by `vtSnapshot`.
+pub fn decode(bytes: &[u8]) -> Result<Screen, DecodeError> {
+    let (_, bytes) = protocol::split_snapshot(bytes)?;
+    decode_with(&mut Reader::new(bytes), Reader::line)
+}
+
+/// `decode` from `r`, with lines read by `line`.
+pub(crate) fn decode_with<'a>(
+    r: &mut Reader<'a>,
+    mut line: impl FnMut(&mut Reader<'a>) -> Result<Line, DecodeError>,
+) -> Result<Screen, DecodeError> {
+    let cols = r.varint()?;
+    let rows = r.varint()?;
+    let (col, row) = (r.varint()?, r.varint()?);
+    let flags = r.byte()?;
+
+    // Every line takes at least two bytes, which bounds the allocation
+    let mut lines = Vec::with_capacity(rows.min(r.remaining() / 2));
+    for _ in 0..rows {
+        lines.push(line(r)?);
+    }
+    let links = r.links()?;
+    let mut images = Vec::new();
+    if r.remaining() > 0 {
+        let count = r.varint()?;
+        images.reserve(count.min(r.remaining() / 7));
+        for _ in 0..count {
+            images.push(r.image_placement()?);
+        }
+    }
+
+    Ok(Screen {
+        cols,
+        rows,
+        cursor: Cursor::with_flags(col, row, flags),
+        lines,
+        links,
+        alt_screen: flags & ALT_SCREEN != 0,
+        images,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Screen {
+        Screen {
+            cols: 8,
+            rows: 2,
+            cursor: Cursor {
+                col: 3,
+                row: 1,
+                visible: false,
+                ..Cursor::default()
+            },
+            lines: vec![
+                Line {
+                    attr: LineAttr::DoubleWidth,
+                    runs: vec![
+                        Run {
+                            col: 0,
+                            text: "hi".to_string(),
+                            cells: 2,
+                            style: Style {
+                                fg: Color::Indexed(1),
+                                bg: Color::Default,
+                                attrs: ATTR_BOLD | ATTR_INVERSE,
+                            },
+                            link: 0,
+                        },
+                        Run {
+                            col: 2,
+                            text: "\u{65e5}\u{672c}".to_string(),
+                            cells: 4,
+                            style: Style {
+                                fg: Color::Rgb(1, 2, 3),
+                                bg: Color::Indexed(232),
+                                attrs: 0,
+                            },
+                            link: 3,
+                        },
+                    ],
+                },
+                Line::default(),
+            ],
+            links: vec![(3, "https://example.com/".to_string())],
+            alt_screen: true,
+            images: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let screen = sample();
+        assert_eq!(decode(&screen.encode()).unwrap(), screen);
+    }
+
+    #[test]
+    fn cursor_flags_carry_shape_and_blink() {
+        assert_eq!(Cursor::default().flags(), CURSOR_VISIBLE);
+        let bar = Cursor {
+            col: 4,
+            row: 2,
+            visible: false,
+            shape: CursorShape::Bar,
+            blink: false,
+        };
+        assert_eq!(bar.flags(), 0x0c);
+        assert_eq!(Cursor::with_flags(4, 2, bar.flags()), bar);
+        // Shape 3 is unassigned
+        assert_eq!(Cursor::with_flags(0, 0, 0x07).shape, CursorShape::Block);
+    }
+
+    #[test]
+    fn every_truncation_is_rejected() {
+        let bytes = sample().encode();
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(decode(&bytes[..len]), Err(DecodeError::Truncated { .. })),
+                "prefix of {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn lenient_where_clients_are() {
+        let mut bytes = sample().encode();
+        // line attr of row 0 sits right after the 5-byte header
+        bytes[5] = 9;
+        // garbage past the (empty) image table is ignored
+        bytes.extend_from_slice(&[0, 0xff, 0xff]);
+        let screen = decode(&bytes).unwrap();
+        assert_eq!(screen.lines[0].attr, LineAttr::Single);
+
+        // unknown color tag decodes as default; invalid UTF-8 is replaced
+        let bytes = [1, 1, 0, 0, 1, 0, 1, 0, 1, 2, 7, 2, 0, 0xff, 0];
+        let screen = decode(&bytes).unwrap();
+        let run = &screen.lines[0].runs[0];
+        assert_eq!(run.style.fg, Color::Default);
+        assert_eq!(run.text, "\u{FFFD}");
+    }
+
+    #[test]
+    fn json_dump_names_styles_and_modes() {
+        let json = sample().to_json(&[("cursor_key_app", true)]).to_string();
+        assert!(json.starts_with(
+            "{\"cols\": 8, \"rows\": 2, \"cursor\": {\"col\": 3, \"row\": 1, \"visible\": false}, \
+             \"modes\": {\"cursor_key_app\": true}, \"lines\": [{\"attr\": \"double-width\""
+        ));
+        assert!(json.contains("\"fg\": 1, \"bg\": null, \"attrs\": [\"bold\", \"inverse\"]"));
+        assert!(json.contains("\"fg\": \"#010203\", \"bg\": 232, \"attrs\": []"));
+        assert!(json.ends_with(
+            "{\"attr\": \"single\", \"runs\": []}], \"links\": {\"3\": \"https://example.com/\"}}"
+        ));
+        assert!(crate::json::parse(&json).is_ok());
+    }
+
+    #[test]
+    fn rejects_oversized_varint() {
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0x7f, 1, 0, 0, 1];
+        assert_eq!(decode(&bytes), Err(DecodeError::VarintOverflow { offset: 0 }));
+    }
+
+    #[test]
+    fn huge_counts_do_not_preallocate() {
+        // rows = u32::MAX with no line data
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0xff, 0x0f, 0, 0, 1];
+        assert!(matches!(decode(&bytes[..5]), Err(DecodeError::Truncated { .. })));
+        assert!(matches!(decode(&bytes), Err(DecodeError::Truncated { .. })));
+    }
+
+    #[test]
+    fn blanks_coalesce_into_neighbouring_runs() {
+        let fg = |color, attrs| Style {
+            fg: Color::Indexed(color),
+            bg: Color::Default,
+            attrs,
+        };
+        let keyword = fg(1, ATTR_BOLD);
+        let name = fg(4, 0);
+        let link = fg(4, ATTR_UNDERLINE);
+        let cells: Vec<Cell> = [
+            ('i', keyword),
+            ('f', keyword),
+            (' ', Style::default()),
+            ('x', name),
+            (' ', fg(2, 0)),
+            (' ', fg(3, 0)),
+            ('y', keyword),
+            (' ', link),
+            ('z', fg(5, ATTR_UNDERLINE)),
+        ]
+        .iter()
+        .map(|&(ch, style)| Cell {
+            ch,
+            style,
+            width: 1,
+            link: 0,
+        })
+        .collect();
+
+        let runs: Vec<_> = Line::of_cells(LineAttr::Single, &cells)
+            .runs
+            .into_iter()
+            .map(|run| (run.col, run.text, run.style))
+            .collect();
+        // An underlined blank shows, so it doesn't join the plain run
+        // before it, but takes the following glyph's color
+        assert_eq!(
+            runs,
+            [
+                (0, "if ".to_string(), keyword),
+                (3, "x  ".to_string(), name),
+                (6, "y".to_string(), keyword),
+                (7, " z".to_string(), fg(5, ATTR_UNDERLINE)),
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_chars_get_runs_of_their_own() {
+        let cell = |ch, width| Cell {
+            ch,
+            style: Style::default(),
+            width,
+            link: 0,
+        };
+        let cells = [
+            cell('a', 1),
+            cell('\u{4e2d}', 2),
+            cell(' ', 0),
+            cell('\u{6587}', 2),
+            cell(' ', 0),
+            cell('b', 1),
+        ];
+        let line = Line::of_cells(LineAttr::Single, &cells);
+        let runs: Vec<_> = line
+            .runs
+            .iter()
+            .map(|run| (run.col, run.text.as_str(), run.cells))
+            .collect();
+        assert_eq!(runs, [(0, "a", 1), (1, "\u{4e2d}\u{6587}", 4), (5, "b", 1)]);
+        let cols: Vec<_> = line.runs[1].columns().map(|(col, _)| col).collect();
+        assert_eq!(cols, [1, 3]);
+
+        let mut buf = Vec::new();
+        line.encode(&mut buf);
+        assert_eq!(Reader::new(&buf).line().unwrap(), line);
+    }
+}
@@ -0,0 +1,490 @@
+//! Where a streamed cast's bytes come from, for recordings that aren't a
+//! plain file the native side can open.
+//!
+//! A `CastSource` is random-access storage: `read_at` an offset, the
+//! `len` so far, and `poll_append` for a recording that is still being
+//! written or downloaded. `SourceReader` reads one front to back for
+//! `CastReader`, asking `poll_append` when it reaches the end it knew of,
+//! so `castFileNextEvents` carries on into appended lines.
+//!
+//! Implemented for a file descriptor (`FdSource`, also over the window of
+//! an uncompressed asset's `AssetFileDescriptor`), a read-only mapping of
+//! one (`MappedSource`), bytes in memory, and an `AvtCastSource` object
+//! the app provides (`JavaSource`). The last is how recordings in
+//! encrypted app storage are streamed, decrypted block by block in
+//! Kotlin, with no plaintext copy on disk, and how HTTP is read too: the
+//! app's client does the range requests, with its TLS and credentials.
+
+use crate::cast::CastError;
+use crate::castfile::{into_handle, CastFile};
+use jni::objects::{GlobalRef, JByteArray, JClass, JObject, JValue};
+use jni::sys::{jint, jlong};
+use jni::{JNIEnv, JavaVM};
+use std::io::{self, BufReader, Read};
+
+/// Bytes read from a source at a time
+pub const READ_SIZE: usize = 64 * 1024;
+
+pub trait CastSource {
+    /// Read bytes at `offset` into `buf`, returning how many; 0 at the end.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Bytes in the source as of now.
+    fn len(&mut self) -> io::Result<u64>;
+
+    fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The new length, if the source grew since `len` or `poll_append`
+    /// last reported it. Sources that can't grow keep the default.
+    fn poll_append(&mut self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+impl CastSource for Box<[u8]> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(copy_at(self, offset, buf))
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+}
+
+pub(crate) fn copy_at(bytes: &[u8], offset: u64, buf: &mut [u8]) -> usize {
+    let rest = &bytes[offset.min(bytes.len() as u64) as usize..];
+    let n = rest.len().min(buf.len());
+    buf[..n].copy_from_slice(&rest[..n]);
+    n
+}
+
+/// A source read front to back, one `read_at` per `read`.
+pub struct SourceReader<S> {
+    source: S,
+    pos: u64,
+    len: u64,
+}
+
+impl<S: CastSource> SourceReader<S> {
+    pub fn new(mut source: S) -> io::Result<Self> {
+        let len = source.len()?;
+        Ok(SourceReader {
+            source,
+            pos: 0,
+            len,
+        })
+    }
+}
+
+impl<S: CastSource> Read for SourceReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len {
+            match self.source.poll_append()? {
+                Some(len) if len > self.pos => self.len = len,
+                _ => return Ok(0),
+            }
+        }
+        let left = usize::try_from(self.len - self.pos).unwrap_or(usize::MAX);
+        let n = buf.len().min(left);
+        let n = self.source.read_at(self.pos, &mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// A `CastFile` streaming `source`. Fails as `CastFile::new` does, or if
+/// the source's length can't be read.
+pub fn open(source: impl CastSource + 'static) -> Result<CastFile, CastError> {
+    let reader = SourceReader::new(source).map_err(|e| CastError::Io(e.kind()))?;
+    CastFile::new(Box::new(BufReader::with_capacity(READ_SIZE, reader)))
+}
+
+/// A file descriptor, whole or a window of it.
+#[cfg(unix)]
+pub struct FdSource {
+    file: std::fs::File,
+    start: u64,
+    /// The window's length; `None` for the rest of the file, which grows
+    /// as it's appended to
+    window: Option<u64>,
+    seen: u64,
+}
+
+#[cfg(unix)]
+impl FdSource {
+    pub fn new(file: std::fs::File) -> Self {
+        FdSource {
+            file,
+            start: 0,
+            window: None,
+            seen: 0,
+        }
+    }
+
+    /// `len` bytes of `file` from `start`, as an `AssetFileDescriptor`
+    /// gives for an uncompressed asset.
+    pub fn range(file: std::fs::File, start: u64, len: u64) -> Self {
+        FdSource {
+            file,
+            start,
+            window: Some(len),
+            seen: len,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl CastSource for FdSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let left = self
+            .window
+            .map_or(u64::MAX, |len| len.saturating_sub(offset));
+        let n = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        let Some(at) = self.start.checked_add(offset) else {
+            return Ok(0);
+        };
+        self.file.read_at(&mut buf[..n], at)
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        if self.window.is_none() {
+            self.seen = self.file.metadata()?.len().saturating_sub(self.start);
+        }
+        Ok(self.seen)
+    }
+
+    fn poll_append(&mut self) -> io::Result<Option<u64>> {
+        let before = self.seen;
+        let now = self.len()?;
+        Ok((now > before).then_some(now))
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_void;
+    use std::os::raw::{c_int, c_long};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+/// A file mapped read-only, its length as when mapped. Pages are read as
+/// the parser reaches them rather than copied through a buffer first.
+#[cfg(unix)]
+pub struct MappedSource {
+    addr: *mut std::ffi::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl MappedSource {
+    pub fn new(file: &std::fs::File) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        if len == 0 {
+            // mmap refuses an empty mapping
+            return Ok(MappedSource {
+                addr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        let fd = file.as_raw_fd();
+        let addr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                fd,
+                0,
+            )
+        };
+        // MAP_FAILED
+        if addr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(MappedSource { addr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.addr.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl CastSource for MappedSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(copy_at(self.bytes(), offset, buf))
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.len as u64)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedSource {
+    fn drop(&mut self) {
+        if !self.addr.is_null() {
+            unsafe { sys::munmap(self.addr, self.len) };
+        }
+    }
+}
+
+/// An `AvtCastSource` from the app, called on whichever thread reads the
+/// cast file. A call that throws fails the read, its exception cleared.
+pub struct JavaSource {
+    vm: JavaVM,
+    source: GlobalRef,
+}
+
+impl JavaSource {
+    pub fn new(env: &JNIEnv, source: &JObject) -> jni::errors::Result<Self> {
+        Ok(JavaSource {
+            vm: env.get_java_vm()?,
+            source: env.new_global_ref(source)?,
+        })
+    }
+
+    fn call<T>(
+        &self,
+        f: impl FnOnce(&mut JNIEnv, &GlobalRef) -> jni::errors::Result<T>,
+    ) -> io::Result<T> {
+        let mut env = self
+            .vm
+            .attach_current_thread()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        f(&mut env, &self.source).map_err(|e| {
+            let _ = env.exception_clear();
+            io::Error::other(e.to_string())
+        })
+    }
+}
+
+impl CastSource for JavaSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let Ok(offset) = jlong::try_from(offset) else {
+            return Ok(0);
+        };
+        let max = buf.len().min(READ_SIZE) as jint;
+        let bytes = self.call(|env, source| {
+            let args = [JValue::Long(offset), JValue::Int(max)];
+            let array = env.call_method(source, "readAt", "(JI)[B", &args)?.l()?;
+            env.convert_byte_array(JByteArray::from(array))
+        })?;
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        Ok(n)
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        let len = self.call(|env, source| env.call_method(source, "length", "()J", &[])?.j())?;
+        u64::try_from(len).map_err(|_| io::ErrorKind::InvalidData.into())
+    }
+
+    fn poll_append(&mut self) -> io::Result<Option<u64>> {
+        let len =
+            self.call(|env, source| env.call_method(source, "pollAppend", "()J", &[])?.j())?;
+        Ok(u64::try_from(len).ok())
+    }
+}
+
+// JNI functions
+
+/// A cast file handle, as from `castFileOpen`, streaming the app's
+/// `AvtCastSource`. Returns 0 if `source` is null, or its header can't be
+/// read or is invalid.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenSource<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    source: JObject<'a>,
+) -> jlong {
+    jni_guard!(env, {
+        if source.is_null() {
+            return 0;
+        }
+        let Ok(source) = JavaSource::new(&env, &source) else {
+            return 0;
+        };
+
+        into_handle(open(source))
+    })
+}
+
+/// A cast file handle over `length` bytes of `fd` from `offset`, or to the
+/// end of the file, reading on as it's appended to, for a negative
+/// `length`. The handle owns `fd`, which is closed on failure too.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenRange(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+    offset: jlong,
+    length: jlong,
+) -> jlong {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let Ok(offset) = u64::try_from(offset) else {
+            return 0;
+        };
+
+        let source = match u64::try_from(length) {
+            Ok(length) => FdSource::range(file, offset, length),
+            Err(_) => FdSource {
+                start: offset,
+                ..FdSource::new(file)
+            },
+        };
+        into_handle(open(source))
+    })
+}
+
+/// A cast file handle over `fd` mapped into memory, as it was when opened.
+/// `fd` is closed once mapped, or on failure.
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castOpenMapped(
+    mut env: JNIEnv,
+    _class: JClass,
+    fd: jint,
+) -> jlong {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        if fd < 0 {
+            return 0;
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let Ok(source) = MappedSource::new(&file) else {
+            return 0;
+        };
+
+        into_handle(open(source))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::castfile::CastReader;
+    use std::io::Write;
+
+    const CAST: &[u8] = b"{\"version\": 2, \"width\": 8, \"height\": 2}\n\
+                          [0.5, \"o\", \"ab\"]\n\
+                          [1.0, \"o\", \"cd\"]\n";
+
+    /// `CAST` up to the first event, then all of it once polled.
+    struct Growing {
+        len: u64,
+    }
+
+    impl CastSource for Growing {
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            Ok(copy_at(&CAST[..self.len as usize], offset, buf))
+        }
+
+        fn len(&mut self) -> io::Result<u64> {
+            Ok(self.len)
+        }
+
+        fn poll_append(&mut self) -> io::Result<Option<u64>> {
+            let grew = self.len < CAST.len() as u64;
+            self.len = CAST.len() as u64;
+            Ok(grew.then_some(self.len))
+        }
+    }
+
+    #[test]
+    fn sources_read_on_into_appended_lines() {
+        let first = CAST.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let with_event = first + CAST[first..].iter().position(|&b| b == b'\n').unwrap() + 1;
+        let mut reader = SourceReader::new(Growing {
+            len: with_event as u64,
+        })
+        .unwrap();
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, CAST);
+
+        let events = |source| {
+            let reader = SourceReader::new(source).unwrap();
+            CastReader::new(BufReader::new(reader)).unwrap().count()
+        };
+        assert_eq!(events(Box::<[u8]>::from(CAST)), 2);
+        assert_eq!(
+            CastReader::new(BufReader::new(
+                SourceReader::new(Growing { len: 0 }).unwrap()
+            ))
+            .unwrap()
+            .count(),
+            2
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_read_whole_in_windows_and_mapped() {
+        let path = std::env::temp_dir().join(format!("source-test-{}", std::process::id()));
+        let padded = [&b"junk"[..], CAST, b"more junk"].concat();
+        std::fs::write(&path, &padded).unwrap();
+        let contents = |source: &mut dyn CastSource| {
+            let mut buf = vec![0; source.len().unwrap() as usize];
+            let mut at = 0;
+            while at < buf.len() {
+                let n = source.read_at(at as u64, &mut buf[at..]).unwrap();
+                assert!(n > 0);
+                at += n;
+            }
+            buf
+        };
+
+        let file = || std::fs::File::open(&path).unwrap();
+        let mut window = FdSource::range(file(), 4, CAST.len() as u64);
+        assert_eq!(contents(&mut window), CAST);
+        assert_eq!(window.read_at(CAST.len() as u64, &mut [0; 4]).unwrap(), 0);
+        let mut whole = FdSource::new(file());
+        assert_eq!(contents(&mut whole), padded);
+        assert_eq!(whole.poll_append().unwrap(), None);
+        assert_eq!(contents(&mut MappedSource::new(&file()).unwrap()), padded);
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"!")
+            .unwrap();
+        assert_eq!(whole.poll_append().unwrap(), Some(padded.len() as u64 + 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
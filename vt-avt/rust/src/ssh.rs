@@ -0,0 +1,379 @@
+//! SSH sessions through russh, behind the `ssh` feature.
+//!
+//! The app's remote terminal: a `Session` connects, authenticates with a
+//! password or an OpenSSH private key, opens a PTY channel at the VT's
+//! size and starts the login shell. It's driven like a `net::Connection`,
+//! `pump` feeding what arrives to a VT on the caller's thread and `send`
+//! writing input and `vtTakeResponses` answers, so everything past the
+//! transport (diffs, events, activity, echo prediction) is shared.
+//!
+//! russh is async. Each session has a single-threaded tokio runtime that
+//! only runs inside these calls, so nothing touches the VT behind the
+//! caller's back, and keep-alives are only answered while it pumps.
+//!
+//! The caller passes the host key fingerprint it expects (SHA-256, as
+//! `ssh-keygen -l` prints it) and a server presenting another is refused.
+//! Without one any key is accepted, and `host_key` gives the one seen so
+//! the app can pin it on first use.
+
+use crate::backend::TerminalBackend;
+use crate::handles::{self, Kind};
+use crate::{AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jboolean, jint, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use russh::client::{self, Handle, Msg};
+use russh::keys::{self, HashAlg, PrivateKeyWithHashAlg, PublicKey};
+use russh::{Channel, ChannelMsg, Disconnect};
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::runtime::{self, Runtime};
+
+/// Terminal type announced for the PTY, which the VT emulates.
+const TERM: &str = "xterm-256color";
+
+/// Most bytes fed per `pump`, so a flood of output still returns to draw.
+const MAX_PUMP: usize = 64 * 1024;
+
+pub enum Auth<'a> {
+    Password(&'a str),
+    /// OpenSSH or PEM private key text
+    Key {
+        key: &'a str,
+        passphrase: Option<&'a str>,
+    },
+}
+
+struct Client {
+    expected: Option<String>,
+    seen: Arc<Mutex<String>>,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+        let trusted = self.expected.as_ref().is_none_or(|e| *e == fingerprint);
+        *self.seen.lock().unwrap_or_else(PoisonError::into_inner) = fingerprint;
+        Ok(trusted)
+    }
+}
+
+pub struct Session {
+    runtime: Runtime,
+    handle: Handle<Client>,
+    channel: Channel<Msg>,
+    host_key: String,
+}
+
+registered!(Session, Kind::Session);
+
+impl Session {
+    /// Connect and start a shell on a `cols`×`rows` PTY, all within
+    /// `timeout`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn connect(
+        host: &str,
+        port: u16,
+        user: &str,
+        auth: Auth,
+        host_key: Option<&str>,
+        cols: usize,
+        rows: usize,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+        let seen = Arc::new(Mutex::new(String::new()));
+        let client = Client {
+            expected: host_key.map(str::to_string),
+            seen: Arc::clone(&seen),
+        };
+
+        let setup = async {
+            let config = Arc::new(client::Config::default());
+            let mut handle = client::connect(config, (host, port), client).await.map_err(io::Error::other)?;
+            let authenticated = match auth {
+                Auth::Password(password) => handle.authenticate_password(user, password).await,
+                Auth::Key { key, passphrase } => {
+                    let key = keys::decode_secret_key(key, passphrase).map_err(io::Error::other)?;
+                    // RSA keys sign with the best hash the server takes
+                    let hash = handle.best_supported_rsa_hash().await.map_err(io::Error::other)?.flatten();
+                    let key = PrivateKeyWithHashAlg::new(Arc::new(key), hash);
+                    handle.authenticate_publickey(user, key).await
+                }
+            };
+            if !authenticated.map_err(io::Error::other)?.success() {
+                return Err(io::Error::new(ErrorKind::PermissionDenied, "authentication failed"));
+            }
+
+            let channel = handle.channel_open_session().await.map_err(io::Error::other)?;
+            channel
+                .request_pty(false, TERM, cols as u32, rows as u32, 0, 0, &[])
+                .await
+                .map_err(io::Error::other)?;
+            channel.request_shell(false).await.map_err(io::Error::other)?;
+            Ok((handle, channel))
+        };
+        let (handle, channel) = runtime
+            .block_on(async { tokio::time::timeout(timeout, setup).await })
+            .map_err(|_| io::Error::new(ErrorKind::TimedOut, "ssh setup timed out"))??;
+
+        let host_key = seen.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        Ok(Session {
+            runtime,
+            handle,
+            channel,
+            host_key,
+        })
+    }
+
+    /// SHA-256 fingerprint of the server's host key.
+    pub fn host_key(&self) -> &str {
+        &self.host_key
+    }
+
+    /// Wait up to `timeout` for output and feed what arrives to `state`,
+    /// as `net::Connection::pump`: the number of bytes fed (0 if none
+    /// arrived in time), or `None` once the channel has closed.
+    pub fn pump<B: TerminalBackend>(
+        &mut self,
+        state: &mut AvtState<B>,
+        timeout: Duration,
+    ) -> io::Result<Option<usize>> {
+        let channel = &mut self.channel;
+        self.runtime.block_on(async {
+            let mut fed = 0;
+            // Drain what's already there so one pump takes a whole burst
+            let mut wait = timeout;
+            while fed < MAX_PUMP {
+                let Ok(msg) = tokio::time::timeout(wait, channel.wait()).await else {
+                    break;
+                };
+                match msg.and_then(|msg| deliver(msg, state)) {
+                    Some(n) => fed += n,
+                    None if fed == 0 => return Ok(None),
+                    None => break,
+                }
+                wait = Duration::ZERO;
+            }
+            Ok(Some(fed))
+        })
+    }
+
+    /// Send user input (or `vtTakeResponses` answers) to the shell.
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.runtime.block_on(self.channel.data(bytes)).map_err(io::Error::other)
+    }
+
+    /// Follow the VT to a new size.
+    pub fn resize(&mut self, cols: usize, rows: usize) -> io::Result<()> {
+        self.runtime
+            .block_on(self.channel.window_change(cols as u32, rows as u32, 0, 0))
+            .map_err(io::Error::other)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self
+            .runtime
+            .block_on(self.handle.disconnect(Disconnect::ByApplication, "", "en"));
+    }
+}
+
+/// Feed a channel message's output to `state`: the bytes fed, or `None`
+/// once the channel is done. Stderr goes to the screen too, as on a
+/// local terminal.
+fn deliver<B: TerminalBackend>(msg: ChannelMsg, state: &mut AvtState<B>) -> Option<usize> {
+    match msg {
+        ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+            state.feed(&data);
+            Some(data.len())
+        }
+        ChannelMsg::Eof | ChannelMsg::Close => None,
+        _ => Some(0),
+    }
+}
+
+fn optional_string(env: &mut JNIEnv, string: &JString) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    env.get_string(string).ok().map(Into::into)
+}
+
+// JNI functions
+
+/// Connect and start a shell at the size of the VT `vt`, waiting up to
+/// `timeout_ms`. Authenticates with `private_key` (and `passphrase`) if
+/// given, else `password`; `host_key` may be null, see the module docs.
+/// Returns 0 on failure.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshConnect(
+    mut env: JNIEnv,
+    _class: JClass,
+    host: JString,
+    port: jint,
+    user: JString,
+    password: JString,
+    private_key: JString,
+    passphrase: JString,
+    host_key: JString,
+    vt: VtHandle,
+    timeout_ms: jint,
+) -> jlong {
+    jni_guard!(env, {
+        let (Some(host), Some(user)) = (optional_string(&mut env, &host), optional_string(&mut env, &user)) else {
+            return 0;
+        };
+        let Ok(port) = u16::try_from(port) else {
+            return 0;
+        };
+        let password = optional_string(&mut env, &password);
+        let private_key = optional_string(&mut env, &private_key);
+        let passphrase = optional_string(&mut env, &passphrase);
+        let host_key = optional_string(&mut env, &host_key);
+        let auth = match (&private_key, &password) {
+            (Some(key), _) => Auth::Key {
+                key,
+                passphrase: passphrase.as_deref(),
+            },
+            (None, Some(password)) => Auth::Password(password),
+            (None, None) => return 0,
+        };
+        let Some(vt) = handles::get(&mut env, vt) else {
+            return 0;
+        };
+
+        let (cols, rows) = vt.backend().size();
+        let timeout = Duration::from_millis(timeout_ms.max(1) as u64);
+        match Session::connect(&host, port, &user, auth, host_key.as_deref(), cols, rows, timeout) {
+            Ok(session) => handles::add(session),
+            Err(_) => 0,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Session>(&mut env, handle));
+    })
+}
+
+/// Bytes read into the VT, 0 on timeout, -1 once closed or failed.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshPump(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    vt: VtHandle,
+    timeout_ms: jint,
+) -> jint {
+    jni_guard!(env, {
+        let Some(session) = handles::object::<Session>(&mut env, handle) else {
+            return -1;
+        };
+        let Some(vt) = handles::get(&mut env, vt) else {
+            return -1;
+        };
+
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+        match session.pump(vt, timeout) {
+            Ok(Some(n)) => n as jint,
+            Ok(None) | Err(_) => -1,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshSend(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    bytes: JByteArray,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(session) = handles::object::<Session>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return JNI_FALSE;
+        };
+        match session.send(&bytes) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// Resize the remote PTY, after `vtResize`.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshResize(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    cols: jint,
+    rows: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(session) = handles::object::<Session>(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+        if cols <= 0 || rows <= 0 {
+            return JNI_FALSE;
+        }
+
+        match session.resize(cols as usize, rows as usize) {
+            Ok(()) => JNI_TRUE,
+            Err(_) => JNI_FALSE,
+        }
+    })
+}
+
+/// The server's host key fingerprint, empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_sshHostKey<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(session) = handles::object_ref::<Session>(&mut env, handle) else {
+            return JString::default();
+        };
+
+        env.new_string(session.host_key()).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use russh::CryptoVec;
+
+    #[test]
+    fn channel_output_is_fed_until_the_channel_closes() {
+        let mut state = AvtState::with_backend(fake(4, 1));
+        let data = CryptoVec::from_slice(b"o");
+        assert_eq!(deliver(ChannelMsg::Data { data }, &mut state), Some(1));
+        let data = CryptoVec::from_slice(b"k");
+        let stderr = ChannelMsg::ExtendedData { data, ext: 1 };
+        assert_eq!(deliver(stderr, &mut state), Some(1));
+        assert_eq!(state.backend().row_text(0), "ok  ");
+
+        let exit = ChannelMsg::ExitStatus { exit_status: 0 };
+        assert_eq!(deliver(exit, &mut state), Some(0));
+        assert_eq!(deliver(ChannelMsg::Eof, &mut state), None);
+        assert_eq!(deliver(ChannelMsg::Close, &mut state), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Stall detection: stretches where a recording only redraws in place.
+//!
+//! Long compiles and downloads show up as minutes of spinner frames or
+//! progress bar updates, each event rewriting the current line without
+//! adding any (`\r` and an erase, no line feed). Such runs are offered to
+//! the user as one-tap speed-up regions for export.
+//!
+//! A run is broken by any event that adds a line, erases the display,
+//! switches screens, or by input. Full-screen apps redraw without line
+//! feeds too, so nothing inside the alternate screen counts.
+
+use crate::cast::{micros_arg, Cast, EventKind};
+use crate::export::SpeedRegion;
+use crate::handles;
+use crate::scan::{Action, Scanner};
+use jni::objects::{JClass, JLongArray};
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+/// Fewest updates for a run to look like a spinner rather than a pause
+const MIN_REDRAWS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    /// First redraw
+    pub start_us: i64,
+    /// The event that ended the run, or the last redraw at the end of the cast
+    pub end_us: i64,
+    pub redraws: usize,
+}
+
+impl Stall {
+    /// Speed-up that plays the stall in `target_us`.
+    pub fn suggested_region(&self, target_us: i64) -> SpeedRegion {
+        SpeedRegion {
+            start_us: self.start_us,
+            end_us: self.end_us,
+            speed: (self.end_us - self.start_us) as f64 / target_us.max(1) as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// Rewrites the current line in place
+    Redraw,
+    Informative,
+}
+
+/// Runs of in-place redraws lasting longer than `min_us`.
+pub fn find_stalls(cast: &Cast, min_us: i64) -> Vec<Stall> {
+    let mut scanner = Scanner::new();
+    let mut alt_screen = false;
+    let mut stalls = Vec::new();
+    let mut run: Option<Stall> = None;
+
+    let mut close = |run: &mut Option<Stall>, end_us: Option<i64>| {
+        if let Some(mut stall) = run.take() {
+            if let Some(end_us) = end_us {
+                stall.end_us = end_us;
+            }
+            if stall.redraws >= MIN_REDRAWS && stall.end_us - stall.start_us > min_us {
+                stalls.push(stall);
+            }
+        }
+    };
+
+    for event in &cast.events {
+        let kind = match &event.kind {
+            EventKind::Output(data) => classify(&mut scanner, &mut alt_screen, data.as_bytes()),
+            EventKind::Input(_) | EventKind::Resize { .. } => Kind::Informative,
+            EventKind::Marker(_) | EventKind::Other { .. } => continue,
+        };
+
+        match (kind, &mut run) {
+            (Kind::Redraw, Some(stall)) => {
+                stall.end_us = event.time_us;
+                stall.redraws += 1;
+            }
+            (Kind::Redraw, None) => {
+                run = Some(Stall {
+                    start_us: event.time_us,
+                    end_us: event.time_us,
+                    redraws: 1,
+                });
+            }
+            (Kind::Informative, _) => close(&mut run, Some(event.time_us)),
+        }
+    }
+    close(&mut run, None);
+    stalls
+}
+
+pub(crate) fn classify(scanner: &mut Scanner, alt_screen: &mut bool, data: &[u8]) -> Kind {
+    let was_alt = *alt_screen;
+    let mut informative = false;
+    scanner.scan(data, |_, action| match action {
+        Action::Control(0x0a..=0x0c) => informative = true,
+        Action::Csi(csi) if csi.intermediates().is_empty() => match (csi.marker, csi.final_byte) {
+            (None, b'J') => informative = true,
+            (Some(b'?'), final_byte @ (b'h' | b'l'))
+                if csi.params().iter().any(|&p| matches!(p, 47 | 1047 | 1049)) =>
+            {
+                *alt_screen = final_byte == b'h';
+                informative = true;
+            }
+            _ => {}
+        },
+        Action::Esc {
+            intermediate: None,
+            final_byte: b'c',
+        } => informative = true,
+        _ => {}
+    });
+
+    if informative || was_alt || *alt_screen {
+        Kind::Informative
+    } else {
+        Kind::Redraw
+    }
+}
+
+// JNI functions
+
+/// `[startMicros, endMicros, playMicros, ...]` for each stall longer than
+/// `min_micros`, played in `min_micros`: the layout `castExport` takes.
+/// Empty for an invalid handle or time.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castFindStalls<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+    min_micros: jlong,
+) -> JLongArray<'a> {
+    jni_guard!(env, {
+        let Some(min_us) = micros_arg(min_micros) else {
+            return JLongArray::default();
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, handle) else {
+            return JLongArray::default();
+        };
+
+        let values: Vec<jlong> = find_stalls(cast, min_us)
+            .iter()
+            .flat_map(|stall| [stall.start_us, stall.end_us, min_us])
+            .collect();
+        crate::long_array(&env, &values)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_spinner_runs_but_not_full_screen_apps() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [1.0, \"o\", \"$ make\\r\\n\"]\n\
+            [2.0, \"o\", \"\\r\\u001b[K[=>   ] 10%\"]\n\
+            [20.0, \"o\", \"\\r\\u001b[K[==>  ] 40%\"]\n\
+            [40.0, \"o\", \"\\r\\u001b[K[====>] 90%\"]\n\
+            [50.0, \"o\", \"\\r\\u001b[Kdone\\r\\n\"]\n\
+            [51.0, \"o\", \"\\r-\"]\n\
+            [52.0, \"o\", \"\\r\\\\\"]\n\
+            [53.0, \"o\", \"\\r|\"]\n\
+            [54.0, \"i\", \"q\"]\n\
+            [60.0, \"o\", \"\\u001b[?1049h\"]\n\
+            [61.0, \"o\", \"\\u001b[5;1Hframe\"]\n\
+            [90.0, \"o\", \"\\u001b[5;1Hframe\"]\n\
+            [99.0, \"o\", \"\\u001b[5;1Hframe\"]\n\
+            [120.0, \"o\", \"\\u001b[?1049l\"]\n",
+        )
+        .unwrap();
+
+        let stalls = find_stalls(&cast, 10_000_000);
+        assert_eq!(
+            stalls,
+            [Stall {
+                start_us: 2_000_000,
+                end_us: 50_000_000,
+                redraws: 3,
+            }]
+        );
+        assert_eq!(find_stalls(&cast, 1_000_000).len(), 2);
+
+        let region = stalls[0].suggested_region(12_000_000);
+        assert_eq!(region.speed, 4.0);
+    }
+}
@@ -0,0 +1,307 @@
+//! Saved VT states that record which emulator made them.
+//!
+//! A `vtDumpAnsi` dump is ANSI that avt replays into a screen, so a dump
+//! cached before an app update is replayed by whatever avt the update
+//! ships, and can silently come out different. Saved states wrap the dump in a header
+//! naming the avt build and the dump format:
+//!
+//! ```text
+//! state := "AVTS" format:varint avt_len:varint avt cols rows
+//!          dump_len:varint dump
+//! ```
+//!
+//! Restoring checks the header against the running build (see `Compat`):
+//! a state from the same avt restores exactly; one from another avt with
+//! the same dump format is replayed anyway (migrated) and the caller should
+//! rebuild anything derived from it; another format is refused. Caches
+//! keyed on a state, like a keyframe index, should store its `Header` and
+//! check it the same way.
+
+use crate::backend::TerminalBackend;
+use crate::json::Value;
+use crate::snapshot::{DecodeError, Reader};
+use crate::{handles, write_varint, AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::jint;
+use jni::JNIEnv;
+use std::fmt;
+
+const MAGIC: &[u8] = b"AVTS";
+
+/// avt build the crate is compiled against; keep in step with Cargo.lock
+/// (a test checks)
+pub const AVT_VERSION: &str = "0.17.0+86302bcf";
+
+/// Version of the dump a state carries. Bump when an avt upgrade changes
+/// what its dumps mean, so older states are refused rather than misread.
+pub const DUMP_FORMAT: usize = 1;
+
+/// How a saved state relates to the running build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// Same avt: restores exactly
+    Exact,
+    /// Different avt, same dump format: restores, rendering may differ
+    Migrate,
+    /// Different dump format: refused
+    Incompatible,
+}
+
+impl Compat {
+    fn name(self) -> &'static str {
+        match self {
+            Compat::Exact => "exact",
+            Compat::Migrate => "migrate",
+            Compat::Incompatible => "incompatible",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub format: usize,
+    pub avt_version: String,
+}
+
+impl Header {
+    pub fn current() -> Header {
+        Header {
+            format: DUMP_FORMAT,
+            avt_version: AVT_VERSION.to_string(),
+        }
+    }
+
+    pub fn compat(&self) -> Compat {
+        if self.format != DUMP_FORMAT {
+            Compat::Incompatible
+        } else if self.avt_version != AVT_VERSION {
+            Compat::Migrate
+        } else {
+            Compat::Exact
+        }
+    }
+
+    /// `{"format", "avt", "current_format", "current_avt", "compat"}`
+    pub fn to_json(&self) -> Value {
+        let fields = [
+            ("format", Value::Number(self.format as f64)),
+            ("avt", Value::String(self.avt_version.clone())),
+            ("current_format", Value::Number(DUMP_FORMAT as f64)),
+            ("current_avt", Value::String(AVT_VERSION.to_string())),
+            ("compat", Value::String(self.compat().name().to_string())),
+        ];
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// Not a saved state
+    NotState,
+    Decode(DecodeError),
+    Incompatible(Header),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotState => write!(f, "not a saved VT state"),
+            StateError::Decode(e) => write!(f, "{}", e),
+            StateError::Incompatible(header) => write!(
+                f,
+                "state uses dump format {}, expected {}",
+                header.format, DUMP_FORMAT
+            ),
+        }
+    }
+}
+
+impl From<DecodeError> for StateError {
+    fn from(e: DecodeError) -> Self {
+        StateError::Decode(e)
+    }
+}
+
+pub struct SavedState<'a> {
+    pub header: Header,
+    pub cols: usize,
+    pub rows: usize,
+    pub dump: &'a [u8],
+}
+
+pub fn save<B: TerminalBackend>(state: &AvtState<B>) -> Vec<u8> {
+    let (cols, rows) = state.backend().size();
+    let dump = state.dump_ansi();
+    let mut buf = MAGIC.to_vec();
+    write_varint(&mut buf, DUMP_FORMAT);
+    write_varint(&mut buf, AVT_VERSION.len());
+    buf.extend_from_slice(AVT_VERSION.as_bytes());
+    write_varint(&mut buf, cols);
+    write_varint(&mut buf, rows);
+    write_varint(&mut buf, dump.len());
+    buf.extend_from_slice(dump.as_bytes());
+    buf
+}
+
+pub fn decode(bytes: &[u8]) -> Result<SavedState<'_>, StateError> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(StateError::NotState);
+    };
+    let mut r = Reader::new(rest);
+    let format = r.varint()?;
+    let len = r.varint()?;
+    let avt_version = String::from_utf8_lossy(r.take(len)?).into_owned();
+    let cols = r.varint()?;
+    let rows = r.varint()?;
+    let len = r.varint()?;
+    let dump = r.take(len)?;
+    Ok(SavedState {
+        header: Header {
+            format,
+            avt_version,
+        },
+        cols,
+        rows,
+        dump,
+    })
+}
+
+/// Restore `bytes` into `state` unless its dump format is incompatible,
+/// in which case `state` is left as it was.
+pub fn restore<B: TerminalBackend>(
+    state: &mut AvtState<B>,
+    bytes: &[u8],
+) -> Result<Compat, StateError> {
+    let saved = decode(bytes)?;
+    let compat = saved.header.compat();
+    if compat == Compat::Incompatible {
+        return Err(StateError::Incompatible(saved.header));
+    }
+    state.restore(saved.cols, saved.rows, saved.dump);
+    Ok(compat)
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSaveState<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&save(vt)).unwrap_or_default()
+    })
+}
+
+/// 0 restored exactly, 1 migrated from another avt, -1 refused
+/// (incompatible format), -2 not a valid state.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtRestoreState(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    state: JByteArray,
+) -> jint {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -2;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(&state) else {
+            return -2;
+        };
+        match restore(vt, &bytes) {
+            Ok(Compat::Exact) => 0,
+            Ok(_) => 1,
+            Err(StateError::Incompatible(_)) => -1,
+            Err(_) => -2,
+        }
+    })
+}
+
+/// Header of a saved state as JSON (see `Header::to_json`), or an empty
+/// string if it isn't one.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_stateVersionInfo<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    state: JByteArray<'a>,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let info = env
+            .convert_byte_array(&state)
+            .ok()
+            .and_then(|bytes| Some(decode(&bytes).ok()?.header.to_json().to_string()))
+            .unwrap_or_default();
+        env.new_string(info).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn restores_by_compatibility() {
+        let mut state = AvtState::with_backend(fake(4, 2));
+        state.feed(b"hi");
+        let saved = save(&state);
+
+        let mut restored = AvtState::with_backend(fake(2, 1));
+        assert_eq!(restore(&mut restored, &saved), Ok(Compat::Exact));
+        assert_eq!(restored.backend().size(), (4, 2));
+        assert_eq!(restored.backend().row_text(0), "hi  ");
+
+        // Same layout, other builds
+        let rewrite = |format: u8, avt: &str| {
+            let mut bytes = saved.clone();
+            bytes[4] = format;
+            let at = 6 + AVT_VERSION.len();
+            bytes.splice(6..at, avt.bytes());
+            bytes
+        };
+        let older = rewrite(1, "0.16.0+00000000");
+        assert_eq!(restore(&mut restored, &older), Ok(Compat::Migrate));
+        let future = rewrite(2, AVT_VERSION);
+        let err = restore(&mut restored, &future).unwrap_err();
+        assert!(matches!(
+            err,
+            StateError::Incompatible(Header { format: 2, .. })
+        ));
+        assert_eq!(restore(&mut restored, b"hi"), Err(StateError::NotState));
+        assert!(matches!(
+            restore(&mut restored, &saved[..saved.len() - 1]),
+            Err(StateError::Decode(_))
+        ));
+
+        let info = decode(&older).unwrap().header.to_json().to_string();
+        assert!(info.contains("\"compat\": \"migrate\""), "{}", info);
+    }
+
+    #[test]
+    fn avt_version_matches_lockfile() {
+        let lock = include_str!("../Cargo.lock");
+        let (version, rev) = AVT_VERSION.split_once('+').unwrap();
+        let entry = lock
+            .split("[[package]]")
+            .find(|entry| entry.contains("name = \"avt\""))
+            .unwrap();
+        assert!(
+            entry.contains(&format!("version = \"{}\"", version))
+                && entry.contains(&format!("#{}", rev)),
+            "update AVT_VERSION (and DUMP_FORMAT if dumps changed):\n{}",
+            entry
+        );
+    }
+}
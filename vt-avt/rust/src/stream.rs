@@ -0,0 +1,298 @@
+//! Push interface for hardware console byte streams (USB serial and the
+//! like).
+//!
+//! Serial readers deliver data on their own thread, in whatever chunks the
+//! driver had. `push` queues them, normalizing line endings on the way,
+//! and the thread that owns the VT feeds the queue to it with `drain`, so
+//! diffs and snapshots work as for any other source.
+//!
+//! The queue is bounded by watermarks. The push that fills it past the
+//! high mark returns `Flow::Pause`, telling the reader to stop reading (or
+//! drop RTS); the drain that takes it below the low mark returns
+//! `Flow::Resume`. Pushes are still queued while paused, since a reader
+//! can't always hold bytes back, so the bound is up to the reader.
+
+use crate::backend::TerminalBackend;
+use crate::handles::{self, Kind};
+use crate::{AvtState, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+const HIGH_WATERMARK: usize = 64 * 1024;
+const LOW_WATERMARK: usize = 16 * 1024;
+
+/// Line ending fixes. Serial devices often send bare LF (no `onlcr`),
+/// which a terminal shows as a staircase, or end lines with bare CR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Newline {
+    /// LF not preceded by CR becomes CR LF
+    pub lf_to_crlf: bool,
+    /// CR not followed by LF becomes CR LF
+    pub cr_to_crlf: bool,
+}
+
+impl Newline {
+    /// Bit 0 `lf_to_crlf`, bit 1 `cr_to_crlf`.
+    pub fn from_code(code: i32) -> Option<Self> {
+        (0..=3).contains(&code).then_some(Newline {
+            lf_to_crlf: code & 1 != 0,
+            cr_to_crlf: code & 2 != 0,
+        })
+    }
+}
+
+/// What the reader should do after a push or drain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Pause,
+    Resume,
+}
+
+impl Flow {
+    fn code(self) -> jint {
+        match self {
+            Flow::Continue => 0,
+            Flow::Pause => 1,
+            Flow::Resume => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    newline: Newline,
+    /// The last byte pushed was CR
+    after_cr: bool,
+    pending: Vec<u8>,
+    paused: bool,
+    high: usize,
+    low: usize,
+}
+
+impl Queue {
+    fn normalize(&mut self, input: &[u8]) {
+        for &byte in input {
+            if self.after_cr && self.newline.cr_to_crlf && byte != b'\n' {
+                self.pending.push(b'\n');
+            }
+            if byte == b'\n' && self.newline.lf_to_crlf && !self.after_cr {
+                self.pending.push(b'\r');
+            }
+            self.pending.push(byte);
+            self.after_cr = byte == b'\r';
+        }
+    }
+}
+
+/// A stream attached to a VT. `push` may be called from any thread, `drain`
+/// only from the VT's.
+#[derive(Debug)]
+pub struct Stream {
+    vt: VtHandle,
+    queue: Mutex<Queue>,
+}
+
+registered!(Stream, Kind::Stream);
+
+impl Stream {
+    pub fn new(vt: VtHandle, newline: Newline) -> Self {
+        Stream {
+            vt,
+            queue: Mutex::new(Queue {
+                newline,
+                after_cr: false,
+                pending: Vec::new(),
+                paused: false,
+                high: HIGH_WATERMARK,
+                low: LOW_WATERMARK,
+            }),
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Pause above `high` queued bytes, resume at or below `low`.
+    pub fn set_watermarks(&self, high: usize, low: usize) {
+        let mut queue = self.queue();
+        queue.high = high.max(1);
+        queue.low = low.min(queue.high - 1);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue().pending.len()
+    }
+
+    pub fn push(&self, bytes: &[u8]) -> Flow {
+        let mut queue = self.queue();
+        queue.normalize(bytes);
+        if !queue.paused && queue.pending.len() > queue.high {
+            queue.paused = true;
+            Flow::Pause
+        } else {
+            Flow::Continue
+        }
+    }
+
+    /// Feed up to `max` queued bytes (all of them for 0) to `state`.
+    pub fn drain<B: TerminalBackend>(&self, state: &mut AvtState<B>, max: usize) -> Flow {
+        // Feed outside the lock so the reader isn't held up by the VT
+        let (bytes, flow) = {
+            let mut queue = self.queue();
+            let n = match max {
+                0 => queue.pending.len(),
+                max => max.min(queue.pending.len()),
+            };
+            let bytes: Vec<u8> = queue.pending.drain(..n).collect();
+            let flow = if queue.paused && queue.pending.len() <= queue.low {
+                queue.paused = false;
+                Flow::Resume
+            } else {
+                Flow::Continue
+            };
+            (bytes, flow)
+        };
+        state.feed(&bytes);
+        flow
+    }
+}
+
+// JNI functions
+
+/// Attach a stream to the VT `handle`, with `newline` as for
+/// `Newline::from_code`. Returns 0 for an invalid VT or newline code.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamAttach(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    newline: jint,
+) -> jlong {
+    jni_guard!(env, {
+        if handles::get(&mut env, handle).is_none() {
+            return 0;
+        }
+        let Some(newline) = Newline::from_code(newline) else {
+            return 0;
+        };
+
+        handles::add(Stream::new(handle, newline))
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamDetach(
+    mut env: JNIEnv,
+    _class: JClass,
+    stream: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Stream>(&mut env, stream));
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamSetWatermarks(
+    mut env: JNIEnv,
+    _class: JClass,
+    stream: jlong,
+    high: jint,
+    low: jint,
+) {
+    jni_guard!(env, {
+        let Some(stream) = handles::object_ref::<Stream>(&mut env, stream) else {
+            return;
+        };
+
+        stream.set_watermarks(high.max(0) as usize, low.max(0) as usize);
+    })
+}
+
+/// Queue bytes from the reader. Returns 1 when the reader should pause,
+/// 0 otherwise, -1 for an invalid stream.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamPush(
+    mut env: JNIEnv,
+    _class: JClass,
+    stream: jlong,
+    bytes: JByteArray,
+) -> jint {
+    jni_guard!(env, {
+        let Some(stream) = handles::object_ref::<Stream>(&mut env, stream) else {
+            return -1;
+        };
+
+        let Ok(bytes) = env.convert_byte_array(bytes) else {
+            return -1;
+        };
+        stream.push(&bytes).code()
+    })
+}
+
+/// Feed queued bytes to the VT. Returns 2 when the reader may resume,
+/// 0 otherwise, -1 for an invalid stream or VT.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_streamDrain(
+    mut env: JNIEnv,
+    _class: JClass,
+    stream: jlong,
+    max_bytes: jint,
+) -> jint {
+    jni_guard!(env, {
+        let Some(stream) = handles::object_ref::<Stream>(&mut env, stream) else {
+            return -1;
+        };
+
+        let Some(vt) = handles::get(&mut env, stream.vt) else {
+            return -1;
+        };
+        stream.drain(vt, max_bytes.max(0) as usize).code()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    fn normalized(code: i32, chunks: &[&[u8]]) -> Vec<u8> {
+        let stream = Stream::new(0, Newline::from_code(code).unwrap());
+        for chunk in chunks {
+            stream.push(chunk);
+        }
+        let pending = stream.queue().pending.clone();
+        pending
+    }
+
+    #[test]
+    fn line_endings_are_normalized_across_chunks() {
+        let input: &[&[u8]] = &[b"a\nb\r", b"\nc\r", b"d"];
+        assert_eq!(normalized(0, input), b"a\nb\r\nc\rd");
+        assert_eq!(normalized(1, input), b"a\r\nb\r\nc\rd");
+        assert_eq!(normalized(2, input), b"a\nb\r\nc\r\nd");
+        assert_eq!(normalized(3, input), b"a\r\nb\r\nc\r\nd");
+        assert_eq!(Newline::from_code(4), None);
+    }
+
+    #[test]
+    fn watermarks_pause_and_resume_the_reader() {
+        let mut state = AvtState::with_backend(fake(8, 1));
+        let stream = Stream::new(0, Newline::default());
+        stream.set_watermarks(4, 2);
+
+        assert_eq!(stream.push(b"abcd"), Flow::Continue);
+        assert_eq!(stream.push(b"e"), Flow::Pause);
+        // Still queued while paused, and paused only once
+        assert_eq!(stream.push(b"f"), Flow::Continue);
+        assert_eq!(stream.queued(), 6);
+
+        assert_eq!(stream.drain(&mut state, 3), Flow::Continue);
+        assert_eq!(stream.drain(&mut state, 1), Flow::Resume);
+        assert_eq!(stream.drain(&mut state, 0), Flow::Continue);
+        assert_eq!(state.backend().row_text(0), "abcdef  ");
+    }
+}
@@ -0,0 +1,219 @@
+//! Seeded escape-sequence noise for soak tests and throughput checks.
+//!
+//! `Noise` generates output that looks like a busy terminal program gone
+//! slightly wrong: mostly text and well-formed sequences, mixed with stray
+//! controls, unterminated strings, out-of-range parameters and invalid
+//! UTF-8. The same seed always gives the same bytes, so a crash on a
+//! device replays exactly in a test.
+//!
+//! `stress` feeds it the way a player would, in uneven chunks with a diff
+//! polled after each and the occasional resize, and reports how long that
+//! took: sustained throughput on real hardware, not just a benchmark.
+
+use crate::backend::TerminalBackend;
+use crate::{handles, AvtState, Instant, VtHandle};
+use jni::objects::JClass;
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::time::Duration;
+
+/// Largest chunk fed at once
+const MAX_CHUNK: usize = 4096;
+
+/// Chunks between resizes, on average
+const RESIZE_EVERY: u32 = 64;
+
+/// Text beyond ASCII: accents, CJK (wide), emoji, a combining mark
+const CHARS: [char; 6] = ['é', 'ß', '中', '한', '😀', '\u{301}'];
+
+/// Seeded generator of terminal output noise.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    state: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        Noise { state: seed }
+    }
+
+    /// splitmix64
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut x = self.state;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    /// Uniform in `0..n`.
+    fn below(&mut self, n: u32) -> u32 {
+        (((self.next() >> 32) * n as u64) >> 32) as u32
+    }
+
+    /// Uniform in `lo..=hi`.
+    fn between(&mut self, lo: u8, hi: u8) -> u8 {
+        lo + self.below((hi - lo) as u32 + 1) as u8
+    }
+
+    /// Append exactly `len` bytes of noise. The last item is cut off where
+    /// it reaches `len`, which is just more noise.
+    pub fn fill(&mut self, out: &mut Vec<u8>, len: usize) {
+        let end = out.len() + len;
+        while out.len() < end {
+            self.item(out);
+        }
+        out.truncate(end);
+    }
+
+    fn item(&mut self, out: &mut Vec<u8>) {
+        match self.below(16) {
+            0..=6 => {
+                for _ in 0..=self.below(32) {
+                    out.push(self.between(0x20, 0x7e));
+                }
+            }
+            7 => {
+                let c = CHARS[self.below(CHARS.len() as u32) as usize];
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            8 => out.push(self.between(0x00, 0x1f)),
+            9..=11 => self.csi(out),
+            12 => {
+                out.push(0x1b);
+                if self.below(4) == 0 {
+                    out.push(self.between(0x20, 0x2f));
+                }
+                out.push(self.between(0x30, 0x7e));
+            }
+            13 => self.string(out, b']'),
+            14 => self.string(out, b'P'),
+            _ => {
+                for _ in 0..=self.below(8) {
+                    out.push(self.next() as u8);
+                }
+            }
+        }
+    }
+
+    fn csi(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(b"\x1b[");
+        if self.below(4) == 0 {
+            out.push(self.between(b'<', b'?'));
+        }
+        for i in 0..self.below(6) {
+            if i > 0 {
+                out.push(if self.below(8) == 0 { b':' } else { b';' });
+            }
+            let param = match self.below(16) {
+                0 => 65535,
+                1..=3 => 0,
+                _ => self.below(100),
+            };
+            out.extend_from_slice(param.to_string().as_bytes());
+        }
+        if self.below(8) == 0 {
+            out.push(self.between(0x20, 0x2f));
+        }
+        out.push(self.between(0x40, 0x7e));
+    }
+
+    /// An OSC (`]`) or DCS (`P`) string, terminated by BEL, ST or nothing.
+    fn string(&mut self, out: &mut Vec<u8>, introducer: u8) {
+        out.extend_from_slice(&[0x1b, introducer]);
+        out.extend_from_slice(self.below(1400).to_string().as_bytes());
+        out.push(b';');
+        for _ in 0..self.below(64) {
+            out.push(self.between(0x20, 0x7e));
+        }
+        match self.below(4) {
+            0 => out.push(0x07),
+            1 | 2 => out.extend_from_slice(b"\x1b\\"),
+            _ => {}
+        }
+    }
+}
+
+/// Feed `bytes` of `Noise::new(seed)` to `vt` as a player would, then
+/// cancel any unfinished sequence and restore its size. Returns the time
+/// taken.
+pub fn stress<B: TerminalBackend>(vt: &mut AvtState<B>, seed: u64, bytes: usize) -> Duration {
+    let (cols, rows) = vt.backend().size();
+    let mut noise = Noise::new(seed);
+    let mut chunk = Vec::with_capacity(MAX_CHUNK);
+    let mut fed = 0;
+
+    let start = Instant::now();
+    while fed < bytes {
+        let len = (noise.below(MAX_CHUNK as u32) as usize + 1).min(bytes - fed);
+        chunk.clear();
+        noise.fill(&mut chunk, len);
+        vt.feed(&chunk);
+        vt.poll_diff_content();
+        fed += len;
+
+        if noise.below(RESIZE_EVERY) == 0 {
+            let size = (noise.between(1, 200), noise.between(1, 60));
+            vt.resize(size.0 as usize, size.1 as usize);
+        }
+    }
+    // Abandon any sequence left half-fed, which would hold the resize back
+    vt.feed(b"\x18");
+    vt.resize(cols, rows);
+    start.elapsed()
+}
+
+// JNI functions
+
+/// Feed `bytes` of noise from `seed` (see `stress`). Returns the
+/// nanoseconds taken, or -1 for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtStress(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    seed: jlong,
+    bytes: jlong,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -1;
+        };
+
+        let elapsed = stress(vt, seed as u64, bytes.max(0) as usize);
+        elapsed.as_nanos().min(jlong::MAX as u128) as jlong
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn noise_is_deterministic_and_exactly_sized() {
+        let mut a = Vec::new();
+        Noise::new(7).fill(&mut a, 10_000);
+        let mut b = Vec::new();
+        let mut noise = Noise::new(7);
+        noise.fill(&mut b, 4_000);
+        noise.fill(&mut b, 6_000);
+        assert_eq!((a.len(), b.len()), (10_000, 10_000));
+        assert_eq!(a[..4_000], b[..4_000]);
+
+        let mut c = Vec::new();
+        Noise::new(8).fill(&mut c, 10_000);
+        assert_ne!(a, c);
+        assert!(a.windows(2).any(|w| w == b"\x1b["));
+    }
+
+    #[test]
+    fn same_seed_same_screen() {
+        let mut a = AvtState::with_backend(fake(40, 10));
+        let mut b = AvtState::with_backend(fake(40, 10));
+        stress(&mut a, 42, 200_000);
+        stress(&mut b, 42, 200_000);
+        assert_eq!(a.backend().size(), (40, 10));
+        assert_eq!(a.encode_snapshot(), b.encode_snapshot());
+    }
+}
@@ -0,0 +1,549 @@
+//! Style tables: every distinct style on a screen, listed once.
+//!
+//! Snapshot runs carry their style inline, so the same few styles repeat
+//! on every row. A style table lists each once, in order of first
+//! appearance, for clients that build their paints up front and look them
+//! up by index:
+//!
+//! ```text
+//! table := style_count style*
+//! style := color(fg) color(bg) attrs:u8
+//! color := 0 | 1 index:u8 | 2 index:u8 | 3 r:u8 g:u8 b:u8
+//! ```
+//!
+//! Unlike the snapshot's colors, the tags separate the 16 ANSI colors
+//! (tag 1: SGR 30-37, 90-97 and their backgrounds) from the rest of the
+//! 256-color palette (tag 2: `38;5` and `48;5`), next to the default color
+//! (tag 0) and true color (tag 3: `38;2` and `48;2`). `38;5;1` comes out as
+//! tag 1, the terminal keeping no record of which form set it. `attrs` holds
+//! the snapshot's `ATTR_*` bits: bold, faint, italic, underline, blink,
+//! inverse and strikethrough, as SGR sets them.
+//!
+//! `decode` follows `snapshot::decode`: truncation is an error, an unknown
+//! color tag is the default color and reads no further bytes.
+//!
+//! ## Interned styles
+//!
+//! `vtSnapshotInterned` and `vtPollDiffInterned` (diff tag 6) refer to
+//! styles by ids that stay the same from frame to frame, so the client can
+//! cache what it resolved each one to. Each carries the entries created
+//! since the last, ahead of the lines using them:
+//!
+//! ```text
+//! update := epoch first_id style_count style*
+//! ```
+//!
+//! and its runs hold a `style_id` varint in place of `style`. Ids count up
+//! from 0 within an epoch. A new epoch means the client drops its cache and
+//! starts again from 0: every interned snapshot starts one, carrying all the
+//! styles it uses, and so does the first diff after the table passes
+//! `MAX_STYLES`. An update whose `first_id` isn't the number of styles the
+//! client holds means it missed one, and it should take a new snapshot.
+//!
+//! ## Regions
+//!
+//! `vtSnapshotRegion` is an interned snapshot of some rows alone, for a
+//! renderer that draws only what is scrolled into view:
+//!
+//! ```text
+//! region := update cols rows cursor_col cursor_row cursor_flags:u8
+//!           first_row line_count line* link_count link*
+//! ```
+//!
+//! The header is the whole screen's, cursor included, and the lines are
+//! rows `first_row` on, cut short at the bottom of the screen. Like an
+//! interned snapshot it starts a new epoch, but the update carries only
+//! the styles of those rows, and the link table only their links.
+
+use crate::snapshot::{self, Color, Cursor, DecodeError, Line, Reader, Screen, Style, ALT_SCREEN};
+use crate::{handles, write_varint, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::JNIEnv;
+use std::collections::HashMap;
+use std::ops::Range;
+
+pub const COLOR_DEFAULT: u8 = 0;
+/// Palette index below 16
+pub const COLOR_ANSI: u8 = 1;
+/// Palette index 16 and up
+pub const COLOR_INDEXED: u8 = 2;
+pub const COLOR_RGB: u8 = 3;
+
+/// Styles an interner holds before it starts a new epoch, which bounds it
+/// for output that rarely repeats a color (true-color gradients)
+pub const MAX_STYLES: usize = 4096;
+
+/// Distinct styles in order of first appearance.
+#[derive(Debug, Clone, Default)]
+pub struct StyleTable {
+    styles: Vec<Style>,
+    /// Position in `styles`, by `Style::id`
+    index: HashMap<u64, usize>,
+}
+
+impl StyleTable {
+    pub fn new() -> Self {
+        StyleTable::default()
+    }
+
+    /// The styles of every run on `screen`.
+    pub fn of_screen(screen: &Screen) -> Self {
+        let mut table = StyleTable::new();
+        for run in screen.lines.iter().flat_map(|line| &line.runs) {
+            table.intern(run.style);
+        }
+        table
+    }
+
+    /// Position of `style` in the table, added at the end if new.
+    pub fn intern(&mut self, style: Style) -> usize {
+        *self.index.entry(style.id()).or_insert_with(|| {
+            self.styles.push(style);
+            self.styles.len() - 1
+        })
+    }
+
+    pub fn styles(&self) -> &[Style] {
+        &self.styles
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + self.styles.len() * 9);
+        write_varint(&mut buf, self.styles.len());
+        for &style in &self.styles {
+            encode_style(&mut buf, style);
+        }
+        buf
+    }
+}
+
+/// Style ids for the interned formats, kept by the VT across frames.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    table: StyleTable,
+    epoch: u32,
+    /// Entries already sent in an update
+    sent: usize,
+}
+
+impl Interner {
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Styles in the current epoch.
+    pub fn len(&self) -> usize {
+        self.table.styles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every id and start a new epoch.
+    pub fn restart(&mut self) {
+        self.table = StyleTable::new();
+        self.epoch = self.epoch.wrapping_add(1);
+        self.sent = 0;
+    }
+
+    /// Append `line` with its styles as ids.
+    pub(crate) fn encode_line(&mut self, line: &Line, buf: &mut Vec<u8>) {
+        line.encode_with(buf, |buf, style| {
+            write_varint(buf, self.table.intern(style))
+        });
+    }
+
+    /// Append the `update` for the entries created since the last one.
+    pub(crate) fn encode_update(&mut self, buf: &mut Vec<u8>) {
+        let new = &self.table.styles[self.sent..];
+        write_varint(buf, self.epoch as usize);
+        write_varint(buf, self.sent);
+        write_varint(buf, new.len());
+        for &style in new {
+            encode_style(buf, style);
+        }
+        self.sent = self.table.styles.len();
+    }
+}
+
+/// Client side of an `Interner`: the current epoch's styles by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cache {
+    epoch: Option<u32>,
+    styles: Vec<Style>,
+}
+
+impl Cache {
+    pub fn styles(&self) -> &[Style] {
+        &self.styles
+    }
+
+    /// Style `id`, or the default for an id never sent, like an unknown
+    /// color tag.
+    pub fn get(&self, id: usize) -> Style {
+        self.styles.get(id).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn read_update(&mut self, r: &mut Reader) -> Result<(), DecodeError> {
+        let epoch = r.varint()? as u32;
+        let first = r.varint()?;
+        let count = r.varint()?;
+        if self.epoch != Some(epoch) {
+            self.epoch = Some(epoch);
+            self.styles.clear();
+        }
+        if first != self.styles.len() {
+            return Err(DecodeError::StyleGap {
+                expected: self.styles.len(),
+                found: first,
+            });
+        }
+        self.styles.reserve(count.min(r.remaining() / 3));
+        for _ in 0..count {
+            self.styles.push(decode_style(r)?);
+        }
+        Ok(())
+    }
+
+    /// Read a line with its styles as ids.
+    pub(crate) fn line(&self, r: &mut Reader) -> Result<Line, DecodeError> {
+        r.line_with(|r| Ok(self.get(r.varint()?)))
+    }
+}
+
+/// `screen` in the interned snapshot form, starting a new epoch.
+pub fn encode_screen(screen: &Screen, interner: &mut Interner) -> Vec<u8> {
+    interner.restart();
+    let mut body = Vec::new();
+    screen.encode_header(&mut body);
+    for line in &screen.lines {
+        interner.encode_line(line, &mut body);
+    }
+    screen.encode_tables(&mut body);
+
+    let mut buf = Vec::with_capacity(body.len() + 4 + interner.len() * 9);
+    interner.encode_update(&mut buf);
+    buf.extend_from_slice(&body);
+    buf
+}
+
+/// Rows `rows` of `screen` in the region form, starting a new epoch.
+pub fn encode_region(screen: &Screen, rows: Range<usize>, interner: &mut Interner) -> Vec<u8> {
+    let end = rows.end.min(screen.lines.len());
+    let first = rows.start.min(end);
+    let lines = &screen.lines[first..end];
+    interner.restart();
+    let mut body = Vec::new();
+    screen.encode_header(&mut body);
+    write_varint(&mut body, first);
+    write_varint(&mut body, lines.len());
+    for line in lines {
+        interner.encode_line(line, &mut body);
+    }
+    let links: Vec<_> = screen
+        .links
+        .iter()
+        .filter(|(id, _)| {
+            lines
+                .iter()
+                .flat_map(|line| &line.runs)
+                .any(|run| run.link == *id)
+        })
+        .cloned()
+        .collect();
+    snapshot::encode_link_table(&links, &mut body);
+
+    let mut buf = Vec::with_capacity(body.len() + 4 + interner.len() * 9);
+    interner.encode_update(&mut buf);
+    buf.extend_from_slice(&body);
+    buf
+}
+
+/// A decoded region: `screen.lines` are rows `first_row` on of a screen of
+/// `screen.rows` rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub first_row: usize,
+    pub screen: Screen,
+}
+
+/// Decode a region, updating `cache`.
+pub fn decode_region(bytes: &[u8], cache: &mut Cache) -> Result<Region, DecodeError> {
+    let mut r = Reader::new(bytes);
+    cache.read_update(&mut r)?;
+    let cols = r.varint()?;
+    let rows = r.varint()?;
+    let (col, row) = (r.varint()?, r.varint()?);
+    let flags = r.byte()?;
+    let first_row = r.varint()?;
+
+    let count = r.varint()?;
+    let mut lines = Vec::with_capacity(count.min(r.remaining() / 2));
+    for _ in 0..count {
+        lines.push(cache.line(&mut r)?);
+    }
+    let links = r.links()?;
+
+    let screen = Screen {
+        cols,
+        rows,
+        cursor: Cursor::with_flags(col, row, flags),
+        lines,
+        links,
+        alt_screen: flags & ALT_SCREEN != 0,
+        images: Vec::new(),
+    };
+    Ok(Region { first_row, screen })
+}
+
+/// Decode an interned snapshot, updating `cache`.
+pub fn decode_screen(bytes: &[u8], cache: &mut Cache) -> Result<Screen, DecodeError> {
+    let mut r = Reader::new(bytes);
+    cache.read_update(&mut r)?;
+    snapshot::decode_with(&mut r, |r| cache.line(r))
+}
+
+fn encode_style(buf: &mut Vec<u8>, style: Style) {
+    encode_color(buf, style.fg);
+    encode_color(buf, style.bg);
+    buf.push(style.attrs);
+}
+
+fn decode_style(r: &mut Reader) -> Result<Style, DecodeError> {
+    Ok(Style {
+        fg: decode_color(r)?,
+        bg: decode_color(r)?,
+        attrs: r.byte()?,
+    })
+}
+
+fn encode_color(buf: &mut Vec<u8>, color: Color) {
+    match color {
+        Color::Default => buf.push(COLOR_DEFAULT),
+        Color::Indexed(idx) if idx < 16 => buf.extend_from_slice(&[COLOR_ANSI, idx]),
+        Color::Indexed(idx) => buf.extend_from_slice(&[COLOR_INDEXED, idx]),
+        Color::Rgb(r, g, b) => buf.extend_from_slice(&[COLOR_RGB, r, g, b]),
+    }
+}
+
+fn decode_color(r: &mut Reader) -> Result<Color, DecodeError> {
+    Ok(match r.byte()? {
+        COLOR_ANSI | COLOR_INDEXED => Color::Indexed(r.byte()?),
+        COLOR_RGB => Color::Rgb(r.byte()?, r.byte()?, r.byte()?),
+        _ => Color::Default,
+    })
+}
+
+/// Decode a table, see the module docs.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Style>, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let count = r.varint()?;
+    let mut styles = Vec::with_capacity(count.min(r.remaining()));
+    for _ in 0..count {
+        styles.push(decode_style(&mut r)?);
+    }
+    Ok(styles)
+}
+
+// JNI functions
+
+/// Style table of the visible screen. Empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtStyleTable<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let table = StyleTable::of_screen(&vt.screen());
+        env.byte_array_from_slice(&table.encode())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{ATTR_BOLD, ATTR_FAINT, ATTR_INVERSE, ATTR_ITALIC};
+    use crate::snapshot::{ATTR_STRIKETHROUGH, ATTR_UNDERLINE};
+    use crate::AvtState;
+
+    fn style(fg: Color, bg: Color, attrs: u8) -> Style {
+        Style { fg, bg, attrs }
+    }
+
+    #[test]
+    fn colors_are_tagged_by_kind() {
+        let mut table = StyleTable::new();
+        let red = style(Color::Indexed(1), Color::Default, ATTR_BOLD);
+        assert_eq!(table.intern(Style::default()), 0);
+        assert_eq!(table.intern(red), 1);
+        assert_eq!(table.intern(Style::default()), 0);
+        table.intern(style(Color::Indexed(208), Color::Rgb(1, 2, 3), ATTR_FAINT));
+
+        let bytes = table.encode();
+        assert_eq!(
+            bytes,
+            [3, 0, 0, 0, 1, 1, 0, ATTR_BOLD, 2, 208, 3, 1, 2, 3, ATTR_FAINT]
+        );
+        assert_eq!(decode(&bytes).unwrap(), table.styles());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        let unknown_fg = style(Color::Default, Color::Indexed(7), 0);
+        assert_eq!(decode(&[1, 9, 1, 7, 0]).unwrap(), [unknown_fg]);
+    }
+
+    #[test]
+    fn sgr_sequences_map_to_table_entries() {
+        let mut state = AvtState::new(10, 1);
+        state.feed(b"\x1b[1;31ma\x1b[0;2;3;4;7;9;38;5;208;48;2;1;2;3mb");
+        state.feed(b"\x1b[0;94;48;5;4mc\x1b[38;5;1md\x1b[0m ");
+
+        let table = StyleTable::of_screen(&state.screen());
+        let attrs = ATTR_FAINT | ATTR_ITALIC | ATTR_UNDERLINE | ATTR_INVERSE | ATTR_STRIKETHROUGH;
+        assert_eq!(
+            table.styles(),
+            [
+                style(Color::Indexed(1), Color::Default, ATTR_BOLD),
+                style(Color::Indexed(208), Color::Rgb(1, 2, 3), attrs),
+                style(Color::Indexed(12), Color::Indexed(4), 0),
+                style(Color::Indexed(1), Color::Indexed(4), 0),
+                Style::default(),
+            ]
+        );
+        assert_eq!(table.encode()[1..4], [COLOR_ANSI, 1, COLOR_DEFAULT]);
+    }
+
+    #[test]
+    fn interned_frames_send_only_new_styles() {
+        use crate::diff::{self, Content, Diff};
+        use crate::lineattr::LineAttr;
+        use crate::snapshot::{Cursor, Run};
+
+        let line = |runs: &[(usize, &str, Style)]| Line {
+            attr: LineAttr::Single,
+            runs: runs
+                .iter()
+                .map(|&(col, text, style)| Run {
+                    col,
+                    text: text.to_string(),
+                    cells: text.chars().count(),
+                    style,
+                    link: 0,
+                })
+                .collect(),
+        };
+        let red = style(Color::Indexed(1), Color::Default, 0);
+        let rgb = style(Color::Rgb(1, 2, 3), Color::Default, ATTR_BOLD);
+        let cursor = Cursor::default();
+        let screen = Screen {
+            cols: 4,
+            rows: 2,
+            cursor,
+            lines: vec![
+                line(&[(0, "ab", red), (2, "  ", Style::default())]),
+                line(&[(0, "    ", red)]),
+            ],
+            links: Vec::new(),
+            alt_screen: false,
+            images: Vec::new(),
+        };
+
+        let mut interner = Interner::default();
+        let mut cache = Cache::default();
+        let bytes = encode_screen(&screen, &mut interner);
+        assert_eq!(bytes[..3], [1, 0, 2]);
+        assert_eq!(decode_screen(&bytes, &mut cache).unwrap(), screen);
+        assert_eq!(cache.styles(), [red, Style::default()]);
+
+        let diff = Diff {
+            lines: vec![1],
+            content: Some(Content {
+                cols: 4,
+                rows: 2,
+                cursor,
+                alt_screen: false,
+                lines: vec![line(&[(1, "x", rgb), (2, "y", red)])],
+                spans: Some(std::iter::once(1..3).collect()),
+            }),
+            ..Diff::default()
+        };
+        let bytes = diff.encode_interned(&mut interner);
+        assert_eq!(bytes[..5], [6, 0, 1, 2, 1]);
+        assert_eq!(diff::decode_interned(&bytes, &mut cache).unwrap(), diff);
+        assert_eq!(cache.styles().len(), 3);
+        assert_eq!(
+            diff::decode(&bytes),
+            Err(DecodeError::StyleGap {
+                expected: 0,
+                found: 2
+            })
+        );
+
+        // Nothing new: an empty update
+        assert_eq!(diff.encode_interned(&mut interner)[..5], [6, 0, 1, 3, 0]);
+        interner.restart();
+        let bytes = diff.encode_interned(&mut interner);
+        assert_eq!(bytes[..5], [6, 0, 2, 0, 2]);
+        assert_eq!(diff::decode(&bytes).unwrap(), diff);
+    }
+
+    #[test]
+    fn regions_carry_only_their_rows_styles_and_links() {
+        use crate::lineattr::LineAttr;
+        use crate::snapshot::Run;
+
+        let line = |text: &str, fg: u8, link: u32| Line {
+            attr: LineAttr::Single,
+            runs: vec![Run {
+                col: 0,
+                text: text.to_string(),
+                cells: text.chars().count(),
+                style: style(Color::Indexed(fg), Color::Default, 0),
+                link,
+            }],
+        };
+        let screen = Screen {
+            cols: 4,
+            rows: 4,
+            cursor: Cursor::with_flags(1, 3, 1),
+            lines: vec![
+                line("a", 1, 1),
+                line("b", 2, 2),
+                line("c", 4, 0),
+                line("d", 1, 0),
+            ],
+            links: vec![(1, "https://x".into()), (2, "https://y".into())],
+            alt_screen: false,
+            images: Vec::new(),
+        };
+
+        let mut interner = Interner::default();
+        let mut cache = Cache::default();
+        let bytes = encode_region(&screen, 1..3, &mut interner);
+        let region = decode_region(&bytes, &mut cache).unwrap();
+        assert_eq!(region.first_row, 1);
+        assert_eq!(region.screen.lines, screen.lines[1..3]);
+        assert_eq!(
+            (region.screen.rows, region.screen.cursor),
+            (4, screen.cursor)
+        );
+        assert_eq!(region.screen.links, screen.links[1..]);
+        let colors: Vec<_> = cache.styles().iter().map(|style| style.fg).collect();
+        assert_eq!(colors, [Color::Indexed(2), Color::Indexed(4)]);
+
+        // Cut short at the bottom
+        let bytes = encode_region(&screen, 3..9, &mut interner);
+        let region = decode_region(&bytes, &mut cache).unwrap();
+        assert_eq!(
+            (region.first_row, region.screen.lines),
+            (3, screen.lines[3..].to_vec())
+        );
+        assert!(region.screen.links.is_empty());
+        assert!(decode_region(&bytes[..bytes.len() - 1], &mut Cache::default()).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! One frame as an SVG document, for sharing a moment of a recording
+//! without rasterizing it.
+//!
+//! The layout follows asciinema's svg-term: a background rect the size of
+//! the grid, a rect for every run with a background of its own, then a
+//! `<text>` per run, with the cursor on top. Cells are `CELL_WIDTH` by
+//! `CELL_HEIGHT` user units and text is set in the viewer's monospace
+//! font, so the document scales to any size. Colors are resolved as by
+//! `vtResolveStyles`; links (OSC 8) become `<a>` elements. Line attributes
+//! are not drawn: double-width and double-height rows come out single.
+
+use crate::cast::{micros_arg, Cast};
+use crate::palette::{self, Options, Palette};
+use crate::snapshot::{
+    CursorShape, Screen, Style, ATTR_BOLD, ATTR_FAINT, ATTR_ITALIC, ATTR_STRIKETHROUGH,
+    ATTR_UNDERLINE,
+};
+use crate::transcript::push_escaped;
+use crate::{handles, player, AvtState, VtHandle};
+use jni::objects::{JClass, JIntArray, JString};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::fmt::Write as _;
+
+/// Size of a cell, and of the text in it, in user units
+pub const CELL_WIDTH: usize = 9;
+pub const CELL_HEIGHT: usize = 18;
+pub const FONT_SIZE: usize = 15;
+
+/// Baseline of a row's text below its top
+const BASELINE: usize = 14;
+
+/// `screen` as a standalone SVG document.
+pub fn frame(screen: &Screen, palette: &Palette) -> String {
+    let options = &Options::new(0, 1.0);
+    let (width, height) = (screen.cols * CELL_WIDTH, screen.rows * CELL_HEIGHT);
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" font-family=\"monospace\" font-size=\"{FONT_SIZE}\" \
+         xml:space=\"preserve\">\n\
+         <rect width=\"{width}\" height=\"{height}\" fill=\"#{:06x}\"/>\n",
+        palette.bg
+    );
+
+    let runs = || {
+        screen
+            .lines
+            .iter()
+            .enumerate()
+            .take(screen.rows)
+            .flat_map(|(row, line)| {
+                line.runs
+                    .iter()
+                    .map(move |run| (row, run, palette::resolve(run.style, palette, options)))
+            })
+    };
+    for (row, run, colors) in runs() {
+        if colors.bg & 0xffffff != palette.bg {
+            let (x, y, width) = (
+                run.col * CELL_WIDTH,
+                row * CELL_HEIGHT,
+                run.cells * CELL_WIDTH,
+            );
+            push_rect(&mut out, x, y, width, CELL_HEIGHT, colors.bg);
+        }
+    }
+    for (row, run, colors) in runs() {
+        if run.text.trim_end().is_empty() && run.style.attrs & ATTR_UNDERLINE == 0 {
+            continue;
+        }
+        let link = screen
+            .links
+            .iter()
+            .find(|(id, _)| *id == run.link && run.link != 0);
+        if let Some((_, uri)) = link {
+            out.push_str("<a href=\"");
+            push_escaped(uri, &mut out);
+            out.push_str("\">");
+        }
+        push_text(&mut out, run.col, row, &run.text, colors.fg, colors.attrs);
+        if link.is_some() {
+            out.push_str("</a>");
+        }
+        out.push('\n');
+    }
+
+    let cursor = &screen.cursor;
+    if cursor.visible && cursor.col < screen.cols && cursor.row < screen.rows {
+        let under = screen.lines.get(cursor.row).and_then(|line| {
+            line.runs.iter().find_map(|run| {
+                let (_, ch) = run.columns().find(|&(col, _)| col == cursor.col)?;
+                Some((ch, run.style, run.char_width()))
+            })
+        });
+        let (ch, style, cells) = under.unwrap_or((' ', Style::default(), 1));
+        let colors = palette::resolve(style, palette, options);
+        let (x, y, width) = (
+            cursor.col * CELL_WIDTH,
+            cursor.row * CELL_HEIGHT,
+            cells * CELL_WIDTH,
+        );
+        match cursor.shape {
+            CursorShape::Block => {
+                push_rect(&mut out, x, y, width, CELL_HEIGHT, colors.fg);
+                if ch != ' ' {
+                    let text = ch.to_string();
+                    push_text(
+                        &mut out,
+                        cursor.col,
+                        cursor.row,
+                        &text,
+                        colors.bg,
+                        colors.attrs,
+                    );
+                    out.push('\n');
+                }
+            }
+            CursorShape::Underline => {
+                push_rect(&mut out, x, y + CELL_HEIGHT - 2, width, 2, colors.fg)
+            }
+            CursorShape::Bar => push_rect(&mut out, x, y, 2, CELL_HEIGHT, colors.fg),
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+fn push_rect(out: &mut String, x: usize, y: usize, width: usize, height: usize, argb: u32) {
+    let _ = writeln!(
+        out,
+        "<rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{height}\" fill=\"#{:06x}\"/>",
+        argb & 0xffffff
+    );
+}
+
+fn push_text(out: &mut String, col: usize, row: usize, text: &str, argb: u32, attrs: u8) {
+    let _ = write!(
+        out,
+        "<text x=\"{}\" y=\"{}\" fill=\"#{:06x}\"",
+        col * CELL_WIDTH,
+        row * CELL_HEIGHT + BASELINE,
+        argb & 0xffffff
+    );
+    if attrs & ATTR_BOLD != 0 {
+        out.push_str(" font-weight=\"bold\"");
+    }
+    if attrs & ATTR_ITALIC != 0 {
+        out.push_str(" font-style=\"italic\"");
+    }
+    if attrs & ATTR_FAINT != 0 {
+        out.push_str(" opacity=\"0.5\"");
+    }
+    match (attrs & ATTR_UNDERLINE != 0, attrs & ATTR_STRIKETHROUGH != 0) {
+        (true, true) => out.push_str(" text-decoration=\"underline line-through\""),
+        (true, false) => out.push_str(" text-decoration=\"underline\""),
+        (false, true) => out.push_str(" text-decoration=\"line-through\""),
+        (false, false) => {}
+    }
+    out.push('>');
+    push_escaped(text, out);
+    out.push_str("</text>");
+}
+
+// JNI functions
+
+/// The screen as an SVG document, see `frame`, with `palette` as for
+/// `vtResolveStyles`. Empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtExportSvg<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    palette: JIntArray<'a>,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(palette) = Palette::from_java(&env, &palette) else {
+            return JString::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let svg = frame(&vt.screen(), &palette);
+        env.new_string(svg).unwrap_or_default()
+    })
+}
+
+/// The cast `cast_handle` at `time_micros` of playback time as an SVG
+/// document, replayed on a VT that lives only for the call. Empty for an
+/// invalid handle or time.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_castExportSvg<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    cast_handle: jlong,
+    time_micros: jlong,
+    palette: JIntArray<'a>,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(time_us) = micros_arg(time_micros) else {
+            return JString::default();
+        };
+        let Some(palette) = Palette::from_java(&env, &palette) else {
+            return JString::default();
+        };
+        let Some(cast) = handles::object_ref::<Cast>(&mut env, cast_handle) else {
+            return JString::default();
+        };
+
+        let mut vt = AvtState::new(cast.header.cols, cast.header.rows);
+        player::seek(&mut vt, cast, time_us);
+        let svg = frame(&vt.screen(), &palette);
+        env.new_string(svg).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{Color, Cursor, Line, Run};
+
+    #[test]
+    fn runs_become_rects_and_text() {
+        let run = |col: usize, text: &str, style: Style, link: u32| Run {
+            col,
+            text: text.to_string(),
+            cells: text.chars().count(),
+            style,
+            link,
+        };
+        let red_on_blue = Style {
+            fg: Color::Indexed(1),
+            bg: Color::Indexed(4),
+            attrs: ATTR_BOLD,
+        };
+        let screen = Screen {
+            cols: 6,
+            rows: 2,
+            cursor: Cursor {
+                col: 1,
+                row: 1,
+                ..Cursor::default()
+            },
+            lines: vec![
+                Line {
+                    attr: Default::default(),
+                    runs: vec![
+                        run(0, "a<b", red_on_blue, 0),
+                        run(3, "   ", Style::default(), 0),
+                    ],
+                },
+                Line {
+                    attr: Default::default(),
+                    runs: vec![run(0, "link", Style::default(), 1)],
+                },
+            ],
+            links: vec![(1, "https://example.com/?a=1&b=\"2\"".to_string())],
+            alt_screen: false,
+            images: Vec::new(),
+        };
+        let svg = frame(&screen, &Palette::default());
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"54\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("<rect x=\"0\" y=\"0\" width=\"27\" height=\"18\" fill=\"#0000ee\"/>"));
+        assert!(svg.contains(
+            "<text x=\"0\" y=\"14\" fill=\"#cd0000\" font-weight=\"bold\">a&lt;b</text>"
+        ));
+        assert_eq!(svg.matches("<text").count(), 3);
+        assert!(svg.contains(
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\"><text x=\"0\" y=\"32\""
+        ));
+        // A block cursor over the i, which is redrawn in the background color
+        assert!(svg.contains("<rect x=\"9\" y=\"18\" width=\"9\" height=\"18\" fill=\"#cccccc\"/>"));
+        assert!(svg.contains("<text x=\"9\" y=\"32\" fill=\"#000000\">i</text>"));
+    }
+}
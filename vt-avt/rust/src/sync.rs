@@ -0,0 +1,217 @@
+//! State sync over lossy links, after mosh's state synchronization.
+//!
+//! The sender doesn't stream output; it sends frames, each a `delta`
+//! against the newest screen the receiver has acknowledged, and the
+//! receiver acks every frame it applies. A lost frame costs nothing extra:
+//! the next one is still against the acked screen, so it carries the lost
+//! changes too. Nothing needs resending and the receiver never replays
+//! stale output, it only jumps to the newest state it has seen.
+//!
+//! On the wire a frame is exactly a delta (see `delta`) and an ack is its
+//! `seq`. The transport, framing and ack packets are up to the caller; a
+//! companion server runs a `Sender`, the app a `Receiver`.
+
+use crate::delta::{self, History};
+use crate::handles::{self, Kind};
+use crate::snapshot::{DecodeError, Screen};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use std::collections::VecDeque;
+
+/// Screens a receiver keeps as possible baselines; the sender's acked one
+/// is always among them unless acks run this far behind
+const KEPT: usize = 16;
+
+#[derive(Default)]
+pub struct Sender {
+    history: History,
+}
+
+impl Sender {
+    /// A frame bringing the receiver from its last acked screen to
+    /// `screen` (every row when nothing was acked yet).
+    pub fn frame(&mut self, screen: &Screen) -> Vec<u8> {
+        let acked = self.history.acked();
+        self.history.delta(screen, acked)
+    }
+
+    /// The receiver applied frame `seq`. Returns false for unknown or
+    /// stale acks, which are harmless.
+    pub fn ack(&mut self, seq: u64) -> bool {
+        self.history.ack(seq)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    Decode(DecodeError),
+    /// The frame's baseline isn't kept: wait for a later frame
+    UnknownBaseline(u64),
+}
+
+impl From<DecodeError> for SyncError {
+    fn from(e: DecodeError) -> Self {
+        SyncError::Decode(e)
+    }
+}
+
+#[derive(Default)]
+pub struct Receiver {
+    /// Newest last
+    screens: VecDeque<(u64, Screen)>,
+}
+
+registered!(Receiver, Kind::SyncReceiver);
+
+impl Receiver {
+    /// The newest screen received, if any.
+    pub fn screen(&self) -> Option<&Screen> {
+        self.screens.back().map(|(_, screen)| screen)
+    }
+
+    /// Apply a frame. Returns the `seq` to ack, or `None` for a frame older
+    /// than the current screen (reordered by the link), which is dropped.
+    pub fn apply(&mut self, frame: &[u8]) -> Result<Option<u64>, SyncError> {
+        let delta = delta::decode(frame)?;
+        if self
+            .screens
+            .back()
+            .is_some_and(|(seq, _)| *seq >= delta.seq)
+        {
+            return Ok(None);
+        }
+
+        let screen = if delta.baseline == 0 {
+            let empty = Screen {
+                cols: 0,
+                rows: 0,
+                cursor: delta.cursor,
+                lines: Vec::new(),
+                links: Vec::new(),
+                alt_screen: false,
+                images: Vec::new(),
+            };
+            delta.apply(&empty)
+        } else {
+            let (_, baseline) = self
+                .screens
+                .iter()
+                .find(|(seq, _)| *seq == delta.baseline)
+                .ok_or(SyncError::UnknownBaseline(delta.baseline))?;
+            delta.apply(baseline)
+        };
+
+        if self.screens.len() == KEPT {
+            self.screens.pop_front();
+        }
+        self.screens.push_back((delta.seq, screen));
+        Ok(Some(delta.seq))
+    }
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_syncReceiverNew(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jlong {
+    jni_guard!(env, {
+        handles::add(Receiver::default())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_syncReceiverFree(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    jni_guard!(env, {
+        drop(handles::take::<Receiver>(&mut env, handle));
+    })
+}
+
+/// Apply a frame from a sender. Returns the seq to ack, 0 for a stale
+/// frame (nothing to ack), or -1 for a corrupt frame or unknown baseline.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_syncReceiverApply(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    frame: JByteArray,
+) -> jlong {
+    jni_guard!(env, {
+        let Some(receiver) = handles::object::<Receiver>(&mut env, handle) else {
+            return -1;
+        };
+
+        let Ok(frame) = env.convert_byte_array(frame) else {
+            return -1;
+        };
+        match receiver.apply(&frame) {
+            Ok(seq) => seq.unwrap_or(0) as jlong,
+            Err(_) => -1,
+        }
+    })
+}
+
+/// The newest screen in the snapshot format, or empty before any frame.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_syncReceiverSnapshot<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let Some(receiver) = handles::object_ref::<Receiver>(&mut env, handle) else {
+            return JByteArray::default();
+        };
+
+        let bytes = receiver.screen().map(Screen::encode).unwrap_or_default();
+        env.byte_array_from_slice(&bytes).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    #[test]
+    fn lost_and_reordered_frames_still_converge() {
+        let mut vt = AvtState::with_backend(fake(6, 2));
+        let mut sender = Sender::default();
+        let mut receiver = Receiver::default();
+
+        let first = sender.frame(&vt.screen());
+        let seq = receiver.apply(&first).unwrap().unwrap();
+        assert!(sender.ack(seq));
+
+        vt.feed(b"ab");
+        let _lost = sender.frame(&vt.screen());
+        vt.feed(b"cd");
+        let late = sender.frame(&vt.screen());
+        vt.feed(b"ef");
+        let newest = sender.frame(&vt.screen());
+
+        // `lost` never arrives and `late` arrives after `newest`; every
+        // frame is against the acked screen, so `newest` alone suffices
+        let seq = receiver.apply(&newest).unwrap().unwrap();
+        assert_eq!(receiver.screen(), Some(&vt.screen()));
+        assert_eq!(receiver.apply(&late), Ok(None));
+        assert!(sender.ack(seq));
+        assert!(!sender.ack(seq));
+
+        // Frames after the ack are against it and carry only new rows
+        let frame = delta::decode(&sender.frame(&vt.screen())).unwrap();
+        assert_eq!((frame.baseline, frame.lines.len()), (seq, 0));
+
+        // A fresh receiver can't use a delta, and says so
+        let mut other = Receiver::default();
+        let err = other.apply(&sender.frame(&vt.screen()));
+        assert_eq!(err, Err(SyncError::UnknownBaseline(seq)));
+    }
+}
@@ -0,0 +1,99 @@
+//! Plain text of a recording's output, for searching and comparing
+//! recordings without running a terminal.
+
+use crate::cast::{Cast, EventKind};
+use crate::scan::Scanner;
+
+/// A line of output, timed by its first printed character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextLine {
+    pub time_us: i64,
+    pub text: String,
+}
+
+/// Lines of text the recording printed. Not a full emulation: escape
+/// sequences are dropped, a carriage return followed by more text replaces
+/// the line (spinners, progress bars) and backspace removes a character.
+/// Blank lines are skipped.
+pub fn text_lines(cast: &Cast) -> Vec<TextLine> {
+    let mut scanner = Scanner::new();
+    let mut lines = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut start_us = 0;
+    let mut overwrite = false;
+
+    let mut finish = |line: &mut Vec<u8>, start_us: i64| {
+        let text = String::from_utf8_lossy(line).trim_end().to_string();
+        if !text.trim_start().is_empty() {
+            lines.push(TextLine {
+                time_us: start_us,
+                text,
+            });
+        }
+        line.clear();
+    };
+
+    for event in &cast.events {
+        let EventKind::Output(data) = &event.kind else {
+            continue;
+        };
+        for (i, &b) in data.as_bytes().iter().enumerate() {
+            let ground = scanner.is_ground();
+            scanner.scan(&data.as_bytes()[i..=i], |_, _| {});
+            if !ground {
+                continue;
+            }
+            match b {
+                b'\n' => {
+                    finish(&mut line, start_us);
+                    overwrite = false;
+                }
+                b'\r' => overwrite = true,
+                0x08 => while line.pop().is_some_and(|b| b & 0xc0 == 0x80) {},
+                0x20.. if b != 0x7f => {
+                    if overwrite {
+                        line.clear();
+                        overwrite = false;
+                    }
+                    if line.is_empty() {
+                        start_us = event.time_us;
+                    }
+                    line.push(b);
+                }
+                _ => {}
+            }
+        }
+    }
+    finish(&mut line, start_us);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_text_lines() {
+        let cast = Cast::parse(
+            b"{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+            [0.5, \"o\", \"$ \\u001b[1mmake\\u001b[0m\\r\\n\"]\n\
+            [1.0, \"o\", \"\\r[=>  ] 10%\"]\n\
+            [2.0, \"o\", \"\\r[===>] 90%\\r\\n\"]\n\
+            [2.5, \"i\", \"x\"]\n\
+            [3.0, \"o\", \"\\r\\n\\u001b[31merror: oops\\u001b[0m\\r\\n\"]\n",
+        )
+        .unwrap();
+        let lines: Vec<_> = text_lines(&cast)
+            .into_iter()
+            .map(|l| (l.time_us, l.text))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (500_000, "$ make".to_string()),
+                (2_000_000, "[===>] 90%".to_string()),
+                (3_000_000, "error: oops".to_string()),
+            ]
+        );
+    }
+}
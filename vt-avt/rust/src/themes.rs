@@ -0,0 +1,334 @@
+//! Named color themes, and screens with their indexed colors resolved.
+//!
+//! Renderers and exporters each take a `Palette`, so a recording only
+//! looks the same in the player, a GIF and an SVG if every one of them is
+//! given the same colors. Themes are kept here by name instead: the
+//! built-ins below (the player's themes), and any the app registers with
+//! `themeRegister`, which replaces a theme of the same name. `themePalette`
+//! gives a theme in the layout the exporters take.
+//!
+//! A VT can also resolve colors itself: after `vtSetTheme`, indexed colors
+//! in its snapshots and diffs are the theme's RGB, so a client needs no
+//! palette at all. Default colors stay default, for the client's own
+//! background. The VT keeps a copy, so registering the theme again doesn't
+//! change it.
+//!
+//! Bold-as-bright is a VT option too: after `vtSetBoldAsBright`, bold text
+//! in colors 0-7 is in 8-15, indexed or resolved, so a client's own
+//! resolver should leave it off. Either option redraws every row when
+//! changed, once, and queues `VtEvent::OptionChanged`, so a live player
+//! takes a new theme at its next diff.
+
+use crate::palette::Palette;
+use crate::snapshot::{Color, Screen, ATTR_BOLD};
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JIntArray, JString};
+use jni::sys::{jboolean, jint, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::sync::Mutex;
+
+/// `(name, fg, bg, colors 0-15)` as `0xRRGGBB`
+const BUILT_IN: [(&str, u32, u32, [u32; 16]); 5] = [
+    (
+        "asciinema",
+        0xcccccc,
+        0x121314,
+        [
+            0x000000, 0xdd3c69, 0x4ebf22, 0xddaf3c, 0x26b0d7, 0xb954e1, 0x54e1b9, 0xd9d9d9,
+            0x4d4d4d, 0xdd3c69, 0x4ebf22, 0xddaf3c, 0x26b0d7, 0xb954e1, 0x54e1b9, 0xffffff,
+        ],
+    ),
+    (
+        "dracula",
+        0xf8f8f2,
+        0x282a36,
+        [
+            0x21222c, 0xff5555, 0x50fa7b, 0xf1fa8c, 0xbd93f9, 0xff79c6, 0x8be9fd, 0xf8f8f2,
+            0x6272a4, 0xff6e6e, 0x69ff94, 0xffffa5, 0xd6acff, 0xff92df, 0xa4ffff, 0xffffff,
+        ],
+    ),
+    (
+        "monokai",
+        0xf8f8f2,
+        0x272822,
+        [
+            0x272822, 0xf92672, 0xa6e22e, 0xf4bf75, 0x66d9ef, 0xae81ff, 0xa1efe4, 0xf8f8f2,
+            0x75715e, 0xf92672, 0xa6e22e, 0xf4bf75, 0x66d9ef, 0xae81ff, 0xa1efe4, 0xf9f8f5,
+        ],
+    ),
+    ("solarized-dark", 0x839496, 0x002b36, SOLARIZED),
+    ("solarized-light", 0x657b83, 0xfdf6e3, SOLARIZED),
+];
+
+const SOLARIZED: [u32; 16] = [
+    0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5, 0x002b36,
+    0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+];
+
+/// Themes the app registered, by name
+static REGISTERED: Mutex<Vec<(String, Palette)>> = Mutex::new(Vec::new());
+
+/// `colors` (up to 16) over the xterm palette, with `fg` and `bg`.
+pub fn palette(fg: u32, bg: u32, colors: &[u32]) -> Palette {
+    let mut palette = Palette {
+        fg: fg & 0xffffff,
+        bg: bg & 0xffffff,
+        ..Palette::default()
+    };
+    for (slot, &color) in palette.colors.iter_mut().zip(colors.iter().take(16)) {
+        *slot = color & 0xffffff;
+    }
+    palette
+}
+
+/// Register theme `name`, replacing any theme of that name.
+pub fn register(name: &str, palette: Palette) {
+    let mut registered = REGISTERED.lock().unwrap_or_else(|e| e.into_inner());
+    registered.retain(|(other, _)| other != name);
+    registered.push((name.to_string(), palette));
+}
+
+/// Theme `name`, registered or built in.
+pub fn get(name: &str) -> Option<Palette> {
+    let registered = REGISTERED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, palette)) = registered.iter().find(|(other, _)| other == name) {
+        return Some(palette.clone());
+    }
+    BUILT_IN
+        .iter()
+        .find(|(other, ..)| *other == name)
+        .map(|&(_, fg, bg, colors)| self::palette(fg, bg, &colors))
+}
+
+/// Replace `screen`'s indexed colors with `palette`'s.
+pub fn resolve_indexed(screen: &mut Screen, palette: &Palette) {
+    let rgb = |color: &mut Color| {
+        if let Color::Indexed(idx) = *color {
+            let value = palette.colors[idx as usize];
+            *color = Color::Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8);
+        }
+    };
+    for run in screen.lines.iter_mut().flat_map(|line| &mut line.runs) {
+        rgb(&mut run.style.fg);
+        rgb(&mut run.style.bg);
+    }
+}
+
+/// Move bold text in `screen` from colors 0-7 to 8-15.
+pub fn brighten_bold(screen: &mut Screen) {
+    for run in screen.lines.iter_mut().flat_map(|line| &mut line.runs) {
+        if let Color::Indexed(idx @ 0..=7) = run.style.fg {
+            if run.style.attrs & ATTR_BOLD != 0 {
+                run.style.fg = Color::Indexed(idx + 8);
+            }
+        }
+    }
+}
+
+/// `name` as a Rust string; `None` for null or an unreadable string.
+pub(crate) fn name_arg(env: &mut JNIEnv, name: &JString) -> Option<String> {
+    if name.is_null() {
+        return None;
+    }
+    env.get_string(name).ok().map(Into::into)
+}
+
+// JNI functions
+
+/// Register theme `name` with colors 0-15 from `palette16` (fewer keep the
+/// xterm ones, 16-255 always do). False for an empty name or an unreadable
+/// array.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_themeRegister(
+    mut env: JNIEnv,
+    _class: JClass,
+    name: JString,
+    palette16: JIntArray,
+    fg: jint,
+    bg: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(name) = name_arg(&mut env, &name).filter(|name| !name.is_empty()) else {
+            return JNI_FALSE;
+        };
+        let Ok(len) = env.get_array_length(&palette16) else {
+            return JNI_FALSE;
+        };
+        let mut colors = vec![0; (len as usize).min(16)];
+        if env
+            .get_int_array_region(&palette16, 0, &mut colors)
+            .is_err()
+        {
+            return JNI_FALSE;
+        }
+
+        let colors: Vec<u32> = colors.iter().map(|&color| color as u32).collect();
+        register(&name, palette(fg as u32, bg as u32, &colors));
+        JNI_TRUE
+    })
+}
+
+/// Theme `name` as `[fg, bg, color0, ..., color255]`, the palette layout
+/// the renderer and exporters take; empty if there is none.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_themePalette<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    name: JString<'a>,
+) -> JIntArray<'a> {
+    jni_guard!(env, {
+        let Some(palette) = name_arg(&mut env, &name).and_then(|name| get(&name)) else {
+            return JIntArray::default();
+        };
+
+        let values: Vec<jint> = [palette.fg, palette.bg]
+            .iter()
+            .chain(&palette.colors)
+            .map(|&color| color as jint)
+            .collect();
+        crate::int_array(&env, &values)
+    })
+}
+
+/// Resolve indexed colors in snapshots and diffs with theme `name`, or
+/// not at all for null, the default. Kept across resets. False for an
+/// invalid handle or an unknown theme, which leaves the last one.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetTheme(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    name: JString,
+) -> jboolean {
+    jni_guard!(env, {
+        let theme = if name.is_null() {
+            None
+        } else {
+            let Some(theme) = name_arg(&mut env, &name).and_then(|name| get(&name)) else {
+                return JNI_FALSE;
+            };
+            Some(theme)
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        vt.set_theme(theme);
+        JNI_TRUE
+    })
+}
+
+/// Show bold text in colors 0-7 as 8-15, with or without a theme. Off by
+/// default, kept across resets. False for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSetBoldAsBright(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    on: jboolean,
+) -> jboolean {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        vt.set_bold_as_bright(on != JNI_FALSE);
+        JNI_TRUE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::events::{self, VtEvent};
+    use crate::snapshot::Style;
+    use crate::AvtState;
+
+    #[test]
+    fn registered_themes_replace_and_keep_xterm_defaults() {
+        let dracula = get("dracula").unwrap();
+        assert_eq!((dracula.fg, dracula.bg), (0xf8f8f2, 0x282a36));
+        assert_eq!(dracula.colors[1], 0xff5555);
+        assert_eq!(dracula.colors[196], Palette::default().colors[196]);
+        assert_eq!(get("no such theme"), None);
+
+        register("test-custom", palette(0x111111, 0x222222, &[0x333333]));
+        register("test-custom", palette(0x444444, 0x555555, &[0x666666]));
+        let custom = get("test-custom").unwrap();
+        assert_eq!(
+            (custom.fg, custom.bg, custom.colors[0]),
+            (0x444444, 0x555555, 0x666666)
+        );
+        assert_eq!(custom.colors[1], Palette::default().colors[1]);
+    }
+
+    #[test]
+    fn themed_screens_have_indexed_colors_as_rgb() {
+        let mut vt = AvtState::with_backend(fake(4, 1));
+        vt.feed(b"ab");
+        let mut screen = vt.screen();
+        screen.lines[0].runs[0].style = Style {
+            fg: Color::Indexed(1),
+            bg: Color::Default,
+            attrs: 0,
+        };
+        resolve_indexed(&mut screen, &get("monokai").unwrap());
+        let style = screen.lines[0].runs[0].style;
+        assert_eq!(
+            (style.fg, style.bg),
+            (Color::Rgb(0xf9, 0x26, 0x72), Color::Default)
+        );
+
+        // Changing the theme redraws everything
+        vt.poll_diff();
+        vt.set_theme(get("monokai"));
+        let diff = crate::diff::decode(&vt.poll_diff().unwrap()).unwrap();
+        assert_eq!(diff.lines, [0]);
+    }
+
+    #[test]
+    fn option_changes_redraw_once_and_are_announced() {
+        let mut screen = AvtState::with_backend(fake(4, 1)).screen();
+        let style = |fg, attrs| Style {
+            fg,
+            bg: Color::Indexed(1),
+            attrs,
+        };
+        screen.lines[0].runs[0].style = style(Color::Indexed(1), ATTR_BOLD);
+        brighten_bold(&mut screen);
+        assert_eq!(
+            screen.lines[0].runs[0].style,
+            style(Color::Indexed(9), ATTR_BOLD)
+        );
+        // Only bold foregrounds in 0-7
+        for unchanged in [
+            style(Color::Indexed(1), 0),
+            style(Color::Indexed(9), ATTR_BOLD),
+        ] {
+            screen.lines[0].runs[0].style = unchanged;
+            brighten_bold(&mut screen);
+            assert_eq!(screen.lines[0].runs[0].style, unchanged);
+        }
+
+        let mut vt = AvtState::with_backend(fake(4, 2));
+        vt.poll_diff();
+        vt.set_bold_as_bright(true);
+        vt.set_theme(get("dracula"));
+        let diff = crate::diff::decode(&vt.poll_diff().unwrap()).unwrap();
+        assert_eq!(diff.lines, [0, 1]);
+        assert_eq!(
+            vt.take_events(),
+            [
+                VtEvent::OptionChanged(events::OPTION_BOLD_AS_BRIGHT),
+                VtEvent::OptionChanged(events::OPTION_THEME),
+            ]
+        );
+
+        // Setting what's already set does nothing
+        vt.set_bold_as_bright(true);
+        vt.set_theme(get("dracula"));
+        assert_eq!(vt.poll_diff(), None);
+        assert!(vt.take_events().is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! Diff rate limiting and idle detection.
+//!
+//! Callers poll `vtPollDiff` on a timer. The update budget caps how often a
+//! poll actually returns a diff (changes in between are coalesced into the
+//! next one), and idle detection tells the app when output has been static
+//! long enough to drop to a slower polling rate, and, after a longer quiet
+//! period, to give back the memory a burst of output left allocated.
+
+use crate::Instant;
+use std::time::Duration;
+
+/// Quiet time after which `AvtState` compacts its buffers on its own
+pub const COMPACT_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    min_interval: Option<Duration>,
+    idle_after: Option<Duration>,
+    last_diff: Option<Instant>,
+    last_change: Instant,
+    idle_reported: bool,
+    compacted: bool,
+}
+
+impl Throttle {
+    pub fn new(now: Instant) -> Self {
+        Throttle {
+            min_interval: None,
+            idle_after: None,
+            last_diff: None,
+            last_change: now,
+            idle_reported: false,
+            compacted: false,
+        }
+    }
+
+    /// At most `max_per_second` diffs per second; 0 removes the limit.
+    pub fn set_budget(&mut self, max_per_second: u32) {
+        self.min_interval = match max_per_second {
+            0 => None,
+            n => Some(Duration::from_secs(1) / n),
+        };
+    }
+
+    /// Report idle after `millis` without changes; 0 disables detection.
+    pub fn set_idle_timeout(&mut self, millis: u32) {
+        self.idle_after = match millis {
+            0 => None,
+            ms => Some(Duration::from_millis(ms.into())),
+        };
+        self.idle_reported = false;
+    }
+
+    pub fn note_change(&mut self, now: Instant) {
+        self.last_change = now;
+        self.idle_reported = false;
+        self.compacted = false;
+    }
+
+    /// Whether a pending diff may be emitted now. Records the emission
+    /// when it may.
+    pub fn take_diff(&mut self, now: Instant) -> bool {
+        if let (Some(interval), Some(last)) = (self.min_interval, self.last_diff) {
+            if now.saturating_duration_since(last) < interval {
+                return false;
+            }
+        }
+        self.last_diff = Some(now);
+        true
+    }
+
+    /// True exactly once per quiet period, the first time this is polled
+    /// after the idle timeout has elapsed since the last change.
+    pub fn poll_idle(&mut self, now: Instant) -> bool {
+        let Some(idle_after) = self.idle_after else {
+            return false;
+        };
+        if self.idle_reported || now.saturating_duration_since(self.last_change) < idle_after {
+            return false;
+        }
+        self.idle_reported = true;
+        true
+    }
+
+    /// Like `poll_idle`, for `COMPACT_AFTER` without changes.
+    pub fn poll_compact(&mut self, now: Instant) -> bool {
+        if self.compacted || now.saturating_duration_since(self.last_change) < COMPACT_AFTER {
+            return false;
+        }
+        self.compacted = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn budget_spaces_out_diffs() {
+        let t0 = Instant::now();
+        let mut throttle = Throttle::new(t0);
+        assert!(throttle.take_diff(t0));
+        assert!(throttle.take_diff(t0));
+
+        throttle.set_budget(10);
+        assert!(!throttle.take_diff(t0 + ms(50)));
+        assert!(throttle.take_diff(t0 + ms(100)));
+        assert!(!throttle.take_diff(t0 + ms(150)));
+
+        throttle.set_budget(0);
+        assert!(throttle.take_diff(t0 + ms(151)));
+    }
+
+    #[test]
+    fn idle_is_reported_once_per_quiet_period() {
+        let t0 = Instant::now();
+        let mut throttle = Throttle::new(t0);
+        assert!(!throttle.poll_idle(t0 + ms(10_000)));
+
+        throttle.set_idle_timeout(500);
+        assert!(!throttle.poll_idle(t0 + ms(499)));
+        throttle.note_change(t0 + ms(1_000));
+        assert!(!throttle.poll_idle(t0 + ms(1_400)));
+        assert!(throttle.poll_idle(t0 + ms(1_500)));
+        assert!(!throttle.poll_idle(t0 + ms(2_000)));
+
+        throttle.note_change(t0 + ms(2_100));
+        assert!(throttle.poll_idle(t0 + ms(2_600)));
+
+        // Compaction comes once per quiet period too, with or without idle
+        assert!(!throttle.poll_compact(t0 + ms(2_100) + COMPACT_AFTER / 2));
+        assert!(throttle.poll_compact(t0 + ms(2_100) + COMPACT_AFTER));
+        assert!(!throttle.poll_compact(t0 + ms(2_100) + COMPACT_AFTER * 2));
+    }
+}
@@ -0,0 +1,164 @@
+//! Throughput counters for live and interactive streams.
+//!
+//! Every feed is counted as one event of its byte length, in one-second
+//! buckets covering the last minute. That is enough for a throughput
+//! sparkline, a current rate (averaged over a few seconds so one burst
+//! doesn't spike it) and the time since the last output, which is how the
+//! app tells a stalled connection from a merely quiet one.
+//!
+//! Bytes the VT drops (NUL padding and the like) are counted separately
+//! too, as a diagnostic for recordings that are mostly noise.
+
+use crate::Instant;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Seconds of history kept
+pub const HISTORY: usize = 60;
+
+/// Seconds the current rate is averaged over
+const RATE_WINDOW: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bucket {
+    pub bytes: u64,
+    pub events: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Traffic {
+    start: Instant,
+    /// (second since `start`, counts), oldest first, no empty seconds
+    buckets: VecDeque<(u64, Bucket)>,
+    last: Option<Instant>,
+    /// Bytes dropped as `scan::is_ignored`, since `start`
+    ignored: u64,
+}
+
+impl Traffic {
+    pub fn new(now: Instant) -> Self {
+        Traffic {
+            start: now,
+            buckets: VecDeque::new(),
+            last: None,
+            ignored: 0,
+        }
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    /// Count a feed at `now`, or at the last one's time if that is later.
+    pub fn record(&mut self, now: Instant, bytes: usize) {
+        let now = self.last.map_or(now, |last| now.max(last));
+        let second = self.second(now);
+        match self.buckets.back_mut() {
+            Some((s, bucket)) if *s == second => {
+                bucket.bytes += bytes as u64;
+                bucket.events += 1;
+            }
+            _ => self.buckets.push_back((
+                second,
+                Bucket {
+                    bytes: bytes as u64,
+                    events: 1,
+                },
+            )),
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(s, _)| *s + (HISTORY as u64) <= second)
+        {
+            self.buckets.pop_front();
+        }
+        self.last = Some(now);
+    }
+
+    pub fn record_ignored(&mut self, bytes: usize) {
+        self.ignored += bytes as u64;
+    }
+
+    pub fn ignored(&self) -> u64 {
+        self.ignored
+    }
+
+    /// The last `HISTORY` seconds, oldest first, the current (partial)
+    /// second last.
+    pub fn history(&self, now: Instant) -> Vec<Bucket> {
+        let current = self.second(now);
+        let first = (current + 1).saturating_sub(HISTORY as u64);
+        let mut out = vec![Bucket::default(); HISTORY];
+        for &(second, bucket) in &self.buckets {
+            if (first..=current).contains(&second) {
+                out[HISTORY - 1 - (current - second) as usize] = bucket;
+            }
+        }
+        out
+    }
+
+    /// Bytes and events per second over the last few whole seconds.
+    pub fn rates(&self, now: Instant) -> (f64, f64) {
+        let current = self.second(now);
+        let window = RATE_WINDOW.min(current).max(1);
+        let (bytes, events) = self
+            .buckets
+            .iter()
+            .filter(|(second, _)| *second < current && *second + window >= current)
+            .fold((0, 0), |(b, e), (_, bucket)| {
+                (b + bucket.bytes, e + bucket.events)
+            });
+        (bytes as f64 / window as f64, events as f64 / window as f64)
+    }
+
+    /// Time since the last feed, `None` before the first.
+    pub fn since_last(&self, now: Instant) -> Option<Duration> {
+        self.last.map(|last| now.saturating_duration_since(last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_second_and_ages_out() {
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let mut traffic = Traffic::new(t0);
+        assert_eq!(traffic.since_last(t0), None);
+
+        traffic.record(at(100), 10);
+        traffic.record(at(900), 30);
+        traffic.record(at(2_500), 5);
+
+        let history = traffic.history(at(2_600));
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(
+            history[HISTORY - 3..],
+            [
+                Bucket {
+                    bytes: 40,
+                    events: 2
+                },
+                Bucket::default(),
+                Bucket {
+                    bytes: 5,
+                    events: 1
+                },
+            ]
+        );
+
+        // Seconds 0 and 1 are complete; second 2 is still filling
+        assert_eq!(traffic.rates(at(2_600)), (20.0, 1.0));
+        assert_eq!(
+            traffic.since_last(at(3_000)),
+            Some(Duration::from_millis(500))
+        );
+
+        traffic.record(at(61_000), 1);
+        let history = traffic.history(at(61_000));
+        assert_eq!(history.iter().map(|b| b.bytes).sum::<u64>(), 6);
+    }
+}
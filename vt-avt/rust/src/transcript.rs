@@ -0,0 +1,357 @@
+//! Whole-history export for "save transcript".
+//!
+//! Scrollback can run to many thousands of lines, and `vtScrollbackLines`
+//! for all of it would copy the lot into one Java array. `vtExportScrollback`
+//! writes the scrollback, oldest first, and then the visible rows straight
+//! to a file descriptor (say a `ParcelFileDescriptor` for a document the
+//! user picked), one line at a time through a buffer of `CHUNK` bytes, so
+//! memory stays flat however long the history is.
+//!
+//! Formats:
+//!
+//! - `FORMAT_TEXT`: plain UTF-8 text
+//! - `FORMAT_ANSI`: text with SGR sequences, for `cat` or `less -R`
+//! - `FORMAT_HTML`: a standalone page, colors resolved with the default
+//!   palette (see `palette`)
+//!
+//! Trailing unstyled blanks are trimmed from every line.
+//!
+//! `vtExportHtml` and `vtExportAnsi` return the same, for the visible rows
+//! with or without the scrollback, as a string for "copy as rich text"; the
+//! HTML then takes the app's theme.
+
+use crate::backend::{Cell, TerminalBackend};
+use crate::lineattr::LineAttr;
+use crate::palette::{self, Palette};
+use crate::snapshot::{Line, Style};
+use crate::snapshot::{ATTR_BOLD, ATTR_FAINT, ATTR_ITALIC, ATTR_STRIKETHROUGH, ATTR_UNDERLINE};
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JIntArray, JString};
+use jni::sys::{jboolean, jint, jlong};
+use jni::JNIEnv;
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+
+pub const FORMAT_TEXT: i32 = 0;
+pub const FORMAT_ANSI: i32 = 1;
+pub const FORMAT_HTML: i32 = 2;
+
+/// Most bytes handed to the output in one write
+pub const CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Ansi,
+    Html,
+}
+
+impl Format {
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            FORMAT_TEXT => Some(Format::Text),
+            FORMAT_ANSI => Some(Format::Ansi),
+            FORMAT_HTML => Some(Format::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Write the scrollback and then the visible rows of `backend` to `out`.
+/// Returns the bytes written.
+pub fn export(backend: &impl TerminalBackend, format: Format, out: impl Write) -> io::Result<u64> {
+    export_with(backend, format, true, &Palette::default(), out)
+}
+
+/// `export`, leaving out the scrollback unless `with_scrollback`, with
+/// HTML colors resolved with `palette`.
+pub fn export_with(
+    backend: &impl TerminalBackend,
+    format: Format,
+    with_scrollback: bool,
+    palette: &Palette,
+    out: impl Write,
+) -> io::Result<u64> {
+    let mut out = BufWriter::with_capacity(CHUNK, out);
+    let scrollback = backend.scrollback_len();
+    let (_, rows) = backend.size();
+    let first = if with_scrollback { 0 } else { scrollback };
+    let mut cells = Vec::new();
+    let mut buf = String::new();
+    let mut written = 0;
+
+    if format == Format::Html {
+        let _ = write!(
+            buf,
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n\
+             <pre style=\"color:#{:06x};background-color:#{:06x}\">\n",
+            palette.fg, palette.bg
+        );
+    }
+    for index in first..scrollback + rows {
+        if index < scrollback {
+            backend.scrollback_cells(index, &mut cells);
+        } else {
+            backend.row_cells(index - scrollback, &mut cells);
+        }
+        while cells.last().is_some_and(is_trimmed) {
+            cells.pop();
+        }
+        let line = Line::of_cells(LineAttr::Single, &cells);
+        match format {
+            Format::Text => line.runs.iter().for_each(|run| buf.push_str(&run.text)),
+            Format::Ansi => push_ansi(&line, &mut buf),
+            Format::Html => push_html(&line, palette, &mut buf),
+        }
+        buf.push('\n');
+        if index + 1 == scrollback + rows && format == Format::Html {
+            buf.push_str("</pre>\n</body>\n</html>\n");
+        }
+
+        out.write_all(buf.as_bytes())?;
+        written += buf.len() as u64;
+        buf.clear();
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+fn is_trimmed(cell: &Cell) -> bool {
+    cell.ch == ' ' && cell.style == Style::default()
+}
+
+fn push_ansi(line: &Line, out: &mut String) {
+    let mut styled = false;
+    for run in &line.runs {
+        if run.style != Style::default() {
+            run.style.push_sgr(out);
+            styled = true;
+        } else if styled {
+            out.push_str("\x1b[0m");
+            styled = false;
+        }
+        out.push_str(&run.text);
+    }
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+}
+
+fn push_html(line: &Line, palette: &Palette, out: &mut String) {
+    let options = palette::Options::new(0, 1.0);
+    for run in &line.runs {
+        if run.style == Style::default() {
+            push_escaped(&run.text, out);
+            continue;
+        }
+
+        let resolved = palette::resolve(run.style, palette, &options);
+        let _ = write!(out, "<span style=\"color:#{:06x}", resolved.fg & 0xffffff);
+        if resolved.bg & 0xffffff != palette.bg {
+            let _ = write!(out, ";background-color:#{:06x}", resolved.bg & 0xffffff);
+        }
+        if resolved.attrs & ATTR_BOLD != 0 {
+            out.push_str(";font-weight:bold");
+        }
+        if resolved.attrs & ATTR_FAINT != 0 {
+            out.push_str(";opacity:0.5");
+        }
+        if resolved.attrs & ATTR_ITALIC != 0 {
+            out.push_str(";font-style:italic");
+        }
+        match (
+            resolved.attrs & ATTR_UNDERLINE != 0,
+            resolved.attrs & ATTR_STRIKETHROUGH != 0,
+        ) {
+            (true, true) => out.push_str(";text-decoration:underline line-through"),
+            (true, false) => out.push_str(";text-decoration:underline"),
+            (false, true) => out.push_str(";text-decoration:line-through"),
+            (false, false) => {}
+        }
+        out.push_str("\">");
+        push_escaped(&run.text, out);
+        out.push_str("</span>");
+    }
+}
+
+pub(crate) fn push_escaped(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+// JNI functions
+
+/// Export the whole history to `fd`, as one of the `FORMAT_*` codes. The
+/// descriptor stays open; the caller closes it. Returns the bytes written,
+/// or -1 for an invalid handle, descriptor or format, or a failed write
+/// (after which the file holds part of the export).
+#[cfg(unix)]
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtExportScrollback(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    fd: jint,
+    format: jint,
+) -> jlong {
+    use std::fs::File;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    jni_guard!(env, {
+        let Some(format) = Format::from_code(format) else {
+            return -1;
+        };
+        if fd < 0 {
+            return -1;
+        }
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return -1;
+        };
+
+        // Borrowed: dropping the File must not close the caller's descriptor
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        match export(vt.backend(), format, &*file) {
+            Ok(written) => written.min(jlong::MAX as u64) as jlong,
+            Err(_) => -1,
+        }
+    })
+}
+
+/// The visible rows, after the scrollback if `with_scrollback`, as a
+/// standalone HTML page with `palette` as for `vtResolveStyles`. Empty for
+/// an invalid handle or palette.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtExportHtml<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    palette: JIntArray<'a>,
+    with_scrollback: jboolean,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(palette) = Palette::from_java(&env, &palette) else {
+            return JString::default();
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let html = exported(vt.backend(), Format::Html, with_scrollback != 0, &palette);
+        env.new_string(html).unwrap_or_default()
+    })
+}
+
+/// The visible rows, after the scrollback if `with_scrollback`, as text
+/// with SGR sequences. Empty for an invalid handle.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtExportAnsi<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: VtHandle,
+    with_scrollback: jboolean,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        let palette = Palette::default();
+        let ansi = exported(vt.backend(), Format::Ansi, with_scrollback != 0, &palette);
+        env.new_string(ansi).unwrap_or_default()
+    })
+}
+
+fn exported(
+    backend: &impl TerminalBackend,
+    format: Format,
+    with_scrollback: bool,
+    palette: &Palette,
+) -> String {
+    let mut out = Vec::new();
+    // Writing to memory can't fail, and every line is a String
+    let _ = export_with(backend, format, with_scrollback, palette, &mut out);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::snapshot::Color;
+    use crate::AvtState;
+
+    fn exported(vt: &AvtState<impl TerminalBackend>, format: Format) -> String {
+        let mut out = Vec::new();
+        let written = export(vt.backend(), format, &mut out).unwrap();
+        assert_eq!(written, out.len() as u64);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn scrollback_comes_before_the_screen() {
+        let mut backend = fake(6, 2);
+        backend.scrollback = ["one   ", "<&>   "].map(String::from).to_vec();
+        let mut vt = AvtState::with_backend(backend);
+        vt.feed(b"hi");
+
+        assert_eq!(exported(&vt, Format::Text), "one\n<&>\nhi\n\n");
+        assert_eq!(exported(&vt, Format::Ansi), "one\n<&>\nhi\n\n");
+        let html = exported(&vt, Format::Html);
+        assert!(html.contains("\">\none\n&lt;&amp;&gt;\nhi\n\n</pre>"));
+        assert!(html.ends_with("</html>\n"));
+        assert_eq!(Format::from_code(3), None);
+
+        let palette = Palette::default();
+        let screen = super::exported(vt.backend(), Format::Ansi, false, &palette);
+        assert_eq!(screen, "hi\n\n");
+    }
+
+    #[test]
+    fn styles_become_sgr_and_css() {
+        let style = Style {
+            fg: Color::Indexed(9),
+            bg: Color::Rgb(1, 2, 3),
+            attrs: ATTR_BOLD | ATTR_UNDERLINE,
+        };
+        let mut sgr = String::new();
+        style.push_sgr(&mut sgr);
+        assert_eq!(sgr, "\x1b[0;1;4;91;48;2;1;2;3m");
+        sgr.clear();
+        Style {
+            fg: Color::Indexed(200),
+            bg: Color::Indexed(3),
+            attrs: ATTR_FAINT,
+        }
+        .push_sgr(&mut sgr);
+        assert_eq!(sgr, "\x1b[0;2;38;5;200;43m");
+
+        let line = Line {
+            attr: LineAttr::Single,
+            runs: vec![crate::snapshot::Run {
+                col: 0,
+                text: "x".into(),
+                cells: 1,
+                style,
+                link: 0,
+            }],
+        };
+        let mut html = String::new();
+        push_html(&line, &Palette::default(), &mut html);
+        assert_eq!(
+            html,
+            "<span style=\"color:#ff0000;background-color:#010203;font-weight:bold;\
+             text-decoration:underline\">x</span>"
+        );
+        let mut ansi = String::new();
+        push_ansi(&line, &mut ansi);
+        assert_eq!(ansi, "\x1b[0;1;4;91;48;2;1;2;3mx\x1b[0m");
+    }
+}
@@ -0,0 +1,244 @@
+//! Viewing metadata synced between devices: bookmarks, annotations and
+//! the user's own markers, in one versioned payload the app passes
+//! through its cloud backend. Indexes and other derived data stay behind;
+//! each device rebuilds those from the recording.
+//!
+//! ```text
+//! payload := version:u8 item_count item*
+//! item    := kind:u8 body_len body
+//! body    := cast_len cast id_len id modified_ms flags:u8 time_us
+//!            duration_us text_len text
+//! ```
+//!
+//! Varints are as in the snapshot format, except that `modified_ms`,
+//! `time_us` and `duration_us` may take up to 64 bits. `cast` is the hex
+//! SHA-256 of the recording (as in `library`), `id` a name for the item
+//! unique within its cast and kind, which the device creating it picks (a
+//! UUID), and `modified_ms` when it last changed, in Unix milliseconds.
+//! Every kind has the same body; `duration_us` is 0 for a point in time.
+//! As in `events`, decoders ignore body bytes past the fields they know,
+//! so bodies gain fields at the end without a version change, and `merge`
+//! keeps those bytes and any kinds it doesn't know.
+//!
+//! `merge` takes every item of either payload, and for an item both have
+//! (same cast, kind and id) the one modified last. A deletion is an item
+//! with `DELETED` set, kept like any other so it reaches every device; at
+//! equal `modified_ms` a deletion wins, then the greater body, which makes
+//! merging order-independent: devices that have seen the same payloads
+//! hold the same items whatever order they merged them in.
+
+use crate::snapshot::{DecodeError, Reader};
+use crate::{write_varint, write_varint_u64};
+use jni::objects::{JByteArray, JClass};
+use jni::JNIEnv;
+use std::collections::BTreeMap;
+
+/// Payload format version, the first byte
+pub const VERSION: u8 = 1;
+
+pub const KIND_BOOKMARK: u8 = 1;
+/// A note on a stretch of the recording, `duration_us` long
+pub const KIND_ANNOTATION: u8 = 2;
+/// A chapter marker the user added, beside those in the recording
+pub const KIND_MARKER: u8 = 3;
+
+/// `flags` bit of a deleted item
+pub const DELETED: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub kind: u8,
+    /// Hex SHA-256 of the recording
+    pub cast: String,
+    pub id: String,
+    /// Unix milliseconds
+    pub modified_ms: u64,
+    pub deleted: bool,
+    pub time_us: u64,
+    pub duration_us: u64,
+    pub text: String,
+    /// Body bytes past the fields above, from a newer writer
+    pub extra: Vec<u8>,
+}
+
+impl Item {
+    fn body(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(self.cast.len() + self.id.len() + self.text.len() + 16);
+        for field in [&self.cast, &self.id] {
+            write_varint(&mut body, field.len());
+            body.extend_from_slice(field.as_bytes());
+        }
+        write_varint_u64(&mut body, self.modified_ms);
+        body.push(if self.deleted { DELETED } else { 0 });
+        write_varint_u64(&mut body, self.time_us);
+        write_varint_u64(&mut body, self.duration_us);
+        write_varint(&mut body, self.text.len());
+        body.extend_from_slice(self.text.as_bytes());
+        body.extend_from_slice(&self.extra);
+        body
+    }
+
+    fn decode_body(kind: u8, body: &[u8]) -> Result<Item, DecodeError> {
+        let mut r = Reader::new(body);
+        let string = |r: &mut Reader| -> Result<String, DecodeError> {
+            let len = r.varint()?;
+            Ok(String::from_utf8_lossy(r.take(len)?).into_owned())
+        };
+        let cast = string(&mut r)?;
+        let id = string(&mut r)?;
+        let modified_ms = r.varint_u64()?;
+        let flags = r.byte()?;
+        let time_us = r.varint_u64()?;
+        let duration_us = r.varint_u64()?;
+        let text = string(&mut r)?;
+        Ok(Item {
+            kind,
+            cast,
+            id,
+            modified_ms,
+            deleted: flags & DELETED != 0,
+            time_us,
+            duration_us,
+            text,
+            extra: r.take(r.remaining())?.to_vec(),
+        })
+    }
+}
+
+pub fn encode(items: &[Item]) -> Vec<u8> {
+    let mut buf = vec![VERSION];
+    write_varint(&mut buf, items.len());
+    for item in items {
+        let body = item.body();
+        buf.push(item.kind);
+        write_varint(&mut buf, body.len());
+        buf.extend_from_slice(&body);
+    }
+    buf
+}
+
+/// Decode a payload; another version is an error.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Item>, DecodeError> {
+    let mut r = Reader::new(bytes);
+    let version = r.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion { version });
+    }
+    let count = r.varint()?;
+
+    // Every item takes at least two bytes, which bounds the allocation
+    let mut items = Vec::with_capacity(count.min(bytes.len() / 2));
+    for _ in 0..count {
+        let kind = r.byte()?;
+        let len = r.varint()?;
+        items.push(Item::decode_body(kind, r.take(len)?)?);
+    }
+    Ok(items)
+}
+
+/// Every item of `local` and `remote`, the winner where both have one,
+/// ordered by cast, kind and id; see the module docs.
+pub fn merge(local: Vec<Item>, remote: Vec<Item>) -> Vec<Item> {
+    let mut merged: BTreeMap<(String, u8, String), (Item, Vec<u8>)> = BTreeMap::new();
+    for item in local.into_iter().chain(remote) {
+        let body = item.body();
+        let key = (item.cast.clone(), item.kind, item.id.clone());
+        let newer = merged.get(&key).is_none_or(|(kept, kept_body)| {
+            (item.modified_ms, item.deleted, &body) > (kept.modified_ms, kept.deleted, kept_body)
+        });
+        if newer {
+            merged.insert(key, (item, body));
+        }
+    }
+    merged.into_values().map(|(item, _)| item).collect()
+}
+
+// JNI functions
+
+/// `merge` of two payloads, encoded; empty if either doesn't decode.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_syncMerge<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    local: JByteArray<'a>,
+    remote: JByteArray<'a>,
+) -> JByteArray<'a> {
+    jni_guard!(env, {
+        let (Ok(local), Ok(remote)) = (
+            env.convert_byte_array(&local),
+            env.convert_byte_array(&remote),
+        ) else {
+            return JByteArray::default();
+        };
+        let (Ok(local), Ok(remote)) = (decode(&local), decode(&remote)) else {
+            return JByteArray::default();
+        };
+
+        env.byte_array_from_slice(&encode(&merge(local, remote)))
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, modified_ms: u64, text: &str) -> Item {
+        Item {
+            kind: KIND_BOOKMARK,
+            cast: "ab12".to_string(),
+            id: id.to_string(),
+            modified_ms,
+            deleted: false,
+            time_us: 90_000_000_000,
+            duration_us: 0,
+            text: text.to_string(),
+            extra: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn merges_keep_the_latest_edit_in_any_order() {
+        let renamed = item("a", 2_000_000_000_000, "intro (fixed)");
+        let deleted = Item {
+            deleted: true,
+            ..item("b", 1_000_000_000_001, "")
+        };
+        let local = vec![
+            item("a", 1_000_000_000_000, "intro"),
+            item("b", 1_000_000_000_000, "x"),
+        ];
+        let remote = vec![
+            renamed.clone(),
+            deleted.clone(),
+            Item {
+                kind: 9,
+                extra: vec![1, 2, 3],
+                ..item("a", 5, "from a newer app")
+            },
+        ];
+
+        let merged = merge(local.clone(), remote.clone());
+        assert_eq!(merge(remote.clone(), local.clone()), merged);
+        assert_eq!(merged[..2], [renamed, deleted]);
+        assert_eq!(merged[2].extra, [1, 2, 3]);
+        assert_eq!(decode(&encode(&merged)).unwrap(), merged);
+
+        // A tie goes to the deletion, whichever side has it
+        let kept = item("c", 7, "kept?");
+        let gone = Item {
+            deleted: true,
+            ..item("c", 7, "")
+        };
+        for (a, b) in [(kept.clone(), gone.clone()), (gone.clone(), kept)] {
+            assert_eq!(merge(vec![a], vec![b]), std::slice::from_ref(&gone));
+        }
+
+        let bytes = encode(&local);
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(
+            decode(&[2, 0]),
+            Err(DecodeError::UnsupportedVersion { version: 2 })
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! The terminal core for the web viewer, through `wasm-bindgen`.
+//!
+//! The companion viewer previews recordings in the browser with the same
+//! engine as the app, so a cast looks the same in both: `VtWasm` is
+//! `AvtState` with the calls a player needs, and its snapshots and diffs
+//! are `Uint8Array`s in the formats of `snapshot` and `diff`, for a port
+//! of the Kotlin decoders (checked with `schemaHash`, as the app checks
+//! `vtSchemaHash`). Build it with
+//! `wasm-pack build --target web -- --features wasm`.
+//!
+//! The JNI functions are still built in, but never called from JS; the
+//! feed worker isn't usable there, since the browser build has no threads.
+
+use crate::{protocol, schema, AvtState};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// A VT, owned by JS and released with `free()`.
+#[wasm_bindgen]
+pub struct VtWasm {
+    state: AvtState,
+}
+
+#[wasm_bindgen]
+impl VtWasm {
+    /// A VT of `cols` by `rows` cells, with unlimited scrollback.
+    #[wasm_bindgen(constructor)]
+    pub fn new(cols: u32, rows: u32) -> VtWasm {
+        VtWasm {
+            state: AvtState::new(cols as usize, rows as usize),
+        }
+    }
+
+    /// Feed output, as the bytes of a cast's `o` events.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.state.feed(bytes);
+    }
+
+    /// Resize, rewrapping soft-wrapped lines, for an `r` event.
+    pub fn resize(&mut self, cols: u32, rows: u32) {
+        self.state.resize(cols as usize, rows as usize);
+    }
+
+    /// Clear everything at a new size, to seek back to the start.
+    pub fn reset(&mut self, cols: u32, rows: u32) {
+        self.state.reset(cols as usize, rows as usize);
+    }
+
+    /// The screen in the snapshot format.
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.state.encode_snapshot()
+    }
+
+    /// What changed since the last diff, in the diff format; `undefined`
+    /// for no change.
+    pub fn diff(&mut self) -> Option<Vec<u8>> {
+        self.state.poll_diff()
+    }
+
+    /// Send snapshots and diffs behind a versioned header, with only
+    /// `features` (see `protocol`). Returns the features it will send.
+    #[wasm_bindgen(js_name = setWireFormat)]
+    pub fn set_wire_format(&mut self, version: u32, features: u32) -> u32 {
+        let wire = protocol::Wire::negotiate(version, features);
+        self.state.set_wire(wire);
+        wire.features
+    }
+
+    /// Hash of the wire formats' constants (see `schema`).
+    #[wasm_bindgen(js_name = schemaHash)]
+    pub fn schema_hash() -> i64 {
+        schema::hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot;
+
+    #[test]
+    fn snapshots_and_diffs_are_the_wire_formats() {
+        let mut vt = VtWasm::new(10, 2);
+        vt.feed(b"hi");
+        let screen = snapshot::decode(&vt.snapshot()).unwrap();
+        assert_eq!(screen.lines[0].runs[0].text.trim_end(), "hi");
+        assert_eq!(vt.snapshot(), vt.state.encode_snapshot());
+
+        assert!(vt.diff().is_some());
+        assert!(vt.diff().is_none());
+        vt.resize(4, 1);
+        assert!(vt.diff().is_some());
+        assert_eq!(VtWasm::schema_hash(), schema::hash());
+    }
+}
@@ -0,0 +1,578 @@
+//! Pattern watchers: events for notable text as it appears on screen.
+//!
+//! An app that buzzes on "ERROR" or pauses playback at a test failure
+//! would otherwise decode every snapshot and search it in Kotlin.
+//! `vtWatch` registers a pattern under a tag of the app's choosing, and
+//! each feed that changes the screen checks the rows that changed; a
+//! match queues a `VtEvent::Watch` with the tag, where it is and what it
+//! matched, taken with the other events.
+//!
+//! A hit fires once, when its text appears: a row whose text was already
+//! on screen before the feed (scrolled, say) is skipped, as is a match
+//! starting where the same watch matched in the row's previous text (the
+//! line redrawn in place, or its tail still being typed). Text on screen
+//! when a watch is added doesn't fire, and neither does a line printed
+//! again while an identical one is still on screen.
+//!
+//! Patterns are a regex subset, matched within one row with its trailing
+//! blanks trimmed:
+//!
+//! - literals, `.`, classes (`[a-z_]`, `[^0-9]`) and `\d \w \s \D \W \S`
+//! - `*`, `+` and `?`, greedy
+//! - `^` and `$` for the start and end of the row's text
+//! - `|` between whole alternatives
+//! - `\` before punctuation for the literal, and `\t`
+//!
+//! Groups, counted repeats and backreferences are rejected rather than
+//! matched literally. Empty matches never fire.
+
+use crate::backend::TerminalBackend;
+use crate::events::VtEvent;
+use crate::{handles, VtHandle};
+use jni::objects::{JClass, JString};
+use jni::sys::{jboolean, jint, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+/// Watches kept per VT
+pub const MAX_WATCHES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternError {
+    /// Groups, counted repeats or an unknown escape, at byte `offset`
+    Unsupported {
+        offset: usize,
+    },
+    /// `*`, `+` or `?` with nothing before it to repeat
+    NothingToRepeat {
+        offset: usize,
+    },
+    /// `[` at `offset` never closed
+    UnclosedClass {
+        offset: usize,
+    },
+    TrailingBackslash,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::Unsupported { offset } => write!(f, "unsupported syntax at {}", offset),
+            PatternError::NothingToRepeat { offset } => {
+                write!(f, "nothing to repeat at {}", offset)
+            }
+            PatternError::UnclosedClass { offset } => write!(f, "unclosed class at {}", offset),
+            PatternError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Char(char),
+    Any,
+    /// Inclusive ranges
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Atom {
+    fn matches(&self, ch: char, ignore_case: bool) -> bool {
+        let is = |c: char| match self {
+            Atom::Char(expected) => *expected == c,
+            Atom::Any => true,
+            Atom::Class { ranges, .. } => ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c)),
+        };
+        let hit = is(ch) || ignore_case && ch.to_lowercase().chain(ch.to_uppercase()).any(is);
+        match self {
+            Atom::Class { negated, .. } => hit != *negated,
+            _ => hit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+/// One `|` alternative.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Branch {
+    nodes: Vec<Node>,
+    at_start: bool,
+    at_end: bool,
+}
+
+/// A parsed watch pattern, see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    branches: Vec<Branch>,
+    ignore_case: bool,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+/// Ranges and negation of a class escape (`d` for `\d`), if it is one.
+fn class_escape(ch: char) -> Option<(&'static [(char, char)], bool)> {
+    match ch {
+        'd' => Some((DIGIT, false)),
+        'w' => Some((WORD, false)),
+        's' => Some((SPACE, false)),
+        'D' => Some((DIGIT, true)),
+        'W' => Some((WORD, true)),
+        'S' => Some((SPACE, true)),
+        _ => None,
+    }
+}
+
+/// The literal an escape stands for: `\t` or escaped punctuation.
+fn literal_escape(ch: char, offset: usize) -> Result<char, PatternError> {
+    match ch {
+        't' => Ok('\t'),
+        _ if ch.is_ascii_punctuation() || ch == ' ' => Ok(ch),
+        _ => Err(PatternError::Unsupported { offset }),
+    }
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, PatternError> {
+        let mut chars = pattern.char_indices().peekable();
+        let mut branches = Vec::new();
+        let mut branch = Branch::default();
+
+        while let Some((offset, ch)) = chars.next() {
+            let atom = match ch {
+                '|' => {
+                    branches.push(std::mem::take(&mut branch));
+                    continue;
+                }
+                '^' if branch.nodes.is_empty() && !branch.at_start => {
+                    branch.at_start = true;
+                    continue;
+                }
+                '$' if chars.peek().is_none_or(|&(_, next)| next == '|') => {
+                    branch.at_end = true;
+                    continue;
+                }
+                '*' | '+' | '?' => {
+                    let Some(node) = branch.nodes.last_mut().filter(|n| n.repeat == Repeat::One)
+                    else {
+                        return Err(PatternError::NothingToRepeat { offset });
+                    };
+                    node.repeat = match ch {
+                        '*' => Repeat::ZeroOrMore,
+                        '+' => Repeat::OneOrMore,
+                        _ => Repeat::ZeroOrOne,
+                    };
+                    continue;
+                }
+                '(' | ')' | '{' | '}' | '^' | '$' => {
+                    return Err(PatternError::Unsupported { offset })
+                }
+                '.' => Atom::Any,
+                '[' => parse_class(&mut chars, offset)?,
+                '\\' => {
+                    let (at, escaped) = chars.next().ok_or(PatternError::TrailingBackslash)?;
+                    match class_escape(escaped) {
+                        Some((ranges, negated)) => Atom::Class {
+                            ranges: ranges.to_vec(),
+                            negated,
+                        },
+                        None => Atom::Char(literal_escape(escaped, at)?),
+                    }
+                }
+                _ => Atom::Char(ch),
+            };
+            branch.nodes.push(Node {
+                atom,
+                repeat: Repeat::One,
+            });
+        }
+        branches.push(branch);
+
+        Ok(Pattern {
+            branches,
+            ignore_case: false,
+        })
+    }
+
+    /// A pattern matching `text` as is, special characters included.
+    pub fn literal(text: &str) -> Pattern {
+        let nodes = text
+            .chars()
+            .map(|ch| Node {
+                atom: Atom::Char(ch),
+                repeat: Repeat::One,
+            })
+            .collect();
+        Pattern {
+            branches: vec![Branch {
+                nodes,
+                ..Branch::default()
+            }],
+            ignore_case: false,
+        }
+    }
+
+    /// The same pattern, matching letters in either case.
+    pub fn ignoring_case(self) -> Pattern {
+        Pattern {
+            ignore_case: true,
+            ..self
+        }
+    }
+
+    /// The first non-empty match in `text` starting at or after `from`, as
+    /// char indices.
+    pub(crate) fn find_at(&self, text: &[char], from: usize) -> Option<Range<usize>> {
+        (from..=text.len()).find_map(|start| {
+            self.branches.iter().find_map(|branch| {
+                if branch.at_start && start > 0 {
+                    return None;
+                }
+                let end = match_here(&branch.nodes, text, start, branch.at_end, self.ignore_case)?;
+                (end > start).then_some(start..end)
+            })
+        })
+    }
+
+    /// Every non-overlapping match in `text`, leftmost first.
+    pub fn find_all(&self, text: &[char]) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut from = 0;
+        while let Some(range) = self.find_at(text, from) {
+            from = range.end;
+            matches.push(range);
+        }
+        matches
+    }
+}
+
+/// After a `[` at `offset`: members up to the closing `]`, which is a
+/// member itself when first.
+fn parse_class(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    offset: usize,
+) -> Result<Atom, PatternError> {
+    let negated = chars.next_if(|&(_, ch)| ch == '^').is_some();
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let (at, ch) = chars.next().ok_or(PatternError::UnclosedClass { offset })?;
+        let lo = match ch {
+            ']' if !first => break,
+            '\\' => {
+                let (at, escaped) = chars.next().ok_or(PatternError::UnclosedClass { offset })?;
+                match class_escape(escaped) {
+                    Some((members, false)) => {
+                        ranges.extend_from_slice(members);
+                        first = false;
+                        continue;
+                    }
+                    Some((_, true)) => return Err(PatternError::Unsupported { offset: at }),
+                    None => literal_escape(escaped, at)?,
+                }
+            }
+            _ => ch,
+        };
+        first = false;
+
+        // `-` last, or before the `]`, is a member
+        let is_range = chars.peek().is_some_and(|&(_, ch)| ch == '-')
+            && chars.clone().nth(1).is_some_and(|(_, ch)| ch != ']');
+        if !is_range {
+            ranges.push((lo, lo));
+            continue;
+        }
+        chars.next();
+        let (_, hi) = chars.next().ok_or(PatternError::UnclosedClass { offset })?;
+        if hi < lo {
+            return Err(PatternError::Unsupported { offset: at });
+        }
+        ranges.push((lo, hi));
+    }
+
+    Ok(Atom::Class { ranges, negated })
+}
+
+/// End of the match of `nodes` at `at`, backtracking through repeats.
+fn match_here(
+    nodes: &[Node],
+    text: &[char],
+    at: usize,
+    at_end: bool,
+    ignore_case: bool,
+) -> Option<usize> {
+    let Some((node, rest)) = nodes.split_first() else {
+        return (!at_end || at == text.len()).then_some(at);
+    };
+    let (min, max) = match node.repeat {
+        Repeat::One => (1, 1),
+        Repeat::ZeroOrOne => (0, 1),
+        Repeat::ZeroOrMore => (0, usize::MAX),
+        Repeat::OneOrMore => (1, usize::MAX),
+    };
+    let run = text[at..]
+        .iter()
+        .take(max)
+        .take_while(|&&ch| node.atom.matches(ch, ignore_case))
+        .count();
+    (min..=run)
+        .rev()
+        .find_map(|n| match_here(rest, text, at + n, at_end, ignore_case))
+}
+
+#[derive(Debug, Clone)]
+struct Watch {
+    tag: u32,
+    pattern: Pattern,
+}
+
+/// Watches of one VT and the screen text they last checked.
+#[derive(Debug, Clone, Default)]
+pub struct Watchers {
+    watches: Vec<Watch>,
+    /// Trimmed text of each row at the last check
+    rows: Vec<String>,
+}
+
+fn trimmed_rows(backend: &impl TerminalBackend) -> Vec<String> {
+    (0..backend.size().1)
+        .map(|row| {
+            let mut text = backend.row_text(row);
+            text.truncate(text.trim_end_matches(' ').len());
+            text
+        })
+        .collect()
+}
+
+impl Watchers {
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Watch for `pattern`, replacing any watch with the same tag. False,
+    /// adding nothing, when `MAX_WATCHES` are set already.
+    pub fn add(&mut self, tag: u32, pattern: Pattern, backend: &impl TerminalBackend) -> bool {
+        self.remove(tag);
+        if self.watches.len() >= MAX_WATCHES {
+            return false;
+        }
+        self.watches.push(Watch { tag, pattern });
+        self.rows = trimmed_rows(backend);
+        true
+    }
+
+    /// Returns whether a watch with `tag` was set.
+    pub fn remove(&mut self, tag: u32) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|watch| watch.tag != tag);
+        self.watches.len() < len
+    }
+
+    /// Forget the screen text, after a reset blanks it.
+    pub fn clear_rows(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Hits on `backend`'s screen that weren't there at the last check.
+    pub fn check(&mut self, backend: &impl TerminalBackend) -> Vec<VtEvent> {
+        let rows = trimmed_rows(backend);
+        let seen: HashSet<&str> = self.rows.iter().map(String::as_str).collect();
+        let mut hits = Vec::new();
+
+        for (row, text) in rows.iter().enumerate() {
+            if seen.contains(text.as_str()) {
+                continue;
+            }
+            let chars: Vec<char> = text.chars().collect();
+            let before: Vec<char> = self
+                .rows
+                .get(row)
+                .map_or(Vec::new(), |t| t.chars().collect());
+            for watch in &self.watches {
+                let old_starts: Vec<usize> = watch
+                    .pattern
+                    .find_all(&before)
+                    .into_iter()
+                    .map(|range| range.start)
+                    .collect();
+                for range in watch.pattern.find_all(&chars) {
+                    if old_starts.contains(&range.start) {
+                        continue;
+                    }
+                    hits.push(VtEvent::Watch {
+                        tag: watch.tag,
+                        row,
+                        col: range.start,
+                        text: chars[range].iter().collect(),
+                    });
+                }
+            }
+        }
+
+        self.rows = rows;
+        hits
+    }
+}
+
+// JNI functions
+
+/// Queue a watch event with `tag` whenever text matching `pattern` appears
+/// (see module docs), replacing any watch with that tag. False for an
+/// invalid handle, a negative tag, a pattern outside the supported subset,
+/// or when `MAX_WATCHES` are set.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtWatch(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    pattern: JString,
+    tag: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Ok(tag) = u32::try_from(tag) else {
+            return JNI_FALSE;
+        };
+        let pattern: String = match env.get_string(&pattern) {
+            Ok(s) => s.into(),
+            Err(_) => return JNI_FALSE,
+        };
+        let Ok(pattern) = Pattern::parse(&pattern) else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if vt.watch(tag, pattern) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Remove the watch with `tag`. False for an invalid handle or when there
+/// was none.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtUnwatch(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    tag: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let Ok(tag) = u32::try_from(tag) else {
+            return JNI_FALSE;
+        };
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JNI_FALSE;
+        };
+
+        if vt.unwatch(tag) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::AvtState;
+
+    fn find(pattern: &str, text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        Pattern::parse(pattern)
+            .unwrap()
+            .find_all(&chars)
+            .into_iter()
+            .map(|range| chars[range].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn patterns_match_the_supported_subset() {
+        assert_eq!(find("ERROR|FAIL", "FAIL: x ERROR"), ["FAIL", "ERROR"]);
+        assert_eq!(find(r"\d+ passed", "ok 12 passed"), ["12 passed"]);
+        assert_eq!(find("^\\$ .*", "$ make all"), ["$ make all"]);
+        assert_eq!(find("^x", "xx"), ["x"]);
+        assert_eq!(find("b?c$", "abcc"), ["c"]);
+        assert_eq!(find("[^a-c-]+", "ab-de-f"), ["de", "f"]);
+        assert_eq!(find(r"[\w.]+@\w+", "to: a.b@ex!"), ["a.b@ex"]);
+        assert_eq!(find("a*", "baa"), ["aa"]);
+        assert_eq!(find("colou?r", "color colour"), ["color", "colour"]);
+
+        assert_eq!(
+            Pattern::parse("(a|b)"),
+            Err(PatternError::Unsupported { offset: 0 })
+        );
+        assert_eq!(
+            Pattern::parse("a{2}"),
+            Err(PatternError::Unsupported { offset: 1 })
+        );
+        assert_eq!(
+            Pattern::parse("+a"),
+            Err(PatternError::NothingToRepeat { offset: 0 })
+        );
+        assert_eq!(
+            Pattern::parse("a**"),
+            Err(PatternError::NothingToRepeat { offset: 2 })
+        );
+        assert_eq!(
+            Pattern::parse("x[ab"),
+            Err(PatternError::UnclosedClass { offset: 1 })
+        );
+        assert_eq!(Pattern::parse("a\\"), Err(PatternError::TrailingBackslash));
+        assert_eq!(
+            Pattern::parse(r"\1"),
+            Err(PatternError::Unsupported { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn hits_fire_once_as_text_appears() {
+        let mut vt = AvtState::with_backend(fake(20, 2));
+        vt.feed(b"old ERROR ");
+        assert!(vt.watch(7, Pattern::parse("ERR[A-Z]*").unwrap()));
+        vt.feed(b"ERR");
+        vt.feed(b"OR");
+        vt.feed(b" done");
+        let hit = |col, text: &str| VtEvent::Watch {
+            tag: 7,
+            row: 0,
+            col,
+            text: text.to_string(),
+        };
+        assert_eq!(vt.take_events(), [hit(10, "ERR")]);
+
+        // Watches outlive resets
+        vt.reset(20, 2);
+        vt.feed(b"ERROR");
+        assert_eq!(vt.take_events(), [hit(0, "ERROR")]);
+        assert!(vt.unwatch(7));
+        assert!(!vt.unwatch(7));
+    }
+}
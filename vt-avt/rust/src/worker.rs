@@ -0,0 +1,408 @@
+//! Feeds parsed on a thread of the VT's own.
+//!
+//! `vtFeed` parses on the caller's thread, so a burst (a recording that
+//! `cat`s a large file, a build log) blocks whatever thread feeds, often
+//! the one that renders. After `vtStartWorker`, `vtFeedAsync` instead
+//! copies the bytes into a ring buffer and returns, and a native thread
+//! parses them in the background; `vtPollDiff` and the other calls keep
+//! working as before and see the output parsed so far, the diff adding up
+//! every feed since the last poll.
+//!
+//! The ring is single-producer, single-consumer and lock-free: the feeder
+//! and the worker only ever publish their own end's position. Feeding it is
+//! a `vtFeedAsync` call, so as with any use of a handle, one thread at a
+//! time. A full ring takes what fits and `vtFeedAsync` says how much, for
+//! the caller to retry the rest once the worker has caught up.
+//!
+//! The VT itself is still used by one thread at a time. The worker holds
+//! the VT's baton while it parses a slice of at most `SLICE` bytes, and
+//! `handles::get` takes it for the rest of the JNI call that looks the VT
+//! up (released by `jni_guard!`), so a call waits for at most one slice.
+//! `vtFeed` and the like go ahead of whatever is still queued.
+//!
+//! `vtStopWorker` parses what's queued and ends the thread; `vtFree` ends
+//! it without. A VT borrowed from a player has no worker, the player
+//! feeding it.
+
+use crate::{handles, VtHandle};
+use jni::objects::{JByteArray, JClass};
+use jni::sys::{jboolean, jint, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::cell::{RefCell, UnsafeCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicU8, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle, Thread};
+
+/// Ring size for `vtStartWorker` with a size of 0 or less
+pub const DEFAULT_QUEUE: usize = 1 << 20;
+
+/// Ring sizes are rounded up to a power of two in this range
+const MIN_QUEUE: usize = 4 << 10;
+const MAX_QUEUE: usize = 64 << 20;
+
+/// Most bytes parsed per hold of the baton
+pub const SLICE: usize = 16 << 10;
+
+const RUNNING: u8 = 0;
+/// Parse what's queued, then end
+const DRAINING: u8 = 1;
+/// End after the slice being parsed
+const ABANDONED: u8 = 2;
+
+/// Bytes from one producer to one consumer, in a ring of a power-of-two
+/// size. Positions only grow; the slot is the position masked.
+pub struct Ring {
+    buf: Box<[UnsafeCell<u8>]>,
+    /// Next position to read, written by the consumer only
+    head: AtomicUsize,
+    /// Next position to write, written by the producer only
+    tail: AtomicUsize,
+}
+
+// Each end only touches the bytes between the positions it owns, and
+// publishes them with release stores
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    /// A ring of `capacity` rounded up to a power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.next_power_of_two();
+        Ring {
+            buf: (0..capacity).map(|_| UnsafeCell::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Bytes written and not yet read.
+    pub fn len(&self) -> usize {
+        self.tail
+            .load(Acquire)
+            .wrapping_sub(self.head.load(Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Raw pointer to the slot of `position`, and the bytes from there to
+    /// the end of the buffer.
+    fn slot(&self, position: usize) -> (*mut u8, usize) {
+        let index = position & (self.capacity() - 1);
+        (self.buf[index].get(), self.capacity() - index)
+    }
+
+    /// Append as much of `bytes` as fits; producer only. Returns how much.
+    pub fn push(&self, bytes: &[u8]) -> usize {
+        let tail = self.tail.load(Relaxed);
+        let free = self.capacity() - tail.wrapping_sub(self.head.load(Acquire));
+        let count = bytes.len().min(free);
+        let mut done = 0;
+        while done < count {
+            let (slot, room) = self.slot(tail.wrapping_add(done));
+            let n = room.min(count - done);
+            unsafe { std::ptr::copy_nonoverlapping(bytes[done..].as_ptr(), slot, n) };
+            done += n;
+        }
+        self.tail.store(tail.wrapping_add(count), Release);
+        count
+    }
+
+    /// Replace `out` with up to `max` of the oldest bytes; consumer only.
+    pub fn pop(&self, out: &mut Vec<u8>, max: usize) -> usize {
+        let head = self.head.load(Relaxed);
+        let count = self.tail.load(Acquire).wrapping_sub(head).min(max);
+        out.clear();
+        while out.len() < count {
+            let (slot, room) = self.slot(head.wrapping_add(out.len()));
+            let n = room.min(count - out.len());
+            out.extend_from_slice(unsafe { std::slice::from_raw_parts(slot, n) });
+        }
+        self.head.store(head.wrapping_add(count), Release);
+        count
+    }
+}
+
+/// Who may use the VT: the worker, or the JNI call that looked it up.
+#[derive(Default)]
+struct Baton {
+    busy: Mutex<bool>,
+    free: Condvar,
+}
+
+impl Baton {
+    fn acquire(&self) {
+        let mut busy = self.busy.lock().unwrap_or_else(PoisonError::into_inner);
+        while *busy {
+            busy = self.free.wait(busy).unwrap_or_else(PoisonError::into_inner);
+        }
+        *busy = true;
+    }
+
+    fn release(&self) {
+        *self.busy.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        self.free.notify_one();
+    }
+}
+
+/// What the worker thread shares with its VT's callers.
+pub struct Shared {
+    ring: Ring,
+    baton: Baton,
+    state: AtomicU8,
+    thread: Thread,
+}
+
+impl Shared {
+    /// Queue as much of `bytes` as fits and wake the worker. Returns how
+    /// much; none once it's stopping.
+    pub fn feed(&self, bytes: &[u8]) -> usize {
+        if self.state.load(Acquire) != RUNNING {
+            return 0;
+        }
+        let count = self.ring.push(bytes);
+        self.thread.unpark();
+        count
+    }
+
+    /// Bytes queued and not yet parsed.
+    pub fn queued(&self) -> usize {
+        self.ring.len()
+    }
+}
+
+thread_local! {
+    /// Batons the current JNI call holds, see `hold`
+    static HELD: RefCell<Vec<Arc<Shared>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Take `shared`'s baton, if this thread doesn't hold it yet, until
+/// `release_held`.
+pub(crate) fn hold(shared: Arc<Shared>) {
+    let held = HELD.with(|held| held.borrow().iter().any(|h| Arc::ptr_eq(h, &shared)));
+    if !held {
+        shared.baton.acquire();
+        HELD.with(|held| held.borrow_mut().push(shared));
+    }
+}
+
+/// Release every baton `hold` took on this thread; the end of each JNI
+/// call (see `jni_guard!`).
+pub(crate) fn release_held() {
+    // A thread that never held one is the usual case
+    if HELD.with(|held| held.borrow().is_empty()) {
+        return;
+    }
+    for shared in HELD.with(|held| std::mem::take(&mut *held.borrow_mut())) {
+        shared.baton.release();
+    }
+}
+
+/// The pointer to the VT the worker thread feeds.
+struct Target<T>(*mut T);
+
+// The VT is used under the baton only, by one thread at a time
+unsafe impl<T: Send> Send for Target<T> {}
+
+/// A VT's worker thread.
+pub struct Worker {
+    shared: Arc<Shared>,
+    thread: JoinHandle<()>,
+}
+
+impl Worker {
+    /// Start parsing into `target` with `feed`, from a ring of
+    /// `capacity` bytes (see `DEFAULT_QUEUE`). `target` must outlive the
+    /// worker, and is only used under the baton from now on.
+    pub fn start<T: Send + 'static>(
+        target: *mut T,
+        feed: fn(&mut T, &[u8]),
+        capacity: usize,
+    ) -> Worker {
+        let capacity = capacity.clamp(MIN_QUEUE, MAX_QUEUE);
+        let (sender, receiver) = std::sync::mpsc::channel::<Arc<Shared>>();
+        let target = Target(target);
+        let thread = thread::Builder::new()
+            .name("avt-worker".into())
+            .spawn(move || {
+                // The wrapper whole, not its pointer alone
+                let target = target;
+                let Ok(shared) = receiver.recv() else {
+                    return;
+                };
+                let mut slice = Vec::with_capacity(SLICE);
+                loop {
+                    let state = shared.state.load(Acquire);
+                    if state == ABANDONED {
+                        break;
+                    }
+                    if shared.ring.pop(&mut slice, SLICE) == 0 {
+                        if state == DRAINING {
+                            break;
+                        }
+                        thread::park();
+                        continue;
+                    }
+                    shared.baton.acquire();
+                    let fed = panic::catch_unwind(AssertUnwindSafe(|| {
+                        feed(unsafe { &mut *target.0 }, &slice)
+                    }));
+                    shared.baton.release();
+                    // The VT is broken; callers find out from their own
+                    // calls, and feeding stops taking bytes
+                    if fed.is_err() {
+                        shared.state.store(ABANDONED, Release);
+                    }
+                }
+            })
+            .expect("spawning the worker thread");
+        let shared = Arc::new(Shared {
+            ring: Ring::new(capacity),
+            baton: Baton::default(),
+            state: AtomicU8::new(RUNNING),
+            thread: thread.thread().clone(),
+        });
+        let _ = sender.send(Arc::clone(&shared));
+        Worker { shared, thread }
+    }
+
+    pub fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+
+    /// End the thread, once what's queued is parsed if `drain`, and wait
+    /// for it.
+    pub fn stop(self, drain: bool) {
+        let state = if drain { DRAINING } else { ABANDONED };
+        let _ = self
+            .shared
+            .state
+            .compare_exchange(RUNNING, state, Release, Relaxed);
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+    }
+}
+
+// JNI functions
+
+/// Parse `vtFeedAsync`'s bytes on a thread of the VT's own, from a queue of
+/// `queue_bytes` (0 or less for `DEFAULT_QUEUE`); see the module docs.
+/// False for an invalid handle, a player's VT or one with a worker already.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtStartWorker(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    queue_bytes: jint,
+) -> jboolean {
+    jni_guard!(env, {
+        let capacity = usize::try_from(queue_bytes)
+            .ok()
+            .filter(|&bytes| bytes > 0)
+            .unwrap_or(DEFAULT_QUEUE);
+        if handles::start_worker(&mut env, handle, capacity) {
+            JNI_TRUE
+        } else {
+            JNI_FALSE
+        }
+    })
+}
+
+/// Queue bytes for the worker to parse, returning how many fit (the rest
+/// is for a later call); 0 for an invalid handle or without a worker.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtFeedAsync(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+    byte_array: JByteArray,
+) -> jint {
+    jni_guard!(env, {
+        let Some(shared) = handles::worker(&mut env, handle) else {
+            return 0;
+        };
+        let Ok(bytes) = env.convert_byte_array(byte_array) else {
+            return 0;
+        };
+
+        shared.feed(&bytes) as jint
+    })
+}
+
+/// Bytes queued and not yet parsed; 0 without a worker.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtQueuedBytes(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jint {
+    jni_guard!(env, {
+        handles::worker(&mut env, handle).map_or(0, |shared| shared.queued() as jint)
+    })
+}
+
+/// Parse what's queued and end the worker, feeding on the caller's thread
+/// again; false if the VT had none.
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtStopWorker(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: VtHandle,
+) -> jboolean {
+    jni_guard!(env, {
+        match handles::take_worker(&mut env, handle) {
+            Some(worker) => {
+                worker.stop(true);
+                JNI_TRUE
+            }
+            None => JNI_FALSE,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+    use crate::backend::TerminalBackend;
+    use crate::AvtState;
+
+    #[test]
+    fn ring_wraps_and_takes_what_fits() {
+        let ring = Ring::new(6);
+        assert_eq!(ring.capacity(), 8);
+        assert_eq!(ring.push(b"abcdef"), 6);
+        let mut out = Vec::new();
+        assert_eq!(ring.pop(&mut out, 4), 4);
+        assert_eq!(out, b"abcd");
+        // Across the end of the buffer, and only as much as is free
+        assert_eq!(ring.push(b"ghijklmn"), 6);
+        assert_eq!(ring.len(), 8);
+        assert_eq!(ring.pop(&mut out, usize::MAX), 8);
+        assert_eq!(out, b"efghijkl");
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn worker_parses_in_the_background_until_drained() {
+        let mut vt = Box::new(AvtState::with_backend(fake(40, 2)));
+        vt.poll_diff();
+        let worker = Worker::start(&mut *vt, |vt, bytes| vt.feed(bytes), 0);
+        assert_eq!(worker.shared().ring.capacity(), MIN_QUEUE);
+        let text = "x".repeat(30);
+        let mut rest = text.as_bytes();
+        while !rest.is_empty() {
+            rest = &rest[worker.shared().feed(rest)..];
+        }
+        worker.stop(true);
+
+        assert_eq!(vt.backend().row_text(0).trim_end(), text);
+        assert!(vt.poll_diff().is_some());
+    }
+}
@@ -0,0 +1,150 @@
+//! xterm.js interop: the `SerializeAddon` text format.
+//!
+//! `serialize()` produces ANSI meant to be written into a fresh terminal
+//! of the same size: the buffer's rows with SGR styling, separated by CRLF
+//! (wrapped rows run on), the alternate buffer after `CSI ?1049h`, a
+//! cursor move back to where the cursor was, and then the terminal's modes
+//! as a run of `CSI h` / `CSI l` sequences.
+//!
+//! That replays like any other restore, except that the modes come after
+//! the cursor move, and setting origin mode homes the cursor. So the mode
+//! tail is split off and the cursor put back once it has been applied.
+//!
+//! The other direction writes the same layout from the backend's dump, for
+//! handing the current frame to a browser view.
+
+use crate::backend::TerminalBackend;
+use crate::scan::{Action, Scanner};
+use crate::{handles, AvtState};
+use jni::objects::{JClass, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+
+/// Replace `state` with the screen `serialized` describes, at the size the
+/// xterm.js terminal had (the format doesn't record it).
+pub fn import<B: TerminalBackend>(
+    state: &mut AvtState<B>,
+    cols: usize,
+    rows: usize,
+    serialized: &[u8],
+) {
+    let Some(split) = modes_start(serialized) else {
+        state.restore(cols, rows, serialized);
+        return;
+    };
+
+    state.restore(cols, rows, &serialized[..split]);
+    let cursor = state.backend().cursor();
+    state.feed(&serialized[split..]);
+    state.feed(format!("\x1b[{};{}H", cursor.row + 1, cursor.col + 1).as_bytes());
+}
+
+/// ANSI that recreates the screen in a fresh xterm.js terminal of the same
+/// size, laid out like `SerializeAddon` output so `import` reads it back.
+/// xterm.js has no double-width or double-height lines, so line attributes
+/// are left out and those rows show at normal size.
+pub fn serialize<B: TerminalBackend>(state: &AvtState<B>) -> String {
+    let vt = state.backend();
+    let cursor = vt.cursor();
+    let mut out = vt.dump();
+    out.push_str(&format!("\x1b[{};{}H", cursor.row + 1, cursor.col + 1));
+    if vt.modes().cursor_key_app {
+        out.push_str("\x1b[?1h");
+    }
+    out
+}
+
+/// Offset of the trailing run of mode sets and resets, if the data ends
+/// with one.
+fn modes_start(data: &[u8]) -> Option<usize> {
+    let mut start = None;
+    let mut last_end = 0;
+
+    Scanner::new().scan(data, |end, action| {
+        let is_mode = matches!(
+            action,
+            Action::Csi(csi) if csi.intermediates().is_empty() && matches!(csi.final_byte, b'h' | b'l')
+        );
+        if !is_mode {
+            start = None;
+        } else {
+            let begin = data[..end].iter().rposition(|&b| b == 0x1b).unwrap_or(last_end);
+            // Text before this sequence ends any run so far
+            if start.is_none() || begin != last_end {
+                start = Some(begin);
+            }
+        }
+        last_end = end;
+    });
+
+    start.filter(|_| last_end == data.len())
+}
+
+// JNI functions
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtImportXterm(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    cols: jint,
+    rows: jint,
+    serialized: JString,
+) {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return;
+        };
+
+        let serialized: String = match env.get_string(&serialized) {
+            Ok(s) => s.into(),
+            Err(_) => return,
+        };
+
+        import(vt, cols as usize, rows as usize, serialized.as_bytes());
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_uk_adedamola_asciicast_vt_avt_AvtNative_vtSerializeXterm<'a>(
+    mut env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    handle: jlong,
+) -> JString<'a> {
+    jni_guard!(env, {
+        let Some(vt) = handles::get(&mut env, handle) else {
+            return JString::default();
+        };
+
+        env.new_string(serialize(vt)).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::fake;
+
+    #[test]
+    fn finds_trailing_mode_run() {
+        let data = b"\x1b[1mhi\x1b[0m\r\n$ \x1b[2;3H\x1b[?1h\x1b[?2004h\x1b[?6h";
+        assert_eq!(modes_start(data), Some(20));
+
+        // Only sequences after the last text count
+        assert_eq!(modes_start(b"\x1b[?25lhi\x1b[4h"), Some(8));
+        assert_eq!(modes_start(b"\x1b[?1h$ "), None);
+        assert_eq!(modes_start(b"\x1b[?1h\x1b[H"), None);
+        assert_eq!(modes_start(b"plain"), None);
+    }
+
+    #[test]
+    fn serialize_drops_line_attributes_and_ends_with_cursor() {
+        let mut state = AvtState::with_backend(fake(8, 2));
+        state.feed(b"\x1b#6hi");
+        assert!(state.dump_ansi().contains("\x1b#6"));
+
+        let out = serialize(&state);
+        assert!(!out.contains("\x1b#"));
+        assert!(out.ends_with("\x1b[1;3H"));
+    }
+}
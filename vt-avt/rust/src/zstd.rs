@@ -0,0 +1,147 @@
+//! Streaming Zstandard (RFC 8878) decompression, through `ruzstd`.
+//!
+//! The counterpart of `gzip` for `.cast.zst`: `Decoder` decodes a block
+//! at a time as it's read, keeping only the frame's window (the distance
+//! matches reach, a few MiB for what `zstd` writes) plus the block not yet
+//! taken. Concatenated frames are read as one stream, skippable frames
+//! are skipped, and a frame's checksum is checked when it has one.
+//! Frames that need a dictionary are refused.
+
+use ruzstd::frame::ReadFrameHeaderError;
+use ruzstd::frame_decoder::{BlockDecodingStrategy, FrameDecoder, FrameDecoderError};
+use std::io::{self, BufRead, Read};
+
+fn corrupt(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Decompresses a Zstandard stream as it's read.
+pub struct Decoder<R> {
+    inner: R,
+    frame: FrameDecoder,
+    /// Whether `frame` is partway through a frame
+    in_frame: bool,
+    done: bool,
+    /// Decoded bytes not yet taken
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> Decoder<R> {
+    /// Read and check the first frame's header.
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut decoder = Decoder {
+            inner,
+            frame: FrameDecoder::new(),
+            in_frame: false,
+            done: false,
+            out: Vec::new(),
+            pos: 0,
+        };
+        decoder.frame.reset(&mut decoder.inner).map_err(corrupt)?;
+        decoder.in_frame = true;
+        Ok(decoder)
+    }
+
+    /// Decode the next block, or start the next frame.
+    fn step(&mut self) -> io::Result<()> {
+        if !self.in_frame {
+            if self.inner.fill_buf()?.is_empty() {
+                self.done = true;
+                return Ok(());
+            }
+            return match self.frame.reset(&mut self.inner) {
+                Ok(()) => {
+                    self.in_frame = true;
+                    Ok(())
+                }
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                    length,
+                    ..
+                })) => {
+                    let skipped =
+                        io::copy(&mut (&mut self.inner).take(length.into()), &mut io::sink())?;
+                    if skipped < length.into() {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    Ok(())
+                }
+                Err(error) => Err(corrupt(error)),
+            };
+        }
+
+        self.frame
+            .decode_blocks(&mut self.inner, BlockDecodingStrategy::UptoBlocks(1))
+            .map_err(corrupt)?;
+        // Keeps the window back until the frame is finished
+        self.out = self.frame.collect().unwrap_or_default();
+        self.pos = 0;
+        if self.frame.is_finished() {
+            if let (Some(expected), Some(actual)) = (
+                self.frame.get_checksum_from_data(),
+                self.frame.get_calculated_checksum(),
+            ) {
+                if expected != actual {
+                    return Err(corrupt("frame checksum doesn't match"));
+                }
+            }
+            self.in_frame = false;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pending = self.fill_buf()?;
+        let n = pending.len().min(buf.len());
+        buf[..n].copy_from_slice(&pending[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.out.len() && !self.done {
+            self.step()?;
+        }
+        Ok(&self.out[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.out.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `printf 'hello hello hello\n' | zstd -3 --check`
+    const HELLO: [u8; 26] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x12, 0x6d, 0x00, 0x00, 0x38, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+        0x20, 0x0a, 0x01, 0x00, 0x31, 0x4a, 0x11, 0xa3, 0xaa, 0x74, 0xce,
+    ];
+
+    fn decode(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Decoder::new(bytes)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn decodes_frames_and_checks_them() {
+        assert_eq!(decode(&HELLO).unwrap(), b"hello hello hello\n");
+        // A skippable frame between two others
+        let skippable = [0x53, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+        let stream = [&HELLO[..], &skippable, &HELLO].concat();
+        assert_eq!(decode(&stream).unwrap(), b"hello hello hello\n".repeat(2));
+
+        let mut corrupt = HELLO;
+        corrupt[25] ^= 1;
+        assert!(decode(&corrupt).is_err());
+        assert!(decode(&HELLO[..20]).is_err());
+        assert!(decode(&[&HELLO[..], b"junk"].concat()).is_err());
+    }
+}